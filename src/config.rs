@@ -1,4 +1,9 @@
 use std::env;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::models::Network;
+use crate::services::rate_limit::jittered;
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -7,8 +12,97 @@ pub struct Config {
     pub database_path: String,
     pub bsv_private_key: Option<String>,
     pub bsv_fee_rate: f64,
+    pub bsv_sighash_forkid: bool,
+    /// Bitails base URL for mainnet: BITAILS_API_URL, else MAINNET_API_URL
     pub bitails_api_url: String,
-    pub bitails_api_key: Option<String>,
+    /// BITAILS_API_KEYS (comma-separated), or the single BITAILS_API_KEY
+    pub bitails_api_keys: Vec<String>,
+    /// MAX_UPLOAD_COST_SATOSHIS, else MAX_REQUIRED_SATOSHIS
+    pub max_upload_cost_satoshis: i64,
+    /// Most chunk transactions one upload may need, 0 for no limit
+    pub max_chunk_count: usize,
+    pub max_concurrent_jobs: usize,
+    pub job_stall_timeout_minutes: i64,
+    /// Longest a job may run before it is stopped and failed, 0 for no limit
+    pub max_job_duration_minutes: u64,
+    pub manifest_metadata_layout: String,
+    /// How often storage maintenance runs
+    pub blob_sweep_interval_minutes: u64,
+    /// How long a finished job keeps its file data
+    pub blob_sweep_min_age_minutes: i64,
+    /// How old a download file no job links to must be before it is deleted
+    pub orphan_file_min_age_minutes: u64,
+    /// Tail of each job's processing log kept for support, in KB; logs are
+    /// deleted on the same age cutoff as orphaned download files
+    pub job_log_max_kb: usize,
+    /// How long the raw hex of broadcast transactions is kept for re-broadcasting, 0 to not keep it
+    pub raw_tx_retention_hours: i64,
+    pub whatsonchain_requests_per_second: f64,
+    /// How often the payment watcher checks pending jobs
+    pub payment_poll_interval_seconds: u64,
+    /// How often status pages are told to poll
+    pub status_poll_interval_ms: u64,
+    /// Random spread applied to both poll intervals, in percent either way
+    pub poll_jitter_percent: u64,
+    /// Confirmations a funding transaction needs before its UTXOs are spent, 0 to spend at once
+    pub min_payment_confirmations: i64,
+    pub admin_pay_daily_budget_satoshis: i64,
+    pub abandoned_payment_minutes: i64,
+    /// How long a repeated prepare of the same file reuses the pending job, 0 to never reuse
+    pub duplicate_job_window_minutes: i64,
+    /// Backlog past which new jobs are refused with 503 until it drains; unset for no limit
+    pub max_pending_jobs: Option<i64>,
+    pub max_queued_jobs: Option<usize>,
+    /// Largest chunked file a stream/discard download or verify-file builds in memory
+    pub max_inline_download_bytes: usize,
+    /// How testnet and STN payment URIs are written: address, scheme or param
+    pub testnet_payment_uri: String,
+    pub sweep_address_mainnet: Option<String>,
+    pub sweep_address_testnet: Option<String>,
+    pub outbound_proxy_url: Option<String>,
+    /// Reverse proxies whose X-Forwarded-For is believed when they are the peer
+    pub trusted_proxies: Vec<IpAddr>,
+    /// HD seed (hex or mnemonic) payment keys are derived from; random keys when unset
+    pub hd_seed: Option<String>,
+    pub hd_passphrase: String,
+    /// User-Agent sent to chain API providers
+    pub user_agent: String,
+    /// Secret admin session cookies are signed with; random per start when unset
+    pub admin_session_secret: Option<String>,
+    pub admin_session_hours: i64,
+    /// Whether admin routes still accept the admin key in the request body
+    pub admin_key_in_body: bool,
+    /// Largest request body for routes that take a file (multipart uploads, raw transactions)
+    pub upload_body_limit_bytes: usize,
+    /// Largest request body for every other route
+    pub json_body_limit_bytes: usize,
+    pub max_push_size: usize,
+    /// Most outputs per split transaction; larger uploads split in batches
+    pub max_split_outputs: usize,
+    /// Most UTXOs combined to fund a split when no single one is large enough
+    pub max_split_inputs: usize,
+    /// Most outputs a transaction may have to be relayed; split batches stay below it
+    pub max_tx_outputs: usize,
+    /// How many times an upload may halve its chunk size after a provider
+    /// rejects a chunk as too large (0 = fail instead)
+    pub max_chunk_halvings: u32,
+    /// Largest FLAC upload stored in one transaction; larger ones are chunked
+    pub flac_single_tx_max_bytes: usize,
+    /// Longest lyrics kept in the track metadata; longer ones get their own transaction
+    pub lyrics_inline_max_bytes: usize,
+    /// Largest transaction Bitails / WhatsOnChain accept; sizes FLAC chunks when set
+    pub bitails_max_tx_bytes: Option<usize>,
+    pub whatsonchain_max_tx_bytes: Option<usize>,
+    /// Satoshis locked in each data-carrying output; raise it for miners with a stricter dust policy
+    pub data_output_satoshis: u64,
+    /// Change at or below this is left to the miner; split outputs, royalties and quotes never go under it
+    pub dust_limit_satoshis: u64,
+    /// WhatsOnChain base URL for mainnet (broadcast fallback and chain info)
+    pub whatsonchain_mainnet_url: String,
+    /// WhatsOnChain base URL for testnet: WHATSONCHAIN_TESTNET_URL, else TESTNET_API_URL
+    pub whatsonchain_testnet_url: String,
+    /// WhatsOnChain base URL for the scaling test network: WHATSONCHAIN_STN_URL, else STN_API_URL
+    pub whatsonchain_stn_url: String,
 }
 
 impl Config {
@@ -26,9 +120,306 @@ impl Config {
                 .unwrap_or_else(|_| "0.002".to_string())
                 .parse()
                 .unwrap_or(0.002),
-            bitails_api_url: env::var("BITAILS_API_URL")
-                .unwrap_or_else(|_| "https://api.bitails.io".to_string()),
-            bitails_api_key: env::var("BITAILS_API_KEY").ok(),
+            // FORKID is mandatory on BSV; only disable it for signer interop testing
+            bsv_sighash_forkid: env::var("BSV_SIGHASH_FORKID")
+                .map(|v| v != "false" && v != "0")
+                .unwrap_or(true),
+            bitails_api_url: base_url(&["BITAILS_API_URL", "MAINNET_API_URL"], "https://api.bitails.io"),
+            bitails_api_keys: env::var("BITAILS_API_KEYS")
+                .or_else(|_| env::var("BITAILS_API_KEY"))
+                .map(|keys| {
+                    keys.split(',')
+                        .map(|k| k.trim().to_string())
+                        .filter(|k| !k.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            max_upload_cost_satoshis: env::var("MAX_UPLOAD_COST_SATOSHIS")
+                .or_else(|_| env::var("MAX_REQUIRED_SATOSHIS"))
+                .unwrap_or_else(|_| "1000000".to_string())
+                .parse()
+                .unwrap_or(1_000_000),
+            max_chunk_count: env::var("MAX_CHUNK_COUNT")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            max_concurrent_jobs: env::var("MAX_CONCURRENT_JOBS")
+                .unwrap_or_else(|_| "2".to_string())
+                .parse()
+                .unwrap_or(2),
+            job_stall_timeout_minutes: env::var("JOB_STALL_TIMEOUT_MINUTES")
+                .unwrap_or_else(|_| "15".to_string())
+                .parse()
+                .unwrap_or(15),
+            max_job_duration_minutes: env::var("MAX_JOB_DURATION_MINUTES")
+                .unwrap_or_else(|_| "360".to_string())
+                .parse()
+                .unwrap_or(360),
+            manifest_metadata_layout: env::var("MANIFEST_METADATA_LAYOUT")
+                .unwrap_or_else(|_| "json".to_string()),
+            blob_sweep_interval_minutes: env::var("BLOB_SWEEP_INTERVAL_MINUTES")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .unwrap_or(60),
+            blob_sweep_min_age_minutes: env::var("BLOB_SWEEP_MIN_AGE_MINUTES")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .unwrap_or(60),
+            orphan_file_min_age_minutes: env::var("ORPHAN_FILE_MIN_AGE_MINUTES")
+                .unwrap_or_else(|_| "1440".to_string())
+                .parse()
+                .unwrap_or(1440),
+            job_log_max_kb: env::var("JOB_LOG_MAX_KB")
+                .unwrap_or_else(|_| "64".to_string())
+                .parse()
+                .unwrap_or(64),
+            raw_tx_retention_hours: env::var("RAW_TX_RETENTION_HOURS")
+                .unwrap_or_else(|_| "72".to_string())
+                .parse()
+                .unwrap_or(72),
+            whatsonchain_requests_per_second: env::var("WHATSONCHAIN_REQUESTS_PER_SECOND")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .unwrap_or(3.0),
+            payment_poll_interval_seconds: env::var("PAYMENT_POLL_INTERVAL_SECONDS")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .unwrap_or(3),
+            status_poll_interval_ms: env::var("STATUS_POLL_INTERVAL_MS")
+                .unwrap_or_else(|_| "3000".to_string())
+                .parse()
+                .unwrap_or(3000),
+            poll_jitter_percent: env::var("POLL_JITTER_PERCENT")
+                .unwrap_or_else(|_| "20".to_string())
+                .parse()
+                .unwrap_or(20),
+            min_payment_confirmations: env::var("MIN_PAYMENT_CONFIRMATIONS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            // 0 leaves admin pay without a daily limit
+            admin_pay_daily_budget_satoshis: env::var("ADMIN_PAY_DAILY_BUDGET_SATOSHIS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .unwrap_or(0),
+            // Unpaid jobs older than this are listed for sweeping on the admin page
+            abandoned_payment_minutes: env::var("ABANDONED_PAYMENT_MINUTES")
+                .unwrap_or_else(|_| "1440".to_string())
+                .parse()
+                .unwrap_or(1440),
+            duplicate_job_window_minutes: env::var("DUPLICATE_JOB_WINDOW_MINUTES")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()
+                .unwrap_or(10),
+            max_pending_jobs: env::var("MAX_PENDING_JOBS").ok().and_then(|v| v.parse().ok()),
+            max_queued_jobs: env::var("MAX_QUEUED_JOBS").ok().and_then(|v| v.parse().ok()),
+            max_inline_download_bytes: env::var("MAX_INLINE_DOWNLOAD_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(100 * 1024 * 1024),
+            testnet_payment_uri: env::var("TESTNET_PAYMENT_URI")
+                .unwrap_or_else(|_| "address".to_string()),
+            sweep_address_mainnet: env::var("SWEEP_ADDRESS_MAINNET").ok(),
+            sweep_address_testnet: env::var("SWEEP_ADDRESS_TESTNET").ok(),
+            outbound_proxy_url: env::var("OUTBOUND_PROXY_URL").ok().filter(|u| !u.trim().is_empty()),
+            trusted_proxies: env::var("TRUSTED_PROXIES")
+                .map(|ips| {
+                    ips.split(',')
+                        .map(str::trim)
+                        .filter_map(|ip| ip.parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            hd_seed: env::var("HD_SEED").ok().filter(|s| !s.trim().is_empty()),
+            hd_passphrase: env::var("HD_PASSPHRASE").unwrap_or_default(),
+            user_agent: env::var("USER_AGENT")
+                .ok()
+                .map(|ua| ua.trim().to_string())
+                .filter(|ua| !ua.is_empty())
+                .unwrap_or_else(|| crate::services::http::DEFAULT_USER_AGENT.to_string()),
+            admin_session_secret: env::var("ADMIN_SESSION_SECRET").ok().filter(|s| !s.trim().is_empty()),
+            admin_session_hours: env::var("ADMIN_SESSION_HOURS")
+                .unwrap_or_else(|_| "12".to_string())
+                .parse()
+                .unwrap_or(12),
+            admin_key_in_body: env::var("ADMIN_KEY_IN_BODY")
+                .map(|v| v.to_lowercase() != "false")
+                .unwrap_or(true),
+            upload_body_limit_bytes: env::var("UPLOAD_BODY_LIMIT_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(50 * 1024 * 1024),
+            json_body_limit_bytes: env::var("JSON_BODY_LIMIT_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(64 * 1024),
+            max_push_size: env::var("MAX_PUSH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(crate::services::bsv::DEFAULT_MAX_PUSH_SIZE),
+            max_split_outputs: env::var("MAX_SPLIT_OUTPUTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(crate::services::bsv::DEFAULT_MAX_SPLIT_OUTPUTS),
+            max_split_inputs: env::var("MAX_SPLIT_INPUTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(crate::services::bsv::DEFAULT_MAX_SPLIT_INPUTS),
+            max_tx_outputs: env::var("MAX_TX_OUTPUTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3000),
+            max_chunk_halvings: env::var("MAX_CHUNK_HALVINGS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3),
+            flac_single_tx_max_bytes: env::var("FLAC_SINGLE_TX_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(crate::services::bsv::DEFAULT_FLAC_SINGLE_TX_MAX_BYTES),
+            lyrics_inline_max_bytes: env::var("LYRICS_INLINE_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(crate::services::bsv::DEFAULT_LYRICS_INLINE_MAX_BYTES),
+            bitails_max_tx_bytes: env::var("BITAILS_MAX_TX_BYTES").ok().and_then(|v| v.parse().ok()),
+            whatsonchain_max_tx_bytes: env::var("WHATSONCHAIN_MAX_TX_BYTES").ok().and_then(|v| v.parse().ok()),
+            data_output_satoshis: env::var("DATA_OUTPUT_SATOSHIS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(crate::services::bsv::DEFAULT_DATA_OUTPUT_SATOSHIS),
+            dust_limit_satoshis: env::var("DUST_LIMIT_SATOSHIS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(crate::services::bsv::DEFAULT_DUST_LIMIT_SATOSHIS),
+            whatsonchain_mainnet_url: base_url(
+                &["WHATSONCHAIN_MAINNET_URL"],
+                "https://api.whatsonchain.com/v1/bsv/main",
+            ),
+            whatsonchain_testnet_url: base_url(
+                &["WHATSONCHAIN_TESTNET_URL", "TESTNET_API_URL"],
+                "https://api.whatsonchain.com/v1/bsv/test",
+            ),
+            whatsonchain_stn_url: base_url(
+                &["WHATSONCHAIN_STN_URL", "STN_API_URL"],
+                "https://api.whatsonchain.com/v1/bsv/stn",
+            ),
         }
     }
+
+    /// Reject chain API URLs that would fail on every request
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, url) in [
+            ("Bitails", &self.bitails_api_url),
+            ("WhatsOnChain mainnet", &self.whatsonchain_mainnet_url),
+            ("WhatsOnChain testnet", &self.whatsonchain_testnet_url),
+            ("WhatsOnChain STN", &self.whatsonchain_stn_url),
+        ] {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return Err(format!("{} API URL needs an http:// or https:// scheme: {}", name, url));
+            }
+        }
+        for (name, value) in [
+            ("DATA_OUTPUT_SATOSHIS", self.data_output_satoshis),
+            ("DUST_LIMIT_SATOSHIS", self.dust_limit_satoshis),
+        ] {
+            if crate::models::Amount::from_sat(value).is_err() {
+                return Err(format!("{} exceeds the coin supply: {}", name, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject uploads nobody will realistically pay for, with a message
+    /// explaining the quote, the limit and how to get under it
+    pub fn check_upload_limits(&self, required_satoshis: i64, chunk_count: usize, tx_outputs: usize) -> Result<(), String> {
+        let problem = if tx_outputs > self.max_tx_outputs {
+            format!(
+                "Upload too large: a transaction would need {} outputs, more than the {} allowed",
+                tx_outputs, self.max_tx_outputs
+            )
+        } else if required_satoshis > self.max_upload_cost_satoshis {
+            format!(
+                "Upload too expensive: {} satoshis exceeds the {} satoshi limit",
+                required_satoshis, self.max_upload_cost_satoshis
+            )
+        } else if self.max_chunk_count > 0 && chunk_count > self.max_chunk_count {
+            format!(
+                "Upload too large: {} chunk transactions exceeds the limit of {}",
+                chunk_count, self.max_chunk_count
+            )
+        } else {
+            return Ok(());
+        };
+        Err(format!(
+            "{}. Try compressing the file, converting WAV to FLAC, or uploading a smaller file.",
+            problem
+        ))
+    }
+
+    /// Watcher sleep before the next round of payment checks
+    pub fn payment_poll_interval(&self) -> Duration {
+        jittered(Duration::from_secs(self.payment_poll_interval_seconds.max(1)), self.poll_jitter_percent)
+    }
+
+    /// Milliseconds a status page should wait before polling again
+    pub fn status_poll_after_ms(&self) -> u64 {
+        jittered(Duration::from_millis(self.status_poll_interval_ms.max(500)), self.poll_jitter_percent).as_millis() as u64
+    }
+
+    /// Where abandoned payments on a network are swept to, if configured (none for STN)
+    pub fn sweep_address(&self, network: Network) -> Option<&str> {
+        let address = match network {
+            Network::Mainnet => self.sweep_address_mainnet.as_deref(),
+            Network::Testnet => self.sweep_address_testnet.as_deref(),
+            Network::Stn => None,
+        };
+        address.filter(|a| !a.trim().is_empty())
+    }
+}
+
+/// First non-empty variable in `names`, without a trailing slash
+fn base_url(names: &[&str], default: &str) -> String {
+    names
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .find(|url| !url.is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_api_urls_need_a_scheme() {
+        let config = crate::test_support::test_config();
+        assert!(config.validate().is_ok());
+        type UrlField = fn(&mut Config) -> &mut String;
+        let fields: [(&str, UrlField); 4] = [
+            ("Bitails", |c| &mut c.bitails_api_url),
+            ("WhatsOnChain mainnet", |c| &mut c.whatsonchain_mainnet_url),
+            ("WhatsOnChain testnet", |c| &mut c.whatsonchain_testnet_url),
+            ("WhatsOnChain STN", |c| &mut c.whatsonchain_stn_url),
+        ];
+        for (name, field) in fields {
+            let mut config = config.clone();
+            *field(&mut config) = "api.example.com".to_string();
+            let error = config.validate().unwrap_err();
+            assert!(error.starts_with(&format!("{} API URL needs", name)), "{}", error);
+        }
+    }
+
+    #[test]
+    fn base_url_takes_the_first_override_set() {
+        let names = ["CONFIG_TEST_PROVIDER_URL", "CONFIG_TEST_NETWORK_URL"];
+        assert_eq!(base_url(&names, "https://default.example"), "https://default.example");
+        env::set_var(names[1], "http://127.0.0.1:3000/v1/");
+        assert_eq!(base_url(&names, "https://default.example"), "http://127.0.0.1:3000/v1");
+        // An empty provider override falls through to the network's
+        env::set_var(names[0], " ");
+        assert_eq!(base_url(&names, "https://default.example"), "http://127.0.0.1:3000/v1");
+        env::set_var(names[0], "https://mirror.example/");
+        assert_eq!(base_url(&names, "https://default.example"), "https://mirror.example");
+    }
 }
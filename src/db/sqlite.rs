@@ -1,9 +1,25 @@
 use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, OptionalExtension, Result};
 use std::path::Path;
 use std::sync::Mutex;
 
-use crate::models::{Job, JobStatus, JobSummary, JobType};
+use crate::models::{
+    AdminJobSummary, ApiKey, BroadcastAttempt, BroadcastAttemptRecord, ErrorCode, Job, JobEvent, JobStatus, JobSummary, JobType,
+    MessageKey, Network, PaymentRecord, StatusMessage,
+};
+
+/// Column list shared by every query that maps rows through `row_to_job`
+const JOB_COLUMNS: &str = "id, job_type, status, filename, file_size, file_data,
+    payment_address, payment_wif, required_satoshis,
+    manifest_txid, download_link, message, progress,
+    created_at, updated_at, track_title, artist_name, cover_txid, cover_data, lyrics, network,
+    error_code, bytes_done, bytes_total, eta_seconds, parent_id, owner_token, to_address, amount_satoshis, fee_satoshis,
+    funding_txid, sender_address, royalty_address, royalty_satoshis, chunk_txids, message_key, message_params, derivation_index, split_txid,
+    content_sha256, client_ip, payment_received_at, payment_received_satoshis, manifest_json,
+    mime_type";
+
+// Weight of the newest sample in the rolling throughput average
+const THROUGHPUT_SMOOTHING: f64 = 0.3;
 
 pub struct Database {
     conn: Mutex<Connection>,
@@ -51,6 +67,35 @@ impl Database {
         let _ = conn.execute("ALTER TABLE jobs ADD COLUMN cover_data BLOB", []);
         let _ = conn.execute("ALTER TABLE jobs ADD COLUMN lyrics TEXT", []);
         let _ = conn.execute("ALTER TABLE jobs ADD COLUMN network TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN error_code TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN bytes_done INTEGER", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN bytes_total INTEGER", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN eta_seconds INTEGER", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN throughput_bps REAL", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN bytes_updated_at TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN parent_id TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN owner_token TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN to_address TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN amount_satoshis INTEGER", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN fee_satoshis INTEGER", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN funding_txid TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN sender_address TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN royalty_address TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN royalty_satoshis INTEGER", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN api_key_id TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN chunk_txids TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN message_key TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN message_params TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN derivation_index INTEGER", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN split_txid TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN content_sha256 TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN client_ip TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN payment_received_at TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN payment_received_satoshis INTEGER", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN manifest_json TEXT", []);
+        let _ = conn.execute("ALTER TABLE jobs ADD COLUMN mime_type TEXT", []);
+        // Job creation counts pending jobs on every request
+        let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_jobs_status ON jobs (status)", []);
 
         // Create admin_config table
         conn.execute(
@@ -65,6 +110,127 @@ impl Database {
             [],
         )?;
 
+        // Default creator royalty applied to FLAC uploads that don't set one
+        let _ = conn.execute("ALTER TABLE admin_config ADD COLUMN royalty_address_mainnet TEXT", []);
+        let _ = conn.execute("ALTER TABLE admin_config ADD COLUMN royalty_address_testnet TEXT", []);
+        let _ = conn.execute("ALTER TABLE admin_config ADD COLUMN royalty_satoshis INTEGER", []);
+        // Part of every admin session signature; bumping it ends all sessions
+        let _ = conn.execute("ALTER TABLE admin_config ADD COLUMN session_epoch INTEGER NOT NULL DEFAULT 0", []);
+
+        // Chunks already on chain, so uploads sharing a chunk reference it instead of paying again
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS stored_chunks (
+                chunk_hash TEXT NOT NULL,
+                network TEXT NOT NULL,
+                txid TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (chunk_hash, network)
+            )",
+            [],
+        )?;
+
+        // Index inscribed in the chunk; a chunk is only reused at the same index,
+        // since downloads place chunks by it. Older rows have none until replaced.
+        let _ = conn.execute("ALTER TABLE stored_chunks ADD COLUMN chunk_index INTEGER", []);
+
+        // Covers attached to tracks after upload; the newest link for a manifest wins
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cover_links (
+                manifest_txid TEXT NOT NULL,
+                network TEXT NOT NULL,
+                cover_txid TEXT NOT NULL,
+                update_txid TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Small copies of cover images for listings, keyed by the full cover's txid
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cover_thumbs (
+                cover_txid TEXT PRIMARY KEY,
+                thumb BLOB NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Every provider answer to a broadcast, so support can see who rejected what
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS broadcast_attempts (
+                job_id TEXT,
+                txid TEXT NOT NULL,
+                network TEXT NOT NULL,
+                provider TEXT NOT NULL,
+                http_status INTEGER,
+                accepted_txid TEXT,
+                error TEXT,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        let _ = conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_broadcast_attempts_job ON broadcast_attempts (job_id)",
+            [],
+        );
+        let _ = conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_broadcast_attempts_txid ON broadcast_attempts (txid)",
+            [],
+        );
+
+        // Raw hex of accepted transactions, so one that drops out of mempools can be re-broadcast
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS broadcast_txs (
+                txid TEXT PRIMARY KEY,
+                network TEXT NOT NULL,
+                raw_tx TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Each status and progress update of a job, so a failed upload's steps can be traced
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS job_events (
+                job_id TEXT NOT NULL,
+                status TEXT,
+                progress REAL,
+                message TEXT NOT NULL,
+                message_key TEXT,
+                message_params TEXT,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_job_events_job ON job_events (job_id)", []);
+
+        // Every transaction seen paying a job's address, so double payments can be traced
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS payments (
+                job_id TEXT NOT NULL,
+                txid TEXT NOT NULL,
+                satoshis INTEGER NOT NULL,
+                block_height INTEGER,
+                first_seen_at TEXT NOT NULL,
+                PRIMARY KEY (job_id, txid)
+            )",
+            [],
+        )?;
+
+        // Next HD index to hand out, so no two jobs ever share a derived payment key
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS hd_state (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                next_index INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        let _ = conn.execute(
+            "INSERT OR IGNORE INTO hd_state (id, next_index)
+             VALUES (1, (SELECT COALESCE(MAX(derivation_index) + 1, 0) FROM jobs))",
+            [],
+        );
+
         // Insert default config if not exists
         let _ = conn.execute(
             "INSERT OR IGNORE INTO admin_config (id, admin_pay_mainnet, admin_pay_testnet, updated_at) 
@@ -72,6 +238,20 @@ impl Database {
             params![Utc::now().to_rfc3339()],
         );
 
+        // API keys of programmatic uploaders; only a SHA-256 of each key is kept
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS api_keys (
+                id TEXT PRIMARY KEY,
+                key_hash TEXT NOT NULL UNIQUE,
+                label TEXT NOT NULL,
+                monthly_quota_bytes INTEGER,
+                enabled INTEGER NOT NULL DEFAULT 1,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_jobs_api_key ON jobs (api_key_id, created_at)", []);
+
         Ok(Database {
             conn: Mutex::new(conn),
         })
@@ -84,8 +264,13 @@ impl Database {
                 id, job_type, status, filename, file_size, file_data,
                 payment_address, payment_wif, required_satoshis,
                 manifest_txid, download_link, message, progress,
-                created_at, updated_at, track_title, artist_name, cover_txid, cover_data, lyrics, network
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)",
+                created_at, updated_at, track_title, artist_name, cover_txid, cover_data, lyrics, network,
+                error_code, bytes_done, bytes_total, eta_seconds, parent_id, owner_token,
+                to_address, amount_satoshis, fee_satoshis, funding_txid, sender_address,
+                royalty_address, royalty_satoshis, chunk_txids, message_key, message_params, derivation_index,
+                split_txid, content_sha256, client_ip
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22,
+                      ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33, ?34, ?35, ?36, ?37, ?38, ?39, ?40, ?41)",
             params![
                 job.id,
                 job.job_type.as_str(),
@@ -107,21 +292,46 @@ impl Database {
                 job.cover_txid,
                 job.cover_data,
                 job.lyrics,
-                job.network,
+                job.network.map(|n| n.as_str()),
+                job.error_code.map(|c| c.as_str()),
+                job.bytes_done,
+                job.bytes_total,
+                job.eta_seconds,
+                job.parent_id,
+                job.owner_token,
+                job.to_address,
+                job.amount_satoshis,
+                job.fee_satoshis,
+                job.funding_txid,
+                job.sender_address,
+                job.royalty_address,
+                job.royalty_satoshis,
+                job.chunk_txids,
+                job.message_key,
+                job.message_params,
+                job.derivation_index,
+                job.split_txid,
+                job.content_sha256,
+                job.client_ip,
             ],
         )?;
         Ok(())
     }
 
-    pub fn get_job(&self, id: &str) -> Result<Option<Job>> {
+    /// Reserve the next HD derivation index
+    pub fn next_derivation_index(&self) -> Result<u32> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, job_type, status, filename, file_size, file_data,
-                    payment_address, payment_wif, required_satoshis,
-                    manifest_txid, download_link, message, progress,
-                    created_at, updated_at, track_title, artist_name, cover_txid, cover_data, lyrics, network
-             FROM jobs WHERE id = ?1",
+        let index: i64 = conn.query_row(
+            "UPDATE hd_state SET next_index = next_index + 1 WHERE id = 1 RETURNING next_index - 1",
+            [],
+            |row| row.get(0),
         )?;
+        u32::try_from(index).map_err(|_| rusqlite::Error::IntegralValueOutOfRange(0, index))
+    }
+
+    pub fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS))?;
 
         let mut rows = stmt.query(params![id])?;
 
@@ -134,13 +344,7 @@ impl Database {
 
     pub fn get_processing_jobs(&self) -> Result<Vec<Job>> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, job_type, status, filename, file_size, file_data,
-                    payment_address, payment_wif, required_satoshis,
-                    manifest_txid, download_link, message, progress,
-                    created_at, updated_at, track_title, artist_name, cover_txid, cover_data, lyrics, network
-             FROM jobs WHERE status = 'processing'",
-        )?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM jobs WHERE status = 'processing'", JOB_COLUMNS))?;
 
         let mut jobs = Vec::new();
         let mut rows = stmt.query([])?;
@@ -154,13 +358,7 @@ impl Database {
 
     pub fn get_pending_payment_jobs(&self) -> Result<Vec<Job>> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, job_type, status, filename, file_size, file_data,
-                    payment_address, payment_wif, required_satoshis,
-                    manifest_txid, download_link, message, progress,
-                    created_at, updated_at, track_title, artist_name, cover_txid, cover_data, lyrics, network
-             FROM jobs WHERE status = 'pending_payment'",
-        )?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM jobs WHERE status = 'pending_payment'", JOB_COLUMNS))?;
 
         let mut jobs = Vec::new();
         let mut rows = stmt.query([])?;
@@ -172,16 +370,99 @@ impl Database {
         Ok(jobs)
     }
 
-    pub fn get_all_jobs(&self) -> Result<Vec<JobSummary>> {
+    /// Completed job serving a download link. Older jobs stored the link
+    /// without percent-encoding, so both forms are matched.
+    pub fn find_completed_download(&self, link: &str, unencoded_link: &str) -> Result<Option<Job>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE status = 'complete' AND download_link IN (?1, ?2) LIMIT 1",
+            JOB_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query(params![link, unencoded_link])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(self.row_to_job(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Newest job of this kind still waiting for payment for the same file on
+    /// the same network, created since `since` by this IP or owner token.
+    /// A job with the owner token wins over a newer one from the IP alone.
+    pub fn find_pending_duplicate(
+        &self,
+        job_type: JobType,
+        network: Network,
+        content_sha256: &str,
+        client_ip: Option<&str>,
+        owner_token: Option<&str>,
+        since: DateTime<Utc>,
+    ) -> Result<Option<Job>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE status = 'pending_payment' AND job_type = ?1 AND network = ?2
+             AND content_sha256 = ?3 AND created_at >= ?4
+             AND ((?5 IS NOT NULL AND client_ip = ?5) OR (?6 IS NOT NULL AND owner_token = ?6))
+             ORDER BY (?6 IS NOT NULL AND owner_token = ?6) DESC, created_at DESC LIMIT 1",
+            JOB_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query(params![
+            job_type.as_str(),
+            network.as_str(),
+            content_sha256,
+            since.to_rfc3339(),
+            client_ip,
+            owner_token,
+        ])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(self.row_to_job(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Completed upload with this manifest txid, used to make imports idempotent
+    pub fn find_upload_by_txid(&self, txid: &str) -> Result<Option<Job>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE manifest_txid = ?1 AND status = 'complete'
+             AND job_type IN ('upload', 'flac_upload') LIMIT 1",
+            JOB_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query(params![txid])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(self.row_to_job(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_child_jobs(&self, parent_id: &str) -> Result<Vec<Job>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM jobs WHERE parent_id = ?1 ORDER BY rowid", JOB_COLUMNS))?;
+
+        let mut jobs = Vec::new();
+        let mut rows = stmt.query(params![parent_id])?;
+
+        while let Some(row) = rows.next()? {
+            jobs.push(self.row_to_job(row)?);
+        }
+
+        Ok(jobs)
+    }
+
+    pub fn get_all_jobs(&self, job_type: Option<JobType>) -> Result<Vec<JobSummary>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
             "SELECT id, job_type, status, filename, file_size,
-                    manifest_txid, message, created_at
-             FROM jobs ORDER BY created_at DESC LIMIT 100",
+                    manifest_txid, message, created_at,
+                    network, to_address, amount_satoshis, fee_satoshis, cover_txid
+             FROM jobs WHERE ?1 IS NULL OR job_type = ?1
+             ORDER BY created_at DESC LIMIT 100",
         )?;
 
         let mut jobs = Vec::new();
-        let mut rows = stmt.query([])?;
+        let mut rows = stmt.query(params![job_type.map(|t| t.as_str())])?;
 
         while let Some(row) = rows.next()? {
             let created_at_str: String = row.get(7)?;
@@ -196,12 +477,98 @@ impl Database {
                 created_at: DateTime::parse_from_rfc3339(&created_at_str)
                     .map(|dt| dt.with_timezone(&Utc))
                     .unwrap_or_else(|_| Utc::now()),
+                network: row.get::<_, Option<String>>(8)?.and_then(|n| Network::from_str(&n)),
+                to_address: row.get(9)?,
+                amount_satoshis: row.get(10)?,
+                fee_satoshis: row.get(11)?,
+                cover_txid: row.get(12)?,
             });
         }
 
         Ok(jobs)
     }
 
+    pub fn get_admin_jobs(&self) -> Result<Vec<AdminJobSummary>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, job_type, status, filename, network,
+                    payment_address, required_satoshis, funding_txid, sender_address,
+                    message, created_at, manifest_txid, cover_txid, split_txid, chunk_txids
+             FROM jobs ORDER BY created_at DESC LIMIT 100",
+        )?;
+
+        let mut jobs = Vec::new();
+        let mut rows = stmt.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let created_at_str: String = row.get(10)?;
+            jobs.push(AdminJobSummary {
+                id: row.get(0)?,
+                job_type: JobType::from_str(&row.get::<_, String>(1)?).unwrap_or(JobType::Upload),
+                status: JobStatus::from_str(&row.get::<_, String>(2)?).unwrap_or(JobStatus::Error),
+                filename: row.get(3)?,
+                network: row.get::<_, Option<String>>(4)?.and_then(|n| Network::from_str(&n)),
+                payment_address: row.get(5)?,
+                required_satoshis: row.get(6)?,
+                funding_txid: row.get(7)?,
+                sender_address: row.get(8)?,
+                manifest_txid: row.get(11)?,
+                cover_txid: row.get(12)?,
+                split_txid: row.get(13)?,
+                chunk_txids: row
+                    .get::<_, Option<String>>(14)?
+                    .map(|txids| txids.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect()),
+                message: row.get(9)?,
+                created_at: DateTime::parse_from_rfc3339(&created_at_str)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now()),
+            });
+        }
+
+        Ok(jobs)
+    }
+
+    pub fn count_jobs_with_status(&self, status: JobStatus) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT COUNT(*) FROM jobs WHERE status = ?1",
+            params![status.as_str()],
+            |row| row.get(0),
+        )
+    }
+
+    /// Satoshis quoted for jobs paid from `payment_address` since `since`
+    pub fn sum_required_satoshis_since(&self, payment_address: &str, since: DateTime<Utc>) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT COALESCE(SUM(required_satoshis), 0) FROM jobs
+             WHERE payment_address = ?1 AND created_at >= ?2",
+            params![payment_address, since.to_rfc3339()],
+            |row| row.get(0),
+        )
+    }
+
+    /// Record when the payment watcher saw a job's payment and its amount
+    pub fn update_job_payment_received(&self, id: &str, satoshis: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET payment_received_at = ?1, payment_received_satoshis = ?2
+             WHERE id = ?3 AND payment_received_at IS NULL",
+            params![Utc::now().to_rfc3339(), satoshis, id],
+        )?;
+        Ok(())
+    }
+
+    /// Record the transaction that paid for a job and the address it came from
+    pub fn update_job_funding(&self, id: &str, funding_txid: &str, sender_address: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET funding_txid = ?1, sender_address = ?2 WHERE id = ?3 AND funding_txid IS NULL",
+            params![funding_txid, sender_address, id],
+        )?;
+        Ok(())
+    }
+
     pub fn update_job_status_only(&self, id: &str, status: JobStatus) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
@@ -211,21 +578,113 @@ impl Database {
         Ok(())
     }
 
-    pub fn update_job_status(&self, id: &str, status: JobStatus, message: &str) -> Result<()> {
+    pub fn update_job_status(&self, id: &str, status: JobStatus, message: impl Into<StatusMessage>) -> Result<()> {
+        let message = message.into();
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "UPDATE jobs SET status = ?1, message = ?2, updated_at = ?3 WHERE id = ?4",
-            params![status.as_str(), message, Utc::now().to_rfc3339(), id],
+            "UPDATE jobs SET status = ?1, message = ?2, message_key = ?3, message_params = ?4, updated_at = ?5
+             WHERE id = ?6",
+            params![
+                status.as_str(),
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                Utc::now().to_rfc3339(),
+                id
+            ],
         )?;
+        Self::record_job_event(&conn, id, Some(status), None, &message)?;
         Ok(())
     }
 
-    pub fn update_job_progress(&self, id: &str, progress: f64, message: &str) -> Result<()> {
+    pub fn update_job_progress(&self, id: &str, progress: f64, message: impl Into<StatusMessage>) -> Result<()> {
+        let message = message.into();
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "UPDATE jobs SET progress = ?1, message = ?2, updated_at = ?3 WHERE id = ?4",
-            params![progress, message, Utc::now().to_rfc3339(), id],
+            "UPDATE jobs SET progress = ?1, message = ?2, message_key = ?3, message_params = ?4, updated_at = ?5
+             WHERE id = ?6",
+            params![
+                progress,
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                Utc::now().to_rfc3339(),
+                id
+            ],
         )?;
+        Self::record_job_event(&conn, id, None, Some(progress), &message)?;
+        Ok(())
+    }
+
+    /// Record transfer progress in bytes and refresh the throughput-based ETA.
+    /// Throughput is an exponential moving average over the byte deltas between calls.
+    pub fn update_job_transfer(
+        &self,
+        id: &str,
+        bytes_done: i64,
+        bytes_total: i64,
+        progress: f64,
+        message: impl Into<StatusMessage>,
+    ) -> Result<()> {
+        let message = message.into();
+        let conn = self.conn.lock().unwrap();
+        let now = Utc::now();
+
+        let previous: Option<(Option<i64>, Option<f64>, Option<String>)> = conn
+            .query_row(
+                "SELECT bytes_done, throughput_bps, bytes_updated_at FROM jobs WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        let (prev_bytes, mut throughput, prev_at) = previous.unwrap_or((None, None, None));
+        let prev_at = prev_at
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+            .map(|dt| dt.with_timezone(&Utc));
+
+        if let (Some(prev_bytes), Some(prev_at)) = (prev_bytes, prev_at) {
+            let elapsed = (now - prev_at).num_milliseconds() as f64 / 1000.0;
+            if bytes_done > prev_bytes && elapsed > 0.0 {
+                let sample = (bytes_done - prev_bytes) as f64 / elapsed;
+                throughput = Some(match throughput {
+                    Some(tp) => THROUGHPUT_SMOOTHING * sample + (1.0 - THROUGHPUT_SMOOTHING) * tp,
+                    None => sample,
+                });
+            }
+        }
+
+        let eta_seconds = throughput
+            .filter(|tp| *tp > 0.0)
+            .map(|tp| ((bytes_total - bytes_done).max(0) as f64 / tp).ceil() as i64);
+
+        // Only move the byte timestamp when bytes advanced so retries don't skew the rate
+        let bytes_updated_at = if prev_bytes == Some(bytes_done) {
+            prev_at.unwrap_or(now)
+        } else {
+            now
+        };
+
+        conn.execute(
+            "UPDATE jobs SET progress = ?1, message = ?2, message_key = ?3, message_params = ?4,
+             bytes_done = ?5, bytes_total = ?6, throughput_bps = ?7, eta_seconds = ?8,
+             bytes_updated_at = ?9, updated_at = ?10
+             WHERE id = ?11",
+            params![
+                progress,
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                bytes_done,
+                bytes_total,
+                throughput,
+                eta_seconds,
+                bytes_updated_at.to_rfc3339(),
+                now.to_rfc3339(),
+                id
+            ],
+        )?;
+        Self::record_job_event(&conn, id, None, Some(progress), &message)?;
         Ok(())
     }
 
@@ -235,12 +694,15 @@ impl Database {
         manifest_txid: &str,
         download_link: Option<&str>,
     ) -> Result<()> {
+        let message = StatusMessage::from(MessageKey::Complete);
         let conn = self.conn.lock().unwrap();
         conn.execute(
             "UPDATE jobs SET status = 'complete', manifest_txid = ?1, download_link = ?2,
-             message = 'Complete', progress = 100.0, updated_at = ?3 WHERE id = ?4",
-            params![manifest_txid, download_link, Utc::now().to_rfc3339(), id],
+             message = 'Complete', message_key = ?3, message_params = NULL, progress = 100.0,
+             bytes_done = bytes_total, eta_seconds = 0, updated_at = ?4 WHERE id = ?5",
+            params![manifest_txid, download_link, MessageKey::Complete.as_str(), Utc::now().to_rfc3339(), id],
         )?;
+        Self::record_job_event(&conn, id, Some(JobStatus::Complete), Some(100.0), &message)?;
         Ok(())
     }
 
@@ -250,25 +712,249 @@ impl Database {
         manifest_txid: &str,
         download_link: Option<&str>,
         filename: &str,
+        mime_type: &str,
     ) -> Result<()> {
+        let message = StatusMessage::from(MessageKey::Complete);
         let conn = self.conn.lock().unwrap();
         conn.execute(
             "UPDATE jobs SET status = 'complete', manifest_txid = ?1, download_link = ?2,
-             filename = ?3, message = 'Complete', progress = 100.0, updated_at = ?4 WHERE id = ?5",
-            params![manifest_txid, download_link, filename, Utc::now().to_rfc3339(), id],
+             filename = ?3, mime_type = ?7, message = 'Complete', message_key = ?4, message_params = NULL, progress = 100.0,
+             bytes_done = bytes_total, eta_seconds = 0, updated_at = ?5 WHERE id = ?6",
+            params![
+                manifest_txid,
+                download_link,
+                filename,
+                MessageKey::Complete.as_str(),
+                Utc::now().to_rfc3339(),
+                id,
+                mime_type
+            ],
         )?;
+        Self::record_job_event(&conn, id, Some(JobStatus::Complete), Some(100.0), &message)?;
         Ok(())
     }
 
-    pub fn update_job_error(&self, id: &str, message: &str) -> Result<()> {
+    pub fn update_job_error(&self, id: &str, code: ErrorCode, message: impl Into<StatusMessage>) -> Result<()> {
+        let message = message.into();
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "UPDATE jobs SET status = 'error', message = ?1, updated_at = ?2 WHERE id = ?3",
-            params![message, Utc::now().to_rfc3339(), id],
+            "UPDATE jobs SET status = 'error', error_code = ?1, message = ?2, message_key = ?3, message_params = ?4,
+             updated_at = ?5 WHERE id = ?6",
+            params![
+                code.as_str(),
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                Utc::now().to_rfc3339(),
+                id
+            ],
         )?;
+        Self::record_job_event(&conn, id, Some(JobStatus::Error), None, &message)?;
+        // Inside a job's span this also lands in the job's processing log
+        tracing::warn!("Job {} failed ({}): {}", id, code.as_str(), message.english());
         Ok(())
     }
 
+    /// Clear file data of jobs that finished before the cutoff; nothing reads
+    /// it again once a job is complete, failed or cancelled.
+    /// The row, metadata and txids are kept for history. Returns the number
+    /// of jobs cleared and the bytes freed.
+    pub fn clear_finished_job_blobs(&self, cutoff: DateTime<Utc>) -> Result<(usize, u64)> {
+        let conn = self.conn.lock().unwrap();
+        let filter = "status IN ('complete', 'error', 'cancelled') AND updated_at < ?1
+               AND (file_data IS NOT NULL OR cover_data IS NOT NULL)";
+        let bytes: i64 = conn.query_row(
+            &format!(
+                "SELECT COALESCE(SUM(COALESCE(LENGTH(file_data), 0) + COALESCE(LENGTH(cover_data), 0)), 0)
+                 FROM jobs WHERE {}",
+                filter
+            ),
+            params![cutoff.to_rfc3339()],
+            |row| row.get(0),
+        )?;
+        let cleared = conn.execute(
+            &format!("UPDATE jobs SET file_data = NULL, cover_data = NULL WHERE {}", filter),
+            params![cutoff.to_rfc3339()],
+        )?;
+        Ok((cleared, bytes as u64))
+    }
+
+    /// Every download link a job still points at
+    pub fn download_links(&self) -> Result<std::collections::HashSet<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT DISTINCT download_link FROM jobs WHERE download_link IS NOT NULL")?;
+        let links = stmt.query_map([], |row| row.get(0))?.collect::<Result<_>>()?;
+        Ok(links)
+    }
+
+    /// Mark a processing job cancelled, keeping its progress and byte counts
+    pub fn update_job_cancelled(&self, id: &str, message: impl Into<StatusMessage>) -> Result<bool> {
+        let message = message.into();
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE jobs SET status = 'cancelled', message = ?1, message_key = ?2, message_params = ?3,
+             eta_seconds = NULL, updated_at = ?4
+             WHERE id = ?5 AND status = 'processing'",
+            params![
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                Utc::now().to_rfc3339(),
+                id
+            ],
+        )?;
+        if updated > 0 {
+            Self::record_job_event(&conn, id, Some(JobStatus::Cancelled), None, &message)?;
+        }
+        Ok(updated > 0)
+    }
+
+    /// Jobs whose throwaway payment key may still hold coins: unpaid jobs
+    /// created before `cutoff`, jobs that expired waiting for payment and
+    /// jobs refused because their API key was revoked.
+    /// HD-derived keys only store their index and are re-derived for the sweep.
+    pub fn get_abandoned_funded_jobs(&self, cutoff: DateTime<Utc>) -> Result<Vec<Job>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs
+             WHERE (payment_wif IS NOT NULL OR derivation_index IS NOT NULL) AND payment_address IS NOT NULL
+               AND ((status = 'pending_payment' AND created_at < ?1)
+                    OR (status = 'error' AND error_code IN (?2, ?3)))
+             ORDER BY created_at",
+            JOB_COLUMNS
+        ))?;
+
+        let mut jobs = Vec::new();
+        let mut rows = stmt.query(params![
+            cutoff.to_rfc3339(),
+            ErrorCode::PaymentExpired.as_str(),
+            ErrorCode::InvalidApiKey.as_str()
+        ])?;
+
+        while let Some(row) = rows.next()? {
+            jobs.push(self.row_to_job(row)?);
+        }
+
+        Ok(jobs)
+    }
+
+    /// Expire a job that is still waiting for payment.
+    /// Returns false if the payment arrived in the meantime.
+    pub fn expire_pending_job(&self, id: &str, message: impl Into<StatusMessage>) -> Result<bool> {
+        let message = message.into();
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE jobs SET status = 'error', error_code = ?1, message = ?2, message_key = ?3, message_params = ?4,
+             updated_at = ?5 WHERE id = ?6 AND status = 'pending_payment'",
+            params![
+                ErrorCode::PaymentExpired.as_str(),
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                Utc::now().to_rfc3339(),
+                id
+            ],
+        )?;
+        if updated > 0 {
+            Self::record_job_event(&conn, id, Some(JobStatus::Error), None, &message)?;
+        }
+        Ok(updated > 0)
+    }
+
+    /// Fail a processing job that hasn't been updated since `cutoff`.
+    /// Returns false if the job finished or made progress in the meantime.
+    pub fn mark_job_stalled(&self, id: &str, message: impl Into<StatusMessage>, cutoff: DateTime<Utc>) -> Result<bool> {
+        let message = message.into();
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE jobs SET status = 'error', error_code = ?1, message = ?2, message_key = ?3, message_params = ?4,
+             updated_at = ?5 WHERE id = ?6 AND status = 'processing' AND updated_at < ?7",
+            params![
+                ErrorCode::Stalled.as_str(),
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                Utc::now().to_rfc3339(),
+                id,
+                cutoff.to_rfc3339()
+            ],
+        )?;
+        if updated > 0 {
+            Self::record_job_event(&conn, id, Some(JobStatus::Error), None, &message)?;
+        }
+        Ok(updated > 0)
+    }
+
+    /// Fail a job that is still processing; false if it already finished
+    pub fn fail_processing_job(&self, id: &str, error_code: ErrorCode, message: impl Into<StatusMessage>) -> Result<bool> {
+        let message = message.into();
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE jobs SET status = 'error', error_code = ?1, message = ?2, message_key = ?3, message_params = ?4,
+             updated_at = ?5 WHERE id = ?6 AND status = 'processing'",
+            params![
+                error_code.as_str(),
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                Utc::now().to_rfc3339(),
+                id
+            ],
+        )?;
+        if updated > 0 {
+            Self::record_job_event(&conn, id, Some(JobStatus::Error), None, &message)?;
+        }
+        Ok(updated > 0)
+    }
+
+    /// Append a status or progress update to the job's timeline
+    fn record_job_event(
+        conn: &Connection,
+        id: &str,
+        status: Option<JobStatus>,
+        progress: Option<f64>,
+        message: &StatusMessage,
+    ) -> Result<()> {
+        conn.execute(
+            "INSERT INTO job_events (job_id, status, progress, message, message_key, message_params, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                id,
+                status.map(|s| s.as_str()),
+                progress,
+                message.english(),
+                message.key.as_str(),
+                message.params_json(),
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Every status and progress update a job went through, oldest first
+    pub fn get_job_events(&self, job_id: &str) -> Result<Vec<JobEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT status, progress, message, message_key, message_params, created_at
+             FROM job_events WHERE job_id = ?1 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(params![job_id], |row| {
+            let message_params: Option<String> = row.get(4)?;
+            let created_at: String = row.get(5)?;
+            Ok(JobEvent {
+                status: row.get(0)?,
+                progress: row.get(1)?,
+                message: row.get(2)?,
+                message_key: row.get(3)?,
+                message_params: message_params.and_then(|p| serde_json::from_str(&p).ok()),
+                created_at: DateTime::parse_from_rfc3339(&created_at)
+                    .map(|t| t.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now()),
+            })
+        })?;
+        rows.collect()
+    }
+
     fn row_to_job(&self, row: &rusqlite::Row) -> Result<Job> {
         let created_at_str: String = row.get(13)?;
         let updated_at_str: String = row.get(14)?;
@@ -298,7 +984,44 @@ impl Database {
             cover_txid: row.get(17).ok(),
             cover_data: row.get(18).ok(),
             lyrics: row.get(19).ok(),
-            network: row.get(20).ok(),
+            network: row
+                .get::<_, Option<String>>(20)
+                .ok()
+                .flatten()
+                .and_then(|n| Network::from_str(&n)),
+            error_code: row
+                .get::<_, Option<String>>(21)
+                .ok()
+                .flatten()
+                .and_then(|c| ErrorCode::from_str(&c)),
+            bytes_done: row.get(22).ok(),
+            bytes_total: row.get(23).ok(),
+            eta_seconds: row.get(24).ok(),
+            parent_id: row.get(25).ok().flatten(),
+            owner_token: row.get(26).ok().flatten(),
+            to_address: row.get(27).ok().flatten(),
+            amount_satoshis: row.get(28).ok().flatten(),
+            fee_satoshis: row.get(29).ok().flatten(),
+            funding_txid: row.get(30).ok().flatten(),
+            sender_address: row.get(31).ok().flatten(),
+            royalty_address: row.get(32).ok().flatten(),
+            royalty_satoshis: row.get(33).ok().flatten(),
+            chunk_txids: row.get(34).ok().flatten(),
+            message_key: row.get(35).ok().flatten(),
+            message_params: row.get(36).ok().flatten(),
+            derivation_index: row.get(37).ok().flatten(),
+            split_txid: row.get(38).ok().flatten(),
+            content_sha256: row.get(39).ok().flatten(),
+            client_ip: row.get(40).ok().flatten(),
+            payment_received_at: row
+                .get::<_, Option<String>>(41)
+                .ok()
+                .flatten()
+                .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+                .map(|t| t.with_timezone(&Utc)),
+            payment_received_satoshis: row.get(42).ok().flatten(),
+            manifest_json: row.get(43).ok().flatten(),
+            mime_type: row.get(44).ok().flatten(),
         })
     }
 
@@ -317,6 +1040,25 @@ impl Database {
         Ok(())
     }
 
+    /// Record the transactions a multi-chunk upload broadcast
+    pub fn update_job_manifest_json(&self, id: &str, manifest_json: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET manifest_json = ?1, updated_at = ?2 WHERE id = ?3",
+            params![manifest_json, Utc::now().to_rfc3339(), id],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_job_chunk_txids(&self, id: &str, chunk_txids: &[String], split_txid: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET chunk_txids = ?1, split_txid = ?2, updated_at = ?3 WHERE id = ?4",
+            params![chunk_txids.join(","), split_txid, Utc::now().to_rfc3339(), id],
+        )?;
+        Ok(())
+    }
+
     pub fn update_job_cover_txid(&self, id: &str, cover_txid: &str) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
@@ -326,11 +1068,221 @@ impl Database {
         Ok(())
     }
 
+    /// Link a manifest to a cover attached after upload, and show the new
+    /// cover on the catalog entries of that upload
+    pub fn record_cover_link(&self, manifest_txid: &str, network: Network, cover_txid: &str, update_txid: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let now = Utc::now().to_rfc3339();
+        conn.execute(
+            "INSERT INTO cover_links (manifest_txid, network, cover_txid, update_txid, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![manifest_txid, network.as_str(), cover_txid, update_txid, now],
+        )?;
+        conn.execute(
+            "UPDATE jobs SET cover_txid = ?1, updated_at = ?2
+             WHERE manifest_txid = ?3 AND job_type = 'flac_upload' AND status = 'complete'",
+            params![cover_txid, now, manifest_txid],
+        )?;
+        Ok(())
+    }
+
+    /// Newest cover attached to a manifest after upload, if any
+    pub fn latest_cover_link(&self, manifest_txid: &str, network: Network) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT cover_txid FROM cover_links WHERE manifest_txid = ?1 AND network = ?2
+             ORDER BY rowid DESC LIMIT 1",
+            params![manifest_txid, network.as_str()],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    /// Keep the thumbnail of a cover, replacing any older one
+    pub fn save_cover_thumb(&self, cover_txid: &str, thumb: &[u8]) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO cover_thumbs (cover_txid, thumb, created_at) VALUES (?1, ?2, ?3)",
+            params![cover_txid, thumb, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_cover_thumb(&self, cover_txid: &str) -> Result<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT thumb FROM cover_thumbs WHERE cover_txid = ?1",
+            params![cover_txid],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    /// Txid of a chunk with this SHA-256 hash already stored on the network
+    pub fn find_stored_chunk(&self, chunk_hash: &str, network: Network, chunk_index: u32) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT txid FROM stored_chunks WHERE chunk_hash = ?1 AND network = ?2 AND chunk_index = ?3",
+        )?;
+        let mut rows = stmt.query(params![chunk_hash, network.as_str(), chunk_index])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remember a broadcast chunk. The first txid recorded for a hash is kept,
+    /// unless it predates chunk indices and so can't be reused.
+    pub fn record_stored_chunk(&self, chunk_hash: &str, network: Network, chunk_index: u32, txid: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO stored_chunks (chunk_hash, network, txid, chunk_index, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (chunk_hash, network) DO UPDATE
+             SET txid = excluded.txid, chunk_index = excluded.chunk_index, created_at = excluded.created_at
+             WHERE stored_chunks.chunk_index IS NULL",
+            params![chunk_hash, network.as_str(), txid, chunk_index, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_broadcast_attempt(
+        &self,
+        job_id: Option<&str>,
+        txid: &str,
+        network: Network,
+        attempt: &BroadcastAttempt,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO broadcast_attempts (job_id, txid, network, provider, http_status, accepted_txid, error, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                job_id,
+                txid,
+                network.as_str(),
+                attempt.provider,
+                attempt.http_status,
+                attempt.accepted_txid,
+                attempt.error,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Broadcast attempts made for a job, oldest first
+    pub fn get_broadcast_attempts(&self, job_id: &str) -> Result<Vec<BroadcastAttemptRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT job_id, txid, network, provider, http_status, accepted_txid, error, created_at
+             FROM broadcast_attempts WHERE job_id = ?1 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(params![job_id], |row| {
+            let created_at: String = row.get(7)?;
+            Ok(BroadcastAttemptRecord {
+                job_id: row.get(0)?,
+                txid: row.get(1)?,
+                network: row.get(2)?,
+                attempt: BroadcastAttempt {
+                    provider: row.get(3)?,
+                    http_status: row.get(4)?,
+                    accepted_txid: row.get(5)?,
+                    error: row.get(6)?,
+                },
+                created_at: DateTime::parse_from_rfc3339(&created_at)
+                    .map(|t| t.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now()),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Record a transaction paying a job's address. Seeing it again keeps the
+    /// first-seen time and fills in the block once it is mined.
+    pub fn record_payment(&self, job_id: &str, txid: &str, satoshis: i64, block_height: Option<i64>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO payments (job_id, txid, satoshis, block_height, first_seen_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (job_id, txid) DO UPDATE SET
+                satoshis = MAX(satoshis, excluded.satoshis),
+                block_height = COALESCE(excluded.block_height, block_height)",
+            params![job_id, txid, satoshis, block_height, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_payments(&self, job_id: &str) -> Result<Vec<PaymentRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT txid, satoshis, block_height, first_seen_at
+             FROM payments WHERE job_id = ?1 ORDER BY first_seen_at, txid",
+        )?;
+        let rows = stmt.query_map(params![job_id], |row| {
+            let first_seen_at: String = row.get(3)?;
+            Ok(PaymentRecord {
+                txid: row.get(0)?,
+                satoshis: row.get(1)?,
+                block_height: row.get(2)?,
+                first_seen_at: DateTime::parse_from_rfc3339(&first_seen_at)
+                    .map(|t| t.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now()),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Whether this server broadcast a transaction and a provider accepted it
+    pub fn is_own_broadcast(&self, txid: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM broadcast_attempts WHERE txid = ?1 AND accepted_txid IS NOT NULL)",
+            params![txid],
+            |row| row.get(0),
+        )
+    }
+
+    /// Keep the raw hex of a transaction a provider accepted
+    pub fn record_broadcast_tx(&self, txid: &str, network: Network, raw_tx: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO broadcast_txs (txid, network, raw_tx, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![txid, network.as_str(), raw_tx, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Raw hex of a transaction this server broadcast, if it is still kept
+    pub fn get_broadcast_tx(&self, txid: &str, network: Network) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT raw_tx FROM broadcast_txs WHERE txid = ?1 AND network = ?2",
+            params![txid, network.as_str()],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    /// Drop raw transactions broadcast before `cutoff`, returning how many
+    /// and their size in bytes
+    pub fn clear_broadcast_txs(&self, cutoff: DateTime<Utc>) -> Result<(usize, u64)> {
+        let conn = self.conn.lock().unwrap();
+        let bytes: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(LENGTH(raw_tx)), 0) FROM broadcast_txs WHERE created_at < ?1",
+            params![cutoff.to_rfc3339()],
+            |row| row.get(0),
+        )?;
+        let cleared = conn.execute("DELETE FROM broadcast_txs WHERE created_at < ?1", params![cutoff.to_rfc3339()])?;
+        Ok((cleared, bytes as u64))
+    }
+
     // Admin config methods
     pub fn get_admin_config(&self) -> Result<AdminConfig> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT admin_pay_mainnet, admin_pay_testnet, mainnet_wif, testnet_wif, updated_at
+            "SELECT admin_pay_mainnet, admin_pay_testnet, mainnet_wif, testnet_wif, updated_at,
+                    royalty_address_mainnet, royalty_address_testnet, royalty_satoshis
              FROM admin_config WHERE id = 1",
         )?;
 
@@ -342,6 +1294,9 @@ impl Database {
                 admin_pay_testnet: row.get::<_, i32>(1)? != 0,
                 mainnet_wif: row.get(2).ok(),
                 testnet_wif: row.get(3).ok(),
+                royalty_address_mainnet: row.get(5).ok().flatten(),
+                royalty_address_testnet: row.get(6).ok().flatten(),
+                royalty_satoshis: row.get(7).ok().flatten(),
             })
         } else {
             Ok(AdminConfig::default())
@@ -352,23 +1307,266 @@ impl Database {
         let conn = self.conn.lock().unwrap();
         conn.execute(
             "UPDATE admin_config SET admin_pay_mainnet = ?1, admin_pay_testnet = ?2, 
-             mainnet_wif = ?3, testnet_wif = ?4, updated_at = ?5 WHERE id = 1",
+             mainnet_wif = ?3, testnet_wif = ?4, updated_at = ?5,
+             royalty_address_mainnet = ?6, royalty_address_testnet = ?7, royalty_satoshis = ?8 WHERE id = 1",
             params![
                 config.admin_pay_mainnet as i32,
                 config.admin_pay_testnet as i32,
                 config.mainnet_wif,
                 config.testnet_wif,
-                Utc::now().to_rfc3339()
+                Utc::now().to_rfc3339(),
+                config.royalty_address_mainnet,
+                config.royalty_address_testnet,
+                config.royalty_satoshis,
             ],
         )?;
         Ok(())
     }
+
+    pub fn admin_session_epoch(&self) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT session_epoch FROM admin_config WHERE id = 1", [], |row| row.get(0))
+    }
+
+    /// Invalidate every admin session issued so far
+    pub fn bump_admin_session_epoch(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("UPDATE admin_config SET session_epoch = session_epoch + 1 WHERE id = 1", [])?;
+        Ok(())
+    }
 }
 
+impl Database {
+    pub fn insert_api_key(&self, id: &str, key_hash: &str, label: &str, monthly_quota_bytes: Option<i64>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO api_keys (id, key_hash, label, monthly_quota_bytes, enabled, created_at)
+             VALUES (?1, ?2, ?3, ?4, 1, ?5)",
+            params![id, key_hash, label, monthly_quota_bytes, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// The key with this hash, with its usage since `month_start`
+    pub fn get_api_key_by_hash(&self, key_hash: &str, month_start: DateTime<Utc>) -> Result<Option<ApiKey>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("{} WHERE k.key_hash = ?2", API_KEY_QUERY),
+            params![month_start.to_rfc3339(), key_hash],
+            Self::row_to_api_key,
+        )
+        .optional()
+    }
+
+    /// Every key with its usage since `month_start`, newest first
+    pub fn get_api_keys(&self, month_start: DateTime<Utc>) -> Result<Vec<ApiKey>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("{} ORDER BY k.created_at DESC", API_KEY_QUERY))?;
+        let keys = stmt
+            .query_map(params![month_start.to_rfc3339()], Self::row_to_api_key)?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(keys)
+    }
+
+    /// Enable or revoke a key; false if there is no such key
+    pub fn set_api_key_enabled(&self, id: &str, enabled: bool) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute("UPDATE api_keys SET enabled = ?1 WHERE id = ?2", params![enabled as i32, id])?;
+        Ok(updated > 0)
+    }
+
+    /// Whether the job was created with an API key that has since been revoked
+    pub fn job_api_key_revoked(&self, job_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let revoked = conn
+            .query_row(
+                "SELECT k.enabled FROM jobs j JOIN api_keys k ON k.id = j.api_key_id WHERE j.id = ?1",
+                params![job_id],
+                |row| row.get::<_, i32>(0),
+            )
+            .optional()?
+            .is_some_and(|enabled| enabled == 0);
+        Ok(revoked)
+    }
+
+    /// Attribute a job to the API key it was created with
+    pub fn set_job_api_key(&self, job_id: &str, api_key_id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("UPDATE jobs SET api_key_id = ?1 WHERE id = ?2", params![api_key_id, job_id])?;
+        Ok(())
+    }
+
+    fn row_to_api_key(row: &rusqlite::Row) -> Result<ApiKey> {
+        let created_at_str: String = row.get(4)?;
+        Ok(ApiKey {
+            id: row.get(0)?,
+            label: row.get(1)?,
+            monthly_quota_bytes: row.get(2)?,
+            enabled: row.get::<_, i32>(3)? != 0,
+            created_at: DateTime::parse_from_rfc3339(&created_at_str)
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            used_bytes_this_month: row.get(5)?,
+        })
+    }
+}
+
+/// API key columns plus the bytes of its jobs created since ?1 that are
+/// waiting for payment or are being or have been written on-chain. Unpaid
+/// jobs hold their share until they expire, so several prepares can't
+/// each fit the quota and then all get paid.
+const API_KEY_QUERY: &str = "SELECT k.id, k.label, k.monthly_quota_bytes, k.enabled, k.created_at,
+        (SELECT COALESCE(SUM(j.file_size), 0) FROM jobs j
+         WHERE j.api_key_id = k.id AND j.created_at >= ?1
+           AND j.status IN ('pending_payment', 'processing', 'complete'))
+     FROM api_keys k";
+
 #[derive(Debug, Clone, Default)]
 pub struct AdminConfig {
     pub admin_pay_mainnet: bool,
     pub admin_pay_testnet: bool,
     pub mainnet_wif: Option<String>,
     pub testnet_wif: Option<String>,
+    pub royalty_address_mainnet: Option<String>,
+    pub royalty_address_testnet: Option<String>,
+    pub royalty_satoshis: Option<i64>,
+}
+
+impl AdminConfig {
+    /// Default royalty (address, satoshis) for uploads on a network
+    pub fn default_royalty(&self, network: Network) -> Option<(String, i64)> {
+        let address = match network {
+            Network::Mainnet => self.royalty_address_mainnet.clone(),
+            Network::Testnet => self.royalty_address_testnet.clone(),
+            Network::Stn => None,
+        };
+        Some((address?, self.royalty_satoshis?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> Database {
+        Database::new(":memory:").unwrap()
+    }
+
+    fn upload_job(id: &str) -> Job {
+        Job::new_upload(
+            id.to_string(),
+            "file.txt".to_string(),
+            4,
+            b"data".to_vec(),
+            "1BoatSLRHtKNngkdXEeobR76b53LETtpyT".to_string(),
+            "wif".to_string(),
+            1000,
+        )
+    }
+
+    #[test]
+    fn abandoned_jobs_include_hd_derived_keys() {
+        let db = test_db();
+        db.insert_job(&upload_job("random")).unwrap();
+        db.insert_job(&upload_job("derived").with_derivation_index(Some(7))).unwrap();
+        let mut keyless = upload_job("keyless");
+        keyless.payment_wif = None;
+        db.insert_job(&keyless).unwrap();
+
+        let cutoff = Utc::now() + chrono::Duration::minutes(1);
+        let ids: Vec<String> = db.get_abandoned_funded_jobs(cutoff).unwrap().into_iter().map(|j| j.id).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&"random".to_string()));
+        assert!(ids.contains(&"derived".to_string()));
+
+        let derived = db.get_job("derived").unwrap().unwrap();
+        assert_eq!(derived.payment_wif, None);
+        assert_eq!(derived.derivation_index, Some(7));
+    }
+
+    #[test]
+    fn transfer_reports_bytes_and_eta() {
+        let db = test_db();
+        db.insert_job(&upload_job("job")).unwrap();
+        db.update_job_transfer("job", 0, 3000, 10.0, MessageKey::UploadingChunks.with("n", 3)).unwrap();
+        let job = db.get_job("job").unwrap().unwrap();
+        assert_eq!((job.bytes_done, job.bytes_total, job.eta_seconds), (Some(0), Some(3000), None));
+
+        // Two chunks of 1000 bytes give a rate, and the last chunk's ETA from it
+        for done in [1000, 2000] {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            let message = MessageKey::ChunkBroadcast.with("i", done / 1000).with("n", 3);
+            db.update_job_transfer("job", done, 3000, 10.0 + 70.0 * done as f64 / 3000.0, message).unwrap();
+        }
+        let job = db.get_job("job").unwrap().unwrap();
+        assert_eq!((job.bytes_done, job.bytes_total), (Some(2000), Some(3000)));
+        let eta = job.eta_seconds.unwrap();
+        assert!((1..=60).contains(&eta), "{}", eta);
+    }
+
+    #[test]
+    fn flac_job_round_trips_every_field() {
+        let db = test_db();
+        let job = Job::new_flac_upload(
+            "flac".to_string(),
+            "song.flac".to_string(),
+            8,
+            b"fLaC\0\0\0\0".to_vec(),
+            "1BoatSLRHtKNngkdXEeobR76b53LETtpyT".to_string(),
+            "wif".to_string(),
+            5000,
+        )
+        .with_track_metadata(Some("Title".to_string()), Some("Artist".to_string()), Some("la la".to_string()))
+        .with_cover_data(Some(vec![0x89, b'P', b'N', b'G']))
+        .with_royalty(Some(("1BoatSLRHtKNngkdXEeobR76b53LETtpyT".to_string(), 600)))
+        .with_network(Network::Testnet)
+        .with_derivation_index(Some(3))
+        .with_submission("ab".repeat(32), Some("127.0.0.1".to_string()))
+        .with_status(JobStatus::Processing, MessageKey::Starting);
+        db.insert_job(&job).unwrap();
+
+        let read = db.get_job("flac").unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&read).unwrap(), serde_json::to_value(&job).unwrap());
+    }
+
+    #[test]
+    fn clearing_blobs_keeps_the_completed_job() {
+        let db = test_db();
+        let track = |id: &str| {
+            Job::new_flac_upload(
+                id.to_string(),
+                "song.flac".to_string(),
+                4,
+                b"fLaC".to_vec(),
+                "1BoatSLRHtKNngkdXEeobR76b53LETtpyT".to_string(),
+                "wif".to_string(),
+                5000,
+            )
+            .with_track_metadata(Some("Title".to_string()), Some("Artist".to_string()), None)
+            .with_cover_data(Some(b"cover".to_vec()))
+        };
+        db.insert_job(&track("done")).unwrap();
+        db.insert_job(&track("running").with_status(JobStatus::Processing, MessageKey::Starting)).unwrap();
+        db.update_job_complete("done", &"ab".repeat(32), Some("/download/done")).unwrap();
+
+        // Completed just now, so not older than an hour
+        assert_eq!(db.clear_finished_job_blobs(Utc::now() - chrono::Duration::hours(1)).unwrap(), (0, 0));
+        let later = Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(db.clear_finished_job_blobs(later).unwrap(), (1, 9));
+        assert_eq!(db.clear_finished_job_blobs(later).unwrap(), (0, 0));
+
+        let done = db.get_job("done").unwrap().unwrap();
+        assert_eq!((done.file_data, done.cover_data), (None, None));
+        assert_eq!(done.status, JobStatus::Complete);
+        assert_eq!(done.manifest_txid, Some("ab".repeat(32)));
+        assert_eq!(done.download_link.as_deref(), Some("/download/done"));
+        assert_eq!(done.filename.as_deref(), Some("song.flac"));
+        assert_eq!(done.file_size, Some(4));
+        assert_eq!(done.track_title.as_deref(), Some("Title"));
+        assert_eq!(done.artist_name.as_deref(), Some("Artist"));
+
+        let running = db.get_job("running").unwrap().unwrap();
+        assert_eq!(running.file_data.as_deref(), Some(&b"fLaC"[..]));
+        assert_eq!(running.cover_data.as_deref(), Some(&b"cover"[..]));
+    }
 }
@@ -3,50 +3,155 @@ mod db;
 mod models;
 mod routes;
 mod services;
+#[cfg(test)]
+mod test_support;
 
 use axum::{
     extract::DefaultBodyLimit,
     routing::{get, post},
     Router,
 };
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tower_http::services::ServeDir;
-use tracing_subscriber;
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
 use crate::config::Config;
 use crate::db::Database;
 use crate::models::job::JobType;
-use crate::services::bitails::{BitailsClient, Utxo};
-use crate::services::bsv::BsvService;
+use crate::models::{broadcast_outcome, Amount, BroadcastAttempt, BroadcastError, ErrorCode, MessageKey, Network, StatusMessage};
+use crate::services::admin_session::AdminSessions;
+use crate::services::bitails::BitailsClient;
+use crate::services::bsv::{BsvService, CoverThumb, ManifestLayout, ProviderTxLimits, SplitPlan, SplitShortfall};
+use crate::services::cancellation::JobCancellations;
+use crate::services::funding_keys::FundingKeys;
+use crate::services::hd::HdWallet;
+use crate::services::maintenance::{MaintenanceReport, MaintenanceStats};
+use crate::services::scheduler::{JobScheduler, QueuedJob};
+use crate::services::tx_parse::{
+    assemble_chunks, chunk_matches, extract_flac_chunk_from_tx, extract_flac_from_tx, extract_flac_manifest_from_tx,
+    extract_op_return_from_tx, extract_pubkey_from_script_sig, find_in_outputs, is_tx_hex_for, parse_flac_chunk_script,
+    parse_flac_manifest_output, parse_flac_output, parse_image_output, parse_lyrics_output, parse_transaction,
+    parse_upfile_output, FlacData,
+};
 
 pub struct AppState {
     pub db: Database,
     pub config: Config,
     pub bitails: BitailsClient,
     pub bsv: BsvService,
+    pub scheduler: JobScheduler,
+    pub cancellations: JobCancellations,
+    /// Keys of user-funded uploads, kept out of the database
+    pub funding_keys: FundingKeys,
+    /// Derives payment keys when an HD seed is configured
+    pub hd_wallet: Option<HdWallet>,
+    pub maintenance: MaintenanceStats,
+    pub admin_sessions: AdminSessions,
 }
 
 #[tokio::main]
 async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
     // Load configuration
     dotenvy::dotenv().ok();
     let config = Config::from_env();
+    config.validate().expect("Invalid configuration");
+
+    // Initialize tracing. Events inside a job's span also go to that job's
+    // log for support, with the configured secrets redacted.
+    let mut secrets = vec![routes::admin::get_admin_key(), config.hd_passphrase.clone()];
+    secrets.extend(config.bitails_api_keys.iter().cloned());
+    secrets.extend(
+        [&config.bsv_private_key, &config.hd_seed, &config.admin_session_secret]
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::filter::LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(services::job_log::JobLogLayer::new(config.job_log_max_kb * 1024, secrets))
+        .init();
+
+    // `selftest` asks the running server to run its self-test instead of serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("selftest") {
+        std::process::exit(services::selftest::cli(&args[1..], config.port).await);
+    }
 
     // Initialize database
     let db = Database::new(&config.database_path).expect("Failed to initialize database");
 
+    // Set up the shared HTTP client before anything makes a request, so a
+    // configured proxy can't be bypassed. A bad proxy URL stops startup
+    // rather than silently sending traffic direct.
+    services::http::init(config.outbound_proxy_url.as_deref(), &config.user_agent).expect("Failed to configure HTTP client");
+    if let Some(ref url) = config.outbound_proxy_url {
+        tracing::info!("Routing chain API requests through proxy {}", url.split('@').next_back().unwrap_or_default());
+    }
+
     // Initialize Bitails client
     let bitails = BitailsClient::new(
         config.bitails_api_url.clone(),
-        config.bitails_api_key.clone(),
+        config.bitails_api_keys.clone(),
     );
 
     // Initialize BSV service
-    let bsv = BsvService::new(config.bsv_private_key.clone(), config.bsv_fee_rate);
+    if !config.bsv_sighash_forkid {
+        tracing::warn!("SIGHASH_FORKID disabled: signatures will not be valid on BSV");
+    }
+    // A split batch also pays change, so it stays one output under the relay limit
+    let max_split_outputs = config.max_split_outputs.min(config.max_tx_outputs.saturating_sub(1).max(2));
+    if max_split_outputs < config.max_split_outputs {
+        tracing::warn!(
+            "MAX_SPLIT_OUTPUTS={} exceeds MAX_TX_OUTPUTS={}; splitting in batches of {}",
+            config.max_split_outputs,
+            config.max_tx_outputs,
+            max_split_outputs
+        );
+    }
+    let bsv = BsvService::new(
+        config.bsv_private_key.clone(),
+        config.bsv_fee_rate,
+        config.bsv_sighash_forkid,
+        config.max_push_size,
+        Amount::from_sat(config.data_output_satoshis).expect("DATA_OUTPUT_SATOSHIS exceeds the coin supply"),
+        Amount::from_sat(config.dust_limit_satoshis).expect("DUST_LIMIT_SATOSHIS exceeds the coin supply"),
+        max_split_outputs,
+        config.max_split_inputs,
+        config.flac_single_tx_max_bytes,
+        config.lyrics_inline_max_bytes,
+        ProviderTxLimits {
+            bitails: config.bitails_max_tx_bytes,
+            whatsonchain: config.whatsonchain_max_tx_bytes,
+        },
+    );
+
+    crate::services::whatsonchain::init(
+        &config.whatsonchain_mainnet_url,
+        &config.whatsonchain_testnet_url,
+        &config.whatsonchain_stn_url,
+    );
+
+    // Throttle WhatsOnChain before any background task starts calling it
+    services::rate_limit::init_whatsonchain(config.whatsonchain_requests_per_second);
+
+    // A seed that can't be read must stop startup, or payments would go to random keys nobody backed up
+    let hd_wallet = config.hd_seed.as_deref().map(|seed| {
+        HdWallet::from_config(seed, &config.hd_passphrase).expect("Invalid HD_SEED")
+    });
+    if hd_wallet.is_some() {
+        tracing::info!("Deriving payment addresses from the configured HD seed");
+    }
+
+    // Initialize job scheduler
+    let scheduler = JobScheduler::new(config.max_concurrent_jobs);
 
     // Create shared state
     let state = Arc::new(RwLock::new(AppState {
@@ -54,16 +159,59 @@ async fn main() {
         config: config.clone(),
         bitails,
         bsv,
+        scheduler,
+        cancellations: JobCancellations::new(),
+        funding_keys: FundingKeys::new(),
+        hd_wallet,
+        maintenance: MaintenanceStats::new(),
+        admin_sessions: AdminSessions::new(config.admin_session_secret.as_deref(), config.admin_session_hours),
     }));
 
+    // Jobs that were processing when the server last stopped go back in the queue
+    requeue_processing_jobs(&*state.read().await);
+
     // Spawn background payment watcher
     let watcher_state = state.clone();
     tokio::spawn(async move {
         payment_watcher(watcher_state).await;
     });
 
-    // Build router with increased body limit for large files (50MB)
-    let app = Router::new()
+    // Spawn job dispatcher that runs queued jobs as slots free up
+    let dispatcher_state = state.clone();
+    tokio::spawn(async move {
+        job_dispatcher(dispatcher_state).await;
+    });
+
+    // Spawn watchdog that times out stalled processing jobs
+    let watchdog_state = state.clone();
+    tokio::spawn(async move {
+        stall_watchdog(watchdog_state).await;
+    });
+
+    // Spawn maintenance that frees file data and downloads no job needs
+    let maintenance_state = state.clone();
+    tokio::spawn(async move {
+        maintenance_task(maintenance_state).await;
+    });
+
+    let app = router(&config, state);
+
+    // Start server
+    let addr = format!("{}:{}", config.host, config.port);
+    tracing::info!("Starting server on {}", addr);
+    
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+}
+
+/// All pages and API routes, each with its body limit
+fn router(config: &Config, state: Arc<RwLock<AppState>>) -> Router {
+    // Only routes that take a file get the large body limit; everything else
+    // is small JSON or form data and gets the JSON limit set below
+    let upload_limit = DefaultBodyLimit::max(config.upload_body_limit_bytes);
+    Router::new()
         // Pages
         .route("/", get(routes::dashboard::dashboard_page))
         .route("/upload", get(routes::upload::upload_page))
@@ -75,46 +223,72 @@ async fn main() {
         .route("/flac/player", get(routes::flac::flac_player_page))
         .route("/flac/status/:job_id", get(routes::flac::flac_status_page))
         // API endpoints
-        .route("/prepare_upload", post(routes::upload::prepare_upload))
+        .route("/prepare_upload", post(routes::upload::prepare_upload).layer(upload_limit))
         .route("/start_download", post(routes::download::start_download))
         .route("/status_update/:job_id", get(routes::status::status_update))
         .route("/api/jobs", get(routes::dashboard::get_jobs))
+        .route("/api/jobs/:job_id/cancel", post(routes::jobs::cancel_job))
+        .route("/api/jobs/:job_id/payments", get(routes::jobs::get_job_payments))
+        .route("/api/jobs/:job_id/logs", get(routes::jobs::get_job_logs))
+        .route("/api/tx/:txid/data_output", get(routes::download::get_data_output))
+        .route("/api/verify", post(routes::download::verify_file))
                 // FLAC API endpoints
-                .route("/api/flac/upload", post(routes::flac::prepare_flac_upload))
+                .route("/api/flac/upload", post(routes::flac::prepare_flac_upload).layer(upload_limit))
+                .route("/api/flac/plan", post(routes::flac::plan_flac_upload))
                 .route("/api/flac/download", post(routes::flac::start_flac_download))
+                .route("/api/flac/download/batch", post(routes::flac::start_flac_batch_download))
+                .route("/api/flac/download/:job_id/cancel", post(routes::flac::cancel_flac_download))
                 .route("/api/flac/status/:job_id", get(routes::flac::get_flac_status))
+                .route("/api/flac/status/:job_id/events", get(routes::flac::flac_status_events))
                 .route("/api/flac/cover", post(routes::flac::get_cover_image))
+                .route("/api/flac/cover/:txid", get(routes::flac::get_cover_image_file))
+                .route("/api/flac/cover/attach", post(routes::flac::prepare_cover_attach).layer(upload_limit))
+                .route("/api/flac/lyrics/:txid", get(routes::flac::get_lyrics))
+                .route("/api/flac/retry-chunk", post(routes::flac::retry_flac_chunk))
         // Wallet API endpoints
         .route("/api/wallet/generate", post(routes::wallet::generate_wallet))
         .route("/api/wallet/import", post(routes::wallet::import_wif))
+        .route("/api/wallet/export", post(routes::wallet::export_wallet))
         .route("/api/wallet/balance", post(routes::wallet::get_balance))
         .route("/api/wallet/send", post(routes::wallet::send_bsv))
                 // Admin panel
                 .route("/admin", get(routes::admin::admin_page))
+                .route("/admin/login", get(routes::admin::admin_login_page))
+                .route("/api/admin/login", post(routes::admin::admin_login))
+                .route("/api/admin/logout", post(routes::admin::admin_logout))
                 .route("/api/admin/verify", post(routes::admin::verify_admin_key))
                 .route("/api/admin/config", post(routes::admin::get_admin_config))
                 .route("/api/admin/config/update", post(routes::admin::update_admin_config))
                 .route("/api/admin/wallet/balance", post(routes::admin::get_admin_wallet_balance))
+                .route("/api/admin/wallet/rotate", post(routes::admin::rotate_admin_wallet))
                 .route("/api/admin/check-pay", post(routes::admin::check_admin_pay))
+                .route("/api/admin/jobs", post(routes::admin::get_admin_jobs))
+                .route("/api/admin/api-keys", post(routes::admin::list_api_keys))
+                .route("/api/admin/api-keys/create", post(routes::admin::create_api_key))
+                .route("/api/admin/api-keys/revoke", post(routes::admin::revoke_api_key))
+                .route("/api/admin/import_txid", post(routes::admin::import_txid))
+                .route("/api/admin/import_txids", post(routes::admin::import_txids))
+                .route("/api/admin/abandoned", post(routes::admin::get_abandoned_payments))
+                .route("/api/admin/abandoned/sweep", post(routes::admin::sweep_abandoned_payments))
+                .route("/api/admin/metrics", post(routes::admin::get_metrics))
+                .route("/api/admin/maintenance/run", post(routes::admin::run_maintenance))
+                .route("/api/admin/selftest", post(routes::admin::run_selftest))
+                .route("/api/admin/decode_tx", post(routes::admin::decode_tx).layer(upload_limit))
+                .route("/api/admin/broadcasts", post(routes::admin::get_broadcast_attempts))
+                .route("/api/admin/jobs/:job_id/log", get(routes::admin::get_job_log))
+        // Debug endpoints
+        .route("/api/debug/address-funding", post(routes::debug::address_funding))
         // Static files and downloads
         .nest_service("/static", ServeDir::new("static"))
-        .nest_service("/downloads", ServeDir::new("./data/downloads"))
-        // Set body limit to 50MB for large file uploads
-        .layer(DefaultBodyLimit::max(50 * 1024 * 1024))
-        .with_state(state);
-
-    // Start server
-    let addr = format!("{}:{}", config.host, config.port);
-    tracing::info!("Starting server on {}", addr);
-    
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+        .route("/downloads/:filename", get(routes::download::serve_download))
+        .layer(DefaultBodyLimit::max(config.json_body_limit_bytes))
+        .with_state(state)
 }
 
 /// Background payment watcher
 async fn payment_watcher(state: Arc<RwLock<AppState>>) {
-    use crate::models::job::{JobStatus, JobType};
-    use tokio::time::{sleep, Duration};
+    use crate::models::job::JobStatus;
+    use tokio::time::sleep;
 
     loop {
         // Get pending payment jobs
@@ -123,1488 +297,4980 @@ async fn payment_watcher(state: Arc<RwLock<AppState>>) {
             state.db.get_pending_payment_jobs().unwrap_or_default()
         };
 
+        // Jobs on WhatsOnChain networks are checked a batch of addresses per
+        // request; anything a batch misses is checked on its own below
+        let mut prefetched = prefetch_whatsonchain_utxos(&pending_jobs).await;
+
         for job in pending_jobs {
             let state_clone = state.clone();
             let job_id = job.id.clone();
             let address = job.payment_address.clone().unwrap_or_default();
             let job_type = job.job_type.clone();
-            let network = job.network.clone().unwrap_or_else(|| "mainnet".to_string());
-            
+            let network = job.network.unwrap_or_default();
+            let file_size = job.file_size.unwrap_or(0);
+            let utxos = prefetched.remove(&address);
+
             tokio::spawn(async move {
                 // Check for payment based on network
-                let has_payment = if network == "testnet" {
-                    // Use WhatsOnChain API for testnet
-                    check_testnet_payment(&address).await
-                } else {
-                    // Use Bitails API for mainnet
-                    let state = state_clone.read().await;
-                    match state.bitails.get_address_unspent(&address).await {
-                        Ok(utxos) => !utxos.is_empty(),
-                        Err(_) => false,
-                    }
+                let utxos = match utxos {
+                    Some(utxos) => utxos,
+                    None => get_address_utxos(&state_clone, &address, network).await.unwrap_or_default(),
                 };
 
-                if has_payment {
-                    // Payment received! Update job status to processing
-                    let state = state_clone.read().await;
-                    let _ = state.db.update_job_status(&job_id, JobStatus::Processing, "Payment received, processing...");
-                    drop(state);
+                record_payments(&*state_clone.read().await, &job_id, &utxos);
+
+                if let Some(funding_txid) = utxos.first().map(|u| u.txid.clone()) {
+                    // A 0-conf payment could still be double-spent, so it stays pending until deep enough
+                    if let Some(confirmations) = unconfirmed_funding(&state_clone, &utxos, network).await {
+                        let state = state_clone.read().await;
+                        let message = MessageKey::AwaitingConfirmations
+                            .with("required", state.config.min_payment_confirmations)
+                            .with("confirmations", confirmations);
+                        let _ = state.db.update_job_status(&job_id, JobStatus::PendingPayment, message);
+                        return;
+                    }
 
-                    // Process the job
-                    process_job(state_clone, job_id, job_type, address, network).await;
+                    if refuse_revoked_api_key(&*state_clone.read().await, &job_id, &address) {
+                        return;
+                    }
+
+                    // Payment received!
+                    let amount = match Amount::sum_sat(utxos.iter().map(|u| u.satoshis)) {
+                        Ok(amount) => amount.to_sat_i64(),
+                        Err(e) => {
+                            // Left pending; a bad UTXO listing is checked again next tick
+                            tracing::warn!("Payment to job {} has an invalid total: {}", job_id, e);
+                            return;
+                        }
+                    };
+                    accept_payment(&*state_clone.read().await, QueuedJob {
+                        job_id: job_id.clone(),
+                        job_type,
+                        address,
+                        network,
+                        admin_pay: false,
+                        file_size,
+                        paid_satoshis: Some(amount),
+                    });
+
+                    // Remember who paid so support can match "I paid but nothing happened" reports
+                    let sender = find_payment_sender(&state_clone, &funding_txid, network).await;
+                    let state = state_clone.read().await;
+                    let _ = state.db.update_job_funding(&job_id, &funding_txid, sender.as_deref());
                 }
             });
         }
 
-        sleep(Duration::from_secs(3)).await;
+        // Jittered so the checks don't settle into a fixed beat against the APIs
+        let interval = state.read().await.config.payment_poll_interval();
+        sleep(interval).await;
     }
 }
 
-/// Check for payment on testnet using WhatsOnChain API
-async fn check_testnet_payment(address: &str) -> bool {
-    let client = reqwest::Client::new();
-    let url = format!("https://api.whatsonchain.com/v1/bsv/test/address/{}/unspent", address);
-    
-    match client.get(&url).send().await {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.json::<Vec<serde_json::Value>>().await {
-                    Ok(utxos) => !utxos.is_empty(),
-                    Err(_) => false,
-                }
-            } else {
-                false
-            }
+/// Fail a paid job whose API key was revoked after it was prepared. The
+/// payment stays at its address, where the abandoned-payment sweep finds it.
+fn refuse_revoked_api_key(state: &AppState, job_id: &str, address: &str) -> bool {
+    match state.db.job_api_key_revoked(job_id) {
+        Ok(false) => false,
+        Ok(true) => {
+            let _ = state
+                .db
+                .update_job_error(job_id, ErrorCode::InvalidApiKey, MessageKey::ApiKeyRevoked.with("address", address));
+            true
+        }
+        // Checked again on the next tick rather than processed unchecked
+        Err(e) => {
+            tracing::error!("Failed to check the API key of job {}: {}", job_id, e);
+            true
         }
-        Err(_) => false,
     }
 }
 
-/// Get testnet UTXOs for upload using WhatsOnChain API
-async fn get_testnet_utxos_for_upload(address: &str) -> Result<Vec<crate::services::bitails::Utxo>, String> {
-    let client = reqwest::Client::new();
-    let url = format!("https://api.whatsonchain.com/v1/bsv/test/address/{}/unspent", address);
-    
-    let response = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {}", e))?;
-    
-    if !response.status().is_success() {
-        return Err(format!("API error: {}", response.status()));
+/// UTXOs of the pending jobs' addresses on WhatsOnChain networks, fetched in bulk
+async fn prefetch_whatsonchain_utxos(
+    jobs: &[crate::models::Job],
+) -> HashMap<String, Vec<crate::services::bitails::Utxo>> {
+    let mut by_network: HashMap<Network, Vec<String>> = HashMap::new();
+    for job in jobs {
+        let network = job.network.unwrap_or_default();
+        if let Some(address) = job.payment_address.clone().filter(|_| crate::services::whatsonchain::serves(network)) {
+            by_network.entry(network).or_default().push(address);
+        }
     }
-    
-    let json: Vec<serde_json::Value> = response
-        .json()
-        .await
-        .map_err(|e| format!("Parse error: {}", e))?;
-    
-    let utxos: Vec<crate::services::bitails::Utxo> = json
-        .iter()
-        .filter_map(|v| {
-            let txid = v.get("tx_hash")?.as_str()?.to_string();
-            let vout = v.get("tx_pos")?.as_u64()? as u32;
-            let satoshis = v.get("value")?.as_i64()?;
-            Some(crate::services::bitails::Utxo { 
-                txid, 
-                vout, 
-                satoshis,
-                script_pubkey: String::new(),
-                blockheight: None,
-                confirmations: None,
-            })
-        })
-        .collect();
-    
-    Ok(utxos)
-}
 
-/// Broadcast transaction to testnet using WhatsOnChain API
-async fn broadcast_testnet_tx(raw_tx: &str) -> Result<String, String> {
-    let client = reqwest::Client::new();
-    let url = "https://api.whatsonchain.com/v1/bsv/test/tx/raw";
-    
-    let response = client
-        .post(url)
-        .header("Content-Type", "application/json")
-        .json(&serde_json::json!({ "txhex": raw_tx }))
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_default();
-        return Err(format!("Broadcast failed: {}", error_text));
+    let mut utxos = HashMap::new();
+    for (network, addresses) in by_network {
+        for batch in addresses.chunks(crate::services::whatsonchain::BULK_ADDRESS_LIMIT) {
+            match get_whatsonchain_utxos_bulk(batch, network).await {
+                Ok(found) => utxos.extend(found),
+                Err(e) => tracing::warn!("Bulk UTXO check of {} addresses failed: {}", batch.len(), e),
+            }
+        }
     }
-    
-    let txid = response
-        .text()
-        .await
-        .map_err(|e| format!("Parse error: {}", e))?;
-    
-    // Remove quotes, whitespace, and newlines
-    Ok(txid.trim().trim_matches('"').trim().to_string())
+    utxos
 }
 
-/// Process a job based on its type
-async fn process_job(state: Arc<RwLock<AppState>>, job_id: String, job_type: JobType, address: String, network: String) {
+/// Record a job's payment before any processing starts, mark the job
+/// processing so the watcher skips it, then queue it
+fn accept_payment(state: &AppState, job: QueuedJob) {
     use crate::models::job::JobStatus;
-    use tokio::time::{sleep, Duration};
 
-    // Get job details
-    let job = {
-        let state = state.read().await;
-        state.db.get_job(&job_id).ok().flatten()
-    };
+    let amount = job.paid_satoshis.unwrap_or(0);
+    let _ = state.db.update_job_payment_received(&job.job_id, amount);
+    let _ = state.db.update_job_status(
+        &job.job_id,
+        JobStatus::Processing,
+        MessageKey::PaymentReceived.with("amount", amount),
+    );
+    enqueue_job(state, job);
+}
 
-    let job = match job {
-        Some(j) => j,
-        None => return,
-    };
+/// Queue a job for processing and refresh the queue position of waiting jobs
+fn enqueue_job(state: &AppState, job: QueuedJob) {
+    state.scheduler.enqueue(job);
+    refresh_queue_messages(state);
+}
 
-    match job_type {
-        JobType::Upload => {
-            process_upload(
-                state,
-                job_id,
-                job.payment_wif.unwrap_or_default(),
-                address,
-                job.file_data,
-                job.filename,
-                network,
-            ).await;
-        }
-        JobType::FlacUpload => {
-            process_flac_upload(
-                state,
-                job_id,
-                job.payment_wif.unwrap_or_default(),
-                address,
-                job.file_data,
-                job.filename,
-                network,
-                job.track_title,
-                job.artist_name,
-                job.lyrics,
-                job.cover_data,
-            ).await;
-        }
-        JobType::Download => {
-            process_download(state, job_id, job.manifest_txid).await;
-        }
-        JobType::FlacDownload => {
-            let network = job.network.unwrap_or_else(|| "mainnet".to_string());
-            process_flac_download(state, job_id, job.manifest_txid, network).await;
+/// Queue the jobs left processing by the last shutdown, oldest first. Batch
+/// children are run by their parent, and sends have nothing to process.
+fn requeue_processing_jobs(state: &AppState) {
+    let mut jobs = match state.db.get_processing_jobs() {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            tracing::error!("Failed to load processing jobs to requeue: {}", e);
+            return;
         }
+    };
+    jobs.retain(|job| job.parent_id.is_none() && job.job_type != JobType::Send);
+    jobs.sort_by_key(|job| job.created_at);
+
+    for job in &jobs {
+        // Whether the admin wallet paid isn't stored, so these queue by size and age only
+        state.scheduler.enqueue(QueuedJob {
+            job_id: job.id.clone(),
+            job_type: job.job_type.clone(),
+            address: job.payment_address.clone().unwrap_or_default(),
+            network: job.network.unwrap_or_default(),
+            admin_pay: false,
+            file_size: job.file_size.unwrap_or(0),
+            paid_satoshis: job.payment_received_satoshis,
+        });
+    }
+    if !jobs.is_empty() {
+        tracing::info!("Requeued {} jobs interrupted by the last shutdown", jobs.len());
+        refresh_queue_messages(state);
     }
 }
 
-/// Process regular upload
-async fn process_upload(
-    state: Arc<RwLock<AppState>>,
-    job_id: String,
-    wif: String,
-    address: String,
-    file_data: Option<Vec<u8>>,
-    filename: Option<String>,
-    _network: String,
-) {
+/// Tell each queued job how many jobs are ahead of it
+fn refresh_queue_messages(state: &AppState) {
     use crate::models::job::JobStatus;
-    use crate::services::bsv::BsvService;
 
-    let file_data = match file_data {
-        Some(data) => data,
-        None => {
+    for (job, ahead) in state.scheduler.positions() {
+        // A paid job keeps acknowledging the payment while it waits for a slot
+        let message = match (job.paid_satoshis, ahead) {
+            (Some(amount), 0) => MessageKey::PaymentQueuedNext.with("amount", amount),
+            (Some(amount), _) => MessageKey::PaymentQueuedBehind.with("amount", amount).with("ahead", ahead),
+            (None, 0) => MessageKey::QueuedNext.into(),
+            (None, _) => MessageKey::QueuedBehind.with("ahead", ahead),
+        };
+        let _ = state.db.update_job_status(&job.job_id, JobStatus::Processing, message);
+    }
+}
+
+/// Background job dispatcher
+async fn job_dispatcher(state: Arc<RwLock<AppState>>) {
+    let scheduler = state.read().await.scheduler.clone();
+
+    loop {
+        let (job, permit) = scheduler.next().await;
+
+        {
             let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, "No file data found");
-            return;
+            let _ = state.db.update_job_progress(&job.job_id, 0.0, MessageKey::Starting);
+            refresh_queue_messages(&state);
         }
-    };
 
-    let filename = filename.unwrap_or_else(|| "file.bin".to_string());
-
-    // Update progress
-    {
-        let state = state.read().await;
-        let _ = state.db.update_job_progress(&job_id, 10.0, "Fetching UTXOs...");
+        let state_clone = state.clone();
+        tokio::spawn(async move {
+            // Hold the slot until the job finishes
+            let _permit = permit;
+            run_job_guarded(state_clone, job).await;
+        });
     }
+}
 
-    // Get UTXOs
-    let utxos = {
-        let state = state.read().await;
-        state.bitails.get_address_unspent(&address).await
-    };
+/// Run a job in its own task so a panic fails the job instead of leaving it
+/// processing, and so a job timed out by the watchdog or past
+/// MAX_JOB_DURATION_MINUTES releases its slot
+async fn run_job_guarded(state: Arc<RwLock<AppState>>, job: QueuedJob) {
+    use tokio::time::{sleep, Duration};
 
-    let utxos = match utxos {
-        Ok(u) => u,
-        Err(e) => {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Failed to get UTXOs: {}", e));
+    let job_id = job.job_id.clone();
+    let max_minutes = {
+        let state = state.read().await;
+        // Cancelled while it was being dequeued
+        let cancelled = state.db.get_job(&job_id).ok().flatten()
+            .map(|j| j.status == crate::models::JobStatus::Cancelled)
+            .unwrap_or(false);
+        if cancelled {
+            state.funding_keys.release(&job_id);
             return;
         }
+        state.cancellations.register(&job_id, None);
+        state.config.max_job_duration_minutes
+    };
+    let time_limit = async {
+        match max_minutes {
+            0 => std::future::pending().await,
+            minutes => sleep(Duration::from_secs(minutes * 60)).await,
+        }
     };
+    tokio::pin!(time_limit);
+    // Everything the job logs is also kept in its own log for support
+    let span = tracing::info_span!("job", job_id = %job_id);
+    let mut handle = tokio::spawn(
+        process_job(state.clone(), job.job_id, job.job_type, job.address, job.network).instrument(span),
+    );
 
-    if utxos.is_empty() {
-        let state = state.read().await;
-        let _ = state.db.update_job_error(&job_id, "No UTXOs found");
-        return;
+    loop {
+        tokio::select! {
+            result = &mut handle => {
+                if let Err(e) = result {
+                    if e.is_panic() {
+                        let panic = e.into_panic();
+                        let reason = panic
+                            .downcast_ref::<&str>()
+                            .map(|s| s.to_string())
+                            .or_else(|| panic.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "unknown panic".to_string());
+                        tracing::error!("Job {} panicked: {}", job_id, reason);
+
+                        let state = state.read().await;
+                        let _ = state.db.update_job_error(
+                            &job_id,
+                            ErrorCode::InternalError,
+                            MessageKey::ProcessingFailed,
+                        );
+                    }
+                }
+                let state = state.read().await;
+                release_job(&state, &job_id);
+                return;
+            }
+            _ = sleep(Duration::from_secs(30)) => {
+                let stalled = {
+                    let state = state.read().await;
+                    state.db.get_job(&job_id).ok().flatten()
+                        .map(|j| j.error_code == Some(ErrorCode::Stalled))
+                        .unwrap_or(false)
+                };
+                if stalled {
+                    tracing::warn!("Aborting stalled job {}", job_id);
+                    handle.abort();
+                    let state = state.read().await;
+                    release_job(&state, &job_id);
+                    return;
+                }
+            }
+            _ = &mut time_limit => {
+                handle.abort();
+                // Let the task drop before failing it, so nothing it does lands after the error
+                let _ = (&mut handle).await;
+                let state = state.read().await;
+                let message = MessageKey::TimeLimitExceeded.with("minutes", max_minutes);
+                if let Ok(true) = state.db.fail_processing_job(&job_id, ErrorCode::TimedOut, message) {
+                    tracing::warn!("Job {} exceeded the {} minute time limit", job_id, max_minutes);
+                }
+                release_job(&state, &job_id);
+                return;
+            }
+        }
     }
+}
 
-    // Update progress
-    {
-        let state = state.read().await;
-        let _ = state.db.update_job_progress(&job_id, 30.0, "Creating transaction...");
+/// Drop what a running job held: its cancellation token and any funding key
+fn release_job(state: &AppState, job_id: &str) {
+    state.cancellations.remove(job_id);
+    state.funding_keys.release(job_id);
+}
+
+/// Whether the owner asked to cancel this job. Processing loops call this
+/// between chunks and stop before spending or fetching anything more.
+async fn is_job_cancelled(state: &Arc<RwLock<AppState>>, job_id: &str) -> bool {
+    let state = state.read().await;
+    state.cancellations.is_cancelled(job_id)
+}
+
+/// Mark a job cancelled, keeping the progress it made so far
+async fn finish_cancelled(state: &Arc<RwLock<AppState>>, job_id: &str, message: impl Into<StatusMessage>) {
+    let message = message.into();
+    let state = state.read().await;
+    tracing::info!("Job {} cancelled: {}", job_id, message.english());
+    let _ = state.db.update_job_cancelled(job_id, message);
+}
+
+/// Background watchdog that fails processing jobs which stopped reporting progress
+async fn stall_watchdog(state: Arc<RwLock<AppState>>) {
+    use tokio::time::{sleep, Duration};
+
+    loop {
+        sleep(Duration::from_secs(60)).await;
+        flag_stalled_jobs(&*state.read().await);
     }
+}
 
-    // Calculate total input
-    let total_input: i64 = utxos.iter().map(|u| u.satoshis).sum();
+/// Fail processing jobs that haven't updated within JOB_STALL_TIMEOUT_MINUTES
+fn flag_stalled_jobs(state: &AppState) {
+    use crate::models::job::JobStatus;
 
-    // Get scriptPubKey for the address
-    let script_pubkey = match BsvService::create_p2pkh_script(&address) {
-        Ok(s) => s,
-        Err(e) => {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Failed to create script: {}", e));
-            return;
+    let timeout_minutes = state.config.job_stall_timeout_minutes;
+    let cutoff = chrono::Utc::now() - chrono::Duration::minutes(timeout_minutes);
+
+    for job in state.db.get_processing_jobs().unwrap_or_default() {
+        // Queued jobs are waiting for a slot, not stalled
+        if job.updated_at >= cutoff || state.scheduler.is_queued(&job.id) {
+            continue;
         }
-    };
 
-    // Prepare UTXOs for transaction
-    let utxo_inputs: Vec<(String, u32, i64, Vec<u8>)> = utxos
-        .iter()
-        .map(|u| (u.txid.clone(), u.vout, u.satoshis, script_pubkey.clone()))
-        .collect();
+        // Batch children are watched through their parent while it runs
+        if let Some(parent_id) = &job.parent_id {
+            let parent_running = state.db.get_job(parent_id).ok().flatten()
+                .map(|p| p.status == JobStatus::Processing)
+                .unwrap_or(false);
+            if parent_running {
+                continue;
+            }
+        }
 
-    // Create OP_RETURN script with file data
-    let protocol = b"upfile";
-    let mime = b"application/octet-stream";
-    let op_return_script = BsvService::create_op_return_script(&[protocol, mime, filename.as_bytes(), &file_data]);
+        let message = MessageKey::Stalled.with("minutes", timeout_minutes);
+        if let Ok(true) = state.db.mark_job_stalled(&job.id, message, cutoff) {
+            tracing::warn!("Job {} stalled (last update {})", job.id, job.updated_at);
+        }
+    }
+}
 
-    // Calculate fee
-    let tx_size = 150 + op_return_script.len();
-    let fee = {
+/// Background maintenance that clears file_data and cover_data of finished
+/// jobs and deletes downloads no job links to. Once a job is over the blobs
+/// are dead weight in the database.
+async fn maintenance_task(state: Arc<RwLock<AppState>>) {
+    use tokio::time::{sleep, Duration};
+
+    let interval = {
         let state = state.read().await;
-        (tx_size as f64 * state.bsv.fee_rate).ceil() as i64
+        state.config.blob_sweep_interval_minutes.max(1)
     };
 
-    // Outputs: OP_RETURN (0 satoshis)
-    let outputs: Vec<(Vec<u8>, i64)> = vec![(op_return_script, 0)];
+    loop {
+        sleep(Duration::from_secs(interval * 60)).await;
 
-    // Check if we have enough for fee
-    if total_input < fee {
         let state = state.read().await;
-        let _ = state.db.update_job_error(
-            &job_id,
-            &format!("Insufficient funds: {} < {}", total_input, fee),
-        );
-        return;
+        run_maintenance(&state);
     }
+}
 
-    // Create transaction
-    let raw_tx = {
+/// Run storage maintenance once, recording it for the admin metrics
+fn run_maintenance(state: &AppState) -> MaintenanceReport {
+    let blob_cutoff = chrono::Utc::now() - chrono::Duration::minutes(state.config.blob_sweep_min_age_minutes);
+    let raw_tx_cutoff = chrono::Utc::now() - chrono::Duration::hours(state.config.raw_tx_retention_hours);
+    let file_cutoff = std::time::SystemTime::now()
+        - std::time::Duration::from_secs(state.config.orphan_file_min_age_minutes * 60);
+    let report = services::maintenance::run(
+        &state.db,
+        std::path::Path::new(routes::download::DOWNLOADS_DIR),
+        std::path::Path::new(services::job_log::JOB_LOGS_DIR),
+        blob_cutoff,
+        raw_tx_cutoff,
+        file_cutoff,
+    );
+    state.maintenance.record(&report);
+
+    if report.cleared_jobs > 0 || report.cleared_raw_txs > 0 || report.removed_files > 0 || report.removed_job_logs > 0 {
+        tracing::info!(
+            "Maintenance reclaimed {} bytes: file data of {} finished jobs, {} raw transactions, {} orphaned downloads, {} job logs",
+            report.reclaimed_bytes(),
+            report.cleared_jobs,
+            report.cleared_raw_txs,
+            report.removed_files,
+            report.removed_job_logs
+        );
+    }
+    report
+}
+
+/// Get the UTXOs of an address from the provider for its network
+async fn get_address_utxos(state: &Arc<RwLock<AppState>>, address: &str, network: Network) -> Result<Vec<crate::services::bitails::Utxo>, String> {
+    if crate::services::whatsonchain::serves(network) {
+        get_whatsonchain_utxos(address, network).await
+    } else {
         let state = state.read().await;
-        state.bsv.create_transaction(&wif, &utxo_inputs, &outputs)
+        state.bitails.get_address_unspent(address).await
+    }
+}
+
+/// Get testnet or STN UTXOs using WhatsOnChain API
+async fn get_whatsonchain_utxos(address: &str, network: Network) -> Result<Vec<crate::services::bitails::Utxo>, String> {
+    let client = crate::services::http::client();
+    let url = format!("{}/address/{}/unspent", crate::services::whatsonchain::base_url(network), address);
+    
+    crate::services::rate_limit::whatsonchain().acquire().await;
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+    
+    if !response.status().is_success() {
+        return Err(format!("API error: {}", response.status()));
+    }
+    
+    let json: Vec<serde_json::Value> = response
+        .json()
+        .await
+        .map_err(|e| format!("Parse error: {}", e))?;
+    
+    Ok(json.iter().filter_map(whatsonchain_utxo).collect())
+}
+
+/// UTXOs of up to `BULK_ADDRESS_LIMIT` addresses in one WhatsOnChain request,
+/// keyed by address. Addresses the response reports an error for are left out.
+async fn get_whatsonchain_utxos_bulk(
+    addresses: &[String],
+    network: Network,
+) -> Result<HashMap<String, Vec<crate::services::bitails::Utxo>>, String> {
+    let client = crate::services::http::client();
+    let url = format!("{}/addresses/unspent", crate::services::whatsonchain::base_url(network));
+
+    crate::services::rate_limit::whatsonchain().acquire().await;
+    let response = client
+        .post(&url)
+        .json(&serde_json::json!({ "addresses": addresses }))
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("API error: {}", response.status()));
+    }
+
+    let json: Vec<serde_json::Value> = response
+        .json()
+        .await
+        .map_err(|e| format!("Parse error: {}", e))?;
+
+    Ok(json
+        .iter()
+        .filter(|entry| entry.get("error").and_then(|e| e.as_str()).unwrap_or("").is_empty())
+        .filter_map(|entry| {
+            let address = entry.get("address")?.as_str()?.to_string();
+            let unspent = entry.get("unspent")?.as_array()?;
+            Some((address, unspent.iter().filter_map(whatsonchain_utxo).collect()))
+        })
+        .collect())
+}
+
+/// One entry of a WhatsOnChain unspent list
+fn whatsonchain_utxo(v: &serde_json::Value) -> Option<crate::services::bitails::Utxo> {
+    Some(crate::services::bitails::Utxo {
+        txid: v.get("tx_hash")?.as_str()?.to_string(),
+        vout: v.get("tx_pos")?.as_u64()? as u32,
+        satoshis: v.get("value")?.as_i64()?,
+        script_pubkey: String::new(),
+        // Mempool outputs are reported at height 0
+        blockheight: v.get("height").and_then(|h| h.as_i64()).filter(|h| *h > 0),
+        confirmations: None,
+    })
+}
+
+/// Log every transaction among `utxos` as a payment to the job, one row per
+/// txid, so a second payment to the same address shows up in its history
+pub fn record_payments(state: &AppState, job_id: &str, utxos: &[crate::services::bitails::Utxo]) {
+    let mut by_txid: HashMap<&str, (i64, Option<i64>)> = HashMap::new();
+    for utxo in utxos {
+        let entry = by_txid.entry(utxo.txid.as_str()).or_insert((0, None));
+        entry.0 += utxo.satoshis;
+        entry.1 = entry.1.or(utxo.blockheight.filter(|h| *h > 0));
+    }
+    for (txid, (satoshis, block_height)) in by_txid {
+        if let Err(e) = state.db.record_payment(job_id, txid, satoshis, block_height) {
+            tracing::warn!("Failed to record payment {} for job {}: {}", txid, job_id, e);
+        }
+    }
+}
+
+/// Lowest confirmation count among the UTXOs whose funding transaction has
+/// fewer than MIN_PAYMENT_CONFIRMATIONS, or None when all of them can be
+/// spent. Outputs of transactions this server broadcast are trusted, so
+/// change from earlier jobs never waits.
+async fn unconfirmed_funding(
+    state: &Arc<RwLock<AppState>>,
+    utxos: &[crate::services::bitails::Utxo],
+    network: Network,
+) -> Option<i64> {
+    let required = state.read().await.config.min_payment_confirmations;
+    if required <= 0 {
+        return None;
+    }
+
+    let mut tip_height: Option<Option<i64>> = None;
+    let mut lowest: Option<i64> = None;
+    for utxo in utxos {
+        let confirmations = match (utxo.confirmations, utxo.blockheight.filter(|h| *h > 0)) {
+            (Some(confirmations), _) => confirmations,
+            (None, Some(height)) => {
+                if tip_height.is_none() {
+                    tip_height = Some(get_chain_height(network).await.ok());
+                }
+                // Without the tip the depth is unknown, so it counts as unconfirmed
+                tip_height.flatten().map(|tip| tip - height + 1).unwrap_or(0)
+            }
+            (None, None) => 0,
+        };
+        if confirmations >= required {
+            continue;
+        }
+        if state.read().await.db.is_own_broadcast(&utxo.txid).unwrap_or(false) {
+            continue;
+        }
+        lowest = Some(lowest.map_or(confirmations, |l| l.min(confirmations)));
+    }
+    lowest
+}
+
+/// Broadcast a transaction through the provider for its network
+async fn broadcast_tx(
+    state: &Arc<RwLock<AppState>>,
+    job_id: Option<&str>,
+    raw_tx: &str,
+    network: Network,
+) -> Result<String, BroadcastError> {
+    let attempts = if crate::services::whatsonchain::serves(network) {
+        vec![broadcast_whatsonchain_tx(raw_tx, network).await]
+    } else {
+        let state = state.read().await;
+        state.bitails.broadcast_transaction(raw_tx).await
     };
 
-    let raw_tx = match raw_tx {
-        Ok(tx) => tx,
-        Err(e) => {
+    // Every attempt is kept, including a provider that failed before the fallback succeeded
+    {
+        let state = state.read().await;
+        let txid = BsvService::txid(raw_tx).unwrap_or_default();
+        for attempt in &attempts {
+            if let Err(e) = state.db.record_broadcast_attempt(job_id, &txid, network, attempt) {
+                tracing::warn!("Failed to record broadcast attempt for {}: {}", txid, e);
+            }
+        }
+    }
+
+    let result = broadcast_outcome(attempts);
+    match &result {
+        // Kept so an operator can re-broadcast it if it drops out of mempools
+        Ok(txid) => {
             let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Failed to create tx: {}", e));
-            return;
+            if state.config.raw_tx_retention_hours > 0 {
+                if let Err(e) = state.db.record_broadcast_tx(txid, network, raw_tx) {
+                    tracing::warn!("Failed to keep raw tx {}: {}", txid, e);
+                }
+            }
         }
-    };
+        Err(e) => tracing::debug!(
+            "Rejected tx {} ({} bytes): {}, inspect with POST /api/admin/decode_tx",
+            BsvService::txid(raw_tx).unwrap_or_default(),
+            raw_tx.len() / 2,
+            e
+        ),
+    }
+    result
+}
 
-    // Update progress
+/// Broadcast transaction to testnet or STN using WhatsOnChain API
+async fn broadcast_whatsonchain_tx(raw_tx: &str, network: Network) -> BroadcastAttempt {
+    let client = crate::services::http::client();
+    let url = format!("{}/tx/raw", crate::services::whatsonchain::base_url(network));
+    
+    crate::services::rate_limit::whatsonchain().acquire().await;
+    let response = match client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .json(&serde_json::json!({ "txhex": raw_tx }))
+        .send()
+        .await
     {
+        Ok(response) => response,
+        Err(e) => return BroadcastAttempt::rejected("whatsonchain", None, &format!("Request failed: {}", e)),
+    };
+    
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return BroadcastAttempt::rejected("whatsonchain", Some(status.as_u16()), &text);
+    }
+    
+    // Remove quotes, whitespace, and newlines
+    BroadcastAttempt::accepted("whatsonchain", status.as_u16(), text.trim().trim_matches('"').trim().to_string())
+}
+
+/// Get testnet or STN address history using WhatsOnChain API
+async fn get_whatsonchain_address_history(address: &str, network: Network) -> Result<Vec<crate::services::bitails::HistoryEntry>, String> {
+    let client = crate::services::http::client();
+    let url = format!("{}/address/{}/history", crate::services::whatsonchain::base_url(network), address);
+
+    crate::services::rate_limit::whatsonchain().acquire().await;
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("API error: {}", response.status()));
+    }
+
+    let json: Vec<serde_json::Value> = response
+        .json()
+        .await
+        .map_err(|e| format!("Parse error: {}", e))?;
+
+    Ok(json
+        .iter()
+        .filter_map(|v| {
+            let txid = v.get("tx_hash")?.as_str()?.to_string();
+            let blockheight = v.get("height").and_then(|h| h.as_i64());
+            Some(crate::services::bitails::HistoryEntry { txid, blockheight })
+        })
+        .collect())
+}
+
+/// Get address history from the appropriate API based on network
+async fn fetch_address_history(
+    state: &Arc<RwLock<AppState>>,
+    address: &str,
+    network: Network,
+) -> Result<Vec<crate::services::bitails::HistoryEntry>, String> {
+    if crate::services::whatsonchain::serves(network) {
+        get_whatsonchain_address_history(address, network).await
+    } else {
         let state = state.read().await;
-        let _ = state.db.update_job_progress(&job_id, 60.0, "Broadcasting transaction...");
+        state.bitails.get_address_history(address).await
     }
+}
 
-    // Broadcast transaction
-    let broadcast_result = {
+/// Get the current chain tip height using WhatsOnChain API
+pub async fn get_chain_height(network: Network) -> Result<i64, String> {
+    let client = crate::services::http::client();
+    let url = format!("{}/chain/info", crate::services::whatsonchain::base_url(network));
+
+    crate::services::rate_limit::whatsonchain().acquire().await;
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("API error: {}", response.status()));
+    }
+
+    let json: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Parse error: {}", e))?;
+
+    json.get("blocks")
+        .and_then(|b| b.as_i64())
+        .ok_or_else(|| "Missing block height".to_string())
+}
+
+/// Process a job based on its type
+async fn process_job(state: Arc<RwLock<AppState>>, job_id: String, job_type: JobType, address: String, network: Network) {
+    // Get job details
+    let job = {
         let state = state.read().await;
-        state.bitails.broadcast_transaction(&raw_tx).await
+        state.db.get_job(&job_id).ok().flatten()
     };
 
-    match broadcast_result {
-        Ok(txid) => {
+    let job = match job {
+        Some(j) => j,
+        None => return,
+    };
+    let payment_wif = {
+        let state = state.read().await;
+        job_payment_wif(&state, &job)
+    };
+
+    // A corrupted key or a rotated admin wallet would only show up as
+    // signature errors after every retry, so catch it before anything is built
+    if matches!(job_type, JobType::Upload | JobType::FlacUpload | JobType::CoverAttach) {
+        let payment_address = job.payment_address.as_deref().unwrap_or(&address);
+        if let Err(message) = check_payment_key(payment_wif.as_deref(), payment_address, network) {
+            tracing::error!("Job {} not processed: {}", job_id, message.english());
             let state = state.read().await;
-            let _ = state.db.update_job_complete(&job_id, &txid, None);
-            tracing::info!("Upload complete for job {}: txid={}", job_id, txid);
+            let _ = state.db.update_job_error(&job_id, ErrorCode::PaymentKeyMismatch, message);
+            return;
+        }
+    }
+
+    match job_type {
+        JobType::Upload => {
+            process_upload(
+                state,
+                job_id,
+                payment_wif.clone().unwrap_or_default(),
+                address,
+                job.file_data,
+                job.filename,
+                network,
+            ).await;
+        }
+        JobType::FlacUpload => {
+            process_flac_upload(
+                state,
+                job_id,
+                payment_wif.clone().unwrap_or_default(),
+                address,
+                job.file_data,
+                job.filename,
+                network,
+                job.track_title,
+                job.artist_name,
+                job.lyrics,
+                job.cover_data,
+                job.royalty_address.zip(job.royalty_satoshis),
+            ).await;
+        }
+        JobType::Download => {
+            process_download(state, job_id, job.manifest_txid, network).await;
+        }
+        JobType::FlacDownload => {
+            let network = job.network.unwrap_or_default();
+            process_flac_download(state, job_id, job.manifest_txid, network).await;
+        }
+        JobType::FlacBatchDownload => {
+            process_flac_batch_download(state, job_id, network).await;
+        }
+        JobType::Send => {
+            // Sends are recorded after they broadcast, there is nothing to process
         }
+        JobType::Import => {
+            process_import(state, job_id, job.manifest_txid, network).await;
+        }
+        JobType::CoverAttach => {
+            process_cover_attach(
+                state,
+                job_id,
+                payment_wif.unwrap_or_default(),
+                address,
+                job.manifest_txid,
+                job.cover_data,
+                network,
+            ).await;
+        }
+    }
+}
+
+/// WIF of a job's payment key. Prefunded uploads hold theirs in memory, and
+/// jobs that only store their derivation index re-derive it from the HD seed.
+pub(crate) fn job_payment_wif(state: &AppState, job: &crate::models::Job) -> Option<String> {
+    if job.payment_wif.is_some() {
+        return job.payment_wif.clone();
+    }
+    if let Some(wif) = state.funding_keys.wif(&job.id) {
+        return Some(wif);
+    }
+    let index = u32::try_from(job.derivation_index?).ok()?;
+    let secret_key = match state.hd_wallet.as_ref()?.payment_key(index) {
+        Ok(key) => key,
         Err(e) => {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Broadcast failed: {}", e));
+            tracing::error!("Failed to derive the payment key of job {}: {}", job.id, e);
+            return None;
         }
+    };
+    let network = job.network.unwrap_or_default();
+    Some(BsvService::keypair_from_secret_key(&secret_key, network).0)
+}
+
+/// Whether `wif` is the key that controls `address` on `network`
+pub fn check_payment_key(wif: Option<&str>, address: &str, network: Network) -> Result<(), StatusMessage> {
+    let key_address = wif
+        .ok_or_else(|| "no key stored".to_string())
+        .and_then(|wif| BsvService::wif_to_address(wif, network))
+        .map_err(|e| MessageKey::PaymentKeyUnreadable.with("address", address).with("error", e))?;
+    if key_address != address {
+        return Err(MessageKey::PaymentKeyMismatch
+            .with("address", address)
+            .with("key_address", key_address));
     }
+    Ok(())
 }
 
-/// Process FLAC upload with multi-transaction chunking
-async fn process_flac_upload(
+/// Inscribe a cover for an existing track, then publish the record linking
+/// the track's manifest to it. The record spends the cover transaction's
+/// change, so one payment funds both.
+async fn process_cover_attach(
     state: Arc<RwLock<AppState>>,
     job_id: String,
     wif: String,
     address: String,
-    file_data: Option<Vec<u8>>,
-    filename: Option<String>,
-    network: String,
-    track_title: Option<String>,
-    artist_name: Option<String>,
-    lyrics: Option<String>,
+    manifest_txid: Option<String>,
     cover_data: Option<Vec<u8>>,
+    network: Network,
 ) {
-    use crate::models::job::JobStatus;
-    use crate::services::bsv::BsvService;
-    use crate::services::bitails::Utxo;
     use tokio::time::{sleep, Duration};
 
-    let file_data = match file_data {
-        Some(data) => data,
-        None => {
+    let (manifest_txid, cover_data) = match (manifest_txid, cover_data) {
+        (Some(txid), Some(data)) => (txid, data),
+        _ => {
             let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, "No file data found");
+            let _ = state.db.update_job_error(&job_id, ErrorCode::NoFileData, MessageKey::NoFileData);
             return;
         }
     };
 
-    let filename = filename.unwrap_or_else(|| "audio.flac".to_string());
-    let file_size = file_data.len();
-
-    // Maximum chunk size per transaction (1MB chunks)
-    let max_tx_data_size = 1024 * 1024; // 1MB chunks
-
-    // Check if we need multi-transaction approach
-    let needs_chunking = file_size > max_tx_data_size;
-
-    // Update progress
     {
         let state = state.read().await;
-        let _ = state.db.update_job_progress(&job_id, 5.0, "Fetching UTXOs...");
+        let _ = state.db.update_job_progress(&job_id, 5.0, MessageKey::FetchingUtxos);
     }
 
-    // Get UTXOs based on network
-    let mut utxos: Vec<Utxo> = if network == "testnet" {
-        match get_testnet_utxos_for_upload(&address).await {
-            Ok(u) => u,
-            Err(e) => {
-                let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Failed to get UTXOs: {}", e));
-                return;
-            }
-        }
-    } else {
-        let result = {
+    let utxos = match get_address_utxos(&state, &address, network).await {
+        Ok(u) => u,
+        Err(e) => {
             let state = state.read().await;
-            state.bitails.get_address_unspent(&address).await
-        };
-        match result {
-            Ok(u) => u,
-            Err(e) => {
-                let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Failed to get UTXOs: {}", e));
-                return;
-            }
+            let _ = state.db.update_job_error(&job_id, ErrorCode::UtxoFetchFailed, MessageKey::UtxoFetchFailed.with("error", e));
+            return;
         }
     };
-
     if utxos.is_empty() {
         let state = state.read().await;
-        let _ = state.db.update_job_error(&job_id, "No UTXOs found");
+        let _ = state.db.update_job_error(&job_id, ErrorCode::NoUtxos, MessageKey::NoUtxos);
         return;
     }
 
-    // Get scriptPubKey for the address
     let script_pubkey = match BsvService::create_p2pkh_script(&address) {
         Ok(s) => s,
         Err(e) => {
             let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Failed to create script: {}", e));
+            let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::ScriptBuildFailed.with("error", e));
             return;
         }
     };
 
-    // Upload cover image to BSV if present
-    let cover_txid: Option<String> = if let Some(ref cover_bytes) = cover_data {
-        {
-            let state = state.read().await;
-            let _ = state.db.update_job_progress(&job_id, 3.0, "Uploading cover image...");
-        }
-        
-        // Create cover image transaction
-        let cover_script = BsvService::create_cover_image_script(cover_bytes);
-        
-        // Use first UTXO for cover image
-        if utxos.is_empty() {
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 20.0, MessageKey::UploadingCover);
+    }
+
+    // Cover transaction: every payment UTXO in, the image plus change out.
+    // The change must still fund the update record.
+    let placeholder_txid = "0".repeat(64);
+    let (cover_raw_tx, cover_change) = {
+        let state = state.read().await;
+        let data_output_satoshis = state.bsv.data_output_satoshis;
+        let cover_script = state.bsv.create_cover_image_script(&cover_data);
+        let update_script_len = BsvService::create_cover_update_script(&placeholder_txid, &placeholder_txid).len();
+        let cover_fee = state.bsv.fee_for_size(150 + 148 * (utxos.len() - 1) + cover_script.len());
+        let update_fee = state.bsv.fee_for_size(150 + update_script_len);
+
+        let available = Amount::sum_sat(utxos.iter().map(|u| u.satoshis)).unwrap_or(Amount::ZERO);
+        let required = cover_fee
+            .saturating_add(update_fee)
+            .saturating_add(data_output_satoshis.saturating_mul(2));
+        let cover_change = match available.checked_sub(cover_fee.saturating_add(data_output_satoshis)) {
+            Ok(change) if available >= required => change,
+            _ => {
+                let _ = state.db.update_job_error(
+                    &job_id,
+                    ErrorCode::InsufficientFunds,
+                    MessageKey::InsufficientFunds
+                        .with("available", available.to_sat())
+                        .with("required", required.to_sat()),
+                );
+                return;
+            }
+        };
+
+        let inputs: Vec<(String, u32, i64, Vec<u8>)> = utxos
+            .iter()
+            .map(|u| (u.txid.clone(), u.vout, u.satoshis, script_pubkey.clone()))
+            .collect();
+        let outputs = vec![(cover_script, data_output_satoshis), (script_pubkey.clone(), cover_change)];
+        match state.bsv.create_transaction(&wif, &inputs, &outputs) {
+            Ok(tx) => (tx, cover_change),
+            Err(e) => {
+                let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::TxBuildFailed.with("error", e));
+                return;
+            }
+        }
+    };
+
+    if is_job_cancelled(&state, &job_id).await {
+        finish_cancelled(&state, &job_id, MessageKey::CancelledBeforeBroadcast).await;
+        return;
+    }
+
+    let cover_txid = match broadcast_tx(&state, Some(&job_id), &cover_raw_tx, network).await {
+        Ok(txid) => txid,
+        Err(e) => {
             let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, "No UTXOs for cover image");
+            let _ = state.db.update_job_error(&job_id, ErrorCode::BroadcastFailed, MessageKey::BroadcastFailed.with_broadcast_error(&e));
             return;
         }
-        
-        let cover_utxo = utxos.remove(0);
-        let cover_utxo_input = vec![(
-            cover_utxo.txid.clone(),
-            cover_utxo.vout,
-            cover_utxo.satoshis,
-            script_pubkey.clone(),
-        )];
-        
-        // Calculate change
-        let cover_tx_size = 150 + cover_script.len();
-        let cover_fee = {
+    };
+    tracing::info!("Cover image attached for manifest {}: {}", manifest_txid, cover_txid);
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_cover_txid(&job_id, &cover_txid);
+        // Listings show the attached cover from a local thumbnail; only the
+        // full image goes on chain for covers attached after upload
+        if let Some(thumb) = crate::services::thumbnail::make_thumbnail(&cover_data) {
+            let _ = state.db.save_cover_thumb(&cover_txid, &thumb);
+        }
+        let _ = state.db.update_job_progress(&job_id, 60.0, MessageKey::PublishingCoverUpdate);
+    }
+
+    // Wait for propagation before spending the cover's change
+    sleep(Duration::from_millis(1000)).await;
+
+    let update_raw_tx = {
+        let state = state.read().await;
+        let data_output_satoshis = state.bsv.data_output_satoshis;
+        let update_script = BsvService::create_cover_update_script(&manifest_txid, &cover_txid);
+        let update_fee = state.bsv.fee_for_size(150 + update_script.len());
+        let input = vec![(cover_txid.clone(), 1, cover_change.to_sat_i64(), script_pubkey.clone())];
+        let mut outputs = vec![(update_script, data_output_satoshis)];
+        if let Ok(change) = cover_change.checked_sub(update_fee.saturating_add(data_output_satoshis)) {
+            if change > state.bsv.dust_limit {
+                outputs.push((script_pubkey.clone(), change));
+            }
+        }
+        state.bsv.create_transaction(&wif, &input, &outputs)
+    };
+
+    let failed = MessageKey::CoverUpdateBroadcastFailed.with("cover_txid", cover_txid.as_str());
+    let update_txid = match update_raw_tx {
+        Ok(tx) => broadcast_tx(&state, Some(&job_id), &tx, network)
+            .await
+            .map_err(|e| failed.with_broadcast_error(&e)),
+        Err(e) => Err(failed.with("error", e)),
+    };
+    let update_txid = match update_txid {
+        Ok(txid) => txid,
+        Err(message) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::BroadcastFailed, message);
+            return;
+        }
+    };
+
+    let state = state.read().await;
+    let _ = state.db.record_cover_link(&manifest_txid, network, &cover_txid, &update_txid);
+    let _ = state.db.update_job_complete(&job_id, &manifest_txid, None);
+    tracing::info!("Cover update {} links manifest {} to cover {}", update_txid, manifest_txid, cover_txid);
+}
+
+/// Process regular upload
+async fn process_upload(
+    state: Arc<RwLock<AppState>>,
+    job_id: String,
+    wif: String,
+    address: String,
+    file_data: Option<Vec<u8>>,
+    filename: Option<String>,
+    network: Network,
+) {
+    use crate::services::bsv::BsvService;
+
+    let file_data = match file_data {
+        Some(data) => data,
+        None => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::NoFileData, MessageKey::NoFileData);
+            return;
+        }
+    };
+
+    let filename = filename.unwrap_or_else(|| "file.bin".to_string());
+
+    // Update progress
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 10.0, MessageKey::FetchingUtxos);
+    }
+
+    // Get UTXOs from the provider for the job's network
+    let utxos = match get_address_utxos(&state, &address, network).await {
+        Ok(u) => u,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::UtxoFetchFailed, MessageKey::UtxoFetchFailed.with("error", e));
+            return;
+        }
+    };
+
+    if utxos.is_empty() {
+        let state = state.read().await;
+        let _ = state.db.update_job_error(&job_id, ErrorCode::NoUtxos, MessageKey::NoUtxos);
+        return;
+    }
+
+    // Update progress
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_transfer(&job_id, 0, file_data.len() as i64, 30.0, MessageKey::CreatingTransaction);
+    }
+
+    // Calculate total input
+    let total_input = match Amount::sum_sat(utxos.iter().map(|u| u.satoshis)) {
+        Ok(total) => total,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::TxBuildFailed.with("error", e.to_string()));
+            return;
+        }
+    };
+
+    // Get scriptPubKey for the address
+    let script_pubkey = match BsvService::create_p2pkh_script(&address) {
+        Ok(s) => s,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::ScriptBuildFailed.with("error", e));
+            return;
+        }
+    };
+
+    // Prepare UTXOs for transaction
+    let utxo_inputs: Vec<(String, u32, i64, Vec<u8>)> = utxos
+        .iter()
+        .map(|u| (u.txid.clone(), u.vout, u.satoshis, script_pubkey.clone()))
+        .collect();
+
+    // Create OP_RETURN script with file data
+    // The file data is spread over pushes of at most max_push_size;
+    // the download parser joins everything after the filename back together
+    let op_return_script = {
+        let state = state.read().await;
+        let mime_type = crate::services::content_type::detect(&file_data, &filename);
+        state.bsv.create_upfile_script(&mime_type, &filename, &file_data)
+    };
+
+    // Calculate fee, and the fee with room for a change output
+    let tx_size = 150 + op_return_script.len();
+    let (fee, change_fee, dust_limit) = {
+        let state = state.read().await;
+        (state.bsv.fee_for_size(tx_size), state.bsv.fee_for_size(tx_size + 34), state.bsv.dust_limit)
+    };
+
+    // Outputs: OP_RETURN (0 satoshis)
+    let mut outputs: Vec<(Vec<u8>, Amount)> = vec![(op_return_script, Amount::ZERO)];
+
+    // Return anything above the fee to the payment address
+    if let Ok(change) = total_input.checked_sub(change_fee) {
+        if change > dust_limit {
+            outputs.push((script_pubkey.clone(), change));
+        }
+    }
+
+    // Check if we have enough for fee
+    if total_input < fee {
+        let state = state.read().await;
+        let _ = state.db.update_job_error(
+            &job_id,
+            ErrorCode::InsufficientFunds,
+            MessageKey::InsufficientFunds
+                .with("available", total_input)
+                .with("required", fee),
+        );
+        return;
+    }
+
+    // Create transaction
+    let raw_tx = {
+        let state = state.read().await;
+        state.bsv.create_transaction(&wif, &utxo_inputs, &outputs)
+    };
+
+    let raw_tx = match raw_tx {
+        Ok(tx) => tx,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::TxBuildFailed.with("error", e));
+            return;
+        }
+    };
+
+    if is_job_cancelled(&state, &job_id).await {
+        finish_cancelled(&state, &job_id, MessageKey::CancelledBeforeBroadcast).await;
+        return;
+    }
+
+    // Update progress
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 60.0, MessageKey::BroadcastingTransaction);
+    }
+
+    // Broadcast transaction
+    match broadcast_tx(&state, Some(&job_id), &raw_tx, network).await {
+        Ok(txid) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_complete(&job_id, &txid, None);
+            tracing::info!("Upload complete for job {}: txid={}", job_id, txid);
+        }
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::BroadcastFailed, MessageKey::BroadcastFailed.with_broadcast_error(&e));
+        }
+    }
+}
+
+/// Inscribe a side transaction of an upload, such as its cover or long
+/// lyrics, from the first of `utxos` with `script` as its data output. Its
+/// change goes back to the front of `utxos`. None if it couldn't be built or
+/// broadcast, which leaves `utxos` without the spent UTXO.
+async fn inscribe_side_tx(
+    state: &Arc<RwLock<AppState>>,
+    job_id: &str,
+    wif: &str,
+    script_pubkey: &[u8],
+    utxos: &mut Vec<crate::services::bitails::Utxo>,
+    script: Vec<u8>,
+    network: Network,
+) -> Option<String> {
+    use tokio::time::{sleep, Duration};
+
+    let utxo = utxos.remove(0);
+    let input = vec![(utxo.txid.clone(), utxo.vout, utxo.satoshis, script_pubkey.to_vec())];
+
+    let (raw_tx, change_amount) = {
+        let state = state.read().await;
+        let fee = state.bsv.fee_for_size(150 + script.len());
+        let data_output_satoshis = state.bsv.data_output_satoshis;
+        let change_amount = Amount::try_from(utxo.satoshis)
+            .and_then(|input| input.checked_sub(fee.checked_add(data_output_satoshis)?))
+            .ok()
+            .filter(|change| *change > state.bsv.dust_limit);
+        let mut outputs: Vec<(Vec<u8>, Amount)> = vec![(script, data_output_satoshis)];
+        if let Some(change) = change_amount {
+            outputs.push((script_pubkey.to_vec(), change));
+        }
+        (state.bsv.create_transaction(wif, &input, &outputs), change_amount)
+    };
+    let raw_tx = match raw_tx {
+        Ok(tx) => tx,
+        Err(e) => {
+            tracing::warn!("Failed to create side tx for job {}: {}", job_id, e);
+            return None;
+        }
+    };
+
+    match broadcast_tx(state, Some(job_id), &raw_tx, network).await {
+        Ok(txid) => {
+            // Add change output as new UTXO if we created one
+            if let Some(change) = change_amount {
+                utxos.insert(0, crate::services::bitails::Utxo {
+                    txid: txid.clone(),
+                    vout: 1,
+                    satoshis: change.to_sat_i64(),
+                    script_pubkey: String::new(),
+                    blockheight: Some(0),
+                    confirmations: Some(0),
+                });
+            }
+            // Wait for propagation
+            sleep(Duration::from_millis(1000)).await;
+            Some(txid)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to broadcast side tx for job {}: {}", job_id, e);
+            None
+        }
+    }
+}
+
+/// Split `inputs` (unused chunk outputs, and the change of an earlier
+/// split) into `num_outputs` outputs of `satoshis_per_output` each, for an
+/// upload going on with smaller chunks. Returns the broadcast split, whose
+/// outputs and change fund the rest of the upload, or the job error to record.
+#[allow(clippy::too_many_arguments)]
+async fn resplit_chunk_outputs(
+    state: &Arc<RwLock<AppState>>,
+    job_id: &str,
+    wif: &str,
+    script_pubkey: &[u8],
+    inputs: &[(String, u32, i64)],
+    num_outputs: usize,
+    satoshis_per_output: Amount,
+    network: Network,
+) -> Result<SplitPlan, (ErrorCode, StatusMessage)> {
+    use tokio::time::{sleep, Duration};
+
+    let plan = {
+        let state = state.read().await;
+        state
+            .bsv
+            .create_split_transactions(wif, inputs, script_pubkey, num_outputs, satoshis_per_output, Amount::ZERO)
+    };
+    let plan = plan.map_err(|e| {
+        let code = if e.starts_with("Insufficient funds") {
+            ErrorCode::InsufficientFunds
+        } else {
+            ErrorCode::TxBuildFailed
+        };
+        (code, MessageKey::SplitBuildFailed.with("error", e))
+    })?;
+
+    for (txid, raw_tx) in &plan.transactions {
+        if let Err(e) = broadcast_tx(state, Some(job_id), raw_tx, network).await {
+            tracing::warn!("Re-split transaction {} failed to broadcast: {}", txid, e);
+            return Err((ErrorCode::BroadcastFailed, MessageKey::SplitBroadcastFailed.with_broadcast_error(&e)));
+        }
+        tracing::info!("Re-split transaction broadcast: {}", txid);
+    }
+    // Let the re-split propagate before chunks spend it
+    sleep(Duration::from_millis(1000)).await;
+    Ok(plan)
+}
+
+/// Process FLAC upload with multi-transaction chunking
+#[allow(clippy::too_many_arguments)]
+async fn process_flac_upload(
+    state: Arc<RwLock<AppState>>,
+    job_id: String,
+    wif: String,
+    address: String,
+    file_data: Option<Vec<u8>>,
+    filename: Option<String>,
+    network: Network,
+    track_title: Option<String>,
+    artist_name: Option<String>,
+    lyrics: Option<String>,
+    cover_data: Option<Vec<u8>>,
+    royalty: Option<(String, i64)>,
+) {
+    use crate::services::bsv::BsvService;
+    use crate::services::bitails::Utxo;
+    use tokio::time::{sleep, Duration};
+
+    let file_data = match file_data {
+        Some(data) => data,
+        None => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::NoFileData, MessageKey::NoFileData);
+            return;
+        }
+    };
+
+    let filename = filename.unwrap_or_else(|| "audio.flac".to_string());
+    let file_size = file_data.len();
+
+    // Creator royalty, paid as an extra output of the manifest transaction
+    let royalty_output = match &royalty {
+        Some((royalty_address, satoshis)) => {
+            match BsvService::create_p2pkh_script(royalty_address)
+                .and_then(|script| Ok((script, Amount::try_from(*satoshis)?)))
+            {
+                Ok(output) => Some(output),
+                Err(e) => {
+                    let state = state.read().await;
+                    let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::InvalidRoyaltyAddress.with("error", e));
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let royalty_satoshis = royalty_output.as_ref().map(|(_, satoshis)| *satoshis).unwrap_or(Amount::ZERO);
+
+    // Store the file the way prepare priced it
+    let plan = state.read().await.bsv.plan_flac_upload(file_size, network);
+    let needs_chunking = plan.chunked;
+    let max_tx_data_size = plan.chunk_size;
+
+    // Update progress
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 5.0, MessageKey::FetchingUtxos);
+    }
+
+    // Get UTXOs based on network
+    let mut utxos: Vec<Utxo> = match get_address_utxos(&state, &address, network).await {
+        Ok(u) => u,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::UtxoFetchFailed, MessageKey::UtxoFetchFailed.with("error", e));
+            return;
+        }
+    };
+
+    if utxos.is_empty() {
+        let state = state.read().await;
+        let _ = state.db.update_job_error(&job_id, ErrorCode::NoUtxos, MessageKey::NoUtxos);
+        return;
+    }
+
+    // Get scriptPubKey for the address
+    let script_pubkey = match BsvService::create_p2pkh_script(&address) {
+        Ok(s) => s,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::ScriptBuildFailed.with("error", e));
+            return;
+        }
+    };
+
+    // Value of every data-carrying output below, and the change that is worth returning
+    let (data_output_satoshis, dust_limit) = {
+        let state = state.read().await;
+        (state.bsv.data_output_satoshis, state.bsv.dust_limit)
+    };
+
+    // Upload cover image to BSV if present
+    let cover_txid: Option<String> = if let Some(ref cover_bytes) = cover_data {
+        {
+            let state = state.read().await;
+            let _ = state.db.update_job_progress(&job_id, 3.0, MessageKey::UploadingCover);
+        }
+        
+        // Use first UTXO for cover image
+        if utxos.is_empty() {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::NoUtxos, MessageKey::NoUtxosForCover);
+            return;
+        }
+
+        let cover_script = {
+            let state = state.read().await;
+            state.bsv.create_cover_image_script(cover_bytes)
+        };
+        let cover_txid = inscribe_side_tx(&state, &job_id, &wif, &script_pubkey, &mut utxos, cover_script, network).await;
+        match &cover_txid {
+            Some(txid) => {
+                tracing::info!("Cover image uploaded: {}", txid);
+                let state = state.read().await;
+                let _ = state.db.update_job_cover_txid(&job_id, txid);
+            }
+            None => tracing::warn!("Cover image was not inscribed for job {}", job_id),
+        }
+        cover_txid
+    } else {
+        None
+    };
+
+    // A small copy of the cover for listings, kept inline in the metadata
+    // unless it is too large, in which case it gets a coverart transaction
+    let thumb = cover_data.as_deref().and_then(crate::services::thumbnail::make_thumbnail);
+    let cover_thumb: Option<CoverThumb> = match (&cover_txid, thumb) {
+        (Some(cover_txid), Some(thumb)) => {
+            {
+                let state = state.read().await;
+                let _ = state.db.save_cover_thumb(cover_txid, &thumb);
+            }
+            if crate::services::thumbnail::fits_inline(thumb.len()) {
+                Some(CoverThumb::Inline(base64::engine::general_purpose::STANDARD.encode(&thumb)))
+            } else if !utxos.is_empty() {
+                let thumb_script = state.read().await.bsv.create_cover_image_script(&thumb);
+                let txid = inscribe_side_tx(&state, &job_id, &wif, &script_pubkey, &mut utxos, thumb_script, network).await;
+                if txid.is_none() {
+                    tracing::warn!("Cover thumbnail was not inscribed for job {}", job_id);
+                }
+                txid.map(CoverThumb::Txid)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+
+    // Lyrics too long for the metadata get their own transaction; if it can't
+    // be inscribed they stay inline as before
+    let lyrics_script = {
+        let state = state.read().await;
+        lyrics
+            .as_deref()
+            .filter(|lyrics| state.bsv.lyrics_need_own_tx(lyrics.len()))
+            .map(|lyrics| state.bsv.create_lyrics_script(lyrics))
+    };
+    let lyrics_txid: Option<String> = match lyrics_script {
+        Some(lyrics_script) if !utxos.is_empty() => {
+            {
+                let state = state.read().await;
+                let _ = state.db.update_job_progress(&job_id, 4.0, MessageKey::UploadingLyrics);
+            }
+            let txid = inscribe_side_tx(&state, &job_id, &wif, &script_pubkey, &mut utxos, lyrics_script, network).await;
+            match &txid {
+                Some(txid) => tracing::info!("Lyrics uploaded: {}", txid),
+                None => tracing::warn!("Lyrics transaction failed for job {}, keeping the lyrics inline", job_id),
+            }
+            txid
+        }
+        _ => None,
+    };
+
+    if needs_chunking {
+        // Multi-transaction chunking approach with UTXO pre-splitting
+        // Split file into chunks
+        let mut chunks: Vec<Vec<u8>> = Vec::new();
+        let mut offset = 0;
+        while offset < file_size {
+            let end = std::cmp::min(offset + max_tx_data_size, file_size);
+            chunks.push(file_data[offset..end].to_vec());
+            offset = end;
+        }
+
+        let mut total_chunks = chunks.len();
+        // Listed in the manifest so downloads can check each chunk on its own
+        let mut chunk_hashes: Vec<String> = chunks.iter().map(|chunk| hex::encode(Sha256::digest(chunk))).collect();
+
+        // A chunk any earlier upload stored on this network at the same index is
+        // referenced by its txid instead of being paid for again, so it gets no
+        // split output. The index must match because downloads place chunks by it.
+        let mut stored_chunk_txids: Vec<Option<String>> = {
             let state = state.read().await;
-            (cover_tx_size as f64 * state.bsv.fee_rate).ceil() as i64
+            chunk_hashes
+                .iter()
+                .enumerate()
+                .map(|(i, hash)| state.db.find_stored_chunk(hash, network, i as u32).ok().flatten())
+                .collect()
         };
+        let new_chunks = stored_chunk_txids.iter().filter(|txid| txid.is_none()).count();
+        let num_outputs = new_chunks + 1; // +1 for manifest
         
-        let change_amount = cover_utxo.satoshis - cover_fee - 1;
-        let mut outputs: Vec<(Vec<u8>, i64)> = vec![(cover_script, 1)];
-        if change_amount > 546 {
-            outputs.push((script_pubkey.clone(), change_amount));
+        tracing::info!("Splitting {} bytes into {} chunks for job {}", file_size, total_chunks, job_id);
+        if new_chunks < total_chunks {
+            tracing::info!("{} of {} chunks are already on chain and will be reused", total_chunks - new_chunks, total_chunks);
         }
-        
-        let cover_raw_tx = {
+
+        // Calculate satoshis needed per output
+        let satoshis_per_output = {
             let state = state.read().await;
-            state.bsv.create_transaction(&wif, &cover_utxo_input, &outputs)
+            state.bsv.calculate_chunk_output_satoshis(max_tx_data_size)
         };
         
-        let cover_raw_tx = match cover_raw_tx {
-            Ok(tx) => tx,
+        tracing::info!("Satoshis per output: {}, total outputs: {}", satoshis_per_output, num_outputs);
+
+        // Update progress
+        {
+            let state = state.read().await;
+            let _ = state.db.update_job_progress(
+                &job_id,
+                5.0,
+                MessageKey::PreparingSplit.with("n", total_chunks),
+            );
+        }
+
+        // Step 1: Create and broadcast UTXO split transaction
+        // The split spends the largest UTXOs, as many as it takes. The cover
+        // may have spent the only UTXO, leaving no change or too little of
+        // it, so check the split is fundable before going further.
+        let candidates: Vec<(String, u32, i64)> = utxos.iter().map(|u| (u.txid.clone(), u.vout, u.satoshis)).collect();
+        let split_inputs = {
+            let state = state.read().await;
+            state.bsv.select_split_inputs(&candidates, num_outputs, satoshis_per_output, royalty_satoshis)
+        };
+        let split_inputs = match split_inputs {
+            Ok(inputs) => inputs,
+            Err(SplitShortfall { available, required }) => {
+                // Past the input cap the wallet as a whole may still hold enough
+                let max_inputs = state.read().await.bsv.max_split_inputs;
+                let balance = Amount::sum_sat(candidates.iter().map(|(_, _, satoshis)| *satoshis)).unwrap_or(available);
+                let message = if candidates.len() > max_inputs && balance >= required {
+                    MessageKey::SplitTooManyInputs.with("max", max_inputs).with("count", candidates.len())
+                } else if cover_txid.is_some() {
+                    MessageKey::InsufficientFundsAfterCover.with("available", balance).with("required", required)
+                } else {
+                    MessageKey::InsufficientFundsAcrossUtxos
+                        .with("count", candidates.len())
+                        .with("available", balance)
+                        .with("required", required)
+                };
+                let state = state.read().await;
+                let _ = state.db.update_job_error(&job_id, ErrorCode::InsufficientFunds, message);
+                return;
+            }
+        };
+        if split_inputs.len() > 1 {
+            tracing::info!("No single UTXO funds the split for job {}; combining {}", job_id, split_inputs.len());
+        }
+        let split_plan = {
+            let state = state.read().await;
+            state.bsv.create_split_transactions(
+                &wif,
+                &split_inputs,
+                &script_pubkey,
+                num_outputs,
+                satoshis_per_output,
+                royalty_satoshis,
+            )
+        };
+
+        let split_plan = match split_plan {
+            Ok(plan) => plan,
             Err(e) => {
-                tracing::warn!("Failed to create cover tx: {}", e);
-                String::new()
+                let code = if e.starts_with("Insufficient funds") {
+                    ErrorCode::InsufficientFunds
+                } else {
+                    ErrorCode::TxBuildFailed
+                };
+                let state = state.read().await;
+                let _ = state.db.update_job_error(&job_id, code, MessageKey::SplitBuildFailed.with("error", e));
+                return;
             }
         };
-        
-        if cover_raw_tx.is_empty() {
-            None
-        } else {
-            // Broadcast cover image transaction
-            let cover_broadcast_result = if network == "testnet" {
-                broadcast_testnet_tx(&cover_raw_tx).await
-            } else {
+
+        if is_job_cancelled(&state, &job_id).await {
+            let message = MessageKey::CancelledBeforeChunks.with("address", address.as_str());
+            finish_cancelled(&state, &job_id, message).await;
+            return;
+        }
+
+        {
+            let state = state.read().await;
+            let _ = state.db.update_job_progress(&job_id, 8.0, MessageKey::BroadcastingSplit);
+        }
+
+        // Parents come first, so each split tx spends an output already broadcast
+        for (txid, raw_tx) in &split_plan.transactions {
+            match broadcast_tx(&state, Some(&job_id), raw_tx, network).await {
+                Ok(broadcast_txid) => {
+                    tracing::info!("UTXO split transaction broadcast: {}", broadcast_txid);
+                }
+                Err(e) => {
+                    tracing::warn!("Split transaction {} failed to broadcast: {}", txid, e);
+                    let state = state.read().await;
+                    let _ = state.db.update_job_error(&job_id, ErrorCode::BroadcastFailed, MessageKey::SplitBroadcastFailed.with_broadcast_error(&e));
+                    return;
+                }
+            }
+        }
+        let split_txid = split_plan
+            .transactions
+            .iter()
+            .map(|(txid, _)| txid.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        // Small delay to let the split tx propagate
+        sleep(Duration::from_millis(1000)).await;
+
+        // Now we have num_outputs UTXOs from the split transactions, in order
+        // We'll use outputs 0 to (new_chunks - 1) for chunks not stored yet
+        // And output new_chunks for the manifest
+
+        {
+            let state = state.read().await;
+            let _ = state.db.update_job_progress(
+                &job_id,
+                10.0,
+                MessageKey::UploadingChunks.with("n", total_chunks),
+            );
+        }
+
+        // Broadcast each chunk using its dedicated UTXO. A provider rejecting
+        // a chunk as too large gets the rest of the data in chunks half the
+        // size, funded by re-splitting the outputs not spent yet.
+        let mut chunk_txids: Vec<String> = Vec::new();
+        let bytes_total = file_size as i64;
+        let mut bytes_done: i64 = 0;
+        let mut chunk_outputs: Vec<(String, u32)> = split_plan.outputs[..new_chunks].to_vec();
+        let mut chunk_output_satoshis = satoshis_per_output;
+        let mut spare_change = split_plan.change.clone();
+        let mut next_vout: usize = 0;
+        let mut chunk_size = max_tx_data_size;
+        let mut halvings: u32 = 0;
+        let max_chunk_halvings = state.read().await.config.max_chunk_halvings;
+
+        let mut i = 0;
+        while i < chunks.len() {
+            let chunk = &chunks[i];
+            if is_job_cancelled(&state, &job_id).await {
+                let message = MessageKey::CancelledDuringChunks
+                    .with("i", i)
+                    .with("n", total_chunks)
+                    .with("unused", chunk_outputs.len() - next_vout + 1)
+                    .with("address", address.as_str());
+                finish_cancelled(&state, &job_id, message).await;
+                return;
+            }
+
+            if let Some(txid) = &stored_chunk_txids[i] {
+                tracing::info!("Chunk {}/{} is already stored as {}, reusing it", i + 1, total_chunks, txid);
+                chunk_txids.push(txid.clone());
+                bytes_done += chunk.len() as i64;
+                let progress = 10.0 + (70.0 * (bytes_done as f64 / bytes_total as f64));
+                let state = state.read().await;
+                let _ = state.db.update_job_transfer(
+                    &job_id,
+                    bytes_done,
+                    bytes_total,
+                    progress,
+                    MessageKey::ChunkReused.with("i", i + 1).with("n", total_chunks),
+                );
+                i += 1;
+                continue;
+            }
+
+            // Derive progress from bytes so a short final chunk doesn't distort it
+            let progress = 10.0 + (70.0 * (bytes_done as f64 / bytes_total as f64));
+            
+            {
+                let state = state.read().await;
+                let _ = state.db.update_job_transfer(
+                    &job_id,
+                    bytes_done,
+                    bytes_total,
+                    progress,
+                    MessageKey::UploadingChunk.with("i", i + 1).with("n", total_chunks),
+                );
+            }
+
+            // Create chunk script
+            let chunk_script = {
+                let state = state.read().await;
+                state.bsv.create_flac_chunk_script(i as u32, total_chunks as u32, chunk)
+            };
+
+            // Use the dedicated UTXO for this chunk (from split transaction)
+            let (utxo_txid, utxo_vout) = chunk_outputs[next_vout].clone();
+            let chunk_utxo_input = vec![(
+                utxo_txid,
+                utxo_vout,  // split outputs go to new chunks in order
+                chunk_output_satoshis.to_sat_i64(),
+                script_pubkey.clone(),
+            )];
+
+            // Output: chunk data only (use all remaining satoshis as implicit fee)
+            let outputs: Vec<(Vec<u8>, Amount)> = vec![(chunk_script, data_output_satoshis)];
+
+            // Create transaction
+            let raw_tx = {
                 let state = state.read().await;
-                state.bitails.broadcast_transaction(&cover_raw_tx).await
+                state.bsv.create_transaction(&wif, &chunk_utxo_input, &outputs)
+            };
+
+            let raw_tx = match raw_tx {
+                Ok(tx) => tx,
+                Err(e) => {
+                    let state = state.read().await;
+                    let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::ChunkBuildFailed.with("i", i + 1).with("error", e));
+                    return;
+                }
             };
+
+            // Known up front so a retry can tell whether a lost broadcast went through
+            let chunk_txid = BsvService::txid(&raw_tx).unwrap_or_default();
+
+            // Broadcast with retry logic
+            let mut broadcast_success = false;
+            let mut last_error = BroadcastError { attempts: Vec::new() };
             
-            match cover_broadcast_result {
-                Ok(txid) => {
-                    tracing::info!("Cover image uploaded: {}", txid);
-                    // Add change output as new UTXO if we created one
-                    if change_amount > 546 {
-                        utxos.insert(0, Utxo {
-                            txid: txid.clone(),
-                            vout: 1,
-                            satoshis: change_amount,
-                            script_pubkey: String::new(),
-                            blockheight: Some(0),
-                            confirmations: Some(0),
-                        });
+            for retry in 0..5 {
+                if retry > 0 {
+                    // Exponential backoff: 1s, 2s, 4s, 8s
+                    let delay = Duration::from_secs(1 << retry);
+                    tracing::warn!("Retrying chunk {} broadcast after {:?} (attempt {})", i + 1, delay, retry + 1);
+                    {
+                        // Progress holds while retrying; only the message changes
+                        let state = state.read().await;
+                        let _ = state.db.update_job_transfer(
+                            &job_id,
+                            bytes_done,
+                            bytes_total,
+                            progress,
+                            MessageKey::RetryingChunk
+                                .with("i", i + 1)
+                                .with("n", total_chunks)
+                                .with("attempt", retry + 1),
+                        );
                     }
-                    // Wait for propagation
-                    sleep(Duration::from_millis(1000)).await;
-                    Some(txid)
+                    sleep(delay).await;
                 }
-                Err(e) => {
-                    tracing::warn!("Failed to broadcast cover image: {}", e);
-                    None
+                
+                // An earlier attempt may have reached the network even though its
+                // response was lost; some providers reject the rebroadcast outright
+                let already_known = retry > 0
+                    && !chunk_txid.is_empty()
+                    && fetch_tx_raw(&state, &chunk_txid, network).await.is_ok();
+
+                let broadcast_result = if already_known {
+                    tracing::info!("Chunk {} is already on the network as {}, not rebroadcasting", i + 1, chunk_txid);
+                    Ok(chunk_txid.clone())
+                } else {
+                    broadcast_tx(&state, Some(&job_id), &raw_tx, network).await
+                };
+
+                match broadcast_result {
+                    Ok(txid) => {
+                        tracing::info!("Chunk {}/{} broadcast: {}", i + 1, total_chunks, txid);
+                        {
+                            let state = state.read().await;
+                            let _ = state.db.record_stored_chunk(&chunk_hashes[i], network, i as u32, &txid);
+                        }
+                        chunk_txids.push(txid);
+                        bytes_done += chunk.len() as i64;
+                        next_vout += 1;
+                        broadcast_success = true;
+
+                        // Count the chunk as soon as it is accepted
+                        let progress = 10.0 + (70.0 * (bytes_done as f64 / bytes_total as f64));
+                        let state = state.read().await;
+                        let _ = state.db.update_job_transfer(
+                            &job_id,
+                            bytes_done,
+                            bytes_total,
+                            progress,
+                            MessageKey::ChunkBroadcast.with("i", i + 1).with("n", total_chunks),
+                        );
+                        break;
+                    }
+                    Err(e) => {
+                        last_error = e;
+                        tracing::warn!("Chunk {} broadcast failed: {}", i + 1, last_error);
+                        // The same transaction would only be rejected again
+                        if last_error.rejected_for_size() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if !broadcast_success && last_error.rejected_for_size() {
+                let halved = BsvService::halved_chunk_size(chunk.len().min(chunk_size))
+                    .filter(|_| halvings < max_chunk_halvings);
+                let Some(halved) = halved else {
+                    let state = state.read().await;
+                    let _ = state.db.update_job_error(
+                        &job_id,
+                        ErrorCode::BroadcastFailed,
+                        MessageKey::ChunkTooLarge
+                            .with("i", i + 1)
+                            .with("size", chunk.len())
+                            .with_broadcast_error(&last_error),
+                    );
+                    return;
+                };
+
+                // Chunks from here on are cut again, none of them broadcast yet
+                let remaining = chunks.split_off(i).concat();
+                chunks.extend(remaining.chunks(halved).map(<[u8]>::to_vec));
+                let pieces = chunks.len() - i;
+                chunk_hashes.truncate(i);
+                chunk_hashes.extend(chunks[i..].iter().map(|chunk| hex::encode(Sha256::digest(chunk))));
+                stored_chunk_txids.truncate(i);
+                stored_chunk_txids.resize(chunks.len(), None);
+                total_chunks = chunks.len();
+                tracing::warn!(
+                    "Chunk {} was rejected as too large; uploading the remaining {} bytes in {} chunks of {} bytes",
+                    i + 1,
+                    remaining.len(),
+                    pieces,
+                    halved
+                );
+                {
+                    let state = state.read().await;
+                    let _ = state.db.update_job_transfer(
+                        &job_id,
+                        bytes_done,
+                        bytes_total,
+                        progress,
+                        MessageKey::ChunkSizeReduced
+                            .with("i", i + 1)
+                            .with("bytes", remaining.len())
+                            .with("n", pieces)
+                            .with("size", halved),
+                    );
+                }
+
+                let mut inputs: Vec<(String, u32, i64)> = chunk_outputs
+                    .split_off(next_vout)
+                    .into_iter()
+                    .map(|(txid, vout)| (txid, vout, chunk_output_satoshis.to_sat_i64()))
+                    .collect();
+                inputs.extend(spare_change.take().map(|(txid, vout, satoshis)| (txid, vout, satoshis.to_sat_i64())));
+                let halved_output_satoshis = state.read().await.bsv.calculate_chunk_output_satoshis(halved);
+                let resplit = resplit_chunk_outputs(
+                    &state,
+                    &job_id,
+                    &wif,
+                    &script_pubkey,
+                    &inputs,
+                    pieces,
+                    halved_output_satoshis,
+                    network,
+                )
+                .await;
+                match resplit {
+                    Ok(plan) => {
+                        chunk_outputs.extend(plan.outputs);
+                        spare_change = plan.change;
+                    }
+                    Err((code, message)) => {
+                        let state = state.read().await;
+                        let _ = state.db.update_job_error(&job_id, code, message);
+                        return;
+                    }
+                }
+                chunk_output_satoshis = halved_output_satoshis;
+                chunk_size = halved;
+                halvings += 1;
+                continue;
+            }
+
+            if !broadcast_success {
+                let state = state.read().await;
+                let _ = state.db.update_job_error(
+                    &job_id,
+                    ErrorCode::BroadcastFailed,
+                    MessageKey::ChunkBroadcastFailed
+                        .with("i", i + 1)
+                        .with("retries", 5)
+                        .with_broadcast_error(&last_error),
+                );
+                return;
+            }
+            
+            // Small delay between broadcasts
+            sleep(Duration::from_millis(500)).await;
+            i += 1;
+        }
+
+        if is_job_cancelled(&state, &job_id).await {
+            let message = MessageKey::CancelledBeforeManifest
+                .with("n", total_chunks)
+                .with("address", address.as_str());
+            finish_cancelled(&state, &job_id, message).await;
+            return;
+        }
+
+        // Now create manifest transaction using the last split UTXO
+        {
+            let state = state.read().await;
+            let _ = state.db.update_job_transfer(&job_id, bytes_done, bytes_total, 85.0, MessageKey::CreatingManifest);
+        }
+
+        // Create manifest script with title, artist, lyrics, and cover
+        let layout = {
+            let state = state.read().await;
+            ManifestLayout::from_str(&state.config.manifest_metadata_layout).unwrap_or(ManifestLayout::Json)
+        };
+        let manifest_script = BsvService::create_flac_manifest_script(
+            &filename,
+            file_size,
+            &chunk_txids,
+            &chunk_hashes,
+            track_title.as_deref(),
+            artist_name.as_deref(),
+            lyrics.as_deref(),
+            lyrics_txid.as_deref(),
+            cover_txid.as_deref(),
+            cover_thumb.as_ref(),
+            royalty.as_ref().map(|(a, sats)| (a.as_str(), *sats)),
+            layout,
+        );
+        // Kept with the job as a reader sees it, so the manifest can be shown offline
+        let manifest_json = parse_flac_manifest_output(&manifest_script).and_then(|m| serde_json::to_string(&m).ok());
+
+        // Use the last split UTXO for manifest
+        let (utxo_txid, utxo_vout) = split_plan.outputs[new_chunks].clone();
+        let manifest_utxo_input = vec![(
+            utxo_txid,
+            utxo_vout,
+            satoshis_per_output.saturating_add(royalty_satoshis).to_sat_i64(),
+            script_pubkey.clone(),
+        )];
+
+        let mut outputs: Vec<(Vec<u8>, Amount)> = vec![(manifest_script, data_output_satoshis)];
+        if let Some(output) = royalty_output {
+            outputs.push(output);
+        }
+
+        let raw_tx = {
+            let state = state.read().await;
+            state.bsv.create_transaction(&wif, &manifest_utxo_input, &outputs)
+        };
+
+        let raw_tx = match raw_tx {
+            Ok(tx) => tx,
+            Err(e) => {
+                let state = state.read().await;
+                let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::ManifestBuildFailed.with("error", e));
+                return;
+            }
+        };
+
+        // Broadcast manifest
+        {
+            let state = state.read().await;
+            let _ = state.db.update_job_progress(&job_id, 95.0, MessageKey::BroadcastingManifest);
+        }
+
+        let broadcast_result = broadcast_tx(&state, Some(&job_id), &raw_tx, network).await;
+
+        match broadcast_result {
+            Ok(manifest_txid) => {
+                let state = state.read().await;
+                let _ = state.db.update_job_chunk_txids(&job_id, &chunk_txids, &split_txid);
+                if let Some(manifest_json) = &manifest_json {
+                    let _ = state.db.update_job_manifest_json(&job_id, manifest_json);
                 }
+                let _ = state.db.update_job_complete(&job_id, &manifest_txid, None);
+                tracing::info!(
+                    "FLAC upload complete for job {}: manifest_txid={}, {} chunks",
+                    job_id,
+                    manifest_txid,
+                    total_chunks
+                );
+            }
+            Err(e) => {
+                let state = state.read().await;
+                let _ = state.db.update_job_error(&job_id, ErrorCode::BroadcastFailed, MessageKey::ManifestBroadcastFailed.with_broadcast_error(&e));
             }
         }
     } else {
-        None
-    };
+        // Single transaction approach (for small files)
+        {
+            let state = state.read().await;
+            let _ = state.db.update_job_transfer(&job_id, 0, file_size as i64, 30.0, MessageKey::CreatingFlacTransaction);
+        }
 
-    if needs_chunking {
-        // Multi-transaction chunking approach with UTXO pre-splitting
-        let total_input: i64 = utxos.iter().map(|u| u.satoshis).sum();
+        let total_input = match Amount::sum_sat(utxos.iter().map(|u| u.satoshis)) {
+            Ok(total) => total,
+            Err(e) => {
+                let state = state.read().await;
+                let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::TxBuildFailed.with("error", e.to_string()));
+                return;
+            }
+        };
+
+        let utxo_inputs: Vec<(String, u32, i64, Vec<u8>)> = utxos
+            .iter()
+            .map(|u| (u.txid.clone(), u.vout, u.satoshis, script_pubkey.clone()))
+            .collect();
+
+        // Create OP_FALSE OP_IF script for FLAC storage
+        let protocol = b"flacstore";
+        let mime_type = b"audio/flac";
         
-        // Split file into chunks
-        let mut chunks: Vec<Vec<u8>> = Vec::new();
-        let mut offset = 0;
-        while offset < file_size {
-            let end = std::cmp::min(offset + max_tx_data_size, file_size);
-            chunks.push(file_data[offset..end].to_vec());
-            offset = end;
+        let mut metadata = serde_json::json!({
+            "filename": filename,
+            "size": file_data.len(),
+            "version": "1.1",
+            "chunked": false
+        });
+        // The same track fields a manifest carries; the cover and long lyrics
+        // are the transactions inscribed above
+        let lyrics_format = lyrics.as_deref().map(crate::services::lyrics::detect_format);
+        let track_fields = [
+            ("title", track_title.as_deref()),
+            ("artist", artist_name.as_deref()),
+            ("lyrics", if lyrics_txid.is_some() { None } else { lyrics.as_deref() }),
+            ("lyrics_format", lyrics_format.as_ref().map(|f| f.as_str())),
+            ("lyrics_txid", lyrics_txid.as_deref()),
+            ("cover_txid", cover_txid.as_deref()),
+        ];
+        let thumb_field = cover_thumb.as_ref().map(|thumb| {
+            let (label, value) = thumb.manifest_field();
+            (label, Some(value))
+        });
+        for (label, value) in track_fields.into_iter().chain(thumb_field) {
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                metadata[label] = serde_json::Value::from(value);
+            }
+        }
+        if let Some((royalty_address, satoshis)) = &royalty {
+            metadata["royalty_address"] = serde_json::Value::from(royalty_address.as_str());
+            metadata["royalty_satoshis"] = serde_json::Value::from(*satoshis);
         }
+        let metadata = metadata.to_string();
 
-        let total_chunks = chunks.len();
-        let num_outputs = total_chunks + 1; // +1 for manifest
-        
-        tracing::info!("Splitting {} bytes into {} chunks for job {}", file_size, total_chunks, job_id);
+        let flac_script = {
+            let state = state.read().await;
+            let data_chunks = BsvService::split_into_chunks(&file_data, state.bsv.max_push_size);
+            state.bsv.create_flac_store_script(
+                protocol,
+                mime_type,
+                metadata.as_bytes(),
+                &data_chunks,
+            )
+        };
 
-        // Calculate satoshis needed per output
-        let satoshis_per_output = {
+        // Fee, and the fee with room for a change output
+        let tx_size = 150 + flac_script.len();
+        let (fee, change_fee) = {
             let state = state.read().await;
-            state.bsv.calculate_chunk_output_satoshis(max_tx_data_size)
+            (state.bsv.fee_for_size(tx_size), state.bsv.fee_for_size(tx_size + 34))
         };
-        
-        tracing::info!("Satoshis per output: {}, total outputs: {}", satoshis_per_output, num_outputs);
 
-        // Update progress
-        {
+        let mut outputs: Vec<(Vec<u8>, Amount)> = vec![(flac_script, data_output_satoshis)];
+        if let Some(output) = royalty_output {
+            outputs.push(output);
+        }
+
+        // Return anything above the fee to the payment address
+        let outputs_total = data_output_satoshis.saturating_add(royalty_satoshis);
+        if let Ok(change) = total_input.checked_sub(change_fee.saturating_add(outputs_total)) {
+            if change > dust_limit {
+                outputs.push((script_pubkey.clone(), change));
+            }
+        }
+
+        let required = fee.saturating_add(outputs_total);
+        if total_input < required {
+            let key = if cover_txid.is_some() {
+                MessageKey::InsufficientFundsAfterCover
+            } else {
+                MessageKey::InsufficientFunds
+            };
             let state = state.read().await;
-            let _ = state.db.update_job_progress(
+            let _ = state.db.update_job_error(
                 &job_id,
-                5.0,
-                &format!("Preparing UTXO split for {} chunks...", total_chunks),
+                ErrorCode::InsufficientFunds,
+                key
+                    .with("available", total_input)
+                    .with("required", required),
             );
+            return;
         }
 
-        // Step 1: Create and broadcast UTXO split transaction
-        let first_utxo = &utxos[0];
-        let split_tx = {
+        let raw_tx = {
             let state = state.read().await;
-            state.bsv.create_split_transaction(
-                &wif,
-                &first_utxo.txid,
-                first_utxo.vout,
-                total_input,
-                &script_pubkey,
-                num_outputs,
-                satoshis_per_output,
-            )
+            state.bsv.create_transaction(&wif, &utxo_inputs, &outputs)
         };
 
-        let split_tx = match split_tx {
+        let raw_tx = match raw_tx {
             Ok(tx) => tx,
             Err(e) => {
                 let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Failed to create split tx: {}", e));
+                let _ = state.db.update_job_error(&job_id, ErrorCode::TxBuildFailed, MessageKey::TxBuildFailed.with("error", e));
                 return;
             }
         };
 
+        if is_job_cancelled(&state, &job_id).await {
+            finish_cancelled(&state, &job_id, MessageKey::CancelledBeforeFileBroadcast).await;
+            return;
+        }
+
         {
             let state = state.read().await;
-            let _ = state.db.update_job_progress(&job_id, 8.0, "Broadcasting UTXO split transaction...");
+            let _ = state.db.update_job_progress(&job_id, 60.0, MessageKey::BroadcastingFlacTransaction);
         }
 
-        let split_txid = if network == "testnet" {
-            broadcast_testnet_tx(&split_tx).await
-        } else {
-            let state = state.read().await;
-            state.bitails.broadcast_transaction(&split_tx).await
-        };
+        let broadcast_result = broadcast_tx(&state, Some(&job_id), &raw_tx, network).await;
 
-        let split_txid = match split_txid {
+        match broadcast_result {
             Ok(txid) => {
-                tracing::info!("UTXO split transaction broadcast: {}", txid);
-                txid
+                let state = state.read().await;
+                let _ = state.db.update_job_complete(&job_id, &txid, None);
+                tracing::info!("FLAC upload complete for job {}: txid={}", job_id, txid);
             }
             Err(e) => {
                 let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Failed to broadcast split tx: {}", e));
-                return;
+                let _ = state.db.update_job_error(&job_id, ErrorCode::BroadcastFailed, MessageKey::BroadcastFailed.with_broadcast_error(&e));
+            }
+        }
+    }
+}
+
+/// Process download
+async fn process_download(state: Arc<RwLock<AppState>>, job_id: String, txid: Option<String>, network: Network) {
+    let txid = match txid {
+        Some(t) => t,
+        None => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::InvalidRequest, MessageKey::NoTxid);
+            return;
+        }
+    };
+
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 10.0, MessageKey::FetchingTransaction);
+    }
+
+    let tx_data = match fetch_tx_data(&state, &txid, network, parse_upfile_output).await {
+        Ok(data) => data,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::TxFetchFailed, MessageKey::TxFetchFailed.with("error", e));
+            return;
+        }
+    };
+
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 50.0, MessageKey::ExtractingData);
+    }
+
+    let file = match tx_data {
+        Some(file) => file,
+        None => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::NoDataFound, MessageKey::NoOpReturnData);
+            return;
+        }
+    };
+
+    let filename = routes::download::safe_filename(&file.filename);
+    let mime_type = crate::services::content_type::recorded_or_detect(&file.mime_type, &file.data, &filename);
+    let downloads_dir = std::path::Path::new(routes::download::DOWNLOADS_DIR);
+    std::fs::create_dir_all(downloads_dir).ok();
+
+    let file_path = downloads_dir.join(&filename);
+    if let Err(e) = std::fs::write(&file_path, &file.data) {
+        let state = state.read().await;
+        let _ = state.db.update_job_error(&job_id, ErrorCode::SaveFailed, MessageKey::SaveFailed.with("error", e.to_string()));
+        return;
+    }
+
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_complete_with_filename(
+            &job_id,
+            &txid,
+            Some(&routes::download::download_link(&filename)),
+            &filename,
+            &mime_type,
+        );
+    }
+
+    tracing::info!("Download complete for job {}: {}", job_id, filename);
+}
+
+/// Fetch transaction data from appropriate API based on network.
+/// A mainnet tx from Bitails that doesn't parse back to the requested txid
+/// is fetched again from WhatsOnChain, which goes first while Bitails is
+/// failing.
+async fn fetch_tx_raw(state: &Arc<RwLock<AppState>>, txid: &str, network: Network) -> Result<String, String> {
+    use crate::services::provider_health::Provider;
+
+    if crate::services::whatsonchain::serves(network) {
+        // Use WhatsOnChain for testnet and STN
+        return fetch_whatsonchain_tx_hex(txid, network).await;
+    }
+
+    let health = crate::services::provider_health::mainnet();
+    let mut errors = Vec::new();
+    for provider in health.order(Provider::Bitails, Provider::WhatsOnChain) {
+        let result = match provider {
+            Provider::Bitails => {
+                let state = state.read().await;
+                match state.bitails.download_tx_raw(txid).await {
+                    Ok(tx_hex) if is_tx_hex_for(&tx_hex, txid) => Ok(tx_hex),
+                    Ok(_) => Err("Bitails returned data that is not the requested transaction".to_string()),
+                    Err(e) => Err(format!("Bitails: {}", e)),
+                }
             }
+            Provider::WhatsOnChain => fetch_whatsonchain_tx_hex(txid, network)
+                .await
+                .map_err(|e| format!("WhatsOnChain: {}", e)),
         };
+        health.record(provider, result.is_ok());
+        match result {
+            Ok(tx_hex) => return Ok(tx_hex),
+            Err(e) => {
+                if errors.is_empty() {
+                    tracing::warn!("{} for {}, trying the other provider", e, txid);
+                }
+                errors.push(e);
+            }
+        }
+    }
+    Err(errors.join("; "))
+}
 
-        // Small delay to let the split tx propagate
-        sleep(Duration::from_millis(1000)).await;
+/// Smallest script that can be a data output; a P2PKH script is 25 bytes
+const MIN_DATA_SCRIPT_SIZE: i64 = 26;
+
+/// Download only the output of a mainnet tx that carries its data: the
+/// largest script Bitails lists, if any is bigger than a P2PKH script
+async fn fetch_bitails_data_script(state: &Arc<RwLock<AppState>>, txid: &str) -> Result<Vec<u8>, String> {
+    let state = state.read().await;
+    let tx = state.bitails.get_transaction(txid).await?;
+    let output = tx
+        .outputs
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|o| o.script_size.unwrap_or(0) >= MIN_DATA_SCRIPT_SIZE)
+        .max_by_key(|o| o.script_size)
+        .ok_or_else(|| "no output large enough to hold data".to_string())?;
+    state.bitails.download_tx_output(txid, output.index).await
+}
+
+/// Read a transaction's data with `parse`, which is given output scripts.
+/// On mainnet only the data output is downloaded; when that fails or
+/// doesn't parse, the whole transaction is fetched and every output tried.
+async fn fetch_tx_data<T>(
+    state: &Arc<RwLock<AppState>>,
+    txid: &str,
+    network: Network,
+    parse: impl Fn(&[u8]) -> Option<T>,
+) -> Result<Option<T>, String> {
+    use crate::services::provider_health::Provider;
+
+    // The data output alone comes from Bitails, so skip it while Bitails is failing
+    let bitails_first = crate::services::provider_health::mainnet()
+        .order(Provider::Bitails, Provider::WhatsOnChain)[0]
+        == Provider::Bitails;
+    if !crate::services::whatsonchain::serves(network) && bitails_first {
+        match fetch_bitails_data_script(state, txid).await {
+            Ok(script) => {
+                if let Some(data) = parse(&script) {
+                    return Ok(Some(data));
+                }
+                tracing::debug!("Data output of {} did not parse, fetching the full tx", txid);
+            }
+            Err(e) => tracing::debug!("Data output download failed for {}: {}, fetching the full tx", txid, e),
+        }
+    }
+
+    let tx_hex = fetch_tx_raw(state, txid, network).await?;
+    Ok(find_in_outputs(&tx_hex, parse))
+}
+
+/// Lyrics and their format from a flacstore-lyrics transaction
+async fn fetch_lyrics_tx(
+    state: &Arc<RwLock<AppState>>,
+    txid: &str,
+    network: Network,
+) -> Result<Option<(String, crate::services::lyrics::LyricsFormat)>, String> {
+    fetch_tx_data(state, txid, network, parse_lyrics_output).await
+}
+
+/// An upload's lyrics: the inline ones, or those of the transaction its
+/// metadata references. Lyrics that can't be fetched leave the track without
+/// them rather than failing the download.
+async fn resolve_lyrics(
+    state: &Arc<RwLock<AppState>>,
+    lyrics: Option<String>,
+    lyrics_txid: Option<&str>,
+    network: Network,
+) -> Option<String> {
+    let txid = match lyrics_txid {
+        Some(txid) => txid,
+        None => return lyrics,
+    };
+    match fetch_lyrics_tx(state, txid, network).await {
+        Ok(Some((lyrics, _))) => Some(lyrics),
+        Ok(None) => {
+            tracing::warn!("Lyrics transaction {} holds no lyrics", txid);
+            None
+        }
+        Err(e) => {
+            tracing::warn!("Failed to fetch lyrics transaction {}: {}", txid, e);
+            None
+        }
+    }
+}
+
+/// Keep the cover thumbnail an upload's metadata carries, inline or in a
+/// coverart transaction of its own, so listings can show the cover without
+/// fetching the full image. A thumbnail that can't be read is skipped.
+async fn store_cover_thumb(
+    state: &Arc<RwLock<AppState>>,
+    cover_txid: Option<&str>,
+    thumb_inline: Option<&str>,
+    thumb_txid: Option<&str>,
+    network: Network,
+) {
+    let cover_txid = match cover_txid {
+        Some(txid) => txid,
+        None => return,
+    };
+    if state.read().await.db.get_cover_thumb(cover_txid).ok().flatten().is_some() {
+        return;
+    }
+    let thumb = match (thumb_inline, thumb_txid) {
+        (Some(inline), _) => base64::engine::general_purpose::STANDARD.decode(inline).ok(),
+        (None, Some(txid)) => fetch_tx_data(state, txid, network, parse_image_output).await.ok().flatten(),
+        (None, None) => return,
+    };
+    match thumb {
+        Some(thumb) => {
+            let _ = state.read().await.db.save_cover_thumb(cover_txid, &thumb);
+        }
+        None => tracing::warn!("Cover thumbnail of {} could not be read", cover_txid),
+    }
+}
+
+/// Raw tx hex from WhatsOnChain
+async fn fetch_whatsonchain_tx_hex(txid: &str, network: Network) -> Result<String, String> {
+    let url = format!("{}/tx/{}/hex", crate::services::whatsonchain::base_url(network), txid);
+    let client = crate::services::http::client();
+    crate::services::rate_limit::whatsonchain().acquire().await;
+    let response = client.get(&url).send().await.map_err(|e| format!("Request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("API error: {}", response.status()));
+    }
+    let tx_hex = response.text().await.map_err(|e| format!("Parse error: {}", e))?;
+    Ok(tx_hex.trim().to_string())
+}
+
+/// Raw tx hex of up to BULK_TX_LIMIT transactions in one WhatsOnChain
+/// request. The outer error means the request itself failed; each txid
+/// maps to its hex or to why WhatsOnChain didn't return it.
+async fn fetch_whatsonchain_txs_hex(
+    txids: &[String],
+    network: Network,
+) -> Result<HashMap<String, Result<String, String>>, String> {
+    #[derive(serde::Deserialize)]
+    struct BulkTx {
+        txid: String,
+        hex: Option<String>,
+        error: Option<String>,
+    }
+
+    let url = format!("{}/txs/hex", crate::services::whatsonchain::base_url(network));
+    let client = crate::services::http::client();
+    crate::services::rate_limit::whatsonchain().acquire().await;
+    let response = client
+        .post(&url)
+        .json(&serde_json::json!({ "txids": txids }))
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("API error: {}", response.status()));
+    }
+    let entries: Vec<BulkTx> = response.json().await.map_err(|e| format!("Parse error: {}", e))?;
+
+    let mut results: HashMap<String, Result<String, String>> = entries
+        .into_iter()
+        .map(|entry| {
+            let result = match (entry.hex, entry.error) {
+                (_, Some(error)) if !error.is_empty() => Err(format!("WhatsOnChain error: {}", error)),
+                (Some(hex), _) if is_tx_hex_for(&hex, &entry.txid) => Ok(hex.trim().to_string()),
+                (Some(_), _) => Err("returned data that is not the requested transaction".to_string()),
+                (None, _) => Err("returned no hex".to_string()),
+            };
+            (entry.txid.to_lowercase(), result)
+        })
+        .collect();
+    for txid in txids {
+        results
+            .entry(txid.to_lowercase())
+            .or_insert_with(|| Err("missing from the bulk response".to_string()));
+    }
+    Ok(results)
+}
+
+/// Output that carries the data in a chunk transaction
+const CHUNK_DATA_VOUT: u32 = 0;
+
+/// Fetch the data of one FLAC chunk, or None if the tx holds no chunk.
+/// On Bitails only the data output is downloaded; anything unexpected
+/// falls back to fetching and parsing the whole transaction.
+async fn fetch_flac_chunk(state: &Arc<RwLock<AppState>>, txid: &str, network: Network) -> Result<Option<(u32, Vec<u8>)>, String> {
+    if !crate::services::whatsonchain::serves(network) {
+        let script = {
+            let state = state.read().await;
+            state.bitails.download_tx_output(txid, CHUNK_DATA_VOUT).await
+        };
+        match script {
+            Ok(script) => {
+                if let Some(chunk) = script.strip_prefix(&[0x00, 0x63]).and_then(parse_flac_chunk_script) {
+                    return Ok(Some(chunk));
+                }
+                tracing::debug!("Output {} of {} is not a chunk script, fetching the full tx", CHUNK_DATA_VOUT, txid);
+            }
+            Err(e) => tracing::debug!("Output download failed for {}: {}, fetching the full tx", txid, e),
+        }
+    }
+
+    let tx_hex = fetch_tx_raw(state, txid, network).await?;
+    Ok(extract_flac_chunk_from_tx(&tx_hex))
+}
+
+/// Address that funded a payment, taken from the first P2PKH input of the funding tx
+async fn find_payment_sender(state: &Arc<RwLock<AppState>>, funding_txid: &str, network: Network) -> Option<String> {
+    let tx_hex = match fetch_tx_raw(state, funding_txid, network).await {
+        Ok(hex) => hex,
+        Err(e) => {
+            tracing::warn!("Failed to fetch funding tx {}: {}", funding_txid, e);
+            return None;
+        }
+    };
+
+    let tx = parse_transaction(&tx_hex)?;
+    tx.inputs
+        .iter()
+        .find_map(|input| extract_pubkey_from_script_sig(&input.script_sig))
+        .map(|pubkey| BsvService::pubkey_bytes_to_address(&pubkey, network))
+}
+
+/// Content type of a downloaded FLAC track
+const FLAC_MIME_TYPE: &str = "audio/flac";
+
+/// Process FLAC download. Returns the saved file, or None once the job
+/// records why there isn't one.
+async fn process_flac_download(
+    state: Arc<RwLock<AppState>>,
+    job_id: String,
+    txid: Option<String>,
+    network: Network,
+) -> Option<Vec<u8>> {
+    let txid = match txid {
+        Some(t) => t,
+        None => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::InvalidRequest, MessageKey::NoTxid);
+            return None;
+        }
+    };
+
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 5.0, MessageKey::FetchingManifest);
+    }
+
+    let tx_data = fetch_tx_data(&state, &txid, network, parse_flac_output).await;
+
+    let tx_data = match tx_data {
+        Ok(data) => data,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(&job_id, ErrorCode::TxFetchFailed, MessageKey::TxFetchFailed.with("error", e));
+            return None;
+        }
+    };
 
-        // Now we have num_outputs UTXOs from the split transaction
-        // Each output is at vout 0, 1, 2, ... (num_outputs - 1)
-        // We'll use outputs 0 to (total_chunks - 1) for chunks
-        // And output total_chunks for the manifest
+    save_flac_download(state, job_id, txid, network, tx_data).await
+}
+
+/// Run a FLAC download inside the request when its txid holds the whole
+/// file. A manifest is left to the queue, which fetches it again along with
+/// its chunks; returns whether the job was handled here.
+async fn download_flac_inline(state: &Arc<RwLock<AppState>>, job_id: &str, txid: &str, network: Network) -> bool {
+    let tx_data = match fetch_tx_data(state, txid, network, parse_flac_output).await {
+        Ok(Some(FlacData::Manifest(_))) => return false,
+        Ok(data) => data,
+        Err(e) => {
+            let state = state.read().await;
+            let _ = state.db.update_job_error(job_id, ErrorCode::TxFetchFailed, MessageKey::TxFetchFailed.with("error", e));
+            return true;
+        }
+    };
+    save_flac_download(state.clone(), job_id.to_string(), txid.to_string(), network, tx_data).await;
+    true
+}
+
+/// Save what a FLAC download's txid holds: the file of a single-transaction
+/// upload, or the chunks its manifest lists. Returns the saved file.
+async fn save_flac_download(
+    state: Arc<RwLock<AppState>>,
+    job_id: String,
+    txid: String,
+    network: Network,
+    tx_data: Option<FlacData>,
+) -> Option<Vec<u8>> {
+    use tokio::time::{sleep, Duration};
+
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 10.0, MessageKey::ParsingTransaction);
+    }
 
-        {
+    // A manifest means a multi-chunk upload
+    if let Some(FlacData::Manifest(manifest)) = tx_data {
+        // Multi-chunk download
+        let filename = manifest.filename;
+        let chunk_txids = manifest.chunk_txids;
+        let chunk_hashes = manifest.chunk_hashes;
+        let track_title = manifest.title;
+        let artist_name = manifest.artist;
+        let lyrics = resolve_lyrics(&state, manifest.lyrics, manifest.lyrics_txid.as_deref(), network).await;
+        store_cover_thumb(
+            &state,
+            manifest.cover_txid.as_deref(),
+            manifest.thumb_inline.as_deref(),
+            manifest.thumb_txid.as_deref(),
+            network,
+        )
+        .await;
+        // A cover attached after upload replaces the one in the manifest
+        let cover_txid = {
             let state = state.read().await;
-            let _ = state.db.update_job_progress(
-                &job_id,
-                10.0,
-                &format!("Uploading {} chunks...", total_chunks),
-            );
+            state.db.latest_cover_link(&txid, network).ok().flatten()
         }
+        .or(manifest.cover_txid);
+        let total_chunks = chunk_txids.len();
+        // Placed by their inscribed index once all are in, not by manifest order
+        let mut fetched_chunks: Vec<(u32, Vec<u8>)> = Vec::with_capacity(total_chunks);
+        let mut bytes_fetched: usize = 0;
+        // Older manifests may not declare a size; fall back to chunk-count progress then
+        let bytes_total = manifest.size.unwrap_or(0) as i64;
+
+        // WhatsOnChain serves up to BULK_TX_LIMIT chunk txs per request;
+        // Bitails has no bulk call and fetches only each data output instead
+        let bulk = crate::services::whatsonchain::serves(network);
+        let mut prefetched: HashMap<String, Result<String, String>> = HashMap::new();
+        let mut prefetched_until = 0;
 
-        // Broadcast each chunk using its dedicated UTXO
-        let mut chunk_txids: Vec<String> = Vec::new();
-        
-        for (i, chunk) in chunks.iter().enumerate() {
-            let progress = 10.0 + (70.0 * (i as f64 / total_chunks as f64));
+        for (i, chunk_txid) in chunk_txids.iter().enumerate() {
+            if is_job_cancelled(&state, &job_id).await {
+                let message = MessageKey::DownloadCancelled.with("i", i).with("n", total_chunks);
+                finish_cancelled(&state, &job_id, message).await;
+                return None;
+            }
+
+            let fraction = if bytes_total > 0 {
+                (bytes_fetched as f64 / bytes_total as f64).min(1.0)
+            } else {
+                i as f64 / total_chunks as f64
+            };
+            let progress = 15.0 + (75.0 * fraction);
             
             {
                 let state = state.read().await;
-                let _ = state.db.update_job_progress(
-                    &job_id,
-                    progress,
-                    &format!("Uploading chunk {}/{}...", i + 1, total_chunks),
-                );
+                let message = MessageKey::DownloadingChunk.with("i", i + 1).with("n", total_chunks);
+                let _ = if bytes_total > 0 {
+                    state.db.update_job_transfer(&job_id, bytes_fetched as i64, bytes_total, progress, message)
+                } else {
+                    state.db.update_job_progress(&job_id, progress, message)
+                };
             }
 
-            // Create chunk script
-            let chunk_script = BsvService::create_flac_chunk_script(i as u32, total_chunks as u32, chunk);
-
-            // Calculate fee for this chunk
-            let tx_size = 200 + chunk_script.len();
-            let fee = {
-                let state = state.read().await;
-                (tx_size as f64 * state.bsv.fee_rate).ceil() as i64
-            };
-
-            // Use the dedicated UTXO for this chunk (from split transaction)
-            let chunk_utxo_input = vec![(
-                split_txid.clone(),
-                i as u32,  // vout is the chunk index
-                satoshis_per_output,
-                script_pubkey.clone(),
-            )];
-
-            // Output: chunk data only (use all remaining satoshis as implicit fee)
-            let outputs: Vec<(Vec<u8>, i64)> = vec![(chunk_script, 1)];
+            if bulk && i >= prefetched_until {
+                let batch = &chunk_txids[i..total_chunks.min(i + crate::services::whatsonchain::BULK_TX_LIMIT)];
+                prefetched_until = i + batch.len();
+                match fetch_whatsonchain_txs_hex(batch, network).await {
+                    Ok(txs) => prefetched.extend(txs),
+                    Err(e) => tracing::warn!(
+                        "Bulk fetch of chunks {}-{} failed, fetching them one by one: {}",
+                        i + 1,
+                        prefetched_until,
+                        e
+                    ),
+                }
+            }
 
-            // Create transaction
-            let raw_tx = {
-                let state = state.read().await;
-                state.bsv.create_transaction(&wif, &chunk_utxo_input, &outputs)
+            // A chunk the bulk request couldn't return is fetched on its own
+            let chunk_data = match prefetched.remove(&chunk_txid.to_lowercase()) {
+                Some(Ok(tx_hex)) => Ok(extract_flac_chunk_from_tx(&tx_hex)),
+                Some(Err(e)) => {
+                    tracing::warn!("Bulk fetch of chunk {} ({}) failed: {}, fetching it alone", i + 1, chunk_txid, e);
+                    fetch_flac_chunk(&state, chunk_txid, network).await
+                }
+                None => fetch_flac_chunk(&state, chunk_txid, network).await,
             };
-
-            let raw_tx = match raw_tx {
-                Ok(tx) => tx,
+            let chunk_data = match chunk_data {
+                Ok(data) => data,
                 Err(e) => {
                     let state = state.read().await;
-                    let _ = state.db.update_job_error(&job_id, &format!("Failed to create chunk {} tx: {}", i + 1, e));
-                    return;
+                    let _ = state.db.update_job_error(
+                        &job_id,
+                        ErrorCode::ChunkFetchFailed,
+                        MessageKey::ChunkFetchFailed.with("i", i + 1).with("error", e),
+                    );
+                    return None;
                 }
             };
 
-            // Broadcast with retry logic
-            let mut broadcast_success = false;
-            let mut last_error = String::new();
-            
-            for retry in 0..5 {
-                if retry > 0 {
-                    // Exponential backoff: 1s, 2s, 4s, 8s
-                    let delay = Duration::from_secs(1 << retry);
-                    tracing::warn!("Retrying chunk {} broadcast after {:?} (attempt {})", i + 1, delay, retry + 1);
-                    sleep(delay).await;
-                }
-                
-                let broadcast_result = if network == "testnet" {
-                    broadcast_testnet_tx(&raw_tx).await
-                } else {
+            if let Some(chunk) = chunk_data {
+                if !chunk_matches(&chunk_hashes, chunk.0, &chunk.1) {
                     let state = state.read().await;
-                    state.bitails.broadcast_transaction(&raw_tx).await
-                };
-
-                match broadcast_result {
-                    Ok(txid) => {
-                        tracing::info!("Chunk {}/{} broadcast: {}", i + 1, total_chunks, txid);
-                        chunk_txids.push(txid);
-                        broadcast_success = true;
-                        break;
-                    }
-                    Err(e) => {
-                        last_error = e;
-                        tracing::warn!("Chunk {} broadcast failed: {}", i + 1, last_error);
-                    }
+                    let _ = state.db.update_job_error(
+                        &job_id,
+                        ErrorCode::ChunkHashMismatch,
+                        MessageKey::ChunkHashMismatch.with("index", chunk.0).with("txid", chunk_txid.as_str()),
+                    );
+                    return None;
                 }
-            }
-            
-            if !broadcast_success {
+                bytes_fetched += chunk.1.len();
+                fetched_chunks.push(chunk);
+            } else {
                 let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Failed to broadcast chunk {} after 5 retries: {}", i + 1, last_error));
-                return;
+                let _ = state.db.update_job_error(
+                    &job_id,
+                    ErrorCode::NoDataFound,
+                    MessageKey::ChunkExtractFailed.with("i", i + 1),
+                );
+                return None;
             }
-            
-            // Small delay between broadcasts
-            sleep(Duration::from_millis(500)).await;
-        }
 
-        // Now create manifest transaction using the last split UTXO
-        {
-            let state = state.read().await;
-            let _ = state.db.update_job_progress(&job_id, 85.0, "Creating manifest...");
+            // Bulk requests are already paced by the WhatsOnChain rate limiter
+            if !bulk {
+                sleep(Duration::from_millis(100)).await;
+            }
         }
 
-        // Create manifest script with title, artist, lyrics, and cover
-        let manifest_script = BsvService::create_flac_manifest_script(
-            &filename,
-            file_size,
-            &chunk_txids,
-            track_title.as_deref(),
-            artist_name.as_deref(),
-            lyrics.as_deref(),
-            cover_txid.as_deref(),
-        );
-
-        // Use the last split UTXO for manifest (vout = total_chunks)
-        let manifest_utxo_input = vec![(
-            split_txid.clone(),
-            total_chunks as u32,  // Last output from split tx
-            satoshis_per_output,
-            script_pubkey.clone(),
-        )];
-
-        let outputs: Vec<(Vec<u8>, i64)> = vec![(manifest_script, 1)];
-
-        let raw_tx = {
-            let state = state.read().await;
-            state.bsv.create_transaction(&wif, &manifest_utxo_input, &outputs)
+        // A reordered manifest still assembles; a missing or repeated index would corrupt the audio
+        let all_data = match assemble_chunks(fetched_chunks, total_chunks) {
+            Ok(data) => data,
+            Err(mismatch) => {
+                let list = |indices: &[u32]| indices.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+                let state = state.read().await;
+                let _ = state.db.update_job_error(
+                    &job_id,
+                    ErrorCode::ChunkIndexMismatch,
+                    MessageKey::ChunkIndexMismatch
+                        .with("last", total_chunks.saturating_sub(1))
+                        .with("missing", list(&mismatch.missing))
+                        .with("duplicated", list(&mismatch.duplicated))
+                        .with("unexpected", list(&mismatch.unexpected)),
+                );
+                return None;
+            }
         };
 
-        let raw_tx = match raw_tx {
-            Ok(tx) => tx,
-            Err(e) => {
+        // A short chunk read would otherwise be saved as a truncated "complete" file
+        if let Some(expected) = manifest.size {
+            if all_data.len() != expected {
                 let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Failed to create manifest tx: {}", e));
-                return;
+                let _ = state.db.update_job_error(
+                    &job_id,
+                    ErrorCode::SizeMismatch,
+                    MessageKey::SizeMismatch
+                        .with("got", all_data.len())
+                        .with("expected", expected),
+                );
+                return None;
             }
-        };
+        }
 
-        // Broadcast manifest
         {
             let state = state.read().await;
-            let _ = state.db.update_job_progress(&job_id, 95.0, "Broadcasting manifest...");
+            let _ = state.db.update_job_progress(&job_id, 95.0, MessageKey::SavingFile);
         }
 
-        let broadcast_result = if network == "testnet" {
-            broadcast_testnet_tx(&raw_tx).await
-        } else {
+        let filename = routes::download::safe_filename(&filename);
+        let downloads_dir = std::path::Path::new(routes::download::DOWNLOADS_DIR);
+        std::fs::create_dir_all(downloads_dir).ok();
+
+        let file_path = downloads_dir.join(&filename);
+        if let Err(e) = std::fs::write(&file_path, &all_data) {
             let state = state.read().await;
-            state.bitails.broadcast_transaction(&raw_tx).await
-        };
+            let _ = state.db.update_job_error(&job_id, ErrorCode::SaveFailed, MessageKey::SaveFailed.with("error", e.to_string()));
+            return None;
+        }
 
-        match broadcast_result {
-            Ok(manifest_txid) => {
-                let state = state.read().await;
-                let _ = state.db.update_job_complete(&job_id, &manifest_txid, None);
-                tracing::info!(
-                    "FLAC upload complete for job {}: manifest_txid={}, {} chunks",
-                    job_id,
-                    manifest_txid,
-                    total_chunks
-                );
-            }
-            Err(e) => {
-                let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Failed to broadcast manifest: {}", e));
+        // Create web-accessible download link
+        let download_link = routes::download::download_link(&filename);
+        
+        {
+            let state = state.read().await;
+            let _ = state.db.update_job_complete_with_filename(
+                &job_id,
+                &txid,
+                Some(&download_link),
+                &filename,
+                FLAC_MIME_TYPE,
+            );
+            // Update metadata (title, artist, lyrics, cover_txid) from manifest
+            let _ = state.db.update_job_metadata(
+                &job_id,
+                track_title.as_deref(),
+                artist_name.as_deref(),
+                lyrics.as_deref(),
+            );
+            // Update cover_txid if available
+            if let Some(ref cover) = cover_txid {
+                let _ = state.db.update_job_cover_txid(&job_id, cover);
             }
         }
-    } else {
-        // Single transaction approach (for small files)
-        {
+        tracing::info!(
+            "FLAC chunked download complete for job {}: {} ({} chunks, {} bytes, title: {:?})",
+            job_id,
+            filename,
+            total_chunks,
+            all_data.len(),
+            track_title
+        );
+        Some(all_data)
+    } else if let Some(FlacData::File(file)) = tx_data {
+        // Single transaction download
+        let filename = routes::download::safe_filename(&file.filename);
+        let file_data = file.data;
+        let downloads_dir = std::path::Path::new(routes::download::DOWNLOADS_DIR);
+        std::fs::create_dir_all(downloads_dir).ok();
+
+        let file_path = downloads_dir.join(&filename);
+        if let Err(e) = std::fs::write(&file_path, &file_data) {
             let state = state.read().await;
-            let _ = state.db.update_job_progress(&job_id, 30.0, "Creating FLAC transaction...");
+            let _ = state.db.update_job_error(&job_id, ErrorCode::SaveFailed, MessageKey::SaveFailed.with("error", e.to_string()));
+            return None;
+        }
+
+        // Create web-accessible download link
+        let download_link = routes::download::download_link(&filename);
+        
+        let lyrics = resolve_lyrics(&state, file.lyrics, file.lyrics_txid.as_deref(), network).await;
+        store_cover_thumb(&state, file.cover_txid.as_deref(), file.thumb_inline.as_deref(), file.thumb_txid.as_deref(), network).await;
+        let state = state.read().await;
+        let _ = state.db.update_job_complete_with_filename(
+            &job_id,
+            &txid,
+            Some(&download_link),
+            &filename,
+            FLAC_MIME_TYPE,
+        );
+        let _ = state.db.update_job_metadata(
+            &job_id,
+            file.title.as_deref(),
+            file.artist.as_deref(),
+            lyrics.as_deref(),
+        );
+        // A cover attached after upload replaces the one in the metadata
+        let cover_txid = state.db.latest_cover_link(&txid, network).ok().flatten().or(file.cover_txid);
+        if let Some(ref cover) = cover_txid {
+            let _ = state.db.update_job_cover_txid(&job_id, cover);
         }
+        tracing::info!("FLAC download complete for job {}: {}", job_id, filename);
+        Some(file_data)
+    } else {
+        let state = state.read().await;
+        let _ = state.db.update_job_error(&job_id, ErrorCode::NoDataFound, MessageKey::NoFlacData);
+        None
+    }
+}
 
-        let total_input: i64 = utxos.iter().map(|u| u.satoshis).sum();
+/// Number of tracks a batch download fetches at the same time
+const BATCH_DOWNLOAD_CONCURRENCY: usize = 2;
 
-        let utxo_inputs: Vec<(String, u32, i64, Vec<u8>)> = utxos
-            .iter()
-            .map(|u| (u.txid.clone(), u.vout, u.satoshis, script_pubkey.clone()))
-            .collect();
+/// Process a batch FLAC download: run each child download, then zip the results
+async fn process_flac_batch_download(state: Arc<RwLock<AppState>>, job_id: String, network: Network) {
+    use crate::models::job::JobStatus;
+    use tokio::sync::Semaphore;
+    use tokio::task::JoinSet;
+    use tokio::time::{sleep, Duration};
 
-        // Create OP_FALSE OP_IF script for FLAC storage
-        let protocol = b"flacstore";
-        let mime_type = b"audio/flac";
-        
-        let metadata = serde_json::json!({
-            "filename": filename,
-            "size": file_data.len(),
-            "version": "1.0",
-            "chunked": false
-        }).to_string();
+    let children = {
+        let state = state.read().await;
+        state.db.get_child_jobs(&job_id).unwrap_or_default()
+    };
+
+    if children.is_empty() {
+        let state = state.read().await;
+        let _ = state.db.update_job_error(&job_id, ErrorCode::InvalidRequest, MessageKey::BatchEmpty);
+        return;
+    }
 
-        let max_chunk_size = 100 * 1024; // 100KB
-        let data_chunks = BsvService::split_into_chunks(&file_data, max_chunk_size);
+    let total = children.len();
+    let child_ids: Vec<String> = children.iter().map(|c| c.id.clone()).collect();
+    let txids: Vec<String> = children.iter().filter_map(|c| c.manifest_txid.clone()).collect();
+    let slots = Arc::new(Semaphore::new(BATCH_DOWNLOAD_CONCURRENCY));
+    let mut downloads = JoinSet::new();
+    // Each track's bytes as its download returned them; read back from
+    // DOWNLOADS_DIR they could be another track's that was saved under the same name
+    let mut tracks: HashMap<String, Vec<u8>> = HashMap::new();
 
-        let flac_script = BsvService::create_flac_store_script(
-            protocol,
-            mime_type,
-            metadata.as_bytes(),
-            &data_chunks,
-        );
+    // Child tokens hang off the batch token, so cancelling the batch stops every track
+    {
+        let state = state.read().await;
+        let batch_token = state.cancellations.token(&job_id);
+        for child in &children {
+            state.cancellations.register(&child.id, batch_token.as_ref());
+        }
+    }
 
-        let tx_size = 150 + flac_script.len();
-        let fee = {
-            let state = state.read().await;
-            (tx_size as f64 * state.bsv.fee_rate).ceil() as i64
+    for child in children {
+        let state = state.clone();
+        let slots = slots.clone();
+        downloads.spawn(async move {
+            let _permit = slots.acquire_owned().await;
+            let data = if is_job_cancelled(&state, &child.id).await {
+                finish_cancelled(&state, &child.id, MessageKey::DownloadCancelledBeforeStart).await;
+                None
+            } else {
+                process_flac_download(state.clone(), child.id.clone(), child.manifest_txid, network).await
+            };
+            state.read().await.cancellations.remove(&child.id);
+            (child.id, data)
+        });
+    }
+
+    // Refresh the aggregate progress until every child download has finished.
+    // Progress is only written when it changes, so a batch whose children all
+    // hang goes stale and gets picked up by the stall watchdog.
+    let mut last_progress = -1.0;
+    loop {
+        let tasks_done = tokio::select! {
+            result = downloads.join_next() => match result {
+                Some(joined) => {
+                    if let Ok((child_id, Some(data))) = joined {
+                        tracks.insert(child_id, data);
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = sleep(Duration::from_secs(1)) => false,
         };
 
-        let outputs: Vec<(Vec<u8>, i64)> = vec![(flac_script, 1)];
+        let state = state.read().await;
+
+        // The watchdog failed this batch: stop the children and give up
+        let batch_running = state.db.get_job(&job_id).ok().flatten()
+            .map(|j| j.status == JobStatus::Processing)
+            .unwrap_or(false);
+        if !batch_running {
+            downloads.abort_all();
+            for child_id in &child_ids {
+                let _ = state.db.mark_job_stalled(child_id, MessageKey::BatchStopped, chrono::Utc::now());
+            }
+            return;
+        }
+
+        let mut progress_sum = 0.0;
+        let mut done = 0;
+        for child_id in &child_ids {
+            if let Ok(Some(child)) = state.db.get_job(child_id) {
+                match child.status {
+                    JobStatus::Complete | JobStatus::Error | JobStatus::Cancelled => {
+                        progress_sum += 100.0;
+                        done += 1;
+                    }
+                    _ => progress_sum += child.progress,
+                }
+            }
+        }
+        let progress = 90.0 * progress_sum / (total as f64 * 100.0);
+        if progress != last_progress {
+            last_progress = progress;
+            let _ = state.db.update_job_progress(
+                &job_id,
+                progress,
+                MessageKey::DownloadedTracks.with("done", done).with("n", total),
+            );
+        }
+
+        if tasks_done || done == total {
+            break;
+        }
+    }
+    // Every track has its final status; its task only has to hand back the bytes
+    while let Some(joined) = downloads.join_next().await {
+        if let Ok((child_id, Some(data))) = joined {
+            tracks.insert(child_id, data);
+        }
+    }
 
-        if total_input < fee {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(
-                &job_id,
-                &format!("Insufficient funds: {} < {}", total_input, fee),
-            );
-            return;
+    // A child task that panicked never reached a final status
+    {
+        let state = state.read().await;
+        for child_id in &child_ids {
+            if let Ok(Some(child)) = state.db.get_job(child_id) {
+                if child.status == JobStatus::Processing {
+                    let _ = state.db.update_job_error(
+                        child_id,
+                        ErrorCode::InternalError,
+                        MessageKey::DownloadTaskEnded,
+                    );
+                }
+            }
         }
+    }
 
-        let raw_tx = {
+    if is_job_cancelled(&state, &job_id).await {
+        let downloaded = {
             let state = state.read().await;
-            state.bsv.create_transaction(&wif, &utxo_inputs, &outputs)
+            child_ids
+                .iter()
+                .filter(|id| {
+                    state.db.get_job(id).ok().flatten()
+                        .map(|c| c.status == JobStatus::Complete)
+                        .unwrap_or(false)
+                })
+                .count()
         };
+        let message = MessageKey::BatchCancelled.with("done", downloaded).with("n", total);
+        finish_cancelled(&state, &job_id, message).await;
+        return;
+    }
 
-        let raw_tx = match raw_tx {
-            Ok(tx) => tx,
-            Err(e) => {
-                let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Failed to create tx: {}", e));
-                return;
-            }
-        };
+    {
+        let state = state.read().await;
+        let _ = state.db.update_job_progress(&job_id, 95.0, MessageKey::CreatingZip);
+    }
 
-        {
-            let state = state.read().await;
-            let _ = state.db.update_job_progress(&job_id, 60.0, "Broadcasting FLAC transaction...");
-        }
+    let downloads_dir = std::path::Path::new(routes::download::DOWNLOADS_DIR);
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut failed = 0;
 
-        let broadcast_result = if network == "testnet" {
-            broadcast_testnet_tx(&raw_tx).await
-        } else {
+    for child_id in &child_ids {
+        let child = {
             let state = state.read().await;
-            state.bitails.broadcast_transaction(&raw_tx).await
+            state.db.get_job(child_id).ok().flatten()
+        };
+        let filename = match child {
+            Some(c) if c.status == JobStatus::Complete => c.filename,
+            _ => None,
         };
+        let data = tracks.remove(child_id);
 
-        match broadcast_result {
-            Ok(txid) => {
-                let state = state.read().await;
-                let _ = state.db.update_job_complete(&job_id, &txid, None);
-                tracing::info!("FLAC upload complete for job {}: txid={}", job_id, txid);
-            }
-            Err(e) => {
-                let state = state.read().await;
-                let _ = state.db.update_job_error(&job_id, &format!("Broadcast failed: {}", e));
+        match (filename, data) {
+            (Some(mut name), Some(data)) => {
+                // Two tracks can share a filename, keep both in the archive
+                if entries.iter().any(|(n, _)| *n == name) {
+                    name = format!("{}-{}", entries.len() + 1, name);
+                }
+                entries.push((name, data));
             }
+            _ => failed += 1,
         }
     }
-}
 
-/// Process download
-async fn process_download(state: Arc<RwLock<AppState>>, job_id: String, txid: Option<String>) {
-    let txid = match txid {
-        Some(t) => t,
-        None => {
+    if entries.is_empty() {
+        let state = state.read().await;
+        let _ = state.db.update_job_error(&job_id, ErrorCode::NoDataFound, MessageKey::NoTracksDownloaded);
+        return;
+    }
+
+    let archive = match crate::services::archive::create_zip(&entries) {
+        Ok(a) => a,
+        Err(e) => {
             let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, "No TXID provided");
+            let _ = state.db.update_job_error(&job_id, ErrorCode::SaveFailed, MessageKey::ZipFailed.with("error", e.to_string()));
             return;
         }
     };
 
+    let filename = format!("batch-{}.zip", job_id);
+    if let Err(e) = std::fs::write(downloads_dir.join(&filename), &archive) {
+        let state = state.read().await;
+        let _ = state.db.update_job_error(&job_id, ErrorCode::SaveFailed, MessageKey::ZipSaveFailed.with("error", e.to_string()));
+        return;
+    }
+
+    let download_link = routes::download::download_link(&filename);
     {
         let state = state.read().await;
-        let _ = state.db.update_job_progress(&job_id, 10.0, "Fetching transaction...");
+        let _ = state.db.update_job_complete_with_filename(
+            &job_id,
+            &txids.join(","),
+            Some(&download_link),
+            &filename,
+            "application/zip",
+        );
+        if failed > 0 {
+            let _ = state.db.update_job_status(
+                &job_id,
+                JobStatus::Complete,
+                MessageKey::CompleteWithFailures.with("failed", failed).with("n", total),
+            );
+        }
     }
+    tracing::info!(
+        "FLAC batch download complete for job {}: {} tracks, {} failed",
+        job_id,
+        entries.len(),
+        failed
+    );
+}
+
+/// Create a completed job record for an upload that is already on-chain.
+/// FLAC manifests, single-transaction FLAC uploads and plain uploads are recognised.
+/// Returns the job id and whether the txid had already been imported.
+async fn import_upload(state: &Arc<RwLock<AppState>>, txid: &str, network: Network) -> Result<(String, bool), (ErrorCode, String)> {
+    use crate::models::Job;
 
-    let tx_data = {
+    {
         let state = state.read().await;
-        state.bitails.download_tx_raw(&txid).await
+        match state.db.find_upload_by_txid(txid) {
+            Ok(Some(job)) => return Ok((job.id, true)),
+            Ok(None) => {}
+            Err(e) => return Err((ErrorCode::DatabaseError, format!("Database error: {}", e))),
+        }
+    }
+
+    let tx_hex = fetch_tx_raw(state, txid, network)
+        .await
+        .map_err(|e| (ErrorCode::TxFetchFailed, format!("Failed to fetch tx: {}", e)))?;
+
+    let job_id = uuid::Uuid::new_v4().to_string().replace("-", "");
+    let job = if let Some(manifest) = extract_flac_manifest_from_tx(&tx_hex) {
+        let lyrics = resolve_lyrics(state, manifest.lyrics, manifest.lyrics_txid.as_deref(), network).await;
+        store_cover_thumb(
+            state,
+            manifest.cover_txid.as_deref(),
+            manifest.thumb_inline.as_deref(),
+            manifest.thumb_txid.as_deref(),
+            network,
+        )
+        .await;
+        let mut job = Job::new_import(
+            job_id,
+            JobType::FlacUpload,
+            txid.to_string(),
+            Some(manifest.filename),
+            manifest.size.map(|s| s as i64),
+            network,
+        )
+        .with_track_metadata(manifest.title, manifest.artist, lyrics);
+        job.cover_txid = manifest.cover_txid;
+        job.chunk_txids = Some(manifest.chunk_txids.join(","));
+        job
+    } else if let Some(file) = extract_flac_from_tx(&tx_hex) {
+        let lyrics = resolve_lyrics(state, file.lyrics, file.lyrics_txid.as_deref(), network).await;
+        store_cover_thumb(state, file.cover_txid.as_deref(), file.thumb_inline.as_deref(), file.thumb_txid.as_deref(), network).await;
+        let mut job = Job::new_import(
+            job_id,
+            JobType::FlacUpload,
+            txid.to_string(),
+            Some(file.filename),
+            Some(file.data.len() as i64),
+            network,
+        )
+        .with_track_metadata(file.title, file.artist, lyrics);
+        job.cover_txid = file.cover_txid;
+        job
+    } else if let Some(file) = extract_op_return_from_tx(&tx_hex) {
+        Job::new_import(job_id, JobType::Upload, txid.to_string(), Some(file.filename), Some(file.data.len() as i64), network)
+    } else {
+        return Err((ErrorCode::NoDataFound, "No supported upload found in transaction".to_string()));
     };
 
-    let tx_data = match tx_data {
-        Ok(data) => data,
-        Err(e) => {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Failed to fetch tx: {}", e));
+    let state = state.read().await;
+    state
+        .db
+        .insert_job(&job)
+        .map_err(|e| (ErrorCode::DatabaseError, format!("Failed to create job: {}", e)))?;
+    tracing::info!("Imported {} as job {}", txid, job.id);
+    Ok((job.id, false))
+}
+
+/// Import a list of on-chain uploads, reporting progress as it goes
+async fn process_import(state: Arc<RwLock<AppState>>, job_id: String, txids: Option<String>, network: Network) {
+    use crate::models::job::JobStatus;
+
+    let txids: Vec<String> = txids
+        .unwrap_or_default()
+        .split(',')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect();
+    let total = txids.len();
+    let mut imported = 0;
+    let mut skipped = 0;
+    let mut failed = 0;
+
+    for (i, txid) in txids.iter().enumerate() {
+        if is_job_cancelled(&state, &job_id).await {
+            let message = MessageKey::ImportCancelled.with("i", i).with("n", total);
+            finish_cancelled(&state, &job_id, message).await;
             return;
         }
+
+        {
+            let state = state.read().await;
+            let progress = 100.0 * i as f64 / total as f64;
+            let _ = state.db.update_job_progress(
+                &job_id,
+                progress,
+                MessageKey::Importing.with("i", i + 1).with("n", total).with("txid", txid.as_str()),
+            );
+        }
+
+        match import_upload(&state, txid, network).await {
+            Ok((_, true)) => skipped += 1,
+            Ok((_, false)) => imported += 1,
+            Err((_, e)) => {
+                tracing::warn!("Import of {} failed: {}", txid, e);
+                failed += 1;
+            }
+        }
+    }
+
+    let state = state.read().await;
+    let _ = state.db.update_job_complete(&job_id, &txids.join(","), None);
+    let _ = state.db.update_job_status(
+        &job_id,
+        JobStatus::Complete,
+        MessageKey::ImportComplete
+            .with("imported", imported)
+            .with("skipped", skipped)
+            .with("failed", failed),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::job::JobStatus;
+    use crate::models::Job;
+    use crate::test_support::{
+        accept, bitails, chain_bitails, chain_bitails_utxos, multipart_body, reject, run_job, serve, test_config, test_state,
+        test_state_with, whatsonchain_chain, whatsonchain_fetches, whatsonchain_fund, BroadcastReply, MockChain,
     };
 
-    {
+    fn upload_job(id: &str) -> Job {
+        Job::new_upload(
+            id.to_string(),
+            "file.txt".to_string(),
+            4,
+            b"data".to_vec(),
+            "1BoatSLRHtKNngkdXEeobR76b53LETtpyT".to_string(),
+            "wif".to_string(),
+            1000,
+        )
+    }
+
+    #[tokio::test]
+    async fn paid_job_of_revoked_key_is_refused() {
+        let state = test_state();
         let state = state.read().await;
-        let _ = state.db.update_job_progress(&job_id, 50.0, "Extracting data...");
+        state.db.insert_api_key("key-1", "hash", "ci", None).unwrap();
+        for id in ["revoked", "anonymous"] {
+            state.db.insert_job(&upload_job(id)).unwrap();
+        }
+        state.db.set_job_api_key("revoked", "key-1").unwrap();
+        state.db.set_api_key_enabled("key-1", false).unwrap();
+
+        assert!(refuse_revoked_api_key(&state, "revoked", "addr"));
+        assert!(!refuse_revoked_api_key(&state, "anonymous", "addr"));
+
+        let job = state.db.get_job("revoked").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Error);
+        assert_eq!(job.error_code, Some(ErrorCode::InvalidApiKey));
+        // Its payment is left for the abandoned-payment sweep
+        let abandoned = state.db.get_abandoned_funded_jobs(chrono::Utc::now()).unwrap();
+        assert!(abandoned.iter().any(|j| j.id == "revoked"));
     }
 
-    let (file_data, filename) = match extract_op_return_from_tx(&tx_data) {
-        Some(data) => data,
-        None => {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, "No OP_RETURN data found in transaction");
-            return;
+    #[tokio::test]
+    async fn payment_is_recorded_before_the_job_runs() {
+        let mut config = test_config();
+        config.max_concurrent_jobs = 1;
+        let shared = test_state_with(config);
+        let state = shared.read().await;
+        let queued = |id: &str, paid_satoshis| QueuedJob {
+            job_id: id.to_string(),
+            job_type: JobType::Upload,
+            address: String::new(),
+            network: Network::Mainnet,
+            admin_pay: false,
+            file_size: 4,
+            paid_satoshis,
+        };
+        state.db.insert_job(&upload_job("ahead")).unwrap();
+        state.db.insert_job(&upload_job("paid")).unwrap();
+        enqueue_job(&state, queued("ahead", None));
+
+        accept_payment(&state, queued("paid", Some(1500)));
+
+        // Still queued with no chunk work done, yet the payment is already on the job
+        assert!(state.scheduler.is_queued("paid"));
+        let job = state.db.get_job("paid").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Processing);
+        assert!(job.payment_received_at.is_some());
+        assert_eq!(job.payment_received_satoshis, Some(1500));
+        assert_eq!(job.bytes_done, None);
+        assert_eq!(job.chunk_txids, None);
+        assert_eq!(job.message, MessageKey::PaymentQueuedBehind.with("amount", 1500).with("ahead", 1).english());
+
+        // A later sighting of the same payment keeps the first one
+        state.db.update_job_payment_received("paid", 9999).unwrap();
+        let again = state.db.get_job("paid").unwrap().unwrap();
+        assert_eq!(again.payment_received_at, job.payment_received_at);
+        assert_eq!(again.payment_received_satoshis, Some(1500));
+        drop(state);
+
+        let status = routes::status::status_update(axum::extract::State(shared), axum::extract::Path("paid".to_string())).await.unwrap();
+        let status = serde_json::to_value(status.0).unwrap();
+        assert_eq!(status["payment_received_satoshis"], 1500);
+        assert!(status["payment_received_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn interrupted_jobs_rerun_one_at_a_time_in_order() {
+        let mut config = test_config();
+        config.max_concurrent_jobs = 1;
+        let state = test_state_with(config);
+        let state = state.read().await;
+
+        // Created newest first, so only the requeue's sort can restore the order
+        let ids: Vec<String> = (0..5).map(|i| format!("job-{}", i)).collect();
+        let start = chrono::Utc::now() - chrono::Duration::minutes(10);
+        for (i, id) in ids.iter().enumerate().rev() {
+            let mut job = upload_job(id).with_status(JobStatus::Processing, MessageKey::Starting);
+            job.created_at = start + chrono::Duration::seconds(i as i64);
+            state.db.insert_job(&job).unwrap();
         }
-    };
+        state.db.update_job_payment_received("job-2", 1000).unwrap();
+        // Neither of these is queued on its own
+        let mut child = upload_job("child").with_status(JobStatus::Processing, MessageKey::Starting);
+        child.parent_id = Some("batch".to_string());
+        state.db.insert_job(&child).unwrap();
+        state.db.insert_job(&upload_job("unpaid")).unwrap();
+
+        requeue_processing_jobs(&state);
+
+        let message = |id: &str| state.db.get_job(id).unwrap().unwrap().message;
+        assert_eq!(message("job-0"), StatusMessage::from(MessageKey::QueuedNext).english());
+        assert_eq!(message("job-1"), MessageKey::QueuedBehind.with("ahead", 1).english());
+        assert_eq!(message("job-2"), MessageKey::PaymentQueuedBehind.with("amount", 1000).with("ahead", 2).english());
+        assert_eq!(message("job-4"), MessageKey::QueuedBehind.with("ahead", 4).english());
+        assert!(!state.scheduler.is_queued("child"));
+        assert!(!state.scheduler.is_queued("unpaid"));
+
+        let timeout = std::time::Duration::from_millis(50);
+        for (i, id) in ids.iter().enumerate() {
+            let (job, permit) = tokio::time::timeout(timeout, state.scheduler.next()).await.unwrap();
+            assert_eq!(&job.job_id, id);
+            refresh_queue_messages(&state);
+            if let Some(next) = ids.get(i + 1) {
+                let expected = match next.as_str() {
+                    "job-2" => MessageKey::PaymentQueuedNext.with("amount", 1000),
+                    _ => MessageKey::QueuedNext.into(),
+                };
+                assert_eq!(message(next), expected.english());
+            }
+            // With one slot the next job waits until this one finishes
+            assert!(tokio::time::timeout(timeout, state.scheduler.next()).await.is_err());
+            drop(permit);
+        }
+    }
 
-    let downloads_dir = std::path::Path::new("./data/downloads");
-    std::fs::create_dir_all(downloads_dir).ok();
+    /// A paid upload of `data` run against a Bitails stand-in holding `satoshis`
+    /// for it; returns the state and the job's id
+    async fn run_upload(satoshis: i64, reply: BroadcastReply, data: &[u8]) -> (Arc<RwLock<AppState>>, String) {
+        let mut config = test_config();
+        config.bitails_api_url = bitails(satoshis, reply).await;
+        let state = test_state_with(config);
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let job = Job::new_upload("upload".to_string(), "a.txt".to_string(), data.len() as i64, data.to_vec(), address, wif, 1000);
+        run_job(&state, &job).await;
+        (state, job.id)
+    }
+
+    async fn job_status(state: &Arc<RwLock<AppState>>, job_id: &str) -> serde_json::Value {
+        let status = routes::status::status_update(axum::extract::State(state.clone()), axum::extract::Path(job_id.to_string()))
+            .await
+            .unwrap();
+        serde_json::to_value(status.0).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn job_that_never_finishes_fails_at_the_time_limit() {
+        // A chain API that accepts requests and never answers them
+        let hung = serve(axum::Router::new().fallback(std::future::pending::<()>)).await;
+        let mut config = test_config();
+        config.bitails_api_url = hung;
+        config.max_job_duration_minutes = 1;
+        let state = test_state_with(config);
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let job = Job::new_upload("hung".to_string(), "a.txt".to_string(), 4, b"data".to_vec(), address, wif, 1000)
+            .with_status(JobStatus::Processing, MessageKey::Starting);
+
+        let started = tokio::time::Instant::now();
+        run_job(&state, &job).await;
+        assert!(started.elapsed() >= std::time::Duration::from_secs(60));
 
-    let file_path = downloads_dir.join(&filename);
-    if let Err(e) = std::fs::write(&file_path, &file_data) {
         let state = state.read().await;
-        let _ = state.db.update_job_error(&job_id, &format!("Failed to save file: {}", e));
-        return;
+        let job = state.db.get_job("hung").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Error);
+        assert_eq!(job.error_code, Some(ErrorCode::TimedOut));
+        assert_eq!(job.message, MessageKey::TimeLimitExceeded.with("minutes", 1).english());
+        // Its slot and token are released
+        assert!(state.cancellations.token("hung").is_none());
     }
 
-    {
+    #[tokio::test]
+    async fn failed_jobs_report_their_error_code() {
+        let (state, job_id) = run_upload(100, accept, b"hello").await;
+        let status = job_status(&state, &job_id).await;
+        assert_eq!(status["status"], "error");
+        assert_eq!(status["error_code"], "INSUFFICIENT_FUNDS");
+
+        let (state, job_id) = run_upload(10_000_000, reject, b"hello").await;
+        let status = job_status(&state, &job_id).await;
+        assert_eq!(status["status"], "error");
+        assert_eq!(status["error_code"], "BROADCAST_FAILED");
+    }
+
+    #[tokio::test]
+    async fn bitails_rejection_recovered_by_whatsonchain_records_both_attempts() {
+        let mut config = test_config();
+        config.bitails_api_url = bitails(10_000_000, reject).await;
+        let state = test_state_with(config);
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        whatsonchain_fund(&address, 10_000_000);
+        let job = Job::new_upload("upload".to_string(), "a.txt".to_string(), 5, b"hello".to_vec(), address, wif, 0);
+        run_job(&state, &job).await;
+
         let state = state.read().await;
-        let _ = state.db.update_job_complete(
-            &job_id,
-            &txid,
-            Some(&file_path.to_string_lossy()),
-        );
+        let job = state.db.get_job("upload").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let txid = job.manifest_txid.unwrap();
+        assert!(whatsonchain_chain().tx(&txid).is_some());
+
+        let attempts = state.db.get_broadcast_attempts("upload").unwrap();
+        let attempts: Vec<&BroadcastAttempt> = attempts.iter().map(|record| &record.attempt).collect();
+        assert_eq!(attempts.len(), 2);
+        assert_eq!((attempts[0].provider.as_str(), attempts[0].http_status), ("bitails", Some(400)));
+        assert!(attempts[0].error.as_ref().unwrap().contains("bad-txns-inputs-missingorspent"));
+        assert_eq!(attempts[0].accepted_txid, None);
+        assert_eq!((attempts[1].provider.as_str(), attempts[1].http_status), ("whatsonchain", Some(200)));
+        assert_eq!(attempts[1].accepted_txid.as_deref(), Some(txid.as_str()));
     }
 
-    tracing::info!("Download complete for job {}: {}", job_id, filename);
-}
+    #[tokio::test]
+    async fn large_uploads_span_several_pushes_and_read_back_whole() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        state.write().await.bsv.max_push_size = 1000;
+        let data: Vec<u8> = (0..2517u32).map(|i| (i % 251) as u8).collect();
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let job = Job::new_upload("upload".to_string(), "big.bin".to_string(), data.len() as i64, data.clone(), address, wif, 0);
+        run_job(&state, &job).await;
+
+        let job = state.read().await.db.get_job("upload").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let tx_hex = chain.tx(job.manifest_txid.as_ref().unwrap()).unwrap();
+        let script = &parse_transaction(&tx_hex).unwrap().outputs[0].script;
+        // Protocol, MIME type and filename, then the data in three pushes
+        let pushes: Vec<&[u8]> = services::tx_parse::PushDataIter::new(&script[2..]).map(Option::unwrap).collect();
+        assert_eq!(pushes.len(), 6);
+        assert_eq!(pushes[3..].iter().map(|push| push.len()).collect::<Vec<_>>(), [1000, 1000, 517]);
+
+        let file = extract_op_return_from_tx(&tx_hex).unwrap();
+        assert_eq!(file.filename, "big.bin");
+        assert_eq!(file.data, data);
+    }
 
-/// Fetch transaction data from appropriate API based on network
-async fn fetch_tx_raw(state: &Arc<RwLock<AppState>>, txid: &str, network: &str) -> Result<String, String> {
-    if network == "testnet" {
-        // Use WhatsOnChain Testnet API
-        let url = format!("https://api.whatsonchain.com/v1/bsv/test/tx/{}/hex", txid);
+    #[tokio::test]
+    async fn uploaded_file_downloads_with_its_name_and_data() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let filename = format!("{}.txt", uuid::Uuid::new_v4().simple());
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let upload = Job::new_upload("upload".to_string(), filename.clone(), 11, b"hello world".to_vec(), address, wif, 0);
+        run_job(&state, &upload).await;
+        let upload = state.read().await.db.get_job("upload").unwrap().unwrap();
+        assert_eq!(upload.status, JobStatus::Complete, "{}", upload.message);
+        let txid = upload.manifest_txid.unwrap();
+
+        // Protocol, MIME type, filename, data: the order downloads read
+        let script = &parse_transaction(&chain.tx(&txid).unwrap()).unwrap().outputs[0].script;
+        let pushes: Vec<&[u8]> = services::tx_parse::PushDataIter::new(&script[2..]).map(Option::unwrap).collect();
+        assert_eq!(pushes, [b"upfile".as_slice(), b"text/plain", filename.as_bytes(), b"hello world"]);
+
+        run_job(&state, &Job::new_download("download".to_string(), txid)).await;
+        let download = state.read().await.db.get_job("download").unwrap().unwrap();
+        assert_eq!(download.status, JobStatus::Complete, "{}", download.message);
+        assert_eq!(download.filename.as_deref(), Some(filename.as_str()));
+        assert_eq!(download.mime_type.as_deref(), Some("text/plain"));
+        let saved = std::path::Path::new(routes::download::DOWNLOADS_DIR).join(&filename);
+        let data = std::fs::read(&saved);
+        let _ = std::fs::remove_file(&saved);
+        assert_eq!(data.unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn mislabeled_upload_is_stored_with_its_sniffed_type() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let png = [&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A][..], b"image data"].concat();
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let job = Job::new_upload("upload".to_string(), "photo.txt".to_string(), png.len() as i64, png, address, wif, 0);
+        run_job(&state, &job).await;
+
+        let job = state.read().await.db.get_job("upload").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let file = extract_op_return_from_tx(&chain.tx(job.manifest_txid.as_ref().unwrap()).unwrap()).unwrap();
+        assert_eq!((file.filename.as_str(), file.mime_type.as_str()), ("photo.txt", "image/png"));
+    }
+
+    #[tokio::test]
+    async fn named_upload_downloads_under_the_same_name_over_http() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let app = serve(
+            axum::Router::new()
+                .route("/prepare_upload", post(routes::upload::prepare_upload))
+                .route("/start_download", post(routes::download::start_download))
+                .route("/downloads/:filename", get(routes::download::serve_download))
+                .with_state(state.clone()),
+        )
+        .await;
+        let client = reqwest::Client::new();
+        let filename = format!("My Song (final) {}.txt", uuid::Uuid::new_v4().simple());
+        let (wif, _) = BsvService::generate_keypair(Network::Mainnet);
+
+        let (content_type, body) =
+            multipart_body(&[("file", Some(filename.as_str()), b"named upload"), ("funding_wif", None, wif.as_bytes())]);
+        let response = client.post(format!("{}/prepare_upload", app)).header("content-type", content_type).body(body);
+        let prepared: serde_json::Value = response.send().await.unwrap().json().await.unwrap();
+        run_next_job(&state).await;
+        let upload = state.read().await.db.get_job(prepared["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(upload.status, JobStatus::Complete, "{}", upload.message);
+
+        let response = client.post(format!("{}/start_download", app)).json(&serde_json::json!({ "txid": upload.manifest_txid }));
+        let started: serde_json::Value = response.send().await.unwrap().json().await.unwrap();
+        run_next_job(&state).await;
+        let download = state.read().await.db.get_job(started["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(download.status, JobStatus::Complete, "{}", download.message);
+        assert_eq!(download.filename.as_deref(), Some(filename.as_str()));
+
+        let response = client.get(format!("{}{}", app, download.download_link.unwrap())).send().await.unwrap();
+        let _ = std::fs::remove_file(std::path::Path::new(routes::download::DOWNLOADS_DIR).join(&filename));
+        let disposition = response.headers()["content-disposition"].to_str().unwrap().to_string();
+        assert!(disposition.starts_with(&format!("attachment; filename=\"{}\";", filename)), "{}", disposition);
+        assert_eq!(response.bytes().await.unwrap().as_ref(), b"named upload");
+    }
+
+    #[tokio::test]
+    async fn waiting_download_returns_the_link_for_single_transaction_files() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let app = serve(
+            axum::Router::new()
+                .route("/start_download", post(routes::download::start_download))
+                .route("/api/flac/download", post(routes::flac::start_flac_download))
+                .with_state(state.clone()),
+        )
+        .await;
         let client = reqwest::Client::new();
-        let response = client.get(&url).send().await.map_err(|e| format!("Request failed: {}", e))?;
-        if !response.status().is_success() {
-            return Err(format!("API error: {}", response.status()));
+        let start = |path: &str, txid: &str| {
+            let request = client.post(format!("{}{}", app, path)).json(&serde_json::json!({ "txid": txid, "wait": true }));
+            async move { request.send().await.unwrap().json::<serde_json::Value>().await.unwrap() }
+        };
+        let saved = |filename: &str| {
+            let path = std::path::Path::new(routes::download::DOWNLOADS_DIR).join(filename);
+            let data = std::fs::read(&path).unwrap();
+            let _ = std::fs::remove_file(path);
+            data
+        };
+
+        let bsv = BsvService::for_tests();
+        let filename = format!("note {}.txt", uuid::Uuid::new_v4().simple());
+        let script = bsv.create_upfile_script("text/plain", &filename, b"generic upload");
+        let txid = chain.add(&bsv.test_transaction(&[(script, Amount::from_sat_const(1))]));
+        let started = start("/start_download", &txid).await;
+        assert_eq!(started["filename"], filename.as_str(), "{}", started);
+        assert_eq!(started["download_link"], routes::download::download_link(&filename));
+        assert_eq!(saved(&filename), b"generic upload");
+
+        let filename = format!("track {}.flac", uuid::Uuid::new_v4().simple());
+        let txid = chain.add(&flac_store_tx(&filename, b"fLaC single transaction"));
+        let started = start("/api/flac/download", &txid).await;
+        assert_eq!(started["filename"], filename.as_str(), "{}", started);
+        assert_eq!(started["download_link"], routes::download::download_link(&filename));
+        assert_eq!(saved(&filename), b"fLaC single transaction");
+        let job = state.read().await.db.get_job(started["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+
+        // A chunked upload is still left to the queue
+        let chunk_txids = add_chunks(&chain, &[b"fLaC first", b" second"]);
+        let manifest_txid = add_manifest(&chain, 17, &chunk_txids, &[]);
+        let started = start("/api/flac/download", &manifest_txid).await;
+        assert!(started.get("download_link").is_none(), "{}", started);
+        assert!(state.read().await.scheduler.is_queued(started["job_id"].as_str().unwrap()));
+    }
+
+    /// State whose FLAC uploads go out in 1024-byte chunks through a Bitails
+    /// stand-in holding 0.1 BSV for every address
+    async fn chunked_flac_state(reply: BroadcastReply) -> Arc<RwLock<AppState>> {
+        let mut config = test_config();
+        config.bitails_api_url = bitails(10_000_000, reply).await;
+        let state = test_state_with(config);
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+        state
+    }
+
+    /// A paid FLAC upload of `data`, as prepare_flac_upload creates it
+    fn flac_job(id: &str, data: &[u8]) -> Job {
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        Job::new_flac_upload(id.to_string(), "song.flac".to_string(), data.len() as i64, data.to_vec(), address, wif, 0)
+    }
+
+    #[tokio::test]
+    async fn corrupted_payment_key_fails_before_anything_is_spent() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let (other_wif, other_address) = BsvService::generate_keypair(Network::Mainnet);
+        let mut garbled = flac_job("garbled", b"fLaC track");
+        garbled.payment_wif = Some("not a wif".to_string());
+        let mut swapped = flac_job("swapped", b"fLaC track");
+        swapped.payment_wif = Some(other_wif.clone());
+        let address = swapped.payment_address.clone().unwrap();
+
+        for job in [&garbled, &swapped] {
+            let started = std::time::Instant::now();
+            run_job(&state, job).await;
+            assert!(started.elapsed() < std::time::Duration::from_secs(1), "{} was retried", job.id);
         }
-        response.text().await.map_err(|e| format!("Parse error: {}", e))
-    } else {
-        // Use Bitails Mainnet API
+        assert_eq!(chain.count(), 0);
+
         let state = state.read().await;
-        state.bitails.download_tx_raw(txid).await
+        let job = |id: &str| state.db.get_job(id).unwrap().unwrap();
+        for id in ["garbled", "swapped"] {
+            assert_eq!(job(id).status, JobStatus::Error);
+            assert_eq!(job(id).error_code, Some(ErrorCode::PaymentKeyMismatch));
+        }
+        let expected = MessageKey::PaymentKeyMismatch.with("address", address.as_str()).with("key_address", other_address.as_str());
+        assert_eq!(job("swapped").message, expected.english());
+        assert!(check_payment_key(Some(&other_wif), &address, Network::Mainnet).is_err());
+        assert!(check_payment_key(swapped.payment_wif.as_deref(), &other_address, Network::Mainnet).is_ok());
     }
-}
 
-/// Process FLAC download
-async fn process_flac_download(state: Arc<RwLock<AppState>>, job_id: String, txid: Option<String>, network: String) {
-    use tokio::time::{sleep, Duration};
+    #[tokio::test]
+    async fn flac_paid_at_its_quote_uploads_either_side_of_the_threshold() {
+        const THRESHOLD: usize = 3000;
+        for (file_size, chunked) in [(THRESHOLD, false), (THRESHOLD + 1, true)] {
+            let mut bsv = BsvService::for_tests();
+            bsv.flac_single_tx_max_bytes = THRESHOLD;
+            let plan = bsv.plan_flac_upload(file_size, Network::Mainnet);
+            assert_eq!(plan.chunked, chunked, "{} bytes", file_size);
+
+            let chain = MockChain::default();
+            let mut config = test_config();
+            config.bitails_api_url = chain_bitails(&chain, plan.cost.to_sat_i64(), accept).await;
+            let state = test_state_with(config);
+            state.write().await.bsv.flac_single_tx_max_bytes = THRESHOLD;
+            let data: Vec<u8> = (0..file_size).map(|i| i as u8).collect();
+            run_job(&state, &flac_job("flac", &data)).await;
+
+            let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+            assert_eq!(job.status, JobStatus::Complete, "{} bytes: {}", file_size, job.message);
+            assert_eq!(job.chunk_txid_list().is_some(), chunked, "{} bytes", file_size);
+        }
+    }
 
-    let txid = match txid {
-        Some(t) => t,
-        None => {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, "No TXID provided");
-            return;
+    #[tokio::test]
+    async fn small_flac_keeps_its_cover_and_metadata_through_download() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let cover = [&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A][..], b"small cover"].concat();
+        let upload = flac_job("upload", b"fLaC a short track")
+            .with_track_metadata(Some("Title".to_string()), Some("Artist".to_string()), Some("la la la".to_string()))
+            .with_cover_data(Some(cover.clone()));
+        run_job(&state, &upload).await;
+        let upload = state.read().await.db.get_job("upload").unwrap().unwrap();
+        assert_eq!(upload.status, JobStatus::Complete, "{}", upload.message);
+        assert_eq!(upload.chunk_txid_list(), None);
+        let cover_txid = upload.cover_txid.unwrap();
+        let manifest_txid = upload.manifest_txid.unwrap();
+        let inscribed = find_in_outputs(&chain.tx(&cover_txid).unwrap(), parse_image_output);
+        assert_eq!(inscribed, Some(cover));
+
+        run_job(&state, &Job::new_flac_download("download".to_string(), manifest_txid)).await;
+        let download = state.read().await.db.get_job("download").unwrap().unwrap();
+        assert_eq!(download.status, JobStatus::Complete, "{}", download.message);
+        assert_eq!(download.track_title.as_deref(), Some("Title"));
+        assert_eq!(download.artist_name.as_deref(), Some("Artist"));
+        assert_eq!(download.lyrics.as_deref(), Some("la la la"));
+        assert_eq!(download.cover_txid, Some(cover_txid));
+    }
+
+    #[tokio::test]
+    async fn cover_and_thumbnail_round_trip_through_download() {
+        let png = |size: u32, pixel: fn(u32, u32) -> [u8; 3]| {
+            let image = image::RgbImage::from_fn(size, size, |x, y| image::Rgb(pixel(x, y)));
+            let mut png = std::io::Cursor::new(Vec::new());
+            image.write_to(&mut png, image::ImageFormat::Png).unwrap();
+            png.into_inner()
+        };
+        // A smooth cover's thumbnail goes inline; a noisy one's needs a transaction of its own
+        let smooth = png(800, |x, y| [(x / 4) as u8, (y / 4) as u8, (x * y / 97) as u8]);
+        let noisy = png(400, |x, y| {
+            let hash = (x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663)).wrapping_mul(0x9E37_79B1);
+            hash.to_le_bytes()[1..].try_into().unwrap()
+        });
+        for (cover, inline) in [(smooth, true), (noisy, false)] {
+            let chain = MockChain::default();
+            let filename = format!("{}.flac", uuid::Uuid::new_v4().simple());
+            let mut upload = flac_job("upload", b"fLaC a short track").with_cover_data(Some(cover.clone()));
+            upload.filename = Some(filename.clone());
+            let state = chain_state(&chain).await;
+            run_job(&state, &upload).await;
+            let upload = state.read().await.db.get_job("upload").unwrap().unwrap();
+            assert_eq!(upload.status, JobStatus::Complete, "{}", upload.message);
+            let cover_txid = upload.cover_txid.unwrap();
+            // The cover, the file and, for the noisy cover, its thumbnail
+            assert_eq!(chain.count(), if inline { 2 } else { 3 });
+
+            // A fresh server learns the thumbnail from the track's metadata
+            let state = chain_state(&chain).await;
+            run_job(&state, &Job::new_flac_download("download".to_string(), upload.manifest_txid.unwrap())).await;
+            let saved = std::path::Path::new(routes::download::DOWNLOADS_DIR).join(&filename);
+            let _ = std::fs::remove_file(&saved);
+            let download = state.read().await.db.get_job("download").unwrap().unwrap();
+            assert_eq!(download.status, JobStatus::Complete, "{}", download.message);
+
+            let fetch = |size| {
+                let query = routes::flac::CoverImageQuery { network: None, manifest_txid: None, size };
+                let state = state.clone();
+                let cover_txid = cover_txid.clone();
+                async move {
+                    let response = routes::flac::get_cover_image_file(
+                        axum::extract::State(state),
+                        axum::extract::Path(cover_txid),
+                        axum::extract::Query(query),
+                    )
+                    .await
+                    .unwrap();
+                    axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+                }
+            };
+            let thumb = fetch(routes::flac::CoverSize::Thumb).await;
+            assert!(chain.downloads(&cover_txid).is_empty(), "the thumbnail was made from the full cover");
+            assert!(thumb.len() * 4 < cover.len(), "{} byte thumbnail of a {} byte cover", thumb.len(), cover.len());
+            assert_eq!(crate::services::thumbnail::fits_inline(thumb.len()), inline);
+            let decoded = image::load_from_memory_with_format(&thumb, image::ImageFormat::Jpeg).unwrap();
+            assert_eq!(decoded.width().max(decoded.height()), crate::services::thumbnail::MAX_DIMENSION);
+            assert_eq!(fetch(routes::flac::CoverSize::Full).await, cover);
         }
-    };
+    }
 
-    {
-        let state = state.read().await;
-        let _ = state.db.update_job_progress(&job_id, 5.0, "Fetching manifest transaction...");
+    #[tokio::test]
+    async fn long_lyrics_are_inscribed_apart_and_resolved_on_download() {
+        let lrc = "[00:01.00]first line\n[00:02.00]second line\n[00:03.00]third line";
+        let lyrics = |state: &Arc<RwLock<AppState>>, txid: &str| {
+            routes::flac::get_lyrics(
+                axum::extract::State(state.clone()),
+                axum::extract::Path(txid.to_string()),
+                axum::extract::Query(routes::flac::LyricsQuery { network: None }),
+            )
+        };
+        // One transaction for the small track, chunks and a manifest for the large one
+        for size in [100u32, 1500] {
+            let chain = MockChain::default();
+            let state = chain_state(&chain).await;
+            {
+                let mut state = state.write().await;
+                if size > 1024 {
+                    state.bsv.provider_tx_limits.bitails = Some(1);
+                }
+                state.bsv.lyrics_inline_max_bytes = 32;
+            }
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            run_job(&state, &flac_job("flac", &data).with_track_metadata(None, None, Some(lrc.to_string()))).await;
+            let upload = state.read().await.db.get_job("flac").unwrap().unwrap();
+            assert_eq!(upload.status, JobStatus::Complete, "{}", upload.message);
+            let manifest_txid = upload.manifest_txid.unwrap();
+
+            // The upload only references the lyrics transaction
+            let tx_hex = chain.tx(&manifest_txid).unwrap();
+            let lyrics_txid = match find_in_outputs(&tx_hex, parse_flac_output).unwrap() {
+                FlacData::Manifest(manifest) => {
+                    assert_eq!(manifest.lyrics, None);
+                    manifest.lyrics_txid
+                }
+                FlacData::File(file) => {
+                    assert_eq!(file.lyrics, None);
+                    file.lyrics_txid
+                }
+            };
+            let inscribed = find_in_outputs(&chain.tx(&lyrics_txid.unwrap()).unwrap(), parse_lyrics_output);
+            assert_eq!(inscribed, Some((lrc.to_string(), services::lyrics::LyricsFormat::Lrc)));
+
+            let served = lyrics(&state, &manifest_txid).await.unwrap().0;
+            assert_eq!((served.lyrics.as_str(), served.format), (lrc, services::lyrics::LyricsFormat::Lrc));
+            assert_eq!(served.lines.len(), 3);
+            run_job(&state, &Job::new_flac_download("download".to_string(), manifest_txid)).await;
+            let download = state.read().await.db.get_job("download").unwrap().unwrap();
+            assert_eq!(download.status, JobStatus::Complete, "{}", download.message);
+            assert_eq!(download.lyrics.as_deref(), Some(lrc), "{} bytes", size);
+        }
     }
 
-    let tx_data = fetch_tx_raw(&state, &txid, &network).await;
+    #[tokio::test]
+    async fn missing_lyrics_transaction_leaves_the_track_without_lyrics() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let bsv = BsvService::for_tests();
+        let not_lyrics = chain.add(&flac_store_tx("other.flac", b"fLaC other"));
+        let store_tx = |lyrics_txid: &str| {
+            let metadata = serde_json::json!({ "filename": "song.flac", "lyrics_txid": lyrics_txid }).to_string();
+            let script = bsv.create_flac_store_script(b"flacstore", b"audio/flac", metadata.as_bytes(), &[b"fLaC".to_vec()]);
+            chain.add(&bsv.test_transaction(&[(script, Amount::from_sat_const(1))]))
+        };
 
-    let tx_data = match tx_data {
-        Ok(data) => data,
-        Err(e) => {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Failed to fetch tx: {}", e));
-            return;
+        for (lyrics_txid, code) in [("ab".repeat(32), ErrorCode::TxFetchFailed), (not_lyrics, ErrorCode::NoDataFound)] {
+            let txid = store_tx(&lyrics_txid);
+            let error = routes::flac::get_lyrics(
+                axum::extract::State(state.clone()),
+                axum::extract::Path(txid.clone()),
+                axum::extract::Query(routes::flac::LyricsQuery { network: None }),
+            )
+            .await
+            .err()
+            .unwrap();
+            assert_eq!(error.code, code);
+
+            // The download itself still succeeds
+            let id = format!("download-{}", lyrics_txid);
+            run_job(&state, &Job::new_flac_download(id.clone(), txid)).await;
+            let download = state.read().await.db.get_job(&id).unwrap().unwrap();
+            assert_eq!(download.status, JobStatus::Complete, "{}", download.message);
+            assert_eq!(download.lyrics, None);
         }
-    };
+    }
+
+    #[tokio::test]
+    async fn retry_chunk_rebroadcasts_the_kept_chunk_and_records_it() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        run_job(&state, &flac_job("flac", &data)).await;
+        let chunk_txids = state.read().await.db.get_job("flac").unwrap().unwrap().chunk_txid_list().unwrap();
+        let attempts = || async { state.read().await.db.get_broadcast_attempts("flac").unwrap() };
+        let before = attempts().await.len();
+
+        let app = serve(
+            axum::Router::new()
+                .route("/api/flac/retry-chunk", post(routes::flac::retry_flac_chunk))
+                .with_state(state.clone()),
+        )
+        .await;
+        let retry = |chunk_index: usize| {
+            reqwest::Client::new()
+                .post(format!("{}/api/flac/retry-chunk", app))
+                .json(&serde_json::json!({ "key": routes::admin::get_admin_key(), "job_id": "flac", "chunk_index": chunk_index }))
+                .send()
+        };
+
+        let response = retry(1).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["txid"], chunk_txids[1].as_str());
+        let attempts_after = attempts().await;
+        assert_eq!(attempts_after.len(), before + 1);
+        let last = attempts_after.last().unwrap();
+        assert_eq!(last.txid, chunk_txids[1]);
+        assert_eq!(last.attempt.accepted_txid.as_deref(), Some(chunk_txids[1].as_str()));
+
+        let response = retry(3).await.unwrap();
+        assert_eq!(response.status().as_u16(), 400);
+        assert_eq!(attempts().await.len(), before + 1);
+    }
+
+    #[tokio::test]
+    async fn chunked_upload_progress_follows_bytes() {
+        let state = chunked_flac_state(accept).await;
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        run_job(&state, &flac_job("flac", &data)).await;
 
-    {
         let state = state.read().await;
-        let _ = state.db.update_job_progress(&job_id, 10.0, "Parsing transaction...");
+        let job = state.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        assert_eq!((job.bytes_done, job.bytes_total), (Some(2500), Some(2500)));
+
+        // Chunks of 1024, 1024 and 452 bytes move the bar by their size
+        let progress: Vec<f64> = state
+            .db
+            .get_job_events("flac")
+            .unwrap()
+            .into_iter()
+            .filter(|event| event.message_key.as_deref() == Some("chunk_broadcast"))
+            .filter_map(|event| event.progress)
+            .collect();
+        let expected = [1024.0, 2048.0, 2500.0].map(|done| 10.0 + 70.0 * done / 2500.0);
+        assert_eq!(progress, expected);
     }
 
-    // Try to extract as manifest first
-    if let Some(manifest) = extract_flac_manifest_from_tx(&tx_data) {
-        // Multi-chunk download
-        let filename = manifest.filename;
-        let chunk_txids = manifest.chunk_txids;
-        let track_title = manifest.title;
-        let artist_name = manifest.artist;
-        let lyrics = manifest.lyrics;
-        let cover_txid = manifest.cover_txid;
-        let total_chunks = chunk_txids.len();
-        let mut all_data: Vec<u8> = Vec::new();
+    #[tokio::test]
+    async fn chunked_upload_stages_carry_their_keys_and_params() {
+        let state = chunked_flac_state(accept).await;
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        run_job(&state, &flac_job("flac", &data)).await;
 
-        for (i, chunk_txid) in chunk_txids.iter().enumerate() {
-            let progress = 15.0 + (75.0 * (i as f64 / total_chunks as f64));
-            
-            {
-                let state = state.read().await;
-                let _ = state.db.update_job_progress(
-                    &job_id,
-                    progress,
-                    &format!("Downloading chunk {}/{}...", i + 1, total_chunks),
-                );
+        let state = state.read().await;
+        let events = state.db.get_job_events("flac").unwrap();
+        let stages: Vec<(&str, serde_json::Value)> = events
+            .iter()
+            .map(|event| (event.message_key.as_deref().unwrap(), event.message_params.clone().unwrap_or_default()))
+            .collect();
+        let none = serde_json::Value::Null;
+        let chunk = |i: u64| serde_json::json!({ "i": i, "n": 3 });
+        assert_eq!(
+            stages,
+            [
+                ("fetching_utxos", none.clone()),
+                ("preparing_split", serde_json::json!({ "n": 3 })),
+                ("broadcasting_split", none.clone()),
+                ("uploading_chunks", serde_json::json!({ "n": 3 })),
+                ("uploading_chunk", chunk(1)),
+                ("chunk_broadcast", chunk(1)),
+                ("uploading_chunk", chunk(2)),
+                ("chunk_broadcast", chunk(2)),
+                ("uploading_chunk", chunk(3)),
+                ("chunk_broadcast", chunk(3)),
+                ("creating_manifest", none.clone()),
+                ("broadcasting_manifest", none.clone()),
+                ("complete", none),
+            ]
+        );
+        // The English text is still filled in from the params
+        assert_eq!(events[4].message, "Uploading chunk 1/3...");
+        assert_eq!(events[9].message, "Chunk 3/3 broadcast");
+    }
+
+    #[tokio::test]
+    async fn retried_chunks_hold_progress_until_accepted() {
+        // Split, first chunk, then the second chunk's first attempt is refused
+        fn refuse_third(raw_tx: &str) -> (u16, serde_json::Value) {
+            static BROADCASTS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+            match BROADCASTS.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                2 => reject(raw_tx),
+                _ => accept(raw_tx),
             }
+        }
+        let state = chunked_flac_state(refuse_third).await;
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        run_job(&state, &flac_job("flac", &data)).await;
 
-            let chunk_tx_data = fetch_tx_raw(&state, chunk_txid, &network).await;
+        let state = state.read().await;
+        let job = state.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let events = state.db.get_job_events("flac").unwrap();
+        let progress_of = |key: &str| -> Vec<(String, f64)> {
+            events
+                .iter()
+                .filter(|event| event.message_key.as_deref() == Some(key))
+                .map(|event| (event.message.clone(), event.progress.unwrap()))
+                .collect()
+        };
+        let after_first = 10.0 + 70.0 * 1024.0 / 2500.0;
+        let retrying = MessageKey::RetryingChunk.with("i", 2).with("n", 3).with("attempt", 2).english();
+        assert_eq!(progress_of("retrying_chunk"), [(retrying, after_first)]);
+        // Each accepted chunk moves the bar once, the retried one included
+        let accepted: Vec<f64> = progress_of("chunk_broadcast").into_iter().map(|(_, progress)| progress).collect();
+        assert_eq!(accepted, [1024.0, 2048.0, 2500.0].map(|done| 10.0 + 70.0 * done / 2500.0));
+    }
 
-            let chunk_tx_data = match chunk_tx_data {
-                Ok(data) => data,
-                Err(e) => {
-                    let state = state.read().await;
-                    let _ = state.db.update_job_error(
-                        &job_id,
-                        &format!("Failed to fetch chunk {}: {}", i + 1, e),
-                    );
-                    return;
-                }
-            };
+    #[tokio::test]
+    async fn chunks_rejected_as_too_large_are_halved_until_accepted() {
+        // A provider refusing transactions over 512KB
+        fn refuse_over_512kb(raw_tx: &str) -> (u16, serde_json::Value) {
+            if raw_tx.len() / 2 > 512 * 1024 {
+                return (400, serde_json::json!({ "error": { "message": "tx-size" } }));
+            }
+            accept(raw_tx)
+        }
+        let data: Vec<u8> = (0..1536 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let upload = |max_chunk_halvings: u32| {
+            let data = data.clone();
+            async move {
+                let chain = MockChain::default();
+                let mut config = test_config();
+                config.bitails_api_url = chain_bitails(&chain, 10_000_000, refuse_over_512kb).await;
+                config.max_chunk_halvings = max_chunk_halvings;
+                let state = test_state_with(config);
+                let mut job = flac_job("flac", &data);
+                job.filename = Some(format!("{}.flac", uuid::Uuid::new_v4().simple()));
+                run_job(&state, &job).await;
+                (chain, state)
+            }
+        };
 
-            if let Some(chunk_data) = extract_flac_chunk_from_tx(&chunk_tx_data) {
-                all_data.extend(chunk_data);
-            } else {
-                let state = state.read().await;
-                let _ = state.db.update_job_error(
-                    &job_id,
-                    &format!("Failed to extract data from chunk {}", i + 1),
-                );
-                return;
+        let (chain, state) = upload(3).await;
+        let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let chunk_txids = job.chunk_txid_list().unwrap();
+        assert!(chunk_txids.iter().all(|txid| chain.tx(txid).unwrap().len() / 2 <= 512 * 1024));
+        let reduced = state.read().await.db.get_job_events("flac").unwrap();
+        let reduced: Vec<&str> = reduced
+            .iter()
+            .filter(|event| event.message_key.as_deref() == Some("chunk_size_reduced"))
+            .map(|event| event.message.as_str())
+            .collect();
+        // 1MB chunks, then 512KB ones, which the framing still puts over the limit
+        let halved = |n: usize, size: usize| {
+            MessageKey::ChunkSizeReduced.with("i", 1).with("bytes", data.len()).with("n", n).with("size", size).english()
+        };
+        assert_eq!(reduced, [halved(3, 512 * 1024), halved(6, 256 * 1024)]);
+        assert_eq!(chunk_txids.len(), 6);
+
+        // The smaller chunks still make up the file
+        run_job(&state, &Job::new_flac_download("download".to_string(), job.manifest_txid.unwrap())).await;
+        let saved = std::path::Path::new(routes::download::DOWNLOADS_DIR).join(job.filename.unwrap());
+        let downloaded = std::fs::read(&saved);
+        let _ = std::fs::remove_file(&saved);
+        assert_eq!(downloaded.unwrap(), data);
+
+        // Without halving the first rejection fails the upload
+        let (chain, state) = upload(0).await;
+        let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Error);
+        assert_eq!(job.error_code, Some(ErrorCode::BroadcastFailed));
+        assert_eq!(job.message_key.as_deref(), Some("chunk_too_large"), "{}", job.message);
+        // Only the split went out
+        assert_eq!(chain.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_finds_a_chunk_whose_broadcast_response_was_lost() {
+        // The first chunk reaches the network but its broadcast answers with
+        // an error; every other broadcast is accepted
+        static CHAIN: std::sync::OnceLock<MockChain> = std::sync::OnceLock::new();
+        static BROADCASTS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        fn lose_second(raw_tx: &str) -> (u16, serde_json::Value) {
+            match BROADCASTS.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                1 => {
+                    CHAIN.get().unwrap().add(raw_tx);
+                    (500, serde_json::json!({ "error": { "message": "connection reset" } }))
+                }
+                _ => accept(raw_tx),
             }
+        }
+        let chain = CHAIN.get_or_init(MockChain::default);
+        let mut config = test_config();
+        config.bitails_api_url = chain_bitails(chain, 10_000_000, lose_second).await;
+        let state = test_state_with(config);
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+
+        let data: Vec<u8> = (0..1500u32).map(|i| i as u8).collect();
+        run_job(&state, &flac_job("flac", &data)).await;
+
+        let state = state.read().await;
+        let job = state.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        // Split, two chunks and the manifest; the lost chunk isn't sent again
+        assert_eq!(BROADCASTS.load(std::sync::atomic::Ordering::SeqCst), 4);
+        let chunk_txids: Vec<&str> = job.chunk_txids.as_deref().unwrap().split(',').collect();
+        assert_eq!(chunk_txids.len(), 2);
+        assert!(chunk_txids.iter().all(|txid| chain.tx(txid).is_some()));
+    }
+
+    /// A single-transaction FLAC upload of `data`
+    fn flac_store_tx(filename: &str, data: &[u8]) -> String {
+        let bsv = BsvService::for_tests();
+        let metadata = serde_json::json!({ "filename": filename }).to_string();
+        let script = bsv.create_flac_store_script(b"flacstore", b"audio/flac", metadata.as_bytes(), &[data.to_vec()]);
+        bsv.test_transaction(&[(script, Amount::from_sat_const(1))])
+    }
+
+    /// State whose Bitails stand-in serves `chain`
+    async fn chain_state(chain: &MockChain) -> Arc<RwLock<AppState>> {
+        let mut config = test_config();
+        config.bitails_api_url = chain_bitails(chain, 10_000_000, accept).await;
+        config.admin_key_in_body = true;
+        test_state_with(config)
+    }
 
-            sleep(Duration::from_millis(100)).await;
-        }
+    /// Run the next job the scheduler hands out
+    async fn run_next_job(state: &Arc<RwLock<AppState>>) {
+        let scheduler = state.read().await.scheduler.clone();
+        let (job, _permit) = tokio::time::timeout(std::time::Duration::from_secs(1), scheduler.next()).await.unwrap();
+        run_job_guarded(state.clone(), job).await;
+    }
 
-        {
-            let state = state.read().await;
-            let _ = state.db.update_job_progress(&job_id, 95.0, "Saving file...");
-        }
+    #[tokio::test]
+    async fn json_routes_take_small_bodies_and_uploads_large_ones() {
+        let mut config = test_config();
+        config.upload_body_limit_bytes = 2 * 1024 * 1024;
+        config.max_upload_cost_satoshis = 10_000_000;
+        let state = test_state_with(config.clone());
+        let app = serve(router(&config, state)).await;
+        let client = reqwest::Client::new();
 
-        let downloads_dir = std::path::Path::new("./data/downloads");
-        std::fs::create_dir_all(downloads_dir).ok();
+        let send = |body: String| {
+            client
+                .post(format!("{}/api/wallet/send", app))
+                .header("content-type", "application/json")
+                .body(body)
+                .send()
+        };
+        let padding = "x".repeat(config.json_body_limit_bytes);
+        let oversized = send(format!("{{\"padding\":\"{}\"}}", padding)).await.unwrap();
+        assert_eq!(oversized.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        // A small body gets as far as the handler
+        let small = send("{}".to_string()).await.unwrap();
+        assert_ne!(small.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+
+        let upload = |size: usize| {
+            let file = vec![b'a'; size];
+            let (content_type, body) = multipart_body(&[("file", Some("big.txt"), &file)]);
+            client
+                .post(format!("{}/prepare_upload", app))
+                .header("content-type", content_type)
+                .body(body)
+                .send()
+        };
+        let large = upload(1024 * 1024).await.unwrap();
+        assert_eq!(large.status(), reqwest::StatusCode::OK);
+        // Past the upload limit the file is cut off before it can be read
+        let too_large = upload(3 * 1024 * 1024).await.unwrap();
+        assert!(too_large.status().is_client_error());
+        let body: serde_json::Value = too_large.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "INVALID_REQUEST");
+    }
 
-        let file_path = downloads_dir.join(&filename);
-        if let Err(e) = std::fs::write(&file_path, &all_data) {
+    #[tokio::test]
+    async fn testnet_admin_paid_upload_runs_end_to_end() {
+        let state = test_state();
+        let (admin_wif, admin_address) = BsvService::generate_keypair(Network::Testnet);
+        whatsonchain_fund(&admin_address, 10_000_000);
+        {
             let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Failed to save file: {}", e));
-            return;
+            let mut admin_config = state.db.get_admin_config().unwrap();
+            admin_config.admin_pay_testnet = true;
+            admin_config.testnet_wif = Some(admin_wif);
+            state.db.update_admin_config(&admin_config).unwrap();
         }
+        let app = serve(
+            axum::Router::new()
+                .route("/prepare_upload", post(routes::upload::prepare_upload))
+                .with_state(state.clone()),
+        )
+        .await;
+
+        let (content_type, body) = multipart_body(&[
+            ("file", Some("hello.txt"), b"hello testnet"),
+            ("network", None, b"testnet"),
+            ("admin_pay", None, b"true"),
+        ]);
+        let response = reqwest::Client::new()
+            .post(format!("{}/prepare_upload", app))
+            .header("content-type", content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["admin_pay"], true, "{}", body);
+        assert!(body["payment_address"].is_null());
+
+        run_next_job(&state).await;
+        let job = state.read().await.db.get_job(body["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        assert_eq!(job.network, Some(Network::Testnet));
+        assert_eq!(job.payment_address.as_deref(), Some(admin_address.as_str()));
+        // Broadcast to WhatsOnChain's testnet API, paid from the admin wallet
+        let tx_hex = whatsonchain_chain().tx(job.manifest_txid.as_ref().unwrap()).unwrap();
+        let tx = parse_transaction(&tx_hex).unwrap();
+        let pubkey = services::tx_parse::extract_pubkey_from_script_sig(&tx.inputs[0].script_sig).unwrap();
+        assert_eq!(BsvService::pubkey_bytes_to_address(&pubkey, Network::Testnet), admin_address);
+        let file = extract_op_return_from_tx(&tx_hex).unwrap();
+        assert_eq!((file.filename.as_str(), file.data.as_slice()), ("hello.txt", b"hello testnet".as_slice()));
+    }
 
-        // Create web-accessible download link
-        let download_link = format!("/downloads/{}", filename);
-        
-        {
-            let state = state.read().await;
-            let _ = state.db.update_job_complete_with_filename(
-                &job_id,
-                &txid,
-                Some(&download_link),
-                &filename,
-            );
-            // Update metadata (title, artist, lyrics, cover_txid) from manifest
-            let _ = state.db.update_job_metadata(
-                &job_id,
-                track_title.as_deref(),
-                artist_name.as_deref(),
-                lyrics.as_deref(),
-            );
-            // Update cover_txid if available
-            if let Some(ref cover) = cover_txid {
-                let _ = state.db.update_job_cover_txid(&job_id, cover);
+    #[tokio::test]
+    async fn stn_paid_upload_is_watched_and_broadcast_on_stn() {
+        let state = test_state();
+        let app = serve(
+            axum::Router::new()
+                .route("/prepare_upload", post(routes::upload::prepare_upload))
+                .with_state(state.clone()),
+        )
+        .await;
+        let data = format!("hello stn {}", uuid::Uuid::new_v4());
+        let (content_type, body) = multipart_body(&[("file", Some("hello.txt"), data.as_bytes()), ("network", None, b"stn")]);
+        let response = reqwest::Client::new()
+            .post(format!("{}/prepare_upload", app))
+            .header("content-type", content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        let job_id = body["job_id"].as_str().unwrap().to_string();
+        let address = body["payment_address"].as_str().unwrap().to_string();
+        // STN addresses are written like testnet ones
+        assert!(address.starts_with(['m', 'n']), "{}", address);
+
+        // The watcher finds the payment on WhatsOnChain's STN API
+        whatsonchain_fund(&address, body["required_satoshis"].as_i64().unwrap());
+        let watcher = tokio::spawn(payment_watcher(state.clone()));
+        let paid = async {
+            while !state.read().await.scheduler.is_queued(&job_id) {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
             }
+        };
+        let paid = tokio::time::timeout(std::time::Duration::from_secs(5), paid).await;
+        watcher.abort();
+        assert!(paid.is_ok(), "payment not seen");
+
+        run_next_job(&state).await;
+        let job = state.read().await.db.get_job(&job_id).unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        assert_eq!(job.network, Some(Network::Stn));
+        let tx_hex = whatsonchain_chain().tx(job.manifest_txid.as_ref().unwrap()).unwrap();
+        let tx = parse_transaction(&tx_hex).unwrap();
+        let pubkey = services::tx_parse::extract_pubkey_from_script_sig(&tx.inputs[0].script_sig).unwrap();
+        assert_eq!(BsvService::pubkey_bytes_to_address(&pubkey, Network::Stn), address);
+        assert_eq!(extract_op_return_from_tx(&tx_hex).unwrap().data, data.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn batch_download_zips_every_track() {
+        let chain = MockChain::default();
+        // Saved under one name, so only the bytes each download returned tell them apart
+        let name = format!("{}.flac", uuid::Uuid::new_v4().simple());
+        let tracks = [b"fLaC first track".to_vec(), b"fLaC second track".to_vec()];
+        let txids: Vec<String> = tracks.iter().map(|data| chain.add(&flac_store_tx(&name, data))).collect();
+        let state = chain_state(&chain).await;
+
+        let app = serve(
+            axum::Router::new()
+                .route("/api/flac/download/batch", post(routes::flac::start_flac_batch_download))
+                .with_state(state.clone()),
+        )
+        .await;
+        let response: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/api/flac/download/batch", app))
+            .json(&serde_json::json!({ "txids": txids, "key": routes::admin::get_admin_key() }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response["child_job_ids"].as_array().unwrap().len(), 2, "{}", response);
+        run_next_job(&state).await;
+
+        let batch = state.read().await.db.get_job(response["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(batch.status, JobStatus::Complete, "{}", batch.message);
+        assert_eq!(batch.progress, 100.0);
+        let downloads_dir = std::path::Path::new(routes::download::DOWNLOADS_DIR);
+        let archive_path = downloads_dir.join(batch.filename.unwrap());
+        let archive = std::fs::read(&archive_path);
+        let _ = std::fs::remove_file(&archive_path);
+        let _ = std::fs::remove_file(downloads_dir.join(&name));
+        let archive = archive.unwrap();
+        let entries = archive.windows(4).filter(|w| *w == 0x04034b50u32.to_le_bytes()).count();
+        assert_eq!(entries, 2);
+        for (entry, data) in [name.clone(), format!("2-{}", name)].iter().zip(&tracks) {
+            assert!(archive.windows(entry.len()).any(|w| w == entry.as_bytes()), "{}", entry);
+            assert!(archive.windows(data.len()).any(|w| w == data.as_slice()), "{}", entry);
         }
-        tracing::info!(
-            "FLAC chunked download complete for job {}: {} ({} chunks, {} bytes, title: {:?})",
-            job_id,
-            filename,
-            total_chunks,
-            all_data.len(),
-            track_title
-        );
-    } else if let Some((file_data, filename)) = extract_flac_from_tx(&tx_data) {
-        // Single transaction download
-        let downloads_dir = std::path::Path::new("./data/downloads");
-        std::fs::create_dir_all(downloads_dir).ok();
+    }
 
-        let file_path = downloads_dir.join(&filename);
-        if let Err(e) = std::fs::write(&file_path, &file_data) {
-            let state = state.read().await;
-            let _ = state.db.update_job_error(&job_id, &format!("Failed to save file: {}", e));
-            return;
+    #[tokio::test]
+    async fn cancelling_mid_upload_stops_broadcasting() {
+        let chain = MockChain::default();
+        let mut config = test_config();
+        config.bitails_api_url = chain_bitails(&chain, 10_000_000, accept).await;
+        let state = test_state_with(config);
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+
+        let data: Vec<u8> = (0..4000u32).map(|i| i as u8).collect();
+        // Paid, as the payment monitor leaves it for the dispatcher
+        let job = flac_job("flac", &data).with_status(JobStatus::Processing, MessageKey::Starting);
+        let owner_token = job.owner_token.clone().unwrap();
+        let upload = tokio::spawn({
+            let state = state.clone();
+            async move { run_job(&state, &job).await }
+        });
+
+        // Cancel once the first chunk is out, while the job waits before the next
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let bytes_done = state.read().await.db.get_job("flac").unwrap().and_then(|job| job.bytes_done);
+            if bytes_done.unwrap_or(0) > 0 {
+                break;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "no chunk was broadcast");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
         }
+        let response = routes::jobs::cancel_job(
+            axum::extract::State(state.clone()),
+            axum::extract::Path("flac".to_string()),
+            axum::Json(routes::jobs::CancelJobRequest { owner_token }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0.status, "processing");
+        let broadcasts = chain.count();
+        upload.await.unwrap();
 
-        // Create web-accessible download link
-        let download_link = format!("/downloads/{}", filename);
-        
-        let state = state.read().await;
-        let _ = state.db.update_job_complete_with_filename(
-            &job_id,
-            &txid,
-            Some(&download_link),
-            &filename,
-        );
-        tracing::info!("FLAC download complete for job {}: {}", job_id, filename);
-    } else {
+        assert_eq!(chain.count(), broadcasts, "broadcast after the cancel");
         let state = state.read().await;
-        let _ = state.db.update_job_error(&job_id, "No FLAC data found in transaction");
+        let job = state.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Cancelled, "{}", job.message);
+        assert_eq!(job.bytes_done, Some(1024));
+        let chunks = state
+            .db
+            .get_job_events("flac")
+            .unwrap()
+            .into_iter()
+            .filter(|event| event.message_key.as_deref() == Some("chunk_broadcast"))
+            .count();
+        assert_eq!(chunks, 1);
     }
-}
 
-// Helper functions for transaction parsing
-
-fn extract_op_return_from_tx(tx_hex: &str) -> Option<(Vec<u8>, String)> {
-    let tx_bytes = hex::decode(tx_hex).ok()?;
-    
-    let mut i = 0;
-    i += 4; // version
-    
-    let (input_count, varint_size) = read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..input_count {
-        i += 32;
-        i += 4;
-        let (script_len, vs) = read_varint(&tx_bytes[i..])?;
-        i += vs;
-        i += script_len as usize;
-        i += 4;
+    #[tokio::test]
+    async fn verify_checks_a_claimed_hash_without_saving_the_file() {
+        let state = test_state();
+        let filename = format!("verified {}.flac", uuid::Uuid::new_v4().simple());
+        let chunks: [&[u8]; 2] = [b"fLaC verified ", b"track"];
+        let chunk_txids = add_chunks(&whatsonchain_chain(), &chunks);
+        let manifest_txid = add_named_manifest(&whatsonchain_chain(), &filename, 19, &chunk_txids, &[]);
+        let sha256 = hex::encode(Sha256::digest(chunks.concat()));
+
+        let verify = |expected_sha256: String| {
+            let request = routes::download::VerifyFileRequest {
+                txid: manifest_txid.clone(),
+                expected_sha256,
+                network: Some(Network::Testnet),
+            };
+            routes::download::verify_file(axum::extract::State(state.clone()), axum::Json(request))
+        };
+        // Hex case doesn't matter
+        let verified = verify(sha256.to_uppercase()).await.unwrap().0;
+        assert!(verified.matches);
+        assert_eq!((verified.filename.as_str(), verified.size), (filename.as_str(), 19));
+        assert_eq!(verified.sha256, sha256);
+
+        let mismatch = verify("00".repeat(32)).await.unwrap().0;
+        assert!(!mismatch.matches);
+        assert_eq!(mismatch.sha256, sha256);
+        assert_eq!(mismatch.expected_sha256, "00".repeat(32));
+
+        assert!(!std::path::Path::new(routes::download::DOWNLOADS_DIR).join(&filename).exists());
+        assert!(state.read().await.db.get_all_jobs(None).unwrap().is_empty());
     }
-    
-    let (output_count, varint_size) = read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..output_count {
-        i += 8;
-        let (script_len, vs) = read_varint(&tx_bytes[i..])?;
-        i += vs;
-        
-        let script = &tx_bytes[i..i + script_len as usize];
-        i += script_len as usize;
-        
-        if script.len() > 2 && ((script[0] == 0x00 && script[1] == 0x6a) || script[0] == 0x6a) {
-            let start = if script[0] == 0x00 { 2 } else { 1 };
-            return parse_op_return_script(&script[start..]);
-        }
+
+    #[tokio::test]
+    async fn manifest_chunk_hashes_round_trip_and_catch_a_tampered_chunk() {
+        // An upload's manifest carries the hash of each chunk it sent
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        let chunk_size = state.read().await.bsv.plan_flac_upload(data.len(), Network::Mainnet).chunk_size;
+        run_job(&state, &flac_job("upload", &data)).await;
+        let upload = state.read().await.db.get_job("upload").unwrap().unwrap();
+        assert_eq!(upload.status, JobStatus::Complete, "{}", upload.message);
+        let manifest_tx = chain.tx(upload.manifest_txid.as_ref().unwrap()).unwrap();
+        let Some(FlacData::Manifest(manifest)) = find_in_outputs(&manifest_tx, parse_flac_output) else {
+            panic!("no manifest");
+        };
+        let hashes: Vec<String> = data.chunks(chunk_size).map(|chunk| hex::encode(Sha256::digest(chunk))).collect();
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(manifest.chunk_hashes, hashes);
+
+        // A chunk changed after its hash was taken is named by index and txid
+        let chunks: Vec<Vec<u8>> = (0..3).map(|_| uuid::Uuid::new_v4().as_bytes().to_vec()).collect();
+        let hashes: Vec<String> = chunks.iter().map(|chunk| hex::encode(Sha256::digest(chunk))).collect();
+        let mut tampered = chunks.clone();
+        tampered[1][0] ^= 1;
+        let chunk_txids = add_chunks(&whatsonchain_chain(), &tampered.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        let filename = format!("{}.flac", uuid::Uuid::new_v4().simple());
+        let manifest_txid = add_named_manifest(&whatsonchain_chain(), &filename, 48, &chunk_txids, &hashes);
+        let expected = MessageKey::ChunkHashMismatch.with("index", 1).with("txid", chunk_txids[1].as_str()).english();
+
+        let state = test_state();
+        let download = Job::new_flac_download("download".to_string(), manifest_txid.clone()).with_network(Network::Testnet);
+        run_job(&state, &download).await;
+        let download = state.read().await.db.get_job("download").unwrap().unwrap();
+        assert_eq!(download.status, JobStatus::Error);
+        assert_eq!(download.error_code, Some(ErrorCode::ChunkHashMismatch));
+        assert_eq!(download.message, expected);
+        assert!(!std::path::Path::new(routes::download::DOWNLOADS_DIR).join(&filename).exists());
+
+        let request = routes::download::VerifyFileRequest {
+            txid: manifest_txid,
+            expected_sha256: hex::encode(Sha256::digest(chunks.concat())),
+            network: Some(Network::Testnet),
+        };
+        let Err(error) = routes::download::verify_file(axum::extract::State(state.clone()), axum::Json(request)).await else {
+            panic!("a tampered chunk verified");
+        };
+        assert_eq!(error.code, ErrorCode::ChunkHashMismatch);
+        assert_eq!(error.message, expected);
     }
-    
-    None
-}
 
-fn extract_flac_manifest_from_tx(tx_hex: &str) -> Option<ManifestMetadata> {
-    let tx_bytes = hex::decode(tx_hex).ok()?;
-    
-    let mut i = 0;
-    i += 4;
-    
-    let (input_count, varint_size) = read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..input_count {
-        i += 32;
-        i += 4;
-        let (script_len, vs) = read_varint(&tx_bytes[i..])?;
-        i += vs;
-        i += script_len as usize;
-        i += 4;
+    /// Chunk transactions of `chunks`, indexed in order, on `chain`
+    fn add_chunks(chain: &MockChain, chunks: &[&[u8]]) -> Vec<String> {
+        let bsv = BsvService::for_tests();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                let script = bsv.create_flac_chunk_script(i as u32, chunks.len() as u32, data);
+                chain.add(&bsv.test_transaction(&[(script, Amount::from_sat_const(1))]))
+            })
+            .collect()
     }
-    
-    let (output_count, varint_size) = read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..output_count {
-        i += 8;
-        let (script_len, vs) = read_varint(&tx_bytes[i..])?;
-        i += vs;
-        
-        let script = &tx_bytes[i..i + script_len as usize];
-        i += script_len as usize;
-        
-        if script.len() > 2 && script[0] == 0x00 && script[1] == 0x63 {
-            if let Some(manifest) = parse_flac_manifest_script(&script[2..]) {
-                return Some(manifest);
-            }
+
+    #[tokio::test]
+    async fn chunk_data_output_matches_the_full_transaction() {
+        let chain = MockChain::default();
+        let chunks: Vec<Vec<u8>> = [10usize, 600, 3000].iter().map(|&len| (0..len).map(|i| (i % 251) as u8).collect()).collect();
+        let txids = add_chunks(&chain, &chunks.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        let state = chain_state(&chain).await;
+
+        for (i, txid) in txids.iter().enumerate() {
+            let script = state.read().await.bitails.download_tx_output(txid, CHUNK_DATA_VOUT).await.unwrap();
+            let from_output = script.strip_prefix(&[0x00, 0x63]).and_then(parse_flac_chunk_script);
+            let from_tx = extract_flac_chunk_from_tx(&chain.tx(txid).unwrap());
+            assert_eq!(from_output, from_tx);
+            assert_eq!(from_output, Some((i as u32, chunks[i].clone())));
+            assert_eq!(fetch_flac_chunk(&state, txid, Network::Mainnet).await.unwrap(), from_tx);
         }
     }
-    
-    None
-}
 
-/// Manifest metadata structure
-#[derive(Debug, Clone)]
-struct ManifestMetadata {
-    filename: String,
-    chunk_txids: Vec<String>,
-    title: Option<String>,
-    artist: Option<String>,
-    lyrics: Option<String>,
-    cover_txid: Option<String>,
-}
+    #[tokio::test]
+    async fn raw_tx_that_does_not_match_falls_back_to_whatsonchain() {
+        let bsv = BsvService::for_tests();
+        let wanted = bsv.test_transaction(&[]);
+        let txid = whatsonchain_chain().add(&wanted);
+        // Bitails answers with some other transaction
+        let other = hex::decode(bsv.test_transaction(&[])).unwrap();
+        let mut config = test_config();
+        config.bitails_api_url = serve(axum::Router::new().route("/download/tx/:txid", get(move || async move { other }))).await;
+        let state = test_state_with(config);
+
+        assert_eq!(fetch_tx_raw(&state, &txid, Network::Mainnet).await.unwrap(), wanted);
+    }
 
-fn parse_flac_manifest_script(script: &[u8]) -> Option<ManifestMetadata> {
-    let mut i = 0;
-    let mut push_data_items: Vec<Vec<u8>> = Vec::new();
-    
-    while i < script.len() {
-        if script[i] == 0x68 {
-            break;
+    #[tokio::test]
+    async fn mainnet_data_downloads_only_the_data_output() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let bsv = BsvService::for_tests();
+        let upfile = bsv.create_upfile_script("text/plain", "a.txt", b"hello");
+        let txid = chain.add(&bsv.test_transaction(&[(upfile.clone(), Amount::from_sat_const(1))]));
+        // The largest script is not the file, so the output guess is wrong
+        let other = bsv.create_op_return_script(&[b"other", &[7u8; 500]]);
+        let decoy_txid = chain.add(&bsv.test_transaction(&[(other, Amount::from_sat_const(1)), (upfile, Amount::from_sat_const(1))]));
+
+        for (txid, downloads) in [(&txid, vec!["output"]), (&decoy_txid, vec!["output", "raw"])] {
+            let file = fetch_tx_data(&state, txid, Network::Mainnet, parse_upfile_output).await.unwrap().unwrap();
+            assert_eq!((file.filename.as_str(), file.data.as_slice()), ("a.txt", b"hello".as_slice()));
+            assert_eq!(chain.downloads(txid), downloads);
         }
-        
-        let (data, consumed) = read_push_data(&script[i..])?;
-        push_data_items.push(data);
-        i += consumed;
     }
-    
-    if push_data_items.len() < 3 {
-        return None;
+
+    /// Manifest of `song.flac` declaring `size` bytes in `chunk_txids`, on `chain`
+    fn add_manifest(chain: &MockChain, size: usize, chunk_txids: &[String], chunk_hashes: &[String]) -> String {
+        add_named_manifest(chain, "song.flac", size, chunk_txids, chunk_hashes)
     }
-    
-    let protocol = String::from_utf8_lossy(&push_data_items[0]);
-    if protocol != "flacstore-manifest" {
-        return None;
+
+    /// Like `add_manifest`, for a track saved as `filename` so tests reading
+    /// the saved file back don't share one
+    fn add_named_manifest(chain: &MockChain, filename: &str, size: usize, chunk_txids: &[String], chunk_hashes: &[String]) -> String {
+        let script = BsvService::create_flac_manifest_script(
+            filename,
+            size,
+            chunk_txids,
+            chunk_hashes,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            ManifestLayout::Json,
+        );
+        chain.add(&BsvService::for_tests().test_transaction(&[(script, Amount::from_sat_const(1))]))
     }
-    
-    let filename = String::from_utf8_lossy(&push_data_items[1]).to_string();
-    
-    // Parse metadata JSON to extract title, artist, lyrics, and cover_txid
-    let metadata_str = String::from_utf8_lossy(&push_data_items[2]);
-    let (title, artist, lyrics, cover_txid) = if let Ok(metadata) = serde_json::from_str::<serde_json::Value>(&metadata_str) {
-        let title = metadata["title"].as_str().filter(|s| !s.is_empty()).map(|s| s.to_string());
-        let artist = metadata["artist"].as_str().filter(|s| !s.is_empty()).map(|s| s.to_string());
-        let lyrics = metadata["lyrics"].as_str().filter(|s| !s.is_empty()).map(|s| s.to_string());
-        let cover_txid = metadata["cover_txid"].as_str().filter(|s| !s.is_empty()).map(|s| s.to_string());
-        (title, artist, lyrics, cover_txid)
-    } else {
-        (None, None, None, None)
-    };
-    
-    let chunk_txids: Vec<String> = push_data_items[3..]
-        .iter()
-        .map(|data| String::from_utf8_lossy(data).to_string())
-        .collect();
-    
-    if chunk_txids.is_empty() {
-        return None;
+
+    #[tokio::test]
+    async fn download_shorter_than_its_manifest_fails() {
+        let chain = MockChain::default();
+        let chunk_txids = add_chunks(&chain, &[b"fLaC first half ", b"second half, cut short"]);
+        // The manifest declares more bytes than the chunks hold
+        let manifest_txid = add_manifest(&chain, 64, &chunk_txids, &[]);
+        let state = chain_state(&chain).await;
+
+        run_job(&state, &Job::new_flac_download("download".to_string(), manifest_txid)).await;
+
+        let job = state.read().await.db.get_job("download").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Error, "{}", job.message);
+        assert_eq!(job.error_code, Some(ErrorCode::SizeMismatch));
+        assert_eq!(job.message, MessageKey::SizeMismatch.with("got", 38).with("expected", 64).english());
+        assert_eq!(job.download_link, None);
     }
-    
-    Some(ManifestMetadata {
-        filename,
-        chunk_txids,
-        title,
-        artist,
-        lyrics,
-        cover_txid,
-    })
-}
 
-fn extract_flac_chunk_from_tx(tx_hex: &str) -> Option<Vec<u8>> {
-    let tx_bytes = hex::decode(tx_hex).ok()?;
-    
-    let mut i = 0;
-    i += 4;
-    
-    let (input_count, varint_size) = read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..input_count {
-        i += 32;
-        i += 4;
-        let (script_len, vs) = read_varint(&tx_bytes[i..])?;
-        i += vs;
-        i += script_len as usize;
-        i += 4;
+    #[tokio::test]
+    async fn zero_conf_payments_wait_unless_this_server_sent_them() {
+        let state = test_state();
+        let utxo = |txid: &str, confirmations: i64| crate::services::bitails::Utxo {
+            txid: txid.to_string(),
+            vout: 0,
+            satoshis: 10_000,
+            script_pubkey: String::new(),
+            blockheight: None,
+            confirmations: Some(confirmations),
+        };
+        let payment = [utxo(&"aa".repeat(32), 0), utxo(&"bb".repeat(32), 3)];
+        // Nothing asked for, so nothing is held
+        assert_eq!(unconfirmed_funding(&state, &payment, Network::Mainnet).await, None);
+
+        state.write().await.config.min_payment_confirmations = 2;
+        assert_eq!(unconfirmed_funding(&state, &payment, Network::Mainnet).await, Some(0));
+        assert_eq!(unconfirmed_funding(&state, &payment[1..], Network::Mainnet).await, None);
+
+        // Change of a transaction this server broadcast is spent at once
+        let accepted = BroadcastAttempt::accepted("bitails", 200, "aa".repeat(32));
+        state.read().await.db.record_broadcast_attempt(None, &"aa".repeat(32), Network::Mainnet, &accepted).unwrap();
+        assert_eq!(unconfirmed_funding(&state, &payment, Network::Mainnet).await, None);
     }
-    
-    let (output_count, varint_size) = read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..output_count {
-        i += 8;
-        let (script_len, vs) = read_varint(&tx_bytes[i..])?;
-        i += vs;
-        
-        let script = &tx_bytes[i..i + script_len as usize];
-        i += script_len as usize;
-        
-        if script.len() > 2 && script[0] == 0x00 && script[1] == 0x63 {
-            if let Some(data) = parse_flac_chunk_script(&script[2..]) {
-                return Some(data);
+
+    #[tokio::test]
+    async fn payment_sender_is_the_p2pkh_input_address() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        let bsv = BsvService::for_tests();
+        let (wif, sender) = BsvService::generate_keypair(Network::Mainnet);
+        let (_, payment_address) = BsvService::generate_keypair(Network::Mainnet);
+        let utxo = ("11".repeat(32), 0, 10_000, BsvService::create_p2pkh_script(&sender).unwrap());
+        let payment = (BsvService::create_p2pkh_script(&payment_address).unwrap(), Amount::from_sat_const(9_000));
+        let funding_txid = chain.add(&bsv.create_transaction(&wif, &[utxo], &[payment]).unwrap());
+
+        assert_eq!(find_payment_sender(&state, &funding_txid, Network::Mainnet).await, Some(sender));
+        // A transaction the provider doesn't have leaves the sender unknown
+        assert_eq!(find_payment_sender(&state, &"33".repeat(32), Network::Mainnet).await, None);
+    }
+
+    #[tokio::test]
+    async fn royalty_is_paid_by_the_manifest_transaction() {
+        let (_, royalty_address) = BsvService::generate_keypair(Network::Mainnet);
+        let royalty_script = BsvService::create_p2pkh_script(&royalty_address).unwrap();
+        // One transaction for the small track, chunks and a manifest for the large one
+        for size in [100u32, 1500] {
+            let chain = MockChain::default();
+            let state = chain_state(&chain).await;
+            if size > 1024 {
+                state.write().await.bsv.provider_tx_limits.bitails = Some(1);
             }
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let job = flac_job("flac", &data).with_royalty(Some((royalty_address.clone(), 600)));
+            run_job(&state, &job).await;
+
+            let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+            assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+            assert_eq!(job.chunk_txid_list().map(|txids| txids.len()), (size > 1024).then_some(2));
+            let manifest = parse_transaction(&chain.tx(job.manifest_txid.as_ref().unwrap()).unwrap()).unwrap();
+            let royalty: Vec<_> = manifest.outputs.iter().filter(|output| output.script == royalty_script).collect();
+            assert_eq!(royalty.len(), 1, "{} bytes", size);
+            assert_eq!(royalty[0].satoshis, 600);
+            // The metadata names the destination too
+            let recorded = format!("\"royalty_address\":\"{}\"", royalty_address);
+            assert!(manifest.outputs[0].script.windows(recorded.len()).any(|w| w == recorded.as_bytes()));
         }
     }
-    
-    None
-}
 
-fn parse_flac_chunk_script(script: &[u8]) -> Option<Vec<u8>> {
-    let mut i = 0;
-    let mut push_data_items: Vec<Vec<u8>> = Vec::new();
-    
-    while i < script.len() {
-        if script[i] == 0x68 {
-            break;
+    #[tokio::test]
+    async fn data_outputs_carry_the_configured_value() {
+        let value = Amount::from_sat_const(600);
+        // One transaction for the small track, chunks and a manifest for the large one
+        for size in [100u32, 1500] {
+            let chain = MockChain::default();
+            let state = chain_state(&chain).await;
+            let outputs = if size > 1024 { 3 } else { 1 };
+            {
+                let mut state = state.write().await;
+                if size > 1024 {
+                    state.bsv.provider_tx_limits.bitails = Some(1);
+                }
+                let default_cost = state.bsv.plan_flac_upload(size as usize, Network::Mainnet).cost;
+                state.bsv.data_output_satoshis = value;
+                let cost = state.bsv.plan_flac_upload(size as usize, Network::Mainnet).cost;
+                // Every data output is quoted at the new value, plus the buffer on it
+                assert!(cost.to_sat() - default_cost.to_sat() >= outputs * 599, "{} bytes", size);
+            }
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            run_job(&state, &flac_job("flac", &data)).await;
+
+            let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+            assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+            let mut txids = job.chunk_txid_list().unwrap_or_default();
+            txids.push(job.manifest_txid.unwrap());
+            assert_eq!(txids.len() as u64, outputs);
+            for txid in txids {
+                let tx = parse_transaction(&chain.tx(&txid).unwrap()).unwrap();
+                assert_eq!(tx.outputs[0].satoshis, 600, "{} bytes", size);
+            }
         }
-        
-        let (data, consumed) = read_push_data(&script[i..])?;
-        push_data_items.push(data);
-        i += consumed;
-    }
-    
-    if push_data_items.len() < 3 {
-        return None;
-    }
-    
-    let protocol = String::from_utf8_lossy(&push_data_items[0]);
-    if protocol != "flacstore-chunk" {
-        return None;
-    }
-    
-    if push_data_items.len() >= 3 {
-        return Some(push_data_items[2].clone());
     }
-    
-    None
-}
 
-fn extract_flac_from_tx(tx_hex: &str) -> Option<(Vec<u8>, String)> {
-    let tx_bytes = hex::decode(tx_hex).ok()?;
-    
-    let mut i = 0;
-    i += 4;
-    
-    let (input_count, varint_size) = read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..input_count {
-        i += 32;
-        i += 4;
-        let (script_len, vs) = read_varint(&tx_bytes[i..])?;
-        i += vs;
-        i += script_len as usize;
-        i += 4;
-    }
-    
-    let (output_count, varint_size) = read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..output_count {
-        i += 8;
-        let (script_len, vs) = read_varint(&tx_bytes[i..])?;
-        i += vs;
-        
-        let script = &tx_bytes[i..i + script_len as usize];
-        i += script_len as usize;
-        
-        if script.len() > 2 && script[0] == 0x00 && script[1] == 0x63 {
-            if let Some((data, filename)) = parse_flac_store_script(&script[2..]) {
-                return Some((data, filename));
+    #[tokio::test]
+    async fn zero_satoshi_data_outputs_with_a_lower_dust_limit() {
+        // One transaction for the small track, chunks and a manifest for the large one
+        for size in [100u32, 1500] {
+            let chain = MockChain::default();
+            let state = chain_state(&chain).await;
+            {
+                let mut state = state.write().await;
+                if size > 1024 {
+                    state.bsv.provider_tx_limits.bitails = Some(1);
+                }
+                state.bsv.data_output_satoshis = Amount::ZERO;
+                state.bsv.dust_limit = Amount::from_sat_const(100);
+            }
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            run_job(&state, &flac_job("flac", &data)).await;
+
+            let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+            assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+            let mut txids = job.chunk_txid_list().unwrap_or_default();
+            txids.push(job.manifest_txid.unwrap());
+            for txid in txids {
+                let tx = parse_transaction(&chain.tx(&txid).unwrap()).unwrap();
+                assert_eq!(tx.outputs[0].satoshis, 0, "{} bytes", size);
+            }
+            // Split outputs still fund their transactions at or above the dust limit
+            assert_eq!(job.split_txid.is_some(), size > 1024);
+            if let Some(split_txid) = job.split_txid {
+                let split = parse_transaction(&chain.tx(&split_txid).unwrap()).unwrap();
+                assert!(split.outputs.iter().all(|output| output.satoshis >= 100));
             }
         }
     }
-    
-    None
-}
 
-fn parse_flac_store_script(script: &[u8]) -> Option<(Vec<u8>, String)> {
-    let mut i = 0;
-    let mut push_data_items: Vec<Vec<u8>> = Vec::new();
-    
-    while i < script.len() {
-        if script[i] == 0x68 {
-            break;
+    #[tokio::test]
+    async fn reupload_only_broadcasts_the_changed_chunk() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+        let mut data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        run_job(&state, &flac_job("first", &data)).await;
+        // Split, three chunks and the manifest
+        assert_eq!(chain.count(), 5);
+
+        data[1500] ^= 0xff;
+        run_job(&state, &flac_job("second", &data)).await;
+        let (first, second) = {
+            let state = state.read().await;
+            (state.db.get_job("first").unwrap().unwrap(), state.db.get_job("second").unwrap().unwrap())
+        };
+        assert_eq!(second.status, JobStatus::Complete, "{}", second.message);
+        // Split, the middle chunk and the manifest
+        assert_eq!(chain.count(), 8);
+        let (first, second) = (first.chunk_txid_list().unwrap(), second.chunk_txid_list().unwrap());
+        assert_eq!((first[0] == second[0], first[1] == second[1], first[2] == second[2]), (true, false, true));
+    }
+
+    #[tokio::test]
+    async fn stored_chunk_txids_are_the_broadcast_chunks() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        run_job(&state, &flac_job("flac", &data)).await;
+
+        let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let split_txid = job.split_txid.clone().unwrap();
+        assert!(chain.tx(&split_txid).is_some());
+        let chunk_txids = job.chunk_txid_list().unwrap();
+        assert_eq!(chunk_txids.len(), 3);
+        for (i, txid) in chunk_txids.iter().enumerate() {
+            let tx_hex = chain.tx(txid).unwrap();
+            let (index, chunk) = extract_flac_chunk_from_tx(&tx_hex).unwrap();
+            assert_eq!(index, i as u32);
+            assert_eq!(chunk, data[i * 1024..data.len().min((i + 1) * 1024)]);
+            // Funded by the split, in order
+            let input = &parse_transaction(&tx_hex).unwrap().inputs[0];
+            assert_eq!((input.prev_txid.as_str(), input.prev_vout), (split_txid.as_str(), i as u32));
         }
-        
-        let (data, consumed) = read_push_data(&script[i..])?;
-        push_data_items.push(data);
-        i += consumed;
+
+        let status = |include: Option<&str>| {
+            let query = routes::flac::FlacStatusQuery { include: include.map(str::to_string) };
+            routes::flac::get_flac_status(
+                axum::extract::State(state.clone()),
+                axum::extract::Path("flac".to_string()),
+                axum::extract::Query(query),
+            )
+        };
+        let full = status(Some("chunks")).await.unwrap().0;
+        assert_eq!(full.chunk_txids, Some(chunk_txids));
+        assert_eq!(full.split_txid, Some(split_txid));
+        let default = status(None).await.unwrap().0;
+        assert_eq!((default.chunk_txids, default.split_txid), (None, None));
     }
-    
-    if push_data_items.len() < 4 {
-        return None;
+
+    #[tokio::test]
+    async fn stored_manifest_matches_the_inscribed_one() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        let job = flac_job("flac", &data).with_track_metadata(
+            Some("Title".to_string()),
+            Some("Artist".to_string()),
+            Some("la la la".to_string()),
+        );
+        run_job(&state, &job).await;
+
+        let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let tx_hex = chain.tx(job.manifest_txid.as_ref().unwrap()).unwrap();
+        let inscribed = services::tx_parse::find_in_outputs(&tx_hex, parse_flac_manifest_output).unwrap();
+        let inscribed = serde_json::to_value(&inscribed).unwrap();
+        let stored: serde_json::Value = serde_json::from_str(job.manifest_json.as_ref().unwrap()).unwrap();
+        assert_eq!(stored, inscribed);
+        assert_eq!(stored["filename"], "song.flac");
+        assert_eq!(stored["title"], "Title");
+        assert_eq!(stored["chunk_txids"], serde_json::json!(job.chunk_txid_list().unwrap()));
+
+        // The status endpoint returns the stored copy
+        let query = routes::flac::FlacStatusQuery { include: Some("manifest".to_string()) };
+        let status = routes::flac::get_flac_status(
+            axum::extract::State(state.clone()),
+            axum::extract::Path("flac".to_string()),
+            axum::extract::Query(query),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(status.manifest, Some(inscribed));
     }
-    
-    let protocol = String::from_utf8_lossy(&push_data_items[0]);
-    if protocol != "flacstore" {
-        return None;
+
+    #[tokio::test]
+    async fn attached_cover_replaces_the_one_served_for_a_track() {
+        let chain = MockChain::default();
+        let state = chain_state(&chain).await;
+        run_job(&state, &flac_job("flac", b"fLaC uploaded without a cover")).await;
+        let upload = state.read().await.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(upload.status, JobStatus::Complete, "{}", upload.message);
+        let manifest_txid = upload.manifest_txid.clone().unwrap();
+        {
+            let state = state.read().await;
+            let mut admin_config = state.db.get_admin_config().unwrap();
+            admin_config.admin_pay_mainnet = true;
+            admin_config.mainnet_wif = Some(BsvService::generate_keypair(Network::Mainnet).0);
+            state.db.update_admin_config(&admin_config).unwrap();
+        }
+        let app = serve(
+            axum::Router::new()
+                .route("/api/flac/cover/attach", post(routes::flac::prepare_cover_attach))
+                .with_state(state.clone()),
+        )
+        .await;
+
+        let cover = [&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A][..], b"attached cover"].concat();
+        let (content_type, body) = multipart_body(&[
+            ("cover", Some("cover.png"), &cover),
+            ("manifest_txid", None, manifest_txid.as_bytes()),
+            ("owner_token", None, upload.owner_token.as_ref().unwrap().as_bytes()),
+            ("admin_pay", None, b"true"),
+        ]);
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/flac/cover/attach", app))
+            .header("content-type", content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["admin_pay"], true, "{}", body);
+
+        let before = chain.count();
+        run_next_job(&state).await;
+        let job = state.read().await.db.get_job(body["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        // The image, then the record linking it to the track
+        assert_eq!(chain.count(), before + 2);
+        let cover_txid = job.cover_txid.unwrap();
+        let inscribed = find_in_outputs(&chain.tx(&cover_txid).unwrap(), parse_image_output);
+        assert_eq!(inscribed.as_deref(), Some(cover.as_slice()));
+
+        let query = routes::flac::CoverImageQuery {
+            network: None,
+            manifest_txid: Some(manifest_txid.clone()),
+            size: routes::flac::CoverSize::Full,
+        };
+        let response = routes::flac::get_cover_image_file(
+            axum::extract::State(state.clone()),
+            axum::extract::Path(manifest_txid),
+            axum::extract::Query(query),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()["content-type"], "image/png");
+        let served = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(served.as_ref(), cover.as_slice());
     }
-    
-    let metadata_str = String::from_utf8_lossy(&push_data_items[2]);
-    let filename = if let Ok(metadata) = serde_json::from_str::<serde_json::Value>(&metadata_str) {
-        metadata["filename"].as_str().unwrap_or("audio.flac").to_string()
-    } else {
-        "audio.flac".to_string()
-    };
-    
-    let mut file_data = Vec::new();
-    for chunk in &push_data_items[3..] {
-        file_data.extend(chunk);
+
+    #[tokio::test]
+    async fn cover_that_spends_the_whole_wallet_stops_before_the_split() {
+        let cover = b"cover image bytes".to_vec();
+        let cover_cost = {
+            let bsv = BsvService::for_tests();
+            let script = bsv.create_cover_image_script(&cover);
+            bsv.fee_for_size(150 + script.len()).to_sat_i64() + bsv.data_output_satoshis.to_sat_i64()
+        };
+        let chain = MockChain::default();
+        let mut config = test_config();
+        config.bitails_api_url = chain_bitails(&chain, cover_cost, accept).await;
+        let state = test_state_with(config);
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        run_job(&state, &flac_job("flac", &data).with_cover_data(Some(cover))).await;
+
+        let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Error);
+        assert_eq!(job.error_code, Some(ErrorCode::InsufficientFunds));
+        assert_eq!(job.message_key.as_deref(), Some("insufficient_funds_after_cover"), "{}", job.message);
+        // The cover went out with no change, and nothing after it
+        assert_eq!(chain.count(), 1);
+        let cover_tx = parse_transaction(&chain.tx(job.cover_txid.as_ref().unwrap()).unwrap()).unwrap();
+        assert_eq!(cover_tx.outputs.len(), 1);
     }
-    
-    Some((file_data, filename))
-}
 
-fn parse_op_return_script(script: &[u8]) -> Option<(Vec<u8>, String)> {
-    let mut i = 0;
-    let mut push_data_items: Vec<Vec<u8>> = Vec::new();
-    
-    while i < script.len() {
-        let (data, consumed) = read_push_data(&script[i..])?;
-        push_data_items.push(data);
-        i += consumed;
+    #[tokio::test]
+    async fn split_combines_utxos_when_none_funds_it_alone() {
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        let cost = {
+            let mut bsv = BsvService::for_tests();
+            bsv.provider_tx_limits.bitails = Some(1);
+            bsv.plan_flac_upload(data.len(), Network::Mainnet).cost.to_sat_i64()
+        };
+        let upload = |utxos: Vec<i64>, max_split_inputs: usize| {
+            let data = data.clone();
+            async move {
+                let chain = MockChain::default();
+                let mut config = test_config();
+                config.bitails_api_url = chain_bitails_utxos(&chain, &utxos, accept).await;
+                let state = test_state_with(config);
+                state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+                state.write().await.bsv.max_split_inputs = max_split_inputs;
+                run_job(&state, &flac_job("flac", &data)).await;
+                let job = state.read().await.db.get_job("flac").unwrap().unwrap();
+                (chain, job)
+            }
+        };
+
+        // Five UTXOs of a third of the cost each: the largest few fund the split
+        let (chain, job) = upload(vec![cost / 3; 5], 50).await;
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let split = parse_transaction(&chain.tx(job.split_txid.as_ref().unwrap()).unwrap()).unwrap();
+        assert!((2..5).contains(&split.inputs.len()), "{} inputs", split.inputs.len());
+
+        // Together they hold enough, but only across more UTXOs than allowed
+        let (chain, job) = upload(vec![cost / 3; 5], 2).await;
+        assert_eq!(job.status, JobStatus::Error);
+        assert_eq!(job.error_code, Some(ErrorCode::InsufficientFunds));
+        assert_eq!(job.message, MessageKey::SplitTooManyInputs.with("max", 2).with("count", 5).english());
+        assert_eq!(chain.count(), 0);
+
+        // Even all of them together fall short
+        let (chain, job) = upload(vec![cost / 4; 3], 50).await;
+        assert_eq!(job.status, JobStatus::Error);
+        assert_eq!(job.error_code, Some(ErrorCode::InsufficientFunds));
+        assert_eq!(job.message_key.as_deref(), Some("insufficient_funds_across_utxos"), "{}", job.message);
+        let available = Amount::try_from(cost / 4 * 3).unwrap();
+        assert!(job.message.starts_with(&format!("Insufficient funds: 3 UTXOs hold {} in total", available)), "{}", job.message);
+        assert_eq!(chain.count(), 0);
     }
-    
-    if push_data_items.len() < 4 {
-        return None;
+
+    /// Chunks of a testnet track, unique to the calling test, on the
+    /// WhatsOnChain stand-in
+    fn testnet_chunks(count: usize) -> (Vec<Vec<u8>>, Vec<String>) {
+        let chunks: Vec<Vec<u8>> = (0..count).map(|_| uuid::Uuid::new_v4().as_bytes().to_vec()).collect();
+        let txids = add_chunks(&whatsonchain_chain(), &chunks.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        (chunks, txids)
     }
-    
-    let filename = String::from_utf8_lossy(&push_data_items[2]).to_string();
-    
-    let mut file_data = Vec::new();
-    for chunk in &push_data_items[3..] {
-        file_data.extend(chunk);
+
+    #[tokio::test]
+    async fn testnet_download_fetches_chunks_in_bulk() {
+        let (chunks, chunk_txids) = testnet_chunks(40);
+        let filename = format!("{}.flac", uuid::Uuid::new_v4().simple());
+        let manifest_txid = add_named_manifest(&whatsonchain_chain(), &filename, 40 * 16, &chunk_txids, &[]);
+        let state = test_state();
+        let job = Job::new_flac_download("download".to_string(), manifest_txid).with_network(Network::Testnet);
+        run_job(&state, &job).await;
+
+        let job = state.read().await.db.get_job("download").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let saved = std::path::Path::new(routes::download::DOWNLOADS_DIR).join(&filename);
+        let data = std::fs::read(&saved);
+        let _ = std::fs::remove_file(&saved);
+        assert_eq!(data.unwrap(), chunks.concat());
+        // Two requests of 20 rather than 40 of one
+        assert_eq!(whatsonchain_fetches(&chunk_txids), [20, 20]);
     }
-    
-    Some((file_data, filename))
-}
 
-fn read_push_data(script: &[u8]) -> Option<(Vec<u8>, usize)> {
-    if script.is_empty() {
-        return None;
+    #[tokio::test]
+    async fn chunk_missing_from_a_bulk_fetch_is_fetched_alone() {
+        let (_, mut chunk_txids) = testnet_chunks(2);
+        // Never broadcast, so WhatsOnChain knows nothing of it
+        let missing = BsvService::txid(&BsvService::for_tests().test_transaction(&[(b"x".to_vec(), Amount::from_sat_const(1))])).unwrap();
+        chunk_txids.insert(1, missing.clone());
+        let manifest_txid = add_manifest(&whatsonchain_chain(), 48, &chunk_txids, &[]);
+        let state = test_state();
+        let job = Job::new_flac_download("download".to_string(), manifest_txid).with_network(Network::Testnet);
+        run_job(&state, &job).await;
+
+        let job = state.read().await.db.get_job("download").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Error);
+        assert_eq!(job.error_code, Some(ErrorCode::ChunkFetchFailed));
+        assert!(job.message.starts_with("Failed to fetch chunk 2:"), "{}", job.message);
+        // Asked for in the batch, then on its own
+        assert_eq!(whatsonchain_fetches(std::slice::from_ref(&missing)), [3, 1]);
     }
-    
-    let opcode = script[0];
-    
-    if opcode <= 0x4b {
-        let len = opcode as usize;
-        if script.len() < 1 + len {
-            return None;
-        }
-        Some((script[1..1 + len].to_vec(), 1 + len))
-    } else if opcode == 0x4c {
-        if script.len() < 2 {
-            return None;
-        }
-        let len = script[1] as usize;
-        if script.len() < 2 + len {
-            return None;
-        }
-        Some((script[2..2 + len].to_vec(), 2 + len))
-    } else if opcode == 0x4d {
-        if script.len() < 3 {
-            return None;
-        }
-        let len = u16::from_le_bytes([script[1], script[2]]) as usize;
-        if script.len() < 3 + len {
-            return None;
-        }
-        Some((script[3..3 + len].to_vec(), 3 + len))
-    } else if opcode == 0x4e {
-        if script.len() < 5 {
-            return None;
-        }
-        let len = u32::from_le_bytes([script[1], script[2], script[3], script[4]]) as usize;
-        if script.len() < 5 + len {
-            return None;
+
+    #[tokio::test]
+    async fn shuffled_manifest_reassembles_by_inscribed_index() {
+        let chain = MockChain::default();
+        let chunks: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 50]).collect();
+        let txids = add_chunks(&chain, &chunks.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        let shuffled = [2, 0, 3, 1].map(|i| txids[i].clone());
+        let filename = format!("{}.flac", uuid::Uuid::new_v4().simple());
+        let shuffled_txid = add_named_manifest(&chain, &filename, 200, &shuffled, &[]);
+        // Index 1 twice and index 2 never
+        let repeated = [0, 1, 1, 3].map(|i| txids[i].clone());
+        let repeated_txid = add_manifest(&chain, 200, &repeated, &[]);
+        let state = chain_state(&chain).await;
+
+        run_job(&state, &Job::new_flac_download("shuffled".to_string(), shuffled_txid)).await;
+        let job = state.read().await.db.get_job("shuffled").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        let saved = std::path::Path::new(routes::download::DOWNLOADS_DIR).join(&filename);
+        let data = std::fs::read(&saved);
+        let _ = std::fs::remove_file(&saved);
+        assert_eq!(data.unwrap(), chunks.concat());
+
+        run_job(&state, &Job::new_flac_download("repeated".to_string(), repeated_txid)).await;
+        let job = state.read().await.db.get_job("repeated").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Error);
+        assert_eq!(job.error_code, Some(ErrorCode::ChunkIndexMismatch));
+        let expected = MessageKey::ChunkIndexMismatch
+            .with("last", 3)
+            .with("missing", "2")
+            .with("duplicated", "1")
+            .with("unexpected", "");
+        assert_eq!(job.message, expected.english());
+    }
+
+    #[tokio::test]
+    async fn cancelling_mid_download_stops_fetching_chunks() {
+        let chain = MockChain::default();
+        let chunks: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 100]).collect();
+        let chunk_txids = add_chunks(&chain, &chunks.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        let manifest_txid = add_manifest(&chain, 1000, &chunk_txids, &[]);
+        let state = chain_state(&chain).await;
+        let job = Job::new_flac_download("download".to_string(), manifest_txid.clone());
+        let owner_token = job.owner_token.clone().unwrap();
+        let download = tokio::spawn({
+            let state = state.clone();
+            async move { run_job(&state, &job).await }
+        });
+
+        // Cancel once a few chunks are in
+        let downloading = |state: &AppState| -> Vec<serde_json::Value> {
+            let events = state.db.get_job_events("download").unwrap();
+            events
+                .into_iter()
+                .filter(|event| event.message_key.as_deref() == Some("downloading_chunk"))
+                .map(|event| event.message_params.unwrap()["i"].clone())
+                .collect()
+        };
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        while downloading(&*state.read().await).len() < 3 {
+            assert!(tokio::time::Instant::now() < deadline, "the download didn't start");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
         }
-        Some((script[5..5 + len].to_vec(), 5 + len))
-    } else {
-        None
+        let response = routes::flac::cancel_flac_download(
+            axum::extract::State(state.clone()),
+            axum::extract::Path("download".to_string()),
+            axum::Json(routes::jobs::CancelJobRequest { owner_token }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0.status, "processing");
+        download.await.unwrap();
+
+        let state = state.read().await;
+        let job = state.db.get_job("download").unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Cancelled, "{}", job.message);
+        assert_eq!(job.message_key.as_deref(), Some("download_cancelled"));
+        let params: serde_json::Value = serde_json::from_str(job.message_params.as_deref().unwrap()).unwrap();
+        let fetched = params["i"].as_u64().unwrap();
+        assert!((3..10).contains(&fetched), "{}", params);
+        // No chunk was started after the one in flight when it was cancelled
+        assert_eq!(downloading(&state).last().unwrap(), fetched);
+        assert_eq!(job.download_link, None);
     }
-}
 
-fn read_varint(data: &[u8]) -> Option<(u64, usize)> {
-    if data.is_empty() {
-        return None;
+    #[tokio::test]
+    async fn imported_uploads_are_listed_in_the_jobs() {
+        let chain = MockChain::default();
+        let chunk_txids = add_chunks(&chain, &[b"fLaC first half ", b"second half"]);
+        let manifest_txid = add_manifest(&chain, 27, &chunk_txids, &[]);
+        let single_txid = chain.add(&flac_store_tx("single.flac", b"fLaC single"));
+        let state = chain_state(&chain).await;
+        let app = serve(
+            axum::Router::new()
+                .route("/api/admin/import_txid", post(routes::admin::import_txid))
+                .route("/api/admin/import_txids", post(routes::admin::import_txids))
+                .route("/api/jobs", get(routes::dashboard::get_jobs))
+                .with_state(state.clone()),
+        )
+        .await;
+        let client = reqwest::Client::new();
+        let key = routes::admin::get_admin_key();
+        let import = |txid: String| {
+            let request = client
+                .post(format!("{}/api/admin/import_txid", app))
+                .json(&serde_json::json!({ "txid": txid, "key": key }));
+            async move { request.send().await.unwrap().json::<serde_json::Value>().await.unwrap() }
+        };
+
+        let first = import(manifest_txid.clone()).await;
+        assert_eq!(first["already_imported"], false, "{}", first);
+        // Importing again finds the same job instead of adding another
+        let again = import(manifest_txid.to_uppercase()).await;
+        assert_eq!(again["already_imported"], true);
+        assert_eq!(again["job_id"], first["job_id"]);
+
+        let jobs: Vec<serde_json::Value> =
+            client.get(format!("{}/api/jobs", app)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0]["id"], first["job_id"]);
+        assert_eq!(jobs[0]["job_type"], "flac_upload");
+        assert_eq!(jobs[0]["status"], "complete");
+        assert_eq!(jobs[0]["manifest_txid"], manifest_txid.as_str());
+        assert_eq!(jobs[0]["filename"], "song.flac");
+        assert_eq!(jobs[0]["file_size"], 27);
+        let job = state.read().await.db.get_job(first["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(job.chunk_txid_list(), Some(chunk_txids));
+
+        // The bulk import skips the track already in the catalog
+        let batch: serde_json::Value = client
+            .post(format!("{}/api/admin/import_txids", app))
+            .json(&serde_json::json!({ "txids": [manifest_txid, single_txid], "key": key }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        run_next_job(&state).await;
+        let batch = state.read().await.db.get_job(batch["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(batch.status, JobStatus::Complete);
+        let summary = MessageKey::ImportComplete.with("imported", 1).with("skipped", 1).with("failed", 0);
+        assert_eq!(batch.message, summary.english());
+
+        let tracks = state.read().await.db.get_all_jobs(Some(JobType::FlacUpload)).unwrap();
+        let single = tracks.iter().find(|job| job.manifest_txid.as_deref() == Some(single_txid.as_str())).unwrap();
+        assert_eq!(single.filename.as_deref(), Some("single.flac"));
+        assert_eq!(tracks.len(), 2);
     }
-    
-    let first = data[0];
-    if first < 0xfd {
-        Some((first as u64, 1))
-    } else if first == 0xfd {
-        if data.len() < 3 {
-            return None;
-        }
-        let value = u16::from_le_bytes([data[1], data[2]]) as u64;
-        Some((value, 3))
-    } else if first == 0xfe {
-        if data.len() < 5 {
-            return None;
-        }
-        let value = u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as u64;
-        Some((value, 5))
-    } else {
-        if data.len() < 9 {
-            return None;
+
+    #[tokio::test]
+    async fn watchdog_fails_jobs_that_stopped_updating() {
+        let mut config = test_config();
+        config.job_stall_timeout_minutes = 10;
+        let state = test_state_with(config);
+        let state = state.read().await;
+
+        let long_ago = chrono::Utc::now() - chrono::Duration::minutes(30);
+        let processing = |id: &str, updated_at| {
+            let mut job = upload_job(id).with_status(JobStatus::Processing, MessageKey::Starting);
+            job.updated_at = updated_at;
+            job
+        };
+        state.db.insert_job(&processing("stuck", long_ago)).unwrap();
+        state.db.insert_job(&processing("busy", chrono::Utc::now())).unwrap();
+        state.db.insert_job(&processing("queued", long_ago)).unwrap();
+        state.scheduler.enqueue(QueuedJob {
+            job_id: "queued".to_string(),
+            job_type: JobType::Upload,
+            address: String::new(),
+            network: Network::Mainnet,
+            admin_pay: false,
+            file_size: 4,
+            paid_satoshis: None,
+        });
+        let batch = processing("batch", chrono::Utc::now());
+        state.db.insert_job(&batch).unwrap();
+        state.db.insert_job(&processing("track", long_ago).with_parent(&batch)).unwrap();
+
+        flag_stalled_jobs(&state);
+
+        let job = |id: &str| state.db.get_job(id).unwrap().unwrap();
+        assert_eq!(job("stuck").status, JobStatus::Error);
+        assert_eq!(job("stuck").error_code, Some(ErrorCode::Stalled));
+        for id in ["busy", "queued", "batch", "track"] {
+            assert_eq!(job(id).status, JobStatus::Processing, "{}", id);
         }
-        let value = u64::from_le_bytes([
-            data[1], data[2], data[3], data[4], data[5], data[6], data[7], data[8],
-        ]);
-        Some((value, 9))
     }
 }
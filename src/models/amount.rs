@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const SATOSHIS_PER_BSV: u64 = 100_000_000;
+
+/// No amount can exceed the 21 million coin supply
+pub const MAX_SATOSHIS: u64 = 21_000_000 * SATOSHIS_PER_BSV;
+
+/// A satoshi amount. Unsigned and capped at `MAX_SATOSHIS`, with checked
+/// arithmetic, so a negative or absurd output value is an error instead of
+/// a transaction that only fails at broadcast.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u64", into = "u64")]
+pub struct Amount(u64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    /// A signed value below zero
+    Negative(i64),
+    /// More than the coin supply
+    TooLarge(u64),
+    /// A subtraction that would go below zero
+    Insufficient { available: u64, required: u64 },
+    /// A BSV decimal string that can't be parsed
+    InvalidDecimal(String),
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmountError::Negative(v) => write!(f, "Amount is negative: {}", v),
+            AmountError::TooLarge(v) => write!(f, "Amount exceeds the coin supply: {}", v),
+            AmountError::Insufficient { available, required } => {
+                write!(f, "Insufficient amount: have {} sats, need {} sats", available, required)
+            }
+            AmountError::InvalidDecimal(s) => write!(f, "Invalid BSV amount: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+impl From<AmountError> for String {
+    fn from(e: AmountError) -> Self {
+        e.to_string()
+    }
+}
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(MAX_SATOSHIS);
+
+    pub fn from_sat(satoshis: u64) -> Result<Self, AmountError> {
+        if satoshis > MAX_SATOSHIS {
+            return Err(AmountError::TooLarge(satoshis));
+        }
+        Ok(Amount(satoshis))
+    }
+
+    /// For constants; fails to compile above `MAX_SATOSHIS`
+    pub const fn from_sat_const(satoshis: u64) -> Self {
+        assert!(satoshis <= MAX_SATOSHIS);
+        Amount(satoshis)
+    }
+
+    pub fn to_sat(self) -> u64 {
+        self.0
+    }
+
+    /// The value as i64, for the database and JSON fields that store signed amounts
+    pub fn to_sat_i64(self) -> i64 {
+        // Always fits: MAX_SATOSHIS is far below i64::MAX
+        self.0 as i64
+    }
+
+    pub fn checked_add(self, other: Amount) -> Result<Amount, AmountError> {
+        Amount::from_sat(self.0.saturating_add(other.0))
+    }
+
+    /// Sum clamped to `MAX_SATOSHIS`, for estimates that get compared against a limit anyway
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0).min(MAX_SATOSHIS))
+    }
+
+    /// Product clamped to `MAX_SATOSHIS`
+    pub fn saturating_mul(self, n: u64) -> Amount {
+        Amount(self.0.saturating_mul(n).min(MAX_SATOSHIS))
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Result<Amount, AmountError> {
+        self.0
+            .checked_sub(other.0)
+            .map(Amount)
+            .ok_or(AmountError::Insufficient { available: self.0, required: other.0 })
+    }
+
+    /// Total of several amounts
+    pub fn sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Result<Amount, AmountError> {
+        amounts.into_iter().try_fold(Amount::ZERO, Amount::checked_add)
+    }
+
+    /// Total of signed satoshi values as reported by chain APIs
+    pub fn sum_sat<I: IntoIterator<Item = i64>>(values: I) -> Result<Amount, AmountError> {
+        values
+            .into_iter()
+            .try_fold(Amount::ZERO, |total, v| total.checked_add(Amount::try_from(v)?))
+    }
+
+    /// Decimal BSV with all 8 places, e.g. "0.00012345"
+    pub fn to_bsv_string(self) -> String {
+        format!("{}.{:08}", self.0 / SATOSHIS_PER_BSV, self.0 % SATOSHIS_PER_BSV)
+    }
+
+    /// Parse a decimal BSV string with up to 8 places
+    pub fn from_bsv_str(s: &str) -> Result<Self, AmountError> {
+        let invalid = || AmountError::InvalidDecimal(s.to_string());
+        let (whole, fraction) = s.trim().split_once('.').unwrap_or((s.trim(), ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if fraction.len() > 8 || !(whole.chars().chain(fraction.chars())).all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let whole: u64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| invalid())? };
+        let fraction: u64 = format!("{:0<8}", fraction).parse().map_err(|_| invalid())?;
+        let satoshis = whole
+            .checked_mul(SATOSHIS_PER_BSV)
+            .and_then(|w| w.checked_add(fraction))
+            .ok_or_else(invalid)?;
+        Amount::from_sat(satoshis)
+    }
+}
+
+impl TryFrom<u64> for Amount {
+    type Error = AmountError;
+
+    fn try_from(satoshis: u64) -> Result<Self, Self::Error> {
+        Amount::from_sat(satoshis)
+    }
+}
+
+impl TryFrom<i64> for Amount {
+    type Error = AmountError;
+
+    fn try_from(satoshis: i64) -> Result<Self, Self::Error> {
+        if satoshis < 0 {
+            return Err(AmountError::Negative(satoshis));
+        }
+        Amount::from_sat(satoshis as u64)
+    }
+}
+
+impl From<Amount> for u64 {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl From<Amount> for serde_json::Value {
+    fn from(amount: Amount) -> Self {
+        serde_json::Value::from(amount.0)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bsv_strings_parse_to_exact_satoshis() {
+        assert_eq!(Amount::from_bsv_str("0.00012345"), Ok(Amount(12_345)));
+        assert_eq!(Amount::from_bsv_str("1.1"), Ok(Amount(110_000_000)));
+        assert_eq!(Amount::from_bsv_str(".5"), Ok(Amount(50_000_000)));
+        assert_eq!(Amount::from_bsv_str("21000000"), Ok(Amount::MAX));
+        for invalid in ["", ".", "-1", "0.000000001", "1e3", "21000000.00000001"] {
+            assert!(Amount::from_bsv_str(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(Amount(12_345).to_bsv_string(), "0.00012345");
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn amount() -> impl Strategy<Value = Amount> {
+            prop_oneof![0..1000u64, 0..=MAX_SATOSHIS].prop_map(Amount)
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(256))]
+
+            #[test]
+            fn bsv_strings_round_trip(amount in amount()) {
+                let text = amount.to_bsv_string();
+                prop_assert_eq!(Amount::from_bsv_str(&text), Ok(amount));
+                // Trailing zeros are optional
+                let trimmed = text.trim_end_matches('0').trim_end_matches('.');
+                prop_assert_eq!(Amount::from_bsv_str(trimmed), Ok(amount));
+            }
+
+            #[test]
+            fn subtraction_never_goes_negative(a in amount(), b in amount()) {
+                match a.checked_sub(b) {
+                    Ok(difference) => {
+                        prop_assert!(a >= b);
+                        prop_assert_eq!(difference.checked_add(b), Ok(a));
+                    }
+                    Err(e) => prop_assert_eq!(e, AmountError::Insufficient { available: a.0, required: b.0 }),
+                }
+            }
+
+            #[test]
+            fn sums_stay_within_the_coin_supply(a in amount(), b in amount(), n in 0..1000u64) {
+                match a.checked_add(b) {
+                    Ok(sum) => prop_assert_eq!(sum.to_sat(), a.0 + b.0),
+                    Err(_) => prop_assert!(a.0 + b.0 > MAX_SATOSHIS),
+                }
+                prop_assert!(a.saturating_add(b) <= Amount::MAX);
+                prop_assert!(a.saturating_mul(n) <= Amount::MAX);
+            }
+
+            #[test]
+            fn negative_values_are_refused(satoshis in any::<i64>()) {
+                let amount = Amount::try_from(satoshis);
+                if satoshis < 0 {
+                    prop_assert_eq!(amount, Err(AmountError::Negative(satoshis)));
+                } else {
+                    prop_assert_eq!(amount.is_ok(), satoshis as u64 <= MAX_SATOSHIS);
+                }
+            }
+        }
+    }
+}
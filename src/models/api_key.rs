@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// An API key of a programmatic uploader. The key itself is only shown
+/// when it is created; the database keeps its SHA-256.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub label: String,
+    /// Bytes the key may upload per calendar month (UTC); None for no limit
+    pub monthly_quota_bytes: Option<i64>,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    /// Bytes of this month's paid uploads attributed to the key
+    pub used_bytes_this_month: i64,
+}
+
+impl ApiKey {
+    /// Whether an upload of `bytes` more stays within the monthly quota
+    pub fn has_quota_for(&self, bytes: i64) -> bool {
+        self.monthly_quota_bytes
+            .is_none_or(|quota| self.used_bytes_this_month.saturating_add(bytes) <= quota)
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// Longest provider error body kept with an attempt
+const ERROR_DETAIL_LIMIT: usize = 500;
+
+/// Provider error texts that mean a transaction is over its size policy
+const SIZE_REJECTION_PATTERNS: [&str; 6] =
+    ["tx-size", "txn-size", "too large", "too big", "exceeds maximum size", "max tx size"];
+
+/// One provider's answer to a broadcast
+#[derive(Debug, Clone, Serialize)]
+pub struct BroadcastAttempt {
+    /// "bitails" or "whatsonchain"
+    pub provider: String,
+    /// HTTP status, unset when the request never got a response
+    pub http_status: Option<u16>,
+    /// Txid the provider accepted the transaction as
+    pub accepted_txid: Option<String>,
+    /// The provider's error, truncated
+    pub error: Option<String>,
+}
+
+impl BroadcastAttempt {
+    pub fn accepted(provider: &str, http_status: u16, txid: String) -> Self {
+        BroadcastAttempt {
+            provider: provider.to_string(),
+            http_status: Some(http_status),
+            accepted_txid: Some(txid),
+            error: None,
+        }
+    }
+
+    pub fn rejected(provider: &str, http_status: Option<u16>, error: &str) -> Self {
+        BroadcastAttempt {
+            provider: provider.to_string(),
+            http_status,
+            accepted_txid: None,
+            error: Some(error.trim().chars().take(ERROR_DETAIL_LIMIT).collect()),
+        }
+    }
+
+    /// The provider itself failed: no response, a server error or rate
+    /// limiting. A provider that answered with a rejection is working.
+    pub fn provider_failed(&self) -> bool {
+        self.accepted_txid.is_none() && self.http_status.is_none_or(|status| status >= 500 || status == 429)
+    }
+
+    /// The provider turned the transaction down for its size
+    pub fn rejected_for_size(&self) -> bool {
+        if self.accepted_txid.is_some() {
+            return false;
+        }
+        let error = self.error.as_deref().unwrap_or("").to_lowercase();
+        self.http_status == Some(413) || SIZE_REJECTION_PATTERNS.iter().any(|pattern| error.contains(pattern))
+    }
+}
+
+impl fmt::Display for BroadcastAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.provider)?;
+        if let Some(status) = self.http_status {
+            write!(f, " (HTTP {})", status)?;
+        }
+        match (&self.accepted_txid, &self.error) {
+            (Some(txid), _) => write!(f, ": accepted as {}", txid),
+            (None, Some(error)) => write!(f, ": {}", error),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Every provider rejected a transaction
+#[derive(Debug, Clone)]
+pub struct BroadcastError {
+    /// In the order they were tried
+    pub attempts: Vec<BroadcastAttempt>,
+}
+
+impl BroadcastError {
+    /// Attempts as stored in job message params
+    pub fn attempts_json(&self) -> Value {
+        serde_json::to_value(&self.attempts).unwrap_or(Value::Null)
+    }
+
+    /// Some provider rejected the transaction for its size, so sending it
+    /// again can't succeed
+    pub fn rejected_for_size(&self) -> bool {
+        self.attempts.iter().any(BroadcastAttempt::rejected_for_size)
+    }
+}
+
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let attempts: Vec<String> = self.attempts.iter().map(ToString::to_string).collect();
+        f.write_str(&attempts.join("; "))
+    }
+}
+
+/// Txid of a broadcast if its last attempt was accepted, else every attempt
+pub fn broadcast_outcome(attempts: Vec<BroadcastAttempt>) -> Result<String, BroadcastError> {
+    match attempts.last().and_then(|attempt| attempt.accepted_txid.clone()) {
+        Some(txid) => Ok(txid),
+        None => Err(BroadcastError { attempts }),
+    }
+}
+
+/// A recorded broadcast attempt
+#[derive(Debug, Serialize)]
+pub struct BroadcastAttemptRecord {
+    pub job_id: Option<String>,
+    pub txid: String,
+    pub network: String,
+    #[serde(flatten)]
+    pub attempt: BroadcastAttempt,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+
+/// Stable, machine-readable error codes stored on failed jobs and returned
+/// by the API so clients don't have to string-match error messages.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The request was malformed or missing required fields
+    InvalidRequest,
+    /// The quoted cost exceeds the configured upload limit
+    UploadTooExpensive,
+    /// The job has no file data to upload
+    NoFileData,
+    /// Fetching UTXOs for the payment address failed
+    UtxoFetchFailed,
+    /// The payment address has no spendable UTXOs
+    NoUtxos,
+    /// The available inputs don't cover outputs and fees
+    InsufficientFunds,
+    /// The funding transaction doesn't have the required confirmations yet
+    FundingUnconfirmed,
+    /// The funding wallet is already paying for another upload
+    FundingWalletBusy,
+    /// Building or signing a transaction failed
+    TxBuildFailed,
+    /// Every broadcast attempt failed
+    BroadcastFailed,
+    /// Fetching a transaction from the chain failed
+    TxFetchFailed,
+    /// Fetching one chunk of a multi-chunk file failed
+    ChunkFetchFailed,
+    /// The transaction contains no recognized data output
+    NoDataFound,
+    /// The reassembled file doesn't match the size declared in its manifest
+    SizeMismatch,
+    /// The chunks' inscribed indices don't cover the manifest exactly once each
+    ChunkIndexMismatch,
+    /// A chunk's data doesn't match the hash its manifest recorded for it
+    ChunkHashMismatch,
+    /// Writing the downloaded file to disk failed
+    SaveFailed,
+    /// The payment window elapsed before funds arrived
+    PaymentExpired,
+    /// The WIF private key can't be decoded or is for another network
+    InvalidWif,
+    /// The job's stored payment key doesn't control its payment address
+    PaymentKeyMismatch,
+    /// The address can't be decoded or is for another network
+    InvalidAddress,
+    /// The requested job does not exist
+    JobNotFound,
+    /// The admin key is missing or wrong
+    Unauthorized,
+    /// The owner token is missing or doesn't match the job
+    Forbidden,
+    /// The Authorization header names an unknown or revoked API key
+    InvalidApiKey,
+    /// The upload would take an API key past its monthly byte quota
+    QuotaExceeded,
+    /// The job already finished and can't be changed
+    JobFinished,
+    /// Too many jobs are pending or queued to accept another right now
+    ServerBusy,
+    /// A database operation failed
+    DatabaseError,
+    /// The job stopped reporting progress and was timed out
+    Stalled,
+    /// The job ran longer than MAX_JOB_DURATION_MINUTES and was stopped
+    TimedOut,
+    /// The processing task panicked
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::UploadTooExpensive => "UPLOAD_TOO_EXPENSIVE",
+            ErrorCode::NoFileData => "NO_FILE_DATA",
+            ErrorCode::UtxoFetchFailed => "UTXO_FETCH_FAILED",
+            ErrorCode::NoUtxos => "NO_UTXOS",
+            ErrorCode::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            ErrorCode::FundingUnconfirmed => "FUNDING_UNCONFIRMED",
+            ErrorCode::FundingWalletBusy => "FUNDING_WALLET_BUSY",
+            ErrorCode::TxBuildFailed => "TX_BUILD_FAILED",
+            ErrorCode::BroadcastFailed => "BROADCAST_FAILED",
+            ErrorCode::TxFetchFailed => "TX_FETCH_FAILED",
+            ErrorCode::ChunkFetchFailed => "CHUNK_FETCH_FAILED",
+            ErrorCode::NoDataFound => "NO_DATA_FOUND",
+            ErrorCode::SizeMismatch => "SIZE_MISMATCH",
+            ErrorCode::ChunkIndexMismatch => "CHUNK_INDEX_MISMATCH",
+            ErrorCode::ChunkHashMismatch => "CHUNK_HASH_MISMATCH",
+            ErrorCode::SaveFailed => "SAVE_FAILED",
+            ErrorCode::PaymentExpired => "PAYMENT_EXPIRED",
+            ErrorCode::InvalidWif => "INVALID_WIF",
+            ErrorCode::PaymentKeyMismatch => "PAYMENT_KEY_MISMATCH",
+            ErrorCode::InvalidAddress => "INVALID_ADDRESS",
+            ErrorCode::JobNotFound => "JOB_NOT_FOUND",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::InvalidApiKey => "INVALID_API_KEY",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::JobFinished => "JOB_FINISHED",
+            ErrorCode::ServerBusy => "SERVER_BUSY",
+            ErrorCode::DatabaseError => "DATABASE_ERROR",
+            ErrorCode::Stalled => "STALLED",
+            ErrorCode::TimedOut => "TIMED_OUT",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "INVALID_REQUEST" => Some(ErrorCode::InvalidRequest),
+            "UPLOAD_TOO_EXPENSIVE" => Some(ErrorCode::UploadTooExpensive),
+            "NO_FILE_DATA" => Some(ErrorCode::NoFileData),
+            "UTXO_FETCH_FAILED" => Some(ErrorCode::UtxoFetchFailed),
+            "NO_UTXOS" => Some(ErrorCode::NoUtxos),
+            "INSUFFICIENT_FUNDS" => Some(ErrorCode::InsufficientFunds),
+            "FUNDING_UNCONFIRMED" => Some(ErrorCode::FundingUnconfirmed),
+            "FUNDING_WALLET_BUSY" => Some(ErrorCode::FundingWalletBusy),
+            "TX_BUILD_FAILED" => Some(ErrorCode::TxBuildFailed),
+            "BROADCAST_FAILED" => Some(ErrorCode::BroadcastFailed),
+            "TX_FETCH_FAILED" => Some(ErrorCode::TxFetchFailed),
+            "CHUNK_FETCH_FAILED" => Some(ErrorCode::ChunkFetchFailed),
+            "NO_DATA_FOUND" => Some(ErrorCode::NoDataFound),
+            "SIZE_MISMATCH" => Some(ErrorCode::SizeMismatch),
+            "CHUNK_INDEX_MISMATCH" => Some(ErrorCode::ChunkIndexMismatch),
+            "CHUNK_HASH_MISMATCH" => Some(ErrorCode::ChunkHashMismatch),
+            "SAVE_FAILED" => Some(ErrorCode::SaveFailed),
+            "PAYMENT_EXPIRED" => Some(ErrorCode::PaymentExpired),
+            "INVALID_WIF" => Some(ErrorCode::InvalidWif),
+            "PAYMENT_KEY_MISMATCH" => Some(ErrorCode::PaymentKeyMismatch),
+            "INVALID_ADDRESS" => Some(ErrorCode::InvalidAddress),
+            "JOB_NOT_FOUND" => Some(ErrorCode::JobNotFound),
+            "UNAUTHORIZED" => Some(ErrorCode::Unauthorized),
+            "FORBIDDEN" => Some(ErrorCode::Forbidden),
+            "INVALID_API_KEY" => Some(ErrorCode::InvalidApiKey),
+            "QUOTA_EXCEEDED" => Some(ErrorCode::QuotaExceeded),
+            "JOB_FINISHED" => Some(ErrorCode::JobFinished),
+            "SERVER_BUSY" => Some(ErrorCode::ServerBusy),
+            "DATABASE_ERROR" => Some(ErrorCode::DatabaseError),
+            "STALLED" => Some(ErrorCode::Stalled),
+            "TIMED_OUT" => Some(ErrorCode::TimedOut),
+            "INTERNAL_ERROR" => Some(ErrorCode::InternalError),
+            _ => None,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::models::{ErrorCode, MessageKey, Network, StatusMessage};
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum JobType {
@@ -8,6 +10,10 @@ pub enum JobType {
     Download,
     FlacUpload,
     FlacDownload,
+    FlacBatchDownload,
+    Send,
+    Import,
+    CoverAttach,
 }
 
 impl JobType {
@@ -17,6 +23,10 @@ impl JobType {
             JobType::Download => "download",
             JobType::FlacUpload => "flac_upload",
             JobType::FlacDownload => "flac_download",
+            JobType::FlacBatchDownload => "flac_batch_download",
+            JobType::Send => "send",
+            JobType::Import => "import",
+            JobType::CoverAttach => "cover_attach",
         }
     }
 
@@ -26,6 +36,10 @@ impl JobType {
             "download" => Some(JobType::Download),
             "flac_upload" => Some(JobType::FlacUpload),
             "flac_download" => Some(JobType::FlacDownload),
+            "flac_batch_download" => Some(JobType::FlacBatchDownload),
+            "send" => Some(JobType::Send),
+            "import" => Some(JobType::Import),
+            "cover_attach" => Some(JobType::CoverAttach),
             _ => None,
         }
     }
@@ -38,6 +52,7 @@ pub enum JobStatus {
     Processing,
     Complete,
     Error,
+    Cancelled,
 }
 
 impl JobStatus {
@@ -47,6 +62,7 @@ impl JobStatus {
             JobStatus::Processing => "processing",
             JobStatus::Complete => "complete",
             JobStatus::Error => "error",
+            JobStatus::Cancelled => "cancelled",
         }
     }
 
@@ -56,6 +72,7 @@ impl JobStatus {
             "processing" => Some(JobStatus::Processing),
             "complete" => Some(JobStatus::Complete),
             "error" => Some(JobStatus::Error),
+            "cancelled" => Some(JobStatus::Cancelled),
             _ => None,
         }
     }
@@ -75,6 +92,9 @@ pub struct Job {
     pub manifest_txid: Option<String>,
     pub download_link: Option<String>,
     pub message: String,
+    // Key and JSON params of the message, for localized frontends
+    pub message_key: Option<String>,
+    pub message_params: Option<String>,
     pub progress: f64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -84,11 +104,52 @@ pub struct Job {
     pub cover_txid: Option<String>,
     pub cover_data: Option<Vec<u8>>,
     pub lyrics: Option<String>,
-    // Network (mainnet or testnet)
-    pub network: Option<String>,
+    pub network: Option<Network>,
+    // Machine-readable code set when the job fails
+    pub error_code: Option<ErrorCode>,
+    // Transfer progress in bytes and the throughput-based ETA
+    pub bytes_done: Option<i64>,
+    pub bytes_total: Option<i64>,
+    pub eta_seconds: Option<i64>,
+    // Batch job this job belongs to, if any
+    pub parent_id: Option<String>,
+    // Secret returned to the creator; required to cancel the job
+    pub owner_token: Option<String>,
+    // Wallet sends: recipient, amount and fee (the sender is payment_address)
+    pub to_address: Option<String>,
+    pub amount_satoshis: Option<i64>,
+    pub fee_satoshis: Option<i64>,
+    // First payment seen on payment_address and who sent it (support lookups only)
+    pub funding_txid: Option<String>,
+    pub sender_address: Option<String>,
+    // When the payment watcher saw the payment, and how much it was
+    pub payment_received_at: Option<DateTime<Utc>>,
+    pub payment_received_satoshis: Option<i64>,
+    // FLAC uploads: the manifest as parsed back from its script, so it can be
+    // shown without fetching the manifest transaction
+    pub manifest_json: Option<String>,
+    // Downloads: MIME type of the saved file, served as its Content-Type
+    pub mime_type: Option<String>,
+    // FLAC uploads: extra output paid to the creator in the manifest transaction
+    pub royalty_address: Option<String>,
+    pub royalty_satoshis: Option<i64>,
+    // Chunk txids of a multi-chunk upload or import, comma-separated in manifest order
+    pub chunk_txids: Option<String>,
+    // UTXO split transactions that funded a multi-chunk upload, comma-separated
+    // in broadcast order (more than one when the split was batched)
+    pub split_txid: Option<String>,
+    // HD index the payment key is derived at; payment_wif is unset for these jobs
+    pub derivation_index: Option<i64>,
+    // Uploads: SHA-256 of the file and the IP that prepared it, to spot double submits
+    pub content_sha256: Option<String>,
+    pub client_ip: Option<String>,
 }
 
 impl Job {
+    pub fn new_owner_token() -> String {
+        uuid::Uuid::new_v4().simple().to_string()
+    }
+
     pub fn new_upload(
         id: String,
         filename: String,
@@ -111,7 +172,9 @@ impl Job {
             required_satoshis: Some(required_satoshis),
             manifest_txid: None,
             download_link: None,
-            message: "Waiting for payment...".to_string(),
+            message: String::new(),
+            message_key: None,
+            message_params: None,
             progress: 0.0,
             created_at: now,
             updated_at: now,
@@ -121,7 +184,30 @@ impl Job {
             cover_data: None,
             lyrics: None,
             network: None,
+            error_code: None,
+            bytes_done: None,
+            bytes_total: None,
+            eta_seconds: None,
+            parent_id: None,
+            owner_token: Some(Job::new_owner_token()),
+            to_address: None,
+            amount_satoshis: None,
+            fee_satoshis: None,
+            funding_txid: None,
+            sender_address: None,
+            payment_received_at: None,
+            payment_received_satoshis: None,
+            manifest_json: None,
+            mime_type: None,
+            royalty_address: None,
+            royalty_satoshis: None,
+            chunk_txids: None,
+            split_txid: None,
+            derivation_index: None,
+            content_sha256: None,
+            client_ip: None,
         }
+        .with_message(MessageKey::WaitingForPayment)
     }
 
     pub fn new_flac_upload(
@@ -146,7 +232,9 @@ impl Job {
             required_satoshis: Some(required_satoshis),
             manifest_txid: None,
             download_link: None,
-            message: "Waiting for payment...".to_string(),
+            message: String::new(),
+            message_key: None,
+            message_params: None,
             progress: 0.0,
             created_at: now,
             updated_at: now,
@@ -156,7 +244,30 @@ impl Job {
             cover_data: None,
             lyrics: None,
             network: None,
+            error_code: None,
+            bytes_done: None,
+            bytes_total: None,
+            eta_seconds: None,
+            parent_id: None,
+            owner_token: Some(Job::new_owner_token()),
+            to_address: None,
+            amount_satoshis: None,
+            fee_satoshis: None,
+            funding_txid: None,
+            sender_address: None,
+            payment_received_at: None,
+            payment_received_satoshis: None,
+            manifest_json: None,
+            mime_type: None,
+            royalty_address: None,
+            royalty_satoshis: None,
+            chunk_txids: None,
+            split_txid: None,
+            derivation_index: None,
+            content_sha256: None,
+            client_ip: None,
         }
+        .with_message(MessageKey::WaitingForPayment)
     }
 
     pub fn new_download(id: String, txid: String) -> Self {
@@ -173,7 +284,9 @@ impl Job {
             required_satoshis: None,
             manifest_txid: Some(txid),
             download_link: None,
-            message: "Fetching data from blockchain...".to_string(),
+            message: String::new(),
+            message_key: None,
+            message_params: None,
             progress: 0.0,
             created_at: now,
             updated_at: now,
@@ -183,7 +296,30 @@ impl Job {
             cover_data: None,
             lyrics: None,
             network: None,
+            error_code: None,
+            bytes_done: None,
+            bytes_total: None,
+            eta_seconds: None,
+            parent_id: None,
+            owner_token: Some(Job::new_owner_token()),
+            to_address: None,
+            amount_satoshis: None,
+            fee_satoshis: None,
+            funding_txid: None,
+            sender_address: None,
+            payment_received_at: None,
+            payment_received_satoshis: None,
+            manifest_json: None,
+            mime_type: None,
+            royalty_address: None,
+            royalty_satoshis: None,
+            chunk_txids: None,
+            split_txid: None,
+            derivation_index: None,
+            content_sha256: None,
+            client_ip: None,
         }
+        .with_message(MessageKey::FetchingFromChain)
     }
 
     pub fn new_flac_download(id: String, txid: String) -> Self {
@@ -200,7 +336,9 @@ impl Job {
             required_satoshis: None,
             manifest_txid: Some(txid),
             download_link: None,
-            message: "Fetching FLAC data from blockchain...".to_string(),
+            message: String::new(),
+            message_key: None,
+            message_params: None,
             progress: 0.0,
             created_at: now,
             updated_at: now,
@@ -210,7 +348,305 @@ impl Job {
             cover_data: None,
             lyrics: None,
             network: None,
+            error_code: None,
+            bytes_done: None,
+            bytes_total: None,
+            eta_seconds: None,
+            parent_id: None,
+            owner_token: Some(Job::new_owner_token()),
+            to_address: None,
+            amount_satoshis: None,
+            fee_satoshis: None,
+            funding_txid: None,
+            sender_address: None,
+            payment_received_at: None,
+            payment_received_satoshis: None,
+            manifest_json: None,
+            mime_type: None,
+            royalty_address: None,
+            royalty_satoshis: None,
+            chunk_txids: None,
+            split_txid: None,
+            derivation_index: None,
+            content_sha256: None,
+            client_ip: None,
+        }
+        .with_message(MessageKey::FetchingFlacFromChain)
+    }
+
+    /// Cover art added to an already uploaded track; the target manifest is kept in manifest_txid
+    pub fn new_cover_attach(
+        id: String,
+        manifest_txid: String,
+        cover_data: Vec<u8>,
+        payment_address: String,
+        payment_wif: String,
+        required_satoshis: i64,
+    ) -> Self {
+        let now = Utc::now();
+        Job {
+            id,
+            job_type: JobType::CoverAttach,
+            status: JobStatus::PendingPayment,
+            filename: None,
+            file_size: Some(cover_data.len() as i64),
+            file_data: None,
+            payment_address: Some(payment_address),
+            payment_wif: Some(payment_wif),
+            required_satoshis: Some(required_satoshis),
+            manifest_txid: Some(manifest_txid),
+            download_link: None,
+            message: String::new(),
+            message_key: None,
+            message_params: None,
+            progress: 0.0,
+            created_at: now,
+            updated_at: now,
+            track_title: None,
+            artist_name: None,
+            cover_txid: None,
+            cover_data: Some(cover_data),
+            lyrics: None,
+            network: None,
+            error_code: None,
+            bytes_done: None,
+            bytes_total: None,
+            eta_seconds: None,
+            parent_id: None,
+            owner_token: Some(Job::new_owner_token()),
+            to_address: None,
+            amount_satoshis: None,
+            fee_satoshis: None,
+            funding_txid: None,
+            sender_address: None,
+            payment_received_at: None,
+            payment_received_satoshis: None,
+            manifest_json: None,
+            mime_type: None,
+            royalty_address: None,
+            royalty_satoshis: None,
+            chunk_txids: None,
+            split_txid: None,
+            derivation_index: None,
+            content_sha256: None,
+            client_ip: None,
+        }
+        .with_message(MessageKey::WaitingForPayment)
+    }
+
+    /// Record of a completed wallet send; there is nothing to process or cancel
+    pub fn new_send(
+        id: String,
+        from_address: String,
+        to_address: String,
+        amount_satoshis: i64,
+        fee_satoshis: i64,
+        txid: String,
+        network: Network,
+    ) -> Self {
+        let message = MessageKey::Sent
+            .with("amount", amount_satoshis)
+            .with("address", to_address.as_str());
+        let now = Utc::now();
+        Job {
+            id,
+            job_type: JobType::Send,
+            status: JobStatus::Complete,
+            filename: None,
+            file_size: None,
+            file_data: None,
+            payment_address: Some(from_address),
+            payment_wif: None,
+            required_satoshis: None,
+            manifest_txid: Some(txid),
+            download_link: None,
+            message: String::new(),
+            message_key: None,
+            message_params: None,
+            progress: 100.0,
+            created_at: now,
+            updated_at: now,
+            track_title: None,
+            artist_name: None,
+            cover_txid: None,
+            cover_data: None,
+            lyrics: None,
+            network: Some(network),
+            error_code: None,
+            bytes_done: None,
+            bytes_total: None,
+            eta_seconds: None,
+            parent_id: None,
+            owner_token: None,
+            to_address: Some(to_address),
+            amount_satoshis: Some(amount_satoshis),
+            fee_satoshis: Some(fee_satoshis),
+            funding_txid: None,
+            sender_address: None,
+            payment_received_at: None,
+            payment_received_satoshis: None,
+            manifest_json: None,
+            mime_type: None,
+            royalty_address: None,
+            royalty_satoshis: None,
+            chunk_txids: None,
+            split_txid: None,
+            derivation_index: None,
+            content_sha256: None,
+            client_ip: None,
+        }
+        .with_message(message)
+    }
+
+    /// Record of an upload found on-chain, e.g. when rebuilding the catalog
+    pub fn new_import(
+        id: String,
+        job_type: JobType,
+        txid: String,
+        filename: Option<String>,
+        file_size: Option<i64>,
+        network: Network,
+    ) -> Self {
+        let now = Utc::now();
+        Job {
+            id,
+            job_type,
+            status: JobStatus::Complete,
+            filename,
+            file_size,
+            file_data: None,
+            payment_address: None,
+            payment_wif: None,
+            required_satoshis: None,
+            manifest_txid: Some(txid),
+            download_link: None,
+            message: String::new(),
+            message_key: None,
+            message_params: None,
+            progress: 100.0,
+            created_at: now,
+            updated_at: now,
+            track_title: None,
+            artist_name: None,
+            cover_txid: None,
+            cover_data: None,
+            lyrics: None,
+            network: Some(network),
+            error_code: None,
+            bytes_done: None,
+            bytes_total: None,
+            eta_seconds: None,
+            parent_id: None,
+            owner_token: None,
+            to_address: None,
+            amount_satoshis: None,
+            fee_satoshis: None,
+            funding_txid: None,
+            sender_address: None,
+            payment_received_at: None,
+            payment_received_satoshis: None,
+            manifest_json: None,
+            mime_type: None,
+            royalty_address: None,
+            royalty_satoshis: None,
+            chunk_txids: None,
+            split_txid: None,
+            derivation_index: None,
+            content_sha256: None,
+            client_ip: None,
+        }
+        .with_message(MessageKey::ImportedFromChain)
+    }
+
+    /// Job that imports a list of on-chain uploads; the txids are kept in manifest_txid
+    pub fn new_import_batch(id: String, txids: &[String], network: Network) -> Self {
+        let mut job = Job::new_import(id, JobType::Import, txids.join(","), None, None, network)
+            .with_status(JobStatus::Processing, MessageKey::QueuedForImport);
+        job.progress = 0.0;
+        job
+    }
+
+    /// Attach track title, artist and lyrics
+    pub fn with_track_metadata(
+        mut self,
+        track_title: Option<String>,
+        artist_name: Option<String>,
+        lyrics: Option<String>,
+    ) -> Self {
+        self.track_title = track_title;
+        self.artist_name = artist_name;
+        self.lyrics = lyrics;
+        self
+    }
+
+    /// Attach cover art to upload alongside the track
+    pub fn with_cover_data(mut self, cover_data: Option<Vec<u8>>) -> Self {
+        self.cover_data = cover_data;
+        self
+    }
+
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Chunk txids in manifest order, if the job has any
+    pub fn chunk_txid_list(&self) -> Option<Vec<String>> {
+        self.chunk_txids
+            .as_ref()
+            .map(|txids| txids.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect())
+    }
+
+    /// Keep the HD index of a derived payment key instead of its WIF
+    pub fn with_derivation_index(mut self, index: Option<u32>) -> Self {
+        if let Some(index) = index {
+            self.derivation_index = Some(index as i64);
+            self.payment_wif = None;
         }
+        self
+    }
+
+    /// Leave the payment key out of the stored job, for a prefunded upload
+    /// whose key is only held in memory
+    pub fn without_payment_wif(mut self) -> Self {
+        self.payment_wif = None;
+        self
+    }
+
+    /// Remember the uploaded file's hash and who prepared it
+    pub fn with_submission(mut self, content_sha256: String, client_ip: Option<String>) -> Self {
+        self.content_sha256 = Some(content_sha256);
+        self.client_ip = client_ip;
+        self
+    }
+
+    pub fn with_royalty(mut self, royalty: Option<(String, i64)>) -> Self {
+        if let Some((address, satoshis)) = royalty {
+            self.royalty_address = Some(address);
+            self.royalty_satoshis = Some(satoshis);
+        }
+        self
+    }
+
+    pub fn with_status(mut self, status: JobStatus, message: impl Into<StatusMessage>) -> Self {
+        self.status = status;
+        self.with_message(message)
+    }
+
+    /// Set the message text along with its key and params
+    pub fn with_message(mut self, message: impl Into<StatusMessage>) -> Self {
+        let message = message.into();
+        self.message = message.english();
+        self.message_key = Some(message.key.as_str().to_string());
+        self.message_params = message.params_json();
+        self
+    }
+
+    /// Make this job part of a batch, sharing the parent's owner token
+    pub fn with_parent(mut self, parent: &Job) -> Self {
+        self.parent_id = Some(parent.id.clone());
+        self.owner_token = parent.owner_token.clone();
+        self
     }
 }
 
@@ -224,6 +660,11 @@ pub struct JobSummary {
     pub manifest_txid: Option<String>,
     pub message: String,
     pub created_at: DateTime<Utc>,
+    pub network: Option<Network>,
+    pub to_address: Option<String>,
+    pub amount_satoshis: Option<i64>,
+    pub fee_satoshis: Option<i64>,
+    pub cover_txid: Option<String>,
 }
 
 impl From<Job> for JobSummary {
@@ -237,6 +678,56 @@ impl From<Job> for JobSummary {
             manifest_txid: job.manifest_txid,
             message: job.message,
             created_at: job.created_at,
+            network: job.network,
+            to_address: job.to_address,
+            amount_satoshis: job.amount_satoshis,
+            fee_satoshis: job.fee_satoshis,
+            cover_txid: job.cover_txid,
+        }
+    }
+}
+
+/// Job listing for the admin panel, including who paid for the job
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminJobSummary {
+    pub id: String,
+    pub job_type: JobType,
+    pub status: JobStatus,
+    pub filename: Option<String>,
+    pub network: Option<Network>,
+    pub payment_address: Option<String>,
+    pub required_satoshis: Option<i64>,
+    pub funding_txid: Option<String>,
+    pub sender_address: Option<String>,
+    pub manifest_txid: Option<String>,
+    pub cover_txid: Option<String>,
+    pub split_txid: Option<String>,
+    pub chunk_txids: Option<Vec<String>>,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_types_round_trip() {
+        let types = [
+            JobType::Upload,
+            JobType::Download,
+            JobType::FlacUpload,
+            JobType::FlacDownload,
+            JobType::FlacBatchDownload,
+            JobType::Send,
+            JobType::Import,
+            JobType::CoverAttach,
+        ];
+        for job_type in types {
+            assert_eq!(JobType::from_str(job_type.as_str()), Some(job_type.clone()));
+            // The API's serde name is the one stored in the database
+            assert_eq!(serde_json::to_value(&job_type).unwrap(), job_type.as_str());
         }
+        assert_eq!(JobType::from_str("refund"), None);
     }
 }
@@ -0,0 +1,15 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// One status or progress update in a job's timeline
+#[derive(Debug, Clone, Serialize)]
+pub struct JobEvent {
+    /// Set when the update moved the job to this status
+    pub status: Option<String>,
+    /// Set when the update reported progress
+    pub progress: Option<f64>,
+    pub message: String,
+    pub message_key: Option<String>,
+    pub message_params: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
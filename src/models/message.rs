@@ -0,0 +1,353 @@
+use serde_json::{Map, Value};
+
+use crate::models::BroadcastError;
+
+/// Stable keys for job status messages. Jobs store the key and its params
+/// next to the English text, so frontends can show localized messages while
+/// older clients keep reading `message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKey {
+    // Job creation
+    WaitingForPayment,
+    FetchingFromChain,
+    FetchingFlacFromChain,
+    Sent,
+    ImportedFromChain,
+    QueuedForImport,
+    AdminPayStarting,
+    FundingVerified,
+    StartingFlacDownload,
+    StartingBatchDownload,
+    WaitingForBatchSlot,
+    // Scheduling and lifecycle
+    AwaitingConfirmations,
+    PaymentReceived,
+    QueuedNext,
+    QueuedBehind,
+    PaymentQueuedNext,
+    PaymentQueuedBehind,
+    Starting,
+    Complete,
+    ProcessingFailed,
+    Stalled,
+    TimeLimitExceeded,
+    ApiKeyRevoked,
+    // Cancellation
+    CancelledBeforePayment,
+    CancelledBeforeProcessing,
+    CancelledBeforeBroadcast,
+    CancelledBeforeFileBroadcast,
+    CancelledBeforeChunks,
+    CancelledDuringChunks,
+    CancelledBeforeManifest,
+    DownloadCancelled,
+    DownloadCancelledBeforeStart,
+    BatchCancelled,
+    ImportCancelled,
+    AbandonedSwept,
+    // Uploads
+    NoFileData,
+    PaymentKeyMismatch,
+    PaymentKeyUnreadable,
+    FetchingUtxos,
+    UtxoFetchFailed,
+    NoUtxos,
+    CreatingTransaction,
+    ScriptBuildFailed,
+    InsufficientFunds,
+    InsufficientFundsAfterCover,
+    InsufficientFundsAcrossUtxos,
+    SplitTooManyInputs,
+    TxBuildFailed,
+    BroadcastingTransaction,
+    BroadcastFailed,
+    InvalidRoyaltyAddress,
+    UploadingCover,
+    UploadingLyrics,
+    NoUtxosForCover,
+    PreparingSplit,
+    SplitBuildFailed,
+    BroadcastingSplit,
+    SplitBroadcastFailed,
+    UploadingChunks,
+    UploadingChunk,
+    ChunkBuildFailed,
+    RetryingChunk,
+    ChunkBroadcast,
+    ChunkReused,
+    ChunkBroadcastFailed,
+    ChunkSizeReduced,
+    ChunkTooLarge,
+    CreatingManifest,
+    ManifestBuildFailed,
+    BroadcastingManifest,
+    ManifestBroadcastFailed,
+    CreatingFlacTransaction,
+    BroadcastingFlacTransaction,
+    // Cover attach
+    PublishingCoverUpdate,
+    CoverUpdateBroadcastFailed,
+    // Downloads
+    NoTxid,
+    FetchingTransaction,
+    TxFetchFailed,
+    ExtractingData,
+    NoOpReturnData,
+    SaveFailed,
+    FetchingManifest,
+    ParsingTransaction,
+    DownloadingChunk,
+    ChunkFetchFailed,
+    ChunkExtractFailed,
+    SizeMismatch,
+    ChunkIndexMismatch,
+    ChunkHashMismatch,
+    SavingFile,
+    NoFlacData,
+    // Batch downloads
+    BatchEmpty,
+    BatchStopped,
+    DownloadedTracks,
+    DownloadTaskEnded,
+    CreatingZip,
+    NoTracksDownloaded,
+    ZipFailed,
+    ZipSaveFailed,
+    CompleteWithFailures,
+    // Imports
+    Importing,
+    ImportComplete,
+}
+
+impl MessageKey {
+    /// The key and its English text, with `{name}` placeholders for params
+    fn entry(&self) -> (&'static str, &'static str) {
+        match self {
+            MessageKey::WaitingForPayment => ("waiting_for_payment", "Waiting for payment..."),
+            MessageKey::FetchingFromChain => ("fetching_from_chain", "Fetching data from blockchain..."),
+            MessageKey::FetchingFlacFromChain => ("fetching_flac_from_chain", "Fetching FLAC data from blockchain..."),
+            MessageKey::Sent => ("sent", "Sent {amount} sats to {address}"),
+            MessageKey::ImportedFromChain => ("imported_from_chain", "Imported from chain"),
+            MessageKey::QueuedForImport => ("queued_for_import", "Queued for import"),
+            MessageKey::AdminPayStarting => ("admin_pay_starting", "Admin pay enabled, starting upload..."),
+            MessageKey::FundingVerified => ("funding_verified", "Funding wallet verified, starting upload..."),
+            MessageKey::StartingFlacDownload => ("starting_flac_download", "Starting FLAC download..."),
+            MessageKey::StartingBatchDownload => ("starting_batch_download", "Starting batch download of {n} tracks..."),
+            MessageKey::WaitingForBatchSlot => ("waiting_for_batch_slot", "Waiting for batch slot..."),
+            MessageKey::AwaitingConfirmations => (
+                "awaiting_confirmations",
+                "Payment seen, waiting for {required} confirmations ({confirmations} so far)...",
+            ),
+            MessageKey::PaymentReceived => ("payment_received", "Payment of {amount} sats detected, queued..."),
+            MessageKey::QueuedNext => ("queued_next", "Queued, next in line"),
+            MessageKey::QueuedBehind => ("queued_behind", "Queued, {ahead} ahead of you"),
+            MessageKey::PaymentQueuedNext => ("payment_queued_next", "Payment of {amount} sats received. Queued, next in line"),
+            MessageKey::PaymentQueuedBehind => (
+                "payment_queued_behind",
+                "Payment of {amount} sats received. Queued, {ahead} ahead of you",
+            ),
+            MessageKey::Starting => ("starting", "Starting..."),
+            MessageKey::Complete => ("complete", "Complete"),
+            MessageKey::ProcessingFailed => ("processing_failed", "Job processing failed unexpectedly"),
+            MessageKey::Stalled => ("stalled", "Job stalled: no progress for {minutes} minutes"),
+            MessageKey::TimeLimitExceeded => ("time_limit_exceeded", "Job exceeded time limit of {minutes} minutes"),
+            MessageKey::ApiKeyRevoked => (
+                "api_key_revoked",
+                "The API key this upload was prepared with has been revoked; the payment at {address} was not used",
+            ),
+            MessageKey::CancelledBeforePayment => ("cancelled_before_payment", "Cancelled by owner before payment"),
+            MessageKey::CancelledBeforeProcessing => ("cancelled_before_processing", "Cancelled before processing started"),
+            MessageKey::CancelledBeforeBroadcast => ("cancelled_before_broadcast", "Cancelled before broadcast; no funds were spent"),
+            MessageKey::CancelledBeforeFileBroadcast => (
+                "cancelled_before_file_broadcast",
+                "Cancelled before broadcast; no funds were spent on the file",
+            ),
+            MessageKey::CancelledBeforeChunks => (
+                "cancelled_before_chunks",
+                "Cancelled before any chunks were inscribed. Unspent funds remain at {address}",
+            ),
+            MessageKey::CancelledDuringChunks => (
+                "cancelled_during_chunks",
+                "Cancelled after {i} of {n} chunks were inscribed. The {unused} unused split outputs remain unspent at {address} and can be swept with the payment key",
+            ),
+            MessageKey::CancelledBeforeManifest => (
+                "cancelled_before_manifest",
+                "Cancelled after all {n} chunks were inscribed, before the manifest. The manifest output remains unspent at {address}",
+            ),
+            MessageKey::DownloadCancelled => ("download_cancelled", "Download cancelled after {i} of {n} chunks"),
+            MessageKey::DownloadCancelledBeforeStart => ("download_cancelled_before_start", "Download cancelled before it started"),
+            MessageKey::BatchCancelled => ("batch_cancelled", "Batch cancelled after {done} of {n} tracks were downloaded"),
+            MessageKey::ImportCancelled => ("import_cancelled", "Import cancelled after {i} of {n} transactions"),
+            MessageKey::AbandonedSwept => ("abandoned_swept", "Abandoned, payment swept by admin"),
+            MessageKey::NoFileData => ("no_file_data", "No file data found"),
+            MessageKey::PaymentKeyMismatch => (
+                "payment_key_mismatch",
+                "Stored payment key controls {key_address}, not the payment address {address}; nothing was spent",
+            ),
+            MessageKey::PaymentKeyUnreadable => (
+                "payment_key_unreadable",
+                "Stored payment key for {address} can't be read ({error}); nothing was spent",
+            ),
+            MessageKey::FetchingUtxos => ("fetching_utxos", "Fetching UTXOs..."),
+            MessageKey::UtxoFetchFailed => ("utxo_fetch_failed", "Failed to get UTXOs: {error}"),
+            MessageKey::NoUtxos => ("no_utxos", "No UTXOs found"),
+            MessageKey::CreatingTransaction => ("creating_transaction", "Creating transaction..."),
+            MessageKey::ScriptBuildFailed => ("script_build_failed", "Failed to create script: {error}"),
+            MessageKey::InsufficientFunds => ("insufficient_funds", "Insufficient funds: {available} < {required}"),
+            MessageKey::InsufficientFundsAcrossUtxos => (
+                "insufficient_funds_across_utxos",
+                "Insufficient funds: {count} UTXOs hold {available} in total, {required} needed",
+            ),
+            MessageKey::SplitTooManyInputs => (
+                "split_too_many_inputs",
+                "Funding the upload needs more than {max} of the {count} UTXOs; consolidate them first",
+            ),
+            MessageKey::InsufficientFundsAfterCover => (
+                "insufficient_funds_after_cover",
+                "Insufficient funds after the cover image: {available} < {required}",
+            ),
+            MessageKey::TxBuildFailed => ("tx_build_failed", "Failed to create tx: {error}"),
+            MessageKey::BroadcastingTransaction => ("broadcasting_transaction", "Broadcasting transaction..."),
+            MessageKey::BroadcastFailed => ("broadcast_failed", "Broadcast failed: {error}"),
+            MessageKey::InvalidRoyaltyAddress => ("invalid_royalty_address", "Invalid royalty address: {error}"),
+            MessageKey::UploadingCover => ("uploading_cover", "Uploading cover image..."),
+            MessageKey::UploadingLyrics => ("uploading_lyrics", "Uploading lyrics..."),
+            MessageKey::NoUtxosForCover => ("no_utxos_for_cover", "No UTXOs for cover image"),
+            MessageKey::PreparingSplit => ("preparing_split", "Preparing UTXO split for {n} chunks..."),
+            MessageKey::SplitBuildFailed => ("split_build_failed", "Failed to create split tx: {error}"),
+            MessageKey::BroadcastingSplit => ("broadcasting_split", "Broadcasting UTXO split transaction..."),
+            MessageKey::SplitBroadcastFailed => ("split_broadcast_failed", "Failed to broadcast split tx: {error}"),
+            MessageKey::UploadingChunks => ("uploading_chunks", "Uploading {n} chunks..."),
+            MessageKey::UploadingChunk => ("uploading_chunk", "Uploading chunk {i}/{n}..."),
+            MessageKey::ChunkBuildFailed => ("chunk_build_failed", "Failed to create chunk {i} tx: {error}"),
+            MessageKey::RetryingChunk => ("retrying_chunk", "Uploading chunk {i}/{n}, retrying (attempt {attempt})..."),
+            MessageKey::ChunkBroadcast => ("chunk_broadcast", "Chunk {i}/{n} broadcast"),
+            MessageKey::ChunkReused => ("chunk_reused", "Chunk {i}/{n} is already on chain, reusing it"),
+            MessageKey::ChunkBroadcastFailed => (
+                "chunk_broadcast_failed",
+                "Failed to broadcast chunk {i} after {retries} retries: {error}",
+            ),
+            MessageKey::ChunkSizeReduced => (
+                "chunk_size_reduced",
+                "Chunk {i} was rejected as too large; uploading the remaining {bytes} bytes in {n} chunks of {size} bytes",
+            ),
+            MessageKey::ChunkTooLarge => (
+                "chunk_too_large",
+                "Chunk {i} was rejected as too large at {size} bytes and can't be made smaller: {error}",
+            ),
+            MessageKey::CreatingManifest => ("creating_manifest", "Creating manifest..."),
+            MessageKey::ManifestBuildFailed => ("manifest_build_failed", "Failed to create manifest tx: {error}"),
+            MessageKey::BroadcastingManifest => ("broadcasting_manifest", "Broadcasting manifest..."),
+            MessageKey::ManifestBroadcastFailed => ("manifest_broadcast_failed", "Failed to broadcast manifest: {error}"),
+            MessageKey::CreatingFlacTransaction => ("creating_flac_transaction", "Creating FLAC transaction..."),
+            MessageKey::BroadcastingFlacTransaction => ("broadcasting_flac_transaction", "Broadcasting FLAC transaction..."),
+            MessageKey::PublishingCoverUpdate => ("publishing_cover_update", "Linking the new cover to the track..."),
+            MessageKey::CoverUpdateBroadcastFailed => (
+                "cover_update_broadcast_failed",
+                "Cover {cover_txid} was inscribed, but linking it to the track failed: {error}",
+            ),
+            MessageKey::NoTxid => ("no_txid", "No TXID provided"),
+            MessageKey::FetchingTransaction => ("fetching_transaction", "Fetching transaction..."),
+            MessageKey::TxFetchFailed => ("tx_fetch_failed", "Failed to fetch tx: {error}"),
+            MessageKey::ExtractingData => ("extracting_data", "Extracting data..."),
+            MessageKey::NoOpReturnData => ("no_op_return_data", "No OP_RETURN data found in transaction"),
+            MessageKey::SaveFailed => ("save_failed", "Failed to save file: {error}"),
+            MessageKey::FetchingManifest => ("fetching_manifest", "Fetching manifest transaction..."),
+            MessageKey::ParsingTransaction => ("parsing_transaction", "Parsing transaction..."),
+            MessageKey::DownloadingChunk => ("downloading_chunk", "Downloading chunk {i}/{n}..."),
+            MessageKey::ChunkFetchFailed => ("chunk_fetch_failed", "Failed to fetch chunk {i}: {error}"),
+            MessageKey::ChunkExtractFailed => ("chunk_extract_failed", "Failed to extract data from chunk {i}"),
+            MessageKey::SizeMismatch => ("size_mismatch", "size mismatch: got {got} expected {expected}"),
+            MessageKey::ChunkIndexMismatch => (
+                "chunk_index_mismatch",
+                "Chunk indices don't match the manifest: expected 0-{last}, missing [{missing}], duplicated [{duplicated}], unexpected [{unexpected}]",
+            ),
+            MessageKey::ChunkHashMismatch => (
+                "chunk_hash_mismatch",
+                "Chunk {index} ({txid}) doesn't match the hash in the manifest; its data is corrupt",
+            ),
+            MessageKey::SavingFile => ("saving_file", "Saving file..."),
+            MessageKey::NoFlacData => ("no_flac_data", "No FLAC data found in transaction"),
+            MessageKey::BatchEmpty => ("batch_empty", "Batch has no tracks"),
+            MessageKey::BatchStopped => ("batch_stopped", "Batch download stopped"),
+            MessageKey::DownloadedTracks => ("downloaded_tracks", "Downloaded {done}/{n} tracks..."),
+            MessageKey::DownloadTaskEnded => ("download_task_ended", "Download task ended unexpectedly"),
+            MessageKey::CreatingZip => ("creating_zip", "Creating zip archive..."),
+            MessageKey::NoTracksDownloaded => ("no_tracks_downloaded", "None of the tracks could be downloaded"),
+            MessageKey::ZipFailed => ("zip_failed", "Failed to create zip: {error}"),
+            MessageKey::ZipSaveFailed => ("zip_save_failed", "Failed to save zip: {error}"),
+            MessageKey::CompleteWithFailures => ("complete_with_failures", "Complete ({failed} of {n} tracks failed)"),
+            MessageKey::Importing => ("importing", "Importing {i}/{n}: {txid}"),
+            MessageKey::ImportComplete => (
+                "import_complete",
+                "Imported {imported}, {skipped} already imported, {failed} failed",
+            ),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.entry().0
+    }
+
+    /// Start a message with this key and one param
+    pub fn with(self, name: &str, value: impl Into<Value>) -> StatusMessage {
+        StatusMessage::from(self).with(name, value)
+    }
+
+    /// Start a message with this key and a failed broadcast's detail
+    pub fn with_broadcast_error(self, error: &BroadcastError) -> StatusMessage {
+        StatusMessage::from(self).with_broadcast_error(error)
+    }
+}
+
+/// A job status message: its key, the params filled into it, and the
+/// English text stored as `message`
+#[derive(Debug, Clone)]
+pub struct StatusMessage {
+    pub key: MessageKey,
+    pub params: Map<String, Value>,
+}
+
+impl StatusMessage {
+    pub fn with(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.params.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Fill `{error}` from a failed broadcast, keeping each provider's answer
+    /// as the `attempts` param
+    pub fn with_broadcast_error(self, error: &BroadcastError) -> Self {
+        self.with("error", error.to_string()).with("attempts", error.attempts_json())
+    }
+
+    /// Params as stored in the database, None when the message has none
+    pub fn params_json(&self) -> Option<String> {
+        if self.params.is_empty() {
+            None
+        } else {
+            Some(Value::Object(self.params.clone()).to_string())
+        }
+    }
+
+    /// The message formatted with the English table
+    pub fn english(&self) -> String {
+        let mut text = self.key.entry().1.to_string();
+        for (name, value) in &self.params {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            text = text.replace(&format!("{{{}}}", name), &value);
+        }
+        text
+    }
+}
+
+impl From<MessageKey> for StatusMessage {
+    fn from(key: MessageKey) -> Self {
+        StatusMessage {
+            key,
+            params: Map::new(),
+        }
+    }
+}
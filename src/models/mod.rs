@@ -1,3 +1,19 @@
+pub mod amount;
+pub mod api_key;
+pub mod broadcast;
+pub mod error;
 pub mod job;
+pub mod job_event;
+pub mod message;
+pub mod network;
+pub mod payment;
 
+pub use amount::*;
+pub use api_key::*;
+pub use broadcast::*;
+pub use error::*;
 pub use job::*;
+pub use job_event::*;
+pub use message::*;
+pub use network::*;
+pub use payment::*;
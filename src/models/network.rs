@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A BSV network. Parsed leniently from user input, always written as
+/// "mainnet", "testnet" or "stn".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", try_from = "String")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    /// Scaling test network
+    Stn,
+}
+
+impl Network {
+    pub const ALL: [Network; 3] = [Network::Mainnet, Network::Testnet, Network::Stn];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Stn => "stn",
+        }
+    }
+
+    /// Case-insensitive, with the common aliases ("main", "test", ...)
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "mainnet" | "main" | "livenet" | "bsv" => Some(Network::Mainnet),
+            "testnet" | "test" | "testnet3" | "tbsv" => Some(Network::Testnet),
+            "stn" | "scaling-testnet" | "scalingtestnet" => Some(Network::Stn),
+            _ => None,
+        }
+    }
+
+    /// STN keys and addresses use the testnet version bytes
+    pub fn uses_testnet_versions(&self) -> bool {
+        matches!(self, Network::Testnet | Network::Stn)
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Network::from_str(&s).ok_or_else(|| format!("Unknown network: {}", s))
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_parse_leniently() {
+        for name in ["testnet", "test", "TESTNET", " Testnet "] {
+            assert_eq!(Network::from_str(name), Some(Network::Testnet), "{:?}", name);
+        }
+        assert_eq!(Network::from_str("Main"), Some(Network::Mainnet));
+        assert_eq!(Network::from_str("STN"), Some(Network::Stn));
+        assert_eq!(Network::from_str("regtest"), None);
+        assert_eq!(Network::from_str(""), None);
+    }
+
+    #[test]
+    fn serde_reads_aliases_and_writes_canonical_names() {
+        assert_eq!(serde_json::from_str::<Network>("\"Test\"").unwrap(), Network::Testnet);
+        let error = serde_json::from_str::<Network>("\"regtest\"").unwrap_err();
+        assert!(error.to_string().contains("Unknown network: regtest"), "{}", error);
+        for network in Network::ALL {
+            assert_eq!(serde_json::to_value(network).unwrap(), network.as_str());
+            assert_eq!(Network::from_str(&network.to_string()), Some(network));
+        }
+    }
+}
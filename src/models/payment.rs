@@ -0,0 +1,13 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// A transaction the payment watcher saw paying a job's address
+#[derive(Debug, Clone, Serialize)]
+pub struct PaymentRecord {
+    pub txid: String,
+    /// Sum of the transaction's outputs to the address
+    pub satoshis: i64,
+    /// Block it was mined in, unset while it was unconfirmed when last seen
+    pub block_height: Option<i64>,
+    pub first_seen_at: DateTime<Utc>,
+}
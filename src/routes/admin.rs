@@ -1,55 +1,169 @@
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{Html, IntoResponse, Json},
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, HeaderMap},
+    response::{Html, IntoResponse, Json, Redirect, Response},
 };
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use crate::db::AdminConfig;
+use crate::models::{AdminJobSummary, Amount, ApiKey, BroadcastAttemptRecord, ErrorCode, Job, JobStatus, JobType, MessageKey, Network};
+use crate::routes::error::ApiError;
+use crate::routes::flac::validate_royalty;
+use crate::services::admin_session::{constant_time_eq, cookie_value, COOKIE_NAME};
+use crate::services::api_keys;
+use crate::services::bitails::{ApiKeyUsage, Utxo};
 use crate::services::bsv::BsvService;
+use crate::services::maintenance::{MaintenanceReport, MaintenanceTotals};
+use crate::services::provider_health::ProviderStatus;
+use crate::services::selftest::SelftestReport;
+use crate::services::scheduler::QueuedJob;
 use crate::AppState;
 
 // Admin key for authentication (should be set via environment variable)
-fn get_admin_key() -> String {
+pub fn get_admin_key() -> String {
     std::env::var("ADMIN_KEY").unwrap_or_else(|_| "nausica-admin-2024".to_string())
 }
 
-/// Admin panel page
-pub async fn admin_page() -> Html<String> {
+fn require_admin_key(key: &str) -> Result<(), ApiError> {
+    if constant_time_eq(key.as_bytes(), get_admin_key().as_bytes()) {
+        Ok(())
+    } else {
+        Err(ApiError::unauthorized())
+    }
+}
+
+/// Whether the caller has a valid admin session cookie. While
+/// ADMIN_KEY_IN_BODY is on, the admin key in the request body still works.
+pub struct AdminAuth {
+    session: bool,
+    key_in_body: bool,
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<RwLock<AppState>>> for AdminAuth {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<RwLock<AppState>>) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::COOKIE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|cookies| cookie_value(cookies, COOKIE_NAME));
+
+        let state = state.read().await;
+        // Without the current epoch a revoked session could pass, so a failed read fails the session
+        let session = match (token, state.db.admin_session_epoch()) {
+            (Some(token), Ok(epoch)) => {
+                state
+                    .admin_sessions
+                    .verify(token, &get_admin_key(), epoch, chrono::Utc::now().timestamp())
+            }
+            _ => false,
+        };
+        Ok(AdminAuth {
+            session,
+            key_in_body: state.config.admin_key_in_body,
+        })
+    }
+}
+
+impl AdminAuth {
+    pub fn is_session(&self) -> bool {
+        self.session
+    }
+
+    /// Accept the session, or the legacy body key while that is allowed
+    pub fn require(&self, key: &str) -> Result<(), ApiError> {
+        if self.session {
+            return Ok(());
+        }
+        if self.key_in_body && !key.is_empty() {
+            require_admin_key(key)?;
+            tracing::debug!("Admin request authenticated with the body key; log in for a session cookie instead");
+            return Ok(());
+        }
+        Err(ApiError::unauthorized())
+    }
+}
+
+/// Session cookie, marked Secure unless the request came over plain HTTP.
+/// The server itself only speaks HTTP, so HTTPS means a TLS proxy that says so.
+fn session_cookie(value: &str, max_age: i64, headers: &HeaderMap) -> String {
+    let https = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|proto| proto.split(',').next().unwrap_or_default().trim().eq_ignore_ascii_case("https"));
+    let secure = if https { "; Secure" } else { "" };
+    format!("{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}", COOKIE_NAME, value, max_age, secure)
+}
+
+/// Admin panel page, or the login form without a session
+pub async fn admin_page(auth: AdminAuth) -> Response {
+    if !auth.is_session() {
+        return Redirect::to("/admin/login").into_response();
+    }
     let html = include_str!("../../templates/admin.html");
+    Html(html.to_string()).into_response()
+}
+
+/// Admin login page
+pub async fn admin_login_page() -> Html<String> {
+    let html = include_str!("../../templates/admin_login.html");
     Html(html.to_string())
 }
 
+/// Exchange the admin key for a session cookie
+pub async fn admin_login(
+    State(state): State<Arc<RwLock<AppState>>>,
+    headers: HeaderMap,
+    Json(req): Json<AdminAuthRequest>,
+) -> Result<Response, ApiError> {
+    require_admin_key(&req.key)?;
+
+    let state = state.read().await;
+    let epoch = state.db.admin_session_epoch().map_err(ApiError::database)?;
+    let token = state.admin_sessions.issue(&req.key, epoch, chrono::Utc::now().timestamp());
+    let cookie = session_cookie(&token, state.admin_sessions.lifetime_seconds(), &headers);
+    Ok(([(header::SET_COOKIE, cookie)], Json(AdminAuthResponse { success: true })).into_response())
+}
+
+/// End the session. Sessions can't be told apart, so every session ends.
+pub async fn admin_logout(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    // Only a signed-in admin may sign everyone out; anyone may drop their own cookie
+    if auth.is_session() {
+        let state = state.read().await;
+        state.db.bump_admin_session_epoch().map_err(ApiError::database)?;
+    }
+    Ok(([(header::SET_COOKIE, session_cookie("", 0, &headers))], Json(AdminAuthResponse { success: true })).into_response())
+}
+
 #[derive(Deserialize)]
 pub struct AdminAuthRequest {
+    #[serde(default)]
     pub key: String,
 }
 
 #[derive(Serialize)]
 pub struct AdminAuthResponse {
     pub success: bool,
-    pub error: Option<String>,
 }
 
 /// Verify admin key
 pub async fn verify_admin_key(
+    auth: AdminAuth,
     Json(req): Json<AdminAuthRequest>,
-) -> Json<AdminAuthResponse> {
-    let admin_key = get_admin_key();
-    
-    if req.key == admin_key {
-        Json(AdminAuthResponse {
-            success: true,
-            error: None,
-        })
-    } else {
-        Json(AdminAuthResponse {
-            success: false,
-            error: Some("Invalid admin key".to_string()),
-        })
-    }
+) -> Result<Json<AdminAuthResponse>, ApiError> {
+    auth.require(&req.key)?;
+    Ok(Json(AdminAuthResponse { success: true }))
 }
 
 #[derive(Serialize)]
@@ -61,124 +175,86 @@ pub struct AdminConfigResponse {
     pub testnet_address: Option<String>,
     pub mainnet_balance: Option<i64>,
     pub testnet_balance: Option<i64>,
-    pub error: Option<String>,
+    pub royalty_address_mainnet: Option<String>,
+    pub royalty_address_testnet: Option<String>,
+    pub royalty_satoshis: Option<i64>,
 }
 
 #[derive(Deserialize)]
 pub struct GetAdminConfigRequest {
+    #[serde(default)]
     pub key: String,
 }
 
 /// Get admin configuration
 pub async fn get_admin_config(
     State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
     Json(req): Json<GetAdminConfigRequest>,
-) -> impl IntoResponse {
-    let admin_key = get_admin_key();
-    
-    if req.key != admin_key {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(AdminConfigResponse {
-                success: false,
-                admin_pay_mainnet: false,
-                admin_pay_testnet: false,
-                mainnet_address: None,
-                testnet_address: None,
-                mainnet_balance: None,
-                testnet_balance: None,
-                error: Some("Invalid admin key".to_string()),
-            }),
-        ).into_response();
-    }
+) -> Result<Json<AdminConfigResponse>, ApiError> {
+    auth.require(&req.key)?;
 
     let state = state.read().await;
-    
-    match state.db.get_admin_config() {
-        Ok(config) => {
-            // Get addresses from WIFs
-            let mainnet_address = config.mainnet_wif.as_ref().and_then(|wif| {
-                BsvService::wif_to_address(wif, "mainnet").ok()
-            });
-            let testnet_address = config.testnet_wif.as_ref().and_then(|wif| {
-                BsvService::wif_to_address(wif, "testnet").ok()
-            });
-
-            Json(AdminConfigResponse {
-                success: true,
-                admin_pay_mainnet: config.admin_pay_mainnet,
-                admin_pay_testnet: config.admin_pay_testnet,
-                mainnet_address,
-                testnet_address,
-                mainnet_balance: None, // Will be fetched separately
-                testnet_balance: None, // Will be fetched separately
-                error: None,
-            }).into_response()
-        }
-        Err(e) => {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(AdminConfigResponse {
-                    success: false,
-                    admin_pay_mainnet: false,
-                    admin_pay_testnet: false,
-                    mainnet_address: None,
-                    testnet_address: None,
-                    mainnet_balance: None,
-                    testnet_balance: None,
-                    error: Some(format!("Database error: {}", e)),
-                }),
-            ).into_response()
-        }
-    }
+    let config = state.db.get_admin_config().map_err(ApiError::database)?;
+
+    // Get addresses from WIFs
+    let mainnet_address = config.mainnet_wif.as_ref().and_then(|wif| {
+        BsvService::wif_to_address(wif, Network::Mainnet).ok()
+    });
+    let testnet_address = config.testnet_wif.as_ref().and_then(|wif| {
+        BsvService::wif_to_address(wif, Network::Testnet).ok()
+    });
+
+    Ok(Json(AdminConfigResponse {
+        success: true,
+        admin_pay_mainnet: config.admin_pay_mainnet,
+        admin_pay_testnet: config.admin_pay_testnet,
+        mainnet_address,
+        testnet_address,
+        mainnet_balance: None, // Will be fetched separately
+        testnet_balance: None, // Will be fetched separately
+        royalty_address_mainnet: config.royalty_address_mainnet,
+        royalty_address_testnet: config.royalty_address_testnet,
+        royalty_satoshis: config.royalty_satoshis,
+    }))
 }
 
 #[derive(Deserialize)]
 pub struct UpdateAdminConfigRequest {
+    #[serde(default)]
     pub key: String,
     pub admin_pay_mainnet: Option<bool>,
     pub admin_pay_testnet: Option<bool>,
     pub mainnet_wif: Option<String>,
     pub testnet_wif: Option<String>,
+    // An empty address clears the default royalty for that network
+    pub royalty_address_mainnet: Option<String>,
+    pub royalty_address_testnet: Option<String>,
+    pub royalty_satoshis: Option<i64>,
 }
 
 #[derive(Serialize)]
 pub struct UpdateAdminConfigResponse {
     pub success: bool,
-    pub error: Option<String>,
 }
 
 /// Update admin configuration
 pub async fn update_admin_config(
     State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
     Json(req): Json<UpdateAdminConfigRequest>,
-) -> impl IntoResponse {
-    let admin_key = get_admin_key();
-    
-    if req.key != admin_key {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(UpdateAdminConfigResponse {
-                success: false,
-                error: Some("Invalid admin key".to_string()),
-            }),
-        ).into_response();
-    }
+) -> Result<Json<UpdateAdminConfigResponse>, ApiError> {
+    auth.require(&req.key)?;
 
     let state = state.read().await;
-    
+
     // Get current config
-    let current_config = match state.db.get_admin_config() {
-        Ok(c) => c,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(UpdateAdminConfigResponse {
-                    success: false,
-                    error: Some(format!("Database error: {}", e)),
-                }),
-            ).into_response();
-        }
+    let current_config = state.db.get_admin_config().map_err(ApiError::database)?;
+
+    let royalty_address = |requested: Option<String>, current: Option<String>| match requested {
+        Some(address) if address.trim().is_empty() => None,
+        Some(address) => Some(address.trim().to_string()),
+        None => current,
     };
 
     // Update config with new values
@@ -187,29 +263,31 @@ pub async fn update_admin_config(
         admin_pay_testnet: req.admin_pay_testnet.unwrap_or(current_config.admin_pay_testnet),
         mainnet_wif: req.mainnet_wif.or(current_config.mainnet_wif),
         testnet_wif: req.testnet_wif.or(current_config.testnet_wif),
+        royalty_address_mainnet: royalty_address(req.royalty_address_mainnet, current_config.royalty_address_mainnet),
+        royalty_address_testnet: royalty_address(req.royalty_address_testnet, current_config.royalty_address_testnet),
+        royalty_satoshis: req.royalty_satoshis.or(current_config.royalty_satoshis),
     };
 
-    match state.db.update_admin_config(&new_config) {
-        Ok(_) => Json(UpdateAdminConfigResponse {
-            success: true,
-            error: None,
-        }).into_response(),
-        Err(e) => {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(UpdateAdminConfigResponse {
-                    success: false,
-                    error: Some(format!("Failed to update config: {}", e)),
-                }),
-            ).into_response()
+    for network in [Network::Mainnet, Network::Testnet] {
+        if let Some((address, satoshis)) = new_config.default_royalty(network) {
+            validate_royalty(&address, satoshis, network, state.bsv.dust_limit)
+                .map_err(|e| ApiError::new(ErrorCode::InvalidAddress, e))?;
         }
     }
+
+    state
+        .db
+        .update_admin_config(&new_config)
+        .map_err(|e| ApiError::new(ErrorCode::DatabaseError, format!("Failed to update config: {}", e)))?;
+
+    Ok(Json(UpdateAdminConfigResponse { success: true }))
 }
 
 #[derive(Deserialize)]
 pub struct GetWalletBalanceRequest {
+    #[serde(default)]
     pub key: String,
-    pub network: String,
+    pub network: Network,
 }
 
 #[derive(Serialize)]
@@ -217,104 +295,75 @@ pub struct GetWalletBalanceResponse {
     pub success: bool,
     pub address: Option<String>,
     pub balance: Option<i64>,
-    pub error: Option<String>,
+    /// Set when no wallet is configured for the network
+    pub message: Option<String>,
 }
 
 /// Get admin wallet balance
 pub async fn get_admin_wallet_balance(
     State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
     Json(req): Json<GetWalletBalanceRequest>,
-) -> impl IntoResponse {
-    let admin_key = get_admin_key();
-    
-    if req.key != admin_key {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(GetWalletBalanceResponse {
-                success: false,
-                address: None,
-                balance: None,
-                error: Some("Invalid admin key".to_string()),
-            }),
-        ).into_response();
-    }
+) -> Result<Json<GetWalletBalanceResponse>, ApiError> {
+    auth.require(&req.key)?;
 
     let config = {
         let state = state.read().await;
-        match state.db.get_admin_config() {
-            Ok(c) => c,
-            Err(e) => {
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(GetWalletBalanceResponse {
-                        success: false,
-                        address: None,
-                        balance: None,
-                        error: Some(format!("Database error: {}", e)),
-                    }),
-                ).into_response();
-            }
-        }
+        state.db.get_admin_config().map_err(ApiError::database)?
     };
 
-    let wif = if req.network == "testnet" {
-        config.testnet_wif
-    } else {
-        config.mainnet_wif
+    // Admin wallets exist for mainnet and testnet only
+    let wif = match req.network {
+        Network::Mainnet => config.mainnet_wif,
+        Network::Testnet => config.testnet_wif,
+        Network::Stn => None,
     };
 
     let wif = match wif {
         Some(w) => w,
         None => {
-            return Json(GetWalletBalanceResponse {
+            return Ok(Json(GetWalletBalanceResponse {
                 success: true,
                 address: None,
                 balance: None,
-                error: Some("No wallet configured for this network".to_string()),
-            }).into_response();
+                message: Some("No wallet configured for this network".to_string()),
+            }));
         }
     };
 
-    let address = match BsvService::wif_to_address(&wif, &req.network) {
-        Ok(addr) => addr,
-        Err(e) => {
-            return Json(GetWalletBalanceResponse {
-                success: false,
-                address: None,
-                balance: None,
-                error: Some(format!("Invalid WIF: {}", e)),
-            }).into_response();
-        }
-    };
+    let address = BsvService::wif_to_address(&wif, req.network)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidWif, format!("Invalid WIF: {}", e)))?;
 
-    // Fetch balance based on network
-    let balance = if req.network == "testnet" {
-        // Use WhatsOnChain API for testnet
-        match fetch_testnet_balance(&address).await {
-            Ok(b) => Some(b),
-            Err(_) => None,
-        }
+    let balance = fetch_wallet_balance(&state, &address, req.network).await;
+
+    Ok(Json(GetWalletBalanceResponse {
+        success: true,
+        address: Some(address),
+        balance,
+        message: None,
+    }))
+}
+
+/// Fetch an address balance based on network
+async fn fetch_wallet_balance(state: &Arc<RwLock<AppState>>, address: &str, network: Network) -> Option<i64> {
+    if crate::services::whatsonchain::serves(network) {
+        // Use WhatsOnChain API for testnet and STN
+        fetch_whatsonchain_balance(address, network).await.ok()
     } else {
         // Use Bitails API for mainnet
         let state = state.read().await;
-        match state.bitails.get_address_balance(&address).await {
+        match state.bitails.get_address_balance(address).await {
             Ok(b) => Some(b.confirmed + b.unconfirmed),
             Err(_) => None,
         }
-    };
-
-    Json(GetWalletBalanceResponse {
-        success: true,
-        address: Some(address),
-        balance,
-        error: None,
-    }).into_response()
+    }
 }
 
-async fn fetch_testnet_balance(address: &str) -> Result<i64, String> {
-    let url = format!("https://api.whatsonchain.com/v1/bsv/test/address/{}/balance", address);
+async fn fetch_whatsonchain_balance(address: &str, network: Network) -> Result<i64, String> {
+    let url = format!("{}/address/{}/balance", crate::services::whatsonchain::base_url(network), address);
     
-    let client = reqwest::Client::new();
+    let client = crate::services::http::client();
+    crate::services::rate_limit::whatsonchain().acquire().await;
     let response = client.get(&url)
         .send()
         .await
@@ -328,15 +377,233 @@ async fn fetch_testnet_balance(address: &str) -> Result<i64, String> {
     Ok(confirmed + unconfirmed)
 }
 
+#[derive(Deserialize)]
+pub struct RotateWalletRequest {
+    #[serde(default)]
+    pub key: String,
+    pub network: Network,
+    pub new_wif: String,
+}
+
+#[derive(Serialize)]
+pub struct RotateWalletResponse {
+    pub success: bool,
+    pub old_address: Option<String>,
+    pub new_address: String,
+    pub sweep_txid: Option<String>,
+    pub swept_satoshis: i64,
+    /// Balance left on the old address because it couldn't cover a sweep fee
+    pub stranded_satoshis: i64,
+}
+
+/// Replace an admin-pay wallet, first sweeping the old wallet's balance to
+/// the new address so no funds are left behind on a key we stop tracking
+pub async fn rotate_admin_wallet(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<RotateWalletRequest>,
+) -> Result<Json<RotateWalletResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let network = req.network;
+    if network == Network::Stn {
+        return Err(ApiError::invalid_request("There is no admin wallet on stn"));
+    }
+
+    let new_wif = req.new_wif.trim().to_string();
+    let invalid = |e: String| ApiError::new(ErrorCode::InvalidWif, format!("Invalid WIF: {}", e));
+    let wif_network = BsvService::wif_network(&new_wif).map_err(invalid)?;
+    if wif_network != network {
+        return Err(ApiError::new(
+            ErrorCode::InvalidWif,
+            format!("WIF is for {}, not {}", wif_network, network),
+        ));
+    }
+    let new_address = BsvService::wif_to_address(&new_wif, network).map_err(invalid)?;
+
+    let config = {
+        let state = state.read().await;
+        state.db.get_admin_config().map_err(ApiError::database)?
+    };
+    let old_wif = match network {
+        Network::Mainnet => config.mainnet_wif.clone(),
+        _ => config.testnet_wif.clone(),
+    };
+
+    let mut response = RotateWalletResponse {
+        success: true,
+        old_address: None,
+        new_address: new_address.clone(),
+        sweep_txid: None,
+        swept_satoshis: 0,
+        stranded_satoshis: 0,
+    };
+
+    if let Some(old_wif) = old_wif {
+        let old_address = BsvService::wif_to_address(&old_wif, network)
+            .map_err(|e| ApiError::new(ErrorCode::InvalidWif, format!("Current admin WIF is invalid: {}", e)))?;
+        if old_address == new_address {
+            return Err(ApiError::invalid_request("The new WIF controls the current admin address"));
+        }
+        response.old_address = Some(old_address.clone());
+
+        // Without a UTXO listing we can't tell whether funds would be stranded
+        let utxos = crate::get_address_utxos(&state, &old_address, network)
+            .await
+            .map_err(|e| ApiError::new(ErrorCode::UtxoFetchFailed, format!("Failed to get UTXOs: {}", e)))?;
+
+        if !utxos.is_empty() {
+            let new_script = BsvService::create_p2pkh_script(&new_address)
+                .map_err(|e| ApiError::new(ErrorCode::InvalidAddress, e))?;
+            let built = {
+                let state = state.read().await;
+                build_sweep_tx(&state.bsv, &old_wif, &old_address, &utxos, &new_script)
+            };
+
+            match built {
+                Ok((raw_tx, amount, fee)) => {
+                    let txid = crate::broadcast_tx(&state, None, &raw_tx, network).await.map_err(|e| {
+                        ApiError::new(ErrorCode::BroadcastFailed, format!("Failed to broadcast sweep: {}", e))
+                    })?;
+                    let state = state.read().await;
+                    crate::routes::wallet::record_send(&state.db, &old_address, &new_address, amount, fee, &txid, network);
+                    tracing::info!("Swept {} sats from old {} admin wallet in {}", amount, network, txid);
+                    response.sweep_txid = Some(txid);
+                    response.swept_satoshis = amount.to_sat_i64();
+                }
+                // Dust that can't pay its own fee is reported rather than blocking the rotation
+                Err(e) => {
+                    tracing::warn!("Not sweeping old {} admin wallet: {}", network, e);
+                    response.stranded_satoshis = utxos.iter().map(|u| u.satoshis).sum();
+                }
+            }
+        }
+    }
+
+    let new_config = match network {
+        Network::Mainnet => AdminConfig { mainnet_wif: Some(new_wif), ..config },
+        _ => AdminConfig { testnet_wif: Some(new_wif), ..config },
+    };
+    let state = state.read().await;
+    state
+        .db
+        .update_admin_config(&new_config)
+        .map_err(|e| ApiError::new(ErrorCode::DatabaseError, format!("Failed to update config: {}", e)))?;
+
+    Ok(Json(response))
+}
+
 #[derive(Deserialize)]
 pub struct CheckAdminPayRequest {
-    pub network: String,
+    pub network: Network,
+    pub file_size: Option<usize>,
 }
 
 #[derive(Serialize)]
 pub struct CheckAdminPayResponse {
     pub admin_pay_enabled: bool,
     pub admin_wallet_address: Option<String>,
+    /// Whether admin pay would cover an upload of `file_size`
+    pub eligible: bool,
+    pub reason: Option<String>,
+    pub wallet_balance: Option<i64>,
+    pub estimated_cost: Option<i64>,
+    /// None when no daily budget is configured
+    pub daily_remaining: Option<i64>,
+}
+
+/// Whether the admin wallet can pay for an upload, and why not
+pub struct AdminPayEligibility {
+    pub enabled: bool,
+    pub address: Option<String>,
+    pub eligible: bool,
+    pub reason: Option<String>,
+    pub wallet_balance: Option<i64>,
+    pub daily_remaining: Option<i64>,
+}
+
+/// Work out admin pay eligibility for a network from the admin config,
+/// the live wallet balance and today's admin spend. A cost of None only
+/// checks that admin pay is enabled and funded.
+pub async fn admin_pay_eligibility(
+    state: &Arc<RwLock<AppState>>,
+    network: Network,
+    estimated_cost: Option<i64>,
+) -> AdminPayEligibility {
+    let mut result = AdminPayEligibility {
+        enabled: false,
+        address: None,
+        eligible: false,
+        reason: None,
+        wallet_balance: None,
+        daily_remaining: None,
+    };
+
+    let (config, budget) = {
+        let state = state.read().await;
+        (state.db.get_admin_config(), state.config.admin_pay_daily_budget_satoshis)
+    };
+    let config = match config {
+        Ok(c) => c,
+        Err(e) => {
+            result.reason = Some(format!("Database error: {}", e));
+            return result;
+        }
+    };
+
+    let (enabled, wif) = match network {
+        Network::Mainnet => (config.admin_pay_mainnet, config.mainnet_wif),
+        Network::Testnet => (config.admin_pay_testnet, config.testnet_wif),
+        // No admin wallet on STN
+        Network::Stn => (false, None),
+    };
+    let address = wif.and_then(|w| BsvService::wif_to_address(&w, network).ok());
+    let address = match (enabled, address) {
+        (true, Some(address)) => address,
+        _ => {
+            result.reason = Some(format!("Admin pay is disabled for {}", network));
+            return result;
+        }
+    };
+    result.enabled = true;
+    result.address = Some(address.clone());
+
+    if budget > 0 {
+        let start_of_day = chrono::Utc::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .map(|t| t.and_utc())
+            .unwrap_or_else(chrono::Utc::now);
+        let spent = {
+            let state = state.read().await;
+            state.db.sum_required_satoshis_since(&address, start_of_day).unwrap_or(0)
+        };
+        result.daily_remaining = Some((budget - spent).max(0));
+    }
+
+    result.wallet_balance = fetch_wallet_balance(state, &address, network).await;
+
+    let cost = estimated_cost.unwrap_or(1);
+    result.reason = match (result.wallet_balance, result.daily_remaining) {
+        (None, _) => Some("Admin wallet balance is unavailable".to_string()),
+        (Some(balance), _) if balance < cost => Some("Admin wallet balance is too low".to_string()),
+        (_, Some(remaining)) if remaining < cost => Some("Daily admin pay budget is used up".to_string()),
+        _ => None,
+    };
+
+    // A deposit into the admin wallet is only spent once it has the confirmations uploads require
+    let required_confirmations = state.read().await.config.min_payment_confirmations;
+    if result.reason.is_none() && required_confirmations > 0 {
+        let utxos = crate::get_address_utxos(state, &address, network).await.unwrap_or_default();
+        if let Some(confirmations) = crate::unconfirmed_funding(state, &utxos, network).await {
+            result.reason = Some(format!(
+                "Admin wallet funding has {} of the {} confirmations required",
+                confirmations, required_confirmations
+            ));
+        }
+    }
+    result.eligible = result.reason.is_none();
+    result
 }
 
 /// Check if admin pay is enabled for a network (public API, no auth required)
@@ -344,52 +611,1101 @@ pub async fn check_admin_pay(
     State(state): State<Arc<RwLock<AppState>>>,
     Json(req): Json<CheckAdminPayRequest>,
 ) -> Json<CheckAdminPayResponse> {
+    let estimated_cost = match req.file_size {
+        Some(size) => {
+            let state = state.read().await;
+            Some(state.bsv.plan_flac_upload(size, req.network).cost.to_sat_i64())
+        }
+        None => None,
+    };
+
+    let eligibility = admin_pay_eligibility(&state, req.network, estimated_cost).await;
+
+    Json(CheckAdminPayResponse {
+        admin_pay_enabled: eligibility.enabled,
+        admin_wallet_address: eligibility.address,
+        eligible: eligibility.eligible,
+        reason: eligibility.reason,
+        wallet_balance: eligibility.wallet_balance,
+        estimated_cost,
+        daily_remaining: eligibility.daily_remaining,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct GetAdminJobsRequest {
+    #[serde(default)]
+    pub key: String,
+}
+
+#[derive(Serialize)]
+pub struct GetAdminJobsResponse {
+    pub success: bool,
+    pub jobs: Vec<AdminJobSummary>,
+}
+
+/// List recent jobs with the funding transaction and sender of each payment
+pub async fn get_admin_jobs(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<GetAdminJobsRequest>,
+) -> Result<Json<GetAdminJobsResponse>, ApiError> {
+    auth.require(&req.key)?;
+
     let state = state.read().await;
-    
-    match state.db.get_admin_config() {
-        Ok(config) => {
-            let (enabled, wif) = if req.network == "testnet" {
-                (config.admin_pay_testnet, config.testnet_wif)
-            } else {
-                (config.admin_pay_mainnet, config.mainnet_wif)
-            };
-            
-            let address = wif.and_then(|w| {
-                BsvService::wif_to_address(&w, &req.network).ok()
-            });
-            
-            Json(CheckAdminPayResponse {
-                admin_pay_enabled: enabled && address.is_some(),
-                admin_wallet_address: if enabled { address } else { None },
-            })
+    let jobs = state.db.get_admin_jobs().map_err(ApiError::database)?;
+
+    Ok(Json(GetAdminJobsResponse { success: true, jobs }))
+}
+
+#[derive(Deserialize)]
+pub struct ListApiKeysRequest {
+    #[serde(default)]
+    pub key: String,
+}
+
+#[derive(Serialize)]
+pub struct ListApiKeysResponse {
+    pub success: bool,
+    pub api_keys: Vec<ApiKey>,
+}
+
+/// List API keys with their usage this month
+pub async fn list_api_keys(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<ListApiKeysRequest>,
+) -> Result<Json<ListApiKeysResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let state = state.read().await;
+    let api_keys = state
+        .db
+        .get_api_keys(api_keys::month_start(Utc::now()))
+        .map_err(ApiError::database)?;
+
+    Ok(Json(ListApiKeysResponse {
+        success: true,
+        api_keys,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    #[serde(default)]
+    pub key: String,
+    pub label: String,
+    /// None for no limit
+    pub monthly_quota_bytes: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct CreateApiKeyResponse {
+    pub success: bool,
+    pub id: String,
+    /// The key itself; only its hash is stored, so it is shown just this once
+    pub api_key: String,
+}
+
+/// Create an API key
+pub async fn create_api_key(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreateApiKeyResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let label = req.label.trim();
+    if label.is_empty() {
+        return Err(ApiError::invalid_request("A label is required"));
+    }
+    if req.monthly_quota_bytes.is_some_and(|quota| quota < 0) {
+        return Err(ApiError::invalid_request("monthly_quota_bytes must not be negative"));
+    }
+
+    let state = state.read().await;
+    let id = uuid::Uuid::new_v4().to_string();
+    let api_key = api_keys::generate_key();
+    state
+        .db
+        .insert_api_key(&id, &api_keys::hash_key(&api_key), label, req.monthly_quota_bytes)
+        .map_err(ApiError::database)?;
+    tracing::info!("API key {} created for {}", id, label);
+
+    Ok(Json(CreateApiKeyResponse {
+        success: true,
+        id,
+        api_key,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct RevokeApiKeyRequest {
+    #[serde(default)]
+    pub key: String,
+    pub id: String,
+}
+
+#[derive(Serialize)]
+pub struct RevokeApiKeyResponse {
+    pub success: bool,
+}
+
+/// Revoke an API key; uploads with it are refused from then on
+pub async fn revoke_api_key(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<RevokeApiKeyRequest>,
+) -> Result<Json<RevokeApiKeyResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let state = state.read().await;
+    if !state.db.set_api_key_enabled(&req.id, false).map_err(ApiError::database)? {
+        return Err(ApiError::new(ErrorCode::NoDataFound, "API key not found"));
+    }
+    tracing::info!("API key {} revoked", req.id);
+
+    Ok(Json(RevokeApiKeyResponse { success: true }))
+}
+
+/// Largest list of txids a single import job accepts
+const MAX_IMPORT_TXIDS: usize = 500;
+
+fn is_txid(txid: &str) -> bool {
+    txid.len() == 64 && txid.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[derive(Deserialize)]
+pub struct ImportTxidRequest {
+    #[serde(default)]
+    pub key: String,
+    pub txid: String,
+    pub network: Option<Network>,
+}
+
+#[derive(Serialize)]
+pub struct ImportTxidResponse {
+    pub success: bool,
+    pub job_id: String,
+    pub already_imported: bool,
+}
+
+/// Add an upload that is already on-chain to the local catalog
+pub async fn import_txid(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<ImportTxidRequest>,
+) -> Result<Json<ImportTxidResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let txid = req.txid.trim().to_lowercase();
+    if !is_txid(&txid) {
+        return Err(ApiError::invalid_request("Invalid TXID format"));
+    }
+
+    let network = req.network.unwrap_or_default();
+    let (job_id, already_imported) = crate::import_upload(&state, &txid, network).await?;
+
+    Ok(Json(ImportTxidResponse {
+        success: true,
+        job_id,
+        already_imported,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ImportTxidsRequest {
+    #[serde(default)]
+    pub key: String,
+    pub txids: Vec<String>,
+    pub network: Option<Network>,
+}
+
+#[derive(Serialize)]
+pub struct ImportTxidsResponse {
+    pub success: bool,
+    pub job_id: String,
+}
+
+/// Import many on-chain uploads through a job; progress is reported on its status page
+pub async fn import_txids(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<ImportTxidsRequest>,
+) -> Result<Json<ImportTxidsResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let mut txids: Vec<String> = Vec::new();
+    for txid in &req.txids {
+        let txid = txid.trim().to_lowercase();
+        if !is_txid(&txid) {
+            return Err(ApiError::invalid_request(format!("Invalid TXID: {}", txid)));
         }
-        Err(_) => {
-            Json(CheckAdminPayResponse {
-                admin_pay_enabled: false,
-                admin_wallet_address: None,
-            })
+        if !txids.contains(&txid) {
+            txids.push(txid);
         }
     }
+    if txids.is_empty() || txids.len() > MAX_IMPORT_TXIDS {
+        return Err(ApiError::invalid_request(format!(
+            "An import must contain between 1 and {} txids",
+            MAX_IMPORT_TXIDS
+        )));
+    }
+
+    let network = req.network.unwrap_or_default();
+    let job_id = uuid::Uuid::new_v4().to_string().replace("-", "");
+    let job = Job::new_import_batch(job_id.clone(), &txids, network);
+
+    let state = state.read().await;
+    state
+        .db
+        .insert_job(&job)
+        .map_err(|e| ApiError::new(ErrorCode::DatabaseError, format!("Failed to create job: {}", e)))?;
+    crate::enqueue_job(&state, QueuedJob {
+        job_id: job_id.clone(),
+        job_type: JobType::Import,
+        address: String::new(),
+        network,
+        admin_pay: false,
+        file_size: 0,
+        paid_satoshis: None,
+    });
+
+    Ok(Json(ImportTxidsResponse { success: true, job_id }))
 }
 
-/// Get admin WIF for a network (internal use only)
-pub fn get_admin_wif_for_network(db: &crate::db::Database, network: &str) -> Option<String> {
-    match db.get_admin_config() {
-        Ok(config) => {
-            if network == "testnet" {
-                if config.admin_pay_testnet {
-                    config.testnet_wif
-                } else {
-                    None
-                }
-            } else {
-                if config.admin_pay_mainnet {
-                    config.mainnet_wif
-                } else {
-                    None
+#[derive(Deserialize)]
+pub struct AbandonedPaymentsRequest {
+    #[serde(default)]
+    pub key: String,
+    pub network: Option<Network>,
+}
+
+#[derive(Serialize)]
+pub struct AbandonedPayment {
+    pub job_id: String,
+    pub job_type: JobType,
+    pub status: JobStatus,
+    pub filename: Option<String>,
+    pub payment_address: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// None when the balance lookup failed
+    pub balance: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct AbandonedPaymentsResponse {
+    pub success: bool,
+    pub network: Network,
+    pub payments: Vec<AbandonedPayment>,
+    pub total_balance: i64,
+    pub sweep_address: Option<String>,
+}
+
+/// Abandoned jobs for a network
+async fn abandoned_jobs(state: &Arc<RwLock<AppState>>, network: Network) -> Result<Vec<Job>, ApiError> {
+    let state = state.read().await;
+    let cutoff = chrono::Utc::now() - chrono::Duration::minutes(state.config.abandoned_payment_minutes);
+    let jobs = state
+        .db
+        .get_abandoned_funded_jobs(cutoff)
+        .map_err(ApiError::database)?;
+    Ok(jobs
+        .into_iter()
+        .filter(|job| job.network.unwrap_or_default() == network)
+        .collect())
+}
+
+async fn sweep_address_for_network(state: &Arc<RwLock<AppState>>, network: Network) -> Option<String> {
+    let state = state.read().await;
+    state.config.sweep_address(network).map(str::to_string)
+}
+
+/// List payment addresses of abandoned jobs with their current balances
+pub async fn get_abandoned_payments(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<AbandonedPaymentsRequest>,
+) -> Result<Json<AbandonedPaymentsResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let network = req.network.unwrap_or_default();
+    let jobs = abandoned_jobs(&state, network).await?;
+
+    let mut payments = Vec::new();
+    for job in jobs {
+        let address = job.payment_address.clone().unwrap_or_default();
+        let utxos = crate::get_address_utxos(&state, &address, network).await.ok();
+        if let Some(utxos) = &utxos {
+            crate::record_payments(&*state.read().await, &job.id, utxos);
+        }
+        let balance = utxos.map(|utxos| utxos.iter().map(|u| u.satoshis).sum::<i64>());
+        payments.push(AbandonedPayment {
+            job_id: job.id,
+            job_type: job.job_type,
+            status: job.status,
+            filename: job.filename,
+            payment_address: address,
+            created_at: job.created_at,
+            balance,
+        });
+    }
+    let total_balance = payments.iter().filter_map(|p| p.balance).sum();
+
+    let sweep_address = sweep_address_for_network(&state, network).await;
+    Ok(Json(AbandonedPaymentsResponse {
+        success: true,
+        network,
+        payments,
+        total_balance,
+        sweep_address,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct SweepResult {
+    pub job_id: String,
+    pub payment_address: String,
+    pub txid: Option<String>,
+    pub satoshis: i64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SweepAbandonedResponse {
+    pub success: bool,
+    pub sweep_address: String,
+    pub results: Vec<SweepResult>,
+    pub total_swept: i64,
+}
+
+/// Send the coins left on every abandoned payment address to the configured sweep address
+pub async fn sweep_abandoned_payments(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<AbandonedPaymentsRequest>,
+) -> Result<Json<SweepAbandonedResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let network = req.network.unwrap_or_default();
+    let sweep_address = sweep_address_for_network(&state, network)
+        .await
+        .ok_or_else(|| ApiError::invalid_request(format!("No sweep address configured for {}", network)))?;
+    BsvService::validate_address(&sweep_address, network)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidAddress, format!("Invalid sweep address: {}", e)))?;
+    let sweep_script = BsvService::create_p2pkh_script(&sweep_address)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidAddress, format!("Invalid sweep address: {}", e)))?;
+
+    let jobs = abandoned_jobs(&state, network).await?;
+
+    let mut results = Vec::new();
+    for job in jobs {
+        let address = job.payment_address.clone().unwrap_or_default();
+        let wif = {
+            let state = state.read().await;
+            crate::job_payment_wif(&state, &job).unwrap_or_default()
+        };
+        let mut result = SweepResult {
+            job_id: job.id.clone(),
+            payment_address: address.clone(),
+            txid: None,
+            satoshis: 0,
+            error: None,
+        };
+
+        let utxos = match crate::get_address_utxos(&state, &address, network).await {
+            Ok(utxos) if utxos.is_empty() => continue,
+            Ok(utxos) => utxos,
+            Err(e) => {
+                result.error = Some(format!("Failed to get UTXOs: {}", e));
+                results.push(result);
+                continue;
+            }
+        };
+        crate::record_payments(&*state.read().await, &job.id, &utxos);
+
+        // Expire unpaid jobs first, so the payment watcher can't start one
+        // while its coins are being swept
+        if job.status == JobStatus::PendingPayment {
+            let state = state.read().await;
+            match state.db.expire_pending_job(&job.id, MessageKey::AbandonedSwept) {
+                Ok(true) => {}
+                Ok(false) => continue, // the payment was just picked up
+                Err(e) => {
+                    result.error = Some(format!("Database error: {}", e));
+                    results.push(result);
+                    continue;
                 }
             }
         }
+
+        let built = {
+            let state = state.read().await;
+            build_sweep_tx(&state.bsv, &wif, &address, &utxos, &sweep_script)
+        };
+        let (raw_tx, amount, fee) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                result.error = Some(e);
+                results.push(result);
+                continue;
+            }
+        };
+
+        match crate::broadcast_tx(&state, None, &raw_tx, network).await {
+            Ok(txid) => {
+                let state = state.read().await;
+                crate::routes::wallet::record_send(&state.db, &address, &sweep_address, amount, fee, &txid, network);
+                tracing::info!("Swept {} sats from abandoned job {} in {}", amount, job.id, txid);
+                result.txid = Some(txid);
+                result.satoshis = amount.to_sat_i64();
+            }
+            Err(e) => result.error = Some(format!("Failed to broadcast: {}", e)),
+        }
+        results.push(result);
+    }
+    let total_swept = results.iter().map(|r| r.satoshis).sum();
+
+    Ok(Json(SweepAbandonedResponse {
+        success: true,
+        sweep_address,
+        results,
+        total_swept,
+    }))
+}
+
+/// Build a transaction moving every UTXO of `from_address` to `to_script`,
+/// returning the raw tx, the amount sent and the fee
+fn build_sweep_tx(
+    bsv: &BsvService,
+    wif: &str,
+    from_address: &str,
+    utxos: &[Utxo],
+    to_script: &[u8],
+) -> Result<(String, Amount, Amount), String> {
+    let sender_script = BsvService::create_p2pkh_script(from_address)
+        .map_err(|e| format!("Invalid address {}: {}", from_address, e))?;
+    let inputs: Vec<(String, u32, i64, Vec<u8>)> = utxos
+        .iter()
+        .map(|u| (u.txid.clone(), u.vout, u.satoshis, sender_script.clone()))
+        .collect();
+
+    let total_input = Amount::sum_sat(utxos.iter().map(|u| u.satoshis)).map_err(|e| e.to_string())?;
+
+    // ~148 bytes per input plus one output and the tx overhead
+    let fee = bsv.fee_for_size(10 + 148 * inputs.len() + 34);
+    match total_input.checked_sub(fee) {
+        Ok(amount) if amount > bsv.dust_limit => bsv
+            .create_transaction(wif, &inputs, &[(to_script.to_vec(), amount)])
+            .map(|raw_tx| (raw_tx, amount, fee)),
+        _ => Err(format!("Balance of {} sats is too small to sweep", total_input)),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GetMetricsRequest {
+    #[serde(default)]
+    pub key: String,
+}
+
+#[derive(Serialize)]
+pub struct MetricsResponse {
+    pub success: bool,
+    pub bitails_keys: Vec<ApiKeyUsage>,
+    /// Backlog depths checked against MAX_PENDING_JOBS and MAX_QUEUED_JOBS
+    pub pending_jobs: i64,
+    pub queued_jobs: usize,
+    pub maintenance: MaintenanceTotals,
+    /// Recent mainnet error rates that decide whether Bitails or WhatsOnChain goes first
+    pub mainnet_providers: Vec<ProviderStatus>,
+}
+
+/// Provider usage counters for operators
+pub async fn get_metrics(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<GetMetricsRequest>,
+) -> Result<Json<MetricsResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let state = state.read().await;
+    let backlog = crate::routes::jobs::backlog(&state)?;
+    Ok(Json(MetricsResponse {
+        success: true,
+        bitails_keys: state.bitails.key_usage(),
+        pending_jobs: backlog.pending_jobs,
+        queued_jobs: backlog.queued_jobs,
+        maintenance: state.maintenance.totals(),
+        mainnet_providers: crate::services::provider_health::mainnet().snapshot(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct RunMaintenanceRequest {
+    #[serde(default)]
+    pub key: String,
+}
+
+#[derive(Serialize)]
+pub struct RunMaintenanceResponse {
+    pub success: bool,
+    pub report: MaintenanceReport,
+}
+
+/// Run storage maintenance now instead of waiting for the next interval
+pub async fn run_maintenance(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<RunMaintenanceRequest>,
+) -> Result<Json<RunMaintenanceResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let state = state.read().await;
+    Ok(Json(RunMaintenanceResponse {
+        success: true,
+        report: crate::run_maintenance(&state),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SelftestRequest {
+    #[serde(default)]
+    pub key: String,
+    /// Defaults to testnet
+    pub network: Option<Network>,
+    /// Build and parse the transactions without broadcasting; required on mainnet
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Size the canary so it is chunked (default), or store it in one transaction
+    pub chunked: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct SelftestResponse {
+    pub success: bool,
+    pub report: SelftestReport,
+}
+
+/// Inscribe a canary file with the admin wallet and download it again,
+/// reporting each stage. Responds once the run is over, which for a live
+/// chunked run takes as long as the upload.
+pub async fn run_selftest(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<SelftestRequest>,
+) -> Result<Json<SelftestResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let network = req.network.unwrap_or(Network::Testnet);
+    if network == Network::Mainnet && !req.dry_run {
+        return Err(ApiError::invalid_request("A mainnet self-test spends real coins; run it with dry_run"));
+    }
+
+    let report = crate::services::selftest::run(&state, network, req.dry_run, req.chunked.unwrap_or(true)).await;
+    if report.success {
+        tracing::info!("Self-test on {} passed in {} ms", network, report.duration_ms);
+    } else {
+        tracing::warn!("Self-test on {} failed at {:?}", network, report.failed_stage);
+    }
+    Ok(Json(SelftestResponse { success: true, report }))
+}
+
+#[derive(Deserialize)]
+pub struct BroadcastAttemptsRequest {
+    #[serde(default)]
+    pub key: String,
+    pub job_id: String,
+}
+
+#[derive(Serialize)]
+pub struct BroadcastAttemptsResponse {
+    pub success: bool,
+    pub attempts: Vec<BroadcastAttemptRecord>,
+}
+
+/// Every provider answer to a job's broadcasts, including ones a fallback recovered from
+pub async fn get_broadcast_attempts(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<BroadcastAttemptsRequest>,
+) -> Result<Json<BroadcastAttemptsResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let state = state.read().await;
+    let attempts = state.db.get_broadcast_attempts(&req.job_id).map_err(ApiError::database)?;
+    Ok(Json(BroadcastAttemptsResponse { success: true, attempts }))
+}
+
+#[derive(Deserialize)]
+pub struct JobLogQuery {
+    #[serde(default)]
+    pub key: String,
+}
+
+/// A job's processing log as plain text, for support. Only the tail is
+/// kept, and secrets were redacted when it was written.
+pub async fn get_job_log(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Path(job_id): Path<String>,
+    Query(query): Query<JobLogQuery>,
+) -> Result<Response, ApiError> {
+    auth.require(&query.key)?;
+
+    {
+        let state = state.read().await;
+        state.db.get_job(&job_id).map_err(ApiError::database)?.ok_or_else(ApiError::job_not_found)?;
+    }
+    let log = crate::services::job_log::read(&job_id)
+        .map_err(|e| ApiError::new(ErrorCode::InternalError, format!("Failed to read job log: {}", e)))?
+        .ok_or_else(|| ApiError::new(ErrorCode::NoDataFound, "Nothing was logged for this job, or its log has expired"))?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], log).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct DecodeTxRequest {
+    #[serde(default)]
+    pub key: String,
+    pub raw_tx: String,
+    pub network: Option<Network>,
+    // Values of the spent outputs in input order, used to compute the fee
+    pub input_values: Option<Vec<i64>>,
+}
+
+#[derive(Serialize)]
+pub struct DecodedInput {
+    pub prev_txid: String,
+    pub prev_vout: u32,
+    pub script_sig_bytes: usize,
+    pub script_sig: String,
+    pub pubkey: Option<String>,
+    pub address: Option<String>,
+    pub satoshis: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct DecodedOutput {
+    pub vout: u32,
+    pub satoshis: i64,
+    pub script_bytes: usize,
+    pub kind: crate::services::tx_parse::ScriptKind,
+    pub summary: String,
+}
+
+#[derive(Serialize)]
+pub struct DecodeTxResponse {
+    pub success: bool,
+    pub txid: String,
+    pub size: usize,
+    pub version: u32,
+    pub lock_time: u32,
+    pub inputs: Vec<DecodedInput>,
+    pub outputs: Vec<DecodedOutput>,
+    pub total_in: Option<i64>,
+    pub total_out: i64,
+    pub fee: Option<i64>,
+    pub fee_rate: Option<f64>,
+}
+
+/// Decode a raw transaction for debugging rejected broadcasts
+pub async fn decode_tx(
+    auth: AdminAuth,
+    Json(req): Json<DecodeTxRequest>,
+) -> Result<Json<DecodeTxResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let raw_tx = req.raw_tx.trim();
+    let network = req.network.unwrap_or_default();
+    let tx = crate::services::tx_parse::parse_transaction(raw_tx)
+        .ok_or_else(|| ApiError::invalid_request("Could not parse raw transaction"))?;
+    let txid = BsvService::txid(raw_tx).map_err(ApiError::invalid_request)?;
+    let size = raw_tx.len() / 2;
+
+    if let Some(values) = &req.input_values {
+        if values.len() != tx.inputs.len() {
+            return Err(ApiError::invalid_request(format!(
+                "Expected {} input values, got {}",
+                tx.inputs.len(),
+                values.len()
+            )));
+        }
+        if let Some(value) = values.iter().find(|value| **value < 0) {
+            return Err(ApiError::invalid_request(format!("Input values can't be negative: {}", value)));
+        }
+    }
+
+    let inputs: Vec<DecodedInput> = tx
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            let pubkey = crate::services::tx_parse::extract_pubkey_from_script_sig(&input.script_sig);
+            DecodedInput {
+                prev_txid: input.prev_txid.clone(),
+                prev_vout: input.prev_vout,
+                script_sig_bytes: input.script_sig.len(),
+                script_sig: match &pubkey {
+                    Some(_) => "P2PKH signature and public key".to_string(),
+                    None if input.script_sig.is_empty() => "empty (unsigned)".to_string(),
+                    None => "non-standard".to_string(),
+                },
+                address: pubkey
+                    .as_ref()
+                    .map(|pk| BsvService::pubkey_bytes_to_address(pk, network)),
+                pubkey: pubkey.map(hex::encode),
+                satoshis: req.input_values.as_ref().map(|values| values[i]),
+            }
+        })
+        .collect();
+
+    let outputs: Vec<DecodedOutput> = tx
+        .outputs
+        .iter()
+        .enumerate()
+        .map(|(vout, output)| {
+            let kind = crate::services::tx_parse::classify_script(&output.script, network);
+            DecodedOutput {
+                vout: vout as u32,
+                satoshis: output.satoshis,
+                script_bytes: output.script.len(),
+                summary: kind.summary(),
+                kind,
+            }
+        })
+        .collect();
+
+    let total_out = Amount::sum_sat(tx.outputs.iter().map(|o| o.satoshis))
+        .map_err(|e| ApiError::invalid_request(format!("Invalid output values: {}", e)))?
+        .to_sat_i64();
+    let total_in = match &req.input_values {
+        Some(values) => Some(
+            Amount::sum_sat(values.iter().copied())
+                .map_err(|e| ApiError::invalid_request(format!("Invalid input values: {}", e)))?
+                .to_sat_i64(),
+        ),
+        None => None,
+    };
+    // Both totals are within the coin supply, so the difference can't overflow
+    let fee = total_in.map(|total_in| total_in - total_out);
+
+    Ok(Json(DecodeTxResponse {
+        success: true,
+        txid,
+        size,
+        version: tx.version,
+        lock_time: tx.lock_time,
+        inputs,
+        outputs,
+        total_in,
+        total_out,
+        fee,
+        fee_rate: fee.filter(|_| size > 0).map(|fee| fee as f64 / size as f64),
+    }))
+}
+
+/// Get admin WIF for a network (internal use only)
+pub fn get_admin_wif_for_network(db: &crate::db::Database, network: Network) -> Option<String> {
+    match db.get_admin_config() {
+        Ok(config) => match network {
+            Network::Mainnet if config.admin_pay_mainnet => config.mainnet_wif,
+            Network::Testnet if config.admin_pay_testnet => config.testnet_wif,
+            _ => None,
+        },
         Err(_) => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{accept, bitails, chain_bitails, reject, test_config, test_state, test_state_with, MockChain};
+
+    fn login_request(key: &str) -> Json<AdminAuthRequest> {
+        Json(AdminAuthRequest { key: key.to_string() })
+    }
+
+    fn set_cookie(response: &Response) -> String {
+        response.headers()[header::SET_COOKIE].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn admin_key_is_checked() {
+        assert!(require_admin_key(&get_admin_key()).is_ok());
+        assert!(require_admin_key("wrong").is_err());
+        assert!(require_admin_key("").is_err());
+        assert!(require_admin_key(&format!("{}x", get_admin_key())).is_err());
+    }
+
+    #[tokio::test]
+    async fn login_issues_a_session_cookie() {
+        let state = test_state();
+        let response = admin_login(State(state.clone()), HeaderMap::new(), login_request(&get_admin_key()))
+            .await
+            .unwrap();
+        let cookie = set_cookie(&response);
+        assert!(cookie.starts_with(&format!("{}=", COOKIE_NAME)));
+        assert!(cookie.contains("HttpOnly"));
+        assert!(!cookie.contains("Secure"));
+
+        let token = cookie_value(cookie.split(';').next().unwrap(), COOKIE_NAME).unwrap();
+        let state = state.read().await;
+        let epoch = state.db.admin_session_epoch().unwrap();
+        assert!(state.admin_sessions.verify(token, &get_admin_key(), epoch, Utc::now().timestamp()));
+    }
+
+    #[tokio::test]
+    async fn login_over_https_marks_the_cookie_secure() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        let response = admin_login(State(test_state()), headers, login_request(&get_admin_key()))
+            .await
+            .unwrap();
+        assert!(set_cookie(&response).ends_with("; Secure"));
+    }
+
+    #[tokio::test]
+    async fn login_rejects_a_wrong_key() {
+        let error = admin_login(State(test_state()), HeaderMap::new(), login_request("wrong"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.into_response().status(), axum::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn logout_ends_every_session() {
+        let state = test_state();
+        let token = {
+            let state = state.read().await;
+            let epoch = state.db.admin_session_epoch().unwrap();
+            state.admin_sessions.issue(&get_admin_key(), epoch, Utc::now().timestamp())
+        };
+        let auth = AdminAuth { session: true, key_in_body: false };
+        let response = admin_logout(State(state.clone()), auth, HeaderMap::new()).await.unwrap();
+        assert!(set_cookie(&response).contains("Max-Age=0"));
+
+        let state = state.read().await;
+        let epoch = state.db.admin_session_epoch().unwrap();
+        assert!(!state.admin_sessions.verify(&token, &get_admin_key(), epoch, Utc::now().timestamp()));
+    }
+
+    #[test]
+    fn body_key_only_works_while_allowed() {
+        let legacy = AdminAuth { session: false, key_in_body: true };
+        assert!(legacy.require(&get_admin_key()).is_ok());
+        assert!(legacy.require("wrong").is_err());
+        assert!(legacy.require("").is_err());
+
+        let session_only = AdminAuth { session: false, key_in_body: false };
+        assert!(session_only.require(&get_admin_key()).is_err());
+        assert!(AdminAuth { session: true, key_in_body: false }.require("").is_ok());
+    }
+
+    /// Admin pay on mainnet from a wallet holding `balance`, under `daily_budget`
+    async fn admin_pay_state(enabled: bool, balance: i64, daily_budget: i64) -> (Arc<RwLock<AppState>>, String) {
+        let mut config = test_config();
+        config.bitails_api_url = bitails(balance, accept).await;
+        config.admin_pay_daily_budget_satoshis = daily_budget;
+        config.min_payment_confirmations = 0;
+        let state = test_state_with(config);
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        {
+            let state = state.read().await;
+            let mut admin_config = state.db.get_admin_config().unwrap();
+            admin_config.admin_pay_mainnet = enabled;
+            admin_config.mainnet_wif = Some(wif);
+            state.db.update_admin_config(&admin_config).unwrap();
+        }
+        (state, address)
+    }
+
+    async fn check_pay(state: &Arc<RwLock<AppState>>) -> CheckAdminPayResponse {
+        let request = CheckAdminPayRequest { network: Network::Mainnet, file_size: Some(10_000) };
+        check_admin_pay(State(state.clone()), Json(request)).await.0
+    }
+
+    #[tokio::test]
+    async fn check_pay_reports_why_admin_pay_is_unavailable() {
+        let (state, _) = admin_pay_state(false, 10_000_000, 0).await;
+        let disabled = check_pay(&state).await;
+        assert!(!disabled.admin_pay_enabled && !disabled.eligible);
+        assert_eq!(disabled.reason.as_deref(), Some("Admin pay is disabled for mainnet"));
+        assert_eq!(disabled.wallet_balance, None);
+
+        let (state, _) = admin_pay_state(true, 100, 0).await;
+        let underfunded = check_pay(&state).await;
+        assert!(underfunded.admin_pay_enabled && !underfunded.eligible);
+        assert_eq!(underfunded.reason.as_deref(), Some("Admin wallet balance is too low"));
+        assert_eq!(underfunded.wallet_balance, Some(100));
+        assert!(underfunded.estimated_cost.unwrap() > 100);
+
+        // Uploads paid today count against the budget
+        let (state, address) = admin_pay_state(true, 10_000_000, 5_000).await;
+        let mut paid = Job::new_upload("paid".to_string(), "a.txt".to_string(), 4, b"data".to_vec(), address, "wif".to_string(), 4_900);
+        paid.payment_wif = None;
+        state.read().await.db.insert_job(&paid).unwrap();
+        let over_budget = check_pay(&state).await;
+        assert!(!over_budget.eligible);
+        assert_eq!(over_budget.reason.as_deref(), Some("Daily admin pay budget is used up"));
+        assert_eq!(over_budget.daily_remaining, Some(100));
+    }
+
+    #[tokio::test]
+    async fn check_pay_accepts_a_funded_wallet_within_budget() {
+        let (state, address) = admin_pay_state(true, 10_000_000, 1_000_000).await;
+        let eligible = check_pay(&state).await;
+        assert!(eligible.eligible, "{:?}", eligible.reason);
+        assert_eq!(eligible.reason, None);
+        assert_eq!(eligible.admin_wallet_address, Some(address));
+        assert_eq!(eligible.wallet_balance, Some(10_000_000));
+        assert_eq!(eligible.daily_remaining, Some(1_000_000));
+        let expected = state.read().await.bsv.plan_flac_upload(10_000, Network::Mainnet).cost.to_sat_i64();
+        assert_eq!(eligible.estimated_cost, Some(expected));
+    }
+
+    fn session() -> AdminAuth {
+        AdminAuth { session: true, key_in_body: false }
+    }
+
+    #[tokio::test]
+    async fn abandoned_payments_total_their_balances() {
+        let mut config = test_config();
+        config.bitails_api_url = bitails(5_000, accept).await;
+        config.abandoned_payment_minutes = 60;
+        let state = test_state_with(config);
+        let pending = |id: &str, network: Network, age_minutes: i64| {
+            let (wif, address) = BsvService::generate_keypair(network);
+            let mut job = Job::new_upload(id.to_string(), "a.txt".to_string(), 4, b"data".to_vec(), address, wif, 1000)
+                .with_network(network);
+            job.created_at = Utc::now() - chrono::Duration::minutes(age_minutes);
+            job
+        };
+        {
+            let state = state.read().await;
+            for job in [
+                pending("old-1", Network::Mainnet, 120),
+                pending("old-2", Network::Mainnet, 600),
+                pending("recent", Network::Mainnet, 5),
+                pending("testnet", Network::Testnet, 120),
+            ] {
+                state.db.insert_job(&job).unwrap();
+            }
+        }
+
+        let request = AbandonedPaymentsRequest { key: String::new(), network: None };
+        let Json(response) = get_abandoned_payments(State(state.clone()), session(), Json(request)).await.unwrap();
+        let mut ids: Vec<&str> = response.payments.iter().map(|p| p.job_id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, ["old-1", "old-2"]);
+        assert!(response.payments.iter().all(|p| p.balance == Some(5_000)));
+        assert_eq!(response.total_balance, 10_000);
+        assert_eq!(response.network, Network::Mainnet);
+    }
+
+    #[tokio::test]
+    async fn decode_tx_classifies_a_chunk_transaction() {
+        // The key whose secret is 1, spending a made-up UTXO into chunk 2 of a track
+        let wif = "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn";
+        let address = "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH";
+        let bsv = BsvService::for_tests();
+        let utxo = ("11".repeat(32), 3, 2_000, BsvService::create_p2pkh_script(address).unwrap());
+        let chunk_script = bsv.create_flac_chunk_script(2, 5, &[0xaa; 300]);
+        let change_script = BsvService::create_p2pkh_script(address).unwrap();
+        let outputs = [(chunk_script, Amount::from_sat_const(1)), (change_script, Amount::from_sat_const(1_500))];
+        let raw_tx = bsv.create_transaction(wif, &[utxo], &outputs).unwrap();
+
+        let request = DecodeTxRequest { key: String::new(), raw_tx: raw_tx.clone(), network: None, input_values: Some(vec![2_000]) };
+        let decoded = decode_tx(session(), Json(request)).await.unwrap().0;
+        assert_eq!(decoded.txid, BsvService::txid(&raw_tx).unwrap());
+        assert_eq!((decoded.version, decoded.lock_time, decoded.size), (1, 0, raw_tx.len() / 2));
+
+        let input = &decoded.inputs[0];
+        assert_eq!((input.prev_txid.as_str(), input.prev_vout), ("11".repeat(32).as_str(), 3));
+        assert_eq!(input.script_sig, "P2PKH signature and public key");
+        assert_eq!(input.address.as_deref(), Some(address));
+
+        let summaries: Vec<&str> = decoded.outputs.iter().map(|output| output.summary.as_str()).collect();
+        assert_eq!(summaries, ["flacstore-chunk index 2 (300 bytes)", &format!("P2PKH to {}", address)]);
+        assert_eq!((decoded.total_in, decoded.total_out, decoded.fee), (Some(2_000), 1_501, Some(499)));
+
+        // Input values have to match the inputs
+        let request = DecodeTxRequest { key: String::new(), raw_tx: raw_tx.clone(), network: None, input_values: Some(vec![]) };
+        let error = decode_tx(session(), Json(request)).await.err().unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn decode_tx_refuses_negative_or_overflowing_values() {
+        let wif = "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn";
+        let script = BsvService::create_p2pkh_script("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH").unwrap();
+        let decode = |raw_tx: String, input_values: Vec<i64>| async move {
+            let request = DecodeTxRequest { key: String::new(), raw_tx, network: None, input_values: Some(input_values) };
+            decode_tx(session(), Json(request)).await.err().unwrap()
+        };
+        let bsv = BsvService::for_tests();
+        let utxos = |values: &[i64]| -> Vec<_> { values.iter().map(|v| ("11".repeat(32), 0, *v, script.clone())).collect() };
+        let raw_tx = bsv.create_transaction(wif, &utxos(&[2_000, 2_000]), &[(script.clone(), Amount::from_sat_const(1_500))]).unwrap();
+
+        let error = decode(raw_tx.clone(), vec![2_000, -1]).await;
+        assert_eq!((error.status, error.code), (axum::http::StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest));
+        assert!(error.message.contains("negative"), "{}", error.message);
+        let error = decode(raw_tx, vec![i64::MAX, i64::MAX]).await;
+        assert_eq!((error.status, error.code), (axum::http::StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest));
+
+        // Output values past the coin supply, as only a hand-made transaction has
+        let mut tx = bsv.create_transaction(wif, &utxos(&[2_000]), &[(script.clone(), Amount::from_sat_const(1_500))]).unwrap();
+        let value = hex::encode(1_500u64.to_le_bytes());
+        assert_eq!(tx.matches(&value).count(), 1);
+        tx = tx.replace(&value, &hex::encode(u64::MAX.to_le_bytes()));
+        let error = decode(tx, vec![2_000]).await;
+        assert_eq!((error.status, error.code), (axum::http::StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest));
+        assert!(error.message.starts_with("Invalid output values"), "{}", error.message);
+    }
+
+    async fn set_mainnet_wif(state: &Arc<RwLock<AppState>>, wif: &str) {
+        let state = state.read().await;
+        let mut admin_config = state.db.get_admin_config().unwrap();
+        admin_config.mainnet_wif = Some(wif.to_string());
+        state.db.update_admin_config(&admin_config).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotation_sweeps_the_old_wallet_before_switching_keys() {
+        let (old_wif, old_address) = BsvService::generate_keypair(Network::Mainnet);
+        let (new_wif, new_address) = BsvService::generate_keypair(Network::Mainnet);
+        let chain = MockChain::default();
+        let mut config = test_config();
+        config.bitails_api_url = chain_bitails(&chain, 50_000, accept).await;
+        let state = test_state_with(config);
+        set_mainnet_wif(&state, &old_wif).await;
+
+        let request = RotateWalletRequest { key: String::new(), network: Network::Mainnet, new_wif: new_wif.clone() };
+        let response = rotate_admin_wallet(State(state.clone()), session(), Json(request)).await.unwrap().0;
+        assert_eq!(response.old_address.as_deref(), Some(old_address.as_str()));
+        assert_eq!(response.new_address, new_address);
+
+        // One input signed by the old key, all of its value less the fee to the new address
+        let sweep = crate::services::tx_parse::parse_transaction(&chain.tx(response.sweep_txid.as_ref().unwrap()).unwrap()).unwrap();
+        let pubkey = crate::services::tx_parse::extract_pubkey_from_script_sig(&sweep.inputs[0].script_sig).unwrap();
+        assert_eq!(BsvService::pubkey_bytes_to_address(&pubkey, Network::Mainnet), old_address);
+        assert_eq!(sweep.outputs.len(), 1);
+        assert_eq!(sweep.outputs[0].script, BsvService::create_p2pkh_script(&new_address).unwrap());
+        assert_eq!(sweep.outputs[0].satoshis, response.swept_satoshis);
+        assert!(response.swept_satoshis > 49_000 && response.swept_satoshis < 50_000);
+        assert_eq!(state.read().await.db.get_admin_config().unwrap().mainnet_wif, Some(new_wif));
+    }
+
+    #[tokio::test]
+    async fn failed_sweep_keeps_the_old_key() {
+        let (old_wif, _) = BsvService::generate_keypair(Network::Mainnet);
+        let (new_wif, _) = BsvService::generate_keypair(Network::Mainnet);
+        let mut config = test_config();
+        config.bitails_api_url = bitails(50_000, reject).await;
+        let state = test_state_with(config);
+        set_mainnet_wif(&state, &old_wif).await;
+
+        let request = RotateWalletRequest { key: String::new(), network: Network::Mainnet, new_wif };
+        let error = rotate_admin_wallet(State(state.clone()), session(), Json(request)).await.err().unwrap();
+        assert_eq!(error.code, ErrorCode::BroadcastFailed);
+        assert_eq!(state.read().await.db.get_admin_config().unwrap().mainnet_wif, Some(old_wif));
+    }
+}
@@ -1,21 +1,68 @@
+use askama::Template;
 use axum::{
-    extract::State,
-    response::{Html, Json},
+    extract::{Query, State},
+    response::{Json, Response},
 };
+use serde::Deserialize;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::models::JobSummary;
+use crate::models::{JobSummary, JobType};
+use crate::routes::error::ApiError;
+use crate::routes::page;
 use crate::AppState;
 
-pub async fn dashboard_page() -> Html<String> {
-    Html(include_str!("../../templates/dashboard.html").to_string())
+/// Job type filters offered on the dashboard, as (value, label)
+const JOB_TYPE_FILTERS: [(&str, &str); 8] = [
+    ("", "All jobs"),
+    ("upload", "Uploads"),
+    ("download", "Downloads"),
+    ("flac_upload", "FLAC uploads"),
+    ("flac_download", "FLAC downloads"),
+    ("flac_batch_download", "Batch downloads"),
+    ("send", "Wallet sends"),
+    ("import", "Imports"),
+];
+
+pub struct FilterOption {
+    pub value: &'static str,
+    pub label: &'static str,
+    pub selected: bool,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard.html")]
+pub struct DashboardTemplate {
+    pub filters: Vec<FilterOption>,
+}
+
+#[derive(Deserialize)]
+pub struct JobsQuery {
+    #[serde(rename = "type")]
+    pub job_type: Option<String>,
+}
+
+/// Dashboard page, with the job type filter preselected from `?type=`
+pub async fn dashboard_page(Query(query): Query<JobsQuery>) -> Response {
+    let selected = query.job_type.unwrap_or_default();
+    let filters = JOB_TYPE_FILTERS
+        .iter()
+        .map(|&(value, label)| FilterOption {
+            value,
+            label,
+            selected: value == selected,
+        })
+        .collect();
+    page::render(&DashboardTemplate { filters })
 }
 
 pub async fn get_jobs(
     State(state): State<Arc<RwLock<AppState>>>,
-) -> Json<Vec<JobSummary>> {
+    Query(query): Query<JobsQuery>,
+) -> Result<Json<Vec<JobSummary>>, ApiError> {
+    // Unknown or empty types list everything
+    let job_type = query.job_type.as_deref().and_then(JobType::from_str);
     let state = state.read().await;
-    let jobs = state.db.get_all_jobs().unwrap_or_default();
-    Json(jobs)
+    let jobs = state.db.get_all_jobs(job_type).map_err(ApiError::database)?;
+    Ok(Json(jobs))
 }
@@ -0,0 +1,245 @@
+use axum::{
+    extract::State,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::models::{Amount, ErrorCode, Network};
+use crate::routes::admin::AdminAuth;
+use crate::routes::error::ApiError;
+use crate::services::bsv::BsvService;
+use crate::AppState;
+
+// Maximum number of history transactions inspected per request
+const MAX_FUNDING_TXS: usize = 20;
+
+#[derive(Deserialize)]
+pub struct AddressFundingRequest {
+    pub address: String,
+    pub network: Option<Network>,
+    #[serde(default)]
+    pub key: String,
+}
+
+#[derive(Serialize)]
+pub struct FundingOutput {
+    pub vout: u32,
+    pub satoshis: i64,
+    pub unspent: bool,
+}
+
+#[derive(Serialize)]
+pub struct FundingInput {
+    pub prev_txid: String,
+    pub prev_vout: u32,
+    pub pubkey: Option<String>,
+    pub address: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FundingTx {
+    pub txid: String,
+    pub blockheight: Option<i64>,
+    pub confirmations: Option<i64>,
+    pub outputs: Vec<FundingOutput>,
+    pub inputs: Vec<FundingInput>,
+}
+
+#[derive(Serialize)]
+pub struct AddressFundingResponse {
+    pub success: bool,
+    pub address: String,
+    pub network: Network,
+    pub total_received: Amount,
+    pub unspent_satoshis: Amount,
+    pub funding: Vec<FundingTx>,
+}
+
+/// List the transactions that funded an address, with decoded inputs (admin only)
+pub async fn address_funding(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<AddressFundingRequest>,
+) -> Result<Json<AddressFundingResponse>, ApiError> {
+    auth.require(&req.key)?;
+    let address = req.address.trim().to_string();
+    let network = req.network.unwrap_or_default();
+
+    let address_script = BsvService::create_p2pkh_script(&address)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidAddress, format!("Invalid address: {}", e)))?;
+
+    let history = crate::fetch_address_history(&state, &address, network)
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::TxFetchFailed, format!("Failed to get address history: {}", e)))?;
+
+    // Unspent outputs tell us which funding outputs are still spendable
+    let unspent = crate::get_address_utxos(&state, &address, network).await.unwrap_or_default();
+
+    // Chain height is only needed for confirmations, so a failure is not fatal
+    let tip_height = crate::get_chain_height(network).await.ok();
+
+    let mut funding: Vec<FundingTx> = Vec::new();
+    let mut total_received = Amount::ZERO;
+    let invalid_amount = |e: crate::models::AmountError| {
+        ApiError::new(ErrorCode::TxFetchFailed, format!("Invalid amount in funding transactions: {}", e))
+    };
+
+    for entry in history.iter().rev().take(MAX_FUNDING_TXS) {
+        let tx_hex = match crate::fetch_tx_raw(&state, &entry.txid, network).await {
+            Ok(hex) => hex,
+            Err(e) => {
+                tracing::warn!("Failed to fetch funding tx {}: {}", entry.txid, e);
+                continue;
+            }
+        };
+
+        let tx = match crate::services::tx_parse::parse_transaction(&tx_hex) {
+            Some(tx) => tx,
+            None => continue,
+        };
+
+        let outputs: Vec<FundingOutput> = tx
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, out)| out.script == address_script)
+            .map(|(vout, out)| FundingOutput {
+                vout: vout as u32,
+                satoshis: out.satoshis,
+                unspent: unspent
+                    .iter()
+                    .any(|u| u.txid == entry.txid && u.vout == vout as u32),
+            })
+            .collect();
+
+        // Transactions that only spend from this address are not funding
+        if outputs.is_empty() {
+            continue;
+        }
+
+        total_received = Amount::sum_sat(outputs.iter().map(|o| o.satoshis))
+            .and_then(|received| total_received.checked_add(received))
+            .map_err(invalid_amount)?;
+
+        let inputs = tx
+            .inputs
+            .iter()
+            .map(|input| {
+                let pubkey = crate::services::tx_parse::extract_pubkey_from_script_sig(&input.script_sig);
+                FundingInput {
+                    prev_txid: input.prev_txid.clone(),
+                    prev_vout: input.prev_vout,
+                    address: pubkey
+                        .as_ref()
+                        .map(|pk| BsvService::pubkey_bytes_to_address(pk, network)),
+                    pubkey: pubkey.map(hex::encode),
+                }
+            })
+            .collect();
+
+        let blockheight = entry.blockheight.filter(|h| *h > 0);
+        let confirmations = match (blockheight, tip_height) {
+            (Some(height), Some(tip)) => Some(tip - height + 1),
+            (None, _) => Some(0),
+            _ => None,
+        };
+
+        funding.push(FundingTx {
+            txid: entry.txid.clone(),
+            blockheight,
+            confirmations,
+            outputs,
+            inputs,
+        });
+    }
+
+    let unspent_satoshis = Amount::sum_sat(unspent.iter().map(|u| u.satoshis)).map_err(invalid_amount)?;
+    Ok(Json(AddressFundingResponse {
+        success: true,
+        address,
+        network,
+        total_received,
+        unspent_satoshis,
+        funding,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, test_config, test_state_with};
+    use axum::{extract::Path, routing::{get, post}, Router};
+
+    /// A Bitails stand-in for one address funded by `tx_hex`, whose first output is unspent
+    async fn bitails_funding(address: String, tx_hex: String) -> String {
+        let txid = BsvService::txid(&tx_hex).unwrap();
+        let (history_txid, unspent_txid) = (txid.clone(), txid);
+        serve(
+            Router::new()
+                .route(
+                    "/address/:address/history",
+                    get(move || async move { Json(serde_json::json!({ "history": [{ "txid": history_txid }] })) }),
+                )
+                .route(
+                    "/address/:address/unspent",
+                    get(move || async move {
+                        Json(serde_json::json!({
+                            "address": address,
+                            "unspent": [{ "txid": unspent_txid, "vout": 0, "satoshis": 3_000 }],
+                        }))
+                    }),
+                )
+                .route("/download/tx/:txid", get(move |Path(_): Path<String>| async move { hex::decode(tx_hex).unwrap() })),
+        )
+        .await
+    }
+
+    async fn request(state: &Arc<RwLock<AppState>>, body: serde_json::Value) -> (reqwest::StatusCode, serde_json::Value) {
+        let app = serve(Router::new().route("/funding", post(address_funding)).with_state(state.clone())).await;
+        let response = reqwest::Client::new().post(format!("{}/funding", app)).json(&body).send().await.unwrap();
+        (response.status(), response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn reports_a_funded_address() {
+        let (_, address) = BsvService::generate_keypair(Network::Mainnet);
+        let script = BsvService::create_p2pkh_script(&address).unwrap();
+        let bsv = BsvService::for_tests();
+        let tx_hex = bsv.test_transaction(&[
+            (script.clone(), Amount::from_sat(3_000).unwrap()),
+            (script, Amount::from_sat(2_000).unwrap()),
+        ]);
+
+        let mut config = test_config();
+        config.bitails_api_url = bitails_funding(address.clone(), tx_hex.clone()).await;
+        config.admin_key_in_body = true;
+        let state = test_state_with(config);
+
+        let key = crate::routes::admin::get_admin_key();
+        let (status, body) = request(&state, serde_json::json!({ "address": address, "key": key })).await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+        assert_eq!(body["total_received"], 5_000);
+        assert_eq!(body["unspent_satoshis"], 3_000);
+        let funding = &body["funding"][0];
+        assert_eq!(funding["txid"], BsvService::txid(&tx_hex).unwrap());
+        assert_eq!(funding["outputs"][0]["unspent"], true);
+        assert_eq!(funding["outputs"][1]["unspent"], false);
+        assert!(funding["inputs"][0]["address"].is_string());
+    }
+
+    #[tokio::test]
+    async fn requires_admin() {
+        let mut config = test_config();
+        config.admin_key_in_body = true;
+        let state = test_state_with(config);
+        let (_, address) = BsvService::generate_keypair(Network::Mainnet);
+
+        for key in ["", "wrong"] {
+            let (status, body) = request(&state, serde_json::json!({ "address": address, "key": key })).await;
+            assert_eq!(status, reqwest::StatusCode::UNAUTHORIZED);
+            assert_eq!(body["error"]["code"], "UNAUTHORIZED");
+        }
+    }
+}
@@ -1,77 +1,823 @@
 use axum::{
-    extract::State,
-    response::{Html, Json},
+    async_trait,
+    body::Body,
+    extract::{FromRequest, Path, Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Json, Response},
     Form,
 };
-use serde::{Deserialize, Serialize};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{OwnedSemaphorePermit, RwLock};
+use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
-use crate::models::Job;
+use crate::models::{ErrorCode, Job, JobStatus, JobType, MessageKey, Network, StatusMessage};
+use crate::routes::admin::AdminAuth;
+use crate::routes::error::ApiError;
+use crate::services::content_type;
+use crate::services::scheduler::QueuedJob;
+use crate::services::tx_parse::{
+    assemble_chunks, chunk_matches, data_pushes, find_data_output, find_in_outputs, parse_flac_output, FlacData, ManifestMetadata,
+};
 use crate::AppState;
 
+/// Retry-After for a stream or discard download refused while every processing slot is busy
+const INLINE_SLOT_RETRY_AFTER_SECONDS: u64 = 10;
+
 pub async fn download_page() -> Html<String> {
     Html(include_str!("../../templates/download.html").to_string())
 }
 
+/// Request body accepted as JSON or as a urlencoded form, by Content-Type
+pub struct JsonOrForm<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for JsonOrForm<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.trim_start().starts_with("application/json"));
+
+        if is_json {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(|e| ApiError::invalid_request(e.body_text()))?;
+            Ok(JsonOrForm(value))
+        } else {
+            let Form(value) = Form::<T>::from_request(req, state)
+                .await
+                .map_err(|e| ApiError::invalid_request(e.body_text()))?;
+            Ok(JsonOrForm(value))
+        }
+    }
+}
+
+/// What a download does with the file it fetches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadMode {
+    /// Run as a job that saves the file and links to it
+    #[default]
+    Save,
+    /// Return the file in the response without writing it to disk
+    Stream,
+    /// Fetch and verify the file only, e.g. for integrity checks
+    Discard,
+}
+
 #[derive(Deserialize)]
 pub struct StartDownloadInput {
     pub txid: String,
+    pub network: Option<Network>,
+    #[serde(default)]
+    pub mode: DownloadMode,
+    /// With the admin key, create the job even past the backlog ceilings
+    #[serde(default)]
+    pub bypass_backlog: bool,
+    #[serde(default)]
+    pub key: String,
+    /// Download in the request and answer with the link instead of queueing
+    #[serde(default)]
+    pub wait: bool,
 }
 
 #[derive(Serialize)]
 pub struct StartDownloadResponse {
     pub success: bool,
-    pub job_id: Option<String>,
-    pub redirect_url: Option<String>,
-    pub error: Option<String>,
+    pub job_id: String,
+    pub owner_token: Option<String>,
+    pub redirect_url: String,
+    /// Only with `wait`: the saved file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyDownloadResponse {
+    pub success: bool,
+    pub txid: String,
+    pub filename: String,
+    pub size: usize,
+    pub sha256: String,
 }
 
 pub async fn start_download(
     State(state): State<Arc<RwLock<AppState>>>,
-    Form(input): Form<StartDownloadInput>,
-) -> Json<StartDownloadResponse> {
+    admin: AdminAuth,
+    JsonOrForm(input): JsonOrForm<StartDownloadInput>,
+) -> Result<Response, ApiError> {
     let txid = input.txid.trim().to_string();
+    let network = input.network.unwrap_or_default();
 
     // Validate TXID format (64 hex characters)
     if txid.len() != 64 || !txid.chars().all(|c| c.is_ascii_hexdigit()) {
-        return Json(StartDownloadResponse {
-            success: false,
-            job_id: None,
-            redirect_url: None,
-            error: Some("Invalid TXID format. Must be 64 hex characters.".to_string()),
-        });
+        return Err(ApiError::invalid_request("Invalid TXID format. Must be 64 hex characters."));
+    }
+
+    {
+        let state = state.read().await;
+        crate::routes::jobs::admit_new_job(&state, &admin, input.bypass_backlog, &input.key)?;
+    }
+
+    match input.mode {
+        DownloadMode::Save => {}
+        DownloadMode::Stream => {
+            let _slot = inline_slot(&state).await?;
+            let (file_data, filename, content_type) = fetch_file(&state, &txid, network).await?;
+            return Response::builder()
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_DISPOSITION, content_disposition(&filename))
+                .header(header::CONTENT_LENGTH, file_data.len())
+                .body(Body::from(file_data))
+                .map_err(|e| ApiError::new(ErrorCode::InternalError, e.to_string()));
+        }
+        DownloadMode::Discard => {
+            let _slot = inline_slot(&state).await?;
+            let (file_data, filename, _) = fetch_file(&state, &txid, network).await?;
+            return Ok(Json(VerifyDownloadResponse {
+                success: true,
+                txid,
+                filename,
+                size: file_data.len(),
+                sha256: hex::encode(Sha256::digest(&file_data)),
+            })
+            .into_response());
+        }
     }
 
     // Create job
     let job_id = Uuid::new_v4().to_string().replace("-", "");
-    let job = Job::new_download(job_id.clone(), txid.clone());
+    let job = Job::new_download(job_id.clone(), txid.clone()).with_network(network);
 
     // Save job to database
     {
         let state_guard = state.read().await;
-        if let Err(e) = state_guard.db.insert_job(&job) {
-            return Json(StartDownloadResponse {
-                success: false,
-                job_id: None,
-                redirect_url: None,
-                error: Some(format!("Failed to create job: {}", e)),
-            });
-        }
+        state_guard
+            .db
+            .insert_job(&job)
+            .map_err(|e| ApiError::new(ErrorCode::DatabaseError, format!("Failed to create job: {}", e)))?;
     }
 
-    // Start download process in background
-    let state_clone = state.clone();
-    let job_id_clone = job_id.clone();
-    tokio::spawn(async move {
-        crate::process_download(state_clone, job_id_clone, Some(txid)).await;
-    });
+    // Every upload this serves fits one transaction, so waiting is always inline
+    let mut saved = None;
+    if input.wait {
+        crate::process_download(state.clone(), job_id.clone(), Some(txid), network).await;
+        saved = Some(inline_download_result(&state, &job_id).await?);
+    } else {
+        let state_guard = state.read().await;
+        crate::enqueue_job(&state_guard, QueuedJob {
+            job_id: job_id.clone(),
+            job_type: JobType::Download,
+            address: String::new(),
+            network,
+            admin_pay: false,
+            file_size: 0,
+            paid_satoshis: None,
+        });
+    }
+    let (download_link, filename) = match saved {
+        Some((link, filename)) => (Some(link), filename),
+        None => (None, None),
+    };
 
-    Json(StartDownloadResponse {
+    Ok(Json(StartDownloadResponse {
         success: true,
-        job_id: Some(job_id.clone()),
-        redirect_url: Some(format!("/status/{}", job_id)),
-        error: None,
+        job_id: job_id.clone(),
+        owner_token: job.owner_token,
+        redirect_url: format!("/status/{}", job_id),
+        download_link,
+        filename,
     })
+    .into_response())
+}
+
+/// A processing slot for a stream or discard download, which fetches in the
+/// request instead of queueing, so it counts toward MAX_CONCURRENT_JOBS
+async fn inline_slot(state: &Arc<RwLock<AppState>>) -> Result<OwnedSemaphorePermit, ApiError> {
+    state.read().await.scheduler.try_slot().ok_or_else(|| {
+        ApiError::new(ErrorCode::ServerBusy, "Every processing slot is busy, try again later")
+            .with_retry_after(INLINE_SLOT_RETRY_AFTER_SECONDS)
+    })
+}
+
+/// (download link, filename) of a download job that just ran in the
+/// request, or the error it recorded
+pub async fn inline_download_result(
+    state: &Arc<RwLock<AppState>>,
+    job_id: &str,
+) -> Result<(String, Option<String>), ApiError> {
+    let job = state
+        .read()
+        .await
+        .db
+        .get_job(job_id)
+        .map_err(ApiError::database)?
+        .ok_or_else(|| ApiError::new(ErrorCode::JobNotFound, "Job not found"))?;
+    match (job.status, job.download_link) {
+        (JobStatus::Complete, Some(link)) => Ok((link, job.filename)),
+        _ => Err(ApiError::new(job.error_code.unwrap_or(ErrorCode::InternalError), job.message)),
+    }
+}
+
+/// Fetch an upload's file in the request itself, checking the transaction
+/// really is the one asked for: an upfile transaction, a single-transaction
+/// FLAC upload or a FLAC manifest and its chunks. Nothing is written to disk.
+/// Returns the file, its name and its content type.
+async fn fetch_file(
+    state: &Arc<RwLock<AppState>>,
+    txid: &str,
+    network: Network,
+) -> Result<(Vec<u8>, String, String), ApiError> {
+    let tx_hex = crate::fetch_tx_raw(state, txid, network)
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::TxFetchFailed, format!("Failed to fetch transaction: {}", e)))?;
+    if !crate::services::tx_parse::is_tx_hex_for(&tx_hex, txid) {
+        return Err(ApiError::new(ErrorCode::TxFetchFailed, "Fetched transaction does not match the txid"));
+    }
+    if let Some(file) = crate::services::tx_parse::extract_op_return_from_tx(&tx_hex) {
+        let content_type = content_type::recorded_or_detect(&file.mime_type, &file.data, &file.filename);
+        return Ok((file.data, file.filename, content_type));
+    }
+    match find_in_outputs(&tx_hex, parse_flac_output) {
+        Some(FlacData::File(file)) => {
+            let content_type = content_type::detect(&file.data, &file.filename);
+            Ok((file.data, file.filename, content_type))
+        }
+        Some(FlacData::Manifest(manifest)) => {
+            let data = fetch_manifest_chunks(state, &manifest, network).await?;
+            let content_type = content_type::detect(&data, &manifest.filename);
+            Ok((data, manifest.filename, content_type))
+        }
+        None => Err(ApiError::new(ErrorCode::NoDataFound, StatusMessage::from(MessageKey::NoOpReturnData).english())),
+    }
+}
+
+/// Fetch the chunks a manifest lists and assemble them in memory, with the
+/// same hash, index and size checks as a saved FLAC download. Files over
+/// MAX_INLINE_DOWNLOAD_BYTES are refused before or while their chunks are fetched.
+async fn fetch_manifest_chunks(
+    state: &Arc<RwLock<AppState>>,
+    manifest: &ManifestMetadata,
+    network: Network,
+) -> Result<Vec<u8>, ApiError> {
+    let max_bytes = state.read().await.config.max_inline_download_bytes;
+    let too_large = || {
+        ApiError::invalid_request(format!(
+            "File is larger than {} bytes, too large to fetch in the request; download it with mode=save",
+            max_bytes
+        ))
+    };
+    if manifest.size.is_some_and(|size| size > max_bytes) {
+        return Err(too_large());
+    }
+
+    let total_chunks = manifest.chunk_txids.len();
+    let mut chunks = Vec::with_capacity(total_chunks);
+    let mut fetched_bytes = 0usize;
+    for (i, chunk_txid) in manifest.chunk_txids.iter().enumerate() {
+        let chunk = crate::fetch_flac_chunk(state, chunk_txid, network)
+            .await
+            .map_err(|e| {
+                let message = MessageKey::ChunkFetchFailed.with("i", i + 1).with("error", e);
+                ApiError::new(ErrorCode::ChunkFetchFailed, message.english())
+            })?
+            .ok_or_else(|| {
+                ApiError::new(ErrorCode::NoDataFound, MessageKey::ChunkExtractFailed.with("i", i + 1).english())
+            })?;
+        if !chunk_matches(&manifest.chunk_hashes, chunk.0, &chunk.1) {
+            let message = MessageKey::ChunkHashMismatch.with("index", chunk.0).with("txid", chunk_txid.as_str());
+            return Err(ApiError::new(ErrorCode::ChunkHashMismatch, message.english()));
+        }
+        fetched_bytes = fetched_bytes.saturating_add(chunk.1.len());
+        if fetched_bytes > max_bytes {
+            return Err(too_large());
+        }
+        chunks.push(chunk);
+    }
+
+    let data = assemble_chunks(chunks, total_chunks).map_err(|mismatch| {
+        let list = |indices: &[u32]| indices.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+        let message = MessageKey::ChunkIndexMismatch
+            .with("last", total_chunks.saturating_sub(1))
+            .with("missing", list(&mismatch.missing))
+            .with("duplicated", list(&mismatch.duplicated))
+            .with("unexpected", list(&mismatch.unexpected));
+        ApiError::new(ErrorCode::ChunkIndexMismatch, message.english())
+    })?;
+    if let Some(expected) = manifest.size {
+        if data.len() != expected {
+            let message = MessageKey::SizeMismatch.with("got", data.len()).with("expected", expected);
+            return Err(ApiError::new(ErrorCode::SizeMismatch, message.english()));
+        }
+    }
+    Ok(data)
+}
+
+#[derive(Deserialize)]
+pub struct VerifyFileRequest {
+    pub txid: String,
+    pub expected_sha256: String,
+    pub network: Option<Network>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyFileResponse {
+    pub success: bool,
+    pub txid: String,
+    pub filename: String,
+    pub size: usize,
+    pub sha256: String,
+    pub expected_sha256: String,
+    /// Whether the file on chain hashes to `expected_sha256`
+    pub matches: bool,
+}
+
+/// Rebuild an upload's file from the chain and check a claimed SHA-256
+/// against it, without saving the file
+pub async fn verify_file(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Json(req): Json<VerifyFileRequest>,
+) -> Result<Json<VerifyFileResponse>, ApiError> {
+    let txid = req.txid.trim().to_string();
+    let network = req.network.unwrap_or_default();
+    let expected_sha256 = req.expected_sha256.trim().to_ascii_lowercase();
+
+    if txid.len() != 64 || !txid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::invalid_request("Invalid TXID format. Must be 64 hex characters."));
+    }
+    if expected_sha256.len() != 64 || !expected_sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::invalid_request("expected_sha256 must be 64 hex characters"));
+    }
+
+    let (file_data, filename, _) = fetch_file(&state, &txid, network).await?;
+    let sha256 = hex::encode(Sha256::digest(&file_data));
+    Ok(Json(VerifyFileResponse {
+        success: true,
+        txid,
+        filename,
+        size: file_data.len(),
+        matches: sha256 == expected_sha256,
+        sha256,
+        expected_sha256,
+    }))
+}
+
+/// Directory that completed downloads are written to
+pub const DOWNLOADS_DIR: &str = "./data/downloads";
+
+/// RFC 5987 attr-char: everything else is percent-encoded
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!').remove(b'#').remove(b'$').remove(b'&').remove(b'+')
+    .remove(b'-').remove(b'.').remove(b'^').remove(b'_').remove(b'`')
+    .remove(b'|').remove(b'~');
+
+/// Name to save an on-chain filename under in DOWNLOADS_DIR. Path
+/// separators, control characters and ".." become "_", so the name can't
+/// leave the directory; `serve_download` refuses any name this would change.
+pub fn safe_filename(filename: &str) -> String {
+    let name: String = filename
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.replace("..", "_");
+    if name.is_empty() {
+        "download".to_string()
+    } else {
+        name
+    }
+}
+
+/// Web path serving a file saved in DOWNLOADS_DIR
+pub fn download_link(filename: &str) -> String {
+    format!("/downloads/{}", utf8_percent_encode(filename, ATTR_CHAR))
+}
+
+/// Serve a completed job's file as an attachment with its real name and type
+pub async fn serve_download(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(filename): Path<String>,
+) -> Response {
+    // Filenames come from on-chain data, so never leave the downloads directory
+    if safe_filename(&filename) != filename {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let job = {
+        let state = state.read().await;
+        state
+            .db
+            .find_completed_download(&download_link(&filename), &format!("/downloads/{}", filename))
+    };
+    let mime_type = match job {
+        Ok(Some(job)) => job.mime_type,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::warn!("Download lookup failed for {}: {}", filename, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut file = match tokio::fs::File::open(std::path::Path::new(DOWNLOADS_DIR).join(&filename)).await {
+        Ok(f) => f,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let length = file.metadata().await.ok().map(|m| m.len());
+
+    // Downloads saved before their type was stored are typed from their
+    // leading bytes, rewinding to stream all of the file
+    let content_type = match mime_type {
+        Some(mime_type) => mime_type,
+        None => {
+            let mut head = Vec::with_capacity(content_type::SNIFF_LEN);
+            if (&mut file).take(content_type::SNIFF_LEN as u64).read_to_end(&mut head).await.is_err()
+                || file.rewind().await.is_err()
+            {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            content_type::detect(&head, &filename)
+        }
+    };
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, content_disposition(&filename));
+    if let Some(length) = length {
+        response = response.header(header::CONTENT_LENGTH, length);
+    }
+    response
+        .body(Body::from_stream(ReaderStream::new(file)))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+#[derive(Deserialize)]
+pub struct DataOutputQuery {
+    pub network: Option<Network>,
+    /// Answer with the script's push items as base64 strings instead of its bytes
+    #[serde(default)]
+    pub decoded: bool,
+}
+
+/// The first upload-protocol output script of a transaction, for tooling that
+/// reads the on-chain formats itself. X-Protocol and X-Output-Index say which
+/// output it was.
+pub async fn get_data_output(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(txid): Path<String>,
+    Query(query): Query<DataOutputQuery>,
+) -> Result<Response, ApiError> {
+    let txid = txid.trim().to_string();
+    let network = query.network.unwrap_or_default();
+
+    if txid.len() != 64 || !txid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::invalid_request("Invalid TXID format. Must be 64 hex characters."));
+    }
+
+    let tx_hex = crate::fetch_tx_raw(&state, &txid, network)
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::TxFetchFailed, format!("Failed to fetch transaction: {}", e)))?;
+    let output = find_data_output(&tx_hex, network)
+        .ok_or_else(|| ApiError::new(ErrorCode::NoDataFound, "No data output found in transaction"))?;
+
+    let mut response = if query.decoded {
+        let pushes: Vec<String> = data_pushes(&output.script)
+            .unwrap_or_default()
+            .iter()
+            .map(|push| STANDARD.encode(push))
+            .collect();
+        Json(pushes).into_response()
+    } else {
+        ([(header::CONTENT_TYPE, "application/octet-stream")], output.script).into_response()
+    };
+    let headers = response.headers_mut();
+    headers.insert("x-protocol", HeaderValue::from_static(output.protocol));
+    headers.insert("x-output-index", HeaderValue::from(output.index));
+    Ok(response)
+}
+
+/// `attachment` with an ASCII fallback name and the exact UTF-8 name (RFC 6266/5987)
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(filename, ATTR_CHAR)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::bsv::BsvService;
+    use crate::test_support::{serve, test_config, test_state, test_state_with, whatsonchain_chain};
+
+    #[test]
+    fn safe_filename_stays_in_the_directory() {
+        assert_eq!(safe_filename("song.flac"), "song.flac");
+        assert_eq!(safe_filename("曲 1.flac"), "曲 1.flac");
+        assert_eq!(safe_filename("../../etc/passwd"), "____etc_passwd");
+        assert_eq!(safe_filename("a\\b\u{0}.txt"), "a_b_.txt");
+        assert_eq!(safe_filename("..."), "_.");
+        assert_eq!(safe_filename(""), "download");
+    }
+
+    #[tokio::test]
+    async fn unicode_download_keeps_its_name_and_stored_type() {
+        let state = test_state();
+        let filename = format!("曲 \"{}\".flac", Uuid::new_v4().simple());
+        let mut job = Job::new_flac_download(Uuid::new_v4().simple().to_string(), "ab".repeat(32));
+        job.status = JobStatus::Complete;
+        job.download_link = Some(download_link(&filename));
+        job.filename = Some(filename.clone());
+        // A type a sniffer would never guess from these bytes
+        job.mime_type = Some("audio/flac".to_string());
+        state.read().await.db.insert_job(&job).unwrap();
+
+        std::fs::create_dir_all(DOWNLOADS_DIR).unwrap();
+        let path = std::path::Path::new(DOWNLOADS_DIR).join(&filename);
+        std::fs::write(&path, b"plain text").unwrap();
+        let response = serve_download(State(state), Path(filename)).await;
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_TYPE], "audio/flac");
+        assert_eq!(headers[header::CONTENT_LENGTH], "10");
+        let disposition = headers[header::CONTENT_DISPOSITION].to_str().unwrap();
+        assert!(disposition.starts_with("attachment; filename=\"_ _"), "{}", disposition);
+        assert!(disposition.contains("filename*=UTF-8''%E6%9B%B2%20%22"), "{}", disposition);
+    }
+
+    #[tokio::test]
+    async fn paths_outside_completed_downloads_are_not_found() {
+        let state = test_state();
+        for name in ["../data.db", "a/b", "missing.txt"] {
+            let response = serve_download(State(state.clone()), Path(name.to_string())).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", name);
+        }
+    }
+
+    /// POST a start_download body, returning the status and the JSON answer
+    async fn start(state: &Arc<RwLock<AppState>>, content_type: &str, body: String) -> (StatusCode, serde_json::Value) {
+        let app = serve(axum::Router::new().route("/api/download", axum::routing::post(start_download)).with_state(state.clone())).await;
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/download", app))
+            .header("content-type", content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        (status, response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn json_and_form_bodies_create_the_same_job() {
+        let state = test_state();
+        let txid = "ab".repeat(32);
+        let (status, json) = start(&state, "application/json", serde_json::json!({ "txid": txid, "network": "testnet" }).to_string()).await;
+        assert_eq!(status, StatusCode::OK, "{}", json);
+        let (status, form) = start(&state, "application/x-www-form-urlencoded", format!("txid={}&network=Test", txid)).await;
+        assert_eq!(status, StatusCode::OK, "{}", form);
+        let (status, default) = start(&state, "application/x-www-form-urlencoded", format!("txid={}", txid)).await;
+        assert_eq!(status, StatusCode::OK, "{}", default);
+
+        let state = state.read().await;
+        let network = |body: &serde_json::Value| state.db.get_job(body["job_id"].as_str().unwrap()).unwrap().unwrap().network;
+        assert_eq!((network(&json), network(&form), network(&default)), (Some(Network::Testnet), Some(Network::Testnet), Some(Network::Mainnet)));
+    }
+
+    #[tokio::test]
+    async fn malformed_txids_and_networks_are_refused() {
+        let state = test_state();
+        for body in [
+            serde_json::json!({ "txid": "zz".repeat(32) }),
+            serde_json::json!({ "txid": "ab".repeat(31) }),
+            serde_json::json!({ "txid": "ab".repeat(32), "network": "regtest" }),
+        ] {
+            let (status, answer) = start(&state, "application/json", body.to_string()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+            assert_eq!(answer["error"]["code"], "INVALID_REQUEST", "{}", body);
+        }
+        assert!(state.read().await.db.get_all_jobs(None).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn testnet_downloads_fetch_from_whatsonchain() {
+        let state = test_state();
+        let bsv = BsvService::for_tests();
+        let filename = format!("{}.txt", Uuid::new_v4().simple());
+        let script = bsv.create_upfile_script("text/plain", &filename, b"hello testnet");
+        let txid = whatsonchain_chain().add(&bsv.test_transaction(&[(script, crate::models::Amount::from_sat_const(1))]));
+
+        let (status, body) = start(&state, "application/json", serde_json::json!({ "txid": txid, "network": "testnet" }).to_string()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let scheduler = state.read().await.scheduler.clone();
+        let (queued, _permit) = tokio::time::timeout(std::time::Duration::from_secs(1), scheduler.next()).await.unwrap();
+        assert_eq!(queued.network, Network::Testnet);
+        crate::run_job_guarded(state.clone(), queued).await;
+
+        let job = state.read().await.db.get_job(body["job_id"].as_str().unwrap()).unwrap().unwrap();
+        let saved = std::path::Path::new(DOWNLOADS_DIR).join(&filename);
+        let data = std::fs::read(&saved);
+        let _ = std::fs::remove_file(&saved);
+        assert_eq!(job.status, JobStatus::Complete, "{}", job.message);
+        assert_eq!(job.download_link, Some(download_link(&filename)));
+        assert_eq!(data.unwrap(), b"hello testnet");
+    }
+
+    #[tokio::test]
+    async fn stream_and_discard_never_touch_the_disk() {
+        let state = test_state();
+        let bsv = BsvService::for_tests();
+        let filename = format!("{}.txt", Uuid::new_v4().simple());
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let script = bsv.create_upfile_script("text/plain", &filename, &data);
+        let txid = whatsonchain_chain().add(&bsv.test_transaction(&[(script, crate::models::Amount::from_sat_const(1))]));
+        let app = serve(axum::Router::new().route("/api/download", axum::routing::post(start_download)).with_state(state.clone())).await;
+        let request = |mode: &str| {
+            reqwest::Client::new()
+                .post(format!("{}/api/download", app))
+                .json(&serde_json::json!({ "txid": txid, "network": "testnet", "mode": mode }))
+                .send()
+        };
+
+        let response = request("stream").await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        let disposition = response.headers()["content-disposition"].to_str().unwrap().to_string();
+        assert!(disposition.contains(&filename), "{}", disposition);
+        assert_eq!(response.bytes().await.unwrap().as_ref(), data.as_slice());
+
+        let response = request("discard").await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["filename"], filename.as_str());
+        assert_eq!(body["size"], 5000);
+        assert_eq!(body["sha256"], hex::encode(Sha256::digest(&data)));
+
+        assert!(!std::path::Path::new(DOWNLOADS_DIR).join(&filename).exists());
+        assert!(state.read().await.db.get_all_jobs(None).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_and_discard_are_admitted_and_capped() {
+        let mut config = test_config();
+        config.max_inline_download_bytes = 4096;
+        let state = test_state_with(config);
+        let bsv = BsvService::for_tests();
+        let one_sat = crate::models::Amount::from_sat_const(1);
+        let app = serve(axum::Router::new().route("/api/download", axum::routing::post(start_download)).with_state(state.clone())).await;
+        let request = |txid: &str, mode: &str| {
+            reqwest::Client::new()
+                .post(format!("{}/api/download", app))
+                .json(&serde_json::json!({ "txid": txid, "network": "testnet", "mode": mode }))
+                .send()
+        };
+
+        // The type the upload recorded wins over the one its bytes suggest
+        let script = bsv.create_upfile_script("text/markdown", "notes.txt", b"# notes");
+        let upfile = whatsonchain_chain().add(&bsv.test_transaction(&[(script, one_sat)]));
+        let response = request(&upfile, "stream").await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["content-type"], "text/markdown");
+
+        // Both modes wait their turn behind the backlog and the processing slots
+        state.write().await.config.max_queued_jobs = Some(0);
+        for mode in ["stream", "discard"] {
+            let response = request(&upfile, mode).await.unwrap();
+            assert_eq!(response.status().as_u16(), 503, "{}", mode);
+        }
+        state.write().await.config.max_queued_jobs = None;
+        let scheduler = state.read().await.scheduler.clone();
+        let busy: Vec<_> = std::iter::from_fn(|| scheduler.try_slot()).collect();
+        for mode in ["stream", "discard"] {
+            let response = request(&upfile, mode).await.unwrap();
+            assert_eq!(response.status().as_u16(), 503, "{}", mode);
+            assert!(response.headers().contains_key("retry-after"), "{}", mode);
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["error"]["code"], "SERVER_BUSY", "{}", mode);
+        }
+        drop(busy);
+        assert_eq!(request(&upfile, "discard").await.unwrap().status().as_u16(), 200);
+
+        // Chunked files past the cap are refused, whether the manifest says so or not
+        let chunk_txids: Vec<String> = (0..2u32)
+            .map(|i| whatsonchain_chain().add(&bsv.test_transaction(&[(bsv.create_flac_chunk_script(i, 2, &[i as u8; 3000]), one_sat)])))
+            .collect();
+        let manifest = |size: usize| {
+            let script = BsvService::create_flac_manifest_script(
+                "song.flac",
+                size,
+                &chunk_txids,
+                &[],
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                crate::services::bsv::ManifestLayout::Json,
+            );
+            whatsonchain_chain().add(&bsv.test_transaction(&[(script, one_sat)]))
+        };
+        for declared in [6000, 100] {
+            let response = request(&manifest(declared), "stream").await.unwrap();
+            assert_eq!(response.status().as_u16(), 400, "{}", declared);
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert!(body["error"]["message"].as_str().unwrap().contains("mode=save"), "{}", body);
+        }
+        state.write().await.config.max_inline_download_bytes = 6000;
+        let response = request(&manifest(6000), "discard").await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["size"], 6000);
+    }
+
+    #[tokio::test]
+    async fn data_output_serves_each_protocol_script() {
+        let state = test_state();
+        let app = serve(
+            axum::Router::new()
+                .route("/api/tx/:txid/data_output", axum::routing::get(get_data_output))
+                .with_state(state),
+        )
+        .await;
+        let bsv = BsvService::for_tests();
+        let change = BsvService::create_p2pkh_script("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn").unwrap();
+        let one_sat = crate::models::Amount::from_sat_const(1);
+        // Behind a change output, so the index is the data output's own
+        let add = |script: Vec<u8>| {
+            whatsonchain_chain().add(&bsv.test_transaction(&[(change.clone(), crate::models::Amount::from_sat_const(1000)), (script, one_sat)]))
+        };
+        let get = |txid: &str, query: &str| reqwest::get(format!("{}/api/tx/{}/data_output?network=testnet{}", app, txid, query));
+
+        let manifest = BsvService::create_flac_manifest_script(
+            "song.flac",
+            4,
+            &["ab".repeat(32)],
+            &[],
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            crate::services::bsv::ManifestLayout::Json,
+        );
+        let metadata = br#"{"filename":"song.flac"}"#;
+        let fixtures = [
+            ("upfile", bsv.create_upfile_script("text/plain", "a.txt", b"upfile data")),
+            ("flacstore-chunk", bsv.create_flac_chunk_script(0, 1, b"fLaC")),
+            ("flacstore-manifest", manifest),
+            ("flacstore", bsv.create_flac_store_script(b"flacstore", b"audio/flac", metadata, &[b"fLaC".to_vec()])),
+            ("coverart", bsv.create_cover_image_script(&[0xff, 0xd8, 0xff, 0xe0])),
+            ("flacstore-lyrics", bsv.create_lyrics_script("la la la")),
+            ("flacstore-coverupdate", BsvService::create_cover_update_script(&"ab".repeat(32), &"cd".repeat(32))),
+        ];
+        for (protocol, script) in fixtures {
+            let txid = add(script.clone());
+            let response = get(&txid, "").await.unwrap();
+            assert_eq!(response.status().as_u16(), 200, "{}", protocol);
+            assert_eq!(response.headers()["content-type"], "application/octet-stream");
+            assert_eq!(response.headers()["x-protocol"], protocol);
+            assert_eq!(response.headers()["x-output-index"], "1");
+            assert_eq!(response.bytes().await.unwrap().as_ref(), script.as_slice(), "{}", protocol);
+
+            let pushes: Vec<String> = get(&txid, "&decoded=true").await.unwrap().json().await.unwrap();
+            let expected: Vec<String> = data_pushes(&script).unwrap().iter().map(|push| STANDARD.encode(push)).collect();
+            assert_eq!(pushes, expected, "{}", protocol);
+        }
+
+        // A plain payment carries no data
+        let txid = whatsonchain_chain().add(&bsv.test_transaction(&[(change.clone(), one_sat)]));
+        let response = get(&txid, "").await.unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "NO_DATA_FOUND");
+
+        // 64 characters that aren't hex are refused before any fetch
+        let response = get(&"zz".repeat(32), "").await.unwrap();
+        assert_eq!(response.status().as_u16(), 400);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "INVALID_REQUEST");
+    }
 }
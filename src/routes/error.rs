@@ -0,0 +1,175 @@
+// API error responses
+// Every failed API request answers with the same body,
+// `{ "success": false, "error": { "code": ..., "message": ... } }`,
+// and an HTTP status matching the code, so clients branch on the code
+// instead of on each route's own response shape.
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+
+use crate::models::ErrorCode;
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: ErrorCode,
+    pub message: String,
+    /// Seconds a client should wait before retrying, sent as Retry-After
+    pub retry_after: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    success: bool,
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    code: ErrorCode,
+    message: &'a str,
+}
+
+impl ApiError {
+    /// An error with the usual status for its code
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status: default_status(code),
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidRequest, message)
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(ErrorCode::Unauthorized, "Invalid admin key")
+    }
+
+    pub fn job_not_found() -> Self {
+        Self::new(ErrorCode::JobNotFound, "Job not found")
+    }
+
+    pub fn database(e: impl std::fmt::Display) -> Self {
+        Self::new(ErrorCode::DatabaseError, format!("Database error: {}", e))
+    }
+}
+
+/// Helpers that report failures as `(ErrorCode, String)` convert directly
+impl From<(ErrorCode, String)> for ApiError {
+    fn from((code, message): (ErrorCode, String)) -> Self {
+        Self::new(code, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            success: false,
+            error: ErrorDetail {
+                code: self.code,
+                message: &self.message,
+            },
+        };
+        let mut response = (self.status, Json(body)).into_response();
+        if let Some(seconds) = self.retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+fn default_status(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::InvalidRequest
+        | ErrorCode::UploadTooExpensive
+        | ErrorCode::NoFileData
+        | ErrorCode::NoUtxos
+        | ErrorCode::InsufficientFunds
+        | ErrorCode::InvalidWif
+        | ErrorCode::InvalidAddress => StatusCode::BAD_REQUEST,
+        ErrorCode::Unauthorized | ErrorCode::InvalidApiKey => StatusCode::UNAUTHORIZED,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+        ErrorCode::JobNotFound | ErrorCode::NoDataFound => StatusCode::NOT_FOUND,
+        ErrorCode::JobFinished | ErrorCode::FundingUnconfirmed | ErrorCode::FundingWalletBusy => {
+            StatusCode::CONFLICT
+        }
+        ErrorCode::PaymentExpired => StatusCode::GONE,
+        ErrorCode::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::ServerBusy => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::UtxoFetchFailed
+        | ErrorCode::TxFetchFailed
+        | ErrorCode::ChunkFetchFailed
+        | ErrorCode::BroadcastFailed => StatusCode::BAD_GATEWAY,
+        ErrorCode::TxBuildFailed
+        | ErrorCode::SizeMismatch
+        | ErrorCode::ChunkIndexMismatch
+        | ErrorCode::ChunkHashMismatch
+        | ErrorCode::SaveFailed
+        | ErrorCode::PaymentKeyMismatch
+        | ErrorCode::DatabaseError
+        | ErrorCode::Stalled
+        | ErrorCode::TimedOut
+        | ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::{get, post};
+    use serde_json::json;
+
+    use crate::models::{Job, Network};
+    use crate::routes;
+    use crate::services::bsv::BsvService;
+    use crate::test_support::{accept, bitails, serve, test_config, test_state_with};
+
+    #[tokio::test]
+    async fn failures_share_one_body_with_their_code_and_status() {
+        let mut config = test_config();
+        config.bitails_api_url = bitails(100, accept).await;
+        let state = test_state_with(config);
+        let job = Job::new_upload("job".to_string(), "a.txt".to_string(), 4, b"data".to_vec(), "addr".to_string(), "wif".to_string(), 1000);
+        state.read().await.db.insert_job(&job).unwrap();
+        let app = serve(
+            axum::Router::new()
+                .route("/status_update/:job_id", get(routes::status::status_update))
+                .route("/api/jobs/:job_id/cancel", post(routes::jobs::cancel_job))
+                .route("/api/wallet/send", post(routes::wallet::send_bsv))
+                .route("/api/admin/verify", post(routes::admin::verify_admin_key))
+                .with_state(state),
+        )
+        .await;
+
+        let (wif, _) = BsvService::generate_keypair(Network::Mainnet);
+        let (_, to_address) = BsvService::generate_keypair(Network::Mainnet);
+        let send = |wif: &str| json!({ "wif": wif, "to_address": to_address, "amount_satoshis": 1000 });
+        let client = reqwest::Client::new();
+        let cases = [
+            (client.get(format!("{}/status_update/missing", app)), 404, "JOB_NOT_FOUND"),
+            (client.post(format!("{}/api/jobs/job/cancel", app)).json(&json!({ "owner_token": "wrong" })), 403, "FORBIDDEN"),
+            (client.post(format!("{}/api/wallet/send", app)).json(&send("not-a-wif")), 400, "INVALID_WIF"),
+            (client.post(format!("{}/api/wallet/send", app)).json(&send(&wif)), 400, "INSUFFICIENT_FUNDS"),
+            (client.post(format!("{}/api/admin/verify", app)).json(&json!({ "key": "wrong" })), 401, "UNAUTHORIZED"),
+        ];
+        for (request, status, code) in cases {
+            let response = request.send().await.unwrap();
+            assert_eq!(response.status().as_u16(), status, "{}", code);
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["success"], false);
+            assert_eq!(body["error"]["code"], code);
+            assert!(!body["error"]["message"].as_str().unwrap().is_empty(), "{}", code);
+        }
+    }
+}
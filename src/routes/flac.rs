@@ -1,15 +1,33 @@
+use askama::Template;
 use axum::{
-    extract::{Multipart, Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Json},
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Json, Response,
+    },
 };
 use base64::Engine;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, Stream};
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
-use crate::models::{Job, JobStatus, JobType};
+use crate::models::{Amount, ErrorCode, Job, JobStatus, JobType, MessageKey, Network};
+use crate::routes::admin::AdminAuth;
+use crate::routes::error::ApiError;
+use crate::routes::jobs::{cancel_job, CancelJobRequest, CancelJobResponse};
+use crate::routes::page;
+use crate::routes::upload::ClientIp;
+use crate::services::api_keys;
+use crate::services::tx_parse::{extract_flac_from_tx, extract_flac_manifest_from_tx, parse_image_output};
 use crate::services::bsv::BsvService;
+use crate::services::lyrics::{self, LyricLine, LyricsFormat};
+use crate::services::scheduler::QueuedJob;
+use crate::services::thumbnail;
 use crate::AppState;
 
 /// FLAC upload page
@@ -18,43 +36,86 @@ pub async fn flac_upload_page() -> Html<String> {
     Html(html.to_string())
 }
 
+#[derive(Deserialize)]
+pub struct PlayerQuery {
+    pub txid: Option<String>,
+    pub network: Option<Network>,
+}
+
+#[derive(Template)]
+#[template(path = "flac_player.html")]
+pub struct PlayerTemplate {
+    /// Manifest to load as soon as the page opens, empty for none
+    pub txid: String,
+    pub network: Network,
+}
+
 /// FLAC player page (download + playback)
-pub async fn flac_player_page() -> Html<String> {
-    let html = include_str!("../../templates/flac_player.html");
-    Html(html.to_string())
+pub async fn flac_player_page(Query(query): Query<PlayerQuery>) -> Response {
+    page::render(&PlayerTemplate {
+        txid: query.txid.map(|t| t.trim().to_string()).unwrap_or_default(),
+        network: query.network.unwrap_or_default(),
+    })
+}
+
+#[derive(Template)]
+#[template(path = "flac_status.html")]
+pub struct FlacStatusTemplate {
+    pub job_id: String,
 }
 
 /// FLAC status page
-pub async fn flac_status_page(Path(job_id): Path<String>) -> Html<String> {
-    let html = include_str!("../../templates/flac_status.html");
-    let html = html.replace("{{JOB_ID}}", &job_id);
-    Html(html)
+pub async fn flac_status_page(Path(job_id): Path<String>) -> Response {
+    page::render(&FlacStatusTemplate { job_id })
 }
 
 #[derive(Serialize)]
 pub struct FlacUploadResponse {
     pub success: bool,
-    pub job_id: Option<String>,
+    pub job_id: String,
+    pub owner_token: Option<String>,
     pub payment_address: Option<String>,
     pub required_satoshis: Option<i64>,
     pub admin_pay: bool,
-    pub error: Option<String>,
+    /// Paid from the user's own funding wallet, no payment needed
+    pub prefunded: bool,
+    /// The same file was just prepared, so that job is returned instead of a new one
+    pub reused: bool,
+}
+
+/// Check a royalty destination: a P2PKH address on the upload's network, paid at least the dust limit
+pub fn validate_royalty(address: &str, satoshis: i64, network: Network, dust_limit: Amount) -> Result<(), String> {
+    BsvService::validate_address(address, network).map_err(|e| format!("Invalid royalty address: {}", e))?;
+    let amount = Amount::try_from(satoshis).map_err(|e| format!("Invalid royalty: {}", e))?;
+    if amount < dust_limit {
+        return Err(format!("Royalty of {} satoshis is below the {} satoshi dust limit", satoshis, dust_limit));
+    }
+    Ok(())
 }
 
 /// Prepare FLAC upload - creates job and returns payment address
 pub async fn prepare_flac_upload(
     State(state): State<Arc<RwLock<AppState>>>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    admin: AdminAuth,
     mut multipart: Multipart,
-) -> impl IntoResponse {
+) -> Result<Json<FlacUploadResponse>, ApiError> {
     let mut filename: Option<String> = None;
     let mut file_data: Option<Vec<u8>> = None;
     let mut track_title: Option<String> = None;
     let mut artist_name: Option<String> = None;
     let mut cover_data: Option<Vec<u8>> = None;
-    let mut cover_filename: Option<String> = None;
     let mut lyrics: Option<String> = None;
-    let mut network: String = "mainnet".to_string();
+    let mut network = Network::Mainnet;
     let mut admin_pay_requested: bool = false;
+    let mut royalty_address: Option<String> = None;
+    let mut royalty_satoshis: Option<String> = None;
+    let mut funding_wif: Option<String> = None;
+    let mut force_new = false;
+    let mut owner_token: Option<String> = None;
+    let mut bypass_backlog = false;
+    let mut admin_key = String::new();
 
     while let Ok(Some(field)) = multipart.next_field().await {
         let name = field.name().unwrap_or("").to_string();
@@ -81,7 +142,6 @@ pub async fn prepare_flac_upload(
                 }
             }
             "cover" => {
-                cover_filename = field.file_name().map(|s| s.to_string());
                 if let Ok(data) = field.bytes().await {
                     if !data.is_empty() {
                         cover_data = Some(data.to_vec());
@@ -97,9 +157,10 @@ pub async fn prepare_flac_upload(
             }
             "network" => {
                 if let Ok(data) = field.text().await {
-                    let net = data.trim().to_lowercase();
-                    if net == "testnet" {
-                        network = "testnet".to_string();
+                    if !data.trim().is_empty() {
+                        network = Network::from_str(&data).ok_or_else(|| {
+                            ApiError::invalid_request(format!("Unknown network: {}", data.trim()))
+                        })?;
                     }
                 }
             }
@@ -108,253 +169,435 @@ pub async fn prepare_flac_upload(
                     admin_pay_requested = data.trim().to_lowercase() == "true";
                 }
             }
+            "funding_wif" => {
+                if let Ok(data) = field.text().await {
+                    if !data.trim().is_empty() {
+                        funding_wif = Some(data.trim().to_string());
+                    }
+                }
+            }
+            "royalty_address" => {
+                if let Ok(data) = field.text().await {
+                    if !data.trim().is_empty() {
+                        royalty_address = Some(data.trim().to_string());
+                    }
+                }
+            }
+            "royalty_satoshis" => {
+                if let Ok(data) = field.text().await {
+                    if !data.trim().is_empty() {
+                        royalty_satoshis = Some(data.trim().to_string());
+                    }
+                }
+            }
+            "force_new" => {
+                if let Ok(data) = field.text().await {
+                    force_new = data.trim().to_lowercase() == "true";
+                }
+            }
+            "owner_token" => {
+                if let Ok(data) = field.text().await {
+                    if !data.trim().is_empty() {
+                        owner_token = Some(data.trim().to_string());
+                    }
+                }
+            }
+            "bypass_backlog" => {
+                if let Ok(data) = field.text().await {
+                    bypass_backlog = data.trim().to_lowercase() == "true";
+                }
+            }
+            "admin_key" => {
+                if let Ok(data) = field.text().await {
+                    admin_key = data.trim().to_string();
+                }
+            }
             _ => {}
         }
     }
 
-    let file_data = match file_data {
-        Some(data) => data,
-        None => {
-                        return (
-                            StatusCode::BAD_REQUEST,
-                            Json(FlacUploadResponse {
-                                success: false,
-                                job_id: None,
-                                payment_address: None,
-                                required_satoshis: None,
-                                admin_pay: false,
-                                error: Some("No file provided".to_string()),
-                            }),
-                        );
-        }
-    };
+    let file_data = file_data.ok_or_else(|| ApiError::invalid_request("No file provided"))?;
 
     let filename = filename.unwrap_or_else(|| "audio.flac".to_string());
 
     // Validate audio file (FLAC, WAV, or MP3)
     let lower_filename = filename.to_lowercase();
     if !lower_filename.ends_with(".flac") && !lower_filename.ends_with(".wav") && !lower_filename.ends_with(".mp3") {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(FlacUploadResponse {
-                        success: false,
-                        job_id: None,
-                        payment_address: None,
-                        required_satoshis: None,
-                        admin_pay: false,
-                        error: Some("Only FLAC, WAV, and MP3 files are supported".to_string()),
-                    }),
-                );
+        return Err(ApiError::invalid_request("Only FLAC, WAV, and MP3 files are supported"));
     }
 
-    // Check if admin pay is enabled and get admin WIF
-    let admin_wif = if admin_pay_requested {
-        let state_read = state.read().await;
-        crate::routes::admin::get_admin_wif_for_network(&state_read.db, &network)
-    } else {
-        None
+    // Royalty from the form, falling back to the admin default for the network
+    let royalty = match (royalty_address, royalty_satoshis) {
+        (None, None) => {
+            let state = state.read().await;
+            state.db.get_admin_config().ok().and_then(|c| c.default_royalty(network))
+        }
+        (Some(address), Some(satoshis)) => match satoshis.parse::<i64>() {
+            Ok(satoshis) => Some((address, satoshis)),
+            Err(_) => {
+                return Err(ApiError::invalid_request("royalty_satoshis must be a whole number of satoshis"));
+            }
+        },
+        _ => {
+            return Err(ApiError::invalid_request("royalty_address and royalty_satoshis must be set together"));
+        }
     };
-    let use_admin_pay = admin_wif.is_some();
 
-    // Generate payment keypair based on selected network (or use admin wallet)
-    let (wif, address) = if let Some(ref admin_wif_value) = admin_wif {
-        let addr = BsvService::wif_to_address(admin_wif_value, &network)
-            .unwrap_or_else(|_| "invalid".to_string());
-        (admin_wif_value.clone(), addr)
-    } else {
-        BsvService::generate_keypair(&network)
-    };
+    if let Some((address, satoshis)) = &royalty {
+        let dust_limit = state.read().await.bsv.dust_limit;
+        validate_royalty(address, *satoshis, network, dust_limit).map_err(|e| ApiError::new(ErrorCode::InvalidAddress, e))?;
+    }
+    let royalty_cost = royalty.as_ref().map(|(_, satoshis)| *satoshis).unwrap_or(0);
 
     // Calculate required satoshis
-    // For large files, we need to account for UTXO splitting and multiple chunk transactions
-    let max_chunk_size = 1024 * 1024; // 1MB chunks
     let file_size = file_data.len();
+
+    // Attribute the upload to an API key, if one was supplied
+    let api_key_id = {
+        let state = state.read().await;
+        api_keys::authorize_upload(&state.db, &headers, file_size as i64)?
+    };
     
-    let required_satoshis = {
+    // The upload inscribes the same thumbnail, so its size is known up front
+    let thumb_bytes = cover_data.as_deref().and_then(thumbnail::make_thumbnail).map_or(0, |thumb| thumb.len());
+    let (required_satoshis, chunked) = {
+        let state = state.read().await;
+        let plan = state.bsv.plan_flac_upload(file_size, network);
+        // The royalty output, any lyrics transaction and the cover thumbnail
+        // are paid on top of the fees
+        let lyrics_cost = state.bsv.lyrics_tx_cost(lyrics.as_ref().map_or(0, String::len));
+        let thumb_cost = state.bsv.thumb_cost(thumb_bytes);
+        let required = plan.cost.saturating_add(lyrics_cost).saturating_add(thumb_cost).to_sat_i64() + royalty_cost;
+
+        // Reject unpayable quotes before creating the job - this also guards the admin wallet
+        state
+            .config
+            .check_upload_limits(required, plan.chunk_count, plan.tx_outputs)
+            .map_err(|e| ApiError::new(ErrorCode::UploadTooExpensive, e))?;
+        (required, plan.chunked)
+    };
+
+    // A repeated submit of the same file gets the job it already created,
+    // unless it pays from its own wallet or asks for a new job
+    let content_hash = crate::routes::upload::content_sha256(&file_data);
+    if !force_new && funding_wif.is_none() {
+        let duplicate = crate::routes::upload::find_duplicate_job(
+            &state,
+            JobType::FlacUpload,
+            network,
+            &content_hash,
+            client_ip.as_deref(),
+            owner_token.as_deref(),
+        )
+        .await;
+        if let Some(job) = duplicate {
+            tracing::info!("Reusing pending FLAC upload job {} for a repeated submit", job.id);
+            return Ok(Json(FlacUploadResponse {
+                success: true,
+                job_id: job.id,
+                owner_token: job.owner_token,
+                payment_address: job.payment_address,
+                required_satoshis: job.required_satoshis,
+                admin_pay: false,
+                prefunded: false,
+                reused: true,
+            }));
+        }
+    }
+
+    {
         let state = state.read().await;
-        if file_size > max_chunk_size {
-            // Multi-chunk upload: use calculate_multi_chunk_cost
-            let (total, _, _) = state.bsv.calculate_multi_chunk_cost(file_size, max_chunk_size);
-            // Add 20% buffer for safety
-            (total as f64 * 1.2).ceil() as i64
+        crate::routes::jobs::admit_new_job(&state, &admin, bypass_backlog, &admin_key)?;
+    }
+
+    // Check if admin pay covers this upload and get admin WIF
+    // (a user paying from their own wallet doesn't need it).
+    // An ineligible request falls back to a normal payment address.
+    let prefunded = funding_wif.is_some();
+    let admin_wif = if admin_pay_requested && !prefunded {
+        let eligibility =
+            crate::routes::admin::admin_pay_eligibility(&state, network, Some(required_satoshis)).await;
+        if eligibility.eligible {
+            let state_read = state.read().await;
+            crate::routes::admin::get_admin_wif_for_network(&state_read.db, network)
         } else {
-            // Single transaction upload
-            state.bsv.calculate_upload_cost(file_size)
+            None
         }
+    } else {
+        None
+    };
+    let use_admin_pay = admin_wif.is_some();
+
+    // Use the user's funding wallet, the admin wallet, or a new payment keypair
+    let (wif, address, derivation_index) = if let Some(funding_wif) = funding_wif {
+        let addr = crate::routes::upload::verify_funding_wif(&state, &funding_wif, network, required_satoshis, chunked).await?;
+        (funding_wif, addr, None)
+    } else if let Some(ref admin_wif_value) = admin_wif {
+        let addr = BsvService::wif_to_address(admin_wif_value, network)
+            .map_err(|e| ApiError::new(ErrorCode::InvalidWif, format!("Invalid admin WIF: {}", e)))?;
+        (admin_wif_value.clone(), addr, None)
+    } else {
+        crate::routes::upload::new_payment_keypair(&state, network).await?
     };
+    crate::routes::upload::ensure_payment_key(&wif, &address, network)?;
 
     // Create job
     let job_id = uuid::Uuid::new_v4().to_string().replace("-", "");
-    let now = chrono::Utc::now();
+    let job = Job::new_flac_upload(
+        job_id.clone(),
+        filename,
+        file_data.len() as i64,
+        file_data,
+        address.clone(),
+        wif.clone(),
+        required_satoshis,
+    )
+    .with_track_metadata(track_title, artist_name, lyrics)
+    .with_cover_data(cover_data) // cover_txid is set once the image is on-chain
+    .with_royalty(royalty)
+    .with_network(network)
+    .with_derivation_index(derivation_index)
+    .with_submission(content_hash, client_ip);
+    let job = if prefunded { job.without_payment_wif() } else { job };
 
-    // If admin pay is enabled, start processing immediately
-    let (initial_status, initial_message) = if use_admin_pay {
-        (JobStatus::Processing, "Admin pay enabled, starting upload...".to_string())
+    // If admin pay is enabled or the wallet is already funded, start processing immediately
+    let job = if use_admin_pay {
+        job.with_status(JobStatus::Processing, MessageKey::AdminPayStarting)
+    } else if prefunded {
+        job.with_status(JobStatus::Processing, MessageKey::FundingVerified)
     } else {
-        (JobStatus::PendingPayment, "Waiting for payment...".to_string())
-    };
-
-    let job = Job {
-        id: job_id.clone(),
-        job_type: JobType::FlacUpload,
-        status: initial_status,
-        filename: Some(filename),
-        file_size: Some(file_data.len() as i64),
-        file_data: Some(file_data),
-        payment_address: Some(address.clone()),
-        payment_wif: Some(wif),
-        required_satoshis: Some(required_satoshis),
-        manifest_txid: None,
-        download_link: None,
-        progress: 0.0,
-        message: initial_message,
-        created_at: now,
-        updated_at: now,
-        track_title,
-        artist_name,
-        cover_txid: None, // Will be set after cover image is uploaded to BSV
-        cover_data,
-        lyrics,
-        network: Some(network.clone()),
+        job
     };
 
     {
         let state = state.read().await;
+        if prefunded {
+            crate::routes::upload::hold_funding_key(&state, &job_id, &wif, &address)?;
+        }
         if let Err(e) = state.db.insert_job(&job) {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(FlacUploadResponse {
-                    success: false,
-                    job_id: None,
-                    payment_address: None,
-                    required_satoshis: None,
-                    admin_pay: false,
-                    error: Some(format!("Failed to create job: {}", e)),
-                }),
-            );
+            state.funding_keys.release(&job_id);
+            return Err(ApiError::new(ErrorCode::DatabaseError, format!("Failed to create job: {}", e)));
+        }
+        if let Some(api_key_id) = &api_key_id {
+            if let Err(e) = state.db.set_job_api_key(&job_id, api_key_id) {
+                tracing::error!("Failed to attribute job {} to API key {}: {}", job_id, api_key_id, e);
+            }
         }
     }
 
-        // If admin pay is enabled, start processing immediately
-        if use_admin_pay {
-            let state_clone = state.clone();
-            let job_id_clone = job_id.clone();
-            let address_clone = address.clone();
-            let network_clone = network.clone();
-            tokio::spawn(async move {
-                crate::process_job(
-                    state_clone, 
-                    job_id_clone, 
-                    crate::models::job::JobType::FlacUpload,
-                    address_clone,
-                    network_clone
-                ).await;
+        // If admin pay is enabled or the wallet is already funded, queue for processing immediately
+        if use_admin_pay || prefunded {
+            let state = state.read().await;
+            crate::enqueue_job(&state, QueuedJob {
+                job_id: job_id.clone(),
+                job_type: JobType::FlacUpload,
+                address: address.clone(),
+                network,
+                admin_pay: use_admin_pay,
+                file_size: file_size as i64,
+                paid_satoshis: None,
             });
         }
 
-    (
-        StatusCode::OK,
-        Json(FlacUploadResponse {
+    Ok(Json(FlacUploadResponse {
+        success: true,
+        job_id,
+        owner_token: job.owner_token,
+        payment_address: if use_admin_pay || prefunded { None } else { Some(address) },
+        required_satoshis: if use_admin_pay || prefunded { None } else { Some(required_satoshis) },
+        admin_pay: use_admin_pay,
+        prefunded,
+        reused: false,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct FlacPlanRequest {
+    pub file_size: usize,
+    pub network: Option<Network>,
+    /// UTF-8 length of the lyrics, which get their own transaction past LYRICS_INLINE_MAX_BYTES
+    #[serde(default)]
+    pub lyrics_bytes: usize,
+}
+
+#[derive(Serialize)]
+pub struct FlacPlanResponse {
+    pub success: bool,
+    pub file_size: usize,
+    pub network: Network,
+    /// False when the file fits in a single transaction
+    pub chunked: bool,
+    pub chunk_count: usize,
+    pub chunk_size: usize,
+    pub last_chunk_size: usize,
+    /// Outputs of the UTXO split: one per chunk plus one for the manifest
+    pub split_outputs: usize,
+    /// Split transactions needed; more than one when the outputs are batched
+    pub split_tx_count: usize,
+    /// Fee of all split transactions together
+    pub split_tx_fee: Amount,
+    /// Fee of each chunk transaction, and of the single transaction for small files
+    pub chunk_tx_fee: Amount,
+    pub manifest_tx_fee: Amount,
+    /// Fee and data output of the lyrics transaction, zero for inline lyrics
+    pub lyrics_tx_cost: Amount,
+    pub required_satoshis: Amount,
+    pub max_upload_cost_satoshis: i64,
+    /// Set when prepare would reject this upload, explaining why
+    pub rejected_reason: Option<String>,
+}
+
+/// Preview how an upload would be laid out on-chain before paying for it
+pub async fn plan_flac_upload(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Json(req): Json<FlacPlanRequest>,
+) -> Result<Json<FlacPlanResponse>, ApiError> {
+    let network = req.network.unwrap_or_default();
+
+    let state = state.read().await;
+    let file_size = req.file_size;
+    let upload_plan = state.bsv.plan_flac_upload(file_size, network);
+    let lyrics_tx_cost = state.bsv.lyrics_tx_cost(req.lyrics_bytes);
+    let required_satoshis = upload_plan.cost.saturating_add(lyrics_tx_cost);
+    let rejected_reason = state
+        .config
+        .check_upload_limits(required_satoshis.to_sat_i64(), upload_plan.chunk_count, upload_plan.tx_outputs)
+        .err();
+
+    let plan = if upload_plan.chunked {
+        let chunk_size = upload_plan.chunk_size;
+        let (_, satoshis_per_chunk, chunk_count) = state.bsv.calculate_multi_chunk_cost(file_size, chunk_size);
+        let split_outputs = chunk_count + 1;
+        // Chunk and manifest transactions spend one split output each, keeping
+        // the data output value and paying the rest as fee
+        let data_tx_fee = satoshis_per_chunk.checked_sub(state.bsv.data_output_satoshis).unwrap_or_default();
+        FlacPlanResponse {
             success: true,
-            job_id: Some(job_id),
-            payment_address: if use_admin_pay { None } else { Some(address) },
-            required_satoshis: if use_admin_pay { None } else { Some(required_satoshis) },
-            admin_pay: use_admin_pay,
-            error: None,
-        }),
-    )
+            file_size,
+            network,
+            chunked: true,
+            chunk_count,
+            chunk_size,
+            last_chunk_size: file_size - (chunk_count - 1) * chunk_size,
+            split_outputs,
+            split_tx_count: state.bsv.split_transaction_count(split_outputs),
+            split_tx_fee: state.bsv.calculate_split_tree_fee(split_outputs),
+            chunk_tx_fee: data_tx_fee,
+            manifest_tx_fee: data_tx_fee,
+            lyrics_tx_cost,
+            required_satoshis,
+            max_upload_cost_satoshis: state.config.max_upload_cost_satoshis,
+            rejected_reason,
+        }
+    } else {
+        FlacPlanResponse {
+            success: true,
+            file_size,
+            network,
+            chunked: false,
+            chunk_count: 1,
+            chunk_size: file_size,
+            last_chunk_size: file_size,
+            split_outputs: 0,
+            split_tx_count: 0,
+            split_tx_fee: Amount::ZERO,
+            chunk_tx_fee: upload_plan.cost,
+            manifest_tx_fee: Amount::ZERO,
+            lyrics_tx_cost,
+            required_satoshis,
+            max_upload_cost_satoshis: state.config.max_upload_cost_satoshis,
+            rejected_reason,
+        }
+    };
+
+    Ok(Json(plan))
 }
 
 #[derive(Deserialize)]
 pub struct FlacDownloadRequest {
     pub txid: String,
-    pub network: Option<String>,
+    pub network: Option<Network>,
+    /// With the admin key, create the job even past the backlog ceilings
+    #[serde(default)]
+    pub bypass_backlog: bool,
+    #[serde(default)]
+    pub key: String,
+    /// Download a single-transaction upload in the request and answer with
+    /// its link; chunked uploads are still queued
+    #[serde(default)]
+    pub wait: bool,
 }
 
 #[derive(Serialize)]
 pub struct FlacDownloadResponse {
     pub success: bool,
-    pub job_id: Option<String>,
-    pub error: Option<String>,
+    pub job_id: String,
+    pub owner_token: Option<String>,
+    /// Only with `wait` on a single-transaction upload: the saved file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
 }
 
 /// Start FLAC download
 pub async fn start_flac_download(
     State(state): State<Arc<RwLock<AppState>>>,
+    admin: AdminAuth,
     Json(req): Json<FlacDownloadRequest>,
-) -> impl IntoResponse {
+) -> Result<Json<FlacDownloadResponse>, ApiError> {
     let txid = req.txid.trim().to_string();
-    let network = req.network.unwrap_or_else(|| "mainnet".to_string());
+    let network = req.network.unwrap_or_default();
 
     if txid.len() != 64 {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(FlacDownloadResponse {
-                success: false,
-                job_id: None,
-                error: Some("Invalid TXID format (must be 64 characters)".to_string()),
-            }),
-        );
+        return Err(ApiError::invalid_request("Invalid TXID format (must be 64 characters)"));
+    }
+    {
+        let state = state.read().await;
+        crate::routes::jobs::admit_new_job(&state, &admin, req.bypass_backlog, &req.key)?;
     }
 
     // Create download job
     let job_id = uuid::Uuid::new_v4().to_string().replace("-", "");
-    let now = chrono::Utc::now();
-
-    let job = Job {
-        id: job_id.clone(),
-        job_type: JobType::FlacDownload,
-        status: JobStatus::Processing,
-        filename: None,
-        file_size: None,
-        file_data: None,
-        payment_address: None,
-        payment_wif: None,
-        required_satoshis: None,
-        manifest_txid: Some(txid.clone()),
-        download_link: None,
-        progress: 0.0,
-        message: "Starting FLAC download...".to_string(),
-        created_at: now,
-        updated_at: now,
-        track_title: None,
-        artist_name: None,
-        cover_txid: None,
-        cover_data: None,
-        lyrics: None,
-        network: Some(network.clone()),
-    };
+    let job = Job::new_flac_download(job_id.clone(), txid.clone())
+        .with_status(JobStatus::Processing, MessageKey::StartingFlacDownload)
+        .with_network(network);
 
     {
         let state_read = state.read().await;
-        if let Err(e) = state_read.db.insert_job(&job) {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(FlacDownloadResponse {
-                    success: false,
-                    job_id: None,
-                    error: Some(format!("Failed to create job: {}", e)),
-                }),
-            );
-        }
+        state_read
+            .db
+            .insert_job(&job)
+            .map_err(|e| ApiError::new(ErrorCode::DatabaseError, format!("Failed to create job: {}", e)))?;
     }
 
-    // Start download process
-    let state_clone = state.clone();
-    let job_id_clone = job_id.clone();
-    let network_clone = network.clone();
-    tokio::spawn(async move {
-        crate::process_flac_download(state_clone, job_id_clone, Some(txid), network_clone).await;
-    });
+    let mut saved = None;
+    if req.wait && crate::download_flac_inline(&state, &job_id, &txid, network).await {
+        saved = Some(crate::routes::download::inline_download_result(&state, &job_id).await?);
+    } else {
+        let state = state.read().await;
+        crate::enqueue_job(&state, QueuedJob {
+            job_id: job_id.clone(),
+            job_type: JobType::FlacDownload,
+            address: String::new(),
+            network,
+            admin_pay: false,
+            file_size: 0,
+            paid_satoshis: None,
+        });
+    }
+    let (download_link, filename) = match saved {
+        Some((link, filename)) => (Some(link), filename),
+        None => (None, None),
+    };
 
-    (
-        StatusCode::OK,
-        Json(FlacDownloadResponse {
-            success: true,
-            job_id: Some(job_id),
-            error: None,
-        }),
-    )
+    Ok(Json(FlacDownloadResponse {
+        success: true,
+        job_id,
+        owner_token: job.owner_token,
+        download_link,
+        filename,
+    }))
 }
 
 #[derive(Serialize)]
@@ -362,6 +605,9 @@ pub struct FlacStatusResponse {
     pub status: String,
     pub progress: f64,
     pub message: String,
+    /// Key and params of `message`, for localized frontends
+    pub message_key: Option<String>,
+    pub message_params: Option<serde_json::Value>,
     pub txid: Option<String>,
     pub download_link: Option<String>,
     pub filename: Option<String>,
@@ -369,190 +615,481 @@ pub struct FlacStatusResponse {
     pub artist_name: Option<String>,
     pub cover_txid: Option<String>,
     pub lyrics: Option<String>,
+    pub bytes_done: Option<i64>,
+    pub bytes_total: Option<i64>,
+    pub eta_seconds: Option<i64>,
+    /// Payment the watcher detected, while the job waits for its turn
+    pub payment_received_at: Option<DateTime<Utc>>,
+    pub payment_received_satoshis: Option<i64>,
+    pub error_code: Option<ErrorCode>,
+    /// Only with `?include=chunks`: the upload's split and chunk transactions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split_txid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_txids: Option<Vec<String>>,
+    /// Only with `?include=manifest`: the manifest a chunked upload inscribed,
+    /// as stored when it was broadcast
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<serde_json::Value>,
+    /// How long to wait before polling again; jittered so pages spread out.
+    /// Not sent over the event stream, which pushes changes itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_after_ms: Option<u64>,
 }
 
-/// Get cover image from BSV transaction
 #[derive(Deserialize)]
-pub struct CoverRequest {
-    pub txid: String,
-    pub network: Option<String>,
+pub struct FlacStatusQuery {
+    /// Comma-separated extras to include; "chunks" adds the chunk txids,
+    /// "manifest" the manifest an upload inscribed
+    pub include: Option<String>,
+}
+
+/// Optional parts of a FLAC status response
+#[derive(Clone, Copy)]
+struct StatusExtras {
+    chunks: bool,
+    manifest: bool,
+}
+
+impl FlacStatusQuery {
+    fn includes(&self, extra: &str) -> bool {
+        self.include
+            .as_deref()
+            .is_some_and(|include| include.split(',').any(|part| part.trim() == extra))
+    }
+
+    fn extras(&self) -> StatusExtras {
+        StatusExtras {
+            chunks: self.includes("chunks"),
+            manifest: self.includes("manifest"),
+        }
+    }
+}
+
+/// Maximum number of tracks in one batch download
+const MAX_BATCH_TXIDS: usize = 50;
+
+#[derive(Deserialize)]
+pub struct FlacBatchDownloadRequest {
+    pub txids: Vec<String>,
+    pub network: Option<Network>,
+    /// With the admin key, create the job even past the backlog ceilings
+    #[serde(default)]
+    pub bypass_backlog: bool,
+    #[serde(default)]
+    pub key: String,
 }
 
 #[derive(Serialize)]
-pub struct CoverResponse {
+pub struct FlacBatchDownloadResponse {
     pub success: bool,
-    pub data: Option<String>,  // Base64 encoded image data
-    pub content_type: Option<String>,
-    pub error: Option<String>,
+    pub job_id: String,
+    pub owner_token: Option<String>,
+    pub child_job_ids: Vec<String>,
 }
 
-pub async fn get_cover_image(
+/// Start a batch FLAC download that zips several tracks together
+pub async fn start_flac_batch_download(
     State(state): State<Arc<RwLock<AppState>>>,
-    Json(req): Json<CoverRequest>,
-) -> impl IntoResponse {
-    let txid = req.txid.trim().to_string();
-    let network = req.network.unwrap_or_else(|| "mainnet".to_string());
+    admin: AdminAuth,
+    Json(req): Json<FlacBatchDownloadRequest>,
+) -> Result<Json<FlacBatchDownloadResponse>, ApiError> {
+    let network = req.network.unwrap_or_default();
+    let txids: Vec<String> = req.txids.iter().map(|t| t.trim().to_string()).collect();
 
-    if txid.len() != 64 {
-        return Json(CoverResponse {
-            success: false,
-            data: None,
-            content_type: None,
-            error: Some("Invalid TXID format".to_string()),
-        });
+    if txids.is_empty() || txids.len() > MAX_BATCH_TXIDS {
+        return Err(ApiError::invalid_request(format!(
+            "A batch must contain between 1 and {} txids",
+            MAX_BATCH_TXIDS
+        )));
     }
 
-    // Fetch transaction from blockchain
-    let tx_data = crate::fetch_tx_raw(&state, &txid, &network).await;
-    
-    match tx_data {
-        Ok(tx_hex) => {
-            // Extract image data from transaction
-            if let Some(image_data) = extract_image_from_tx(&tx_hex) {
-                let base64_data = base64::engine::general_purpose::STANDARD.encode(&image_data);
-                
-                // Detect content type from magic bytes
-                let content_type = detect_image_type(&image_data);
-                
-                Json(CoverResponse {
-                    success: true,
-                    data: Some(base64_data),
-                    content_type: Some(content_type),
-                    error: None,
-                })
-            } else {
-                Json(CoverResponse {
-                    success: false,
-                    data: None,
-                    content_type: None,
-                    error: Some("No image data found in transaction".to_string()),
-                })
-            }
-        }
-        Err(e) => {
-            Json(CoverResponse {
-                success: false,
-                data: None,
-                content_type: None,
-                error: Some(format!("Failed to fetch transaction: {}", e)),
-            })
+    if let Some(bad) = txids.iter().find(|t| t.len() != 64) {
+        return Err(ApiError::invalid_request(format!(
+            "Invalid TXID format (must be 64 characters): {}",
+            bad
+        )));
+    }
+
+    {
+        let state = state.read().await;
+        crate::routes::jobs::admit_new_job(&state, &admin, req.bypass_backlog, &req.key)?;
+    }
+
+    // Parent job tracks aggregate progress and owns the zip
+    let job_id = uuid::Uuid::new_v4().to_string().replace("-", "");
+    let mut parent = Job::new_flac_download(job_id.clone(), txids.join(","))
+        .with_status(
+            JobStatus::Processing,
+            MessageKey::StartingBatchDownload.with("n", txids.len()),
+        )
+        .with_network(network);
+    parent.job_type = JobType::FlacBatchDownload;
+
+    let children: Vec<Job> = txids
+        .iter()
+        .map(|txid| {
+            Job::new_flac_download(uuid::Uuid::new_v4().to_string().replace("-", ""), txid.clone())
+                .with_status(JobStatus::Processing, MessageKey::WaitingForBatchSlot)
+                .with_network(network)
+                .with_parent(&parent)
+        })
+        .collect();
+
+    {
+        let state = state.read().await;
+        for job in std::iter::once(&parent).chain(children.iter()) {
+            state
+                .db
+                .insert_job(job)
+                .map_err(|e| ApiError::new(ErrorCode::DatabaseError, format!("Failed to create job: {}", e)))?;
         }
+
+        // Only the parent takes a scheduler slot; it runs the children itself
+        crate::enqueue_job(&state, QueuedJob {
+            job_id: job_id.clone(),
+            job_type: JobType::FlacBatchDownload,
+            address: String::new(),
+            network,
+            admin_pay: false,
+            file_size: 0,
+            paid_satoshis: None,
+        });
     }
+
+    Ok(Json(FlacBatchDownloadResponse {
+        success: true,
+        job_id,
+        owner_token: parent.owner_token,
+        child_job_ids: children.into_iter().map(|c| c.id).collect(),
+    }))
 }
 
-fn extract_image_from_tx(tx_hex: &str) -> Option<Vec<u8>> {
-    let tx_bytes = hex::decode(tx_hex).ok()?;
-    
-    let mut i = 0;
-    i += 4; // version
-    
-    let (input_count, varint_size) = crate::read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..input_count {
-        i += 32;
-        i += 4;
-        let (script_len, vs) = crate::read_varint(&tx_bytes[i..])?;
-        i += vs;
-        i += script_len as usize;
-        i += 4;
+/// Cancel a FLAC download. The download loop stops before fetching the
+/// next chunk; a batch cancels its remaining tracks along with it.
+pub async fn cancel_flac_download(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(job_id): Path<String>,
+    Json(req): Json<CancelJobRequest>,
+) -> Result<Json<CancelJobResponse>, ApiError> {
+    let job_type = {
+        let state = state.read().await;
+        state
+            .db
+            .get_job(&job_id)
+            .map_err(ApiError::database)?
+            .ok_or_else(ApiError::job_not_found)?
+            .job_type
+    };
+    if !matches!(job_type, JobType::FlacDownload | JobType::FlacBatchDownload) {
+        return Err(ApiError::invalid_request("Job is not a FLAC download"));
     }
-    
-    let (output_count, varint_size) = crate::read_varint(&tx_bytes[i..])?;
-    i += varint_size;
-    
-    for _ in 0..output_count {
-        i += 8; // value
-        let (script_len, vs) = crate::read_varint(&tx_bytes[i..])?;
-        i += vs;
-        
-        if i + script_len as usize > tx_bytes.len() {
-            break;
-        }
-        
-        let script = &tx_bytes[i..i + script_len as usize];
-        i += script_len as usize;
-        
-        // Check for OP_FALSE OP_IF (0x00 0x63) - our cover image format
-        if script.len() > 4 && script[0] == 0x00 && script[1] == 0x63 {
-            if let Some(data) = parse_coverart_script(&script[2..]) {
-                return Some(data);
+
+    cancel_job(State(state), Path(job_id), Json(req)).await
+}
+
+#[derive(Serialize)]
+pub struct CoverAttachResponse {
+    pub success: bool,
+    pub job_id: String,
+    pub owner_token: Option<String>,
+    pub payment_address: Option<String>,
+    pub required_satoshis: Option<i64>,
+    pub admin_pay: bool,
+}
+
+/// Satoshis to request for attaching a cover: the cover transaction and the
+/// cover update record, each with one data output
+pub fn quote_cover_attach_cost(bsv: &BsvService, cover_data: &[u8]) -> Amount {
+    let placeholder_txid = "0".repeat(64);
+    let cover_script_len = bsv.create_cover_image_script(cover_data).len();
+    let update_script_len = BsvService::create_cover_update_script(&placeholder_txid, &placeholder_txid).len();
+    let fees = bsv.fee_for_size(150 + cover_script_len).saturating_add(bsv.fee_for_size(150 + update_script_len));
+    fees.saturating_add(bsv.data_output_satoshis.saturating_mul(2))
+}
+
+/// Attach a cover to a track that is already on chain. The image is
+/// inscribed as a coverart transaction and linked to the manifest by a
+/// flacstore-coverupdate record; players here then show the new cover.
+pub async fn prepare_cover_attach(
+    State(state): State<Arc<RwLock<AppState>>>,
+    admin: AdminAuth,
+    mut multipart: Multipart,
+) -> Result<Json<CoverAttachResponse>, ApiError> {
+    let mut manifest_txid: Option<String> = None;
+    let mut cover_data: Option<Vec<u8>> = None;
+    let mut network = Network::Mainnet;
+    let mut admin_pay_requested = false;
+    let mut owner_token: Option<String> = None;
+    let mut admin_key: Option<String> = None;
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        let name = field.name().unwrap_or("").to_string();
+
+        match name.as_str() {
+            "cover" => {
+                if let Ok(data) = field.bytes().await {
+                    if !data.is_empty() {
+                        cover_data = Some(data.to_vec());
+                    }
+                }
             }
-        }
-        
-        // Also check for OP_RETURN (0x6a) or OP_FALSE OP_RETURN (0x00 0x6a)
-        if script.len() > 2 && (script[0] == 0x6a || (script[0] == 0x00 && script[1] == 0x6a)) {
-            let start = if script[0] == 0x6a { 1 } else { 2 };
-            if let Some(data) = parse_image_script(&script[start..]) {
-                return Some(data);
+            "manifest_txid" => {
+                if let Ok(data) = field.text().await {
+                    manifest_txid = Some(data.trim().to_string());
+                }
+            }
+            "network" => {
+                if let Ok(data) = field.text().await {
+                    if !data.trim().is_empty() {
+                        network = Network::from_str(&data).ok_or_else(|| {
+                            ApiError::invalid_request(format!("Unknown network: {}", data.trim()))
+                        })?;
+                    }
+                }
             }
+            "admin_pay" => {
+                if let Ok(data) = field.text().await {
+                    admin_pay_requested = data.trim().to_lowercase() == "true";
+                }
+            }
+            "owner_token" => {
+                if let Ok(data) = field.text().await {
+                    owner_token = Some(data.trim().to_string());
+                }
+            }
+            "admin_key" => {
+                if let Ok(data) = field.text().await {
+                    admin_key = Some(data.trim().to_string());
+                }
+            }
+            _ => {}
         }
     }
-    
-    None
-}
 
-/// Parse cover art script in OP_FALSE OP_IF "coverart" <data chunks> OP_ENDIF format
-fn parse_coverart_script(script: &[u8]) -> Option<Vec<u8>> {
-    let mut i = 0;
-    
-    // First push should be "coverart" protocol identifier
-    if let Some((data, size)) = crate::read_push_data(&script[i..]) {
-        let data_str = String::from_utf8_lossy(&data);
-        if data_str == "coverart" {
-            i += size;
-        } else {
-            return None; // Not a coverart script
+    let manifest_txid = manifest_txid
+        .filter(|t| t.len() == 64)
+        .ok_or_else(|| ApiError::invalid_request("Invalid manifest TXID format"))?;
+    let cover_data = cover_data.ok_or_else(|| ApiError::invalid_request("No cover image provided"))?;
+
+    // Only the uploader, or an admin, may change a track's cover
+    let upload = {
+        let state = state.read().await;
+        state
+            .db
+            .find_upload_by_txid(&manifest_txid)
+            .map_err(ApiError::database)?
+            .filter(|job| job.job_type == JobType::FlacUpload)
+    };
+    let is_admin = admin.require(admin_key.as_deref().unwrap_or("")).is_ok();
+    if !is_admin {
+        match &upload {
+            Some(job) if job.owner_token.is_some() && job.owner_token == owner_token => {}
+            Some(_) => return Err(ApiError::new(ErrorCode::Forbidden, "Invalid owner token")),
+            None => {
+                return Err(ApiError::new(
+                    ErrorCode::Forbidden,
+                    "Only an admin can attach a cover to a track not uploaded here",
+                ))
+            }
         }
-    } else {
-        return None;
     }
-    
-    // Read all image data chunks until OP_ENDIF (0x68)
-    let mut image_data = Vec::new();
-    while i < script.len() {
-        // Check for OP_ENDIF
-        if script[i] == 0x68 {
-            break;
+
+    // Tracks outside the catalog are checked on chain before anything is paid
+    if upload.is_none() {
+        let tx_hex = crate::fetch_tx_raw(&state, &manifest_txid, network)
+            .await
+            .map_err(|e| ApiError::new(ErrorCode::TxFetchFailed, format!("Failed to fetch manifest: {}", e)))?;
+        if extract_flac_manifest_from_tx(&tx_hex).is_none() && extract_flac_from_tx(&tx_hex).is_none() {
+            return Err(ApiError::invalid_request("Transaction is not a FLAC upload"));
         }
-        
-        if let Some((chunk, size)) = crate::read_push_data(&script[i..]) {
-            image_data.extend_from_slice(&chunk);
-            i += size;
+    }
+
+    let required_satoshis = {
+        let state = state.read().await;
+        let required = quote_cover_attach_cost(&state.bsv, &cover_data).to_sat_i64();
+        state
+            .config
+            .check_upload_limits(required, 1, 2)
+            .map_err(|e| ApiError::new(ErrorCode::UploadTooExpensive, e))?;
+        required
+    };
+
+    // An ineligible admin pay request falls back to a normal payment address
+    let admin_wif = if admin_pay_requested {
+        let eligibility =
+            crate::routes::admin::admin_pay_eligibility(&state, network, Some(required_satoshis)).await;
+        if eligibility.eligible {
+            let state = state.read().await;
+            crate::routes::admin::get_admin_wif_for_network(&state.db, network)
         } else {
-            break;
+            None
         }
-    }
-    
-    if !image_data.is_empty() {
-        Some(image_data)
     } else {
         None
+    };
+    let use_admin_pay = admin_wif.is_some();
+
+    let (wif, address, derivation_index) = if let Some(wif) = admin_wif {
+        let address = BsvService::wif_to_address(&wif, network)
+            .map_err(|e| ApiError::new(ErrorCode::InvalidWif, format!("Invalid admin WIF: {}", e)))?;
+        (wif, address, None)
+    } else {
+        crate::routes::upload::new_payment_keypair(&state, network).await?
+    };
+    crate::routes::upload::ensure_payment_key(&wif, &address, network)?;
+
+    let job_id = uuid::Uuid::new_v4().to_string().replace("-", "");
+    let file_size = cover_data.len() as i64;
+    let job = Job::new_cover_attach(job_id.clone(), manifest_txid, cover_data, address.clone(), wif, required_satoshis)
+        .with_network(network)
+        .with_derivation_index(derivation_index);
+    let job = if use_admin_pay {
+        job.with_status(JobStatus::Processing, MessageKey::AdminPayStarting)
+    } else {
+        job
+    };
+
+    {
+        let state = state.read().await;
+        state
+            .db
+            .insert_job(&job)
+            .map_err(|e| ApiError::new(ErrorCode::DatabaseError, format!("Failed to create job: {}", e)))?;
+
+        if use_admin_pay {
+            crate::enqueue_job(&state, QueuedJob {
+                job_id: job_id.clone(),
+                job_type: JobType::CoverAttach,
+                address: address.clone(),
+                network,
+                admin_pay: true,
+                file_size,
+                paid_satoshis: None,
+            });
+        }
     }
+
+    Ok(Json(CoverAttachResponse {
+        success: true,
+        job_id,
+        owner_token: job.owner_token,
+        payment_address: if use_admin_pay { None } else { Some(address) },
+        required_satoshis: if use_admin_pay { None } else { Some(required_satoshis) },
+        admin_pay: use_admin_pay,
+    }))
 }
 
-fn parse_image_script(script: &[u8]) -> Option<Vec<u8>> {
-    let mut i = 0;
-    
-    // Skip protocol identifier if present
-    if let Some((data, size)) = crate::read_push_data(&script[i..]) {
-        let data_str = String::from_utf8_lossy(&data);
-        if data_str == "NAUSICA_COVER" || data_str == "19HxigV4QyBv3tHpQVcUEQyq1pzZVdoAut" {
-            i += size;
+/// Which version of a cover to serve
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverSize {
+    /// Small JPEG for listings
+    Thumb,
+    /// The image as inscribed, for the player
+    #[default]
+    Full,
+}
+
+/// Get cover image from BSV transaction
+#[derive(Deserialize)]
+pub struct CoverRequest {
+    pub txid: String,
+    pub network: Option<Network>,
+    /// Track the cover belongs to; a cover attached to it later is served instead
+    pub manifest_txid: Option<String>,
+    #[serde(default)]
+    pub size: CoverSize,
+}
+
+#[derive(Serialize)]
+pub struct CoverResponse {
+    pub success: bool,
+    pub data: String,  // Base64 encoded image data
+    pub content_type: String,
+}
+
+pub async fn get_cover_image(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Json(req): Json<CoverRequest>,
+) -> Result<Json<CoverResponse>, ApiError> {
+    let network = req.network.unwrap_or_default();
+    let image_data = load_cover(&state, &req.txid, req.manifest_txid.as_deref(), network, req.size).await?;
+    let base64_data = base64::engine::general_purpose::STANDARD.encode(&image_data);
+
+    // Detect content type from magic bytes
+    let content_type = detect_image_type(&image_data);
+
+    Ok(Json(CoverResponse {
+        success: true,
+        data: base64_data,
+        content_type,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct CoverImageQuery {
+    pub network: Option<Network>,
+    pub manifest_txid: Option<String>,
+    #[serde(default)]
+    pub size: CoverSize,
+}
+
+/// Cover image bytes, for use as an `<img>` source in listings
+pub async fn get_cover_image_file(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(txid): Path<String>,
+    Query(query): Query<CoverImageQuery>,
+) -> Result<Response, ApiError> {
+    let network = query.network.unwrap_or_default();
+    let image_data = load_cover(&state, &txid, query.manifest_txid.as_deref(), network, query.size).await?;
+    let content_type = detect_image_type(&image_data);
+    Ok(([(header::CONTENT_TYPE, content_type)], image_data).into_response())
+}
+
+/// A track's cover at `size`. Thumbnails come from the local copy kept at
+/// upload or download; a cover without one is fetched once and shrunk, and
+/// the result kept for later listings.
+async fn load_cover(
+    state: &Arc<RwLock<AppState>>,
+    txid: &str,
+    manifest_txid: Option<&str>,
+    network: Network,
+    size: CoverSize,
+) -> Result<Vec<u8>, ApiError> {
+    let linked_cover = match manifest_txid.map(str::trim) {
+        Some(manifest_txid) => {
+            let state = state.read().await;
+            state.db.latest_cover_link(manifest_txid, network).map_err(ApiError::database)?
         }
+        None => None,
+    };
+    let txid = linked_cover.unwrap_or_else(|| txid.trim().to_string());
+
+    if txid.len() != 64 {
+        return Err(ApiError::invalid_request("Invalid TXID format"));
     }
-    
-    // Read image data
-    if i < script.len() {
-        if let Some((data, _)) = crate::read_push_data(&script[i..]) {
-            // Check if it looks like image data (PNG, JPEG, etc.)
-            if data.len() > 4 {
-                return Some(data);
-            }
+
+    if size == CoverSize::Thumb {
+        let state = state.read().await;
+        if let Some(thumb) = state.db.get_cover_thumb(&txid).map_err(ApiError::database)? {
+            return Ok(thumb);
         }
     }
-    
-    None
+
+    // Fetch the image from the blockchain
+    let image_data = crate::fetch_tx_data(state, &txid, network, parse_image_output)
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::TxFetchFailed, format!("Failed to fetch transaction: {}", e)))?
+        .ok_or_else(|| ApiError::new(ErrorCode::NoDataFound, "No image data found in transaction"))?;
+
+    if size == CoverSize::Full {
+        return Ok(image_data);
+    }
+    // A cover already small enough is its own thumbnail
+    let thumb = thumbnail::make_thumbnail(&image_data).unwrap_or(image_data);
+    let _ = state.read().await.db.save_cover_thumb(&txid, &thumb);
+    Ok(thumb)
 }
 
 fn detect_image_type(data: &[u8]) -> String {
@@ -577,58 +1114,432 @@ fn detect_image_type(data: &[u8]) -> String {
     "image/png".to_string() // Default
 }
 
+/// Get lyrics from a FLAC manifest or single-transaction upload
+#[derive(Deserialize)]
+pub struct LyricsQuery {
+    pub network: Option<Network>,
+}
+
+#[derive(Serialize)]
+pub struct LyricsResponse {
+    pub success: bool,
+    pub txid: String,
+    pub format: LyricsFormat,
+    pub lyrics: String,  // Lyrics as stored on-chain
+    pub plain: String,   // Lyrics with LRC tags stripped
+    pub lines: Vec<LyricLine>,   // Timed lines, empty for plain lyrics
+}
+
+pub async fn get_lyrics(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(txid): Path<String>,
+    Query(query): Query<LyricsQuery>,
+) -> Result<Json<LyricsResponse>, ApiError> {
+    let txid = txid.trim().to_string();
+    let network = query.network.unwrap_or_default();
+
+    if txid.len() != 64 {
+        return Err(ApiError::invalid_request("Invalid TXID format"));
+    }
+
+    let tx_hex = crate::fetch_tx_raw(&state, &txid, network)
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::TxFetchFailed, format!("Failed to fetch transaction: {}", e)))?;
+
+    // Single-transaction uploads keep the lyrics in their own metadata
+    let (lyrics, format, lyrics_txid) = match extract_flac_manifest_from_tx(&tx_hex) {
+        Some(manifest) => (manifest.lyrics, manifest.lyrics_format, manifest.lyrics_txid),
+        None => extract_flac_from_tx(&tx_hex)
+            .map(|file| (file.lyrics, file.lyrics_format, file.lyrics_txid))
+            .ok_or_else(|| ApiError::new(ErrorCode::NoDataFound, "No FLAC upload found in transaction"))?,
+    };
+
+    // Long lyrics are inscribed in their own transaction and only referenced
+    let (lyrics, format) = match lyrics_txid {
+        Some(lyrics_txid) => crate::fetch_lyrics_tx(&state, &lyrics_txid, network)
+            .await
+            .map_err(|e| ApiError::new(ErrorCode::TxFetchFailed, format!("Failed to fetch lyrics transaction: {}", e)))?
+            .ok_or_else(|| {
+                ApiError::new(ErrorCode::NoDataFound, format!("Lyrics transaction {} holds no lyrics", lyrics_txid))
+            })?,
+        None => (
+            lyrics.ok_or_else(|| ApiError::new(ErrorCode::NoDataFound, "Upload has no lyrics"))?,
+            format,
+        ),
+    };
+
+    let (plain, lines) = match format {
+        LyricsFormat::Lrc => (lyrics::to_plain(&lyrics), lyrics::parse_lrc(&lyrics)),
+        LyricsFormat::Plain => (lyrics.clone(), Vec::new()),
+    };
+
+    Ok(Json(LyricsResponse {
+        success: true,
+        txid,
+        format,
+        lyrics,
+        plain,
+        lines,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct RetryChunkRequest {
+    #[serde(default)]
+    pub key: String,
+    pub job_id: String,
+    /// Zero-based, like the index inscribed in the chunk
+    pub chunk_index: usize,
+}
+
+#[derive(Serialize)]
+pub struct RetryChunkResponse {
+    pub success: bool,
+    pub job_id: String,
+    pub chunk_index: usize,
+    pub txid: String,
+}
+
+/// Re-broadcast one chunk of a FLAC upload from its kept raw transaction,
+/// for a chunk that dropped out of mempools. Each provider's answer is
+/// recorded with the job's other broadcast attempts.
+pub async fn retry_flac_chunk(
+    State(state): State<Arc<RwLock<AppState>>>,
+    auth: AdminAuth,
+    Json(req): Json<RetryChunkRequest>,
+) -> Result<Json<RetryChunkResponse>, ApiError> {
+    auth.require(&req.key)?;
+
+    let (txid, raw_tx, network) = {
+        let state = state.read().await;
+        let job = state
+            .db
+            .get_job(&req.job_id)
+            .map_err(ApiError::database)?
+            .filter(|job| job.job_type == JobType::FlacUpload)
+            .ok_or_else(|| ApiError::new(ErrorCode::JobNotFound, "FLAC upload not found"))?;
+        let network = job.network.unwrap_or_default();
+
+        let chunk_txids: Vec<String> = job
+            .chunk_txids
+            .unwrap_or_default()
+            .split(',')
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect();
+        let txid = chunk_txids.get(req.chunk_index).cloned().ok_or_else(|| {
+            ApiError::invalid_request(format!(
+                "Chunk {} does not exist, the upload has {} chunks",
+                req.chunk_index,
+                chunk_txids.len()
+            ))
+        })?;
+
+        let raw_tx = state
+            .db
+            .get_broadcast_tx(&txid, network)
+            .map_err(ApiError::database)?
+            .ok_or_else(|| {
+                ApiError::new(
+                    ErrorCode::NoDataFound,
+                    format!("The raw transaction of chunk {} ({}) is no longer kept", req.chunk_index, txid),
+                )
+            })?;
+        (txid, raw_tx, network)
+    };
+
+    crate::broadcast_tx(&state, Some(&req.job_id), &raw_tx, network)
+        .await
+        .map_err(|e| {
+            ApiError::new(
+                ErrorCode::BroadcastFailed,
+                format!("Re-broadcast of chunk {} ({}) failed: {}", req.chunk_index, txid, e),
+            )
+        })?;
+    tracing::info!("Re-broadcast chunk {} of job {}: {}", req.chunk_index, req.job_id, txid);
+
+    Ok(Json(RetryChunkResponse {
+        success: true,
+        job_id: req.job_id,
+        chunk_index: req.chunk_index,
+        txid,
+    }))
+}
+
 /// Get FLAC job status
 pub async fn get_flac_status(
     State(state): State<Arc<RwLock<AppState>>>,
     Path(job_id): Path<String>,
-) -> impl IntoResponse {
+    Query(query): Query<FlacStatusQuery>,
+) -> Result<Json<FlacStatusResponse>, ApiError> {
     let state = state.read().await;
 
-    match state.db.get_job(&job_id) {
-        Ok(Some(job)) => {
-            let status = match job.status {
-                JobStatus::PendingPayment => "pending_payment",
-                JobStatus::Processing => "processing",
-                JobStatus::Complete => "complete",
-                JobStatus::Error => "error",
-            };
-
-            Json(FlacStatusResponse {
-                status: status.to_string(),
-                progress: job.progress,
-                message: job.message,
-                txid: job.manifest_txid,
-                download_link: job.download_link,
-                filename: job.filename,
-                track_title: job.track_title,
-                artist_name: job.artist_name,
-                cover_txid: job.cover_txid,
-                lyrics: job.lyrics,
-            })
+    let job = state
+        .db
+        .get_job(&job_id)
+        .map_err(ApiError::database)?
+        .ok_or_else(ApiError::job_not_found)?;
+
+    let mut response = flac_status_response(job, query.extras());
+    response.poll_after_ms = Some(state.config.status_poll_after_ms());
+    Ok(Json(response))
+}
+
+/// How often the status stream checks the job for changes
+const STATUS_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Stream FLAC job status as server-sent events.
+/// A `status` event carries the same body as `get_flac_status` and is sent
+/// whenever it changes; the stream ends once the job has finished.
+pub async fn flac_status_events(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(job_id): Path<String>,
+    Query(query): Query<FlacStatusQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let extras = query.extras();
+    {
+        let state = state.read().await;
+        state
+            .db
+            .get_job(&job_id)
+            .map_err(ApiError::database)?
+            .ok_or_else(ApiError::job_not_found)?;
+    }
+
+    // (last body sent, whether the job has finished)
+    let events = stream::unfold((None::<String>, false), move |(last, finished)| {
+        let state = state.clone();
+        let job_id = job_id.clone();
+        async move {
+            if finished {
+                return None;
+            }
+            loop {
+                let job = {
+                    let state = state.read().await;
+                    state.db.get_job(&job_id).ok().flatten()?
+                };
+                let finished = matches!(
+                    job.status,
+                    JobStatus::Complete | JobStatus::Error | JobStatus::Cancelled
+                );
+                let body = serde_json::to_string(&flac_status_response(job, extras)).ok()?;
+                if last.as_deref() != Some(body.as_str()) {
+                    let event = Event::default().event("status").data(body.clone());
+                    return Some((Ok(event), (Some(body), finished)));
+                }
+                tokio::time::sleep(STATUS_EVENT_INTERVAL).await;
+            }
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+fn flac_status_response(job: Job, extras: StatusExtras) -> FlacStatusResponse {
+    let chunk_txids = job.chunk_txid_list().filter(|_| extras.chunks);
+    let manifest = job
+        .manifest_json
+        .filter(|_| extras.manifest)
+        .and_then(|json| serde_json::from_str(&json).ok());
+    let status = match job.status {
+        JobStatus::PendingPayment => "pending_payment",
+        JobStatus::Processing => "processing",
+        JobStatus::Complete => "complete",
+        JobStatus::Error => "error",
+        JobStatus::Cancelled => "cancelled",
+    };
+
+    FlacStatusResponse {
+        status: status.to_string(),
+        progress: job.progress,
+        message: job.message,
+        message_key: job.message_key,
+        message_params: job.message_params.and_then(|p| serde_json::from_str(&p).ok()),
+        txid: job.manifest_txid,
+        download_link: job.download_link,
+        filename: job.filename,
+        track_title: job.track_title,
+        artist_name: job.artist_name,
+        cover_txid: job.cover_txid,
+        lyrics: job.lyrics,
+        bytes_done: job.bytes_done,
+        bytes_total: job.bytes_total,
+        eta_seconds: job.eta_seconds,
+        payment_received_at: job.payment_received_at,
+        payment_received_satoshis: job.payment_received_satoshis,
+        error_code: job.error_code,
+        chunk_txids,
+        split_txid: job.split_txid.filter(|_| extras.chunks),
+        manifest,
+        poll_after_ms: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::bsv::FLAC_CHUNK_SIZE;
+    use crate::test_support::{multipart_body, serve, test_config, test_state, test_state_with};
+
+    async fn plan_for(file_size: usize) -> FlacPlanResponse {
+        plan_with(&test_state(), file_size).await
+    }
+
+    async fn plan_with(state: &Arc<RwLock<AppState>>, file_size: usize) -> FlacPlanResponse {
+        let request = FlacPlanRequest { file_size, network: None, lyrics_bytes: 0 };
+        plan_flac_upload(State(state.clone()), Json(request)).await.unwrap().0
+    }
+
+    #[tokio::test]
+    async fn plan_splits_files_into_full_chunks_and_a_remainder() {
+        let plan = plan_for(2 * FLAC_CHUNK_SIZE + FLAC_CHUNK_SIZE / 2).await;
+        assert!(plan.chunked);
+        assert_eq!(plan.chunk_count, 3);
+        assert_eq!(plan.chunk_size, FLAC_CHUNK_SIZE);
+        assert_eq!(plan.last_chunk_size, FLAC_CHUNK_SIZE / 2);
+        assert_eq!(plan.split_outputs, 4);
+        assert_eq!(plan.split_tx_count, 1);
+        assert!(plan.chunk_tx_fee > Amount::ZERO);
+        assert!(plan.required_satoshis > plan.split_tx_fee);
+
+        // An exact multiple has no short last chunk
+        let plan = plan_for(2 * FLAC_CHUNK_SIZE).await;
+        assert_eq!((plan.chunk_count, plan.last_chunk_size), (2, FLAC_CHUNK_SIZE));
+    }
+
+    #[tokio::test]
+    async fn small_files_plan_a_single_transaction() {
+        let plan = plan_for(1000).await;
+        assert!(!plan.chunked);
+        assert_eq!((plan.chunk_count, plan.chunk_size, plan.last_chunk_size), (1, 1000, 1000));
+        assert_eq!((plan.split_outputs, plan.split_tx_count), (0, 0));
+        assert_eq!(plan.chunk_tx_fee, plan.required_satoshis);
+    }
+
+    #[tokio::test]
+    async fn plan_flags_uploads_just_past_each_limit() {
+        let mut config = test_config();
+        config.max_upload_cost_satoshis = 100_000_000;
+        let state = test_state_with(config);
+        let file_size = 2 * FLAC_CHUNK_SIZE + FLAC_CHUNK_SIZE / 2;
+        let plan = plan_with(&state, file_size).await;
+        assert_eq!(plan.rejected_reason, None);
+        let required = plan.required_satoshis.to_sat_i64();
+        let tx_outputs = state.read().await.bsv.plan_flac_upload(file_size, Network::Mainnet).tx_outputs;
+
+        // Each limit's setter, the plan's own value for it and the problem reported past it
+        type SetLimit = fn(&mut crate::config::Config, i64);
+        let limits: [(SetLimit, i64, &str); 3] = [
+            (|config, limit| config.max_upload_cost_satoshis = limit, required, "satoshi limit"),
+            (|config, limit| config.max_chunk_count = limit as usize, 3, "chunk transactions exceeds the limit of 2"),
+            (|config, limit| config.max_tx_outputs = limit as usize, tx_outputs as i64, "outputs, more than the"),
+        ];
+        for (set_limit, at_limit, problem) in limits {
+            let defaults = state.read().await.config.clone();
+            set_limit(&mut state.write().await.config, at_limit);
+            assert_eq!(plan_with(&state, file_size).await.rejected_reason, None, "{}", problem);
+            set_limit(&mut state.write().await.config, at_limit - 1);
+            let reason = plan_with(&state, file_size).await.rejected_reason.unwrap();
+            assert!(reason.contains(problem), "{}", reason);
+            assert!(reason.ends_with("Try compressing the file, converting WAV to FLAC, or uploading a smaller file."));
+            state.write().await.config = defaults;
+        }
+    }
+
+    /// Body of the next `status` event on an SSE response
+    async fn next_status(response: &mut reqwest::Response, buffer: &mut String) -> serde_json::Value {
+        loop {
+            if let Some(end) = buffer.find("\n\n") {
+                let frame: String = buffer.drain(..end + 2).collect();
+                if let Some(data) = frame.lines().find_map(|line| line.strip_prefix("data: ")) {
+                    assert!(frame.contains("event: status"), "{}", frame);
+                    return serde_json::from_str(data).unwrap();
+                }
+                continue;
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(5), response.chunk()).await.unwrap().unwrap();
+            buffer.push_str(std::str::from_utf8(&chunk.expect("stream ended")).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn progress_updates_stream_with_the_track_title() {
+        let state = test_state();
+        let job = Job::new_flac_upload("flac".to_string(), "song.flac".to_string(), 4, b"fLaC".to_vec(), "addr".to_string(), "wif".to_string(), 0)
+            .with_track_metadata(Some("Night Drive".to_string()), Some("The Band".to_string()), None)
+            .with_status(JobStatus::Processing, MessageKey::Starting);
+        state.read().await.db.insert_job(&job).unwrap();
+        let app = serve(
+            axum::Router::new()
+                .route("/api/flac/status/:job_id/events", axum::routing::get(flac_status_events))
+                .with_state(state.clone()),
+        )
+        .await;
+
+        let mut response = reqwest::get(format!("{}/api/flac/status/flac/events", app)).await.unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut buffer = String::new();
+        let first = next_status(&mut response, &mut buffer).await;
+        assert_eq!((first["status"].as_str(), first["progress"].as_f64()), (Some("processing"), Some(0.0)));
+
+        let message = MessageKey::UploadingChunks.with("n", 3);
+        state.read().await.db.update_job_progress("flac", 42.0, message).unwrap();
+        let update = next_status(&mut response, &mut buffer).await;
+        assert_eq!(update["progress"], 42.0);
+        assert_eq!(update["message_key"], "uploading_chunks");
+        assert_eq!(update["track_title"], "Night Drive");
+        assert_eq!(update["artist_name"], "The Band");
+        assert!(update.get("poll_after_ms").is_none());
+
+        // The stream ends after the job's final status
+        state.read().await.db.update_job_complete("flac", &"ab".repeat(32), None).unwrap();
+        assert_eq!(next_status(&mut response, &mut buffer).await["status"], "complete");
+        let end = tokio::time::timeout(Duration::from_secs(5), response.chunk()).await.unwrap().unwrap();
+        assert_eq!(end, None);
+    }
+
+    #[tokio::test]
+    async fn prepare_refuses_uploads_over_the_output_cap() {
+        let state = test_state();
+        state.write().await.bsv.provider_tx_limits.bitails = Some(1);
+        let app = serve(
+            axum::Router::new()
+                .route("/api/flac/upload", axum::routing::post(prepare_flac_upload))
+                .with_state(state.clone()),
+        )
+        .await;
+        let data: Vec<u8> = [&b"fLaC"[..], &[7; 2496]].concat();
+        let prepare = || async {
+            let (content_type, body) = multipart_body(&[("file", Some("song.flac"), &data), ("force_new", None, b"true")]);
+            let response = reqwest::Client::new()
+                .post(format!("{}/api/flac/upload", app))
+                .header("content-type", content_type)
+                .body(body)
+                .send()
+                .await
+                .unwrap();
+            (response.status(), response.json::<serde_json::Value>().await.unwrap())
+        };
+        let tx_outputs = || async { state.read().await.bsv.plan_flac_upload(data.len(), Network::Mainnet).tx_outputs };
+
+        // Three chunks and the manifest, plus change, in one split
+        assert_eq!(tx_outputs().await, 5);
+        for (max_split_outputs, max_tx_outputs) in [(250, 5), (2, 3)] {
+            state.write().await.bsv.max_split_outputs = max_split_outputs;
+            assert_eq!(tx_outputs().await, max_tx_outputs);
+            state.write().await.config.max_tx_outputs = max_tx_outputs;
+            let (status, body) = prepare().await;
+            assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+
+            state.write().await.config.max_tx_outputs = max_tx_outputs - 1;
+            let (status, body) = prepare().await;
+            assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+            assert_eq!(body["error"]["code"], "UPLOAD_TOO_EXPENSIVE");
+            let message = body["error"]["message"].as_str().unwrap();
+            assert!(message.contains(&format!("{} outputs, more than the {} allowed", max_tx_outputs, max_tx_outputs - 1)), "{}", message);
         }
-        Ok(None) => Json(FlacStatusResponse {
-            status: "not_found".to_string(),
-            progress: 0.0,
-            message: "Job not found".to_string(),
-            txid: None,
-            download_link: None,
-            filename: None,
-            track_title: None,
-            artist_name: None,
-            cover_txid: None,
-            lyrics: None,
-        }),
-        Err(e) => Json(FlacStatusResponse {
-            status: "error".to_string(),
-            progress: 0.0,
-            message: format!("Database error: {}", e),
-            txid: None,
-            download_link: None,
-            filename: None,
-            track_title: None,
-            artist_name: None,
-            cover_txid: None,
-            lyrics: None,
-        }),
+        // Only the jobs within the cap were created
+        assert_eq!(state.read().await.db.get_all_jobs(None).unwrap().len(), 2);
     }
 }
@@ -0,0 +1,345 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::models::{Amount, AmountError, ErrorCode, JobEvent, JobStatus, MessageKey, StatusMessage};
+use crate::routes::admin::AdminAuth;
+use crate::routes::error::ApiError;
+use crate::AppState;
+
+/// Retry-After sent while the job backlog is over its ceiling
+const BACKLOG_RETRY_AFTER_SECONDS: u64 = 30;
+
+/// Jobs waiting for payment and paid jobs waiting for a processing slot
+#[derive(Debug, Clone, Copy)]
+pub struct Backlog {
+    pub pending_jobs: i64,
+    pub queued_jobs: usize,
+}
+
+pub fn backlog(state: &AppState) -> Result<Backlog, ApiError> {
+    Ok(Backlog {
+        pending_jobs: state
+            .db
+            .count_jobs_with_status(JobStatus::PendingPayment)
+            .map_err(ApiError::database)?,
+        queued_jobs: state.scheduler.positions().len(),
+    })
+}
+
+/// Refuse a new job while MAX_PENDING_JOBS or MAX_QUEUED_JOBS is exceeded,
+/// so the payment watcher's tick stays shorter than its interval. Admins
+/// can pass `bypass` with their key to create one anyway.
+pub fn admit_new_job(state: &AppState, auth: &AdminAuth, bypass: bool, key: &str) -> Result<(), ApiError> {
+    if bypass {
+        return auth.require(key);
+    }
+    let config = &state.config;
+    if config.max_pending_jobs.is_none() && config.max_queued_jobs.is_none() {
+        return Ok(());
+    }
+
+    let backlog = backlog(state)?;
+    let busy = |what: &str, depth: String, limit: String| {
+        Err(ApiError::new(
+            ErrorCode::ServerBusy,
+            format!("Too many {} ({}, limit {}), try again later", what, depth, limit),
+        )
+        .with_retry_after(BACKLOG_RETRY_AFTER_SECONDS))
+    };
+    if let Some(limit) = config.max_pending_jobs.filter(|&limit| backlog.pending_jobs >= limit) {
+        return busy("jobs waiting for payment", backlog.pending_jobs.to_string(), limit.to_string());
+    }
+    if let Some(limit) = config.max_queued_jobs.filter(|&limit| backlog.queued_jobs >= limit) {
+        return busy("jobs queued for processing", backlog.queued_jobs.to_string(), limit.to_string());
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct CancelJobRequest {
+    pub owner_token: String,
+}
+
+#[derive(Serialize)]
+pub struct CancelJobResponse {
+    pub success: bool,
+    pub status: String,
+    pub message: String,
+}
+
+/// Cancel a job. Running jobs stop at the next chunk boundary.
+pub async fn cancel_job(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(job_id): Path<String>,
+    Json(req): Json<CancelJobRequest>,
+) -> Result<Json<CancelJobResponse>, ApiError> {
+    let ok_response = |status: JobStatus, message: &str| {
+        Ok(Json(CancelJobResponse {
+            success: true,
+            status: status.as_str().to_string(),
+            message: message.to_string(),
+        }))
+    };
+
+    let state = state.read().await;
+
+    let job = state
+        .db
+        .get_job(&job_id)
+        .map_err(ApiError::database)?
+        .ok_or_else(ApiError::job_not_found)?;
+
+    // Jobs created before owner tokens existed can't be cancelled
+    if job.owner_token.as_deref() != Some(req.owner_token.as_str()) {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Invalid owner token"));
+    }
+
+    match job.status {
+        JobStatus::PendingPayment => {
+            let message = MessageKey::CancelledBeforePayment;
+            if state.db.expire_pending_job(&job_id, message).map_err(ApiError::database)? {
+                ok_response(JobStatus::Error, &StatusMessage::from(message).english())
+            } else {
+                // The payment arrived between the read and the update
+                Err(ApiError::new(
+                    ErrorCode::JobFinished,
+                    "Payment was already received, try cancelling again",
+                ))
+            }
+        }
+        JobStatus::Processing => {
+            if state.cancellations.cancel(&job_id) {
+                return ok_response(JobStatus::Processing, "Cancelling, the job stops after the current chunk");
+            }
+
+            // Not running yet: take it out of the queue along with any batch children
+            state.scheduler.remove(&job_id);
+            state.funding_keys.release(&job_id);
+            let message = MessageKey::CancelledBeforeProcessing;
+            let _ = state.db.update_job_cancelled(&job_id, message);
+            for child in state.db.get_child_jobs(&job_id).unwrap_or_default() {
+                let _ = state.db.update_job_cancelled(&child.id, message);
+            }
+            ok_response(JobStatus::Cancelled, &StatusMessage::from(message).english())
+        }
+        JobStatus::Complete | JobStatus::Error | JobStatus::Cancelled => {
+            Err(ApiError::new(ErrorCode::JobFinished, "Job has already finished"))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JobPaymentsQuery {
+    pub owner_token: String,
+}
+
+#[derive(Serialize)]
+pub struct JobPayment {
+    pub txid: String,
+    pub satoshis: i64,
+    pub block_height: Option<i64>,
+    /// Unset when the chain tip couldn't be fetched
+    pub confirmations: Option<i64>,
+    pub first_seen_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct JobPaymentsResponse {
+    pub success: bool,
+    pub job_id: String,
+    pub payment_address: Option<String>,
+    pub required_satoshis: Option<i64>,
+    pub total_satoshis: Amount,
+    /// What was paid beyond the quote, e.g. when the same payment was sent twice
+    pub surplus_satoshis: Amount,
+    pub payments: Vec<JobPayment>,
+}
+
+/// Every transaction seen paying a job's address, for "I paid twice" reports
+pub async fn get_job_payments(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(job_id): Path<String>,
+    Query(query): Query<JobPaymentsQuery>,
+) -> Result<Json<JobPaymentsResponse>, ApiError> {
+    let (job, records) = {
+        let state = state.read().await;
+        let job = state
+            .db
+            .get_job(&job_id)
+            .map_err(ApiError::database)?
+            .ok_or_else(ApiError::job_not_found)?;
+        if job.owner_token.as_deref() != Some(query.owner_token.as_str()) {
+            return Err(ApiError::new(ErrorCode::Forbidden, "Invalid owner token"));
+        }
+        let records = state.db.get_payments(&job_id).map_err(ApiError::database)?;
+        (job, records)
+    };
+
+    // Depth is counted from the tip now, not from when the payment was seen
+    let tip = if records.iter().any(|r| r.block_height.is_some()) {
+        crate::get_chain_height(job.network.unwrap_or_default()).await.ok()
+    } else {
+        None
+    };
+    let payments: Vec<JobPayment> = records
+        .into_iter()
+        .map(|record| JobPayment {
+            confirmations: match record.block_height {
+                Some(height) => tip.map(|tip| (tip - height + 1).max(0)),
+                None => Some(0),
+            },
+            txid: record.txid,
+            satoshis: record.satoshis,
+            block_height: record.block_height,
+            first_seen_at: record.first_seen_at,
+        })
+        .collect();
+
+    let invalid_amount = |e: AmountError| ApiError::new(ErrorCode::InternalError, format!("Invalid payment amount: {}", e));
+    let total_satoshis = Amount::sum_sat(payments.iter().map(|p| p.satoshis)).map_err(invalid_amount)?;
+    let surplus_satoshis = match job.required_satoshis {
+        Some(required) => {
+            let required = Amount::try_from(required).map_err(invalid_amount)?;
+            total_satoshis.checked_sub(required).unwrap_or(Amount::ZERO)
+        }
+        None => Amount::ZERO,
+    };
+    Ok(Json(JobPaymentsResponse {
+        success: true,
+        job_id: job.id,
+        payment_address: job.payment_address,
+        required_satoshis: job.required_satoshis,
+        total_satoshis,
+        surplus_satoshis,
+        payments,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct JobLogsQuery {
+    pub owner_token: String,
+}
+
+#[derive(Serialize)]
+pub struct JobLogsResponse {
+    pub success: bool,
+    pub job_id: String,
+    pub events: Vec<JobEvent>,
+}
+
+/// Every status and progress update of a job, oldest first, so a failed
+/// upload can be traced step by step instead of only by its last message
+pub async fn get_job_logs(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(job_id): Path<String>,
+    Query(query): Query<JobLogsQuery>,
+) -> Result<Json<JobLogsResponse>, ApiError> {
+    let state = state.read().await;
+    let job = state
+        .db
+        .get_job(&job_id)
+        .map_err(ApiError::database)?
+        .ok_or_else(ApiError::job_not_found)?;
+    if job.owner_token.as_deref() != Some(query.owner_token.as_str()) {
+        return Err(ApiError::new(ErrorCode::Forbidden, "Invalid owner token"));
+    }
+    let events = state.db.get_job_events(&job_id).map_err(ApiError::database)?;
+    Ok(Json(JobLogsResponse { success: true, job_id: job.id, events }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Job;
+    use crate::services::bitails::Utxo;
+    use crate::test_support::{serve, test_state};
+    use axum::{routing::get, Router};
+
+    fn utxo(txid: &str, satoshis: i64, blockheight: Option<i64>) -> Utxo {
+        Utxo {
+            txid: txid.to_string(),
+            vout: 0,
+            satoshis,
+            script_pubkey: String::new(),
+            blockheight,
+            confirmations: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn second_payment_is_recorded_as_surplus() {
+        let state = test_state();
+        let job = Job::new_upload("job-1".to_string(), "a.txt".to_string(), 5, b"hello".to_vec(), "addr".to_string(), "wif".to_string(), 1500);
+        let owner_token = job.owner_token.clone().unwrap();
+        state.read().await.db.insert_job(&job).unwrap();
+
+        // The watcher sees the first payment, then on a later tick both
+        let first = "aa".repeat(32);
+        let second = "bb".repeat(32);
+        crate::record_payments(&*state.read().await, "job-1", &[utxo(&first, 1500, None)]);
+        crate::record_payments(
+            &*state.read().await,
+            "job-1",
+            &[utxo(&first, 1500, Some(800_000)), utxo(&second, 1200, None)],
+        );
+
+        let app = serve(Router::new().route("/api/jobs/:job_id/payments", get(get_job_payments)).with_state(state)).await;
+        let body: serde_json::Value = reqwest::get(format!("{}/api/jobs/job-1/payments?owner_token={}", app, owner_token))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["total_satoshis"], 2700);
+        assert_eq!(body["surplus_satoshis"], 1200);
+        let payments = body["payments"].as_array().unwrap();
+        assert_eq!(payments.len(), 2);
+        assert_eq!(payments[0]["txid"], first);
+        assert_eq!(payments[0]["satoshis"], 1500);
+        assert_eq!(payments[0]["block_height"], 800_000);
+        assert_eq!(payments[1]["txid"], second);
+        assert_eq!(payments[1]["satoshis"], 1200);
+        assert_eq!(payments[1]["confirmations"], 0);
+    }
+
+    #[tokio::test]
+    async fn job_logs_list_progress_updates_in_order() {
+        let state = test_state();
+        let job = Job::new_upload("job-1".to_string(), "a.txt".to_string(), 5, b"hello".to_vec(), "addr".to_string(), "wif".to_string(), 1500);
+        let owner_token = job.owner_token.clone().unwrap();
+        {
+            let state = state.read().await;
+            state.db.insert_job(&job).unwrap();
+            state.db.update_job_status("job-1", JobStatus::Processing, MessageKey::Starting).unwrap();
+            state.db.update_job_progress("job-1", 0.25, MessageKey::Starting).unwrap();
+            state.db.update_job_progress("job-1", 0.75, MessageKey::Starting).unwrap();
+            state.db.update_job_status("job-1", JobStatus::Complete, MessageKey::Complete).unwrap();
+        }
+
+        let app = serve(Router::new().route("/api/jobs/:job_id/logs", get(get_job_logs)).with_state(state)).await;
+        let body: serde_json::Value = reqwest::get(format!("{}/api/jobs/job-1/logs?owner_token={}", app, owner_token))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let events = body["events"].as_array().unwrap();
+        let tail = &events[events.len() - 4..];
+        assert_eq!(tail[0]["status"], JobStatus::Processing.as_str());
+        assert_eq!(tail[1]["progress"], 0.25);
+        assert_eq!(tail[2]["progress"], 0.75);
+        assert_eq!(tail[3]["status"], JobStatus::Complete.as_str());
+        assert_eq!(tail[3]["message_key"], MessageKey::Complete.as_str());
+
+        let forbidden = reqwest::get(format!("{}/api/jobs/job-1/logs?owner_token=wrong", app)).await.unwrap();
+        assert_eq!(forbidden.status(), reqwest::StatusCode::FORBIDDEN);
+        let missing = reqwest::get(format!("{}/api/jobs/job-2/logs?owner_token={}", app, owner_token)).await.unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
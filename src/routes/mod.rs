@@ -1,7 +1,11 @@
 pub mod admin;
 pub mod dashboard;
+pub mod debug;
 pub mod download;
+pub mod error;
 pub mod flac;
+pub mod jobs;
+pub mod page;
 pub mod status;
 pub mod upload;
 pub mod wallet;
@@ -0,0 +1,49 @@
+// Server-rendered HTML pages
+// Pages that show request data are askama templates, so every value is
+// HTML-escaped when rendered. Fully static pages stay as include_str! assets.
+
+use askama::Template;
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+
+/// Render a page template, answering 500 if rendering fails
+pub fn render<T: Template>(template: &T) -> Response {
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to render template: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to render page").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, Query};
+
+    use crate::routes::{flac, status};
+
+    const HOSTILE: &str = "<script>alert(1)</script>";
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_values_are_escaped() {
+        let player = flac::PlayerQuery { txid: Some(HOSTILE.to_string()), network: None };
+        let pages = [
+            body(flac::flac_status_page(Path(HOSTILE.to_string())).await).await,
+            body(status::status_page(Path(HOSTILE.to_string())).await).await,
+            body(flac::flac_player_page(Query(player)).await).await,
+        ];
+        for html in pages {
+            assert!(!html.contains(HOSTILE));
+            assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        }
+    }
+}
@@ -1,8 +1,10 @@
+use askama::Template;
 use axum::{
     extract::{Path, State},
-    response::{Html, Json},
+    response::{Json, Response},
 };
 use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
 use image::Luma;
 use qrcode::QrCode;
 use serde::Serialize;
@@ -10,11 +12,20 @@ use std::io::Cursor;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::models::JobStatus;
+use crate::config::Config;
+use crate::models::{Amount, ErrorCode, JobStatus, Network};
+use crate::routes::error::ApiError;
+use crate::routes::page;
 use crate::AppState;
 
-pub async fn status_page() -> Html<String> {
-    Html(include_str!("../../templates/status.html").to_string())
+#[derive(Template)]
+#[template(path = "status.html")]
+pub struct StatusTemplate {
+    pub job_id: String,
+}
+
+pub async fn status_page(Path(job_id): Path<String>) -> Response {
+    page::render(&StatusTemplate { job_id })
 }
 
 #[derive(Serialize)]
@@ -23,71 +34,53 @@ pub struct StatusUpdateResponse {
     pub job_id: String,
     pub job_type: String,
     pub status: String,
+    /// Network the payment address is on, so pages can flag test networks
+    pub network: Network,
     pub filename: Option<String>,
     pub file_size: Option<i64>,
     pub payment_address: Option<String>,
-    pub required_satoshis: Option<i64>,
+    pub required_satoshis: Option<Amount>,
     pub required_bsv: Option<String>,
     pub qr_code: Option<String>,
     pub manifest_txid: Option<String>,
     pub download_link: Option<String>,
     pub message: String,
+    /// Key and params of `message`, for localized frontends
+    pub message_key: Option<String>,
+    pub message_params: Option<serde_json::Value>,
     pub progress: f64,
-    pub error: Option<String>,
+    pub bytes_done: Option<i64>,
+    pub bytes_total: Option<i64>,
+    pub eta_seconds: Option<i64>,
+    /// When the watcher saw the payment and how much it found. Set before the
+    /// job is queued, so a paid job waiting for a slot reads as paid
+    pub payment_received_at: Option<DateTime<Utc>>,
+    pub payment_received_satoshis: Option<i64>,
+    /// Why the job failed, once its status is error
+    pub error_code: Option<ErrorCode>,
+    /// How long to wait before polling again; jittered so pages spread out
+    pub poll_after_ms: u64,
 }
 
 pub async fn status_update(
     State(state): State<Arc<RwLock<AppState>>>,
     Path(job_id): Path<String>,
-) -> Json<StatusUpdateResponse> {
+) -> Result<Json<StatusUpdateResponse>, ApiError> {
     let state = state.read().await;
 
-    let job = match state.db.get_job(&job_id) {
-        Ok(Some(j)) => j,
-        Ok(None) => {
-            return Json(StatusUpdateResponse {
-                success: false,
-                job_id: job_id.clone(),
-                job_type: "unknown".to_string(),
-                status: "error".to_string(),
-                filename: None,
-                file_size: None,
-                payment_address: None,
-                required_satoshis: None,
-                required_bsv: None,
-                qr_code: None,
-                manifest_txid: None,
-                download_link: None,
-                message: "Job not found".to_string(),
-                progress: 0.0,
-                error: Some("Job not found".to_string()),
-            });
-        }
-        Err(e) => {
-            return Json(StatusUpdateResponse {
-                success: false,
-                job_id: job_id.clone(),
-                job_type: "unknown".to_string(),
-                status: "error".to_string(),
-                filename: None,
-                file_size: None,
-                payment_address: None,
-                required_satoshis: None,
-                required_bsv: None,
-                qr_code: None,
-                manifest_txid: None,
-                download_link: None,
-                message: format!("Database error: {}", e),
-                progress: 0.0,
-                error: Some(format!("Database error: {}", e)),
-            });
-        }
-    };
+    let job = state
+        .db
+        .get_job(&job_id)
+        .map_err(ApiError::database)?
+        .ok_or_else(ApiError::job_not_found)?;
+
+    let required_satoshis = job.required_satoshis.and_then(|s| Amount::try_from(s).ok());
+    let network = job.network.unwrap_or_default();
 
     // Generate QR code if pending payment
     let qr_code = if job.status == JobStatus::PendingPayment {
-        if let (Some(address), Some(sats)) = (&job.payment_address, job.required_satoshis) {
-            generate_qr_code(address, sats as u64).ok()
+        if let (Some(address), Some(amount)) = (&job.payment_address, required_satoshis) {
+            generate_qr_code(&payment_uri(&state.config, address, amount, network)).ok()
         } else {
             None
         }
@@ -95,31 +88,74 @@ pub async fn status_update(
         None
     };
 
-    let required_bsv = job.required_satoshis.map(|s| format!("{:.8}", s as f64 / 100_000_000.0));
+    let required_bsv = required_satoshis.map(Amount::to_bsv_string);
 
-    Json(StatusUpdateResponse {
+    Ok(Json(StatusUpdateResponse {
         success: true,
         job_id: job.id,
         job_type: job.job_type.as_str().to_string(),
         status: job.status.as_str().to_string(),
+        network,
         filename: job.filename,
         file_size: job.file_size,
         payment_address: job.payment_address,
-        required_satoshis: job.required_satoshis,
+        required_satoshis,
         required_bsv,
         qr_code,
         manifest_txid: job.manifest_txid,
         download_link: job.download_link,
         message: job.message,
+        message_key: job.message_key,
+        message_params: job.message_params.and_then(|p| serde_json::from_str(&p).ok()),
         progress: job.progress,
-        error: None,
-    })
+        bytes_done: job.bytes_done,
+        bytes_total: job.bytes_total,
+        eta_seconds: job.eta_seconds,
+        payment_received_at: job.payment_received_at,
+        payment_received_satoshis: job.payment_received_satoshis,
+        error_code: job.error_code,
+        poll_after_ms: state.config.status_poll_after_ms(),
+    }))
+}
+
+/// How payment URIs for testnet and STN addresses are written. Wallets
+/// disagree, and mainnet wallets reject a test address in a bitcoin: URI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestnetUriStyle {
+    /// The bare address, without an amount
+    Address,
+    /// bitcoin-testnet:<address>?amount=...
+    Scheme,
+    /// bitcoin:<address>?sv&network=test&amount=...
+    Param,
+}
+
+impl TestnetUriStyle {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "address" => Some(TestnetUriStyle::Address),
+            "scheme" => Some(TestnetUriStyle::Scheme),
+            "param" => Some(TestnetUriStyle::Param),
+            _ => None,
+        }
+    }
 }
 
-fn generate_qr_code(address: &str, amount_satoshis: u64) -> Result<String, String> {
-    let amount_bsv = amount_satoshis as f64 / 100_000_000.0;
-    let uri = format!("bitcoin:{}?sv&amount={:.8}", address, amount_bsv);
+/// Payment URI for paying `amount` to `address`: BIP21 on mainnet, the
+/// configured testnet style on testnet and STN
+pub fn payment_uri(config: &Config, address: &str, amount: Amount, network: Network) -> String {
+    if network == Network::Mainnet {
+        return format!("bitcoin:{}?sv&amount={}", address, amount.to_bsv_string());
+    }
+    match TestnetUriStyle::from_str(&config.testnet_payment_uri).unwrap_or(TestnetUriStyle::Address) {
+        TestnetUriStyle::Address => address.to_string(),
+        TestnetUriStyle::Scheme => format!("bitcoin-testnet:{}?amount={}", address, amount.to_bsv_string()),
+        TestnetUriStyle::Param => format!("bitcoin:{}?sv&network=test&amount={}", address, amount.to_bsv_string()),
+    }
+}
 
+/// PNG data URI of a QR code for a payment URI
+pub fn generate_qr_code(uri: &str) -> Result<String, String> {
     let code = QrCode::new(uri.as_bytes()).map_err(|e| format!("QR error: {}", e))?;
 
     let image = code.render::<Luma<u8>>().min_dimensions(200, 200).build();
@@ -132,3 +168,49 @@ fn generate_qr_code(address: &str, amount_satoshis: u64) -> Result<String, Strin
 
     Ok(format!("data:image/png;base64,{}", STANDARD.encode(&buffer)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Job;
+    use crate::services::bsv::BsvService;
+    use crate::test_support::{test_config, test_state};
+
+    #[test]
+    fn testnet_uris_differ_from_mainnet_bip21() {
+        let mut config = test_config();
+        let amount = Amount::from_sat_const(12_345);
+        let (_, address) = BsvService::generate_keypair(Network::Testnet);
+        let mainnet = payment_uri(&config, &address, amount, Network::Mainnet);
+        assert_eq!(mainnet, format!("bitcoin:{}?sv&amount=0.00012345", address));
+
+        for (style, expected) in [
+            ("address", address.clone()),
+            ("scheme", format!("bitcoin-testnet:{}?amount=0.00012345", address)),
+            ("param", format!("bitcoin:{}?sv&network=test&amount=0.00012345", address)),
+            // An unknown style falls back to the bare address
+            ("other", address.clone()),
+        ] {
+            config.testnet_payment_uri = style.to_string();
+            for network in [Network::Testnet, Network::Stn] {
+                let uri = payment_uri(&config, &address, amount, network);
+                assert_eq!(uri, expected, "{} on {:?}", style, network);
+                assert_ne!(uri, mainnet);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn status_reports_the_job_network() {
+        let state = test_state();
+        let (wif, address) = BsvService::generate_keypair(Network::Testnet);
+        let job = Job::new_upload("upload".to_string(), "a.txt".to_string(), 4, b"data".to_vec(), address, wif, 1000)
+            .with_network(Network::Testnet);
+        state.read().await.db.insert_job(&job).unwrap();
+
+        let status = status_update(State(state), Path("upload".to_string())).await.unwrap();
+        let status = serde_json::to_value(status.0).unwrap();
+        assert_eq!(status["network"], "testnet");
+        assert!(status["qr_code"].as_str().unwrap().starts_with("data:image/png;base64,"));
+    }
+}
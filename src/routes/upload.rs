@@ -1,89 +1,405 @@
 use axum::{
-    extract::{Multipart, State},
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Multipart, Query, State},
+    http::{request::Parts, HeaderMap},
     response::{Html, Json},
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
-use crate::models::Job;
+use crate::models::{Amount, ErrorCode, Job, JobStatus, JobType, MessageKey, Network};
+use crate::routes::admin::AdminAuth;
+use crate::routes::error::ApiError;
+use crate::services::api_keys;
 use crate::services::bsv::BsvService;
+use crate::services::scheduler::QueuedJob;
 use crate::AppState;
 
 pub async fn upload_page() -> Html<String> {
     Html(include_str!("../../templates/upload.html").to_string())
 }
 
+#[derive(Deserialize)]
+pub struct PrepareUploadQuery {
+    /// Include a payment QR code in the response
+    pub qr: Option<bool>,
+}
+
 #[derive(Serialize)]
 pub struct PrepareUploadResponse {
     pub success: bool,
-    pub job_id: Option<String>,
-    pub redirect_url: Option<String>,
-    pub error: Option<String>,
+    pub job_id: String,
+    pub owner_token: Option<String>,
+    pub redirect_url: String,
+    /// Payment details, unset when no payment is needed
+    pub payment_address: Option<String>,
+    pub required_satoshis: Option<Amount>,
+    pub required_bsv: Option<String>,
+    pub payment_uri: Option<String>,
+    pub qr_code: Option<String>,
+    pub admin_pay: bool,
+    /// Paid from the user's own funding wallet, no payment needed
+    pub prefunded: bool,
+    /// The same file was just prepared, so that job is returned instead of a new one
+    pub reused: bool,
+}
+
+/// IP of the client: the peer address, or when the peer is one of
+/// TRUSTED_PROXIES, the last X-Forwarded-For hop that isn't. Anyone else can
+/// write what they like in X-Forwarded-For, so it is ignored from them.
+pub struct ClientIp(pub Option<String>);
+
+#[async_trait]
+impl FromRequestParts<Arc<RwLock<AppState>>> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<RwLock<AppState>>) -> Result<Self, Self::Rejection> {
+        let Some(peer) = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip()) else {
+            return Ok(ClientIp(None));
+        };
+        let trusted = &state.read().await.config.trusted_proxies;
+        let hops = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim);
+        // Each trusted proxy appends the address it was connected from
+        let mut client = peer;
+        for hop in hops.collect::<Vec<_>>().into_iter().rev() {
+            if !trusted.contains(&client) {
+                break;
+            }
+            match hop.parse::<IpAddr>() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+        Ok(ClientIp(Some(client.to_string())))
+    }
+}
+
+/// Hex SHA-256 of an uploaded file
+pub fn content_sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// A job still waiting for payment for the same file, kind and network that
+/// this client prepared within DUPLICATE_JOB_WINDOW_MINUTES, e.g. after a
+/// double-click. The client is matched by IP or by an owner token it sent;
+/// only a match by owner token gets the job's token back, as clients behind
+/// one NAT share an IP.
+pub async fn find_duplicate_job(
+    state: &Arc<RwLock<AppState>>,
+    job_type: JobType,
+    network: Network,
+    content_sha256: &str,
+    client_ip: Option<&str>,
+    owner_token: Option<&str>,
+) -> Option<Job> {
+    let state = state.read().await;
+    let window = state.config.duplicate_job_window_minutes;
+    if window <= 0 || (client_ip.is_none() && owner_token.is_none()) {
+        return None;
+    }
+    let since = chrono::Utc::now() - chrono::Duration::minutes(window);
+    state
+        .db
+        .find_pending_duplicate(job_type, network, content_sha256, client_ip, owner_token, since)
+        .unwrap_or_else(|e| {
+            tracing::warn!("Failed to look up duplicate jobs: {}", e);
+            None
+        })
+        .map(|mut job| {
+            if owner_token.is_none() || job.owner_token.as_deref() != owner_token {
+                job.owner_token = None;
+            }
+            job
+        })
+}
+
+/// Check a user-supplied funding WIF and return its address.
+/// The wallet must be on `network` and hold at least `required_satoshis`;
+/// chunked uploads also need a single UTXO that covers the whole cost,
+/// since the UTXO split spends only one.
+pub async fn verify_funding_wif(
+    state: &Arc<RwLock<AppState>>,
+    wif: &str,
+    network: Network,
+    required_satoshis: i64,
+    single_utxo: bool,
+) -> Result<String, (ErrorCode, String)> {
+    let invalid = |e: String| (ErrorCode::InvalidWif, format!("Invalid funding WIF: {}", e));
+    // STN shares testnet's WIF version byte, so a testnet WIF also funds STN
+    let wif_network = BsvService::wif_network(wif).map_err(invalid)?;
+    if wif_network.uses_testnet_versions() != network.uses_testnet_versions() {
+        return Err((
+            ErrorCode::InvalidWif,
+            format!("Funding WIF is for {}, but the upload is on {}", wif_network, network),
+        ));
+    }
+    let address = BsvService::wif_to_address(wif, network).map_err(invalid)?;
+
+    let utxos = crate::get_address_utxos(state, &address, network)
+        .await
+        .map_err(|e| (ErrorCode::UtxoFetchFailed, format!("Failed to check funding wallet balance: {}", e)))?;
+    if let Some(confirmations) = crate::unconfirmed_funding(state, &utxos, network).await {
+        let required = state.read().await.config.min_payment_confirmations;
+        return Err((
+            ErrorCode::FundingUnconfirmed,
+            format!(
+                "Funding wallet {} holds funds with {} of the {} confirmations required; try again once they confirm",
+                address, confirmations, required
+            ),
+        ));
+    }
+    let available = if single_utxo {
+        utxos.iter().map(|u| u.satoshis).max().unwrap_or(0)
+    } else {
+        utxos.iter().map(|u| u.satoshis).sum()
+    };
+
+    if available < required_satoshis {
+        return Err((
+            ErrorCode::InsufficientFunds,
+            format!(
+                "Funding wallet {} has {} satoshis available, {} required",
+                address, available, required_satoshis
+            ),
+        ));
+    }
+    Ok(address)
+}
+
+/// Hold a prefunded job's key in memory, where the job reads it while it
+/// runs, rather than in the database. A wallet already funding another
+/// upload is refused, so two jobs never spend the same UTXOs.
+pub fn hold_funding_key(state: &AppState, job_id: &str, wif: &str, address: &str) -> Result<(), ApiError> {
+    if state.funding_keys.reserve(job_id, wif, address) {
+        Ok(())
+    } else {
+        Err(ApiError::new(
+            ErrorCode::FundingWalletBusy,
+            format!("Funding wallet {} is already paying for another upload; try again once it finishes", address),
+        ))
+    }
+}
+
+/// A new payment key for a job, as (WIF, address, derivation index).
+/// With an HD seed configured the key is derived at a freshly reserved
+/// index, so only the index needs storing; otherwise it is random.
+pub async fn new_payment_keypair(
+    state: &Arc<RwLock<AppState>>,
+    network: Network,
+) -> Result<(String, String, Option<u32>), ApiError> {
+    let state = state.read().await;
+    let hd_wallet = match &state.hd_wallet {
+        Some(hd_wallet) => hd_wallet,
+        None => {
+            let (wif, address) = BsvService::generate_keypair(network);
+            return Ok((wif, address, None));
+        }
+    };
+
+    let index = state.db.next_derivation_index().map_err(ApiError::database)?;
+    let secret_key = hd_wallet
+        .payment_key(index)
+        .map_err(|e| ApiError::new(ErrorCode::InternalError, e))?;
+    let (wif, address) = BsvService::keypair_from_secret_key(&secret_key, network);
+    Ok((wif, address, Some(index)))
+}
+
+/// Refuse to create a job whose key doesn't control its payment address
+pub fn ensure_payment_key(wif: &str, address: &str, network: Network) -> Result<(), ApiError> {
+    crate::check_payment_key(Some(wif), address, network)
+        .map_err(|message| ApiError::new(ErrorCode::PaymentKeyMismatch, message.english()))
 }
 
 pub async fn prepare_upload(
     State(state): State<Arc<RwLock<AppState>>>,
+    Query(query): Query<PrepareUploadQuery>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    admin: AdminAuth,
     mut multipart: Multipart,
-) -> Json<PrepareUploadResponse> {
+) -> Result<Json<PrepareUploadResponse>, ApiError> {
     let mut filename: Option<String> = None;
     let mut file_data: Option<Vec<u8>> = None;
+    let mut funding_wif: Option<String> = None;
+    let mut network = Network::Mainnet;
+    let mut admin_pay_requested = false;
+    let mut force_new = false;
+    let mut owner_token: Option<String> = None;
+    let mut bypass_backlog = false;
+    let mut admin_key = String::new();
 
     // Parse multipart form
     while let Ok(Some(field)) = multipart.next_field().await {
         let name = field.name().unwrap_or("").to_string();
 
-        if name == "file" {
-            filename = field.file_name().map(|s: &str| s.to_string());
-            match field.bytes().await {
-                Ok(bytes) => file_data = Some(bytes.to_vec()),
-                Err(e) => {
-                    return Json(PrepareUploadResponse {
-                        success: false,
-                        job_id: None,
-                        redirect_url: None,
-                        error: Some(format!("Failed to read file: {}", e)),
-                    });
+        match name.as_str() {
+            "file" => {
+                filename = field.file_name().map(|s: &str| s.to_string());
+                let bytes = field
+                    .bytes()
+                    .await
+                    .map_err(|e| ApiError::invalid_request(format!("Failed to read file: {}", e)))?;
+                file_data = Some(bytes.to_vec());
+            }
+            "funding_wif" => {
+                if let Ok(data) = field.text().await {
+                    if !data.trim().is_empty() {
+                        funding_wif = Some(data.trim().to_string());
+                    }
+                }
+            }
+            "network" => {
+                if let Ok(data) = field.text().await {
+                    if !data.trim().is_empty() {
+                        network = Network::from_str(&data).ok_or_else(|| {
+                            ApiError::invalid_request(format!("Unknown network: {}", data.trim()))
+                        })?;
+                    }
+                }
+            }
+            "admin_pay" => {
+                if let Ok(data) = field.text().await {
+                    admin_pay_requested = data.trim().to_lowercase() == "true";
+                }
+            }
+            "force_new" => {
+                if let Ok(data) = field.text().await {
+                    force_new = data.trim().to_lowercase() == "true";
+                }
+            }
+            "owner_token" => {
+                if let Ok(data) = field.text().await {
+                    if !data.trim().is_empty() {
+                        owner_token = Some(data.trim().to_string());
+                    }
+                }
+            }
+            "bypass_backlog" => {
+                if let Ok(data) = field.text().await {
+                    bypass_backlog = data.trim().to_lowercase() == "true";
+                }
+            }
+            "admin_key" => {
+                if let Ok(data) = field.text().await {
+                    admin_key = data.trim().to_string();
                 }
             }
+            _ => {}
         }
     }
 
-    let filename = match filename {
-        Some(f) => f,
-        None => {
-            return Json(PrepareUploadResponse {
-                success: false,
-                job_id: None,
-                redirect_url: None,
-                error: Some("No file provided".to_string()),
-            });
-        }
-    };
-
-    let file_data = match file_data {
-        Some(d) => d,
-        None => {
-            return Json(PrepareUploadResponse {
-                success: false,
-                job_id: None,
-                redirect_url: None,
-                error: Some("No file data".to_string()),
-            });
-        }
-    };
+    let filename = filename.ok_or_else(|| ApiError::invalid_request("No file provided"))?;
+    let file_data = file_data.ok_or_else(|| ApiError::invalid_request("No file data"))?;
 
     let file_size = file_data.len() as i64;
 
-    // Generate new keypair for payment (mainnet for production)
-    let (wif, address) = BsvService::generate_keypair("mainnet");
+    // Attribute the upload to an API key, if one was supplied
+    let api_key_id = {
+        let state = state.read().await;
+        api_keys::authorize_upload(&state.db, &headers, file_size)?
+    };
 
     // Calculate required payment
     let required_satoshis = {
         let state = state.read().await;
-        state.bsv.calculate_upload_cost(file_data.len())
+        let required = state.bsv.calculate_upload_cost(file_data.len()).to_sat_i64();
+
+        // Reject unpayable quotes before creating the job - this also guards the
+        // admin wallet. Generic uploads are always a single transaction.
+        state
+            .config
+            .check_upload_limits(required, 1, 2)
+            .map_err(|e| ApiError::new(ErrorCode::UploadTooExpensive, e))?;
+        required
+    };
+
+    // A repeated submit of the same file gets the job it already created,
+    // unless it pays from its own wallet or asks for a new job
+    let content_hash = content_sha256(&file_data);
+    if !force_new && funding_wif.is_none() {
+        let duplicate = find_duplicate_job(
+            &state,
+            JobType::Upload,
+            network,
+            &content_hash,
+            client_ip.as_deref(),
+            owner_token.as_deref(),
+        )
+        .await;
+        if let Some(job) = duplicate {
+            tracing::info!("Reusing pending upload job {} for a repeated submit", job.id);
+            let address = job.payment_address.unwrap_or_default();
+            let payment = job.required_satoshis.and_then(|sats| Amount::try_from(sats).ok());
+            let payment_uri = {
+                let state = state.read().await;
+                payment.map(|amount| crate::routes::status::payment_uri(&state.config, &address, amount, network))
+            };
+            let qr_code = payment_uri
+                .as_deref()
+                .filter(|_| query.qr.unwrap_or(false))
+                .and_then(|uri| crate::routes::status::generate_qr_code(uri).ok());
+            return Ok(Json(PrepareUploadResponse {
+                success: true,
+                redirect_url: format!("/status/{}", job.id),
+                job_id: job.id,
+                owner_token: job.owner_token,
+                payment_address: payment.map(|_| address.clone()),
+                required_satoshis: payment,
+                required_bsv: payment.map(Amount::to_bsv_string),
+                payment_uri,
+                qr_code,
+                admin_pay: false,
+                prefunded: false,
+                reused: true,
+            }));
+        }
+    }
+
+    {
+        let state = state.read().await;
+        crate::routes::jobs::admit_new_job(&state, &admin, bypass_backlog, &admin_key)?;
+    }
+
+    // Admin pay covers the upload if it is eligible; an ineligible request
+    // falls back to a normal payment address
+    let prefunded = funding_wif.is_some();
+    let admin_wif = if admin_pay_requested && !prefunded {
+        let eligibility =
+            crate::routes::admin::admin_pay_eligibility(&state, network, Some(required_satoshis)).await;
+        if eligibility.eligible {
+            let state = state.read().await;
+            crate::routes::admin::get_admin_wif_for_network(&state.db, network)
+        } else {
+            None
+        }
+    } else {
+        None
     };
+    let use_admin_pay = admin_wif.is_some();
+
+    // Use the user's funding wallet, the admin wallet, or a new payment keypair
+    let (wif, address, derivation_index) = if let Some(wif) = funding_wif {
+        let address = verify_funding_wif(&state, &wif, network, required_satoshis, false).await?;
+        (wif, address, None)
+    } else if let Some(wif) = admin_wif {
+        let address = BsvService::wif_to_address(&wif, network)
+            .map_err(|e| ApiError::new(ErrorCode::InvalidWif, format!("Invalid admin WIF: {}", e)))?;
+        (wif, address, None)
+    } else {
+        new_payment_keypair(&state, network).await?
+    };
+    ensure_payment_key(&wif, &address, network)?;
 
     // Create job
     let job_id = Uuid::new_v4().to_string().replace("-", "");
@@ -92,28 +408,344 @@ pub async fn prepare_upload(
         filename,
         file_size,
         file_data,
-        address,
-        wif,
+        address.clone(),
+        wif.clone(),
         required_satoshis,
-    );
+    )
+    .with_network(network)
+    .with_derivation_index(derivation_index)
+    .with_submission(content_hash, client_ip);
+    let job = if prefunded { job.without_payment_wif() } else { job };
+
+    // Admin-paid and prefunded uploads need no payment wait
+    let job = if use_admin_pay {
+        job.with_status(JobStatus::Processing, MessageKey::AdminPayStarting)
+    } else if prefunded {
+        job.with_status(JobStatus::Processing, MessageKey::FundingVerified)
+    } else {
+        job
+    };
 
     // Save job to database
     {
         let state = state.read().await;
+        if prefunded {
+            hold_funding_key(&state, &job_id, &wif, &address)?;
+        }
         if let Err(e) = state.db.insert_job(&job) {
-            return Json(PrepareUploadResponse {
-                success: false,
-                job_id: None,
-                redirect_url: None,
-                error: Some(format!("Failed to create job: {}", e)),
+            state.funding_keys.release(&job_id);
+            return Err(ApiError::new(ErrorCode::DatabaseError, format!("Failed to create job: {}", e)));
+        }
+        if let Some(api_key_id) = &api_key_id {
+            if let Err(e) = state.db.set_job_api_key(&job_id, api_key_id) {
+                tracing::error!("Failed to attribute job {} to API key {}: {}", job_id, api_key_id, e);
+            }
+        }
+
+        if use_admin_pay || prefunded {
+            crate::enqueue_job(&state, QueuedJob {
+                job_id: job_id.clone(),
+                job_type: JobType::Upload,
+                address: address.clone(),
+                network,
+                admin_pay: use_admin_pay,
+                file_size,
+                paid_satoshis: None,
             });
         }
     }
 
-    Json(PrepareUploadResponse {
+    // Same payment details the status endpoint reports while payment is pending
+    let payment = Amount::try_from(required_satoshis)
+        .ok()
+        .filter(|_| job.status == JobStatus::PendingPayment);
+    let payment_uri = {
+        let state = state.read().await;
+        payment.map(|amount| crate::routes::status::payment_uri(&state.config, &address, amount, network))
+    };
+    let qr_code = payment_uri
+        .as_deref()
+        .filter(|_| query.qr.unwrap_or(false))
+        .and_then(|uri| crate::routes::status::generate_qr_code(uri).ok());
+
+    Ok(Json(PrepareUploadResponse {
         success: true,
-        job_id: Some(job_id.clone()),
-        redirect_url: Some(format!("/status/{}", job_id)),
-        error: None,
-    })
+        job_id: job_id.clone(),
+        owner_token: job.owner_token,
+        redirect_url: format!("/status/{}", job_id),
+        payment_address: payment.map(|_| address.clone()),
+        required_satoshis: payment,
+        required_bsv: payment.map(Amount::to_bsv_string),
+        payment_uri,
+        qr_code,
+        admin_pay: use_admin_pay,
+        prefunded,
+        reused: false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{multipart_body, serve, test_config, test_state_with};
+    use axum::{routing::{get, post}, Router};
+
+    /// A Bitails stand-in that reports one confirmed 0.1 BSV UTXO for every address
+    async fn funded_bitails() -> String {
+        bitails_with_confirmations(6).await
+    }
+
+    /// A Bitails stand-in that reports one 0.1 BSV UTXO with `confirmations` for every address
+    async fn bitails_with_confirmations(confirmations: i64) -> String {
+        serve(Router::new().route(
+            "/address/:address/unspent",
+            get(move |axum::extract::Path(address): axum::extract::Path<String>| async move {
+                Json(serde_json::json!({
+                    "address": address,
+                    "unspent": [{ "txid": "22".repeat(32), "vout": 0, "satoshis": 10_000_000, "confirmations": confirmations }],
+                }))
+            }),
+        ))
+        .await
+    }
+
+    async fn prepare(state: &Arc<RwLock<AppState>>, funding_wif: &str) -> (reqwest::StatusCode, serde_json::Value) {
+        prepare_with_query(state, funding_wif, "").await
+    }
+
+    async fn prepare_with_query(
+        state: &Arc<RwLock<AppState>>,
+        funding_wif: &str,
+        query: &str,
+    ) -> (reqwest::StatusCode, serde_json::Value) {
+        let mut fields: Vec<(&str, Option<&str>, &[u8])> = vec![("file", Some("hello.txt"), b"hello world")];
+        if !funding_wif.is_empty() {
+            fields.push(("funding_wif", None, funding_wif.as_bytes()));
+        }
+        let (content_type, body) = multipart_body(&fields);
+        let app = serve(Router::new().route("/prepare_upload", post(prepare_upload)).with_state(state.clone())).await;
+        let response = reqwest::Client::new()
+            .post(format!("{}/prepare_upload{}", app, query))
+            .header("content-type", content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.bytes().await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn funded_wif_skips_pending_payment() {
+        let mut config = test_config();
+        config.bitails_api_url = funded_bitails().await;
+        config.min_payment_confirmations = 1;
+        let state = test_state_with(config);
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+
+        let (status, body) = prepare(&state, &wif).await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+        assert_eq!(body["prefunded"], true);
+        assert!(body["payment_address"].is_null());
+
+        let state = state.read().await;
+        let job = state.db.get_job(body["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Processing);
+        assert_eq!(job.payment_address.as_deref(), Some(address.as_str()));
+        // The user's key is held for the job but never stored
+        assert_eq!(job.payment_wif, None);
+        assert_eq!(crate::job_payment_wif(&state, &job), Some(wif));
+    }
+
+    #[tokio::test]
+    async fn wallet_funds_one_upload_at_a_time() {
+        let mut config = test_config();
+        config.bitails_api_url = funded_bitails().await;
+        let state = test_state_with(config);
+        let (wif, _) = BsvService::generate_keypair(Network::Mainnet);
+
+        let (status, first) = prepare(&state, &wif).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        let (status, body) = prepare(&state, &wif).await;
+        assert_eq!(status, reqwest::StatusCode::CONFLICT);
+        assert_eq!(body["error"]["code"], "FUNDING_WALLET_BUSY");
+
+        // Once the first job lets go of the wallet it can fund another
+        state.read().await.funding_keys.release(first["job_id"].as_str().unwrap());
+        let (status, _) = prepare(&state, &wif).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn quotes_over_the_cap_are_refused() {
+        let mut config = test_config();
+        config.max_upload_cost_satoshis = BsvService::for_tests().calculate_upload_cost(b"hello world".len()).to_sat_i64();
+        let state = test_state_with(config);
+        let (status, body) = prepare(&state, "").await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+
+        state.write().await.config.max_upload_cost_satoshis -= 1;
+        let (status, body) = prepare(&state, "").await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "UPLOAD_TOO_EXPENSIVE");
+        // Only the job quoted within the cap was created
+        assert_eq!(state.read().await.db.get_all_jobs(None).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn prepare_reports_the_payment_the_status_asks_for() {
+        let state = test_state_with(test_config());
+        let (status, prepared) = prepare_with_query(&state, "", "?qr=true").await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", prepared);
+        let job_id = prepared["job_id"].as_str().unwrap();
+        assert_eq!(prepared["redirect_url"], format!("/status/{}", job_id));
+
+        let app = serve(
+            Router::new()
+                .route("/api/status/:job_id", get(crate::routes::status::status_update))
+                .with_state(state.clone()),
+        )
+        .await;
+        let status: serde_json::Value = reqwest::get(format!("{}/api/status/{}", app, job_id)).await.unwrap().json().await.unwrap();
+        assert_eq!(status["status"], "pending_payment");
+        for field in ["payment_address", "required_satoshis", "required_bsv", "qr_code"] {
+            assert!(!prepared[field].is_null(), "{}", field);
+            assert_eq!(prepared[field], status[field], "{}", field);
+        }
+        let address = prepared["payment_address"].as_str().unwrap();
+        let uri = format!("bitcoin:{}?sv&amount={}", address, prepared["required_bsv"].as_str().unwrap());
+        assert_eq!(prepared["payment_uri"], uri);
+
+        // The QR code is opt-in
+        let (_, prepared) = prepare(&state, "").await;
+        assert!(prepared["qr_code"].is_null());
+        assert!(!prepared["payment_uri"].is_null());
+    }
+
+    #[tokio::test]
+    async fn double_submit_reuses_the_pending_job() {
+        let mut config = test_config();
+        config.trusted_proxies = vec!["127.0.0.1".parse().unwrap(), "10.0.0.1".parse().unwrap()];
+        let state = test_state_with(config);
+        let app = serve(Router::new().route("/prepare_upload", post(prepare_upload)).with_state(state.clone())).await;
+        let submit = |ip: &'static str, force_new: bool, owner_token: Option<String>| {
+            let mut fields: Vec<(&str, Option<&str>, &[u8])> = vec![("file", Some("hello.txt"), b"hello world")];
+            if force_new {
+                fields.push(("force_new", None, b"true"));
+            }
+            if let Some(token) = &owner_token {
+                fields.push(("owner_token", None, token.as_bytes()));
+            }
+            let (content_type, body) = multipart_body(&fields);
+            let request = reqwest::Client::new()
+                .post(format!("{}/prepare_upload", app))
+                .header("content-type", content_type)
+                .header("x-forwarded-for", ip)
+                .body(body);
+            async move { request.send().await.unwrap().json::<serde_json::Value>().await.unwrap() }
+        };
+
+        let first = submit("203.0.113.5", false, None).await;
+        let second = submit("203.0.113.5, 10.0.0.1", false, None).await;
+        assert_eq!(first["reused"], false, "{}", first);
+        assert_eq!(second["reused"], true, "{}", second);
+        assert_eq!(second["job_id"], first["job_id"]);
+        assert_eq!(second["payment_address"], first["payment_address"]);
+        assert_eq!(second["required_satoshis"], first["required_satoshis"]);
+        assert_eq!(state.read().await.db.get_all_jobs(None).unwrap().len(), 1);
+        // The same IP alone could be anyone behind the same NAT, so it isn't handed the token
+        let token = first["owner_token"].as_str().unwrap().to_string();
+        assert!(second["owner_token"].is_null(), "{}", second);
+        let with_token = submit("198.51.100.7", false, Some(token.clone())).await;
+        assert_eq!(with_token["reused"], true, "{}", with_token);
+        assert_eq!(with_token["job_id"], first["job_id"]);
+        assert_eq!(with_token["owner_token"], token.as_str());
+
+        // Another client, or one asking for a new job, gets its own
+        let other = submit("198.51.100.7", false, None).await;
+        let forced = submit("203.0.113.5", true, None).await;
+        assert_eq!(other["reused"], false, "{}", other);
+        assert_eq!(forced["reused"], false, "{}", forced);
+        assert_ne!(other["job_id"], first["job_id"]);
+        assert_ne!(forced["job_id"], first["job_id"]);
+        assert_eq!(state.read().await.db.get_all_jobs(None).unwrap().len(), 3);
+
+        // X-Forwarded-For from a peer that isn't a trusted proxy is ignored
+        state.write().await.config.trusted_proxies.clear();
+        let spoofed = submit("198.51.100.7", false, None).await;
+        assert_eq!(spoofed["reused"], false, "{}", spoofed);
+        let job = state.read().await.db.get_job(spoofed["job_id"].as_str().unwrap()).unwrap().unwrap();
+        assert_eq!(job.client_ip.as_deref(), Some("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_funding_wallet_is_held_until_deep_enough() {
+        let mut config = test_config();
+        config.bitails_api_url = bitails_with_confirmations(0).await;
+        config.min_payment_confirmations = 1;
+        let state = test_state_with(config);
+        let (wif, _) = BsvService::generate_keypair(Network::Mainnet);
+
+        let (status, body) = prepare(&state, &wif).await;
+        assert_eq!(status, reqwest::StatusCode::CONFLICT);
+        assert_eq!(body["error"]["code"], "FUNDING_UNCONFIRMED");
+        assert!(state.read().await.db.get_all_jobs(None).unwrap().is_empty());
+
+        // The same 0-conf wallet is spent at once when no confirmations are asked for
+        state.write().await.config.min_payment_confirmations = 0;
+        let (status, body) = prepare(&state, &wif).await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+        assert_eq!(body["prefunded"], true);
+    }
+
+    #[tokio::test]
+    async fn backlog_refuses_new_jobs_until_it_drains() {
+        let mut config = test_config();
+        config.max_pending_jobs = Some(2);
+        config.admin_key_in_body = true;
+        let state = test_state_with(config);
+        let app = serve(Router::new().route("/prepare_upload", post(prepare_upload)).with_state(state.clone())).await;
+        let submit = |extra: Vec<(&'static str, String)>| {
+            let mut fields: Vec<(&str, Option<&str>, &[u8])> =
+                vec![("file", Some("hello.txt"), b"hello world"), ("force_new", None, b"true")];
+            fields.extend(extra.iter().map(|(name, value)| (*name, None, value.as_bytes())));
+            let (content_type, body) = multipart_body(&fields);
+            let request = reqwest::Client::new()
+                .post(format!("{}/prepare_upload", app))
+                .header("content-type", content_type)
+                .body(body);
+            async move {
+                let response = request.send().await.unwrap();
+                let retry_after = response.headers().get("retry-after").map(|v| v.to_str().unwrap().to_string());
+                (response.status(), retry_after, response.json::<serde_json::Value>().await.unwrap())
+            }
+        };
+
+        for _ in 0..2 {
+            let (status, _, body) = submit(vec![]).await;
+            assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+        }
+        let (status, retry_after, body) = submit(vec![]).await;
+        assert_eq!(status, reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(retry_after.as_deref(), Some("30"));
+        assert_eq!(body["error"]["code"], "SERVER_BUSY");
+
+        // An admin can still create one, but only with the right key
+        let bypass = |key: String| vec![("bypass_backlog", "true".to_string()), ("admin_key", key)];
+        let (status, _, _) = submit(bypass("wrong".to_string())).await;
+        assert_eq!(status, reqwest::StatusCode::UNAUTHORIZED);
+        let (status, _, body) = submit(bypass(crate::routes::admin::get_admin_key())).await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+
+        // New jobs are admitted again once the pending ones finish
+        {
+            let state = state.read().await;
+            for job in state.db.get_all_jobs(None).unwrap() {
+                state.db.update_job_status(&job.id, JobStatus::Complete, MessageKey::Complete).unwrap();
+            }
+        }
+        let (status, _, body) = submit(vec![]).await;
+        assert_eq!(status, reqwest::StatusCode::OK, "{}", body);
+    }
 }
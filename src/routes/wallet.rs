@@ -1,67 +1,89 @@
 use axum::{
     extract::State,
     response::Json,
-    http::StatusCode,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use uuid::Uuid;
 
+use crate::db::Database;
+use crate::models::{Amount, ErrorCode, Job, Network};
+use crate::routes::error::ApiError;
 use crate::AppState;
 use crate::services::bsv::BsvService;
 
 #[derive(Deserialize)]
 pub struct GenerateWalletRequest {
-    pub network: Option<String>, // "mainnet" or "testnet"
+    pub network: Option<Network>,
 }
 
 #[derive(Serialize)]
 pub struct WalletResponse {
     pub success: bool,
-    pub wif: Option<String>,
-    pub address: Option<String>,
-    pub error: Option<String>,
+    pub wif: String,
+    pub address: String,
+    /// Whether the address uses the compressed public key
+    pub compressed: bool,
 }
 
 #[derive(Deserialize)]
 pub struct ImportWifRequest {
     pub wif: String,
-    pub network: Option<String>,
+    pub network: Option<Network>,
 }
 
 #[derive(Deserialize)]
-pub struct ImportMnemonicRequest {
-    pub mnemonic: String,
-    pub network: Option<String>,
+pub struct ExportWalletRequest {
+    pub wif: String,
+    /// Defaults to the network of the WIF
+    pub network: Option<Network>,
+}
+
+/// Portable backup of a single-key wallet. Keys here are generated or
+/// imported directly, never derived from a mnemonic, so there is no
+/// derivation path to record.
+#[derive(Serialize)]
+pub struct WalletBackup {
+    pub version: u32,
+    pub network: Network,
+    pub address: String,
+    pub wif: String,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct ExportWalletResponse {
+    pub success: bool,
+    pub backup: WalletBackup,
 }
 
 #[derive(Deserialize)]
 pub struct BalanceRequest {
     pub address: String,
-    pub network: Option<String>,
+    pub network: Option<Network>,
 }
 
 #[derive(Serialize)]
 pub struct BalanceResponse {
     pub success: bool,
-    pub balance: Option<i64>,
-    pub balance_bsv: Option<String>,
-    pub error: Option<String>,
+    pub balance: Amount,
+    pub balance_bsv: String,
 }
 
 #[derive(Deserialize)]
 pub struct SendRequest {
     pub wif: String,
     pub to_address: String,
-    pub amount_satoshis: i64,
-    pub network: Option<String>,
+    /// Rejected at deserialization if negative or above the coin supply
+    pub amount_satoshis: Amount,
+    pub network: Option<Network>,
 }
 
 #[derive(Serialize)]
 pub struct SendResponse {
     pub success: bool,
-    pub txid: Option<String>,
-    pub error: Option<String>,
+    pub txid: String,
 }
 
 /// Generate a new wallet
@@ -69,18 +91,18 @@ pub async fn generate_wallet(
     State(_state): State<Arc<RwLock<AppState>>>,
     Json(req): Json<GenerateWalletRequest>,
 ) -> Json<WalletResponse> {
-    let network = req.network.unwrap_or_else(|| "mainnet".to_string());
+    let network = req.network.unwrap_or_default();
     
     // Generate keypair with correct network format
     // Mainnet: address starts with "1", WIF starts with "5", "K", or "L"
     // Testnet: address starts with "m" or "n", WIF starts with "c"
-    let (wif, address) = BsvService::generate_keypair(&network);
+    let (wif, address) = BsvService::generate_keypair(network);
     
     Json(WalletResponse {
         success: true,
-        wif: Some(wif),
-        address: Some(address),
-        error: None,
+        wif,
+        address,
+        compressed: true,
     })
 }
 
@@ -88,81 +110,82 @@ pub async fn generate_wallet(
 pub async fn import_wif(
     State(_state): State<Arc<RwLock<AppState>>>,
     Json(req): Json<ImportWifRequest>,
-) -> Json<WalletResponse> {
-    let network = req.network.unwrap_or_else(|| "mainnet".to_string());
+) -> Result<Json<WalletResponse>, ApiError> {
+    let network = req.network.unwrap_or_default();
     
-    match BsvService::wif_to_address(&req.wif, &network) {
-        Ok(address) => Json(WalletResponse {
-            success: true,
-            wif: Some(req.wif),
-            address: Some(address),
-            error: None,
-        }),
-        Err(e) => Json(WalletResponse {
-            success: false,
-            wif: None,
-            address: None,
-            error: Some(format!("Invalid WIF: {}", e)),
-        }),
+    let invalid = |e: String| ApiError::new(ErrorCode::InvalidWif, format!("Invalid WIF: {}", e));
+    let (_, compressed) = BsvService::decode_wif(&req.wif).map_err(invalid)?;
+    let address = BsvService::wif_to_address(&req.wif, network).map_err(invalid)?;
+    Ok(Json(WalletResponse {
+        success: true,
+        wif: req.wif,
+        address,
+        compressed,
+    }))
+}
+
+/// Export a wallet as a backup JSON the user can store and import later
+pub async fn export_wallet(
+    State(_state): State<Arc<RwLock<AppState>>>,
+    Json(req): Json<ExportWalletRequest>,
+) -> Result<Json<ExportWalletResponse>, ApiError> {
+    let wif = req.wif.trim().to_string();
+    let invalid = |e: String| ApiError::new(ErrorCode::InvalidWif, format!("Invalid WIF: {}", e));
+
+    let wif_network = BsvService::wif_network(&wif).map_err(invalid)?;
+    let network = req.network.unwrap_or(wif_network);
+    if wif_network.uses_testnet_versions() != network.uses_testnet_versions() {
+        return Err(ApiError::new(
+            ErrorCode::InvalidWif,
+            format!("WIF is for {}, not {}", wif_network, network),
+        ));
     }
+    let address = BsvService::wif_to_address(&wif, network).map_err(invalid)?;
+
+    Ok(Json(ExportWalletResponse {
+        success: true,
+        backup: WalletBackup {
+            version: 1,
+            network,
+            address,
+            wif,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        },
+    }))
 }
 
 /// Get balance for an address
 pub async fn get_balance(
     State(state): State<Arc<RwLock<AppState>>>,
     Json(req): Json<BalanceRequest>,
-) -> Json<BalanceResponse> {
-    let network = req.network.unwrap_or_else(|| "mainnet".to_string());
+) -> Result<Json<BalanceResponse>, ApiError> {
+    let network = req.network.unwrap_or_default();
+    let fetch_failed = |e: String| ApiError::new(ErrorCode::UtxoFetchFailed, format!("Failed to get balance: {}", e));
     
-    // Use WhatsOnChain API for testnet, Bitails for mainnet
-    if network == "testnet" {
-        match get_testnet_balance(&req.address).await {
-            Ok((balance, balance_bsv)) => {
-                Json(BalanceResponse {
-                    success: true,
-                    balance: Some(balance),
-                    balance_bsv: Some(balance_bsv),
-                    error: None,
-                })
-            }
-            Err(e) => Json(BalanceResponse {
-                success: false,
-                balance: None,
-                balance_bsv: None,
-                error: Some(format!("Failed to get balance: {}", e)),
-            }),
-        }
+    // Use WhatsOnChain API for testnet and STN, Bitails for mainnet
+    let balance = if crate::services::whatsonchain::serves(network) {
+        get_whatsonchain_balance(&req.address, network).await.map_err(fetch_failed)?
     } else {
         let state = state.read().await;
         
         // Get UTXOs for the address
-        match state.bitails.get_address_unspent(&req.address).await {
-            Ok(utxos) => {
-                let balance: i64 = utxos.iter().map(|u| u.satoshis).sum();
-                let balance_bsv = format!("{:.8}", balance as f64 / 100_000_000.0);
-                
-                Json(BalanceResponse {
-                    success: true,
-                    balance: Some(balance),
-                    balance_bsv: Some(balance_bsv),
-                    error: None,
-                })
-            }
-            Err(e) => Json(BalanceResponse {
-                success: false,
-                balance: None,
-                balance_bsv: None,
-                error: Some(format!("Failed to get balance: {}", e)),
-            }),
-        }
-    }
+        let utxos = state.bitails.get_address_unspent(&req.address).await.map_err(fetch_failed)?;
+        Amount::sum_sat(utxos.iter().map(|u| u.satoshis)).map_err(|e| fetch_failed(e.to_string()))?
+    };
+
+    Ok(Json(BalanceResponse {
+        success: true,
+        balance,
+        balance_bsv: balance.to_bsv_string(),
+    }))
 }
 
-/// Get testnet balance using WhatsOnChain API
-async fn get_testnet_balance(address: &str) -> Result<(i64, String), String> {
-    let client = reqwest::Client::new();
-    let url = format!("https://api.whatsonchain.com/v1/bsv/test/address/{}/balance", address);
+/// Get testnet or STN balance using WhatsOnChain API
+async fn get_whatsonchain_balance(address: &str, network: Network) -> Result<Amount, String> {
+    let client = crate::services::http::client();
+    let url = format!("{}/address/{}/balance", crate::services::whatsonchain::base_url(network), address);
     
+    crate::services::rate_limit::whatsonchain().acquire().await;
     let response = client
         .get(&url)
         .send()
@@ -180,96 +203,57 @@ async fn get_testnet_balance(address: &str) -> Result<(i64, String), String> {
     
     let confirmed = json.get("confirmed").and_then(|v| v.as_i64()).unwrap_or(0);
     let unconfirmed = json.get("unconfirmed").and_then(|v| v.as_i64()).unwrap_or(0);
-    let balance = confirmed + unconfirmed;
-    let balance_bsv = format!("{:.8}", balance as f64 / 100_000_000.0);
-    
-    Ok((balance, balance_bsv))
+    // Unconfirmed spends are negative, so only the sum has to be non-negative
+    Ok(Amount::try_from(confirmed + unconfirmed)?)
 }
 
 /// Send BSV to an address
 pub async fn send_bsv(
     State(state): State<Arc<RwLock<AppState>>>,
     Json(req): Json<SendRequest>,
-) -> Json<SendResponse> {
-    let network = req.network.unwrap_or_else(|| "mainnet".to_string());
+) -> Result<Json<SendResponse>, ApiError> {
+    let network = req.network.unwrap_or_default();
     
     // Validate WIF and get sender address
-    let sender_address = match BsvService::wif_to_address(&req.wif, &network) {
-        Ok(addr) => addr,
-        Err(e) => {
-            return Json(SendResponse {
-                success: false,
-                txid: None,
-                error: Some(format!("Invalid WIF: {}", e)),
-            });
-        }
-    };
+    let sender_address = BsvService::wif_to_address(&req.wif, network)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidWif, format!("Invalid WIF: {}", e)))?;
     
     let state_guard = state.read().await;
+    let utxo_fetch_failed = |e: String| ApiError::new(ErrorCode::UtxoFetchFailed, format!("Failed to get UTXOs: {}", e));
     
     // Get UTXOs based on network
-    let utxos = if network == "testnet" {
-        match get_testnet_utxos(&sender_address).await {
-            Ok(u) => u,
-            Err(e) => {
-                return Json(SendResponse {
-                    success: false,
-                    txid: None,
-                    error: Some(format!("Failed to get UTXOs: {}", e)),
-                });
-            }
-        }
+    let utxos = if crate::services::whatsonchain::serves(network) {
+        get_whatsonchain_utxos(&sender_address, network).await.map_err(utxo_fetch_failed)?
     } else {
-        match state_guard.bitails.get_address_unspent(&sender_address).await {
-            Ok(u) => u.iter().map(|utxo| TestnetUtxo {
+        state_guard
+            .bitails
+            .get_address_unspent(&sender_address)
+            .await
+            .map_err(utxo_fetch_failed)?
+            .iter()
+            .map(|utxo| TestnetUtxo {
                 txid: utxo.txid.clone(),
                 vout: utxo.vout,
                 satoshis: utxo.satoshis,
-            }).collect(),
-            Err(e) => {
-                return Json(SendResponse {
-                    success: false,
-                    txid: None,
-                    error: Some(format!("Failed to get UTXOs: {}", e)),
-                });
-            }
-        }
+            })
+            .collect()
     };
     
     if utxos.is_empty() {
-        return Json(SendResponse {
-            success: false,
-            txid: None,
-            error: Some("No UTXOs available".to_string()),
-        });
+        return Err(ApiError::new(ErrorCode::NoUtxos, "No UTXOs available"));
     }
     
     // Calculate total input
-    let total_input: i64 = utxos.iter().map(|u| u.satoshis).sum();
+    let total_input = Amount::sum_sat(utxos.iter().map(|u| u.satoshis))
+        .map_err(|e| utxo_fetch_failed(e.to_string()))?;
     
     // Get scriptPubKey for sender address
-    let sender_script = match BsvService::create_p2pkh_script(&sender_address) {
-        Ok(s) => s,
-        Err(e) => {
-            return Json(SendResponse {
-                success: false,
-                txid: None,
-                error: Some(format!("Failed to create sender script: {}", e)),
-            });
-        }
-    };
+    let sender_script = BsvService::create_p2pkh_script(&sender_address)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidAddress, format!("Failed to create sender script: {}", e)))?;
     
     // Get scriptPubKey for recipient address
-    let recipient_script = match BsvService::create_p2pkh_script(&req.to_address) {
-        Ok(s) => s,
-        Err(e) => {
-            return Json(SendResponse {
-                success: false,
-                txid: None,
-                error: Some(format!("Invalid recipient address: {}", e)),
-            });
-        }
-    };
+    let recipient_script = BsvService::create_p2pkh_script(&req.to_address)
+        .map_err(|e| ApiError::new(ErrorCode::InvalidAddress, format!("Invalid recipient address: {}", e)))?;
     
     // Prepare UTXOs for transaction
     let utxo_inputs: Vec<(String, u32, i64, Vec<u8>)> = utxos
@@ -278,74 +262,85 @@ pub async fn send_bsv(
         .collect();
     
     // Calculate fee (estimate ~250 bytes for a simple tx)
-    let fee = (250.0 * state_guard.bsv.fee_rate).ceil() as i64;
-    
-    // Check if we have enough funds
-    if total_input < req.amount_satoshis + fee {
-        return Json(SendResponse {
-            success: false,
-            txid: None,
-            error: Some(format!(
+    let fee = state_guard.bsv.fee_for_size(250);
+    
+    // Check if we have enough funds, and calculate change
+    let required = req.amount_satoshis.saturating_add(fee);
+    let change = total_input.checked_sub(required).map_err(|_| {
+        ApiError::new(
+            ErrorCode::InsufficientFunds,
+            format!(
                 "Insufficient funds: have {} sats, need {} sats (including {} fee)",
-                total_input,
-                req.amount_satoshis + fee,
-                fee
-            )),
-        });
-    }
-    
-    // Calculate change
-    let change = total_input - req.amount_satoshis - fee;
+                total_input, required, fee
+            ),
+        )
+    })?;
     
     // Create outputs
-    let mut outputs: Vec<(Vec<u8>, i64)> = vec![
+    let mut outputs: Vec<(Vec<u8>, Amount)> = vec![
         (recipient_script, req.amount_satoshis),
     ];
     
     // Add change output if significant (> dust limit)
-    if change > 546 {
+    if change > state_guard.bsv.dust_limit {
         outputs.push((sender_script.clone(), change));
     }
     
     // Create transaction
-    let raw_tx = match state_guard.bsv.create_transaction(&req.wif, &utxo_inputs, &outputs) {
-        Ok(tx) => tx,
-        Err(e) => {
-            return Json(SendResponse {
-                success: false,
-                txid: None,
-                error: Some(format!("Failed to create transaction: {}", e)),
-            });
-        }
-    };
+    let raw_tx = state_guard
+        .bsv
+        .create_transaction(&req.wif, &utxo_inputs, &outputs)
+        .map_err(|e| ApiError::new(ErrorCode::TxBuildFailed, format!("Failed to create transaction: {}", e)))?;
     
     // Broadcast transaction based on network
-    if network == "testnet" {
-        match broadcast_testnet_transaction(&raw_tx).await {
-            Ok(txid) => Json(SendResponse {
-                success: true,
-                txid: Some(txid),
-                error: None,
-            }),
-            Err(e) => Json(SendResponse {
-                success: false,
-                txid: None,
-                error: Some(format!("Failed to broadcast: {}", e)),
-            }),
-        }
+    let broadcast_result = if crate::services::whatsonchain::serves(network) {
+        broadcast_whatsonchain_transaction(&raw_tx, network).await
     } else {
-        match state_guard.bitails.broadcast_transaction(&raw_tx).await {
-            Ok(txid) => Json(SendResponse {
-                success: true,
-                txid: Some(txid),
-                error: None,
-            }),
-            Err(e) => Json(SendResponse {
-                success: false,
-                txid: None,
-                error: Some(format!("Failed to broadcast: {}", e)),
-            }),
-        }
+        crate::models::broadcast_outcome(state_guard.bitails.broadcast_transaction(&raw_tx).await)
+            .map_err(|e| e.to_string())
+    };
+    let txid = broadcast_result
+        .map_err(|e| ApiError::new(ErrorCode::BroadcastFailed, format!("Failed to broadcast: {}", e)))?;
+
+    // Change below the dust limit is left to the miner
+    let paid_fee = if change > state_guard.bsv.dust_limit { fee } else { fee.saturating_add(change) };
+    record_send(
+        &state_guard.db,
+        &sender_address,
+        &req.to_address,
+        req.amount_satoshis,
+        paid_fee,
+        &txid,
+        network,
+    );
+    Ok(Json(SendResponse {
+        success: true,
+        txid,
+    }))
+}
+
+/// Keep a completed Send job for a broadcast transaction so it shows up in the job history
+pub fn record_send(
+    db: &Database,
+    from_address: &str,
+    to_address: &str,
+    amount: Amount,
+    fee: Amount,
+    txid: &str,
+    network: Network,
+) {
+    let job = Job::new_send(
+        Uuid::new_v4().to_string().replace("-", ""),
+        from_address.to_string(),
+        to_address.to_string(),
+        amount.to_sat_i64(),
+        fee.to_sat_i64(),
+        txid.to_string(),
+        network,
+    );
+    // The coins have already moved, so a failed insert only loses history
+    if let Err(e) = db.insert_job(&job) {
+        tracing::warn!("Failed to record send {}: {}", txid, e);
     }
 }
 
@@ -356,11 +351,12 @@ struct TestnetUtxo {
     satoshis: i64,
 }
 
-/// Get testnet UTXOs using WhatsOnChain API
-async fn get_testnet_utxos(address: &str) -> Result<Vec<TestnetUtxo>, String> {
-    let client = reqwest::Client::new();
-    let url = format!("https://api.whatsonchain.com/v1/bsv/test/address/{}/unspent", address);
+/// Get testnet or STN UTXOs using WhatsOnChain API
+async fn get_whatsonchain_utxos(address: &str, network: Network) -> Result<Vec<TestnetUtxo>, String> {
+    let client = crate::services::http::client();
+    let url = format!("{}/address/{}/unspent", crate::services::whatsonchain::base_url(network), address);
     
+    crate::services::rate_limit::whatsonchain().acquire().await;
     let response = client
         .get(&url)
         .send()
@@ -389,11 +385,12 @@ async fn get_testnet_utxos(address: &str) -> Result<Vec<TestnetUtxo>, String> {
     Ok(utxos)
 }
 
-/// Broadcast transaction to testnet using WhatsOnChain API
-async fn broadcast_testnet_transaction(raw_tx: &str) -> Result<String, String> {
-    let client = reqwest::Client::new();
-    let url = "https://api.whatsonchain.com/v1/bsv/test/tx/raw";
+/// Broadcast transaction to testnet or STN using WhatsOnChain API
+async fn broadcast_whatsonchain_transaction(raw_tx: &str, network: Network) -> Result<String, String> {
+    let client = crate::services::http::client();
+    let url = format!("{}/tx/raw", crate::services::whatsonchain::base_url(network));
     
+    crate::services::rate_limit::whatsonchain().acquire().await;
     let response = client
         .post(url)
         .header("Content-Type", "application/json")
@@ -415,3 +412,64 @@ async fn broadcast_testnet_transaction(raw_tx: &str) -> Result<String, String> {
     // Remove quotes if present
     Ok(txid.trim_matches('"').to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::job::{JobStatus, JobType};
+    use crate::test_support::{accept, bitails, test_config, test_state, test_state_with};
+
+    async fn export_and_import(wif: String) -> (ExportWalletResponse, WalletResponse) {
+        let state = test_state();
+        let Json(exported) = export_wallet(State(state.clone()), Json(ExportWalletRequest { wif, network: None }))
+            .await
+            .unwrap();
+        let import = ImportWifRequest { wif: exported.backup.wif.clone(), network: Some(exported.backup.network) };
+        let Json(imported) = import_wif(State(state), Json(import)).await.unwrap();
+        (exported, imported)
+    }
+
+    #[tokio::test]
+    async fn exported_wif_imports_to_the_same_address() {
+        for network in [Network::Mainnet, Network::Testnet] {
+            let (wif, address) = BsvService::generate_keypair(network);
+            let (exported, imported) = export_and_import(wif.clone()).await;
+            assert_eq!(exported.backup.network, network);
+            assert_eq!(exported.backup.wif, wif);
+            assert_eq!(exported.backup.address, address);
+            assert_eq!(imported.address, address);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_is_recorded_as_a_completed_job() {
+        let mut config = test_config();
+        config.bitails_api_url = bitails(100_000, accept).await;
+        let state = test_state_with(config);
+        let (wif, from_address) = BsvService::generate_keypair(Network::Mainnet);
+        let (_, to_address) = BsvService::generate_keypair(Network::Mainnet);
+        state.read().await.db.insert_job(&Job::new_download("download".to_string(), "ab".repeat(32))).unwrap();
+
+        let request = SendRequest {
+            wif,
+            to_address: to_address.clone(),
+            amount_satoshis: Amount::from_sat_const(40_000),
+            network: None,
+        };
+        let Json(sent) = send_bsv(State(state.clone()), Json(request)).await.unwrap();
+
+        let state = state.read().await;
+        let sends = state.db.get_all_jobs(Some(JobType::Send)).unwrap();
+        assert_eq!(sends.len(), 1);
+        let job = state.db.get_job(&sends[0].id).unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete);
+        assert_eq!(job.manifest_txid, Some(sent.txid));
+        assert_eq!(job.payment_address, Some(from_address));
+        assert_eq!(job.to_address, Some(to_address));
+        assert_eq!(job.amount_satoshis, Some(40_000));
+        // The change is above the dust limit, so the fee is the estimate
+        assert_eq!(job.fee_satoshis, Some(state.bsv.fee_for_size(250).to_sat_i64()));
+        assert_eq!(job.network, Some(Network::Mainnet));
+        assert_eq!(state.db.get_all_jobs(None).unwrap().len(), 2);
+    }
+}
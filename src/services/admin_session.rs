@@ -0,0 +1,137 @@
+// Admin sessions
+// Logging in trades the admin key for a signed cookie, so the key isn't sent
+// with every request. The cookie is `<expiry>.<epoch>.<signature>`: the
+// signature is an HMAC over the expiry, the session epoch and a hash of the
+// admin key, so changing the key or bumping the epoch (logout) ends every
+// session at once.
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::services::hd::hmac_sha512;
+
+pub const COOKIE_NAME: &str = "nausica_admin";
+
+pub struct AdminSessions {
+    secret: Vec<u8>,
+    lifetime_seconds: i64,
+}
+
+impl AdminSessions {
+    /// Sessions signed with `secret`, or with a random secret when none is
+    /// configured (sessions then end when the server restarts)
+    pub fn new(secret: Option<&str>, lifetime_hours: i64) -> Self {
+        let secret = match secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                let mut secret = vec![0u8; 32];
+                rand::rngs::OsRng.fill_bytes(&mut secret);
+                secret
+            }
+        };
+        AdminSessions {
+            secret,
+            lifetime_seconds: lifetime_hours.max(1) * 3600,
+        }
+    }
+
+    pub fn lifetime_seconds(&self) -> i64 {
+        self.lifetime_seconds
+    }
+
+    fn signature(&self, expiry: i64, epoch: i64, admin_key: &str) -> String {
+        let key_hash = hex::encode(Sha256::digest(admin_key.as_bytes()));
+        let message = format!("admin-session:{}:{}:{}", expiry, epoch, key_hash);
+        hex::encode(hmac_sha512(&self.secret, message.as_bytes()))
+    }
+
+    /// New session token valid from `now` (unix seconds)
+    pub fn issue(&self, admin_key: &str, epoch: i64, now: i64) -> String {
+        let expiry = now + self.lifetime_seconds;
+        format!("{}.{}.{}", expiry, epoch, self.signature(expiry, epoch, admin_key))
+    }
+
+    /// Whether a token is unexpired, of the current epoch and signed for this key
+    pub fn verify(&self, token: &str, admin_key: &str, epoch: i64, now: i64) -> bool {
+        let mut parts = token.splitn(3, '.');
+        let (Some(expiry), Some(token_epoch), Some(signature)) = (parts.next(), parts.next(), parts.next()) else {
+            return false;
+        };
+        let (Ok(expiry), Ok(token_epoch)) = (expiry.parse::<i64>(), token_epoch.parse::<i64>()) else {
+            return false;
+        };
+        if expiry <= now || token_epoch != epoch {
+            return false;
+        }
+        constant_time_eq(signature.as_bytes(), self.signature(expiry, epoch, admin_key).as_bytes())
+    }
+}
+
+/// Compare secrets without returning early at the first differing byte
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Value of a cookie in a `Cookie` header
+pub fn cookie_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "admin-key";
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn issued_session_verifies_until_expiry() {
+        let sessions = AdminSessions::new(Some("secret"), 2);
+        let token = sessions.issue(KEY, 3, NOW);
+
+        assert!(sessions.verify(&token, KEY, 3, NOW));
+        assert!(sessions.verify(&token, KEY, 3, NOW + 2 * 3600 - 1));
+        assert!(!sessions.verify(&token, KEY, 3, NOW + 2 * 3600));
+    }
+
+    #[test]
+    fn session_ends_with_epoch_key_or_secret() {
+        let sessions = AdminSessions::new(Some("secret"), 1);
+        let token = sessions.issue(KEY, 3, NOW);
+
+        assert!(!sessions.verify(&token, KEY, 4, NOW));
+        assert!(!sessions.verify(&token, "new-admin-key", 3, NOW));
+        assert!(!AdminSessions::new(Some("other"), 1).verify(&token, KEY, 3, NOW));
+        assert!(!AdminSessions::new(None, 1).verify(&token, KEY, 3, NOW));
+    }
+
+    #[test]
+    fn tampered_session_is_rejected() {
+        let sessions = AdminSessions::new(Some("secret"), 1);
+        let token = sessions.issue(KEY, 3, NOW);
+        let (expiry, rest) = token.split_once('.').unwrap();
+
+        // A later expiry under the old signature
+        let extended = format!("{}.{}", expiry.parse::<i64>().unwrap() + 3600, rest);
+        assert!(!sessions.verify(&extended, KEY, 3, NOW));
+
+        let mut flipped = token.clone().into_bytes();
+        let last = flipped.last_mut().unwrap();
+        *last = if *last == b'0' { b'1' } else { b'0' };
+        assert!(!sessions.verify(&String::from_utf8(flipped).unwrap(), KEY, 3, NOW));
+
+        assert!(!sessions.verify(&token[..token.len() - 1], KEY, 3, NOW));
+        assert!(!sessions.verify("", KEY, 3, NOW));
+        assert!(!sessions.verify("not.a.token", KEY, 3, NOW));
+    }
+
+    #[test]
+    fn cookie_value_finds_named_cookie() {
+        let header = "theme=dark; nausica_admin=abc.1.def; other=x";
+        assert_eq!(cookie_value(header, COOKIE_NAME), Some("abc.1.def"));
+        assert_eq!(cookie_value(header, "missing"), None);
+    }
+}
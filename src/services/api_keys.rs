@@ -0,0 +1,153 @@
+// API keys for programmatic uploaders
+// Team members get their own key, with a monthly byte quota, instead of
+// sharing the admin key. Uploads without a key stay anonymous.
+
+use axum::http::{header, HeaderMap};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::db::Database;
+use crate::models::ErrorCode;
+
+/// Prefix that makes keys recognizable, e.g. in leaked-secret scans
+const KEY_PREFIX: &str = "nsk_";
+
+/// A new random API key
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", KEY_PREFIX, hex::encode(bytes))
+}
+
+/// What the database stores in place of a key
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Start of the calendar month (UTC) that quotas are counted from
+pub fn month_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .single()
+        .unwrap_or(now)
+}
+
+/// Key an upload of `upload_bytes` is attributed to. None without an
+/// Authorization header; an error for an unknown or revoked key, or one
+/// whose monthly quota the upload would exceed.
+pub fn authorize_upload(
+    db: &Database,
+    headers: &HeaderMap,
+    upload_bytes: i64,
+) -> Result<Option<String>, (ErrorCode, String)> {
+    let Some(value) = headers.get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+    let token = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| invalid("Authorization must be `Bearer <API key>`"))?;
+
+    let key = db
+        .get_api_key_by_hash(&hash_key(token), month_start(Utc::now()))
+        .map_err(|e| (ErrorCode::DatabaseError, format!("Database error: {}", e)))?
+        .ok_or_else(|| invalid("Unknown API key"))?;
+
+    if !key.enabled {
+        return Err(invalid("API key has been revoked"));
+    }
+    if !key.has_quota_for(upload_bytes) {
+        return Err((
+            ErrorCode::QuotaExceeded,
+            format!(
+                "Monthly quota exceeded: {} of {} bytes used, this upload needs {} more",
+                key.used_bytes_this_month,
+                key.monthly_quota_bytes.unwrap_or_default(),
+                upload_bytes
+            ),
+        ));
+    }
+    Ok(Some(key.id))
+}
+
+fn invalid(message: &str) -> (ErrorCode, String) {
+    (ErrorCode::InvalidApiKey, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Job, JobStatus, MessageKey};
+
+    fn key_headers(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", key).parse().unwrap());
+        headers
+    }
+
+    /// A database with one key of `quota` bytes, and the key
+    fn db_with_key(quota: Option<i64>) -> (Database, String) {
+        let db = Database::new(":memory:").unwrap();
+        let key = generate_key();
+        db.insert_api_key("key-1", &hash_key(&key), "ci", quota).unwrap();
+        (db, key)
+    }
+
+    fn add_job(db: &Database, id: &str, size: i64, status: JobStatus) {
+        let job = Job::new_upload(id.to_string(), "f".to_string(), size, Vec::new(), "addr".to_string(), "wif".to_string(), 1)
+            .with_status(status, MessageKey::WaitingForPayment);
+        db.insert_job(&job).unwrap();
+        db.set_job_api_key(id, "key-1").unwrap();
+    }
+
+    #[test]
+    fn uploads_without_a_key_stay_anonymous() {
+        let (db, _) = db_with_key(Some(10));
+        assert_eq!(authorize_upload(&db, &HeaderMap::new(), 1_000).unwrap(), None);
+    }
+
+    #[test]
+    fn unknown_or_malformed_keys_are_rejected() {
+        let (db, _) = db_with_key(None);
+        let (code, _) = authorize_upload(&db, &key_headers("nsk_unknown"), 1).unwrap_err();
+        assert_eq!(code, ErrorCode::InvalidApiKey);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Basic abc".parse().unwrap());
+        assert_eq!(authorize_upload(&db, &headers, 1).unwrap_err().0, ErrorCode::InvalidApiKey);
+    }
+
+    #[test]
+    fn quota_counts_unpaid_and_paid_uploads() {
+        let (db, key) = db_with_key(Some(100));
+        assert_eq!(authorize_upload(&db, &key_headers(&key), 100).unwrap().as_deref(), Some("key-1"));
+
+        add_job(&db, "pending", 40, JobStatus::PendingPayment);
+        add_job(&db, "done", 30, JobStatus::Complete);
+        // Failed uploads give their share back
+        add_job(&db, "failed", 50, JobStatus::Error);
+
+        assert!(authorize_upload(&db, &key_headers(&key), 30).is_ok());
+        let (code, message) = authorize_upload(&db, &key_headers(&key), 31).unwrap_err();
+        assert_eq!(code, ErrorCode::QuotaExceeded);
+        assert!(message.contains("70 of 100"));
+    }
+
+    #[test]
+    fn revoked_key_is_refused_before_and_after_prepare() {
+        let (db, key) = db_with_key(None);
+        add_job(&db, "prepared", 10, JobStatus::PendingPayment);
+        assert!(!db.job_api_key_revoked("prepared").unwrap());
+
+        db.set_api_key_enabled("key-1", false).unwrap();
+        assert_eq!(authorize_upload(&db, &key_headers(&key), 1).unwrap_err().0, ErrorCode::InvalidApiKey);
+        assert!(db.job_api_key_revoked("prepared").unwrap());
+
+        let anonymous = Job::new_upload("anon".to_string(), "f".to_string(), 1, Vec::new(), "a".to_string(), "w".to_string(), 1);
+        db.insert_job(&anonymous).unwrap();
+        assert!(!db.job_api_key_revoked("anon").unwrap());
+    }
+}
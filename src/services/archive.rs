@@ -0,0 +1,83 @@
+// Zip archive writer for batch downloads
+// FLAC is already compressed, so entries are stored uncompressed (method 0).
+
+use chrono::{Datelike, Timelike, Utc};
+
+/// Build a zip archive from (filename, data) entries.
+/// Archives over 4GB or with more than 65535 entries are not supported (no zip64).
+pub fn create_zip(entries: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    if entries.len() > u16::MAX as usize {
+        return Err("Too many files for zip archive".to_string());
+    }
+
+    let (dos_time, dos_date) = dos_timestamp();
+    let mut archive: Vec<u8> = Vec::new();
+    let mut central_directory: Vec<u8> = Vec::new();
+
+    for (name, data) in entries {
+        let name = name.as_bytes();
+        let offset = u32::try_from(archive.len()).map_err(|_| "Zip archive too large".to_string())?;
+        let size = u32::try_from(data.len()).map_err(|_| "File too large for zip archive".to_string())?;
+        let crc = crc32fast::hash(data);
+
+        // Local file header
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        archive.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        archive.extend_from_slice(&0x0800u16.to_le_bytes()); // flags: UTF-8 names
+        archive.extend_from_slice(&0u16.to_le_bytes()); // method: stored
+        archive.extend_from_slice(&dos_time.to_le_bytes());
+        archive.extend_from_slice(&dos_date.to_le_bytes());
+        archive.extend_from_slice(&crc.to_le_bytes());
+        archive.extend_from_slice(&size.to_le_bytes()); // compressed size
+        archive.extend_from_slice(&size.to_le_bytes()); // uncompressed size
+        archive.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        archive.extend_from_slice(name);
+        archive.extend_from_slice(data);
+
+        // Central directory entry
+        central_directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central_directory.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        central_directory.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        central_directory.extend_from_slice(&0x0800u16.to_le_bytes());
+        central_directory.extend_from_slice(&0u16.to_le_bytes());
+        central_directory.extend_from_slice(&dos_time.to_le_bytes());
+        central_directory.extend_from_slice(&dos_date.to_le_bytes());
+        central_directory.extend_from_slice(&crc.to_le_bytes());
+        central_directory.extend_from_slice(&size.to_le_bytes());
+        central_directory.extend_from_slice(&size.to_le_bytes());
+        central_directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central_directory.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        central_directory.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        central_directory.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        central_directory.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        central_directory.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central_directory.extend_from_slice(&offset.to_le_bytes());
+        central_directory.extend_from_slice(name);
+    }
+
+    let cd_offset = u32::try_from(archive.len()).map_err(|_| "Zip archive too large".to_string())?;
+    let cd_size = central_directory.len() as u32;
+    archive.extend_from_slice(&central_directory);
+
+    // End of central directory record
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes()); // this disk
+    archive.extend_from_slice(&0u16.to_le_bytes()); // central directory disk
+    archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&cd_size.to_le_bytes());
+    archive.extend_from_slice(&cd_offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes()); // comment length
+
+    Ok(archive)
+}
+
+/// Current time in MS-DOS format: (time, date)
+fn dos_timestamp() -> (u16, u16) {
+    let now = Utc::now();
+    let time = ((now.hour() as u16) << 11) | ((now.minute() as u16) << 5) | (now.second() as u16 / 2);
+    let year = (now.year() - 1980).clamp(0, 127) as u16;
+    let date = (year << 9) | ((now.month() as u16) << 5) | now.day() as u16;
+    (time, date)
+}
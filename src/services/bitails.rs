@@ -1,5 +1,10 @@
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::models::{BroadcastAttempt, Network};
+use crate::services::provider_health::Provider;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AddressBalance {
@@ -27,16 +32,10 @@ pub struct UnspentResponse {
     pub unspent: Vec<Utxo>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BroadcastResponse {
-    pub txid: Option<String>,
-    pub error: Option<BroadcastError>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BroadcastError {
-    pub code: Option<i32>,
-    pub message: Option<String>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub txid: String,
+    pub blockheight: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -67,42 +66,161 @@ pub struct Transaction {
     pub outputs: Option<Vec<TransactionOutput>>,
 }
 
+/// How long a rate-limited key is skipped before it is tried again
+const KEY_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Request counters for one API key, as shown in the admin metrics
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyUsage {
+    /// Only the last four characters, so the metrics don't leak keys
+    pub key: String,
+    pub requests: u64,
+    pub rate_limited: u64,
+    pub cooling_down: bool,
+}
+
+struct PooledKey {
+    key: String,
+    requests: u64,
+    rate_limited: u64,
+    cooldown_until: Option<Instant>,
+}
+
+/// Round-robin pool of API keys. A key that is rate limited cools down
+/// and the next key takes its traffic.
+struct ApiKeyPool {
+    keys: Mutex<Vec<PooledKey>>,
+    next: Mutex<usize>,
+}
+
+impl ApiKeyPool {
+    fn new(keys: Vec<String>) -> Self {
+        ApiKeyPool {
+            keys: Mutex::new(
+                keys.into_iter()
+                    .map(|key| PooledKey { key, requests: 0, rate_limited: 0, cooldown_until: None })
+                    .collect(),
+            ),
+            next: Mutex::new(0),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.lock().unwrap().len()
+    }
+
+    /// Pick the next key that isn't cooling down. When every key is cooling
+    /// down, the one that recovers first is used anyway.
+    fn acquire(&self) -> Option<(usize, String)> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.is_empty() {
+            return None;
+        }
+        let mut next = self.next.lock().unwrap();
+        let now = Instant::now();
+        let start = *next % keys.len();
+
+        let index = (0..keys.len())
+            .map(|offset| (start + offset) % keys.len())
+            .find(|&i| keys[i].cooldown_until.is_none_or(|until| until <= now))
+            .unwrap_or_else(|| {
+                (0..keys.len())
+                    .min_by_key(|&i| keys[i].cooldown_until)
+                    .unwrap_or(start)
+            });
+
+        *next = index + 1;
+        let key = &mut keys[index];
+        key.requests += 1;
+        Some((index, key.key.clone()))
+    }
+
+    fn mark_rate_limited(&self, index: usize) {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(key) = keys.get_mut(index) {
+            key.rate_limited += 1;
+            key.cooldown_until = Some(Instant::now() + KEY_COOLDOWN);
+        }
+    }
+
+    fn usage(&self) -> Vec<ApiKeyUsage> {
+        let keys = self.keys.lock().unwrap();
+        let now = Instant::now();
+        keys.iter()
+            .map(|k| ApiKeyUsage {
+                key: format!("...{}", &k.key[k.key.char_indices().rev().nth(3).map_or(0, |(i, _)| i)..]),
+                requests: k.requests,
+                rate_limited: k.rate_limited,
+                cooling_down: k.cooldown_until.is_some_and(|until| until > now),
+            })
+            .collect()
+    }
+}
+
+/// Bitails answers 429 when a key is over its rate and 402 when its quota is used up
+fn is_rate_limited(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::PAYMENT_REQUIRED
+}
+
 pub struct BitailsClient {
     client: Client,
     base_url: String,
-    api_key: Option<String>,
+    keys: ApiKeyPool,
 }
 
 impl BitailsClient {
-    pub fn new(base_url: String, api_key: Option<String>) -> Self {
+    pub fn new(base_url: String, api_keys: Vec<String>) -> Self {
         BitailsClient {
-            client: Client::new(),
+            client: crate::services::http::client(),
             base_url,
-            api_key,
+            keys: ApiKeyPool::new(api_keys),
         }
     }
 
-    fn build_request(&self, url: &str) -> reqwest::RequestBuilder {
-        let mut req = self.client.get(url);
-        if let Some(ref key) = self.api_key {
-            req = req.header("apikey", key);
-        }
-        req
+    /// Per-key request counters
+    pub fn key_usage(&self) -> Vec<ApiKeyUsage> {
+        self.keys.usage()
     }
 
-    fn build_post_request(&self, url: &str) -> reqwest::RequestBuilder {
-        let mut req = self.client.post(url);
-        if let Some(ref key) = self.api_key {
-            req = req.header("apikey", key);
+    /// Send a request built by `build`, moving on to the next key when one is
+    /// rate limited. The last rate-limited response is returned once every key
+    /// has been tried, so callers still see the error and back off as before.
+    async fn send(
+        &self,
+        build: impl Fn(&Client) -> RequestBuilder,
+    ) -> Result<Response, reqwest::Error> {
+        let attempts = self.keys.len().max(1);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut req = build(&self.client);
+            let key = self.keys.acquire();
+            if let Some((_, ref key)) = key {
+                req = req.header("apikey", key);
+            }
+
+            let response = req.send().await?;
+            match key {
+                Some((index, _)) if is_rate_limited(response.status()) => {
+                    self.keys.mark_rate_limited(index);
+                    if attempt >= attempts {
+                        return Ok(response);
+                    }
+                    tracing::warn!("Bitails key rate limited ({}), retrying with the next key", response.status());
+                }
+                _ => return Ok(response),
+            }
         }
-        req
+    }
+
+    async fn get(&self, url: &str) -> Result<Response, reqwest::Error> {
+        self.send(|client| client.get(url)).await
     }
 
     pub async fn get_address_balance(&self, address: &str) -> Result<AddressBalance, String> {
         let url = format!("{}/address/{}/balance", self.base_url, address);
         let response = self
-            .build_request(&url)
-            .send()
+            .get(&url)
             .await
             .map_err(|e| format!("Request failed: {}", e))?;
 
@@ -119,8 +237,7 @@ impl BitailsClient {
     pub async fn get_address_unspent(&self, address: &str) -> Result<Vec<Utxo>, String> {
         let url = format!("{}/address/{}/unspent", self.base_url, address);
         let response = self
-            .build_request(&url)
-            .send()
+            .get(&url)
             .await
             .map_err(|e| format!("Request failed: {}", e))?;
 
@@ -137,29 +254,84 @@ impl BitailsClient {
         Ok(result.unspent)
     }
 
-    pub async fn broadcast_transaction(&self, raw_tx_hex: &str) -> Result<String, String> {
-        // Try Bitails first
-        match self.broadcast_via_bitails(raw_tx_hex).await {
-            Ok(txid) => return Ok(txid),
-            Err(e) => {
-                tracing::warn!("Bitails broadcast failed: {}, trying WhatsOnChain...", e);
+    pub async fn get_address_history(&self, address: &str) -> Result<Vec<HistoryEntry>, String> {
+        let url = format!("{}/address/{}/history", self.base_url, address);
+        let response = self
+            .get(&url)
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("API error: {}", response.status()));
+        }
+
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Parse error: {}", e))?;
+
+        // Bitails wraps the list in a "history" field; accept a bare array too
+        let items = json
+            .get("history")
+            .and_then(|h| h.as_array())
+            .or_else(|| json.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        Ok(items
+            .iter()
+            .filter_map(|v| {
+                let txid = v.get("txid").or_else(|| v.get("tx_hash"))?.as_str()?.to_string();
+                let blockheight = v
+                    .get("blockheight")
+                    .or_else(|| v.get("height"))
+                    .and_then(|h| h.as_i64());
+                Some(HistoryEntry { txid, blockheight })
+            })
+            .collect())
+    }
+
+    /// Broadcast through Bitails, falling back to WhatsOnChain. While Bitails
+    /// is failing, WhatsOnChain is tried first. Returns every attempt in
+    /// order; the broadcast succeeded if the last one has a txid.
+    pub async fn broadcast_transaction(&self, raw_tx_hex: &str) -> Vec<BroadcastAttempt> {
+        let health = crate::services::provider_health::mainnet();
+        let mut attempts = Vec::new();
+        for provider in health.order(Provider::Bitails, Provider::WhatsOnChain) {
+            let attempt = match provider {
+                Provider::Bitails => self.broadcast_via_bitails(raw_tx_hex).await,
+                Provider::WhatsOnChain => self.broadcast_via_whatsonchain(raw_tx_hex).await,
+            };
+            health.record(provider, !attempt.provider_failed());
+            let accepted = attempt.accepted_txid.is_some();
+            if !accepted && attempts.is_empty() {
+                tracing::warn!("Broadcast failed: {}, trying the other provider...", attempt);
+            }
+            attempts.push(attempt);
+            if accepted {
+                break;
             }
         }
-        
-        // Fallback to WhatsOnChain
-        self.broadcast_via_whatsonchain(raw_tx_hex).await
+        attempts
     }
     
-    async fn broadcast_via_bitails(&self, raw_tx_hex: &str) -> Result<String, String> {
+    async fn broadcast_via_bitails(&self, raw_tx_hex: &str) -> BroadcastAttempt {
         let url = format!("{}/tx/broadcast", self.base_url);
-        let response = self
-            .build_post_request(&url)
-            .header("Content-Type", "application/json")
-            .body(format!("{{\"raw\":\"{}\"}}", raw_tx_hex))
-            .send()
+        let body = format!("{{\"raw\":\"{}\"}}", raw_tx_hex);
+        let response = match self
+            .send(|client| {
+                client
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .body(body.clone())
+            })
             .await
-            .map_err(|e| format!("Request failed: {}", e))?;
+        {
+            Ok(response) => response,
+            Err(e) => return BroadcastAttempt::rejected("bitails", None, &format!("Request failed: {}", e)),
+        };
 
+        let status = response.status().as_u16();
         let response_text = response.text().await.unwrap_or_default();
         
         // Try to parse as JSON
@@ -168,59 +340,64 @@ impl BitailsClient {
             if let Some(error) = json.get("error") {
                 let error_msg = error.get("message")
                     .and_then(|m| m.as_str())
-                    .unwrap_or("Unknown error");
-                return Err(format!("Broadcast failed: {}", error_msg));
+                    .map(str::to_string)
+                    .unwrap_or_else(|| error.to_string());
+                return BroadcastAttempt::rejected("bitails", Some(status), &error_msg);
             }
             
             // Get txid
             if let Some(txid) = json.get("txid").and_then(|t| t.as_str()) {
-                return Ok(txid.to_string());
+                return BroadcastAttempt::accepted("bitails", status, txid.to_string());
             }
         }
         
         // If response looks like a txid (64 hex chars), return it
         let trimmed = response_text.trim().trim_matches('"');
         if trimmed.len() == 64 && trimmed.chars().all(|c| c.is_ascii_hexdigit()) {
-            return Ok(trimmed.to_string());
+            return BroadcastAttempt::accepted("bitails", status, trimmed.to_string());
         }
         
-        Err(format!("Unexpected response: {}", response_text))
+        BroadcastAttempt::rejected("bitails", Some(status), &response_text)
     }
     
-    async fn broadcast_via_whatsonchain(&self, raw_tx_hex: &str) -> Result<String, String> {
-        let url = "https://api.whatsonchain.com/v1/bsv/main/tx/raw";
-        let response = self.client
+    async fn broadcast_via_whatsonchain(&self, raw_tx_hex: &str) -> BroadcastAttempt {
+        let url = format!("{}/tx/raw", crate::services::whatsonchain::base_url(Network::Mainnet));
+        crate::services::rate_limit::whatsonchain().acquire().await;
+        let response = match self.client
             .post(url)
             .header("Content-Type", "application/json")
             .body(format!("{{\"txhex\":\"{}\"}}", raw_tx_hex))
             .send()
             .await
-            .map_err(|e| format!("WoC request failed: {}", e))?;
+        {
+            Ok(response) => response,
+            Err(e) => return BroadcastAttempt::rejected("whatsonchain", None, &format!("Request failed: {}", e)),
+        };
 
+        let status = response.status().as_u16();
         let response_text = response.text().await.unwrap_or_default();
         
         // WhatsOnChain returns the txid directly as a quoted string
         let trimmed = response_text.trim().trim_matches('"');
         if trimmed.len() == 64 && trimmed.chars().all(|c| c.is_ascii_hexdigit()) {
             tracing::info!("Broadcast via WhatsOnChain successful: {}", trimmed);
-            return Ok(trimmed.to_string());
+            return BroadcastAttempt::accepted("whatsonchain", status, trimmed.to_string());
         }
         
         // Check for error response
         if let Ok(json) = serde_json::from_str::<serde_json::Value>(&response_text) {
             if let Some(error) = json.get("error") {
-                return Err(format!("WoC broadcast failed: {}", error));
+                return BroadcastAttempt::rejected("whatsonchain", Some(status), &error.to_string());
             }
         }
         
-        Err(format!("WoC unexpected response: {}", response_text))
+        BroadcastAttempt::rejected("whatsonchain", Some(status), &response_text)
     }
     
     pub async fn get_transaction(&self, txid: &str) -> Result<Transaction, String> {
         let url = format!("{}/tx/{}", self.base_url, txid);
         let response = self
-            .build_request(&url)
-            .send()
+            .get(&url)
             .await
             .map_err(|e| format!("Request failed: {}", e))?;
 
@@ -237,8 +414,7 @@ impl BitailsClient {
     pub async fn download_tx_output(&self, txid: &str, output_index: u32) -> Result<Vec<u8>, String> {
         let url = format!("{}/download/tx/{}/output/{}", self.base_url, txid, output_index);
         let response = self
-            .build_request(&url)
-            .send()
+            .get(&url)
             .await
             .map_err(|e| format!("Request failed: {}", e))?;
 
@@ -256,8 +432,7 @@ impl BitailsClient {
     pub async fn download_tx_raw(&self, txid: &str) -> Result<String, String> {
         let url = format!("{}/download/tx/{}", self.base_url, txid);
         let response = self
-            .build_request(&url)
-            .send()
+            .get(&url)
             .await
             .map_err(|e| format!("Request failed: {}", e))?;
 
@@ -265,12 +440,109 @@ impl BitailsClient {
             return Err(format!("API error: {}", response.status()));
         }
 
-        // The API returns raw binary transaction data, convert to hex
         let bytes = response
             .bytes()
             .await
             .map_err(|e| format!("Download error: {}", e))?;
         
-        Ok(hex::encode(&bytes))
+        Ok(raw_tx_body_to_hex(&bytes))
+    }
+}
+
+/// Hex of a raw tx download. The API returns binary, but a body that is
+/// already hex text is passed through rather than encoded twice; binary
+/// can't be mistaken for it, since the version field holds zero bytes.
+fn raw_tx_body_to_hex(body: &[u8]) -> String {
+    let text = body.trim_ascii();
+    let text = text
+        .strip_prefix(b"\"")
+        .and_then(|t| t.strip_suffix(b"\""))
+        .unwrap_or(text);
+    if !text.is_empty() && text.len().is_multiple_of(2) && text.iter().all(u8::is_ascii_hexdigit) {
+        String::from_utf8_lossy(text).to_ascii_lowercase()
+    } else {
+        hex::encode(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    /// Bitails stand-in that rate limits `hot-key` and counts requests per key
+    async fn rate_limiting_bitails() -> (String, Arc<Mutex<HashMap<String, u64>>>) {
+        let seen: Arc<Mutex<HashMap<String, u64>>> = Arc::default();
+        let counter = seen.clone();
+        let router = axum::Router::new().route(
+            "/address/:address/unspent",
+            axum::routing::get(move |headers: HeaderMap| async move {
+                let key = headers.get("apikey").and_then(|v| v.to_str().ok()).unwrap_or("none").to_string();
+                *counter.lock().unwrap().entry(key.clone()).or_default() += 1;
+                if key == "hot-key" {
+                    return (axum::http::StatusCode::TOO_MANY_REQUESTS, axum::Json(serde_json::json!({})));
+                }
+                let body = serde_json::json!({ "address": "addr", "unspent": [] });
+                (axum::http::StatusCode::OK, axum::Json(body))
+            }),
+        );
+        (crate::test_support::serve(router).await, seen)
+    }
+
+    #[tokio::test]
+    async fn rate_limited_key_hands_its_traffic_to_the_next() {
+        let (url, seen) = rate_limiting_bitails().await;
+        let client = BitailsClient::new(url, vec!["hot-key".to_string(), "cool-key".to_string()]);
+        for _ in 0..4 {
+            assert!(client.get_address_unspent("addr").await.is_ok());
+        }
+
+        // The hot key is tried once, then cools down while the other serves everything
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(seen.get("hot-key"), Some(&1));
+        assert_eq!(seen.get("cool-key"), Some(&4));
+        let usage = client.key_usage();
+        assert_eq!((usage[0].key.as_str(), usage[0].requests, usage[0].rate_limited), ("...-key", 1, 1));
+        assert!(usage[0].cooling_down);
+        assert_eq!((usage[1].requests, usage[1].rate_limited, usage[1].cooling_down), (4, 0, false));
+    }
+
+    #[tokio::test]
+    async fn single_key_returns_the_rate_limit_error() {
+        let (url, seen) = rate_limiting_bitails().await;
+        let client = BitailsClient::new(url.clone(), vec!["hot-key".to_string()]);
+        let error = client.get_address_unspent("addr").await.unwrap_err();
+        assert!(error.contains("429"), "{}", error);
+        assert_eq!(seen.lock().unwrap().get("hot-key"), Some(&1));
+
+        // Without keys no header is sent
+        let client = BitailsClient::new(url, Vec::new());
+        assert!(client.get_address_unspent("addr").await.is_ok());
+        assert_eq!(seen.lock().unwrap().get("none"), Some(&1));
+        assert!(client.key_usage().is_empty());
+    }
+
+    #[tokio::test]
+    async fn binary_and_hex_text_downloads_decode_alike() {
+        let raw_tx = crate::services::bsv::BsvService::for_tests().test_transaction(&[]);
+        let bytes = hex::decode(&raw_tx).unwrap();
+        let bodies: Vec<Vec<u8>> = vec![
+            bytes,
+            raw_tx.clone().into_bytes(),
+            format!("\"{}\"\n", raw_tx.to_uppercase()).into_bytes(),
+        ];
+        let router = axum::Router::new().route(
+            "/download/tx/:index",
+            axum::routing::get(move |axum::extract::Path(index): axum::extract::Path<usize>| {
+                let body = bodies[index].clone();
+                async move { body }
+            }),
+        );
+        let client = BitailsClient::new(crate::test_support::serve(router).await, Vec::new());
+        for index in 0..3 {
+            assert_eq!(client.download_tx_raw(&index.to_string()).await.unwrap(), raw_tx, "body {}", index);
+        }
     }
 }
@@ -4,26 +4,222 @@ use ripemd::Ripemd160;
 use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
 use sha2::{Digest, Sha256};
 
+use crate::models::{Amount, Network};
+use crate::services::lyrics::{self, LyricsFormat};
+use crate::services::thumbnail;
+
+/// Labels that may precede a value in a labeled-layout manifest
+pub const MANIFEST_LABELS: [&str; 8] =
+    ["title", "artist", "lyrics", "lyrics_format", "cover_txid", "lyrics_txid", "thumb_txid", "thumb_inline"];
+
+/// How track metadata is laid out in a FLAC manifest script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestLayout {
+    /// Title, artist and lyrics inside the metadata JSON push
+    Json,
+    /// Title, artist and lyrics as separate label/value pushes
+    Labeled,
+}
+
+impl ManifestLayout {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "json" => Some(ManifestLayout::Json),
+            "labeled" => Some(ManifestLayout::Labeled),
+            _ => None,
+        }
+    }
+}
+
+/// Where an upload keeps the thumbnail of its cover
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoverThumb {
+    /// Base64 JPEG inside the track metadata
+    Inline(String),
+    /// A coverart transaction of its own
+    Txid(String),
+}
+
+impl CoverThumb {
+    /// Label and value the thumbnail is recorded under in track metadata
+    pub fn manifest_field(&self) -> (&'static str, &str) {
+        match self {
+            CoverThumb::Inline(data) => ("thumb_inline", data),
+            CoverThumb::Txid(txid) => ("thumb_txid", txid),
+        }
+    }
+}
+
+/// Default cap on a single data push. Larger data is spread over several
+/// pushes; 100KB matches the pushes FLAC single-tx uploads always used.
+pub const DEFAULT_MAX_PUSH_SIZE: usize = 100 * 1024;
+
+/// Default cap on outputs per split transaction, comfortably inside the
+/// standard transaction size limit
+pub const DEFAULT_MAX_SPLIT_OUTPUTS: usize = 250;
+
+/// Default cap on the UTXOs combined to fund a split when no single one
+/// can; each adds about 148 bytes to the split transaction
+pub const DEFAULT_MAX_SPLIT_INPUTS: usize = 50;
+
+/// Default size above which a FLAC upload is stored in chunk transactions
+/// instead of a single transaction
+pub const DEFAULT_FLAC_SINGLE_TX_MAX_BYTES: usize = 1024 * 1024;
+
+/// Data carried by each chunk transaction of a chunked FLAC upload, unless
+/// the broadcasting provider's transaction limit sets the size
+pub const FLAC_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Default size above which lyrics get their own flacstore-lyrics
+/// transaction instead of riding in the track metadata
+pub const DEFAULT_LYRICS_INLINE_MAX_BYTES: usize = 2048;
+
+/// Bytes of a side transaction (long lyrics, a large cover thumbnail)
+/// besides its data and push opcodes: the input, the data and change
+/// outputs, and the script framing
+const SIDE_TX_OVERHEAD: usize = 250;
+
+/// Smallest chunk a provider limit can shrink chunks to
+const MIN_FLAC_CHUNK_SIZE: usize = 1024;
+
+/// Bytes of a chunk transaction besides its data and push opcodes: the
+/// input, the data output, and the chunk script's framing and index
+const CHUNK_TX_OVERHEAD: usize = 200;
+
+/// Room in a single-transaction FLAC script for the flacstore framing and
+/// the metadata JSON (filename, royalty)
+const FLAC_STORE_HEADER_ALLOWANCE: usize = 1024;
+
+/// Largest transaction each broadcast provider accepts, where configured.
+/// Neither provider publishes its policy, so the limits are set by hand.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProviderTxLimits {
+    pub bitails: Option<usize>,
+    pub whatsonchain: Option<usize>,
+}
+
+impl ProviderTxLimits {
+    /// Limit of the provider that broadcasts on `network`
+    pub fn for_network(&self, network: Network) -> Option<usize> {
+        if crate::services::whatsonchain::serves(network) {
+            self.whatsonchain
+        } else {
+            self.bitails
+        }
+    }
+}
+
+/// How a FLAC upload is stored on chain and what it is quoted
+pub struct FlacUploadPlan {
+    /// Chunk transactions plus a manifest, rather than one transaction
+    pub chunked: bool,
+    pub chunk_count: usize,
+    /// Data in each chunk transaction, the last one aside
+    pub chunk_size: usize,
+    /// Satoshis to request, before any royalty
+    pub cost: Amount,
+    /// Most outputs any of the upload's transactions has, change included
+    pub tx_outputs: usize,
+}
+
+/// Split transactions that fund a chunked upload
+pub struct SplitPlan {
+    /// (txid, raw_tx) in broadcast order; each spends an output of an earlier one
+    pub transactions: Vec<(String, String)>,
+    /// (txid, vout) of each requested output, in order
+    pub outputs: Vec<(String, u32)>,
+    /// (txid, vout, satoshis) of the change, when above the dust limit
+    pub change: Option<(String, u32, Amount)>,
+}
+
+/// Default dust limit (DUST_LIMIT_SATOSHIS)
+pub const DEFAULT_DUST_LIMIT_SATOSHIS: u64 = 546;
+
+/// Default value of each data-carrying output (DATA_OUTPUT_SATOSHIS)
+pub const DEFAULT_DATA_OUTPUT_SATOSHIS: u64 = 1;
+
+/// Slack on each split output beyond the chunk fee and data output
+const CHUNK_OUTPUT_BUFFER: Amount = Amount::from_sat_const(9);
+
+const SIGHASH_ALL: u32 = 0x01;
+const SIGHASH_FORKID: u32 = 0x40;
+
 pub struct BsvService {
     _private_key: Option<String>,
     pub fee_rate: f64,
+    /// Sign with SIGHASH_FORKID (BIP143 digest). Required on BSV; turning it
+    /// off produces legacy signatures for testing against non-FORKID signers.
+    pub use_forkid: bool,
+    /// Largest single push the script builders emit
+    pub max_push_size: usize,
+    /// Value of each FLAC data-carrying output (chunk, manifest, cover, single tx)
+    pub data_output_satoshis: Amount,
+    /// Outputs at or below this are dust; change that small is left to the miner
+    pub dust_limit: Amount,
+    /// Most outputs one split transaction creates before splitting is batched
+    pub max_split_outputs: usize,
+    /// Most UTXOs the first split transaction spends together
+    pub max_split_inputs: usize,
+    /// Largest FLAC upload stored in a single transaction
+    pub flac_single_tx_max_bytes: usize,
+    /// Longest lyrics kept inline in the track metadata
+    pub lyrics_inline_max_bytes: usize,
+    pub provider_tx_limits: ProviderTxLimits,
 }
 
 impl BsvService {
-    pub fn new(private_key: Option<String>, fee_rate: f64) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        private_key: Option<String>,
+        fee_rate: f64,
+        use_forkid: bool,
+        max_push_size: usize,
+        data_output_satoshis: Amount,
+        dust_limit: Amount,
+        max_split_outputs: usize,
+        max_split_inputs: usize,
+        flac_single_tx_max_bytes: usize,
+        lyrics_inline_max_bytes: usize,
+        provider_tx_limits: ProviderTxLimits,
+    ) -> Self {
         BsvService {
             _private_key: private_key,
             fee_rate,
+            use_forkid,
+            max_push_size: max_push_size.max(1),
+            data_output_satoshis,
+            dust_limit,
+            // A batch needs at least two outputs or batching never converges
+            max_split_outputs: max_split_outputs.max(2),
+            max_split_inputs: max_split_inputs.max(1),
+            flac_single_tx_max_bytes,
+            lyrics_inline_max_bytes,
+            provider_tx_limits,
+        }
+    }
+
+    /// Sighash type appended to signatures and committed to in the digest
+    pub fn sighash_type(&self) -> u32 {
+        if self.use_forkid {
+            SIGHASH_ALL | SIGHASH_FORKID
+        } else {
+            SIGHASH_ALL
         }
     }
 
     /// Generate a new keypair and return (WIF private key, address)
-    /// network: "mainnet" or "testnet"
-    pub fn generate_keypair(network: &str) -> (String, String) {
+    pub fn generate_keypair(network: Network) -> (String, String) {
+        let secp = Secp256k1::new();
+        let (secret_key, _) = secp.generate_keypair(&mut OsRng);
+        Self::keypair_from_secret_key(&secret_key, network)
+    }
+
+    /// (WIF private key, address) of an existing secret key
+    pub fn keypair_from_secret_key(secret_key: &SecretKey, network: Network) -> (String, String) {
         let secp = Secp256k1::new();
-        let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+        let public_key = PublicKey::from_secret_key(&secp, secret_key);
 
-        let wif = Self::secret_key_to_wif(&secret_key, network);
+        let wif = Self::secret_key_to_wif(secret_key, network);
         let address = Self::public_key_to_address(&public_key, network);
 
         (wif, address)
@@ -31,6 +227,11 @@ impl BsvService {
 
     /// Convert WIF to SecretKey
     pub fn wif_to_secret_key(wif: &str) -> Result<SecretKey, String> {
+        Self::decode_wif(wif).map(|(secret_key, _)| secret_key)
+    }
+
+    /// Convert WIF to SecretKey and whether its public key is used compressed
+    pub fn decode_wif(wif: &str) -> Result<(SecretKey, bool), String> {
         let decoded = bs58::decode(wif)
             .into_vec()
             .map_err(|e| format!("Invalid WIF: {}", e))?;
@@ -41,96 +242,147 @@ impl BsvService {
 
         // Remove version byte (first) and checksum (last 4 bytes)
         // Also handle compressed key indicator (0x01 before checksum)
-        let key_bytes = if decoded.len() == 38 {
+        let compressed = if decoded.len() == 38 {
             // Compressed: version(1) + key(32) + compressed(1) + checksum(4)
-            &decoded[1..33]
+            if decoded[33] != 0x01 {
+                return Err(format!("Invalid compression flag: 0x{:02x}", decoded[33]));
+            }
+            true
         } else if decoded.len() == 37 {
             // Uncompressed: version(1) + key(32) + checksum(4)
-            &decoded[1..33]
+            false
         } else {
             return Err(format!("Unexpected WIF length: {}", decoded.len()));
         };
 
-        SecretKey::from_slice(key_bytes).map_err(|e| format!("Invalid key: {}", e))
+        let secret_key = SecretKey::from_slice(&decoded[1..33]).map_err(|e| format!("Invalid key: {}", e))?;
+        Ok((secret_key, compressed))
+    }
+
+    /// Public key bytes in the encoding the key's address was derived from
+    fn serialize_public_key(public_key: &PublicKey, compressed: bool) -> Vec<u8> {
+        if compressed {
+            public_key.serialize().to_vec()
+        } else {
+            public_key.serialize_uncompressed().to_vec()
+        }
+    }
+
+    /// Network a WIF belongs to, from its version byte
+    pub fn wif_network(wif: &str) -> Result<Network, String> {
+        Self::wif_to_secret_key(wif)?;
+        let decoded = bs58::decode(wif)
+            .into_vec()
+            .map_err(|e| format!("Invalid WIF: {}", e))?;
+
+        match decoded[0] {
+            0x80 => Ok(Network::Mainnet),
+            0xef => Ok(Network::Testnet),
+            v => Err(format!("Unknown WIF version byte: 0x{:02x}", v)),
+        }
     }
 
     /// Convert SecretKey to WIF (compressed)
-    /// network: "mainnet" or "testnet"
-    fn secret_key_to_wif(secret_key: &SecretKey, network: &str) -> String {
+    fn secret_key_to_wif(secret_key: &SecretKey, network: Network) -> String {
         // Mainnet: 0x80, Testnet: 0xef
-        let version_byte = if network == "testnet" { 0xef } else { 0x80 };
+        let version_byte = if network.uses_testnet_versions() { 0xef } else { 0x80 };
         let mut data = vec![version_byte];
         data.extend_from_slice(&secret_key[..]);
         data.push(0x01); // Compressed flag
 
         // Double SHA256 for checksum
         let hash1 = Sha256::digest(&data);
-        let hash2 = Sha256::digest(&hash1);
+        let hash2 = Sha256::digest(hash1);
         data.extend_from_slice(&hash2[..4]);
 
         bs58::encode(data).into_string()
     }
 
     /// Convert public key to BSV address
-    /// network: "mainnet" or "testnet"
-    fn public_key_to_address(public_key: &PublicKey, network: &str) -> String {
+    fn public_key_to_address(public_key: &PublicKey, network: Network) -> String {
         let serialized = public_key.serialize(); // Compressed
+        Self::pubkey_bytes_to_address(&serialized, network)
+    }
 
+    /// Convert serialized public key bytes (compressed or uncompressed) to BSV address
+    pub fn pubkey_bytes_to_address(serialized: &[u8], network: Network) -> String {
         // SHA256
-        let sha256_hash = Sha256::digest(&serialized);
+        let sha256_hash = Sha256::digest(serialized);
 
         // RIPEMD160
-        let ripemd_hash = Ripemd160::digest(&sha256_hash);
+        let ripemd_hash = Ripemd160::digest(sha256_hash);
+
+        Self::pubkey_hash_to_address(&ripemd_hash, network)
+    }
 
+    /// Encode a 20-byte public key hash as a P2PKH address
+    pub fn pubkey_hash_to_address(pubkey_hash: &[u8], network: Network) -> String {
         // Add version byte (0x00 for mainnet, 0x6f for testnet)
         // Testnet addresses start with 'm' or 'n'
-        let version_byte = if network == "testnet" { 0x6f } else { 0x00 };
+        let version_byte = if network.uses_testnet_versions() { 0x6f } else { 0x00 };
         let mut address_bytes = vec![version_byte];
-        address_bytes.extend_from_slice(&ripemd_hash);
+        address_bytes.extend_from_slice(pubkey_hash);
 
         // Checksum
         let hash1 = Sha256::digest(&address_bytes);
-        let hash2 = Sha256::digest(&hash1);
+        let hash2 = Sha256::digest(hash1);
         address_bytes.extend_from_slice(&hash2[..4]);
 
         bs58::encode(address_bytes).into_string()
     }
 
     /// Get address from WIF
-    /// network: "mainnet" or "testnet"
-    pub fn wif_to_address(wif: &str, network: &str) -> Result<String, String> {
-        let secret_key = Self::wif_to_secret_key(wif)?;
+    /// An uncompressed WIF hashes the uncompressed public key, so it controls a different address
+    pub fn wif_to_address(wif: &str, network: Network) -> Result<String, String> {
+        let (secret_key, compressed) = Self::decode_wif(wif)?;
         let secp = Secp256k1::new();
         let public_key = PublicKey::from_secret_key(&secp, &secret_key);
-        Ok(Self::public_key_to_address(&public_key, network))
+        Ok(Self::pubkey_bytes_to_address(&Self::serialize_public_key(&public_key, compressed), network))
+    }
+
+    /// Fee for a transaction of `tx_size` bytes at the configured rate
+    pub fn fee_for_size(&self, tx_size: usize) -> Amount {
+        let fee = (tx_size as f64 * self.fee_rate).ceil().max(0.0) as u64;
+        Amount::from_sat(fee).unwrap_or(Amount::MAX)
     }
 
     /// Calculate required satoshis for uploading data
-    pub fn calculate_upload_cost(&self, data_size: usize) -> i64 {
+    pub fn calculate_upload_cost(&self, data_size: usize) -> Amount {
         // Transaction overhead: ~150 bytes for inputs/outputs
-        // Plus data size
-        let tx_size = 150 + data_size;
-        let fee = (tx_size as f64 * self.fee_rate).ceil() as i64;
-        
-        // Minimum 1 satoshi, plus some buffer
-        std::cmp::max(fee + 1, 546) // 546 is dust limit
+        // Plus data size and the opcodes of its pushes
+        let tx_size = 150 + data_size + self.push_overhead(data_size);
+        let fee = self.fee_for_size(tx_size);
+
+        // Fee plus the data output value
+        std::cmp::max(fee.saturating_add(self.data_output_satoshis), self.dust_limit)
     }
 
     /// Create OP_RETURN script with data (legacy method)
-    pub fn create_op_return_script(data_parts: &[&[u8]]) -> Vec<u8> {
+    /// The last part is the file data and may span several pushes
+    pub fn create_op_return_script(&self, data_parts: &[&[u8]]) -> Vec<u8> {
         let mut script = Vec::new();
 
         // OP_FALSE OP_RETURN
         script.push(0x00); // OP_FALSE
         script.push(0x6a); // OP_RETURN
 
-        for data in data_parts {
-            Self::push_data(&mut script, data);
+        if let Some((data, fields)) = data_parts.split_last() {
+            for field in fields {
+                Self::push_data(&mut script, field);
+            }
+            self.push_data_bounded(&mut script, data);
         }
 
         script
     }
 
+    /// Create the upfile OP_RETURN script for a generic upload.
+    /// Field order is fixed: "upfile", MIME type, filename, then the file
+    /// data in as many pushes as needed. tx_parse reads it back in that order.
+    pub fn create_upfile_script(&self, mime_type: &str, filename: &str, data: &[u8]) -> Vec<u8> {
+        self.create_op_return_script(&[b"upfile", mime_type.as_bytes(), filename.as_bytes(), data])
+    }
+
     /// Create OP_FALSE OP_IF script for FLAC storage
     /// Format:
     ///   OP_FALSE (0x00)
@@ -143,6 +395,7 @@ impl BsvService {
     ///     ...
     ///   OP_ENDIF (0x68)
     pub fn create_flac_store_script(
+        &self,
         protocol: &[u8],
         mime_type: &[u8],
         metadata: &[u8],
@@ -165,7 +418,7 @@ impl BsvService {
 
         // Data chunks
         for chunk in data_chunks {
-            Self::push_data(&mut script, chunk);
+            self.push_data_bounded(&mut script, chunk);
         }
 
         // OP_ENDIF
@@ -183,90 +436,21 @@ impl BsvService {
             .collect()
     }
 
-    /// Parse OP_FALSE OP_IF script and extract data
-    /// Returns: (protocol, mime_type, metadata, data_chunks)
-    pub fn parse_flac_store_script(script: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>, Vec<Vec<u8>>), String> {
-        if script.len() < 4 {
-            return Err("Script too short".to_string());
+    /// Push data in as many pushes as needed to keep each one within
+    /// `max_push_size`. Readers join the pushes back together.
+    pub fn push_data_bounded(&self, script: &mut Vec<u8>, data: &[u8]) {
+        if data.is_empty() {
+            Self::push_data(script, data);
+            return;
         }
-
-        // Check OP_FALSE OP_IF
-        if script[0] != 0x00 || script[1] != 0x63 {
-            return Err("Not a valid OP_FALSE OP_IF script".to_string());
+        for part in data.chunks(self.max_push_size) {
+            Self::push_data(script, part);
         }
-
-        let mut pos = 2;
-        let mut data_parts: Vec<Vec<u8>> = Vec::new();
-
-        while pos < script.len() {
-            // Check for OP_ENDIF
-            if script[pos] == 0x68 {
-                break;
-            }
-
-            // Read PUSHDATA
-            let (data, new_pos) = Self::read_push_data(script, pos)?;
-            data_parts.push(data);
-            pos = new_pos;
-        }
-
-        if data_parts.len() < 3 {
-            return Err("Not enough data parts in script".to_string());
-        }
-
-        let protocol = data_parts.remove(0);
-        let mime_type = data_parts.remove(0);
-        let metadata = data_parts.remove(0);
-        let data_chunks = data_parts;
-
-        Ok((protocol, mime_type, metadata, data_chunks))
     }
 
-    /// Read PUSHDATA from script at given position
-    fn read_push_data(script: &[u8], pos: usize) -> Result<(Vec<u8>, usize), String> {
-        if pos >= script.len() {
-            return Err("Unexpected end of script".to_string());
-        }
-
-        let opcode = script[pos];
-        let (data_len, data_start) = if opcode <= 75 {
-            // Direct push
-            (opcode as usize, pos + 1)
-        } else if opcode == 0x4c {
-            // OP_PUSHDATA1
-            if pos + 1 >= script.len() {
-                return Err("Missing length byte for OP_PUSHDATA1".to_string());
-            }
-            (script[pos + 1] as usize, pos + 2)
-        } else if opcode == 0x4d {
-            // OP_PUSHDATA2
-            if pos + 2 >= script.len() {
-                return Err("Missing length bytes for OP_PUSHDATA2".to_string());
-            }
-            let len = u16::from_le_bytes([script[pos + 1], script[pos + 2]]) as usize;
-            (len, pos + 3)
-        } else if opcode == 0x4e {
-            // OP_PUSHDATA4
-            if pos + 4 >= script.len() {
-                return Err("Missing length bytes for OP_PUSHDATA4".to_string());
-            }
-            let len = u32::from_le_bytes([
-                script[pos + 1],
-                script[pos + 2],
-                script[pos + 3],
-                script[pos + 4],
-            ]) as usize;
-            (len, pos + 5)
-        } else {
-            return Err(format!("Unexpected opcode: 0x{:02x}", opcode));
-        };
-
-        if data_start + data_len > script.len() {
-            return Err("Data extends beyond script".to_string());
-        }
-
-        let data = script[data_start..data_start + data_len].to_vec();
-        Ok((data, data_start + data_len))
+    /// Opcode bytes added when `data_size` bytes are pushed via `push_data_bounded`
+    fn push_overhead(&self, data_size: usize) -> usize {
+        5 * data_size.div_ceil(self.max_push_size).max(1)
     }
 
     /// Push data with appropriate opcode
@@ -312,16 +496,58 @@ impl BsvService {
         Ok(script)
     }
 
+    /// Check that an address is a well-formed P2PKH address for the network
+    pub fn validate_address(address: &str, network: Network) -> Result<(), String> {
+        let decoded = bs58::decode(address)
+            .into_vec()
+            .map_err(|e| format!("Invalid address: {}", e))?;
+
+        if decoded.len() != 25 {
+            return Err("Invalid address length".to_string());
+        }
+
+        let hash1 = Sha256::digest(&decoded[..21]);
+        let hash2 = Sha256::digest(hash1);
+        if decoded[21..] != hash2[..4] {
+            return Err("Invalid address checksum".to_string());
+        }
+
+        let expected_version = if network.uses_testnet_versions() { 0x6f } else { 0x00 };
+        if decoded[0] != expected_version {
+            return Err(format!("Address is not a {} address", network));
+        }
+
+        Ok(())
+    }
+
+    /// Txid of a raw transaction: its double SHA-256, byte-reversed
+    pub fn txid(raw_tx_hex: &str) -> Result<String, String> {
+        let bytes = hex::decode(raw_tx_hex).map_err(|e| format!("Invalid tx hex: {}", e))?;
+        let mut hash = Self::double_sha256(&bytes);
+        hash.reverse();
+        Ok(hex::encode(hash))
+    }
+
     /// Create a raw transaction
     pub fn create_transaction(
         &self,
         wif: &str,
         utxos: &[(String, u32, i64, Vec<u8>)], // (txid, vout, satoshis, scriptPubKey)
-        outputs: &[(Vec<u8>, i64)],             // (scriptPubKey, satoshis)
+        outputs: &[(Vec<u8>, Amount)],          // (scriptPubKey, satoshis)
     ) -> Result<String, String> {
-        let secret_key = Self::wif_to_secret_key(wif)?;
+        // Refuse to sign anything with a negative input or outputs the inputs can't pay for
+        let mut input_total = Amount::ZERO;
+        for (_, _, satoshis, _) in utxos {
+            input_total = input_total.checked_add(Amount::try_from(*satoshis)?)?;
+        }
+        let output_total = Amount::sum(outputs.iter().map(|(_, satoshis)| *satoshis))?;
+        input_total.checked_sub(output_total)?;
+
+        let (secret_key, compressed) = Self::decode_wif(wif)?;
         let secp = Secp256k1::new();
         let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+        // Must hash to the address the UTXOs are locked to
+        let pubkey_bytes = Self::serialize_public_key(&public_key, compressed);
 
         let mut tx = Vec::new();
 
@@ -354,7 +580,7 @@ impl BsvService {
 
         // Outputs
         for (script, satoshis) in outputs {
-            tx.extend_from_slice(&satoshis.to_le_bytes());
+            tx.extend_from_slice(&satoshis.to_sat().to_le_bytes());
             Self::write_varint(&mut tx, script.len() as u64);
             tx.extend_from_slice(script);
         }
@@ -379,9 +605,7 @@ impl BsvService {
 
             // Create scriptSig
             let mut sig_bytes = signature.serialize_der().to_vec();
-            sig_bytes.push(0x41); // SIGHASH_ALL | SIGHASH_FORKID
-
-            let pubkey_bytes = public_key.serialize();
+            sig_bytes.push(self.sighash_type() as u8);
 
             let mut script_sig = Vec::new();
             Self::push_data(&mut script_sig, &sig_bytes);
@@ -401,7 +625,7 @@ impl BsvService {
         // Outputs
         Self::write_varint(&mut signed_tx, outputs.len() as u64);
         for (script, satoshis) in outputs {
-            signed_tx.extend_from_slice(&satoshis.to_le_bytes());
+            signed_tx.extend_from_slice(&satoshis.to_sat().to_le_bytes());
             Self::write_varint(&mut signed_tx, script.len() as u64);
             signed_tx.extend_from_slice(script);
         }
@@ -418,9 +642,66 @@ impl BsvService {
         input_index: usize,
         script_pubkey: &[u8],
         utxos: &[(String, u32, i64, Vec<u8>)],
-        outputs: &[(Vec<u8>, i64)],
+        outputs: &[(Vec<u8>, Amount)],
     ) -> Result<[u8; 32], String> {
-        // BIP143 sighash for BSV (SIGHASH_ALL | SIGHASH_FORKID)
+        let preimage = if self.use_forkid {
+            Self::forkid_sighash_preimage(input_index, script_pubkey, utxos, outputs)?
+        } else {
+            Self::legacy_sighash_preimage(input_index, script_pubkey, utxos, outputs)?
+        };
+        Ok(Self::double_sha256(&preimage))
+    }
+
+    /// Original (pre-FORKID) SIGHASH_ALL preimage: the transaction with every
+    /// scriptSig emptied except the signed input's, which carries the scriptCode
+    pub fn legacy_sighash_preimage(
+        input_index: usize,
+        script_pubkey: &[u8],
+        utxos: &[(String, u32, i64, Vec<u8>)],
+        outputs: &[(Vec<u8>, Amount)],
+    ) -> Result<Vec<u8>, String> {
+        let mut preimage = Vec::new();
+
+        preimage.extend_from_slice(&1u32.to_le_bytes());
+
+        Self::write_varint(&mut preimage, utxos.len() as u64);
+        for (i, (txid, vout, _, _)) in utxos.iter().enumerate() {
+            let txid_bytes = hex::decode(txid).map_err(|e| format!("Invalid txid: {}", e))?;
+            let mut reversed = txid_bytes.clone();
+            reversed.reverse();
+            preimage.extend_from_slice(&reversed);
+            preimage.extend_from_slice(&vout.to_le_bytes());
+
+            if i == input_index {
+                Self::write_varint(&mut preimage, script_pubkey.len() as u64);
+                preimage.extend_from_slice(script_pubkey);
+            } else {
+                preimage.push(0x00);
+            }
+
+            preimage.extend_from_slice(&0xffffffffu32.to_le_bytes());
+        }
+
+        Self::write_varint(&mut preimage, outputs.len() as u64);
+        for (script, sats) in outputs {
+            preimage.extend_from_slice(&sats.to_sat().to_le_bytes());
+            Self::write_varint(&mut preimage, script.len() as u64);
+            preimage.extend_from_slice(script);
+        }
+
+        preimage.extend_from_slice(&0u32.to_le_bytes());
+        preimage.extend_from_slice(&SIGHASH_ALL.to_le_bytes());
+
+        Ok(preimage)
+    }
+
+    /// BIP143 preimage for BSV (SIGHASH_ALL | SIGHASH_FORKID)
+    pub fn forkid_sighash_preimage(
+        input_index: usize,
+        script_pubkey: &[u8],
+        utxos: &[(String, u32, i64, Vec<u8>)],
+        outputs: &[(Vec<u8>, Amount)],
+    ) -> Result<Vec<u8>, String> {
         let mut preimage = Vec::new();
 
         // 1. nVersion
@@ -468,7 +749,7 @@ impl BsvService {
         // 8. hashOutputs
         let mut outputs_data = Vec::new();
         for (script, sats) in outputs {
-            outputs_data.extend_from_slice(&sats.to_le_bytes());
+            outputs_data.extend_from_slice(&sats.to_sat().to_le_bytes());
             Self::write_varint(&mut outputs_data, script.len() as u64);
             outputs_data.extend_from_slice(script);
         }
@@ -479,14 +760,14 @@ impl BsvService {
         preimage.extend_from_slice(&0u32.to_le_bytes());
 
         // 10. sighash type (SIGHASH_ALL | SIGHASH_FORKID = 0x41)
-        preimage.extend_from_slice(&0x41u32.to_le_bytes());
+        preimage.extend_from_slice(&(SIGHASH_ALL | SIGHASH_FORKID).to_le_bytes());
 
-        Ok(Self::double_sha256(&preimage))
+        Ok(preimage)
     }
 
     fn double_sha256(data: &[u8]) -> [u8; 32] {
         let hash1 = Sha256::digest(data);
-        let hash2 = Sha256::digest(&hash1);
+        let hash2 = Sha256::digest(hash1);
         let mut result = [0u8; 32];
         result.copy_from_slice(&hash2);
         result
@@ -514,10 +795,10 @@ impl BsvService {
     ///   OP_FALSE (0x00)
     ///   OP_IF (0x63)
     ///     PUSHDATA "flacstore-chunk"
-    ///     PUSHDATA <chunk_index as 4-byte LE>
-    ///     PUSHDATA <data>
+    ///     PUSHDATA <chunk_index as decimal text>
+    ///     PUSHDATA <data> (one or more pushes)
     ///   OP_ENDIF (0x68)
-    pub fn create_flac_chunk_script(chunk_index: u32, _total_chunks: u32, data: &[u8]) -> Vec<u8> {
+    pub fn create_flac_chunk_script(&self, chunk_index: u32, _total_chunks: u32, data: &[u8]) -> Vec<u8> {
         let mut script = Vec::new();
 
         // OP_FALSE OP_IF
@@ -531,7 +812,7 @@ impl BsvService {
         Self::push_data(&mut script, chunk_index.to_string().as_bytes());
 
         // Data
-        Self::push_data(&mut script, data);
+        self.push_data_bounded(&mut script, data);
 
         // OP_ENDIF
         script.push(0x68);
@@ -546,7 +827,7 @@ impl BsvService {
     ///     PUSHDATA "coverart"
     ///     PUSHDATA <image_data>
     ///   OP_ENDIF (0x68)
-    pub fn create_cover_image_script(image_data: &[u8]) -> Vec<u8> {
+    pub fn create_cover_image_script(&self, image_data: &[u8]) -> Vec<u8> {
         let mut script = Vec::new();
 
         // OP_FALSE OP_IF
@@ -557,7 +838,7 @@ impl BsvService {
         Self::push_data(&mut script, b"coverart");
 
         // Image data (may need to be split into chunks if large)
-        let max_chunk_size = 520; // Max push data size
+        let max_chunk_size = 520.min(self.max_push_size); // Max push data size
         for chunk in image_data.chunks(max_chunk_size) {
             Self::push_data(&mut script, chunk);
         }
@@ -568,6 +849,88 @@ impl BsvService {
         script
     }
 
+    /// Create a lyrics script for lyrics too long to keep in the track metadata
+    /// Format:
+    ///   OP_FALSE (0x00)
+    ///   OP_IF (0x63)
+    ///     PUSHDATA "flacstore-lyrics"
+    ///     PUSHDATA <lyrics format: "lrc" or "plain">
+    ///     PUSHDATA <UTF-8 lyrics> (one or more pushes)
+    ///   OP_ENDIF (0x68)
+    pub fn create_lyrics_script(&self, lyrics: &str) -> Vec<u8> {
+        let mut script = Vec::new();
+
+        // OP_FALSE OP_IF
+        script.push(0x00); // OP_FALSE
+        script.push(0x63); // OP_IF
+
+        Self::push_data(&mut script, b"flacstore-lyrics");
+        Self::push_data(&mut script, lyrics::detect_format(lyrics).as_str().as_bytes());
+        self.push_data_bounded(&mut script, lyrics.as_bytes());
+
+        // OP_ENDIF
+        script.push(0x68);
+
+        script
+    }
+
+    /// Whether lyrics of `lyrics_bytes` go in their own transaction
+    pub fn lyrics_need_own_tx(&self, lyrics_bytes: usize) -> bool {
+        lyrics_bytes > self.lyrics_inline_max_bytes
+    }
+
+    /// Fee and data output of the lyrics transaction an upload with
+    /// `lyrics_bytes` of lyrics needs; zero when they stay inline
+    pub fn lyrics_tx_cost(&self, lyrics_bytes: usize) -> Amount {
+        if !self.lyrics_need_own_tx(lyrics_bytes) {
+            return Amount::ZERO;
+        }
+        let tx_size = SIDE_TX_OVERHEAD + lyrics_bytes + self.push_overhead(lyrics_bytes);
+        self.fee_for_size(tx_size).saturating_add(self.data_output_satoshis)
+    }
+
+    /// Extra fee for the cover thumbnail of an upload: the bytes it adds to
+    /// the track metadata, or its own coverart transaction when too large
+    /// to keep inline
+    pub fn thumb_cost(&self, thumb_bytes: usize) -> Amount {
+        if thumb_bytes == 0 {
+            return Amount::ZERO;
+        }
+        if thumbnail::fits_inline(thumb_bytes) {
+            // Base64 plus the label push
+            let inline_bytes = thumb_bytes.div_ceil(3) * 4 + 20;
+            return self.fee_for_size(inline_bytes + self.push_overhead(inline_bytes));
+        }
+        let tx_size = SIDE_TX_OVERHEAD + thumb_bytes + self.push_overhead(thumb_bytes);
+        self.fee_for_size(tx_size).saturating_add(self.data_output_satoshis)
+    }
+
+    /// Create a cover update record that links an existing manifest to a
+    /// newer cover image, for tracks whose cover was added after upload
+    /// Format:
+    ///   OP_FALSE (0x00)
+    ///   OP_IF (0x63)
+    ///     PUSHDATA "flacstore-coverupdate"
+    ///     PUSHDATA <manifest_txid>
+    ///     PUSHDATA <cover_txid>
+    ///   OP_ENDIF (0x68)
+    pub fn create_cover_update_script(manifest_txid: &str, cover_txid: &str) -> Vec<u8> {
+        let mut script = Vec::new();
+
+        // OP_FALSE OP_IF
+        script.push(0x00); // OP_FALSE
+        script.push(0x63); // OP_IF
+
+        Self::push_data(&mut script, b"flacstore-coverupdate");
+        Self::push_data(&mut script, manifest_txid.as_bytes());
+        Self::push_data(&mut script, cover_txid.as_bytes());
+
+        // OP_ENDIF
+        script.push(0x68);
+
+        script
+    }
+
     /// Create FLAC manifest script that references chunk transactions
     /// Format:
     ///   OP_FALSE (0x00)
@@ -579,14 +942,23 @@ impl BsvService {
     ///     PUSHDATA <chunk_txid_2>
     ///     ...
     ///   OP_ENDIF (0x68)
+    ///
+    /// `chunk_hashes` (hex SHA-256 of each chunk's data, in chunk order) go
+    /// in the metadata JSON, so a download can tell which chunk is corrupt.
+    #[allow(clippy::too_many_arguments)]
     pub fn create_flac_manifest_script(
         filename: &str,
         file_size: usize,
         chunk_txids: &[String],
+        chunk_hashes: &[String],
         track_title: Option<&str>,
         artist_name: Option<&str>,
         lyrics: Option<&str>,
+        lyrics_txid: Option<&str>,
         cover_txid: Option<&str>,
+        thumb: Option<&CoverThumb>,
+        royalty: Option<(&str, i64)>,
+        layout: ManifestLayout,
     ) -> Vec<u8> {
         let mut script = Vec::new();
 
@@ -600,18 +972,74 @@ impl BsvService {
         // Filename
         Self::push_data(&mut script, filename.as_bytes());
 
-        // Metadata JSON (includes title, artist, lyrics, and cover_txid)
-        let metadata = serde_json::json!({
-            "size": file_size,
-            "chunks": chunk_txids.len(),
-            "version": "1.2",
-            "mime": "audio/flac",
-            "title": track_title.unwrap_or(""),
-            "artist": artist_name.unwrap_or(""),
-            "lyrics": lyrics.unwrap_or(""),
-            "cover_txid": cover_txid.unwrap_or("")
-        }).to_string();
-        Self::push_data(&mut script, metadata.as_bytes());
+        // lyrics_format tells the player whether the lyrics carry LRC timestamps.
+        // Lyrics inscribed in their own transaction are only referenced here.
+        let lyrics_format = lyrics.map(lyrics::detect_format).unwrap_or(LyricsFormat::Plain);
+        let inline_lyrics = if lyrics_txid.is_some() { None } else { lyrics };
+        let track_fields = [
+            ("title", track_title.unwrap_or("")),
+            ("artist", artist_name.unwrap_or("")),
+            ("lyrics", inline_lyrics.unwrap_or("")),
+            ("lyrics_format", lyrics_format.as_str()),
+            ("cover_txid", cover_txid.unwrap_or("")),
+        ];
+
+        match layout {
+            ManifestLayout::Json => {
+                // Metadata JSON (includes title, artist, lyrics, and cover_txid)
+                let mut metadata = serde_json::json!({
+                    "size": file_size,
+                    "chunks": chunk_txids.len(),
+                    "version": "1.3",
+                    "mime": "audio/flac",
+                });
+                if !chunk_hashes.is_empty() {
+                    metadata["chunk_hashes"] = serde_json::Value::from(chunk_hashes);
+                }
+                for (label, value) in track_fields {
+                    metadata[label] = serde_json::Value::from(value);
+                }
+                if let Some(txid) = lyrics_txid {
+                    metadata["lyrics_txid"] = serde_json::Value::from(txid);
+                }
+                if let Some((label, value)) = thumb.map(CoverThumb::manifest_field) {
+                    metadata[label] = serde_json::Value::from(value);
+                }
+                if let Some((address, satoshis)) = royalty {
+                    metadata["royalty_address"] = serde_json::Value::from(address);
+                    metadata["royalty_satoshis"] = serde_json::Value::from(satoshis);
+                }
+                Self::push_data(&mut script, metadata.to_string().as_bytes());
+            }
+            ManifestLayout::Labeled => {
+                // File metadata stays in JSON; track metadata follows as
+                // label/value pushes so indexers can read it without JSON
+                let mut metadata = serde_json::json!({
+                    "size": file_size,
+                    "chunks": chunk_txids.len(),
+                    "version": "1.4",
+                    "mime": "audio/flac",
+                    "layout": "labeled",
+                });
+                if !chunk_hashes.is_empty() {
+                    metadata["chunk_hashes"] = serde_json::Value::from(chunk_hashes);
+                }
+                if let Some((address, satoshis)) = royalty {
+                    metadata["royalty_address"] = serde_json::Value::from(address);
+                    metadata["royalty_satoshis"] = serde_json::Value::from(satoshis);
+                }
+                Self::push_data(&mut script, metadata.to_string().as_bytes());
+
+                let lyrics_txid_field = ("lyrics_txid", lyrics_txid.unwrap_or(""));
+                let thumb_field = thumb.map(CoverThumb::manifest_field);
+                for (label, value) in track_fields.into_iter().chain([lyrics_txid_field]).chain(thumb_field) {
+                    if !value.is_empty() {
+                        Self::push_data(&mut script, label.as_bytes());
+                        Self::push_data(&mut script, value.as_bytes());
+                    }
+                }
+            }
+        }
 
         // Chunk TXIDs
         for txid in chunk_txids {
@@ -626,82 +1054,515 @@ impl BsvService {
 }
 
 
+/// A split the UTXOs it may combine can't fund: what the most UTXOs
+/// allowed hold, and what they would need
+#[derive(Debug, Clone, Copy)]
+pub struct SplitShortfall {
+    pub available: Amount,
+    pub required: Amount,
+}
+
 impl BsvService {
-    /// Create a UTXO split transaction that divides one UTXO into multiple outputs
+    /// Create the UTXO split transactions that divide the wallet's funds into multiple outputs
     /// This is used to prepare for multi-chunk uploads where each chunk needs its own UTXO
-    /// 
-    /// Returns: (raw_tx_hex, Vec<(output_index, satoshis)>)
-    pub fn create_split_transaction(
+    ///
+    /// Up to `max_split_outputs` outputs fit in one transaction. Beyond that
+    /// the outputs are split in batches, each batch funded by an output of a
+    /// split of the batch totals, so every transaction stays within policy.
+    /// Only the first transaction spends `inputs`; see `select_split_inputs`.
+    ///
+    /// The last output (the manifest's) carries `last_output_extra` on top,
+    /// e.g. to fund a royalty output in the manifest transaction
+    pub fn create_split_transactions(
         &self,
         wif: &str,
-        input_txid: &str,
-        input_vout: u32,
-        input_satoshis: i64,
+        inputs: &[(String, u32, i64)],
         script_pubkey: &[u8],
         num_outputs: usize,
-        satoshis_per_output: i64,
-    ) -> Result<String, String> {
-        // Calculate total needed for outputs
-        let total_output = satoshis_per_output * num_outputs as i64;
-        
-        // Estimate transaction size: ~10 bytes overhead + ~148 bytes per input + ~34 bytes per output
-        let tx_size = 10 + 148 + (34 * num_outputs);
-        let fee = (tx_size as f64 * self.fee_rate).ceil() as i64;
-        
-        if input_satoshis < total_output + fee {
-            return Err(format!(
-                "Insufficient funds for split: {} < {} + {}",
-                input_satoshis, total_output, fee
-            ));
-        }
-        
-        // Create outputs
-        let mut outputs: Vec<(Vec<u8>, i64)> = Vec::new();
-        for _ in 0..num_outputs {
-            outputs.push((script_pubkey.to_vec(), satoshis_per_output));
-        }
-        
+        satoshis_per_output: Amount,
+        last_output_extra: Amount,
+    ) -> Result<SplitPlan, String> {
+        let mut values = vec![satoshis_per_output; num_outputs];
+        if let Some(last) = values.last_mut() {
+            *last = last.checked_add(last_output_extra)?;
+        }
+
+        let mut transactions = Vec::new();
+        let inputs = inputs
+            .iter()
+            .map(|(txid, vout, satoshis)| Ok((txid.clone(), *vout, Amount::try_from(*satoshis)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let mut change = None;
+        let outputs = self.build_split(&mut transactions, &mut change, wif, inputs, script_pubkey, &values)?;
+        Ok(SplitPlan { transactions, outputs, change })
+    }
+
+    /// The UTXOs to fund a split of `num_outputs` outputs from, largest
+    /// first and no more than needed, so the largest file an upload can
+    /// store is bounded by the wallet's balance rather than its biggest
+    /// UTXO.
+    pub fn select_split_inputs(
+        &self,
+        utxos: &[(String, u32, i64)],
+        num_outputs: usize,
+        satoshis_per_output: Amount,
+        last_output_extra: Amount,
+    ) -> Result<Vec<(String, u32, i64)>, SplitShortfall> {
+        let mut candidates = utxos.to_vec();
+        candidates.sort_by_key(|(_, _, satoshis)| std::cmp::Reverse(*satoshis));
+        candidates.truncate(self.max_split_inputs);
+
+        let outputs_total = satoshis_per_output
+            .saturating_mul(num_outputs as u64)
+            .saturating_add(last_output_extra);
+        let mut available = Amount::ZERO;
+        let mut required = outputs_total;
+        for (count, (_, _, satoshis)) in candidates.iter().enumerate() {
+            available = available.saturating_add(Amount::try_from(*satoshis).unwrap_or(Amount::ZERO));
+            required = outputs_total.saturating_add(self.split_tree_fee(count + 1, num_outputs));
+            if available >= required {
+                candidates.truncate(count + 1);
+                return Ok(candidates);
+            }
+        }
+        Err(SplitShortfall { available, required })
+    }
+
+    /// Split `inputs` into `values`, appending the transactions parents first.
+    /// Change above dust goes back to `script_pubkey` after the outputs and
+    /// is noted in `change`.
+    fn build_split(
+        &self,
+        transactions: &mut Vec<(String, String)>,
+        change_output: &mut Option<(String, u32, Amount)>,
+        wif: &str,
+        inputs: Vec<(String, u32, Amount)>,
+        script_pubkey: &[u8],
+        values: &[Amount],
+    ) -> Result<Vec<(String, u32)>, String> {
+        if values.len() > self.max_split_outputs {
+            let batches: Vec<&[Amount]> = values.chunks(self.max_split_outputs).collect();
+            let batch_totals = batches
+                .iter()
+                .map(|batch| Ok(Amount::sum(batch.iter().copied())?.checked_add(self.calculate_split_fee(batch.len()))?))
+                .collect::<Result<Vec<Amount>, String>>()?;
+            let funding = self.build_split(transactions, change_output, wif, inputs, script_pubkey, &batch_totals)?;
+
+            let mut outputs = Vec::with_capacity(values.len());
+            for ((batch, total), (txid, vout)) in batches.into_iter().zip(batch_totals).zip(funding) {
+                outputs.extend(self.build_split(transactions, change_output, wif, vec![(txid, vout, total)], script_pubkey, batch)?);
+            }
+            return Ok(outputs);
+        }
+
+        let input_satoshis = Amount::sum(inputs.iter().map(|(_, _, satoshis)| *satoshis))?;
+        let total_output = Amount::sum(values.iter().copied())?;
+        let fee = self.split_fee(inputs.len(), values.len());
+        let change = input_satoshis
+            .checked_sub(total_output.checked_add(fee)?)
+            .map_err(|_| format!("Insufficient funds for split: {} < {} + {}", input_satoshis, total_output, fee))?;
+
+        let mut outputs: Vec<(Vec<u8>, Amount)> = values.iter().map(|value| (script_pubkey.to_vec(), *value)).collect();
+
         // Add change output if there's any remaining
-        let change = input_satoshis - total_output - fee;
-        if change > 546 {
+        if change > self.dust_limit {
             outputs.push((script_pubkey.to_vec(), change));
         }
-        
-        // Create the transaction
-        let utxos = vec![(input_txid.to_string(), input_vout, input_satoshis, script_pubkey.to_vec())];
-        self.create_transaction(wif, &utxos, &outputs)
+
+        let utxos: Vec<_> = inputs
+            .into_iter()
+            .map(|(txid, vout, satoshis)| (txid, vout, satoshis.to_sat_i64(), script_pubkey.to_vec()))
+            .collect();
+        let raw_tx = self.create_transaction(wif, &utxos, &outputs)?;
+        let txid = Self::txid(&raw_tx)?;
+        if change > self.dust_limit && change_output.is_none() {
+            *change_output = Some((txid.clone(), values.len() as u32, change));
+        }
+        transactions.push((txid.clone(), raw_tx));
+        Ok((0..values.len() as u32).map(|vout| (txid.clone(), vout)).collect())
     }
-    
+
+    /// Fee of a split transaction with one input and `num_outputs` outputs
+    pub fn calculate_split_fee(&self, num_outputs: usize) -> Amount {
+        self.split_fee(1, num_outputs)
+    }
+
+    fn split_fee(&self, num_inputs: usize, num_outputs: usize) -> Amount {
+        // Estimate transaction size: ~10 bytes overhead + ~148 bytes per input + ~34 bytes per output
+        let tx_size = 10 + 148 * num_inputs + (34 * num_outputs);
+        self.fee_for_size(tx_size)
+    }
+
+    /// Fee of every split transaction needed for `num_outputs` outputs
+    pub fn calculate_split_tree_fee(&self, num_outputs: usize) -> Amount {
+        self.split_tree_fee(1, num_outputs)
+    }
+
+    /// Split tree fee when the first transaction spends `num_inputs` UTXOs
+    fn split_tree_fee(&self, num_inputs: usize, num_outputs: usize) -> Amount {
+        if num_outputs <= self.max_split_outputs {
+            return self.split_fee(num_inputs, num_outputs);
+        }
+        let batches = num_outputs.div_ceil(self.max_split_outputs);
+        let last_batch = num_outputs - (batches - 1) * self.max_split_outputs;
+        self.calculate_split_fee(self.max_split_outputs)
+            .saturating_mul((batches - 1) as u64)
+            .saturating_add(self.calculate_split_fee(last_batch))
+            .saturating_add(self.split_tree_fee(num_inputs, batches))
+    }
+
+    /// Outputs of the widest split transaction for `num_outputs` outputs, change included
+    pub fn split_tx_output_count(&self, num_outputs: usize) -> usize {
+        num_outputs.min(self.max_split_outputs) + 1
+    }
+
+    /// Number of split transactions needed for `num_outputs` outputs
+    pub fn split_transaction_count(&self, num_outputs: usize) -> usize {
+        if num_outputs <= self.max_split_outputs {
+            return 1;
+        }
+        let batches = num_outputs.div_ceil(self.max_split_outputs);
+        batches + self.split_transaction_count(batches)
+    }
+
     /// Calculate the required satoshis per output for a split transaction
-    /// Each output needs to cover the chunk transaction fee + 1 satoshi for data output
-    pub fn calculate_chunk_output_satoshis(&self, chunk_size: usize) -> i64 {
+    /// Each output needs to cover the chunk transaction fee + the data output value
+    pub fn calculate_chunk_output_satoshis(&self, chunk_size: usize) -> Amount {
         // Chunk transaction size: ~150 bytes overhead + chunk data size
-        let chunk_tx_size = 200 + chunk_size;
-        let chunk_fee = (chunk_tx_size as f64 * self.fee_rate).ceil() as i64;
-        
-        // Need fee + 1 satoshi for data output + small buffer
-        chunk_fee + 10
+        let chunk_tx_size = CHUNK_TX_OVERHEAD + chunk_size + self.push_overhead(chunk_size);
+        let chunk_fee = self.fee_for_size(chunk_tx_size);
+
+        // Need fee + data output value + small buffer; the split output
+        // itself is P2PKH, so it must also clear the dust limit
+        let value = chunk_fee
+            .saturating_add(self.data_output_satoshis)
+            .saturating_add(CHUNK_OUTPUT_BUFFER);
+        std::cmp::max(value, self.dust_limit)
     }
-    
+
     /// Calculate total cost for multi-chunk upload
     /// Returns (total_satoshis, satoshis_per_chunk, num_chunks)
-    pub fn calculate_multi_chunk_cost(&self, file_size: usize, chunk_size: usize) -> (i64, i64, usize) {
-        let num_chunks = (file_size + chunk_size - 1) / chunk_size;
+    pub fn calculate_multi_chunk_cost(&self, file_size: usize, chunk_size: usize) -> (Amount, Amount, usize) {
+        let num_chunks = file_size.div_ceil(chunk_size);
         let satoshis_per_chunk = self.calculate_chunk_output_satoshis(chunk_size);
-        
+
         // Number of outputs in split transaction: num_chunks + 1 (for manifest)
         let num_outputs = num_chunks + 1;
-        
+
         // Split transaction cost
-        let split_tx_size = 10 + 148 + (34 * num_outputs);
-        let split_fee = (split_tx_size as f64 * self.fee_rate).ceil() as i64;
-        
+        let split_fee = self.calculate_split_tree_fee(num_outputs);
+
         // Total output value needed for split transaction
-        let split_output_total = satoshis_per_chunk * num_outputs as i64;
-        
+        let split_output_total = satoshis_per_chunk.saturating_mul(num_outputs as u64);
+
         // Total = split outputs + split fee
-        let total = split_output_total + split_fee;
-        
+        let total = split_output_total.saturating_add(split_fee);
+
         (total, satoshis_per_chunk, num_chunks)
     }
+
+    /// Storage strategy and quote for a FLAC upload of `file_size` bytes.
+    /// Prepare, plan and processing all decide by this, so an upload is
+    /// stored the way it was priced. Either quote carries a 20% buffer.
+    pub fn plan_flac_upload(&self, file_size: usize, network: Network) -> FlacUploadPlan {
+        let chunk_size = self.flac_chunk_size(network);
+        let fits_one_tx = file_size <= self.flac_single_tx_max_bytes
+            && self
+                .provider_tx_limits
+                .for_network(network)
+                .is_none_or(|limit| self.flac_single_tx_size(file_size) <= limit);
+        let (chunked, chunk_count, cost, tx_outputs) = if fits_one_tx {
+            // Data output and change
+            (false, 1, self.calculate_flac_single_tx_cost(file_size), 2)
+        } else {
+            let (total, _, chunk_count) = self.calculate_multi_chunk_cost(file_size, chunk_size);
+            // One split output per chunk plus the manifest's
+            (true, chunk_count, total, self.split_tx_output_count(chunk_count + 1))
+        };
+        let buffer = Amount::from_sat(cost.to_sat().div_ceil(5)).unwrap_or(Amount::MAX);
+        FlacUploadPlan {
+            chunked,
+            chunk_count,
+            chunk_size,
+            cost: cost.saturating_add(buffer),
+            tx_outputs,
+        }
+    }
+
+    /// Most data a chunk transaction can carry within the transaction limit
+    /// of the provider broadcasting on `network`; FLAC_CHUNK_SIZE when none
+    /// is configured
+    pub fn flac_chunk_size(&self, network: Network) -> usize {
+        let Some(limit) = self.provider_tx_limits.for_network(network) else {
+            return FLAC_CHUNK_SIZE;
+        };
+        // Less data never needs more push opcodes, so reserving the opcodes
+        // for all of the room always fits
+        let room = limit.saturating_sub(CHUNK_TX_OVERHEAD);
+        room.saturating_sub(self.push_overhead(room)).max(MIN_FLAC_CHUNK_SIZE)
+    }
+
+    /// Chunk size to retry with after a provider rejected chunks of
+    /// `chunk_size` as too large; None once halving would go below the
+    /// smallest chunk
+    pub fn halved_chunk_size(chunk_size: usize) -> Option<usize> {
+        Some(chunk_size / 2).filter(|size| *size >= MIN_FLAC_CHUNK_SIZE)
+    }
+
+    /// Size of a single-transaction FLAC upload, with the script framing and a change output
+    fn flac_single_tx_size(&self, file_size: usize) -> usize {
+        150 + 34 + FLAC_STORE_HEADER_ALLOWANCE + file_size + self.push_overhead(file_size)
+    }
+
+    /// Fee and data output of a single-transaction FLAC upload, with room for
+    /// the script framing, a change output and the same slack as a chunk
+    fn calculate_flac_single_tx_cost(&self, file_size: usize) -> Amount {
+        let tx_size = self.flac_single_tx_size(file_size);
+        let cost = self
+            .fee_for_size(tx_size)
+            .saturating_add(self.data_output_satoshis)
+            .saturating_add(CHUNK_OUTPUT_BUFFER);
+        std::cmp::max(cost, self.dust_limit)
+    }
+}
+
+#[cfg(test)]
+impl BsvService {
+    /// Service with the default limits at 1 sat/byte and no provider limits
+    pub fn for_tests() -> Self {
+        BsvService::new(
+            None,
+            1.0,
+            true,
+            DEFAULT_MAX_PUSH_SIZE,
+            Amount::from_sat_const(DEFAULT_DATA_OUTPUT_SATOSHIS),
+            Amount::from_sat_const(DEFAULT_DUST_LIMIT_SATOSHIS),
+            DEFAULT_MAX_SPLIT_OUTPUTS,
+            DEFAULT_MAX_SPLIT_INPUTS,
+            DEFAULT_FLAC_SINGLE_TX_MAX_BYTES,
+            DEFAULT_LYRICS_INLINE_MAX_BYTES,
+            ProviderTxLimits::default(),
+        )
+    }
+
+    /// Signed transaction paying `outputs` from a throwaway key's made-up UTXO
+    pub fn test_transaction(&self, outputs: &[(Vec<u8>, Amount)]) -> String {
+        let (wif, address) = Self::generate_keypair(Network::Mainnet);
+        let script = Self::create_p2pkh_script(&address).unwrap();
+        let utxo = ("11".repeat(32), 0, 10_000_000, script);
+        self.create_transaction(&wif, &[utxo], outputs).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Spend = (String, Vec<(String, u32, i64, Vec<u8>)>, Vec<(Vec<u8>, Amount)>);
+
+    /// One made-up input and one output, and a throwaway key to sign it with
+    fn fixed_spend() -> Spend {
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let script = BsvService::create_p2pkh_script(&address).unwrap();
+        let utxos = vec![("11".repeat(32), 3, 50_000, script.clone())];
+        let outputs = vec![(script, Amount::from_sat_const(40_000))];
+        (wif, utxos, outputs)
+    }
+
+    #[test]
+    fn forkid_and_legacy_preimages_differ() {
+        let (_, utxos, outputs) = fixed_spend();
+        let script_code = &utxos[0].3;
+        let forkid = BsvService::forkid_sighash_preimage(0, script_code, &utxos, &outputs).unwrap();
+        let legacy = BsvService::legacy_sighash_preimage(0, script_code, &utxos, &outputs).unwrap();
+
+        // BIP143: version, two hashes, outpoint, scriptCode, value, sequence,
+        // hashOutputs, locktime and type; it alone commits to the input's value
+        assert_eq!(forkid.len(), 4 + 32 + 32 + 36 + 1 + 25 + 8 + 4 + 32 + 4 + 4);
+        assert!(forkid.ends_with(&0x41u32.to_le_bytes()));
+        assert!(forkid.windows(8).any(|w| w == 50_000i64.to_le_bytes()));
+
+        // Legacy: the unsigned transaction with the scriptCode as the input's
+        // scriptSig, outputs in the clear, then the type
+        assert_eq!(legacy.len(), 4 + 1 + 36 + 1 + 25 + 4 + 1 + 8 + 1 + 25 + 4 + 4);
+        assert!(legacy.ends_with(&0x01u32.to_le_bytes()));
+        assert!(legacy.windows(25).filter(|w| *w == script_code.as_slice()).count() == 2);
+        assert!(!legacy.windows(8).any(|w| w == 50_000i64.to_le_bytes()));
+
+        assert_ne!(BsvService::double_sha256(&forkid), BsvService::double_sha256(&legacy));
+    }
+
+    /// Public key of the first input's scriptSig, once its signature is
+    /// checked against the sighash of `utxos` spent to `outputs`
+    fn verified_signer(tx: &str, utxos: &[(String, u32, i64, Vec<u8>)], outputs: &[(Vec<u8>, Amount)], use_forkid: bool) -> Vec<u8> {
+        let tx = crate::services::tx_parse::parse_transaction(tx).unwrap();
+
+        // scriptSig: <DER signature + sighash type> <public key>
+        let script_sig = &tx.inputs[0].script_sig;
+        let sig_len = script_sig[0] as usize;
+        let (signature, sighash_type) = script_sig[1..sig_len + 1].split_at(sig_len - 1);
+        let public_key = &script_sig[sig_len + 2..];
+        assert_eq!(script_sig[sig_len + 1] as usize, public_key.len());
+        assert_eq!(sighash_type, [if use_forkid { 0x41 } else { 0x01 }]);
+
+        let preimage = if use_forkid {
+            BsvService::forkid_sighash_preimage(0, &utxos[0].3, utxos, outputs)
+        } else {
+            BsvService::legacy_sighash_preimage(0, &utxos[0].3, utxos, outputs)
+        };
+        let digest = Message::from_digest_slice(&BsvService::double_sha256(&preimage.unwrap())).unwrap();
+        let signature = secp256k1::ecdsa::Signature::from_der(signature).unwrap();
+        let key = PublicKey::from_slice(public_key).unwrap();
+        assert!(Secp256k1::new().verify_ecdsa(&digest, &signature, &key).is_ok(), "forkid {}", use_forkid);
+        public_key.to_vec()
+    }
+
+    #[test]
+    fn signatures_carry_and_sign_the_configured_sighash() {
+        let (wif, utxos, outputs) = fixed_spend();
+        for use_forkid in [true, false] {
+            let mut bsv = BsvService::for_tests();
+            bsv.use_forkid = use_forkid;
+            let tx = bsv.create_transaction(&wif, &utxos, &outputs).unwrap();
+            verified_signer(&tx, &utxos, &outputs, use_forkid);
+        }
+    }
+
+    #[test]
+    fn uncompressed_wifs_control_their_own_address() {
+        let (compressed_wif, compressed_address) = BsvService::generate_keypair(Network::Mainnet);
+        // The same secret without the compression flag
+        let mut payload = bs58::decode(&compressed_wif).into_vec().unwrap()[..33].to_vec();
+        payload.extend_from_slice(&BsvService::double_sha256(&payload)[..4]);
+        let uncompressed_wif = bs58::encode(payload).into_string();
+
+        let (secret_key, compressed) = BsvService::decode_wif(&uncompressed_wif).unwrap();
+        assert!(!compressed);
+        assert_eq!(secret_key, BsvService::wif_to_secret_key(&compressed_wif).unwrap());
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+        let uncompressed_address = BsvService::wif_to_address(&uncompressed_wif, Network::Mainnet).unwrap();
+        assert_eq!(BsvService::wif_to_address(&compressed_wif, Network::Mainnet).unwrap(), compressed_address);
+        assert_eq!(
+            uncompressed_address,
+            BsvService::pubkey_bytes_to_address(&public_key.serialize_uncompressed(), Network::Mainnet)
+        );
+        assert_ne!(uncompressed_address, compressed_address);
+
+        // Each spends its own address's coins with the matching public key
+        let bsv = BsvService::for_tests();
+        for (wif, address, public_key) in [
+            (&compressed_wif, &compressed_address, public_key.serialize().to_vec()),
+            (&uncompressed_wif, &uncompressed_address, public_key.serialize_uncompressed().to_vec()),
+        ] {
+            let script = BsvService::create_p2pkh_script(address).unwrap();
+            let utxos = vec![("11".repeat(32), 0, 50_000, script.clone())];
+            let outputs = vec![(script, Amount::from_sat_const(40_000))];
+            let tx = bsv.create_transaction(wif, &utxos, &outputs).unwrap();
+            assert_eq!(verified_signer(&tx, &utxos, &outputs, true), public_key);
+        }
+    }
+
+    #[test]
+    fn large_data_is_pushed_in_bounded_pushes() {
+        let mut bsv = BsvService::for_tests();
+        bsv.max_push_size = 1000;
+        let data = vec![7u8; 3500];
+        let lyrics = "a".repeat(3500);
+        // (script, pushes before the data)
+        let scripts = [
+            (bsv.create_upfile_script("audio/flac", "a.flac", &data), 3),
+            (bsv.create_flac_store_script(b"flacstore", b"audio/flac", b"{}", std::slice::from_ref(&data)), 3),
+            (bsv.create_flac_chunk_script(0, 1, &data), 2),
+            (bsv.create_lyrics_script(&lyrics), 2),
+        ];
+        for (i, (script, fields)) in scripts.iter().enumerate() {
+            // Past OP_FALSE and OP_RETURN or OP_IF
+            let pushes: Vec<&[u8]> = crate::services::tx_parse::PushDataIter::new(&script[2..]).map(Option::unwrap).collect();
+            let sizes: Vec<usize> = pushes[*fields..].iter().map(|push| push.len()).collect();
+            assert_eq!(sizes, [1000, 1000, 1000, 500], "script {}", i);
+            assert!(!script.contains(&0x4e), "script {} has an OP_PUSHDATA4", i);
+        }
+
+        // Cover images keep to 520-byte pushes even under a larger cap
+        let script = bsv.create_cover_image_script(&data);
+        let pushes: Vec<&[u8]> = crate::services::tx_parse::PushDataIter::new(&script[2..]).map(Option::unwrap).collect();
+        assert!(pushes[1..].iter().all(|push| push.len() <= 520));
+        assert_eq!(pushes[1..].concat(), data);
+    }
+
+    #[test]
+    fn split_for_600_chunks_is_batched_and_fully_funded() {
+        use crate::services::tx_parse::parse_transaction;
+        use std::collections::HashMap;
+
+        let bsv = BsvService::for_tests();
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let script = BsvService::create_p2pkh_script(&address).unwrap();
+        // 600 chunks and the manifest
+        let (num_outputs, per_output, extra) = (601, Amount::from_sat_const(700), Amount::from_sat_const(5000));
+        let fees = bsv.calculate_split_tree_fee(num_outputs);
+        let funding = per_output.saturating_mul(num_outputs as u64).saturating_add(extra).saturating_add(fees);
+        let input = ("11".repeat(32), 2, funding.to_sat_i64());
+
+        let plan = bsv
+            .create_split_transactions(&wif, std::slice::from_ref(&input), &script, num_outputs, per_output, extra)
+            .unwrap();
+        // A split of the three batch totals, then batches of 250, 250 and 101
+        assert_eq!(plan.transactions.len(), 4);
+        assert_eq!(plan.transactions.len(), bsv.split_transaction_count(num_outputs));
+        assert!(plan.change.is_none());
+
+        // Parents come first, and only the first transaction spends the wallet
+        let mut spendable: HashMap<(String, u32), i64> = HashMap::from([((input.0.clone(), input.1), input.2)]);
+        let mut fees_paid = 0;
+        for (i, (txid, raw_tx)) in plan.transactions.iter().enumerate() {
+            assert_eq!(&BsvService::txid(raw_tx).unwrap(), txid);
+            let tx = parse_transaction(raw_tx).unwrap();
+            assert!(tx.outputs.len() <= bsv.split_tx_output_count(num_outputs), "transaction {}", i);
+            let spent: i64 = tx
+                .inputs
+                .iter()
+                .map(|input| spendable.remove(&(input.prev_txid.clone(), input.prev_vout)).expect("spends an earlier output"))
+                .sum();
+            let paid: i64 = tx.outputs.iter().map(|output| output.satoshis).sum();
+            assert!(spent >= paid, "transaction {}", i);
+            fees_paid += spent - paid;
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                spendable.insert((txid.clone(), vout as u32), output.satoshis);
+            }
+        }
+        assert_eq!(fees_paid, fees.to_sat_i64());
+
+        // What is left unspent is exactly the requested outputs
+        assert_eq!(plan.outputs.len(), num_outputs);
+        let values: Vec<i64> = plan.outputs.iter().map(|output| spendable.remove(output).unwrap()).collect();
+        assert!(spendable.is_empty());
+        assert!(values[..600].iter().all(|value| *value == 700));
+        assert_eq!(values[600], 5700);
+    }
+
+    #[test]
+    fn smaller_provider_limits_give_smaller_chunks() {
+        let file_size = 3 * 1024 * 1024;
+        let mut bsv = BsvService::for_tests();
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let script_pubkey = BsvService::create_p2pkh_script(&address).unwrap();
+
+        let mut last = bsv.plan_flac_upload(file_size, Network::Mainnet);
+        assert_eq!((last.chunk_size, last.chunk_count), (FLAC_CHUNK_SIZE, 3));
+        for limit in [500_000, 100_000, 10_000] {
+            bsv.provider_tx_limits.bitails = Some(limit);
+            let plan = bsv.plan_flac_upload(file_size, Network::Mainnet);
+            assert!(plan.chunk_size < last.chunk_size && plan.chunk_count > last.chunk_count, "limit {}", limit);
+            assert_eq!(plan.chunk_count, file_size.div_ceil(plan.chunk_size));
+            // A full chunk transaction, as the upload builds it, fits the limit
+            let script = bsv.create_flac_chunk_script(0, plan.chunk_count as u32, &vec![0xab; plan.chunk_size]);
+            let utxo = ("11".repeat(32), 0, 10_000_000, script_pubkey.clone());
+            let tx = bsv.create_transaction(&wif, &[utxo], &[(script, Amount::from_sat_const(1))]).unwrap();
+            assert!(tx.len() / 2 <= limit, "limit {}: {} bytes", limit, tx.len() / 2);
+            last = plan;
+        }
+        // Other networks broadcast elsewhere and keep their own limit
+        assert_eq!(bsv.plan_flac_upload(file_size, Network::Testnet).chunk_size, FLAC_CHUNK_SIZE);
+    }
 }
@@ -0,0 +1,58 @@
+// Per-job cancellation tokens
+// Processing loops check their job's token between chunks and stop
+// cooperatively, so no transaction is left half-built.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Default)]
+pub struct JobCancellations {
+    tokens: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl JobCancellations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a token for a job that is starting to run.
+    /// Passing a parent ties the job to it, so cancelling the parent cancels the job too.
+    pub fn register(&self, job_id: &str, parent: Option<&CancellationToken>) -> CancellationToken {
+        let token = match parent {
+            Some(p) => p.child_token(),
+            None => CancellationToken::new(),
+        };
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.insert(job_id.to_string(), token.clone());
+        token
+    }
+
+    /// Drop the token of a job that has finished
+    pub fn remove(&self, job_id: &str) {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.remove(job_id);
+    }
+
+    /// Request cancellation. Returns false if the job isn't running.
+    pub fn cancel(&self, job_id: &str) -> bool {
+        let tokens = self.tokens.lock().unwrap();
+        match tokens.get(job_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn token(&self, job_id: &str) -> Option<CancellationToken> {
+        let tokens = self.tokens.lock().unwrap();
+        tokens.get(job_id).cloned()
+    }
+
+    pub fn is_cancelled(&self, job_id: &str) -> bool {
+        let tokens = self.tokens.lock().unwrap();
+        tokens.get(job_id).map(|t| t.is_cancelled()).unwrap_or(false)
+    }
+}
@@ -0,0 +1,50 @@
+// File content types
+// A filename's extension is whatever the uploader called it, so the type is
+// read from the file's magic bytes first and the extension only decides for
+// formats without a signature, such as plain text.
+
+/// Bytes from the start of a file that are enough to recognize its format
+pub const SNIFF_LEN: usize = 8192;
+
+/// MIME type of a file, from its leading bytes or else its filename
+pub fn detect(data: &[u8], filename: &str) -> String {
+    match infer::get(&data[..data.len().min(SNIFF_LEN)]) {
+        Some(kind) => kind.mime_type().to_string(),
+        None => mime_guess::from_path(filename).first_or_octet_stream().to_string(),
+    }
+}
+
+/// MIME type an upload recorded for its file if it is a well-formed
+/// type/subtype, else the one detected from the file
+pub fn recorded_or_detect(recorded: &str, data: &[u8], filename: &str) -> String {
+    let well_formed = recorded.parse::<mime_guess::Mime>().is_ok()
+        && recorded.bytes().all(|b| b.is_ascii_graphic() || b == b' ');
+    if well_formed {
+        recorded.to_string()
+    } else {
+        detect(data, filename)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0x0D, 0x49, 0x48, 0x44, 0x52];
+
+    #[test]
+    fn magic_bytes_win_over_the_extension() {
+        assert_eq!(detect(PNG, "photo.txt"), "image/png");
+        assert_eq!(detect(b"fLaC\0\0\0\x22", "song.mp3"), "audio/x-flac");
+        // Text has no signature, so the extension decides
+        assert_eq!(detect(b"plain words", "notes.txt"), "text/plain");
+        assert_eq!(detect(b"plain words", "notes"), "application/octet-stream");
+    }
+
+    #[test]
+    fn recorded_type_is_kept_only_when_well_formed() {
+        assert_eq!(recorded_or_detect("audio/flac", PNG, "photo.txt"), "audio/flac");
+        assert_eq!(recorded_or_detect("not a type", PNG, "photo.txt"), "image/png");
+        assert_eq!(recorded_or_detect("text/plain\r\nX: y", PNG, "photo.txt"), "image/png");
+    }
+}
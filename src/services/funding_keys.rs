@@ -0,0 +1,60 @@
+// Funding keys of prefunded uploads
+// A user's own wallet key is only held in memory while its job runs, so it
+// never lands in the database, and a wallet funds one upload at a time so
+// two jobs can't spend the same UTXOs.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct FundingKeys {
+    /// Job id to (WIF, funding address)
+    keys: Mutex<HashMap<String, (String, String)>>,
+}
+
+impl FundingKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold a job's funding key. Returns false, holding nothing, if another
+    /// job is already funded from the same address.
+    pub fn reserve(&self, job_id: &str, wif: &str, address: &str) -> bool {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.iter().any(|(id, (_, a))| a == address && id != job_id) {
+            return false;
+        }
+        keys.insert(job_id.to_string(), (wif.to_string(), address.to_string()));
+        true
+    }
+
+    pub fn wif(&self, job_id: &str) -> Option<String> {
+        let keys = self.keys.lock().unwrap();
+        keys.get(job_id).map(|(wif, _)| wif.clone())
+    }
+
+    /// Forget the key of a job that has finished, freeing its wallet
+    pub fn release(&self, job_id: &str) {
+        let mut keys = self.keys.lock().unwrap();
+        keys.remove(job_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_job_per_wallet() {
+        let keys = FundingKeys::new();
+        assert!(keys.reserve("a", "wif", "addr"));
+        assert!(!keys.reserve("b", "wif", "addr"));
+        assert!(keys.reserve("c", "other-wif", "other-addr"));
+        assert_eq!(keys.wif("a").as_deref(), Some("wif"));
+        assert_eq!(keys.wif("b"), None);
+
+        keys.release("a");
+        assert_eq!(keys.wif("a"), None);
+        assert!(keys.reserve("b", "wif", "addr"));
+    }
+}
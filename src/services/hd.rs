@@ -0,0 +1,254 @@
+// Deterministic payment keys
+// With an HD seed configured, each job's payment key is derived at its own
+// index (BIP32 path m/0'/index') instead of being generated at random, so
+// one seed backup recovers every payment address. The seed may be given as
+// hex or as a BIP39 mnemonic phrase.
+
+use secp256k1::{Scalar, SecretKey};
+use sha2::{Digest, Sha256, Sha512};
+
+const BLOCK_SIZE: usize = 128;
+const HARDENED: u32 = 0x8000_0000;
+// Account under which payment keys live
+const PAYMENT_ACCOUNT: u32 = 0;
+
+/// HMAC-SHA512 (RFC 2104)
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..64].copy_from_slice(&Sha512::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha512::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(data);
+
+    let mut outer = Sha512::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// BIP39 English wordlist, in index order
+const WORDLIST: &str = include_str!("bip39_english.txt");
+
+/// Check a mnemonic's words against the BIP39 English wordlist and verify
+/// its checksum, so a mistyped phrase can't silently become another seed
+pub fn validate_mnemonic(mnemonic: &str) -> Result<(), String> {
+    let words: Vec<&str> = mnemonic.split_whitespace().collect();
+    if ![12, 15, 18, 21, 24].contains(&words.len()) {
+        return Err(format!("Mnemonic must have 12, 15, 18, 21 or 24 words, got {}", words.len()));
+    }
+
+    let wordlist: Vec<&str> = WORDLIST.lines().collect();
+    let mut bits = Vec::with_capacity(words.len() * 11);
+    for word in &words {
+        let index = wordlist
+            .binary_search(word)
+            .map_err(|_| format!("\"{}\" is not a BIP39 English word", word))?;
+        bits.extend((0..11).rev().map(|i| (index >> i) & 1 == 1));
+    }
+
+    // 32 bits of entropy per checksum bit
+    let checksum_len = bits.len() / 33;
+    let entropy: Vec<u8> = bits[..bits.len() - checksum_len]
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |acc, &bit| (acc << 1) | bit as u8))
+        .collect();
+    let hash = Sha256::digest(&entropy);
+    let expected = (0..checksum_len).map(|i| (hash[i / 8] >> (7 - i % 8)) & 1 == 1);
+    if !expected.eq(bits[bits.len() - checksum_len..].iter().copied()) {
+        return Err("Mnemonic checksum doesn't match".to_string());
+    }
+    Ok(())
+}
+
+/// BIP39 seed from a mnemonic: PBKDF2-HMAC-SHA512, 2048 rounds, salted
+/// with "mnemonic" plus the passphrase
+pub fn mnemonic_to_seed(mnemonic: &str, passphrase: &str) -> [u8; 64] {
+    let mnemonic = mnemonic.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut salt = format!("mnemonic{}", passphrase).into_bytes();
+    salt.extend_from_slice(&1u32.to_be_bytes());
+
+    let mut u = hmac_sha512(mnemonic.as_bytes(), &salt);
+    let mut seed = u;
+    for _ in 1..2048 {
+        u = hmac_sha512(mnemonic.as_bytes(), &u);
+        for (s, b) in seed.iter_mut().zip(u.iter()) {
+            *s ^= b;
+        }
+    }
+    seed
+}
+
+/// An extended private key
+#[derive(Clone)]
+pub struct HdWallet {
+    key: SecretKey,
+    chain_code: [u8; 32],
+}
+
+impl HdWallet {
+    /// Master key from a BIP32 seed
+    pub fn from_seed(seed: &[u8]) -> Result<Self, String> {
+        if !(16..=64).contains(&seed.len()) {
+            return Err(format!("HD seed must be 16 to 64 bytes, got {}", seed.len()));
+        }
+        Self::from_hmac(hmac_sha512(b"Bitcoin seed", seed))
+    }
+
+    /// Master key from HD_SEED: hex seed bytes, or a mnemonic phrase.
+    /// BIP39 salts with the NFKD form of the passphrase; an ASCII passphrase
+    /// is already in that form, anything else is refused rather than guessed.
+    pub fn from_config(seed: &str, passphrase: &str) -> Result<Self, String> {
+        let seed = seed.trim();
+        if seed.contains(char::is_whitespace) {
+            validate_mnemonic(seed)?;
+            if !passphrase.is_ascii() {
+                return Err("HD_PASSPHRASE must be ASCII".to_string());
+            }
+            Self::from_seed(&mnemonic_to_seed(seed, passphrase))
+        } else {
+            let bytes = hex::decode(seed).map_err(|e| format!("HD seed is neither hex nor a mnemonic: {}", e))?;
+            Self::from_seed(&bytes)
+        }
+    }
+
+    fn from_hmac(i: [u8; 64]) -> Result<Self, String> {
+        let key = SecretKey::from_slice(&i[..32]).map_err(|e| format!("Invalid derived key: {}", e))?;
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&i[32..]);
+        Ok(HdWallet { key, chain_code })
+    }
+
+    /// Hardened child at `index`
+    fn derive_hardened(&self, index: u32) -> Result<Self, String> {
+        let mut data = Vec::with_capacity(37);
+        data.push(0x00);
+        data.extend_from_slice(&self.key.secret_bytes());
+        data.extend_from_slice(&(index | HARDENED).to_be_bytes());
+
+        let i = hmac_sha512(&self.chain_code, &data);
+        let mut tweak = [0u8; 32];
+        tweak.copy_from_slice(&i[..32]);
+        let tweak = Scalar::from_be_bytes(tweak).map_err(|_| "Derived tweak out of range".to_string())?;
+        let key = self
+            .key
+            .add_tweak(&tweak)
+            .map_err(|e| format!("Invalid derived key: {}", e))?;
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&i[32..]);
+        Ok(HdWallet { key, chain_code })
+    }
+
+    /// Payment key for a job's derivation index (m/0'/index')
+    pub fn payment_key(&self, index: u32) -> Result<SecretKey, String> {
+        if index >= HARDENED {
+            return Err(format!("Derivation index {} is out of range", index));
+        }
+        Ok(self.derive_hardened(PAYMENT_ACCOUNT)?.derive_hardened(index)?.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Network;
+    use crate::services::bsv::BsvService;
+
+    const ABANDON_ABOUT: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    /// Key and chain code of a base58check-encoded xprv
+    fn decode_xprv(xprv: &str) -> ([u8; 32], [u8; 32]) {
+        let data = bs58::decode(xprv).into_vec().unwrap();
+        let (payload, checksum) = data.split_at(data.len() - 4);
+        assert_eq!(&Sha256::digest(Sha256::digest(payload))[..4], checksum);
+        (payload[46..78].try_into().unwrap(), payload[13..45].try_into().unwrap())
+    }
+
+    #[test]
+    fn hmac_matches_rfc4231() {
+        assert_eq!(
+            hex::encode(hmac_sha512(b"Jefe", b"what do ya want for nothing?")),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+        // Keys longer than a block are hashed first
+        assert_eq!(
+            hex::encode(hmac_sha512(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f3526b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598"
+        );
+    }
+
+    #[test]
+    fn mnemonic_seed_matches_bip39_vectors() {
+        let vectors = [
+            (
+                ABANDON_ABOUT,
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            ),
+            (
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+                "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            ),
+            (
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+                "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069",
+            ),
+        ];
+        for (mnemonic, seed) in vectors {
+            validate_mnemonic(mnemonic).unwrap();
+            assert_eq!(hex::encode(mnemonic_to_seed(mnemonic, "TREZOR")), seed);
+        }
+    }
+
+    #[test]
+    fn invalid_mnemonics_are_rejected() {
+        let bad_checksum = "abandon ".repeat(12);
+        assert!(validate_mnemonic(&bad_checksum).unwrap_err().contains("checksum"));
+        let unknown_word = ABANDON_ABOUT.replacen("abandon", "abandonn", 1);
+        assert!(validate_mnemonic(&unknown_word).unwrap_err().contains("abandonn"));
+        let too_short = ABANDON_ABOUT.replacen("abandon ", "", 1);
+        assert!(validate_mnemonic(&too_short).is_err());
+
+        assert!(HdWallet::from_config(&bad_checksum, "").is_err());
+        assert!(HdWallet::from_config(ABANDON_ABOUT, "pässphrase").is_err());
+        assert!(HdWallet::from_config(ABANDON_ABOUT, "TREZOR").is_ok());
+    }
+
+    #[test]
+    fn derivation_matches_bip32_vector() {
+        let master = HdWallet::from_seed(&hex::decode("000102030405060708090a0b0c0d0e0f").unwrap()).unwrap();
+        let (key, chain_code) = decode_xprv(
+            "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi",
+        );
+        assert_eq!(master.key.secret_bytes(), key);
+        assert_eq!(master.chain_code, chain_code);
+
+        // m/0H
+        let child = master.derive_hardened(0).unwrap();
+        let (key, chain_code) = decode_xprv(
+            "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7",
+        );
+        assert_eq!(child.key.secret_bytes(), key);
+        assert_eq!(child.chain_code, chain_code);
+    }
+
+    #[test]
+    fn payment_address_is_reproducible_from_seed() {
+        let address = |wallet: &HdWallet, index| {
+            BsvService::keypair_from_secret_key(&wallet.payment_key(index).unwrap(), Network::Mainnet).1
+        };
+        let from_mnemonic = HdWallet::from_config(ABANDON_ABOUT, "TREZOR").unwrap();
+        let seed_hex = hex::encode(mnemonic_to_seed(ABANDON_ABOUT, "TREZOR"));
+        let from_hex = HdWallet::from_config(&seed_hex, "").unwrap();
+
+        assert_eq!(address(&from_mnemonic, 42), address(&from_hex, 42));
+        assert_eq!(address(&from_mnemonic, 42), address(&HdWallet::from_config(ABANDON_ABOUT, "TREZOR").unwrap(), 42));
+        assert_ne!(address(&from_mnemonic, 42), address(&from_mnemonic, 43));
+        assert!(from_mnemonic.payment_key(HARDENED).is_err());
+    }
+}
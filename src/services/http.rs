@@ -0,0 +1,111 @@
+// Shared HTTP client for chain API requests
+// Every outbound request goes through one client so a configured proxy
+// applies everywhere. Use a socks5h:// proxy URL to resolve hostnames on
+// the proxy side, otherwise DNS lookups leak around it (e.g. with Tor).
+// Requests identify themselves with a user agent so providers can
+// recognize (and whitelist) our traffic.
+
+use reqwest::{Client, Proxy};
+use std::sync::OnceLock;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+pub const DEFAULT_USER_AGENT: &str = concat!("nausica/", env!("CARGO_PKG_VERSION"));
+
+/// Build the shared client, sending `user_agent` and routing through `proxy_url`
+/// when set (http://, https://, socks5:// or socks5h://). Only the first call has an effect.
+pub fn init(proxy_url: Option<&str>, user_agent: &str) -> Result<(), String> {
+    let client = build(proxy_url, user_agent)?;
+    let _ = CLIENT.set(client);
+    Ok(())
+}
+
+fn build(proxy_url: Option<&str>, user_agent: &str) -> Result<Client, String> {
+    let mut builder = Client::builder().user_agent(user_agent);
+    if let Some(url) = proxy_url {
+        let scheme = url.split("://").next().unwrap_or_default();
+        if !matches!(scheme, "http" | "https" | "socks5" | "socks5h") {
+            return Err(format!("Unsupported proxy scheme: {}", scheme));
+        }
+        let proxy = Proxy::all(url).map_err(|e| format!("Invalid proxy URL: {}", e))?;
+        builder = builder.proxy(proxy);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
+
+/// The shared client (a direct client with the default user agent unless `init` ran)
+pub fn client() -> Client {
+    CLIENT
+        .get_or_init(|| {
+            Client::builder()
+                .user_agent(DEFAULT_USER_AGENT)
+                .build()
+                .unwrap_or_default()
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Local server answering every request with `name`, recording the URIs it saw
+    async fn stub(name: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        let seen: Arc<Mutex<Vec<String>>> = Arc::default();
+        let log = seen.clone();
+        let router = axum::Router::new().fallback(move |uri: axum::http::Uri| async move {
+            log.lock().unwrap().push(uri.to_string());
+            name
+        });
+        (crate::test_support::serve(router).await, seen)
+    }
+
+    #[tokio::test]
+    async fn requests_go_through_the_configured_proxy() {
+        let (proxy_url, proxied) = stub("proxy").await;
+        let (upstream_url, direct) = stub("upstream").await;
+
+        let client = build(Some(&proxy_url), DEFAULT_USER_AGENT).unwrap();
+        let body = client.get(format!("{}/tx/abc", upstream_url)).send().await.unwrap().text().await.unwrap();
+        assert_eq!(body, "proxy");
+        // A forward proxy is asked for the full upstream URL
+        assert_eq!(*proxied.lock().unwrap(), [format!("{}/tx/abc", upstream_url)]);
+        assert!(direct.lock().unwrap().is_empty());
+
+        let client = build(None, DEFAULT_USER_AGENT).unwrap();
+        let body = client.get(format!("{}/tx/abc", upstream_url)).send().await.unwrap().text().await.unwrap();
+        assert_eq!(body, "upstream");
+        assert_eq!(proxied.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn unsupported_proxy_schemes_are_refused() {
+        assert_eq!(build(Some("ftp://proxy:21"), DEFAULT_USER_AGENT).unwrap_err(), "Unsupported proxy scheme: ftp");
+        assert!(build(Some("socks5h://127.0.0.1:9050"), DEFAULT_USER_AGENT).is_ok());
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_user_agent() {
+        let agents: Arc<Mutex<Vec<String>>> = Arc::default();
+        let log = agents.clone();
+        let router = axum::Router::new().fallback(move |headers: axum::http::HeaderMap| async move {
+            let agent = headers.get(axum::http::header::USER_AGENT).map(|v| v.to_str().unwrap().to_string());
+            log.lock().unwrap().push(agent.unwrap_or_default());
+            "{}"
+        });
+        let url = crate::test_support::serve(router).await;
+
+        build(None, "custom-agent/2.0").unwrap().get(&url).send().await.unwrap();
+        client().get(&url).send().await.unwrap();
+        // Chain API clients share the client
+        let bitails = crate::services::bitails::BitailsClient::new(url.clone(), Vec::new());
+        let _ = bitails.get_address_unspent("addr").await;
+
+        let expected = ["custom-agent/2.0", DEFAULT_USER_AGENT, DEFAULT_USER_AGENT];
+        assert_eq!(*agents.lock().unwrap(), expected);
+        assert!(DEFAULT_USER_AGENT.starts_with("nausica/"));
+    }
+}
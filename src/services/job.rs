@@ -1,5 +0,0 @@
-// Job processing logic has been moved to main.rs for better organization
-// This file is kept for module compatibility
-
-pub use crate::services::bsv::BsvService;
-pub use crate::services::bitails::BitailsClient;
@@ -0,0 +1,302 @@
+// Per-job processing logs
+// Support answering "my upload failed" used to mean grepping the server log
+// by timestamp. Events logged inside a job's span are also appended to a file
+// for that job, so an operator can fetch exactly what the job did. Secrets
+// are redacted before anything is written, and only the tail of each log is
+// kept.
+
+use chrono::Utc;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Directory the per-job logs are written to
+pub const JOB_LOGS_DIR: &str = "./data/job_logs";
+
+/// Name of the span field that ties events to a job
+const JOB_ID_FIELD: &str = "job_id";
+
+/// Replaces secrets and private keys in a log
+const REDACTED: &str = "[redacted]";
+
+/// Lines waiting for the writer thread. A job that logs faster than the
+/// disk keeps up loses lines rather than waiting.
+const QUEUED_LINES: usize = 4096;
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Job a span belongs to, kept in the span's extensions
+struct JobSpan(String);
+
+/// Tracing layer appending the events of each job's span to that job's log
+pub struct JobLogLayer {
+    /// Configured secrets, e.g. the admin key and API keys
+    secrets: Vec<String>,
+    /// (job id, line) for the writer thread, so jobs never wait on file I/O
+    lines: SyncSender<(String, String)>,
+}
+
+impl JobLogLayer {
+    pub fn new(max_bytes: usize, secrets: Vec<String>) -> Self {
+        let writer = LogWriter {
+            dir: PathBuf::from(JOB_LOGS_DIR),
+            max_bytes: max_bytes.max(1024),
+        };
+        let (lines, queued) = mpsc::sync_channel(QUEUED_LINES);
+        std::thread::Builder::new()
+            .name("job-log-writer".to_string())
+            .spawn(move || writer.run(queued))
+            .expect("Failed to start the job log writer");
+        JobLogLayer {
+            secrets: secrets.into_iter().filter(|s| !s.trim().is_empty()).collect(),
+            lines,
+        }
+    }
+
+    fn redact(&self, line: &str) -> String {
+        let mut line = redact_keys(line);
+        for secret in &self.secrets {
+            line = line.replace(secret.as_str(), REDACTED);
+        }
+        line
+    }
+}
+
+impl<S> Layer<S> for JobLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = JobIdVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(job_id), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(JobSpan(job_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let job_id = match ctx.event_scope(event) {
+            Some(scope) => scope
+                .from_root()
+                .find_map(|span| span.extensions().get::<JobSpan>().map(|job| job.0.clone())),
+            None => None,
+        };
+        let job_id = match job_id {
+            Some(job_id) => job_id,
+            None => return,
+        };
+
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+        let line = format!(
+            "{} {} {}: {}{}\n",
+            Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            event.metadata().level(),
+            event.metadata().target(),
+            visitor.message,
+            visitor.fields
+        );
+        // Dropped if the writer is behind or gone
+        let _ = self.lines.try_send((job_id, self.redact(&line)));
+    }
+}
+
+/// Appends queued lines to the job logs on its own thread
+struct LogWriter {
+    dir: PathBuf,
+    max_bytes: usize,
+}
+
+impl LogWriter {
+    fn run(self, lines: Receiver<(String, String)>) {
+        for (job_id, line) in lines {
+            if let Err(e) = self.append(&job_id, &line) {
+                // Not in any job's span, so this goes to the server log only
+                tracing::warn!("Failed to write the log of job {}: {}", job_id, e);
+            }
+        }
+    }
+
+    fn append(&self, job_id: &str, line: &str) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = log_path(&self.dir, job_id);
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(line.as_bytes())?;
+
+        // Trimmed in steps rather than on every line
+        let len = file.metadata()?.len() as usize;
+        if len > self.max_bytes + self.max_bytes / 4 {
+            drop(file);
+            let log = std::fs::read(&path)?;
+            let tail = &log[log.len() - self.max_bytes..];
+            let start = tail.iter().position(|b| *b == b'\n').map_or(0, |i| i + 1);
+            std::fs::write(&path, &tail[start..])?;
+        }
+        Ok(())
+    }
+}
+
+struct JobIdVisitor(Option<String>);
+
+impl Visit for JobIdVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == JOB_ID_FIELD {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == JOB_ID_FIELD {
+            self.0 = Some(format!("{:?}", value).trim_matches('"').to_string());
+        }
+    }
+}
+
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Log file of a job. Job ids are generated hex, but anything else is
+/// dropped so a crafted id can't leave the directory.
+fn log_path(dir: &Path, job_id: &str) -> PathBuf {
+    let name: String = job_id.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    dir.join(format!("{}.log", name))
+}
+
+/// The kept tail of a job's log, if anything was logged for it
+pub fn read(job_id: &str) -> std::io::Result<Option<String>> {
+    match std::fs::read(log_path(Path::new(JOB_LOGS_DIR), job_id)) {
+        Ok(log) => Ok(Some(String::from_utf8_lossy(&log).into_owned())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace anything shaped like a WIF private key: a base58 word of 51 or
+/// 52 characters starting with the mainnet or testnet WIF prefix
+fn redact_keys(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut word = String::new();
+    for c in line.chars().chain(std::iter::once('\n')) {
+        if BASE58_ALPHABET.contains(c) {
+            word.push(c);
+            continue;
+        }
+        if is_wif_shaped(&word) {
+            out.push_str(REDACTED);
+        } else {
+            out.push_str(&word);
+        }
+        word.clear();
+        out.push(c);
+    }
+    // Drop the newline added to flush the last word
+    out.pop();
+    out
+}
+
+fn is_wif_shaped(word: &str) -> bool {
+    matches!(word.len(), 51 | 52) && word.starts_with(['5', 'K', 'L', '9', 'c'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Job, JobType, Network};
+    use crate::services::bsv::BsvService;
+    use crate::services::scheduler::QueuedJob;
+    use crate::test_support::{serve, test_config, test_state_with};
+    use axum::{routing::{get, post}, Json, Router};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// A Bitails stand-in funding every address and rejecting FLAC chunks as too large
+    async fn chunk_rejecting_bitails() -> String {
+        let unspent = get(|axum::extract::Path(address): axum::extract::Path<String>| async move {
+            Json(serde_json::json!({
+                "address": address,
+                "unspent": [{ "txid": "22".repeat(32), "vout": 0, "satoshis": 10_000_000, "confirmations": 6 }],
+            }))
+        });
+        let broadcast = post(|Json(body): Json<serde_json::Value>| async move {
+            let raw = body["raw"].as_str().unwrap_or_default().to_string();
+            if raw.contains(&hex::encode("flacstore-chunk")) {
+                let error = serde_json::json!({ "error": { "message": "transaction too large" } });
+                return (axum::http::StatusCode::PAYLOAD_TOO_LARGE, Json(error));
+            }
+            (axum::http::StatusCode::OK, Json(serde_json::json!({ "txid": BsvService::txid(&raw).unwrap() })))
+        });
+        serve(Router::new().route("/address/:address/unspent", unspent).route("/tx/broadcast", broadcast)).await
+    }
+
+    #[test]
+    fn redacts_keys_and_secrets() {
+        let (wif, _) = BsvService::generate_keypair(Network::Mainnet);
+        let layer = JobLogLayer::new(1024, vec!["admin-secret".to_string(), " ".to_string()]);
+        let line = layer.redact(&format!("key={} admin=admin-secret, txid {}", wif, "ab".repeat(32)));
+        assert_eq!(line, format!("key=[redacted] admin=[redacted], txid {}", "ab".repeat(32)));
+    }
+
+    #[tokio::test]
+    async fn failed_upload_log_has_chunk_errors_without_the_key() {
+        let mut config = test_config();
+        config.bitails_api_url = chunk_rejecting_bitails().await;
+        config.max_chunk_halvings = 0;
+        config.max_job_duration_minutes = 0;
+        let state = test_state_with(config);
+        state.write().await.bsv.flac_single_tx_max_bytes = 10;
+
+        let (wif, address) = BsvService::generate_keypair(Network::Mainnet);
+        let job_id = format!("joblogtest{}", std::process::id());
+        let data = vec![0x5a; 3000];
+        let job = Job::new_flac_upload(job_id.clone(), "song.flac".to_string(), 3000, data, address.clone(), wif.clone(), 0);
+        state.read().await.db.insert_job(&job).unwrap();
+
+        let subscriber = tracing_subscriber::registry().with(JobLogLayer::new(64 * 1024, Vec::new()));
+        let _default = tracing::subscriber::set_default(subscriber);
+        let queued = QueuedJob {
+            job_id: job_id.clone(),
+            job_type: JobType::FlacUpload,
+            address,
+            network: Network::Mainnet,
+            admin_pay: false,
+            file_size: 3000,
+            paid_satoshis: None,
+        };
+        crate::run_job_guarded(state.clone(), queued).await;
+
+        let job = state.read().await.db.get_job(&job_id).unwrap().unwrap();
+        assert_eq!(job.error_code, Some(crate::models::ErrorCode::BroadcastFailed));
+
+        // The writer thread catches up on its own
+        let mut log = String::new();
+        for _ in 0..50 {
+            log = read(&job_id).unwrap().unwrap_or_default();
+            if log.contains("Chunk 1 broadcast failed") {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        let _ = std::fs::remove_file(log_path(Path::new(JOB_LOGS_DIR), &job_id));
+        assert!(log.contains("Chunk 1 broadcast failed: bitails (HTTP 413): transaction too large"), "{}", log);
+        assert!(log.contains("UTXO split transaction broadcast"), "{}", log);
+        assert!(!log.contains(&wif), "{}", log);
+    }
+}
@@ -0,0 +1,210 @@
+// Lyrics parsing for FLAC manifests
+// Lyrics are stored either as plain text or in LRC format with
+// `[mm:ss.xx]` timestamps that the player uses for synced highlighting.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsFormat {
+    Plain,
+    Lrc,
+}
+
+impl LyricsFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LyricsFormat::Plain => "plain",
+            LyricsFormat::Lrc => "lrc",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "plain" => Some(LyricsFormat::Plain),
+            "lrc" => Some(LyricsFormat::Lrc),
+            _ => None,
+        }
+    }
+}
+
+/// A single timed lyric line
+#[derive(Debug, Clone, Serialize)]
+pub struct LyricLine {
+    pub time_ms: u64,
+    pub text: String,
+}
+
+/// Detect the lyrics format: LRC if any line starts with a timestamp tag
+pub fn detect_format(lyrics: &str) -> LyricsFormat {
+    let has_timestamps = lyrics
+        .lines()
+        .any(|line| parse_line_tags(line.trim()).map(|(times, _)| !times.is_empty()).unwrap_or(false));
+
+    if has_timestamps {
+        LyricsFormat::Lrc
+    } else {
+        LyricsFormat::Plain
+    }
+}
+
+/// Parse LRC lyrics into timed lines sorted by time.
+/// Lines with several timestamps (`[00:12.00][01:30.00]Chorus`) produce one
+/// entry per timestamp; metadata tags like `[ar:Artist]` are skipped.
+pub fn parse_lrc(lyrics: &str) -> Vec<LyricLine> {
+    let mut lines = Vec::new();
+
+    for line in lyrics.lines() {
+        if let Some((times, text)) = parse_line_tags(line.trim()) {
+            let text = text.trim();
+            if text.is_empty() {
+                continue;
+            }
+            for time_ms in times {
+                lines.push(LyricLine {
+                    time_ms,
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+
+    // Stable sort keeps the original order for lines sharing a timestamp
+    lines.sort_by_key(|l| l.time_ms);
+    lines
+}
+
+/// Strip timestamps and metadata tags, leaving the lyrics as plain text
+pub fn to_plain(lyrics: &str) -> String {
+    match detect_format(lyrics) {
+        LyricsFormat::Plain => lyrics.to_string(),
+        LyricsFormat::Lrc => lyrics
+            .lines()
+            .filter_map(|line| match parse_line_tags(line.trim()) {
+                Some((times, text)) if times.is_empty() && text.is_empty() => None,
+                Some((_, text)) => Some(text.trim().to_string()),
+                None => Some(line.trim().to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Split the leading `[...]` tags off a line.
+/// Returns the timestamps found (in milliseconds) and the remaining text,
+/// or None if the line has no leading tags at all.
+fn parse_line_tags(line: &str) -> Option<(Vec<u64>, &str)> {
+    if !line.starts_with('[') {
+        return None;
+    }
+
+    let mut times = Vec::new();
+    let mut rest = line;
+
+    while let Some(inner) = rest.strip_prefix('[') {
+        let end = inner.find(']')?;
+        if let Some(time_ms) = parse_timestamp(&inner[..end]) {
+            times.push(time_ms);
+        }
+        rest = &inner[end + 1..];
+    }
+
+    Some((times, rest))
+}
+
+/// Parse `mm:ss`, `mm:ss.xx` or `mm:ss.xxx` into milliseconds
+fn parse_timestamp(tag: &str) -> Option<u64> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes: u64 = minutes.parse().ok()?;
+
+    // Some editors write `mm:ss:xx` instead of `mm:ss.xx`
+    let (secs, fraction) = match seconds.split_once(['.', ':']) {
+        Some((s, f)) => (s, Some(f)),
+        None => (seconds, None),
+    };
+
+    if secs.len() != 2 {
+        return None;
+    }
+    let secs: u64 = secs.parse().ok()?;
+    if secs >= 60 {
+        return None;
+    }
+
+    let fraction_ms = match fraction {
+        Some(f) if !f.is_empty() && f.len() <= 3 && f.bytes().all(|b| b.is_ascii_digit()) => {
+            // Hundredths ("12") and milliseconds ("120") both map to 120ms
+            format!("{:0<3}", f).parse::<u64>().ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    // Absurd minute counts would overflow; such a tag isn't a timestamp
+    minutes.checked_mul(60_000)?.checked_add(secs * 1000 + fraction_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::bsv::{BsvService, ManifestLayout};
+    use crate::services::tx_parse::parse_flac_manifest_output;
+
+    fn manifest_lyrics(lyrics: &str) -> (Option<String>, LyricsFormat) {
+        let script = BsvService::create_flac_manifest_script(
+            "track.flac",
+            1000,
+            &["a".repeat(64)],
+            &[],
+            Some("Title"),
+            Some("Artist"),
+            Some(lyrics),
+            None,
+            None,
+            None,
+            None,
+            ManifestLayout::Json,
+        );
+        let manifest = parse_flac_manifest_output(&script).unwrap();
+        (manifest.lyrics, manifest.lyrics_format)
+    }
+
+    #[test]
+    fn timestamps_parse_to_milliseconds() {
+        assert_eq!(parse_timestamp("01:02"), Some(62_000));
+        assert_eq!(parse_timestamp("01:02.5"), Some(62_500));
+        assert_eq!(parse_timestamp("01:02.12"), Some(62_120));
+        assert_eq!(parse_timestamp("01:02:120"), Some(62_120));
+        assert_eq!(parse_timestamp("01:60"), None);
+        assert_eq!(parse_timestamp("ar"), None);
+    }
+
+    #[test]
+    fn oversized_timestamps_are_not_timestamps() {
+        assert_eq!(parse_timestamp("99999999999999999:00"), None);
+        assert_eq!(parse_timestamp("307445734561825:59.999"), None);
+        assert_eq!(detect_format("[99999999999999999:00]Line"), LyricsFormat::Plain);
+        assert!(parse_lrc("[99999999999999999:00]Line").is_empty());
+    }
+
+    #[test]
+    fn plain_lyrics_from_manifest() {
+        let (lyrics, format) = manifest_lyrics("First line\nSecond line");
+        assert_eq!(format, LyricsFormat::Plain);
+        assert_eq!(lyrics.as_deref(), Some("First line\nSecond line"));
+        assert_eq!(to_plain(lyrics.as_deref().unwrap()), "First line\nSecond line");
+    }
+
+    #[test]
+    fn timed_lyrics_from_manifest() {
+        let lrc = "[ar:Artist]\n[00:12.00][01:30.00]Chorus\n[00:05.50]Intro";
+        let (lyrics, format) = manifest_lyrics(lrc);
+        assert_eq!(format, LyricsFormat::Lrc);
+        let lyrics = lyrics.unwrap();
+
+        let lines: Vec<(u64, String)> = parse_lrc(&lyrics).into_iter().map(|l| (l.time_ms, l.text)).collect();
+        let expected = [(5_500, "Intro"), (12_000, "Chorus"), (90_000, "Chorus")].map(|(t, s)| (t, s.to_string()));
+        assert_eq!(lines, expected);
+        assert_eq!(to_plain(&lyrics), "Chorus\nIntro");
+    }
+}
@@ -0,0 +1,218 @@
+// Storage maintenance
+// Frees data no live job needs any more: the file BLOBs of finished jobs,
+// raw transactions kept past their re-broadcast window, and files in the
+// downloads directory that no job links to, e.g. after a crash between saving
+// a download and completing its job. Per-job processing logs follow the
+// same age cutoff as those files.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::db::Database;
+use crate::routes::download::download_link;
+
+/// What one maintenance run reclaimed
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceReport {
+    pub ran_at: DateTime<Utc>,
+    /// Finished jobs whose file data was cleared
+    pub cleared_jobs: usize,
+    pub blob_bytes: u64,
+    /// Raw transactions no longer kept for re-broadcasting
+    pub cleared_raw_txs: usize,
+    pub raw_tx_bytes: u64,
+    /// Download files no job links to
+    pub removed_files: usize,
+    pub file_bytes: u64,
+    /// Per-job processing logs past the file cutoff
+    pub removed_job_logs: usize,
+    pub job_log_bytes: u64,
+}
+
+impl MaintenanceReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.blob_bytes + self.raw_tx_bytes + self.file_bytes + self.job_log_bytes
+    }
+}
+
+/// Clear BLOBs of jobs finished before `blob_cutoff` and raw transactions
+/// broadcast before `raw_tx_cutoff`, delete files in `downloads_dir` last
+/// modified before `file_cutoff` that no job links to, and job logs in
+/// `job_logs_dir` last written before it
+pub fn run(
+    db: &Database,
+    downloads_dir: &Path,
+    job_logs_dir: &Path,
+    blob_cutoff: DateTime<Utc>,
+    raw_tx_cutoff: DateTime<Utc>,
+    file_cutoff: SystemTime,
+) -> MaintenanceReport {
+    let (cleared_jobs, blob_bytes) = db.clear_finished_job_blobs(blob_cutoff).unwrap_or_else(|e| {
+        tracing::warn!("Failed to clear finished job data: {}", e);
+        (0, 0)
+    });
+    let (cleared_raw_txs, raw_tx_bytes) = db.clear_broadcast_txs(raw_tx_cutoff).unwrap_or_else(|e| {
+        tracing::warn!("Failed to clear raw transactions: {}", e);
+        (0, 0)
+    });
+
+    let mut removed_files = 0;
+    let mut file_bytes = 0;
+    // Without the list of live links every file would look orphaned
+    match (db.download_links(), std::fs::read_dir(downloads_dir)) {
+        (Ok(links), Ok(entries)) => {
+            for entry in entries.flatten() {
+                let metadata = match entry.metadata() {
+                    Ok(m) if m.is_file() => m,
+                    _ => continue,
+                };
+                let filename = entry.file_name().to_string_lossy().to_string();
+                // Older jobs stored the link without percent-encoding
+                if links.contains(&download_link(&filename)) || links.contains(&format!("/downloads/{}", filename)) {
+                    continue;
+                }
+                if metadata.modified().map(|m| m >= file_cutoff).unwrap_or(true) {
+                    continue;
+                }
+                match std::fs::remove_file(entry.path()) {
+                    Ok(()) => {
+                        removed_files += 1;
+                        file_bytes += metadata.len();
+                    }
+                    Err(e) => tracing::warn!("Failed to remove orphaned download {}: {}", filename, e),
+                }
+            }
+        }
+        (Err(e), _) => tracing::warn!("Failed to list download links: {}", e),
+        // No downloads directory yet, nothing to clean
+        (_, Err(_)) => {}
+    }
+
+    let mut removed_job_logs = 0;
+    let mut job_log_bytes = 0;
+    // No job has logged anything yet when the directory is missing
+    for entry in std::fs::read_dir(job_logs_dir).into_iter().flatten().flatten() {
+        let metadata = match entry.metadata() {
+            Ok(m) if m.is_file() => m,
+            _ => continue,
+        };
+        if metadata.modified().map(|m| m >= file_cutoff).unwrap_or(true) {
+            continue;
+        }
+        match std::fs::remove_file(entry.path()) {
+            Ok(()) => {
+                removed_job_logs += 1;
+                job_log_bytes += metadata.len();
+            }
+            Err(e) => tracing::warn!("Failed to remove job log {}: {}", entry.file_name().to_string_lossy(), e),
+        }
+    }
+
+    MaintenanceReport {
+        ran_at: Utc::now(),
+        cleared_jobs,
+        blob_bytes,
+        cleared_raw_txs,
+        raw_tx_bytes,
+        removed_files,
+        file_bytes,
+        removed_job_logs,
+        job_log_bytes,
+    }
+}
+
+/// Totals across runs, as shown in the admin metrics
+#[derive(Default)]
+pub struct MaintenanceStats {
+    inner: Mutex<MaintenanceTotals>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MaintenanceTotals {
+    pub runs: u64,
+    pub reclaimed_bytes: u64,
+    pub last_run: Option<MaintenanceReport>,
+}
+
+impl MaintenanceStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, report: &MaintenanceReport) {
+        let mut totals = self.inner.lock().unwrap();
+        totals.runs += 1;
+        totals.reclaimed_bytes += report.reclaimed_bytes();
+        totals.last_run = Some(report.clone());
+    }
+
+    pub fn totals(&self) -> MaintenanceTotals {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::job::JobStatus;
+    use crate::models::{Job, MessageKey};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn upload(id: &str) -> Job {
+        Job::new_upload(id.to_string(), "file.txt".to_string(), 4, b"data".to_vec(), "address".to_string(), "wif".to_string(), 0)
+    }
+
+    /// Write `data` to `dir/name`, last modified `age` ago
+    fn plant(dir: &Path, name: &str, data: &[u8], age: Duration) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+        path
+    }
+
+    #[test]
+    fn removes_orphans_and_keeps_what_live_jobs_use() {
+        let root = std::env::temp_dir().join(format!("maintenance-{}", uuid::Uuid::new_v4()));
+        let (downloads, job_logs) = (root.join("downloads"), root.join("job_logs"));
+        std::fs::create_dir_all(&downloads).unwrap();
+        std::fs::create_dir_all(&job_logs).unwrap();
+        let two_days = Duration::from_secs(2 * 24 * 3600);
+
+        let db = Database::new(":memory:").unwrap();
+        db.insert_job(&upload("done")).unwrap();
+        db.update_job_complete("done", &"ab".repeat(32), Some(&download_link("linked.flac"))).unwrap();
+        db.insert_job(&upload("running").with_status(JobStatus::Processing, MessageKey::Starting)).unwrap();
+
+        let linked = plant(&downloads, "linked.flac", b"linked", two_days);
+        let orphan = plant(&downloads, "orphan.flac", b"orphaned", two_days);
+        let fresh = plant(&downloads, "fresh.flac", b"still being saved", Duration::ZERO);
+        let old_log = plant(&job_logs, "done.log", b"old log", two_days);
+        let new_log = plant(&job_logs, "running.log", b"new log", Duration::ZERO);
+
+        let now = Utc::now();
+        let report = run(
+            &db,
+            &downloads,
+            &job_logs,
+            now + chrono::Duration::minutes(1),
+            now - chrono::Duration::hours(1),
+            SystemTime::now() - Duration::from_secs(24 * 3600),
+        );
+
+        assert_eq!((report.cleared_jobs, report.blob_bytes), (1, 4));
+        assert_eq!((report.removed_files, report.file_bytes), (1, 8));
+        assert_eq!((report.removed_job_logs, report.job_log_bytes), (1, 7));
+        assert_eq!(report.reclaimed_bytes(), 19);
+        assert!(!orphan.exists() && !old_log.exists());
+        assert!(linked.exists() && fresh.exists() && new_log.exists());
+        assert_eq!(db.get_job("done").unwrap().unwrap().file_data, None);
+        assert_eq!(db.get_job("running").unwrap().unwrap().file_data, Some(b"data".to_vec()));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,3 +1,20 @@
+pub mod admin_session;
+pub mod api_keys;
+pub mod archive;
 pub mod bitails;
 pub mod bsv;
-pub mod job;
+pub mod cancellation;
+pub mod content_type;
+pub mod funding_keys;
+pub mod hd;
+pub mod http;
+pub mod job_log;
+pub mod lyrics;
+pub mod maintenance;
+pub mod provider_health;
+pub mod rate_limit;
+pub mod scheduler;
+pub mod selftest;
+pub mod thumbnail;
+pub mod tx_parse;
+pub mod whatsonchain;
@@ -0,0 +1,155 @@
+// Chain provider health
+// Mainnet broadcasts and transaction fetches go to Bitails with WhatsOnChain
+// as the fallback. While Bitails is failing, every request would first wait
+// for it to fail; the recent outcomes kept here let callers try the
+// healthier provider first until the failures age out of the window.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Outcomes older than this no longer count
+const WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Most outcomes kept per provider
+const MAX_SAMPLES: usize = 50;
+
+/// A provider needs this many recent outcomes before it is judged
+const MIN_SAMPLES: usize = 3;
+
+/// Error rate at which the primary stops being tried first
+const DEGRADED_ERROR_RATE: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Bitails,
+    WhatsOnChain,
+}
+
+impl Provider {
+    fn index(self) -> usize {
+        match self {
+            Provider::Bitails => 0,
+            Provider::WhatsOnChain => 1,
+        }
+    }
+}
+
+/// Recent successes and failures of each provider
+#[derive(Default)]
+pub struct ProviderHealth {
+    outcomes: Mutex<[VecDeque<(Instant, bool)>; 2]>,
+}
+
+/// One provider's health, as shown in the admin metrics
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderStatus {
+    pub provider: Provider,
+    /// Requests in the window
+    pub samples: usize,
+    pub failures: usize,
+    pub error_rate: f64,
+}
+
+impl ProviderHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note whether a request to `provider` got a usable answer
+    pub fn record(&self, provider: Provider, ok: bool) {
+        let mut outcomes = self.outcomes.lock().unwrap();
+        let samples = &mut outcomes[provider.index()];
+        samples.push_back((Instant::now(), ok));
+        while samples.len() > MAX_SAMPLES {
+            samples.pop_front();
+        }
+    }
+
+    pub fn status(&self, provider: Provider) -> ProviderStatus {
+        let mut outcomes = self.outcomes.lock().unwrap();
+        let samples = &mut outcomes[provider.index()];
+        while samples.front().is_some_and(|(at, _)| at.elapsed() > WINDOW) {
+            samples.pop_front();
+        }
+        let failures = samples.iter().filter(|(_, ok)| !ok).count();
+        ProviderStatus {
+            provider,
+            samples: samples.len(),
+            failures,
+            error_rate: if samples.is_empty() { 0.0 } else { failures as f64 / samples.len() as f64 },
+        }
+    }
+
+    /// `primary` and `fallback` in the order to try them. The fallback goes
+    /// first while the primary is failing at least half its recent requests
+    /// and the fallback is doing better.
+    pub fn order(&self, primary: Provider, fallback: Provider) -> [Provider; 2] {
+        let p = self.status(primary);
+        let f = self.status(fallback);
+        let degraded = p.samples >= MIN_SAMPLES && p.error_rate >= DEGRADED_ERROR_RATE;
+        if degraded && f.error_rate < p.error_rate {
+            [fallback, primary]
+        } else {
+            [primary, fallback]
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<ProviderStatus> {
+        vec![self.status(Provider::Bitails), self.status(Provider::WhatsOnChain)]
+    }
+}
+
+static MAINNET: OnceLock<ProviderHealth> = OnceLock::new();
+
+/// Health of the mainnet providers, shared by every broadcast and fetch
+pub fn mainnet() -> &'static ProviderHealth {
+    MAINNET.get_or_init(ProviderHealth::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Provider::{Bitails, WhatsOnChain};
+
+    #[test]
+    fn failing_primary_goes_second_until_it_recovers() {
+        let health = ProviderHealth::new();
+        assert_eq!(health.order(Bitails, WhatsOnChain), [Bitails, WhatsOnChain]);
+
+        // Too few failures to judge it
+        health.record(Bitails, false);
+        health.record(Bitails, false);
+        assert_eq!(health.order(Bitails, WhatsOnChain), [Bitails, WhatsOnChain]);
+
+        health.record(Bitails, false);
+        assert_eq!(health.order(Bitails, WhatsOnChain), [WhatsOnChain, Bitails]);
+        let status = health.status(Bitails);
+        assert_eq!((status.samples, status.failures, status.error_rate), (3, 3, 1.0));
+
+        // Successes bring its error rate back under the threshold
+        for _ in 0..4 {
+            health.record(Bitails, true);
+        }
+        assert_eq!(health.order(Bitails, WhatsOnChain), [Bitails, WhatsOnChain]);
+    }
+
+    #[test]
+    fn primary_stays_first_when_the_fallback_is_no_better() {
+        let health = ProviderHealth::new();
+        for _ in 0..3 {
+            health.record(Bitails, false);
+            health.record(WhatsOnChain, false);
+        }
+        assert_eq!(health.order(Bitails, WhatsOnChain), [Bitails, WhatsOnChain]);
+
+        // Only the most recent outcomes are kept
+        for _ in 0..MAX_SAMPLES {
+            health.record(WhatsOnChain, true);
+        }
+        assert_eq!(health.status(WhatsOnChain).samples, MAX_SAMPLES);
+        assert_eq!(health.order(Bitails, WhatsOnChain), [WhatsOnChain, Bitails]);
+    }
+}
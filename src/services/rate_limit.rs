@@ -0,0 +1,113 @@
+// Request rate limiting for third-party chain APIs
+// WhatsOnChain rejects bursts with 429s, so every call to it waits for a
+// token from one shared bucket instead of each caller retrying on its own.
+
+use rand::Rng;
+use std::sync::{Mutex, OnceLock};
+use tokio::time::{sleep, Duration, Instant};
+
+/// Token bucket allowing `rate` requests per second with bursts of up to `rate`
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64) -> Self {
+        let rate = requests_per_second.max(0.1);
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate.max(1.0),
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until a request may be sent
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate.max(1.0));
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+            sleep(wait).await;
+        }
+    }
+}
+
+static WHATSONCHAIN: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Set the WhatsOnChain request rate. Only the first call has an effect.
+pub fn init_whatsonchain(requests_per_second: f64) {
+    let _ = WHATSONCHAIN.set(RateLimiter::new(requests_per_second));
+}
+
+/// Limiter shared by all WhatsOnChain calls (3 req/s unless configured)
+pub fn whatsonchain() -> &'static RateLimiter {
+    WHATSONCHAIN.get_or_init(|| RateLimiter::new(3.0))
+}
+
+/// `base` moved randomly by up to `jitter_percent` either way, so pollers
+/// started together drift apart instead of hitting the API in step
+pub fn jittered(base: Duration, jitter_percent: u64) -> Duration {
+    let spread = base.as_secs_f64() * jitter_percent.min(100) as f64 / 100.0;
+    if spread <= 0.0 {
+        return base;
+    }
+    let offset = rand::thread_rng().gen_range(-spread..=spread);
+    Duration::from_secs_f64((base.as_secs_f64() + offset).max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_after_a_burst_are_spaced_by_the_rate() {
+        let limiter = RateLimiter::new(10.0);
+        let start = Instant::now();
+        for _ in 0..10 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(50), "{:?}", start.elapsed());
+
+        // The burst spent the bucket, so each further request waits a tenth of a second
+        let mut last = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+            let gap = last.elapsed();
+            assert!(gap >= Duration::from_millis(95), "{:?}", gap);
+            assert!(gap < Duration::from_millis(200), "{:?}", gap);
+            last = Instant::now();
+        }
+    }
+
+    #[test]
+    fn jitter_stays_within_its_bounds() {
+        let base = Duration::from_secs(10);
+        let samples: Vec<Duration> = (0..1000).map(|_| jittered(base, 20)).collect();
+        assert!(samples.iter().all(|d| (Duration::from_secs(8)..=Duration::from_secs(12)).contains(d)));
+        // Spread across the range rather than stuck at the base
+        assert!(samples.iter().any(|d| *d < Duration::from_millis(9500)));
+        assert!(samples.iter().any(|d| *d > Duration::from_millis(10500)));
+
+        assert_eq!(jittered(base, 0), base);
+        // The spread is capped at the base interval itself
+        assert!((0..1000).map(|_| jittered(base, 500)).all(|d| d <= Duration::from_secs(20)));
+    }
+}
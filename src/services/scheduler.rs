@@ -0,0 +1,136 @@
+// Job scheduler
+// Paid jobs are queued here instead of being spawned directly, so only a
+// bounded number of uploads/downloads hit the chain providers at once.
+
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::models::{JobType, Network};
+
+/// Jobs at or below this size fit in a single transaction and jump ahead
+/// of multi-chunk uploads
+const SMALL_JOB_BYTES: i64 = 1024 * 1024;
+
+/// A job waiting for a free processing slot
+#[derive(Debug, Clone)]
+pub struct QueuedJob {
+    pub job_id: String,
+    pub job_type: JobType,
+    pub address: String,
+    pub network: Network,
+    pub admin_pay: bool,
+    pub file_size: i64,
+    /// Payment the watcher saw for the job, named in its queue messages
+    pub paid_satoshis: Option<i64>,
+}
+
+struct QueueEntry {
+    job: QueuedJob,
+    seq: u64,
+}
+
+impl QueueEntry {
+    /// Lower sorts first: admin-pay, then small jobs, then FIFO
+    fn priority(&self) -> (bool, bool, u64) {
+        (
+            !self.job.admin_pay,
+            self.job.file_size > SMALL_JOB_BYTES,
+            self.seq,
+        )
+    }
+}
+
+#[derive(Default)]
+struct SchedulerQueue {
+    entries: Vec<QueueEntry>,
+    next_seq: u64,
+}
+
+#[derive(Clone)]
+pub struct JobScheduler {
+    queue: Arc<Mutex<SchedulerQueue>>,
+    slots: Arc<Semaphore>,
+    notify: Arc<Notify>,
+}
+
+impl JobScheduler {
+    pub fn new(max_concurrent_jobs: usize) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(SchedulerQueue::default())),
+            slots: Arc::new(Semaphore::new(max_concurrent_jobs.max(1))),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Add a job to the queue. Jobs already queued are ignored.
+    pub fn enqueue(&self, job: QueuedJob) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.entries.iter().any(|e| e.job.job_id == job.job_id) {
+                return;
+            }
+            let seq = queue.next_seq;
+            queue.next_seq += 1;
+            queue.entries.push(QueueEntry { job, seq });
+            queue.entries.sort_by_key(|e| e.priority());
+        }
+        self.notify.notify_one();
+    }
+
+    /// Queued jobs in run order, each with the number of jobs ahead of it
+    pub fn positions(&self) -> Vec<(QueuedJob, usize)> {
+        let queue = self.queue.lock().unwrap();
+        queue
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(ahead, e)| (e.job.clone(), ahead))
+            .collect()
+    }
+
+    /// Take a job out of the queue before it starts. Returns false if it wasn't queued.
+    pub fn remove(&self, job_id: &str) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let before = queue.entries.len();
+        queue.entries.retain(|e| e.job.job_id != job_id);
+        queue.entries.len() != before
+    }
+
+    pub fn is_queued(&self, job_id: &str) -> bool {
+        let queue = self.queue.lock().unwrap();
+        queue.entries.iter().any(|e| e.job.job_id == job_id)
+    }
+
+    /// Wait for a free slot and the next job to run.
+    /// The slot is released when the returned permit is dropped.
+    pub async fn next(&self) -> (QueuedJob, OwnedSemaphorePermit) {
+        let permit = self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("scheduler semaphore closed");
+
+        loop {
+            if let Some(job) = self.pop() {
+                return (job, permit);
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Take a free slot for work done in the request itself rather than
+    /// queued, or None if every slot is busy. Released when the permit is dropped.
+    pub fn try_slot(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    fn pop(&self) -> Option<QueuedJob> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.entries.is_empty() {
+            None
+        } else {
+            Some(queue.entries.remove(0).job)
+        }
+    }
+}
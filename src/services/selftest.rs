@@ -0,0 +1,819 @@
+// End-to-end self-test
+// Inscribes a random canary file with the admin wallet and downloads it
+// again, so an operator can prove the keys, chain providers and fee rate
+// work before pointing users at the instance. A live run goes through the
+// same job queue, upload and download code as a user's FLAC upload. A dry
+// run (the only kind allowed on mainnet) builds and signs every transaction
+// from the admin wallet's real UTXOs, then parses them back instead of
+// broadcasting them.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::models::{Amount, ErrorCode, Job, JobStatus, JobType, MessageKey, Network};
+use crate::services::bsv::BsvService;
+use crate::services::scheduler::QueuedJob;
+use crate::services::tx_parse::{assemble_chunks, chunk_matches, extract_flac_chunk_from_tx, extract_flac_manifest_from_tx};
+use crate::AppState;
+
+/// Size of the canary when it is stored in a single transaction
+const SINGLE_TX_CANARY_BYTES: usize = 1024;
+
+/// How long a live run waits for the canary upload job to finish
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// How often a live run checks on the canary upload job
+const UPLOAD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Admin wallet key for the network
+    Config,
+    /// Chain height and the admin wallet's UTXOs from the network's provider
+    Chain,
+    /// Canary, quote, upload limits and admin pay eligibility
+    Prepare,
+    /// Live: the canary's upload job (split, chunks and manifest broadcast)
+    Upload,
+    /// Live: a FLAC download of the manifest the upload inscribed
+    Download,
+    /// Dry run: split, chunk and manifest transactions signed, not broadcast
+    Build,
+    /// Dry run: the built transactions parsed back into the file
+    Parse,
+    /// Retrieved bytes compared with the canary
+    Verify,
+}
+
+const LIVE_STAGES: [Stage; 6] = [Stage::Config, Stage::Chain, Stage::Prepare, Stage::Upload, Stage::Download, Stage::Verify];
+const DRY_RUN_STAGES: [Stage; 6] = [Stage::Config, Stage::Chain, Stage::Prepare, Stage::Build, Stage::Parse, Stage::Verify];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageStatus {
+    Ok,
+    Failed,
+    /// Not run because an earlier stage failed
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageResult {
+    pub stage: Stage,
+    pub status: StageStatus,
+    pub duration_ms: u64,
+    pub message: Option<String>,
+    /// Set when the stage failed inside a job that recorded an error code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    /// Transactions the stage broadcast, or built in a dry run
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub txids: Vec<String>,
+}
+
+/// Why a stage failed
+struct StageError {
+    message: String,
+    error_code: Option<ErrorCode>,
+    job_id: Option<String>,
+}
+
+impl From<String> for StageError {
+    fn from(message: String) -> Self {
+        StageError { message, error_code: None, job_id: None }
+    }
+}
+
+impl From<&str> for StageError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl StageError {
+    /// The error a finished job recorded
+    fn from_job(job: &Job) -> Self {
+        StageError {
+            message: job.message.clone(),
+            error_code: job.error_code,
+            job_id: Some(job.id.clone()),
+        }
+    }
+}
+
+/// What a stage that passed reports
+struct StageOk {
+    message: String,
+    job_id: Option<String>,
+    txids: Vec<String>,
+}
+
+impl StageOk {
+    fn new(message: impl Into<String>) -> Self {
+        StageOk { message: message.into(), job_id: None, txids: Vec::new() }
+    }
+
+    fn with_job(mut self, job_id: &str) -> Self {
+        self.job_id = Some(job_id.to_string());
+        self
+    }
+
+    fn with_txids(mut self, txids: Vec<String>) -> Self {
+        self.txids = txids;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelftestReport {
+    pub success: bool,
+    pub network: Network,
+    pub dry_run: bool,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub canary_bytes: usize,
+    pub canary_sha256: Option<String>,
+    /// The stage to look at when the self-test failed
+    pub failed_stage: Option<Stage>,
+    pub stages: Vec<StageResult>,
+}
+
+impl SelftestReport {
+    fn new(network: Network, dry_run: bool) -> Self {
+        SelftestReport {
+            success: false,
+            network,
+            dry_run,
+            started_at: Utc::now(),
+            duration_ms: 0,
+            canary_bytes: 0,
+            canary_sha256: None,
+            failed_stage: None,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Record a stage that ran; false once one has failed
+    fn record(&mut self, stage: Stage, started: Instant, result: Result<StageOk, StageError>) -> bool {
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let (status, entry) = match result {
+            Ok(ok) => (
+                StageStatus::Ok,
+                StageResult {
+                    stage,
+                    status: StageStatus::Ok,
+                    duration_ms,
+                    message: Some(ok.message),
+                    error_code: None,
+                    job_id: ok.job_id,
+                    txids: ok.txids,
+                },
+            ),
+            Err(e) => (
+                StageStatus::Failed,
+                StageResult {
+                    stage,
+                    status: StageStatus::Failed,
+                    duration_ms,
+                    message: Some(e.message),
+                    error_code: e.error_code,
+                    job_id: e.job_id,
+                    txids: Vec::new(),
+                },
+            ),
+        };
+        if status == StageStatus::Failed {
+            self.failed_stage = Some(stage);
+        }
+        self.stages.push(entry);
+        status == StageStatus::Ok
+    }
+
+    /// Mark the stages that never ran and total up the run
+    fn finish(mut self, order: &[Stage], started: Instant) -> Self {
+        for stage in order.iter().skip(self.stages.len()) {
+            self.stages.push(StageResult {
+                stage: *stage,
+                status: StageStatus::Skipped,
+                duration_ms: 0,
+                message: None,
+                error_code: None,
+                job_id: None,
+                txids: Vec::new(),
+            });
+        }
+        self.success = self.failed_stage.is_none();
+        self.duration_ms = started.elapsed().as_millis() as u64;
+        self
+    }
+}
+
+/// Random bytes behind a FLAC marker. Random so no chunk matches one stored
+/// by an earlier upload and every chunk is really inscribed.
+fn canary_bytes(size: usize) -> Vec<u8> {
+    use rand::RngCore;
+
+    let mut data = vec![0u8; size.max(4)];
+    rand::rngs::OsRng.fill_bytes(&mut data);
+    data[..4].copy_from_slice(b"fLaC");
+    data
+}
+
+/// Canary size: the smallest that is chunked when `chunked`, otherwise one
+/// that fits a single transaction
+fn canary_size(bsv: &BsvService, network: Network, chunked: bool) -> usize {
+    if chunked {
+        bsv.flac_single_tx_max_bytes.min(bsv.flac_chunk_size(network)) + 1
+    } else {
+        SINGLE_TX_CANARY_BYTES
+    }
+}
+
+/// Run the self-test on `network`. `chunked` sizes the canary so the split,
+/// chunk and manifest path runs; without it a small single-transaction
+/// upload is tested.
+pub async fn run(state: &Arc<RwLock<AppState>>, network: Network, dry_run: bool, chunked: bool) -> SelftestReport {
+    let started = Instant::now();
+    let order: &[Stage] = if dry_run { &DRY_RUN_STAGES } else { &LIVE_STAGES };
+    let mut report = SelftestReport::new(network, dry_run);
+
+    // Config: the admin wallet pays for the canary
+    let t = Instant::now();
+    let wallet = {
+        let state = state.read().await;
+        crate::routes::admin::get_admin_wif_for_network(&state.db, network)
+    }
+    .ok_or_else(|| StageError::from(format!("Admin pay is not enabled with a WIF for {}", network)))
+    .and_then(|wif| {
+        BsvService::wif_to_address(&wif, network)
+            .map(|address| (wif, address))
+            .map_err(|e| format!("Invalid admin WIF: {}", e).into())
+    });
+    let (wif, address) = match wallet {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            report.record(Stage::Config, t, Err(e));
+            return report.finish(order, started);
+        }
+    };
+    report.record(Stage::Config, t, Ok(StageOk::new(format!("Admin wallet {}", address))));
+
+    // Chain: the provider answers for this network and sees the wallet
+    let t = Instant::now();
+    let chain = async {
+        let height = crate::get_chain_height(network).await.map_err(|e| format!("Chain height: {}", e))?;
+        let utxos = crate::get_address_utxos(state, &address, network)
+            .await
+            .map_err(|e| format!("Admin wallet UTXOs: {}", e))?;
+        Ok::<_, StageError>((height, utxos))
+    }
+    .await;
+    let utxos = match chain {
+        Ok((height, utxos)) => {
+            let balance: i64 = utxos.iter().map(|u| u.satoshis).sum();
+            let message = format!(
+                "Chain height {}, admin wallet holds {} sats in {} UTXOs",
+                height,
+                balance,
+                utxos.len()
+            );
+            report.record(Stage::Chain, t, Ok(StageOk::new(message)));
+            utxos
+        }
+        Err(e) => {
+            report.record(Stage::Chain, t, Err(e));
+            return report.finish(order, started);
+        }
+    };
+
+    // Prepare: quote the canary the way a user's upload is quoted
+    let t = Instant::now();
+    let (canary, filename, plan) = {
+        let state = state.read().await;
+        let canary = canary_bytes(canary_size(&state.bsv, network, chunked));
+        let plan = state.bsv.plan_flac_upload(canary.len(), network);
+        let filename = format!("nausica-selftest-{}.flac", Utc::now().format("%Y%m%d%H%M%S"));
+        (canary, filename, plan)
+    };
+    report.canary_bytes = canary.len();
+    report.canary_sha256 = Some(hex::encode(Sha256::digest(&canary)));
+    let required = plan.cost.to_sat_i64();
+    let prepared = async {
+        if chunked && !plan.chunked {
+            return Err(StageError::from(format!("A {} byte canary is not chunked on {}", canary.len(), network)));
+        }
+        state
+            .read()
+            .await
+            .config
+            .check_upload_limits(required, plan.chunk_count, plan.tx_outputs)?;
+        if dry_run {
+            // Nothing is spent, so only the funds matter, not the daily budget
+            // The split combines UTXOs when no single one is enough
+            let balance: i64 = utxos.iter().map(|u| u.satoshis).sum();
+            if balance < required {
+                return Err(format!("Admin UTXOs hold {} sats in total, the canary needs {}", balance, required).into());
+            }
+        } else {
+            let eligibility = crate::routes::admin::admin_pay_eligibility(state, network, Some(required)).await;
+            if let Some(reason) = eligibility.reason.filter(|_| !eligibility.eligible) {
+                return Err(reason.into());
+            }
+        }
+        Ok(StageOk::new(format!(
+            "{} byte canary in {} chunk(s), quoted {} sats",
+            canary.len(),
+            plan.chunk_count,
+            required
+        )))
+    }
+    .await;
+    if !report.record(Stage::Prepare, t, prepared) {
+        return report.finish(order, started);
+    }
+
+    let retrieved = if dry_run {
+        dry_run_stages(state, &mut report, &wif, &address, &utxos, &canary, &filename, plan.chunk_size).await
+    } else {
+        live_stages(state, &mut report, &wif, &address, &canary, &filename, required, network).await
+    };
+    let Some(retrieved) = retrieved else {
+        return report.finish(order, started);
+    };
+
+    // Verify: byte for byte what went in
+    let t = Instant::now();
+    let verified = if retrieved == canary {
+        Ok(StageOk::new(format!("{} bytes match the canary", retrieved.len())))
+    } else {
+        Err(StageError::from(format!(
+            "Retrieved {} bytes (sha256 {}) differ from the {} byte canary",
+            retrieved.len(),
+            hex::encode(Sha256::digest(&retrieved)),
+            canary.len()
+        )))
+    };
+    report.record(Stage::Verify, t, verified);
+    report.finish(order, started)
+}
+
+/// Upload the canary as an admin-pay job through the queue, then download
+/// it again. Returns the downloaded bytes.
+#[allow(clippy::too_many_arguments)]
+async fn live_stages(
+    state: &Arc<RwLock<AppState>>,
+    report: &mut SelftestReport,
+    wif: &str,
+    address: &str,
+    canary: &[u8],
+    filename: &str,
+    required: i64,
+    network: Network,
+) -> Option<Vec<u8>> {
+    // Upload
+    let t = Instant::now();
+    let uploaded = async {
+        let job_id = uuid::Uuid::new_v4().to_string().replace("-", "");
+        let job = Job::new_flac_upload(
+            job_id.clone(),
+            filename.to_string(),
+            canary.len() as i64,
+            canary.to_vec(),
+            address.to_string(),
+            wif.to_string(),
+            required,
+        )
+        .with_track_metadata(Some("Self-test canary".to_string()), None, None)
+        .with_network(network)
+        .with_status(JobStatus::Processing, MessageKey::AdminPayStarting);
+        {
+            let state = state.read().await;
+            state.db.insert_job(&job).map_err(|e| format!("Failed to create job: {}", e))?;
+            crate::enqueue_job(&state, QueuedJob {
+                job_id: job_id.clone(),
+                job_type: JobType::FlacUpload,
+                address: address.to_string(),
+                network,
+                admin_pay: true,
+                file_size: canary.len() as i64,
+                paid_satoshis: None,
+            });
+        }
+
+        let job = wait_for_job(state, &job_id, UPLOAD_TIMEOUT).await?;
+        if job.status != JobStatus::Complete {
+            return Err(StageError::from_job(&job));
+        }
+        let manifest_txid = job
+            .manifest_txid
+            .clone()
+            .ok_or_else(|| StageError::from("Upload completed without a txid"))?;
+        let mut txids: Vec<String> = job
+            .split_txid
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect();
+        txids.extend(job.chunk_txid_list().unwrap_or_default());
+        txids.push(manifest_txid.clone());
+        let ok = StageOk::new(format!("Inscribed as {}", manifest_txid))
+            .with_job(&job_id)
+            .with_txids(txids);
+        Ok((manifest_txid, ok))
+    }
+    .await;
+    let manifest_txid = match uploaded {
+        Ok((manifest_txid, ok)) => {
+            report.record(Stage::Upload, t, Ok(ok));
+            manifest_txid
+        }
+        Err(e) => {
+            report.record(Stage::Upload, t, Err(e));
+            return None;
+        }
+    };
+
+    // Download, in this task: it only reads from the chain
+    let t = Instant::now();
+    let downloaded = async {
+        let job_id = uuid::Uuid::new_v4().to_string().replace("-", "");
+        let job = Job::new_flac_download(job_id.clone(), manifest_txid.clone())
+            .with_status(JobStatus::Processing, MessageKey::StartingFlacDownload)
+            .with_network(network);
+        state
+            .read()
+            .await
+            .db
+            .insert_job(&job)
+            .map_err(|e| format!("Failed to create job: {}", e))?;
+        crate::process_flac_download(state.clone(), job_id.clone(), Some(manifest_txid.clone()), network).await;
+
+        let job = state
+            .read()
+            .await
+            .db
+            .get_job(&job_id)
+            .map_err(|e| format!("Database error: {}", e))?
+            .ok_or_else(|| StageError::from("Download job disappeared"))?;
+        let saved = match (&job.status, &job.filename) {
+            (JobStatus::Complete, Some(saved)) => saved.clone(),
+            _ => return Err(StageError::from_job(&job)),
+        };
+        // The canary is only needed for the comparison, so it does not linger in downloads
+        let path = std::path::Path::new(crate::routes::download::DOWNLOADS_DIR).join(&saved);
+        let data = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", saved, e))?;
+        let _ = std::fs::remove_file(&path);
+        Ok((data, job_id))
+    }
+    .await;
+    match downloaded {
+        Ok((data, job_id)) => {
+            let ok = StageOk::new(format!("Downloaded {} bytes", data.len())).with_job(&job_id);
+            report.record(Stage::Download, t, Ok(ok));
+            Some(data)
+        }
+        Err(e) => {
+            report.record(Stage::Download, t, Err(e));
+            None
+        }
+    }
+}
+
+/// Poll a job until it finishes or `timeout` passes
+async fn wait_for_job(state: &Arc<RwLock<AppState>>, job_id: &str, timeout: Duration) -> Result<Job, StageError> {
+    let started = Instant::now();
+    loop {
+        tokio::time::sleep(UPLOAD_POLL_INTERVAL).await;
+        let job = state
+            .read()
+            .await
+            .db
+            .get_job(job_id)
+            .map_err(|e| format!("Database error: {}", e))?
+            .ok_or_else(|| StageError::from("Upload job disappeared"))?;
+        if matches!(job.status, JobStatus::Complete | JobStatus::Error | JobStatus::Cancelled) {
+            return Ok(job);
+        }
+        if started.elapsed() >= timeout {
+            return Err(StageError {
+                message: format!("Still running after {} seconds: {}", timeout.as_secs(), job.message),
+                error_code: None,
+                job_id: Some(job.id),
+            });
+        }
+    }
+}
+
+/// Build and sign the split, chunk and manifest transactions from the
+/// admin wallet's UTXOs, then read the file back out of them.
+/// Nothing is broadcast. Returns the parsed bytes.
+#[allow(clippy::too_many_arguments)]
+async fn dry_run_stages(
+    state: &Arc<RwLock<AppState>>,
+    report: &mut SelftestReport,
+    wif: &str,
+    address: &str,
+    utxos: &[crate::services::bitails::Utxo],
+    canary: &[u8],
+    filename: &str,
+    chunk_size: usize,
+) -> Option<Vec<u8>> {
+    // Build
+    let t = Instant::now();
+    let built = {
+        let state = state.read().await;
+        build_transactions(&state, wif, address, utxos, canary, filename, chunk_size)
+    };
+    let (chunk_txs, manifest_tx) = match built {
+        Ok((split_txids, chunk_txs, manifest_tx)) => {
+            let mut txids = split_txids;
+            txids.extend(chunk_txs.iter().map(|(txid, _)| txid.clone()));
+            txids.push(manifest_tx.0.clone());
+            let message = format!(
+                "Signed {} transactions ({} chunks) without broadcasting them",
+                txids.len(),
+                chunk_txs.len()
+            );
+            report.record(Stage::Build, t, Ok(StageOk::new(message).with_txids(txids)));
+            (chunk_txs, manifest_tx)
+        }
+        Err(e) => {
+            report.record(Stage::Build, t, Err(e.into()));
+            return None;
+        }
+    };
+
+    // Parse: read the transactions the way a download reads them from the chain
+    let t = Instant::now();
+    let parsed = (|| {
+        let manifest = extract_flac_manifest_from_tx(&manifest_tx.1).ok_or("The manifest transaction does not parse")?;
+        let built_txids: Vec<String> = chunk_txs.iter().map(|(txid, _)| txid.clone()).collect();
+        if manifest.chunk_txids != built_txids {
+            return Err("The manifest lists other chunk txids than were built".to_string());
+        }
+        if manifest.chunk_hashes.len() != built_txids.len() {
+            return Err("The manifest does not carry a hash for every chunk".to_string());
+        }
+        let chunks = chunk_txs
+            .iter()
+            .enumerate()
+            .map(|(i, (_, raw_tx))| {
+                extract_flac_chunk_from_tx(raw_tx).ok_or_else(|| format!("Chunk {} does not parse", i + 1))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if let Some((index, _)) = chunks.iter().find(|(index, data)| !chunk_matches(&manifest.chunk_hashes, *index, data)) {
+            return Err(format!("Chunk {} does not match its hash in the manifest", index));
+        }
+        let data = assemble_chunks(chunks, built_txids.len()).map_err(|e| format!("Chunk indices do not line up: {:?}", e))?;
+        Ok((data, format!("Manifest for {} reassembles {} chunks", manifest.filename, built_txids.len())))
+    })();
+    match parsed {
+        Ok((data, message)) => {
+            report.record(Stage::Parse, t, Ok(StageOk::new(message)));
+            Some(data)
+        }
+        Err(e) => {
+            report.record(Stage::Parse, t, Err(e.into()));
+            None
+        }
+    }
+}
+
+/// (split txids, (txid, raw tx) of each chunk, (txid, raw tx) of the manifest)
+type BuiltUpload = (Vec<String>, Vec<(String, String)>, (String, String));
+
+/// The transactions a chunked upload of `canary` broadcasts, as
+/// process_flac_upload builds them
+fn build_transactions(
+    state: &AppState,
+    wif: &str,
+    address: &str,
+    utxos: &[crate::services::bitails::Utxo],
+    canary: &[u8],
+    filename: &str,
+    chunk_size: usize,
+) -> Result<BuiltUpload, String> {
+    let bsv = &state.bsv;
+    let script_pubkey = BsvService::create_p2pkh_script(address)?;
+    let chunks: Vec<&[u8]> = canary.chunks(chunk_size).collect();
+    let satoshis_per_output = bsv.calculate_chunk_output_satoshis(chunk_size);
+    if utxos.is_empty() {
+        return Err("The admin wallet has no UTXOs".to_string());
+    }
+
+    // Split: one output per chunk plus the manifest's
+    let candidates: Vec<(String, u32, i64)> = utxos.iter().map(|u| (u.txid.clone(), u.vout, u.satoshis)).collect();
+    let inputs = bsv
+        .select_split_inputs(&candidates, chunks.len() + 1, satoshis_per_output, Amount::ZERO)
+        .map_err(|e| format!("Split: insufficient funds: {} < {}", e.available, e.required))?;
+    let split = bsv
+        .create_split_transactions(wif, &inputs, &script_pubkey, chunks.len() + 1, satoshis_per_output, Amount::ZERO)
+        .map_err(|e| format!("Split: {}", e))?;
+    let split_txids = split.transactions.iter().map(|(txid, _)| txid.clone()).collect();
+
+    let spend = |vout: usize, script: Vec<u8>| -> Result<(String, String), String> {
+        let (txid, index) = split.outputs[vout].clone();
+        let input = vec![(txid, index, satoshis_per_output.to_sat_i64(), script_pubkey.clone())];
+        let raw_tx = bsv.create_transaction(wif, &input, &[(script, bsv.data_output_satoshis)])?;
+        Ok((BsvService::txid(&raw_tx)?, raw_tx))
+    };
+
+    let chunk_txs = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let script = bsv.create_flac_chunk_script(i as u32, chunks.len() as u32, chunk);
+            spend(i, script).map_err(|e| format!("Chunk {}: {}", i + 1, e))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let layout = crate::services::bsv::ManifestLayout::from_str(&state.config.manifest_metadata_layout)
+        .unwrap_or(crate::services::bsv::ManifestLayout::Json);
+    let chunk_txids: Vec<String> = chunk_txs.iter().map(|(txid, _)| txid.clone()).collect();
+    let chunk_hashes: Vec<String> = chunks.iter().map(|chunk| hex::encode(Sha256::digest(chunk))).collect();
+    let manifest_script = BsvService::create_flac_manifest_script(
+        filename,
+        canary.len(),
+        &chunk_txids,
+        &chunk_hashes,
+        Some("Self-test canary"),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        layout,
+    );
+    let manifest_tx = spend(chunks.len(), manifest_script).map_err(|e| format!("Manifest: {}", e))?;
+
+    Ok((split_txids, chunk_txs, manifest_tx))
+}
+
+/// `upfile-protocol selftest [--network testnet] [--dry-run] [--single-tx] [--url URL]`:
+/// run the self-test on the server at URL (default this instance's port on
+/// localhost) with ADMIN_KEY, print the report and exit non-zero on failure
+pub async fn cli(args: &[String], port: u16) -> i32 {
+    let mut network = Network::Testnet;
+    let mut dry_run = false;
+    let mut chunked = true;
+    let mut url = format!("http://127.0.0.1:{}", port);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--network" => match args.next().and_then(|n| Network::from_str(n)) {
+                Some(n) => network = n,
+                None => {
+                    eprintln!("--network needs mainnet, testnet or stn");
+                    return 2;
+                }
+            },
+            "--dry-run" => dry_run = true,
+            "--single-tx" => chunked = false,
+            "--url" => match args.next() {
+                Some(u) => url = u.trim_end_matches('/').to_string(),
+                None => {
+                    eprintln!("--url needs the server's base URL");
+                    return 2;
+                }
+            },
+            other => {
+                eprintln!("Unknown selftest option {}", other);
+                return 2;
+            }
+        }
+    }
+
+    let body = serde_json::json!({
+        "key": std::env::var("ADMIN_KEY").unwrap_or_else(|_| "nausica-admin-2024".to_string()),
+        "network": network,
+        "dry_run": dry_run,
+        "chunked": chunked,
+    });
+    // Straight to the server, never through OUTBOUND_PROXY_URL
+    let response = match reqwest::Client::new()
+        .post(format!("{}/api/admin/selftest", url))
+        .json(&body)
+        .send()
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Self-test request to {} failed: {}", url, e);
+            return 2;
+        }
+    };
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    let json = serde_json::from_str::<serde_json::Value>(&text).ok();
+    match &json {
+        Some(json) => println!("{}", serde_json::to_string_pretty(json).unwrap_or_else(|_| text.clone())),
+        None => println!("{}", text),
+    }
+    if !status.is_success() {
+        return 2;
+    }
+    match json.and_then(|j| j["report"]["success"].as_bool()) {
+        Some(true) => 0,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{test_config, test_state, test_state_with, whatsonchain_chain, whatsonchain_fund};
+
+    /// A testnet admin wallet holding `satoshis` on the WhatsOnChain stand-in
+    async fn fund_admin_wallet(state: &Arc<RwLock<AppState>>, satoshis: i64) {
+        let (wif, address) = BsvService::generate_keypair(Network::Testnet);
+        whatsonchain_fund(&address, satoshis);
+        let state = state.read().await;
+        let mut admin_config = state.db.get_admin_config().unwrap();
+        admin_config.admin_pay_testnet = true;
+        admin_config.testnet_wif = Some(wif);
+        state.db.update_admin_config(&admin_config).unwrap();
+    }
+
+    /// State whose canary is chunked yet small, quoted under the cost limit
+    async fn canary_state() -> Arc<RwLock<AppState>> {
+        let mut config = test_config();
+        config.max_upload_cost_satoshis = 100_000_000;
+        let state = test_state_with(config);
+        state.write().await.bsv.flac_single_tx_max_bytes = 2000;
+        state
+    }
+
+    fn statuses(report: &SelftestReport) -> Vec<(Stage, StageStatus)> {
+        report.stages.iter().map(|s| (s.stage, s.status)).collect()
+    }
+
+    #[tokio::test]
+    async fn missing_admin_wallet_fails_at_config() {
+        let report = run(&test_state(), Network::Testnet, true, true).await;
+        assert!(!report.success);
+        assert_eq!(report.failed_stage, Some(Stage::Config));
+        let expected: Vec<_> = DRY_RUN_STAGES
+            .iter()
+            .map(|&stage| (stage, if stage == Stage::Config { StageStatus::Failed } else { StageStatus::Skipped }))
+            .collect();
+        assert_eq!(statuses(&report), expected);
+    }
+
+    #[tokio::test]
+    async fn underfunded_dry_run_fails_at_prepare() {
+        let state = canary_state().await;
+        fund_admin_wallet(&state, 1).await;
+        let report = run(&state, Network::Testnet, true, true).await;
+        assert_eq!(report.failed_stage, Some(Stage::Prepare));
+        assert_eq!(report.stages[1].status, StageStatus::Ok);
+        assert!(report.stages[2].message.as_deref().unwrap().contains("the canary needs"));
+        assert!(report.stages[3..].iter().all(|s| s.status == StageStatus::Skipped));
+    }
+
+    #[tokio::test]
+    async fn dry_run_parses_the_canary_back_without_broadcasting() {
+        let state = canary_state().await;
+        fund_admin_wallet(&state, 10_000_000).await;
+        let report = run(&state, Network::Testnet, true, true).await;
+
+        assert!(report.success, "{:?}", report);
+        assert_eq!(report.canary_bytes, 2001);
+        assert!(report.stages.iter().all(|s| s.status == StageStatus::Ok));
+        let built = &report.stages.iter().find(|s| s.stage == Stage::Build).unwrap().txids;
+        // A split, one chunk and the manifest
+        assert_eq!(built.len(), 3);
+        assert!(built.iter().all(|txid| whatsonchain_chain().tx(txid).is_none()));
+    }
+
+    #[tokio::test]
+    async fn live_run_uploads_and_downloads_the_canary() {
+        let state = canary_state().await;
+        fund_admin_wallet(&state, 10_000_000).await;
+        // Stands in for the job dispatcher, running the canary upload
+        let scheduler = state.read().await.scheduler.clone();
+        let dispatcher = state.clone();
+        tokio::spawn(async move {
+            let (job, _permit) = scheduler.next().await;
+            crate::run_job_guarded(dispatcher, job).await;
+        });
+        let report = run(&state, Network::Testnet, false, true).await;
+
+        assert!(report.success, "{:?}", report);
+        assert_eq!(statuses(&report), LIVE_STAGES.iter().map(|&stage| (stage, StageStatus::Ok)).collect::<Vec<_>>());
+        let upload = &report.stages[3];
+        assert_eq!(upload.txids.len(), 3);
+        assert!(upload.txids.iter().all(|txid| whatsonchain_chain().tx(txid).is_some()));
+        let job = state.read().await.db.get_job(upload.job_id.as_deref().unwrap()).unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Complete);
+    }
+}
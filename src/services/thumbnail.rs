@@ -0,0 +1,34 @@
+// Cover thumbnails
+// Listings only need a small cover, but the stored cover is the full image.
+// Uploads keep a small JPEG next to it, inline in the track metadata when it
+// is small enough, so a listing can show the cover without fetching the
+// full image from the chain.
+
+use image::codecs::jpeg::JpegEncoder;
+
+/// Longest side of a thumbnail, in pixels
+pub const MAX_DIMENSION: u32 = 200;
+
+/// JPEG quality of a thumbnail
+const JPEG_QUALITY: u8 = 80;
+
+/// Thumbnails up to this size go in the track metadata as base64; larger
+/// ones are inscribed as a coverart transaction of their own
+pub const INLINE_MAX_BYTES: usize = 20 * 1024;
+
+/// A JPEG thumbnail of `image` no larger than `MAX_DIMENSION` on either side.
+/// None if the image can't be decoded, or if the thumbnail would not be
+/// smaller than the image itself, in which case the full cover serves as
+/// its own thumbnail.
+pub fn make_thumbnail(image: &[u8]) -> Option<Vec<u8>> {
+    let decoded = image::load_from_memory(image).ok()?;
+    let thumb = decoded.thumbnail(MAX_DIMENSION, MAX_DIMENSION).to_rgb8();
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY).encode_image(&thumb).ok()?;
+    (jpeg.len() < image.len()).then_some(jpeg)
+}
+
+/// Whether a thumbnail of `thumb_bytes` is kept inline in the track metadata
+pub fn fits_inline(thumb_bytes: usize) -> bool {
+    thumb_bytes <= INLINE_MAX_BYTES
+}
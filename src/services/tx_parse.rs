@@ -0,0 +1,1157 @@
+// Transaction parsing
+// Reads raw transactions and the scripts the upload protocols write: upfile
+// OP_RETURN data, flacstore envelopes (single-tx files, chunks, manifests, lyrics
+// and cover updates) and cover art. The matching script builders live on
+// BsvService.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::models::Network;
+use crate::services::bsv::{BsvService, MANIFEST_LABELS};
+use crate::services::lyrics::LyricsFormat;
+
+/// Transaction input as parsed from raw bytes
+#[derive(Debug, Clone)]
+pub struct ParsedTxInput {
+    pub prev_txid: String,
+    pub prev_vout: u32,
+    pub script_sig: Vec<u8>,
+}
+
+/// Transaction output as parsed from raw bytes
+#[derive(Debug, Clone)]
+pub struct ParsedTxOutput {
+    pub satoshis: i64,
+    pub script: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsedTx {
+    pub version: u32,
+    pub inputs: Vec<ParsedTxInput>,
+    pub outputs: Vec<ParsedTxOutput>,
+    pub lock_time: u32,
+}
+
+/// Parse a raw transaction into its inputs and outputs
+pub fn parse_transaction(tx_hex: &str) -> Option<ParsedTx> {
+    let tx_bytes = hex::decode(tx_hex.trim()).ok()?;
+
+    let version = u32::from_le_bytes(tx_bytes.get(0..4)?.try_into().ok()?);
+    let mut i = 4;
+
+    let (input_count, varint_size) = read_varint(tx_bytes.get(i..)?)?;
+    i += varint_size;
+
+    let mut inputs = Vec::new();
+    for _ in 0..input_count {
+        let mut prev_txid = tx_bytes.get(i..i + 32)?.to_vec();
+        prev_txid.reverse();
+        i += 32;
+        let prev_vout = u32::from_le_bytes(tx_bytes.get(i..i + 4)?.try_into().ok()?);
+        i += 4;
+        let (script_len, vs) = read_varint(tx_bytes.get(i..)?)?;
+        i += vs;
+        // A length past the end of the transaction can't be trusted to add up
+        let script_end = i.checked_add(usize::try_from(script_len).ok()?)?;
+        let script_sig = tx_bytes.get(i..script_end)?.to_vec();
+        i = script_end + 4; // sequence
+        inputs.push(ParsedTxInput {
+            prev_txid: hex::encode(prev_txid),
+            prev_vout,
+            script_sig,
+        });
+    }
+
+    let (output_count, varint_size) = read_varint(tx_bytes.get(i..)?)?;
+    i += varint_size;
+
+    let mut outputs = Vec::new();
+    for _ in 0..output_count {
+        let satoshis = i64::from_le_bytes(tx_bytes.get(i..i + 8)?.try_into().ok()?);
+        i += 8;
+        let (script_len, vs) = read_varint(tx_bytes.get(i..)?)?;
+        i += vs;
+        let script_end = i.checked_add(usize::try_from(script_len).ok()?)?;
+        let script = tx_bytes.get(i..script_end)?.to_vec();
+        i = script_end;
+        outputs.push(ParsedTxOutput { satoshis, script });
+    }
+
+    let lock_time = u32::from_le_bytes(tx_bytes.get(i..i + 4)?.try_into().ok()?);
+
+    Some(ParsedTx { version, inputs, outputs, lock_time })
+}
+
+/// Whether `tx_hex` parses as a transaction whose txid is `txid`
+pub fn is_tx_hex_for(tx_hex: &str, txid: &str) -> bool {
+    parse_transaction(tx_hex).is_some()
+        && BsvService::txid(tx_hex).is_ok_and(|id| id.eq_ignore_ascii_case(txid))
+}
+
+/// Extract the public key from a standard P2PKH scriptSig (<sig> <pubkey>)
+pub fn extract_pubkey_from_script_sig(script_sig: &[u8]) -> Option<Vec<u8>> {
+    let (_sig, consumed) = read_push_data(script_sig)?;
+    let (pubkey, _) = read_push_data(script_sig.get(consumed..)?)?;
+    match (pubkey.len(), pubkey.first()) {
+        (33, Some(0x02)) | (33, Some(0x03)) | (65, Some(0x04)) => Some(pubkey.to_vec()),
+        _ => None,
+    }
+}
+
+/// First output script of the transaction that `parse` accepts
+pub fn find_in_outputs<T>(tx_hex: &str, parse: impl Fn(&[u8]) -> Option<T>) -> Option<T> {
+    parse_transaction(tx_hex)?
+        .outputs
+        .iter()
+        .find_map(|output| parse(&output.script))
+}
+
+/// Body of an OP_FALSE OP_IF envelope
+fn envelope_body(script: &[u8]) -> Option<&[u8]> {
+    script.strip_prefix(&[0x00, 0x63]).filter(|body| !body.is_empty())
+}
+
+/// Body of an OP_RETURN or OP_FALSE OP_RETURN script
+fn op_return_body(script: &[u8]) -> Option<&[u8]> {
+    script
+        .strip_prefix(&[0x00, 0x6a])
+        .or_else(|| script.strip_prefix(&[0x6a]))
+        .filter(|body| !body.is_empty())
+}
+
+/// File in an upfile OP_RETURN output script
+pub fn parse_upfile_output(script: &[u8]) -> Option<UpfileFile> {
+    op_return_body(script).and_then(parse_op_return_script)
+}
+
+/// What a FLAC download transaction holds
+pub enum FlacData {
+    /// Manifest of a multi-chunk upload
+    Manifest(ManifestMetadata),
+    /// Whole file of a single-transaction upload
+    File(FlacFile),
+}
+
+/// Manifest of a multi-chunk FLAC upload in an output script
+pub fn parse_flac_manifest_output(script: &[u8]) -> Option<ManifestMetadata> {
+    envelope_body(script).and_then(parse_flac_manifest_script)
+}
+
+/// FLAC manifest or single-transaction file in an output script
+pub fn parse_flac_output(script: &[u8]) -> Option<FlacData> {
+    let body = envelope_body(script)?;
+    parse_flac_manifest_script(body)
+        .map(FlacData::Manifest)
+        .or_else(|| parse_flac_store_script(body).map(FlacData::File))
+}
+
+/// File of an upfile OP_RETURN transaction
+pub fn extract_op_return_from_tx(tx_hex: &str) -> Option<UpfileFile> {
+    find_in_outputs(tx_hex, parse_upfile_output)
+}
+
+/// Manifest of a multi-chunk FLAC upload
+pub fn extract_flac_manifest_from_tx(tx_hex: &str) -> Option<ManifestMetadata> {
+    find_in_outputs(tx_hex, parse_flac_manifest_output)
+}
+
+/// Index and data of one FLAC chunk transaction
+pub fn extract_flac_chunk_from_tx(tx_hex: &str) -> Option<(u32, Vec<u8>)> {
+    find_in_outputs(tx_hex, |script| envelope_body(script).and_then(parse_flac_chunk_script))
+}
+
+/// File and track fields of a single-transaction FLAC upload
+pub fn extract_flac_from_tx(tx_hex: &str) -> Option<FlacFile> {
+    find_in_outputs(tx_hex, |script| envelope_body(script).and_then(parse_flac_store_script))
+}
+
+/// Cover image in a coverart envelope, or in an older OP_RETURN cover
+pub fn parse_image_output(script: &[u8]) -> Option<Vec<u8>> {
+    envelope_body(script)
+        .and_then(parse_coverart_script)
+        .or_else(|| op_return_body(script).and_then(parse_image_script))
+}
+
+/// Manifest metadata structure
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestMetadata {
+    pub filename: String,
+    pub size: Option<usize>,
+    pub chunk_txids: Vec<String>,
+    /// Hex SHA-256 of each chunk's data, by chunk index; empty for manifests
+    /// that predate them
+    pub chunk_hashes: Vec<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub lyrics: Option<String>,
+    pub lyrics_format: LyricsFormat,
+    /// flacstore-lyrics transaction holding lyrics too long to keep inline
+    pub lyrics_txid: Option<String>,
+    pub cover_txid: Option<String>,
+    /// Coverart transaction holding a small version of the cover
+    pub thumb_txid: Option<String>,
+    /// Small version of the cover as base64 JPEG
+    pub thumb_inline: Option<String>,
+}
+
+/// Parse the body of a flacstore-manifest envelope
+pub fn parse_flac_manifest_script(script: &[u8]) -> Option<ManifestMetadata> {
+    let push_data_items = read_pushes(script)?;
+
+    if push_data_items.len() < 3 {
+        return None;
+    }
+
+    let protocol = String::from_utf8_lossy(&push_data_items[0]);
+    if protocol != "flacstore-manifest" {
+        return None;
+    }
+
+    let filename = String::from_utf8_lossy(&push_data_items[1]).to_string();
+
+    // Parse metadata JSON; track fields live here in the JSON layout
+    let metadata_str = String::from_utf8_lossy(&push_data_items[2]);
+    let metadata_json = serde_json::from_str::<serde_json::Value>(&metadata_str).ok();
+    let size = metadata_json
+        .as_ref()
+        .and_then(|m| m["size"].as_u64())
+        .map(|s| s as usize);
+    let mut fields: HashMap<String, String> = HashMap::new();
+    if let Some(metadata) = &metadata_json {
+        for label in MANIFEST_LABELS {
+            if let Some(value) = metadata[label].as_str().filter(|s| !s.is_empty()) {
+                fields.insert(label.to_string(), value.to_string());
+            }
+        }
+    }
+
+    // In the labeled layout, track fields follow as label/value pushes
+    let mut next = 3;
+    while next + 1 < push_data_items.len() {
+        let label = String::from_utf8_lossy(&push_data_items[next]);
+        if !MANIFEST_LABELS.contains(&label.as_ref()) {
+            break;
+        }
+        let value = String::from_utf8_lossy(&push_data_items[next + 1]).to_string();
+        if !value.is_empty() {
+            fields.insert(label.to_string(), value);
+        }
+        next += 2;
+    }
+
+    let title = fields.remove("title");
+    let artist = fields.remove("artist");
+    let lyrics = fields.remove("lyrics");
+    let cover_txid = fields.remove("cover_txid");
+    let lyrics_txid = fields.remove("lyrics_txid");
+    let thumb_txid = fields.remove("thumb_txid");
+    let thumb_inline = fields.remove("thumb_inline");
+
+    // Manifests before v1.3 carry no lyrics_format
+    let lyrics_format = lyrics_format_or_detect(fields.remove("lyrics_format"), lyrics.as_deref());
+
+    let chunk_txids: Vec<String> = push_data_items[next..]
+        .iter()
+        .map(|data| String::from_utf8_lossy(data).to_string())
+        .collect();
+
+    if chunk_txids.is_empty() {
+        return None;
+    }
+
+    // Hashes that don't line up with the chunks can't say which one is corrupt
+    let chunk_hashes: Vec<String> = metadata_json
+        .as_ref()
+        .and_then(|m| m["chunk_hashes"].as_array())
+        .map(|hashes| hashes.iter().filter_map(|h| h.as_str().map(str::to_string)).collect())
+        .filter(|hashes: &Vec<String>| hashes.len() == chunk_txids.len())
+        .unwrap_or_default();
+
+    Some(ManifestMetadata {
+        filename,
+        size,
+        chunk_txids,
+        chunk_hashes,
+        title,
+        artist,
+        lyrics,
+        lyrics_format,
+        lyrics_txid,
+        cover_txid,
+        thumb_txid,
+        thumb_inline,
+    })
+}
+
+/// Parse the body of a flacstore-chunk envelope into (chunk index, chunk data)
+pub fn parse_flac_chunk_script(script: &[u8]) -> Option<(u32, Vec<u8>)> {
+    let push_data_items = read_pushes(script)?;
+
+    if push_data_items.len() < 3 || push_data_items[0] != b"flacstore-chunk" {
+        return None;
+    }
+
+    // The index is written as decimal text
+    let index = std::str::from_utf8(&push_data_items[1]).ok()?.parse().ok()?;
+
+    // Chunk data may span several pushes
+    Some((index, push_data_items[2..].concat()))
+}
+
+/// Declared lyrics format, or the one detected from the lyrics text when
+/// the metadata predates the field
+fn lyrics_format_or_detect(declared: Option<String>, lyrics: Option<&str>) -> LyricsFormat {
+    declared
+        .and_then(|f| LyricsFormat::from_str(&f))
+        .unwrap_or_else(|| {
+            lyrics
+                .map(crate::services::lyrics::detect_format)
+                .unwrap_or(LyricsFormat::Plain)
+        })
+}
+
+/// A single-transaction FLAC upload: the file and the track fields of its metadata JSON
+#[derive(Debug, Clone)]
+pub struct FlacFile {
+    pub data: Vec<u8>,
+    pub filename: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub lyrics: Option<String>,
+    pub lyrics_format: LyricsFormat,
+    pub lyrics_txid: Option<String>,
+    pub cover_txid: Option<String>,
+    pub thumb_txid: Option<String>,
+    pub thumb_inline: Option<String>,
+}
+
+/// Chunk indices that kept a download from covering its manifest exactly once each
+#[derive(Debug, Default)]
+pub struct ChunkIndexMismatch {
+    pub missing: Vec<u32>,
+    pub duplicated: Vec<u32>,
+    /// Indices at or past the manifest's chunk count
+    pub unexpected: Vec<u32>,
+}
+
+/// Whether chunk `index` holds the data its manifest hashed. Chunks of
+/// manifests without `chunk_hashes` always match.
+pub fn chunk_matches(chunk_hashes: &[String], index: u32, data: &[u8]) -> bool {
+    match chunk_hashes.get(index as usize) {
+        Some(hash) => hex::encode(Sha256::digest(data)).eq_ignore_ascii_case(hash),
+        None => chunk_hashes.is_empty(),
+    }
+}
+
+/// Join chunks in the order of their inscribed indices, whatever order the
+/// manifest listed or the download fetched them in. Every index below
+/// `count` must appear exactly once.
+pub fn assemble_chunks(chunks: Vec<(u32, Vec<u8>)>, count: usize) -> Result<Vec<u8>, ChunkIndexMismatch> {
+    let mut slots: Vec<Option<Vec<u8>>> = vec![None; count];
+    let mut mismatch = ChunkIndexMismatch::default();
+
+    for (index, data) in chunks {
+        match slots.get_mut(index as usize) {
+            None => mismatch.unexpected.push(index),
+            Some(Some(_)) => mismatch.duplicated.push(index),
+            Some(slot) => *slot = Some(data),
+        }
+    }
+    mismatch.missing = (0..count as u32).filter(|i| slots[*i as usize].is_none()).collect();
+
+    if mismatch.missing.is_empty() && mismatch.duplicated.is_empty() && mismatch.unexpected.is_empty() {
+        Ok(slots.into_iter().flatten().flatten().collect())
+    } else {
+        mismatch.duplicated.sort_unstable();
+        mismatch.duplicated.dedup();
+        mismatch.unexpected.sort_unstable();
+        mismatch.unexpected.dedup();
+        Err(mismatch)
+    }
+}
+
+/// Parse the body of a single-transaction flacstore envelope
+pub fn parse_flac_store_script(script: &[u8]) -> Option<FlacFile> {
+    let push_data_items = read_pushes(script)?;
+
+    if push_data_items.len() < 4 || push_data_items[0] != b"flacstore" {
+        return None;
+    }
+
+    // Track fields were added in metadata v1.1; older uploads only name the file
+    let metadata_str = String::from_utf8_lossy(&push_data_items[2]);
+    let metadata = serde_json::from_str::<serde_json::Value>(&metadata_str).ok();
+    let field = |label: &str| {
+        metadata
+            .as_ref()
+            .and_then(|m| m[label].as_str())
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+    };
+
+    let lyrics = field("lyrics");
+    Some(FlacFile {
+        data: push_data_items[3..].concat(),
+        filename: field("filename").unwrap_or_else(|| "audio.flac".to_string()),
+        title: field("title"),
+        artist: field("artist"),
+        lyrics_format: lyrics_format_or_detect(field("lyrics_format"), lyrics.as_deref()),
+        lyrics,
+        lyrics_txid: field("lyrics_txid"),
+        cover_txid: field("cover_txid"),
+        thumb_txid: field("thumb_txid"),
+        thumb_inline: field("thumb_inline"),
+    })
+}
+
+/// Lyrics and their format in a flacstore-lyrics output script
+pub fn parse_lyrics_output(script: &[u8]) -> Option<(String, LyricsFormat)> {
+    envelope_body(script).and_then(parse_lyrics_script)
+}
+
+/// Parse the body of a flacstore-lyrics envelope
+fn parse_lyrics_script(script: &[u8]) -> Option<(String, LyricsFormat)> {
+    let push_data_items = read_pushes(script)?;
+
+    if push_data_items.len() < 3 || push_data_items[0] != b"flacstore-lyrics" {
+        return None;
+    }
+
+    // Lyrics may span several pushes; split UTF-8 sequences rejoin here
+    let lyrics = String::from_utf8(push_data_items[2..].concat()).ok()?;
+    let format = lyrics_format_or_detect(Some(String::from_utf8_lossy(&push_data_items[1]).to_string()), Some(&lyrics));
+    Some((lyrics, format))
+}
+
+/// File stored by an upfile transaction
+#[derive(Debug, PartialEq, Eq)]
+pub struct UpfileFile {
+    pub data: Vec<u8>,
+    pub filename: String,
+    /// MIME type the uploader recorded for the file
+    pub mime_type: String,
+}
+
+/// Parse the body of an upfile OP_RETURN. The layout is the one
+/// BsvService::create_upfile_script writes: protocol, MIME type, filename,
+/// data pushes.
+pub fn parse_op_return_script(script: &[u8]) -> Option<UpfileFile> {
+    let push_data_items: Vec<&[u8]> = PushDataIter::new(script).collect::<Option<_>>()?;
+
+    let [_protocol, mime_type, filename, data @ ..] = push_data_items.as_slice() else {
+        return None;
+    };
+    if data.is_empty() {
+        return None;
+    }
+
+    Some(UpfileFile {
+        data: data.concat(),
+        filename: String::from_utf8_lossy(filename).to_string(),
+        mime_type: String::from_utf8_lossy(mime_type).to_string(),
+    })
+}
+
+/// Parse cover art script in OP_FALSE OP_IF "coverart" <data chunks> OP_ENDIF format
+pub fn parse_coverart_script(script: &[u8]) -> Option<Vec<u8>> {
+    let mut pushes = PushDataIter::new(script);
+    if pushes.next()?? != b"coverart" {
+        return None;
+    }
+
+    // Image data chunks up to OP_ENDIF; a malformed tail ends the image
+    let image_data: Vec<u8> = pushes.map_while(|push| push).flatten().copied().collect();
+
+    if image_data.is_empty() {
+        None
+    } else {
+        Some(image_data)
+    }
+}
+
+/// Parse an older OP_RETURN cover: an optional protocol push, then the image
+pub fn parse_image_script(script: &[u8]) -> Option<Vec<u8>> {
+    let mut i = 0;
+
+    // Skip protocol identifier if present
+    if let Some((data, size)) = read_push_data(script) {
+        if data == b"NAUSICA_COVER" || data == b"19HxigV4QyBv3tHpQVcUEQyq1pzZVdoAut" {
+            i += size;
+        }
+    }
+
+    // Anything shorter can't be an image
+    let (data, _) = read_push_data(script.get(i..)?)?;
+    (data.len() > 4).then(|| data.to_vec())
+}
+
+/// What an output script does, as recognized by the upload protocols
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScriptKind {
+    P2pkh { address: String },
+    Upfile { filename: String, mime_type: String, data_bytes: usize },
+    FlacstoreChunk { index: Option<u32>, data_bytes: usize },
+    FlacstoreManifest { filename: String, chunk_count: usize },
+    Flacstore { filename: String, data_bytes: usize },
+    Coverart { data_bytes: usize },
+    FlacstoreLyrics { format: String, data_bytes: usize },
+    FlacstoreCoverUpdate { manifest_txid: String, cover_txid: String },
+    OpReturn { pushes: usize, data_bytes: usize },
+    Unknown,
+}
+
+impl ScriptKind {
+    /// One-line human-readable description
+    pub fn summary(&self) -> String {
+        match self {
+            ScriptKind::P2pkh { address } => format!("P2PKH to {}", address),
+            ScriptKind::Upfile { filename, data_bytes, .. } => {
+                format!("upfile data of {} bytes ({})", data_bytes, filename)
+            }
+            ScriptKind::FlacstoreChunk { index: Some(index), data_bytes } => {
+                format!("flacstore-chunk index {} ({} bytes)", index, data_bytes)
+            }
+            ScriptKind::FlacstoreChunk { index: None, data_bytes } => {
+                format!("flacstore-chunk with unreadable index ({} bytes)", data_bytes)
+            }
+            ScriptKind::FlacstoreManifest { filename, chunk_count } => {
+                format!("flacstore-manifest for {} referencing {} chunks", filename, chunk_count)
+            }
+            ScriptKind::Flacstore { filename, data_bytes } => {
+                format!("flacstore data of {} bytes ({})", data_bytes, filename)
+            }
+            ScriptKind::Coverart { data_bytes } => format!("coverart image of {} bytes", data_bytes),
+            ScriptKind::FlacstoreLyrics { format, data_bytes } => {
+                format!("flacstore-lyrics of {} bytes ({})", data_bytes, format)
+            }
+            ScriptKind::FlacstoreCoverUpdate { manifest_txid, cover_txid } => {
+                format!("flacstore-coverupdate linking manifest {} to cover {}", manifest_txid, cover_txid)
+            }
+            ScriptKind::OpReturn { pushes, data_bytes } => {
+                format!("OP_RETURN with {} pushes ({} bytes)", pushes, data_bytes)
+            }
+            ScriptKind::Unknown => "unrecognized script".to_string(),
+        }
+    }
+
+    /// Protocol tag of a script that carries upload data
+    pub fn protocol(&self) -> Option<&'static str> {
+        match self {
+            ScriptKind::Upfile { .. } => Some("upfile"),
+            ScriptKind::FlacstoreChunk { .. } => Some("flacstore-chunk"),
+            ScriptKind::FlacstoreManifest { .. } => Some("flacstore-manifest"),
+            ScriptKind::Flacstore { .. } => Some("flacstore"),
+            ScriptKind::Coverart { .. } => Some("coverart"),
+            ScriptKind::FlacstoreLyrics { .. } => Some("flacstore-lyrics"),
+            ScriptKind::FlacstoreCoverUpdate { .. } => Some("flacstore-coverupdate"),
+            ScriptKind::P2pkh { .. } | ScriptKind::OpReturn { .. } | ScriptKind::Unknown => None,
+        }
+    }
+}
+
+/// An output whose script one of the upload protocols wrote
+pub struct DataOutput {
+    pub index: usize,
+    pub protocol: &'static str,
+    pub script: Vec<u8>,
+}
+
+/// First output of the transaction that carries upload data
+pub fn find_data_output(tx_hex: &str, network: Network) -> Option<DataOutput> {
+    parse_transaction(tx_hex)?
+        .outputs
+        .into_iter()
+        .enumerate()
+        .find_map(|(index, output)| {
+            let protocol = classify_script(&output.script, network).protocol()?;
+            Some(DataOutput { index, protocol, script: output.script })
+        })
+}
+
+/// Push items of an envelope or OP_RETURN script, protocol tag first
+pub fn data_pushes(script: &[u8]) -> Option<Vec<Vec<u8>>> {
+    envelope_body(script).or_else(|| op_return_body(script)).and_then(read_pushes)
+}
+
+/// Classify an output script by the protocol that wrote it
+pub fn classify_script(script: &[u8], network: Network) -> ScriptKind {
+    if script.len() == 25
+        && script[..3] == [0x76, 0xa9, 0x14]
+        && script[23..] == [0x88, 0xac]
+    {
+        return ScriptKind::P2pkh {
+            address: BsvService::pubkey_hash_to_address(&script[3..23], network),
+        };
+    }
+
+    let (is_envelope, body) = match script {
+        [0x00, 0x63, rest @ ..] => (true, rest),
+        [0x00, 0x6a, rest @ ..] | [0x6a, rest @ ..] => (false, rest),
+        _ => return ScriptKind::Unknown,
+    };
+
+    let pushes = match read_pushes(body) {
+        Some(pushes) => pushes,
+        None => return ScriptKind::Unknown,
+    };
+    let text = |i: usize| String::from_utf8_lossy(&pushes[i]).to_string();
+    let bytes_from = |i: usize| pushes.iter().skip(i).map(|p| p.len()).sum::<usize>();
+    let protocol = pushes.first().map(|p| p.as_slice());
+
+    match (is_envelope, protocol) {
+        (false, Some(b"upfile")) if pushes.len() >= 4 => ScriptKind::Upfile {
+            filename: text(2),
+            mime_type: text(1),
+            data_bytes: bytes_from(3),
+        },
+        (true, Some(b"flacstore-chunk")) if pushes.len() >= 3 => ScriptKind::FlacstoreChunk {
+            index: text(1).parse().ok(),
+            data_bytes: bytes_from(2),
+        },
+        (true, Some(b"flacstore-manifest")) => match parse_flac_manifest_script(body) {
+            Some(manifest) => ScriptKind::FlacstoreManifest {
+                filename: manifest.filename,
+                chunk_count: manifest.chunk_txids.len(),
+            },
+            None => ScriptKind::Unknown,
+        },
+        (true, Some(b"flacstore")) => match parse_flac_store_script(body) {
+            Some(file) => ScriptKind::Flacstore { filename: file.filename, data_bytes: file.data.len() },
+            None => ScriptKind::Unknown,
+        },
+        (true, Some(b"coverart")) => ScriptKind::Coverart { data_bytes: bytes_from(1) },
+        (true, Some(b"flacstore-lyrics")) if pushes.len() >= 3 => ScriptKind::FlacstoreLyrics {
+            format: text(1),
+            data_bytes: bytes_from(2),
+        },
+        (true, Some(b"flacstore-coverupdate")) if pushes.len() >= 3 => ScriptKind::FlacstoreCoverUpdate {
+            manifest_txid: text(1),
+            cover_txid: text(2),
+        },
+        (false, _) => ScriptKind::OpReturn {
+            pushes: pushes.len(),
+            data_bytes: bytes_from(0),
+        },
+        _ => ScriptKind::Unknown,
+    }
+}
+
+/// Pushes at the start of a script, up to OP_ENDIF or the end of the script.
+/// Yields `None` once for an opcode that isn't a push or a push that runs
+/// past the end, then stops.
+pub struct PushDataIter<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> PushDataIter<'a> {
+    pub fn new(script: &'a [u8]) -> Self {
+        PushDataIter { rest: script }
+    }
+}
+
+impl<'a> Iterator for PushDataIter<'a> {
+    type Item = Option<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rest.first() {
+            None | Some(0x68) => None,
+            Some(_) => match read_push_data(self.rest) {
+                Some((data, consumed)) => {
+                    self.rest = &self.rest[consumed..];
+                    Some(Some(data))
+                }
+                None => {
+                    self.rest = &[];
+                    Some(None)
+                }
+            },
+        }
+    }
+}
+
+/// Read consecutive pushes, stopping at OP_ENDIF or the end of the script;
+/// `None` if any push is malformed
+pub fn read_pushes(script: &[u8]) -> Option<Vec<Vec<u8>>> {
+    PushDataIter::new(script).map(|push| push.map(<[u8]>::to_vec)).collect()
+}
+
+/// Read one push at the start of `script`, returning the data and the bytes consumed
+pub fn read_push_data(script: &[u8]) -> Option<(&[u8], usize)> {
+    let opcode = *script.first()?;
+
+    let (len, start): (usize, usize) = match opcode {
+        0x00..=0x4b => (opcode as usize, 1),
+        0x4c => (*script.get(1)? as usize, 2),
+        0x4d => (u16::from_le_bytes(script.get(1..3)?.try_into().ok()?) as usize, 3),
+        0x4e => (u32::from_le_bytes(script.get(1..5)?.try_into().ok()?) as usize, 5),
+        _ => return None,
+    };
+
+    let data = script.get(start..start.checked_add(len)?)?;
+    Some((data, start + len))
+}
+
+/// Read a Bitcoin varint, returning the value and its size in bytes
+pub fn read_varint(data: &[u8]) -> Option<(u64, usize)> {
+    match *data.first()? {
+        first @ 0x00..=0xfc => Some((first as u64, 1)),
+        0xfd => Some((u16::from_le_bytes(data.get(1..3)?.try_into().ok()?) as u64, 3)),
+        0xfe => Some((u32::from_le_bytes(data.get(1..5)?.try_into().ok()?) as u64, 5)),
+        0xff => Some((u64::from_le_bytes(data.get(1..9)?.try_into().ok()?), 9)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Amount;
+    use crate::services::bsv::ManifestLayout;
+
+    const ONE_SAT: Amount = Amount::from_sat_const(1);
+
+    fn tx_with(script: Vec<u8>) -> String {
+        let change = BsvService::create_p2pkh_script("1BoatSLRHtKNngkdXEeobR76b53LETtpyT").unwrap();
+        BsvService::for_tests().test_transaction(&[(change, Amount::from_sat_const(1000)), (script, ONE_SAT)])
+    }
+
+    #[test]
+    fn transaction_round_trips() {
+        let script = BsvService::for_tests().create_upfile_script("text/plain", "a.txt", b"hello");
+        let tx_hex = tx_with(script.clone());
+        let tx = parse_transaction(&tx_hex).unwrap();
+
+        assert_eq!(tx.version, 1);
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(tx.inputs[0].prev_txid, "11".repeat(32));
+        assert!(extract_pubkey_from_script_sig(&tx.inputs[0].script_sig).is_some());
+        assert_eq!(tx.outputs.len(), 2);
+        assert_eq!(tx.outputs[1].script, script);
+        assert_eq!(tx.outputs[1].satoshis, 1);
+        assert!(is_tx_hex_for(&tx_hex, &BsvService::txid(&tx_hex).unwrap()));
+    }
+
+    #[test]
+    fn huge_script_lengths_are_rejected() {
+        let mut input = "01000000".to_string() + "01" + &"00".repeat(32) + "00000000";
+        input += "ff";
+        input += "ffffffffffffffff";
+        assert!(parse_transaction(&input).is_none());
+
+        let output = "01000000".to_string() + "00" + "01" + &"00".repeat(8) + "ffffffffffffffffff";
+        assert!(parse_transaction(&output).is_none());
+
+        // Truncated transactions end the parse rather than panicking
+        let tx_hex = tx_with(vec![0x6a]);
+        for end in (0..tx_hex.len()).step_by(2) {
+            assert!(parse_transaction(&tx_hex[..end]).is_none());
+        }
+    }
+
+    #[test]
+    fn push_iter_reads_every_push_form_up_to_op_endif() {
+        let big = vec![0xab; 300];
+        let mut script = vec![0x00, 0x03, 1, 2, 3];
+        script.extend_from_slice(&[0x4c, 2, 4, 5]);
+        script.extend_from_slice(&[0x4d, 0x2c, 0x01]);
+        script.extend_from_slice(&big);
+        script.extend_from_slice(&[0x4e, 1, 0, 0, 0, 6]);
+        let pushes: Vec<_> = PushDataIter::new(&script).collect();
+        let expected: Vec<Option<&[u8]>> = vec![Some(&[]), Some(&[1, 2, 3]), Some(&[4, 5]), Some(&big), Some(&[6])];
+        assert_eq!(pushes, expected);
+
+        // OP_ENDIF ends the pushes, whatever follows it
+        let mut ended = script.clone();
+        ended.extend_from_slice(&[0x68, 0x01, 7]);
+        assert_eq!(PushDataIter::new(&ended).collect::<Vec<_>>(), expected);
+        assert_eq!(PushDataIter::new(&[0x68, 0x01, 7]).count(), 0);
+
+        // A non-push opcode or a push past the end yields one None, then stops
+        for bad in [&[0x01, 9, 0x6a, 0x01, 7][..], &[0x01, 9, 0x4c, 5, 1, 2]] {
+            let pushes: Vec<_> = PushDataIter::new(bad).collect();
+            assert_eq!(pushes, vec![Some(&[9][..]), None]);
+            assert_eq!(read_pushes(bad), None);
+        }
+    }
+
+    #[test]
+    fn upfile_output_round_trips() {
+        let script = BsvService::for_tests().create_upfile_script("text/plain", "a.txt", b"hello");
+        let file = extract_op_return_from_tx(&tx_with(script)).unwrap();
+        assert_eq!(file.data, b"hello");
+        assert_eq!(file.filename, "a.txt");
+        assert_eq!(file.mime_type, "text/plain");
+    }
+
+    #[test]
+    fn flac_chunk_round_trips() {
+        let script = BsvService::for_tests().create_flac_chunk_script(3, 5, b"chunk data");
+        assert_eq!(extract_flac_chunk_from_tx(&tx_with(script)), Some((3, b"chunk data".to_vec())));
+    }
+
+    #[test]
+    fn flac_store_round_trips() {
+        let metadata = serde_json::json!({"filename": "song.flac", "title": "Song"}).to_string();
+        let script = BsvService::for_tests().create_flac_store_script(
+            b"flacstore",
+            b"audio/flac",
+            metadata.as_bytes(),
+            &[b"fLaC".to_vec(), b"rest".to_vec()],
+        );
+        let file = extract_flac_from_tx(&tx_with(script)).unwrap();
+        assert_eq!(file.data, b"fLaCrest");
+        assert_eq!(file.filename, "song.flac");
+        assert_eq!(file.title.as_deref(), Some("Song"));
+        assert_eq!(file.artist, None);
+    }
+
+    #[test]
+    fn flac_manifest_round_trips() {
+        let chunk_txids = vec!["a".repeat(64), "b".repeat(64)];
+        let script = BsvService::create_flac_manifest_script(
+            "song.flac",
+            2048,
+            &chunk_txids,
+            &[],
+            Some("Song"),
+            None,
+            None,
+            None,
+            Some(&"c".repeat(64)),
+            None,
+            None,
+            ManifestLayout::Json,
+        );
+        let manifest = extract_flac_manifest_from_tx(&tx_with(script.clone())).unwrap();
+        assert_eq!(manifest.filename, "song.flac");
+        assert_eq!(manifest.size, Some(2048));
+        assert_eq!(manifest.chunk_txids, chunk_txids);
+        assert_eq!(manifest.title.as_deref(), Some("Song"));
+        assert_eq!(manifest.cover_txid, Some("c".repeat(64)));
+        assert!(matches!(parse_flac_output(&script), Some(FlacData::Manifest(_))));
+        assert!(matches!(
+            classify_script(&script, Network::Mainnet),
+            ScriptKind::FlacstoreManifest { chunk_count: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn both_manifest_layouts_round_trip() {
+        let chunk_txids = vec!["a".repeat(64)];
+        let manifest = |layout| {
+            BsvService::create_flac_manifest_script(
+                "song.flac",
+                2048,
+                &chunk_txids,
+                &[],
+                Some("Song"),
+                Some("Artist"),
+                Some("la la la"),
+                None,
+                None,
+                None,
+                None,
+                layout,
+            )
+        };
+
+        for layout in [ManifestLayout::Json, ManifestLayout::Labeled] {
+            let parsed = parse_flac_manifest_output(&manifest(layout)).unwrap();
+            assert_eq!(parsed.title.as_deref(), Some("Song"));
+            assert_eq!(parsed.artist.as_deref(), Some("Artist"));
+            assert_eq!(parsed.lyrics.as_deref(), Some("la la la"));
+            assert_eq!(parsed.lyrics_format, LyricsFormat::Plain);
+            assert_eq!(parsed.chunk_txids, chunk_txids);
+        }
+
+        // Only the labeled layout has the fields as pushes of their own
+        let pushes = |layout| read_pushes(envelope_body(&manifest(layout)).unwrap()).unwrap();
+        let labeled = pushes(ManifestLayout::Labeled);
+        assert_eq!(labeled[3..7], [b"title".to_vec(), b"Song".to_vec(), b"artist".to_vec(), b"Artist".to_vec()]);
+        assert!(!labeled[2].windows(5).any(|w| w == b"title"));
+        let json = pushes(ManifestLayout::Json);
+        assert_eq!(json.len(), 4);
+        assert!(json[2].windows(5).any(|w| w == b"title"));
+    }
+
+    #[test]
+    fn cover_scripts_round_trip() {
+        let image = vec![0x89; 1200];
+        let script = BsvService::for_tests().create_cover_image_script(&image);
+        assert_eq!(parse_image_output(&script), Some(image.clone()));
+
+        let mut legacy = vec![0x00, 0x6a];
+        BsvService::push_data(&mut legacy, b"NAUSICA_COVER");
+        BsvService::push_data(&mut legacy, &image);
+        assert_eq!(parse_image_output(&legacy), Some(image));
+    }
+
+    #[test]
+    fn lyrics_script_round_trips() {
+        let lyrics = "[00:01.00]歌詞".repeat(100);
+        let script = BsvService::for_tests().create_lyrics_script(&lyrics);
+        assert_eq!(parse_lyrics_output(&script), Some((lyrics, LyricsFormat::Lrc)));
+    }
+
+    #[test]
+    fn cover_update_is_classified() {
+        let script = BsvService::create_cover_update_script(&"a".repeat(64), &"b".repeat(64));
+        assert!(matches!(
+            classify_script(&script, Network::Mainnet),
+            ScriptKind::FlacstoreCoverUpdate { ref manifest_txid, .. } if *manifest_txid == "a".repeat(64)
+        ));
+        assert_eq!(data_pushes(&script).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn data_output_is_found_past_change() {
+        let script = BsvService::for_tests().create_flac_chunk_script(0, 1, b"x");
+        let output = find_data_output(&tx_with(script.clone()), Network::Mainnet).unwrap();
+        assert_eq!(output.index, 1);
+        assert_eq!(output.protocol, "flacstore-chunk");
+        assert_eq!(output.script, script);
+    }
+
+    #[test]
+    fn pubkey_is_read_from_p2pkh_script_sigs_only() {
+        let signature = [0x30; 71];
+        let compressed = [&[0x02][..], &[0x11; 32]].concat();
+        let uncompressed = [&[0x04][..], &[0x11; 64]].concat();
+        let script_sig = |pubkey: &[u8]| {
+            let mut script = Vec::new();
+            BsvService::push_data(&mut script, &signature);
+            BsvService::push_data(&mut script, pubkey);
+            script
+        };
+        assert_eq!(extract_pubkey_from_script_sig(&script_sig(&compressed)), Some(compressed));
+        assert_eq!(extract_pubkey_from_script_sig(&script_sig(&uncompressed)), Some(uncompressed));
+        // A P2PK spend carries only the signature, and other pushes aren't keys
+        let mut p2pk = Vec::new();
+        BsvService::push_data(&mut p2pk, &signature);
+        assert_eq!(extract_pubkey_from_script_sig(&p2pk), None);
+        assert_eq!(extract_pubkey_from_script_sig(&script_sig(&[0x05; 33])), None);
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Lengths on both sides of each push opcode's limit: 75 for a
+        /// direct push, 255 for OP_PUSHDATA1 and 65535 for OP_PUSHDATA2
+        fn push_len() -> impl Strategy<Value = usize> {
+            prop_oneof![0..=80usize, 250..=260usize, 65_530..=65_540usize, 0..2000usize]
+        }
+
+        fn push_bytes() -> impl Strategy<Value = Vec<u8>> {
+            push_len().prop_flat_map(|len| prop::collection::vec(any::<u8>(), len))
+        }
+
+        /// The default push limit, or one small enough to split data into many pushes
+        fn max_push_size() -> impl Strategy<Value = usize> {
+            prop_oneof![Just(crate::services::bsv::DEFAULT_MAX_PUSH_SIZE), 1..300usize]
+        }
+
+        fn bsv_with_max_push(max_push_size: usize) -> BsvService {
+            let mut bsv = BsvService::for_tests();
+            bsv.max_push_size = max_push_size;
+            bsv
+        }
+
+        fn txid() -> impl Strategy<Value = String> {
+            "[0-9a-f]{64}"
+        }
+
+        fn layout() -> impl Strategy<Value = ManifestLayout> {
+            prop_oneof![Just(ManifestLayout::Json), Just(ManifestLayout::Labeled)]
+        }
+
+        /// Every parser, fed the same bytes as a script and as a transaction
+        fn parse_everything(bytes: &[u8]) {
+            let _ = read_pushes(bytes);
+            let _ = read_push_data(bytes);
+            let _ = read_varint(bytes);
+            let _ = data_pushes(bytes);
+            let _ = parse_upfile_output(bytes);
+            let _ = parse_op_return_script(bytes);
+            let _ = parse_flac_output(bytes);
+            let _ = parse_flac_manifest_script(bytes);
+            let _ = parse_flac_chunk_script(bytes);
+            let _ = parse_flac_store_script(bytes);
+            let _ = parse_lyrics_output(bytes);
+            let _ = parse_image_output(bytes);
+            let _ = parse_coverart_script(bytes);
+            let _ = parse_image_script(bytes);
+            let _ = extract_pubkey_from_script_sig(bytes);
+            let _ = classify_script(bytes, Network::Mainnet);
+            let _ = parse_transaction(&hex::encode(bytes));
+        }
+
+        /// A push of `len` bytes starts with `opcode` and reads back whole
+        fn assert_push(len: usize, opcode: u8) {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut script = Vec::new();
+            BsvService::push_data(&mut script, &data);
+            assert_eq!(script[0], opcode, "{} bytes", len);
+            let (read, consumed) = read_push_data(&script).unwrap();
+            assert_eq!(read, &data[..], "{} bytes", len);
+            assert_eq!(consumed, script.len(), "{} bytes", len);
+        }
+
+        #[test]
+        fn push_opcode_changes_at_75_bytes() {
+            assert_push(75, 75);
+            assert_push(76, 0x4c);
+        }
+
+        #[test]
+        fn push_opcode_changes_at_255_bytes() {
+            assert_push(255, 0x4c);
+            assert_push(256, 0x4d);
+        }
+
+        #[test]
+        fn push_opcode_changes_at_65535_bytes() {
+            assert_push(65_535, 0x4d);
+            assert_push(65_536, 0x4e);
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(64))]
+
+            #[test]
+            fn push_data_round_trips(data in push_bytes(), tail in prop::collection::vec(any::<u8>(), 0..4)) {
+                let mut script = Vec::new();
+                BsvService::push_data(&mut script, &data);
+                let opcode_len = script.len() - data.len();
+                let expected_opcode = match data.len() {
+                    0..=75 => data.len() as u8,
+                    76..=255 => 0x4c,
+                    256..=65535 => 0x4d,
+                    _ => 0x4e,
+                };
+                prop_assert_eq!(script[0], expected_opcode);
+
+                script.extend_from_slice(&tail);
+                let (read, consumed) = read_push_data(&script).unwrap();
+                prop_assert_eq!(read, &data[..]);
+                prop_assert_eq!(consumed, opcode_len + data.len());
+            }
+
+            #[test]
+            fn pushes_read_back_in_order(pushes in prop::collection::vec(push_bytes(), 0..4)) {
+                let mut script = Vec::new();
+                for data in &pushes {
+                    BsvService::push_data(&mut script, data);
+                }
+                prop_assert_eq!(read_pushes(&script), Some(pushes));
+            }
+
+            #[test]
+            fn upfile_script_round_trips(
+                max_push_size in max_push_size(),
+                mime_type in "[a-z]{1,12}/[a-z0-9.+-]{1,20}",
+                filename in "\\PC{0,40}",
+                data in push_bytes(),
+            ) {
+                let script = bsv_with_max_push(max_push_size).create_upfile_script(&mime_type, &filename, &data);
+                let file = parse_upfile_output(&script).unwrap();
+                prop_assert_eq!(file, UpfileFile { data, filename, mime_type });
+            }
+
+            #[test]
+            fn flac_chunk_script_round_trips(max_push_size in max_push_size(), index in any::<u32>(), data in push_bytes()) {
+                let script = bsv_with_max_push(max_push_size).create_flac_chunk_script(index, index.saturating_add(1), &data);
+                let body = envelope_body(&script).unwrap();
+                prop_assert_eq!(parse_flac_chunk_script(body), Some((index, data)));
+            }
+
+            #[test]
+            fn flac_store_script_round_trips(
+                chunks in prop::collection::vec(push_bytes(), 1..3),
+                filename in "\\PC{1,40}",
+                title in proptest::option::of("\\PC{1,40}"),
+            ) {
+                let metadata = serde_json::json!({ "filename": filename, "title": title }).to_string();
+                let script = BsvService::for_tests().create_flac_store_script(b"flacstore", b"audio/flac", metadata.as_bytes(), &chunks);
+                let Some(FlacData::File(file)) = parse_flac_output(&script) else {
+                    return Err(TestCaseError::fail("not parsed as a flacstore file"));
+                };
+                prop_assert_eq!(file.data, chunks.concat());
+                prop_assert_eq!(file.filename, filename);
+                prop_assert_eq!(file.title, title);
+            }
+
+            #[test]
+            fn flac_manifest_script_round_trips(
+                filename in "\\PC{0,300}",
+                file_size in any::<u32>(),
+                chunk_txids in prop::collection::vec(txid(), 1..6),
+                title in proptest::option::of("\\PC{1,300}"),
+                artist in proptest::option::of("\\PC{1,80}"),
+                cover_txid in proptest::option::of(txid()),
+                layout in layout(),
+            ) {
+                let chunk_hashes: Vec<String> = chunk_txids.iter().map(|txid| txid.chars().rev().collect()).collect();
+                let script = BsvService::create_flac_manifest_script(
+                    &filename,
+                    file_size as usize,
+                    &chunk_txids,
+                    &chunk_hashes,
+                    title.as_deref(),
+                    artist.as_deref(),
+                    None,
+                    None,
+                    cover_txid.as_deref(),
+                    None,
+                    None,
+                    layout,
+                );
+                let manifest = parse_flac_manifest_output(&script).unwrap();
+                prop_assert_eq!(manifest.filename, filename);
+                prop_assert_eq!(manifest.size, Some(file_size as usize));
+                prop_assert_eq!(manifest.chunk_txids, chunk_txids);
+                prop_assert_eq!(manifest.chunk_hashes, chunk_hashes);
+                prop_assert_eq!(manifest.title, title);
+                prop_assert_eq!(manifest.artist, artist);
+                prop_assert_eq!(manifest.cover_txid, cover_txid);
+            }
+
+            #[test]
+            fn parsers_never_panic_on_arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..600)) {
+                parse_everything(&bytes);
+                // Past the envelope and OP_RETURN prefixes the parsers check first
+                parse_everything(&[&[0x00, 0x63][..], &bytes].concat());
+                parse_everything(&[&[0x00, 0x6a][..], &bytes].concat());
+            }
+
+            #[test]
+            fn parsers_never_panic_on_truncated_scripts(
+                max_push_size in max_push_size(),
+                data in push_bytes(),
+                chunk_txids in prop::collection::vec(txid(), 1..4),
+                layout in layout(),
+                cut in any::<prop::sample::Index>(),
+            ) {
+                let bsv = bsv_with_max_push(max_push_size);
+                let scripts = [
+                    bsv.create_upfile_script("audio/flac", "a.flac", &data),
+                    bsv.create_flac_chunk_script(0, 1, &data),
+                    bsv.create_flac_store_script(b"flacstore", b"audio/flac", b"{}", std::slice::from_ref(&data)),
+                    bsv.create_cover_image_script(&data),
+                    BsvService::create_flac_manifest_script(
+                        "a.flac", data.len(), &chunk_txids, &[], Some("Title"), None, None, None, None, None, None, layout,
+                    ),
+                ];
+                for script in scripts {
+                    parse_everything(&script[..cut.index(script.len())]);
+                    let tx_hex = tx_with(script);
+                    let end = cut.index(tx_hex.len() / 2) * 2;
+                    prop_assert!(parse_transaction(&tx_hex[..end]).is_none());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// WhatsOnChain base URLs
+// Testnet and STN traffic and the mainnet broadcast fallback go to
+// WhatsOnChain. The bases come from config so a mirror or a self-hosted
+// indexer with the same API can stand in for it.
+
+use std::sync::OnceLock;
+
+use crate::models::Network;
+
+/// Most txids the bulk raw transaction endpoint accepts per request
+pub const BULK_TX_LIMIT: usize = 20;
+
+/// Most addresses the bulk unspent endpoint accepts per request
+pub const BULK_ADDRESS_LIMIT: usize = 20;
+
+static MAINNET: OnceLock<String> = OnceLock::new();
+static TESTNET: OnceLock<String> = OnceLock::new();
+static STN: OnceLock<String> = OnceLock::new();
+
+/// Set the base URLs. Only the first call has an effect.
+pub fn init(mainnet_url: &str, testnet_url: &str, stn_url: &str) {
+    let _ = MAINNET.set(mainnet_url.to_string());
+    let _ = TESTNET.set(testnet_url.to_string());
+    let _ = STN.set(stn_url.to_string());
+}
+
+/// Whether a network's chain calls go to WhatsOnChain (mainnet uses Bitails)
+pub fn serves(network: Network) -> bool {
+    matches!(network, Network::Testnet | Network::Stn)
+}
+
+/// Base URL for a network, no trailing slash
+pub fn base_url(network: Network) -> &'static str {
+    match network {
+        Network::Testnet => TESTNET.get_or_init(|| "https://api.whatsonchain.com/v1/bsv/test".to_string()),
+        Network::Stn => STN.get_or_init(|| "https://api.whatsonchain.com/v1/bsv/stn".to_string()),
+        Network::Mainnet => MAINNET.get_or_init(|| "https://api.whatsonchain.com/v1/bsv/main".to_string()),
+    }
+}
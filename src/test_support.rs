@@ -0,0 +1,370 @@
+// Fixtures shared by the unit tests
+// An in-memory app state, so handlers run without a database file or the
+// environment's keys.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+use crate::db::Database;
+use crate::services::admin_session::AdminSessions;
+use crate::services::bitails::BitailsClient;
+use crate::models::Job;
+use crate::services::bsv::BsvService;
+use crate::services::cancellation::JobCancellations;
+use crate::services::funding_keys::FundingKeys;
+use crate::services::maintenance::MaintenanceStats;
+use crate::services::scheduler::{JobScheduler, QueuedJob};
+use crate::AppState;
+
+/// Configuration from the environment's defaults, with an in-memory database
+/// and WhatsOnChain pointed at the shared stand-in
+pub fn test_config() -> Config {
+    whatsonchain();
+    let mut config = Config::from_env();
+    config.database_path = ":memory:".to_string();
+    config.bitails_api_keys = Vec::new();
+    config.hd_seed = None;
+    config
+}
+
+pub fn test_state() -> Arc<RwLock<AppState>> {
+    test_state_with(test_config())
+}
+
+/// State over a fresh in-memory database; Bitails calls go to `config.bitails_api_url`
+pub fn test_state_with(config: Config) -> Arc<RwLock<AppState>> {
+    Arc::new(RwLock::new(AppState {
+        db: Database::new(&config.database_path).unwrap(),
+        bitails: BitailsClient::new(config.bitails_api_url.clone(), config.bitails_api_keys.clone()),
+        bsv: BsvService::for_tests(),
+        scheduler: JobScheduler::new(config.max_concurrent_jobs),
+        cancellations: JobCancellations::new(),
+        funding_keys: FundingKeys::new(),
+        hd_wallet: None,
+        maintenance: MaintenanceStats::new(),
+        admin_sessions: AdminSessions::new(Some("test-secret"), config.admin_session_hours),
+        config,
+    }))
+}
+
+/// Serve `router` on a local port, returning its base URL
+pub async fn serve(router: axum::Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let app = router.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+}
+
+/// A multipart/form-data body of `(name, filename, value)` fields, with its content type
+pub fn multipart_body(fields: &[(&str, Option<&str>, &[u8])]) -> (String, Vec<u8>) {
+    let boundary = "test-boundary";
+    let mut body = Vec::new();
+    for (name, filename, value) in fields {
+        body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", boundary, name).as_bytes());
+        if let Some(filename) = filename {
+            body.extend_from_slice(format!("; filename=\"{}\"", filename).as_bytes());
+        }
+        body.extend_from_slice(b"\r\n\r\n");
+        body.extend_from_slice(value);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
+
+/// Answer of the Bitails stand-in to a broadcast: HTTP status and JSON body
+pub type BroadcastReply = fn(&str) -> (u16, serde_json::Value);
+
+/// Accept the transaction under its txid
+pub fn accept(raw_tx: &str) -> (u16, serde_json::Value) {
+    (200, serde_json::json!({ "txid": BsvService::txid(raw_tx).unwrap() }))
+}
+
+/// Reject the transaction as a node rejects an invalid one
+pub fn reject(_raw_tx: &str) -> (u16, serde_json::Value) {
+    (400, serde_json::json!({ "error": { "message": "bad-txns-inputs-missingorspent" } }))
+}
+
+/// Transactions a Bitails stand-in serves: those added by a test and those
+/// it accepted as broadcasts
+#[derive(Clone, Default)]
+pub struct MockChain {
+    txs: Arc<Mutex<HashMap<String, String>>>,
+    /// (txid, "output" or "raw") of each download served
+    downloads: Arc<Mutex<Vec<(String, &'static str)>>>,
+}
+
+impl MockChain {
+    /// Serve the raw transaction `tx_hex`, returning its txid
+    pub fn add(&self, tx_hex: &str) -> String {
+        let txid = BsvService::txid(tx_hex).unwrap();
+        self.txs.lock().unwrap().insert(txid.clone(), tx_hex.to_string());
+        txid
+    }
+
+    pub fn tx(&self, txid: &str) -> Option<String> {
+        self.txs.lock().unwrap().get(txid).cloned()
+    }
+
+    /// Number of transactions served, counting accepted broadcasts
+    pub fn count(&self) -> usize {
+        self.txs.lock().unwrap().len()
+    }
+
+    /// What was downloaded of `txid`, in order: "output" for a single
+    /// output script, "raw" for the whole transaction
+    pub fn downloads(&self, txid: &str) -> Vec<&'static str> {
+        let downloads = self.downloads.lock().unwrap();
+        downloads.iter().filter(|(id, _)| id == txid).map(|(_, kind)| *kind).collect()
+    }
+
+    fn output_script(&self, txid: &str, index: usize) -> Option<Vec<u8>> {
+        let tx = crate::services::tx_parse::parse_transaction(&self.tx(txid)?)?;
+        tx.outputs.into_iter().nth(index).map(|output| output.script)
+    }
+}
+
+/// A Bitails stand-in: every address holds one confirmed UTXO of `satoshis`,
+/// and each broadcast gets `reply`'s answer to its raw transaction
+pub async fn bitails(satoshis: i64, reply: BroadcastReply) -> String {
+    chain_bitails(&MockChain::default(), satoshis, reply).await
+}
+
+/// A Bitails stand-in like `bitails` that also serves the transactions of
+/// `chain` and adds the broadcasts it accepts to it
+pub async fn chain_bitails(chain: &MockChain, satoshis: i64, reply: BroadcastReply) -> String {
+    chain_bitails_utxos(chain, &[satoshis], reply).await
+}
+
+/// A Bitails stand-in like `chain_bitails` where every address holds one
+/// confirmed UTXO of each of `utxos`
+pub async fn chain_bitails_utxos(chain: &MockChain, utxos: &[i64], reply: BroadcastReply) -> String {
+    use axum::extract::{Path, State};
+    use axum::http::StatusCode;
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::Json;
+
+    async fn unspent(State((_, utxos)): State<(MockChain, Vec<i64>)>, Path(address): Path<String>) -> Response {
+        let unspent: Vec<serde_json::Value> = utxos
+            .iter()
+            .enumerate()
+            .map(|(vout, satoshis)| serde_json::json!({ "txid": "22".repeat(32), "vout": vout, "satoshis": satoshis, "confirmations": 6 }))
+            .collect();
+        Json(serde_json::json!({ "address": address, "unspent": unspent })).into_response()
+    }
+
+    async fn balance(State((_, utxos)): State<(MockChain, Vec<i64>)>, Path(address): Path<String>) -> Response {
+        let satoshis: i64 = utxos.iter().sum();
+        Json(serde_json::json!({
+            "address": address,
+            "confirmed": satoshis,
+            "unconfirmed": 0,
+            "summary": satoshis,
+            "count": utxos.len(),
+        }))
+        .into_response()
+    }
+
+    async fn transaction(State((chain, _)): State<(MockChain, Vec<i64>)>, Path(txid): Path<String>) -> Response {
+        let Some(tx) = chain.tx(&txid).and_then(|tx| crate::services::tx_parse::parse_transaction(&tx)) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let outputs: Vec<serde_json::Value> = tx
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| {
+                serde_json::json!({ "index": index, "satoshis": output.satoshis, "scriptSize": output.script.len() })
+            })
+            .collect();
+        Json(serde_json::json!({ "txid": txid, "outputs": outputs })).into_response()
+    }
+
+    async fn raw_tx(State((chain, _)): State<(MockChain, Vec<i64>)>, Path(txid): Path<String>) -> Response {
+        chain.downloads.lock().unwrap().push((txid.clone(), "raw"));
+        match chain.tx(&txid) {
+            Some(tx) => hex::decode(tx).unwrap().into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn output(State((chain, _)): State<(MockChain, Vec<i64>)>, Path((txid, index)): Path<(String, usize)>) -> Response {
+        chain.downloads.lock().unwrap().push((txid.clone(), "output"));
+        match chain.output_script(&txid, index) {
+            Some(script) => script.into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    let broadcast = post(move |State((chain, _)): State<(MockChain, Vec<i64>)>, Json(body): Json<serde_json::Value>| async move {
+        let raw_tx = body["raw"].as_str().unwrap_or_default();
+        let (status, body) = reply(raw_tx);
+        if status == 200 {
+            chain.add(raw_tx);
+        }
+        (StatusCode::from_u16(status).unwrap(), Json(body))
+    });
+
+    let router = axum::Router::new()
+        .route("/address/:address/unspent", get(unspent))
+        .route("/address/:address/balance", get(balance))
+        .route("/tx/broadcast", broadcast)
+        .route("/tx/:txid", get(transaction))
+        .route("/download/tx/:txid", get(raw_tx))
+        .route("/download/tx/:txid/output/:index", get(output))
+        .with_state((chain.clone(), utxos.to_vec()));
+    serve(router).await
+}
+
+/// Insert `job` and run it as the dispatcher would once it is paid
+pub async fn run_job(state: &Arc<RwLock<AppState>>, job: &Job) {
+    state.read().await.db.insert_job(job).unwrap();
+    let queued = QueuedJob {
+        job_id: job.id.clone(),
+        job_type: job.job_type.clone(),
+        address: job.payment_address.clone().unwrap_or_default(),
+        network: job.network.unwrap_or_default(),
+        admin_pay: false,
+        file_size: job.file_size.unwrap_or(0),
+        paid_satoshis: None,
+    };
+    crate::run_job_guarded(state.clone(), queued).await;
+}
+
+/// Addresses the WhatsOnChain stand-in holds a UTXO for, and the
+/// transactions it accepted
+#[derive(Default)]
+struct WhatsOnChainFunds {
+    balances: Mutex<HashMap<String, i64>>,
+    chain: MockChain,
+    /// Txids asked for by each raw transaction request, bulk or single
+    fetches: Mutex<Vec<Vec<String>>>,
+}
+
+fn whatsonchain_funds() -> &'static WhatsOnChainFunds {
+    static FUNDS: OnceLock<WhatsOnChainFunds> = OnceLock::new();
+    FUNDS.get_or_init(WhatsOnChainFunds::default)
+}
+
+/// Give `address` one confirmed UTXO of `satoshis` on the WhatsOnChain
+/// stand-in. It then accepts broadcasts signed by the address's key.
+pub fn whatsonchain_fund(address: &str, satoshis: i64) {
+    whatsonchain();
+    whatsonchain_funds().balances.lock().unwrap().insert(address.to_string(), satoshis);
+}
+
+/// Transactions the WhatsOnChain stand-in accepted as broadcasts
+pub fn whatsonchain_chain() -> MockChain {
+    whatsonchain_funds().chain.clone()
+}
+
+/// Number of txids in each raw transaction request the WhatsOnChain
+/// stand-in served that asked for any of `txids`, in order
+pub fn whatsonchain_fetches(txids: &[String]) -> Vec<usize> {
+    let fetches = whatsonchain_funds().fetches.lock().unwrap();
+    fetches.iter().filter(|fetch| fetch.iter().any(|txid| txids.contains(txid))).map(Vec::len).collect()
+}
+
+/// Point WhatsOnChain at a local stand-in shared by every test. Its base URLs
+/// can only be set once per process, so it runs on its own thread. It only
+/// knows the chain height, the addresses funded with `whatsonchain_fund` and
+/// the transactions it accepted from them; every other request gets a 404, so nothing a test
+/// does reaches the real API.
+pub fn whatsonchain() {
+    use axum::extract::Path;
+    use axum::http::{header, HeaderValue, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::Json;
+
+    async fn unspent(Path((_, address)): Path<(String, String)>) -> Response {
+        match whatsonchain_funds().balances.lock().unwrap().get(&address) {
+            Some(satoshis) => {
+                Json(serde_json::json!([{ "tx_hash": "33".repeat(32), "tx_pos": 0, "value": satoshis, "height": 100 }]))
+                    .into_response()
+            }
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn balance(Path((_, address)): Path<(String, String)>) -> Response {
+        match whatsonchain_funds().balances.lock().unwrap().get(&address) {
+            Some(satoshis) => Json(serde_json::json!({ "confirmed": satoshis, "unconfirmed": 0 })).into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn broadcast(Path(network): Path<String>, Json(body): Json<serde_json::Value>) -> Response {
+        let raw_tx = body["txhex"].as_str().unwrap_or_default();
+        let signer = crate::services::tx_parse::parse_transaction(raw_tx)
+            .and_then(|tx| crate::services::tx_parse::extract_pubkey_from_script_sig(&tx.inputs.first()?.script_sig))
+            .zip(crate::models::Network::from_str(&network))
+            .map(|(pubkey, network)| BsvService::pubkey_bytes_to_address(&pubkey, network));
+        let funded = signer.is_some_and(|address| whatsonchain_funds().balances.lock().unwrap().contains_key(&address));
+        if !funded {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Json(whatsonchain_funds().chain.add(raw_tx)).into_response()
+    }
+
+    async fn chain_info() -> Json<serde_json::Value> {
+        Json(serde_json::json!({ "blocks": 100 }))
+    }
+
+    async fn tx_hex(Path((_, txid)): Path<(String, String)>) -> Response {
+        whatsonchain_funds().fetches.lock().unwrap().push(vec![txid.clone()]);
+        match whatsonchain_funds().chain.tx(&txid) {
+            Some(tx) => tx.into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn txs_hex(Json(body): Json<serde_json::Value>) -> Response {
+        let txids: Vec<String> = serde_json::from_value(body["txids"].clone()).unwrap_or_default();
+        whatsonchain_funds().fetches.lock().unwrap().push(txids.clone());
+        let entries: Vec<serde_json::Value> = txids
+            .iter()
+            .map(|txid| match whatsonchain_funds().chain.tx(txid) {
+                Some(tx) => serde_json::json!({ "txid": txid, "hex": tx }),
+                None => serde_json::json!({ "txid": txid, "error": "unknown" }),
+            })
+            .collect();
+        Json(entries).into_response()
+    }
+
+    // Not kept alive: each test's runtime has its own connections
+    async fn close(mut response: Response) -> Response {
+        response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("close"));
+        response
+    }
+
+    static STARTED: OnceLock<()> = OnceLock::new();
+    STARTED.get_or_init(|| {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let router = axum::Router::new()
+            .route("/:network/address/:address/unspent", get(unspent))
+            .route("/:network/address/:address/balance", get(balance))
+            .route("/:network/chain/info", get(chain_info))
+            .route("/:network/tx/raw", post(broadcast))
+            .route("/:network/tx/:txid/hex", get(tx_hex))
+            .route("/:network/txs/hex", post(txs_hex))
+            .fallback(|| async { (StatusCode::NOT_FOUND, "not found") })
+            .layer(axum::middleware::map_response(close));
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, router).await.unwrap();
+            });
+        });
+
+        crate::services::whatsonchain::init(&format!("{}/main", url), &format!("{}/test", url), &format!("{}/stn", url));
+    });
+}
@@ -126,7 +126,7 @@ async function generateWallet() {
             showWalletDashboard();
             refreshBalance();
         } else {
-            showWalletStatus(data.error || 'Failed to generate wallet', 'error');
+            showWalletStatus((data.error && data.error.message) || 'Failed to generate wallet', 'error');
         }
     } catch (error) {
         showWalletStatus('Network error: ' + error.message, 'error');
@@ -160,12 +160,14 @@ async function importWif() {
             localStorage.setItem('bsv_address', data.address);
             
             document.getElementById('wifInput').value = '';
-            showWalletStatus('Wallet imported successfully!', 'success');
+            showWalletStatus(data.compressed
+                ? 'Wallet imported successfully!'
+                : 'Wallet imported successfully (uncompressed key)!', 'success');
             
             showWalletDashboard();
             refreshBalance();
         } else {
-            showWalletStatus(data.error || 'Failed to import wallet', 'error');
+            showWalletStatus((data.error && data.error.message) || 'Failed to import wallet', 'error');
         }
     } catch (error) {
         showWalletStatus('Network error: ' + error.message, 'error');
@@ -229,7 +231,7 @@ async function refreshBalance() {
             document.getElementById('balanceSats').textContent = data.balance.toLocaleString() + ' sats';
             clearWalletStatus();
         } else {
-            showWalletStatus(data.error || 'Failed to fetch balance', 'error');
+            showWalletStatus((data.error && data.error.message) || 'Failed to fetch balance', 'error');
         }
     } catch (error) {
         showWalletStatus('Network error: ' + error.message, 'error');
@@ -246,6 +248,35 @@ function copyWalletAddress() {
     }
 }
 
+// Download a backup file of the current wallet
+async function exportWallet() {
+    if (!walletState.wif) return;
+
+    try {
+        const response = await fetch('/api/wallet/export', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ wif: walletState.wif, network: walletState.network })
+        });
+
+        const data = await response.json();
+
+        if (data.success) {
+            const blob = new Blob([JSON.stringify(data.backup, null, 2)], { type: 'application/json' });
+            const link = document.createElement('a');
+            link.href = URL.createObjectURL(blob);
+            link.download = 'bsv-wallet-' + data.backup.address + '.json';
+            link.click();
+            URL.revokeObjectURL(link.href);
+            showWalletStatus('Backup downloaded. Keep it somewhere safe.', 'success');
+        } else {
+            showWalletStatus((data.error && data.error.message) || 'Failed to export wallet', 'error');
+        }
+    } catch (error) {
+        showWalletStatus('Network error: ' + error.message, 'error');
+    }
+}
+
 // Send form
 function showSendForm() {
     document.getElementById('sendForm').classList.remove('hidden');
@@ -303,7 +334,7 @@ async function sendBsv() {
             hideSendForm();
             refreshBalance();
         } else {
-            showWalletStatus(data.error || 'Failed to send', 'error');
+            showWalletStatus((data.error && data.error.message) || 'Failed to send', 'error');
         }
     } catch (error) {
         showWalletStatus('Network error: ' + error.message, 'error');